@@ -0,0 +1,21 @@
+// Any text as a bound expression, alone and nested far deeper than MAX_DEPTH: it fails to
+// parse, or evaluates without panicking
+#![no_main]
+use cgmath::Vector2;
+use libfuzzer_sys::fuzz_target;
+use metal_raymarcher::expression::{Expression, Variables};
+
+fuzz_target!(|text: &str| {
+    let nested = format!("{}{}{}", "(".repeat(50_000), text, ")".repeat(50_000));
+    assert!(Expression::parse(&nested).is_err());
+    let Ok(expression) = Expression::parse(text) else {
+        return;
+    };
+    let variables = Variables {
+        time: 1.5,
+        mouse: Vector2::new(0.5, 0.5),
+        audio: Default::default(),
+        clock: Default::default(),
+    };
+    expression.eval(&variables);
+});
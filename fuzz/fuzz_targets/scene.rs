@@ -0,0 +1,21 @@
+// Any text as a scene file: it fails to parse or validate, or animates and generates shaders
+// without panicking (see tests/scene.rs for the property tests over the example scenes)
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use metal_raymarcher::codegen::{self, Dialect};
+use metal_raymarcher::{animation, Scene};
+
+fuzz_target!(|text: &str| {
+    let Ok(scene) = toml::from_str::<Scene>(text) else {
+        return;
+    };
+    if scene.validate().is_err() {
+        return;
+    }
+    for time in [-1.0, 0.0, 0.5, 100.0] {
+        animation::apply(&mut scene.clone(), time);
+    }
+    for dialect in [Dialect::Msl, Dialect::Glsl, Dialect::Wgsl] {
+        codegen::scene_source(&scene, dialect);
+    }
+});
@@ -0,0 +1,31 @@
+// Adaptive quality, per-tile supersampling in the spirit of variable rate shading: a pre-pass
+// at one texel per `tile` x `tile` pixels casts rays through each tile's corners and center and
+// marks it detailed where they disagree (a silhouette, another object, a crease, a surface
+// bending away). Only detailed tiles get `samples` x `samples` rays per pixel (a cone for 0);
+// the rest get one, so smooth surfaces and sky cost a single ray. The quality preset's own
+// supersampling is replaced. [adaptive] in the config or --adaptive turns it on.
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Adaptive {
+    pub tile: u32,    // Side of a tile in pixels
+    pub samples: u32, // Rays per pixel per axis in detailed tiles, 0 for a cone
+}
+
+impl Default for Adaptive {
+    fn default() -> Self {
+        Self {
+            tile: 16,
+            samples: 3,
+        }
+    }
+}
+
+impl Adaptive {
+    // Size of the tile texture for a target of `size`
+    pub fn size(&self, [width, height]: [u32; 2]) -> [u32; 2] {
+        let tile = self.tile.max(1);
+        [width.div_ceil(tile), height.div_ceil(tile)]
+    }
+}
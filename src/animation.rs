@@ -0,0 +1,234 @@
+// Keyframe animation of scene parameters. A track drives one parameter (see scene.rs for
+// the "<name>.<property>" addresses) from a list of keys on the timeline clock; tracks are
+// stored with the scene, e.g.
+//
+//   [[animations]]
+//   target = "ball.position"
+//   interpolation = "smooth"
+//   repeat = true
+//   keys = [
+//       { time = 0.0, value = [0.0, 0.5, 0.0] },
+//       { time = 2.0, value = [0.0, 1.5, 0.0] },
+//       { time = 4.0, value = [0.0, 0.5, 0.0] },
+//   ]
+//
+// Scalar parameters take plain numbers (`value = 0.6`). Objects also have a `rotation`
+// (Euler angles in degrees, applied X, then Y, then Z) and a uniform `scale` to animate.
+//
+// Orbits move an object's position procedurally around a circle instead:
+//
+//   [[orbits]]
+//   target = "moon.position"
+//   center = [0.0, 0.5, 0.0]
+//   radius = 3.0
+//   period = 4.0                 # Seconds per revolution (negative turns the other way)
+//   axis = [0.0, 1.0, 0.0]       # Normal of the orbit's plane (default: up)
+//   phase = 0.25                 # Where on the circle it starts, in revolutions
+//
+// Animated parameters are read by the shader from the uniforms, so changing them every
+// frame needs no recompile.
+use crate::scene::Scene;
+use cgmath::{InnerSpace, Vector3};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::f32::consts::TAU;
+
+// Latest scene time (seconds, over 11 days) a run can be told to carry on from by a shared
+// view, a sync tick or a workspace
+pub const MAX_TIME: f32 = 1.0e6;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation {
+    Step, // Hold each key until the next one
+    #[default]
+    Linear,
+    Smooth,     // Ease in and out of every key
+    CatmullRom, // Smooth curve through all keys
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Keyframe {
+    pub time: f32, // Seconds
+    #[serde(deserialize_with = "scalar_or_vec")]
+    pub value: Vec<f32>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Track {
+    pub target: String,
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    #[serde(default)]
+    pub repeat: bool, // Loop from the first key after the last one
+    pub keys: Vec<Keyframe>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Orbit {
+    pub target: String, // A position
+    #[serde(default)]
+    pub center: [f32; 3],
+    pub radius: f32,
+    pub period: f32, // Seconds per revolution
+    #[serde(default = "default_axis")]
+    pub axis: [f32; 3],
+    #[serde(default)]
+    pub phase: f32, // Revolutions
+}
+
+fn default_axis() -> [f32; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+fn scalar_or_vec<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<f32>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        Scalar(f32),
+        Vector(Vec<f32>),
+    }
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Scalar(value) => vec![value],
+        Value::Vector(values) => values,
+    })
+}
+
+impl Track {
+    // Check the target exists and every key fits it, with keys in time order
+    pub fn validate(&self, scene: &Scene) -> Result<(), String> {
+        let len = scene
+            .parameter(&self.target)
+            .ok_or_else(|| format!("animation target \"{}\" not found", self.target))?
+            .len();
+        if self.keys.is_empty() {
+            return Err(format!("animation of \"{}\" has no keys", self.target));
+        }
+        if let Some(key) = self.keys.iter().find(|key| key.value.len() != len) {
+            return Err(format!(
+                "key at {}s of \"{}\" has {} values, expected {}",
+                key.time,
+                self.target,
+                key.value.len(),
+                len
+            ));
+        }
+        if self.keys.windows(2).any(|pair| pair[1].time < pair[0].time) {
+            return Err(format!("keys of \"{}\" are not in time order", self.target));
+        }
+        Ok(())
+    }
+
+    // Value of the track at `time`; held at the first/last key outside the keyed range
+    pub fn sample(&self, time: f32) -> Vec<f32> {
+        let (first, last) = match (self.keys.first(), self.keys.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Vec::new(),
+        };
+        let duration = last.time - first.time;
+        let time = if self.repeat && duration > 0.0 {
+            first.time + (time - first.time).rem_euclid(duration)
+        } else {
+            time
+        };
+        if time <= first.time {
+            return first.value.clone();
+        }
+        if time >= last.time {
+            return last.value.clone();
+        }
+
+        // Segment [i, i + 1] containing `time`
+        let i = self.keys.partition_point(|key| key.time <= time) - 1;
+        let (a, b) = (&self.keys[i], &self.keys[i + 1]);
+        let span = b.time - a.time;
+        let u = if span > 0.0 {
+            (time - a.time) / span
+        } else {
+            1.0
+        };
+        match self.interpolation {
+            Interpolation::Step => a.value.clone(),
+            Interpolation::Linear => lerp(&a.value, &b.value, u),
+            Interpolation::Smooth => lerp(&a.value, &b.value, u * u * (3.0 - 2.0 * u)),
+            Interpolation::CatmullRom => {
+                let before = &self.keys[i.saturating_sub(1)].value;
+                let after = &self.keys[(i + 2).min(self.keys.len() - 1)].value;
+                let points = before.iter().zip(&a.value).zip(&b.value).zip(after);
+                points
+                    .map(|(((&p0, &p1), &p2), &p3)| catmull_rom(p0, p1, p2, p3, u))
+                    .collect()
+            }
+        }
+    }
+}
+
+impl Orbit {
+    pub fn validate(&self, scene: &Scene) -> Result<(), String> {
+        if !self.target.ends_with(".position") || scene.parameter(&self.target).is_none() {
+            return Err(format!(
+                "orbit target \"{}\" is not an object position",
+                self.target
+            ));
+        }
+        if self.period == 0.0 {
+            return Err(format!("orbit of \"{}\" needs a period", self.target));
+        }
+        if Vector3::from(self.axis).magnitude2() == 0.0 {
+            return Err(format!("orbit of \"{}\" has no axis", self.target));
+        }
+        Ok(())
+    }
+
+    // Position on the circle at `time`
+    pub fn sample(&self, time: f32) -> [f32; 3] {
+        let axis = Vector3::from(self.axis).normalize();
+        // Two directions spanning the orbit's plane
+        let helper = if axis.x.abs() < 0.9 {
+            Vector3::unit_x()
+        } else {
+            Vector3::unit_y()
+        };
+        let u = axis.cross(helper).normalize();
+        let v = axis.cross(u);
+        let angle = TAU * (time / self.period + self.phase);
+        (Vector3::from(self.center) + (u * angle.cos() + v * angle.sin()) * self.radius).into()
+    }
+}
+
+fn lerp(a: &[f32], b: &[f32], u: f32) -> Vec<f32> {
+    a.iter().zip(b).map(|(a, b)| a + (b - a) * u).collect()
+}
+
+// Uniform Catmull-Rom spline between p1 and p2
+fn catmull_rom(p0: f32, p1: f32, p2: f32, p3: f32, u: f32) -> f32 {
+    let u2 = u * u;
+    let u3 = u2 * u;
+    0.5 * (2.0 * p1
+        + (p2 - p0) * u
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * u2
+        + (3.0 * p1 - p0 - 3.0 * p2 + p3) * u3)
+}
+
+// Evaluate every track and orbit of the scene at `time` and write the values into its
+// parameters
+pub fn apply(scene: &mut Scene, time: f32) {
+    let tracks = std::mem::take(&mut scene.animations);
+    let orbits = std::mem::take(&mut scene.orbits);
+    let values = tracks
+        .iter()
+        .map(|track| (&track.target, track.sample(time)))
+        .chain(
+            orbits
+                .iter()
+                .map(|orbit| (&orbit.target, orbit.sample(time).to_vec())),
+        );
+    for (target, value) in values {
+        if let Some(parameter) = scene.parameter_mut(target) {
+            if parameter.len() == value.len() {
+                parameter.copy_from_slice(&value);
+            }
+        }
+    }
+    scene.animations = tracks;
+    scene.orbits = orbits;
+}
@@ -0,0 +1,251 @@
+// Art-Net input (--artnet FILE): DMX from a lighting desk, received on the Art-Net port (UDP
+// 6454, on every interface) and mapped onto scene parameters by a TOML file:
+//
+//   [[channel]]
+//   target = "ball.radius"   # Parameter address (see scene.rs)
+//   universe = 0             # Port-address: net, sub-net and universe as one number (0)
+//   channel = 1              # 1-512
+//   range = [0.5, 2.0]       # Values at the bottom and top of the fader ([0, 1])
+//   curve = "ease_in"        # Easing from one to the other, as in tween.rs ("linear")
+//
+//   [[channel]]
+//   target = "ball.color"    # One channel per component from `channel` up (1, 2, 3 here)...
+//   channel = 1
+//
+//   [[channel]]
+//   target = "ball.color"
+//   component = 2            # ...or one component alone, 0 for the first
+//   channel = 10
+//   fine = true              # 16 bits: coarse on `channel`, fine on the next one
+//
+// Like bound expressions, mapped parameters follow their channels every frame once the desk
+// has sent their universe; parameters the scene doesn't have are skipped. The desk has to
+// send ArtDmx to this machine or broadcast it (this node doesn't answer ArtPoll).
+use metal_raymarcher::tween::Easing;
+use metal_raymarcher::Scene;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::net::{Ipv4Addr, UdpSocket};
+use std::path::Path;
+
+const PORT: u16 = 6454;
+const HEADER: &[u8; 8] = b"Art-Net\0";
+const OP_DMX: u16 = 0x5000;
+const CHANNELS: usize = 512;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MappingFile {
+    #[serde(default)]
+    channel: Vec<Mapping>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Mapping {
+    target: String,
+    #[serde(default)]
+    universe: u16,
+    channel: usize,
+    #[serde(default)]
+    component: Option<usize>,
+    #[serde(default = "full_range")]
+    range: [f32; 2],
+    #[serde(default)]
+    curve: Option<Easing>,
+    #[serde(default)]
+    fine: bool,
+}
+
+fn full_range() -> [f32; 2] {
+    [0.0, 1.0]
+}
+
+impl Mapping {
+    // The value of the component starting at `channel` (from 1) of `levels`
+    fn value(&self, levels: &[u8; CHANNELS], channel: usize) -> Option<f32> {
+        let coarse = *levels.get(channel - 1)?;
+        let t = if self.fine {
+            let fine = *levels.get(channel)?;
+            u16::from_be_bytes([coarse, fine]) as f32 / u16::MAX as f32
+        } else {
+            coarse as f32 / u8::MAX as f32
+        };
+        let t = self.curve.unwrap_or(Easing::Linear).apply(t);
+        Some(self.range[0] + (self.range[1] - self.range[0]) * t)
+    }
+}
+
+pub struct ArtNet {
+    socket: UdpSocket,
+    mappings: Vec<Mapping>,
+    universes: HashMap<u16, [u8; CHANNELS]>, // Latest levels of each universe heard from
+    reported: HashSet<String>,               // Targets that failed, so each is only reported once
+}
+
+impl ArtNet {
+    // Listen for Art-Net with the mappings in `path`
+    pub fn new(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+        let file: MappingFile = toml::from_str(&text)
+            .map_err(|err| format!("Invalid Art-Net mapping {}: {}", path.display(), err))?;
+        if let Some(mapping) = (file.channel.iter()).find(|m| !(1..=CHANNELS).contains(&m.channel))
+        {
+            return Err(format!(
+                "Invalid Art-Net mapping {}: \"{}\" is on channel {}, not 1-{}",
+                path.display(),
+                mapping.target,
+                mapping.channel,
+                CHANNELS
+            ));
+        }
+        if let Some(mapping) = (file.channel.iter())
+            .find(|m| !m.range.iter().all(|end| end.is_finite()) || m.range[0] == m.range[1])
+        {
+            return Err(format!(
+                "Invalid Art-Net mapping {}: the range of \"{}\" needs two different, finite ends",
+                path.display(),
+                mapping.target
+            ));
+        }
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, PORT))
+            .map_err(|err| format!("Failed to listen for Art-Net on UDP port {}: {}", PORT, err))?;
+        socket
+            .set_nonblocking(true)
+            .map_err(|err| err.to_string())?;
+        println!(
+            "Taking Art-Net on UDP port {} ({} channel mappings)",
+            PORT,
+            file.channel.len()
+        );
+        Ok(Self {
+            socket,
+            mappings: file.channel,
+            universes: HashMap::new(),
+            reported: HashSet::new(),
+        })
+    }
+
+    // Take the packets that have arrived and write the mapped parameters into `scene`. Returns
+    // true when a parameter baked into the shader became live and the shader must be rebuilt.
+    pub fn apply(&mut self, scene: &mut Scene) -> bool {
+        let mut packet = [0; 18 + CHANNELS];
+        while let Ok(len) = self.socket.recv(&mut packet) {
+            take(&mut self.universes, &packet[..len]);
+        }
+
+        let mut rebuild = false;
+        for mapping in &self.mappings {
+            let Some(levels) = self.universes.get(&mapping.universe) else {
+                continue;
+            };
+            let Some(len) = scene.parameter(&mapping.target).map(<[f32]>::len) else {
+                continue;
+            };
+            let width = if mapping.fine { 2 } else { 1 };
+            let values: Vec<(usize, f32)> = match mapping.component {
+                Some(component) if component < len => (mapping.value(levels, mapping.channel))
+                    .map(|value| (component, value))
+                    .into_iter()
+                    .collect(),
+                Some(_) => Vec::new(),
+                None => (0..len)
+                    .map_while(|i| Some((i, mapping.value(levels, mapping.channel + i * width)?)))
+                    .collect(),
+            };
+            if values.is_empty() {
+                continue;
+            }
+            match scene.make_live(&mapping.target) {
+                Ok(live) => rebuild |= live,
+                Err(err) => {
+                    if self.reported.insert(mapping.target.clone()) {
+                        eprintln!("Art-Net: {}", err);
+                    }
+                    continue;
+                }
+            }
+            if let Some(parameter) = scene.parameter_mut(&mapping.target) {
+                for (i, value) in values {
+                    parameter[i] = value;
+                }
+            }
+        }
+        rebuild
+    }
+}
+
+// Update the levels of the universe `packet` is for, if it is ArtDmx
+fn take(universes: &mut HashMap<u16, [u8; CHANNELS]>, packet: &[u8]) {
+    if let Some((universe, data)) = dmx(packet) {
+        let levels = universes.entry(universe).or_insert([0; CHANNELS]);
+        levels[..data.len()].copy_from_slice(data);
+    }
+}
+
+// The universe and channel levels of an ArtDmx packet (None for anything else)
+fn dmx(packet: &[u8]) -> Option<(u16, &[u8])> {
+    if packet.len() < 18 || &packet[..8] != HEADER {
+        return None;
+    }
+    if u16::from_le_bytes([packet[8], packet[9]]) != OP_DMX {
+        return None;
+    }
+    let universe = u16::from_le_bytes([packet[14], packet[15]]) & 0x7fff;
+    let len = (u16::from_be_bytes([packet[16], packet[17]]) as usize).min(CHANNELS);
+    Some((universe, packet.get(18..18 + len)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An ArtDmx packet for `universe` that says it carries `len` channels, with `levels`
+    fn packet(universe: u16, len: u16, levels: &[u8]) -> Vec<u8> {
+        let mut packet = HEADER.to_vec();
+        packet.extend_from_slice(&OP_DMX.to_le_bytes());
+        packet.extend_from_slice(&[0, 14, 0, 0]); // Protocol version, sequence, physical
+        packet.extend_from_slice(&universe.to_le_bytes());
+        packet.extend_from_slice(&len.to_be_bytes());
+        packet.extend_from_slice(levels);
+        packet
+    }
+
+    #[test]
+    fn dmx_packets_are_read() {
+        assert_eq!(dmx(&packet(3, 2, &[10, 20])), Some((3, &[10, 20][..])));
+        // The top bit isn't part of the port-address
+        assert_eq!(dmx(&packet(0x8003, 1, &[10])), Some((3, &[10][..])));
+    }
+
+    #[test]
+    fn packets_set_their_universe_alone() {
+        let mut universes = HashMap::new();
+        take(&mut universes, &packet(1, 2, &[10, 20]));
+        take(&mut universes, &packet(1, 1, &[30]));
+        assert_eq!(universes.len(), 1);
+        assert_eq!(universes[&1][..3], [30, 20, 0]);
+        assert!(!universes.contains_key(&0));
+    }
+
+    #[test]
+    fn other_packets_are_ignored() {
+        let dmx_packet = packet(0, 2, &[10, 20]);
+        assert_eq!(dmx(&dmx_packet[..17]), None);
+        assert_eq!(dmx(&packet(0, 3, &[10, 20])), None);
+        let mut poll = dmx_packet.clone();
+        poll[8..10].copy_from_slice(&0x2000u16.to_le_bytes());
+        assert_eq!(dmx(&poll), None);
+        let mut other = dmx_packet;
+        other[..8].copy_from_slice(b"Art-Net!");
+        assert_eq!(dmx(&other), None);
+    }
+
+    #[test]
+    fn dmx_is_cut_at_512_channels() {
+        let levels = vec![1; CHANNELS + 10];
+        let packet = packet(0, levels.len() as u16, &levels);
+        assert_eq!(dmx(&packet), Some((0, &levels[..CHANNELS])));
+    }
+}
@@ -0,0 +1,50 @@
+// Audio-reactive input (with the "audio" feature): live audio from an input device is
+// analysed every frame into three band energies, an overall level and a beat pulse, all
+// roughly 0..1. Shaders read them as `uniforms.audio` (bass, mid, high, level) and
+// `uniforms.beat`, expressions as `audio.bass`, `audio.mid`, `audio.high`, `audio.level`
+// and `audio.beat`, and scripts through the `audio` map.
+//
+// Select the device in the config's [audio] section or with --audio; "default" is the
+// system's default input (usually the microphone). To react to system audio, pick a
+// loopback device (e.g. BlackHole on macOS, a monitor source on Linux).
+#[cfg(feature = "audio")]
+use crate::audio_input::AudioInput;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AudioLevels {
+    pub bass: f32, // Below 250 Hz
+    pub mid: f32,  // 250 Hz to 2 kHz
+    pub high: f32, // Above 2 kHz
+    pub level: f32,
+    pub beat: f32, // 1 on a detected beat, decaying to 0
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioConfig {
+    pub input: Option<String>, // "default" or part of the device name; None = off
+    pub gain: f32,             // Scales the levels before they are clamped to 0..1
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            input: None,
+            gain: 1.0,
+        }
+    }
+}
+
+// Start the configured audio input, if any
+#[cfg(feature = "audio")]
+pub fn open(config: &AudioConfig) -> Option<AudioInput> {
+    let input = config.input.as_deref()?;
+    match AudioInput::new(input, config.gain) {
+        Ok(audio) => Some(audio),
+        Err(err) => {
+            eprintln!("Audio input disabled: {}", err);
+            None
+        }
+    }
+}
@@ -0,0 +1,219 @@
+// Live audio capture (cpal) and its analysis into AudioLevels (see audio.rs)
+use crate::audio::AudioLevels;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, SampleFormat, SizedSample, Stream, StreamConfig};
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use std::collections::VecDeque;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+
+// Samples per analysis (about 23 ms at 44.1 kHz)
+const FFT_SIZE: usize = 1024;
+// Frames of bass energy a beat is compared against (about a second)
+const BEAT_HISTORY: usize = 60;
+// Bass energy over its recent average that counts as a beat
+const BEAT_THRESHOLD: f32 = 1.4;
+// Shortest time between two beats, in seconds
+const BEAT_COOLDOWN: f32 = 0.2;
+
+type Samples = Arc<Mutex<VecDeque<f32>>>;
+
+pub struct AudioInput {
+    samples: Samples, // Latest FFT_SIZE mono samples, filled by the audio thread
+    sample_rate: f32,
+    gain: f32,
+    _stop: Sender<()>, // Dropping it ends the thread that owns the stream
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>, // Hann window
+    peak: f32,        // Slowly decaying maximum band energy, for automatic gain
+    history: VecDeque<f32>,
+    since_beat: f32,
+    levels: AudioLevels, // Smoothed levels of the previous frame
+}
+
+impl AudioInput {
+    pub fn new(input: &str, gain: f32) -> Result<Self, String> {
+        let samples: Samples = Arc::new(Mutex::new(VecDeque::with_capacity(FFT_SIZE)));
+
+        // cpal streams cannot move between threads, so one thread owns it until
+        // the input is dropped
+        let (ready, started) = mpsc::channel();
+        let (stop, stopped) = mpsc::channel::<()>();
+        let input = input.to_string();
+        let buffer = samples.clone();
+        std::thread::Builder::new()
+            .name("audio".to_string())
+            .spawn(move || match start(&input, buffer) {
+                Ok((stream, sample_rate)) => {
+                    let _ = ready.send(Ok(sample_rate));
+                    let _ = stopped.recv();
+                    drop(stream);
+                }
+                Err(err) => {
+                    let _ = ready.send(Err(err));
+                }
+            })
+            .map_err(|err| format!("Failed to start the audio thread: {}", err))?;
+        let sample_rate = started
+            .recv()
+            .map_err(|_| "The audio thread stopped".to_string())??;
+
+        Ok(Self {
+            samples,
+            sample_rate,
+            gain,
+            _stop: stop,
+            fft: FftPlanner::new().plan_fft_forward(FFT_SIZE),
+            window: (0..FFT_SIZE)
+                .map(|i| {
+                    let x = i as f32 / (FFT_SIZE - 1) as f32;
+                    0.5 - 0.5 * (2.0 * std::f32::consts::PI * x).cos()
+                })
+                .collect(),
+            peak: 0.0,
+            history: VecDeque::with_capacity(BEAT_HISTORY),
+            since_beat: 0.0,
+            levels: AudioLevels::default(),
+        })
+    }
+
+    // Analyse the latest audio; call once per frame with the time since the last call
+    pub fn levels(&mut self, dt: f32) -> AudioLevels {
+        let mut buffer = vec![Complex::default(); FFT_SIZE];
+        {
+            let samples = self.samples.lock().unwrap();
+            let offset = FFT_SIZE - samples.len();
+            for (i, sample) in samples.iter().enumerate() {
+                buffer[offset + i].re = sample * self.window[offset + i];
+            }
+        }
+        let rms = (buffer.iter().map(|c| c.re * c.re).sum::<f32>() / FFT_SIZE as f32).sqrt();
+        self.fft.process(&mut buffer);
+
+        // Mean magnitude of the bins in [low, high) Hz
+        let bin_hz = self.sample_rate / FFT_SIZE as f32;
+        let band = |low: f32, high: f32| {
+            let first = ((low / bin_hz) as usize).max(1);
+            let last = ((high / bin_hz) as usize).clamp(first + 1, FFT_SIZE / 2);
+            buffer[first..last].iter().map(|c| c.norm()).sum::<f32>() / (last - first) as f32
+        };
+        let bands = [
+            band(20.0, 250.0),
+            band(250.0, 2000.0),
+            band(2000.0, 12000.0),
+        ];
+
+        // Normalize by a peak that falls by half every two seconds
+        let loudest = bands.iter().copied().fold(0.0, f32::max);
+        self.peak = (self.peak * 0.5f32.powf(dt / 2.0)).max(loudest).max(1e-3);
+        let normalized = bands.map(|band| (band / self.peak * self.gain).min(1.0));
+
+        // Beat: bass well above its average over the last second
+        let bass = bands[0];
+        let average = self.history.iter().sum::<f32>() / self.history.len().max(1) as f32;
+        self.since_beat += dt;
+        let beat = self.history.len() == BEAT_HISTORY
+            && bass > average * BEAT_THRESHOLD
+            && bass > self.peak * 0.1
+            && self.since_beat > BEAT_COOLDOWN;
+        if self.history.len() == BEAT_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(bass);
+        if beat {
+            self.since_beat = 0.0;
+        }
+
+        // Rise immediately, fall smoothly
+        let release = 1.0 - (-dt * 8.0).exp();
+        let smooth = |old: f32, new: f32| {
+            if new > old {
+                new
+            } else {
+                old + (new - old) * release
+            }
+        };
+        let old = self.levels;
+        self.levels = AudioLevels {
+            bass: smooth(old.bass, normalized[0]),
+            mid: smooth(old.mid, normalized[1]),
+            high: smooth(old.high, normalized[2]),
+            level: smooth(old.level, (rms * 4.0 * self.gain).min(1.0)),
+            beat: if beat {
+                1.0
+            } else {
+                old.beat * (-dt * 6.0).exp()
+            },
+        };
+        self.levels
+    }
+}
+
+// Open the input device and start streaming mono samples into `samples`
+fn start(input: &str, samples: Samples) -> Result<(Stream, f32), String> {
+    let host = cpal::default_host();
+    let device = if input == "default" {
+        host.default_input_device()
+            .ok_or_else(|| "no default audio input device".to_string())?
+    } else {
+        let wanted = input.to_lowercase();
+        host.input_devices()
+            .map_err(|err| format!("Failed to list audio inputs: {}", err))?
+            .find(|device| {
+                device
+                    .name()
+                    .map(|name| name.to_lowercase().contains(&wanted))
+                    .unwrap_or(false)
+            })
+            .ok_or_else(|| format!("no audio input matching \"{}\"", input))?
+    };
+    let supported = device
+        .default_input_config()
+        .map_err(|err| format!("Failed to configure audio input: {}", err))?;
+    let sample_rate = supported.sample_rate().0 as f32;
+    let config = supported.config();
+    let stream = match supported.sample_format() {
+        SampleFormat::F32 => build::<f32>(&device, &config, samples),
+        SampleFormat::I16 => build::<i16>(&device, &config, samples),
+        SampleFormat::U16 => build::<u16>(&device, &config, samples),
+        SampleFormat::I32 => build::<i32>(&device, &config, samples),
+        format => return Err(format!("unsupported audio sample format {}", format)),
+    }
+    .map_err(|err| format!("Failed to open audio input: {}", err))?;
+    stream
+        .play()
+        .map_err(|err| format!("Failed to start audio input: {}", err))?;
+    println!(
+        "Audio input: {} ({} Hz)",
+        device.name().unwrap_or_default(),
+        sample_rate
+    );
+    Ok((stream, sample_rate))
+}
+
+fn build<T: SizedSample>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    samples: Samples,
+) -> Result<Stream, cpal::BuildStreamError>
+where
+    f32: FromSample<T>,
+{
+    let channels = config.channels.max(1) as usize;
+    device.build_input_stream(
+        config,
+        move |data: &[T], _: &cpal::InputCallbackInfo| {
+            let mut samples = samples.lock().unwrap();
+            for frame in data.chunks(channels) {
+                let sum: f32 = frame.iter().map(|sample| sample.to_sample::<f32>()).sum();
+                if samples.len() == FFT_SIZE {
+                    samples.pop_front();
+                }
+                samples.push_back(sum / channels as f32);
+            }
+        },
+        |err| eprintln!("Audio input error: {}", err),
+        None,
+    )
+}
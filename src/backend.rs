@@ -0,0 +1,170 @@
+// GPU backends behind the app: native Metal (the default on macOS and iOS) and wgpu
+// (everywhere else, or with --backend wgpu when built with the "wgpu" feature).
+use crate::config::Config;
+use cgmath::Vector2;
+use clap::ValueEnum;
+use metal_raymarcher::adaptive::Adaptive;
+use metal_raymarcher::audio::AudioLevels;
+use metal_raymarcher::clock::ClockPhase;
+use metal_raymarcher::edges::Edges;
+use metal_raymarcher::gizmo::OverlayVertex;
+use metal_raymarcher::quality::{QualityConfig, QualityPreset};
+use metal_raymarcher::shafts::Shafts;
+use metal_raymarcher::toon::Toon;
+use metal_raymarcher::{Camera, Scene};
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+use winit::dpi::PhysicalSize;
+use winit::window::Window;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    Metal,
+    Wgpu,
+}
+
+impl BackendKind {
+    pub fn platform_default() -> Self {
+        if cfg!(any(target_os = "macos", target_os = "ios")) {
+            BackendKind::Metal
+        } else {
+            BackendKind::Wgpu
+        }
+    }
+}
+
+// Send so the app can be driven from the render thread
+pub trait Backend: Send {
+    // Preset to start with when none is configured, based on the GPU
+    fn default_preset(&self) -> QualityPreset;
+    fn set_quality(&mut self, quality: QualityConfig);
+    fn set_mouse(&mut self, mouse: Vector2<f32>);
+    fn set_audio(&mut self, audio: AudioLevels);
+    fn set_clock(&mut self, clock: ClockPhase);
+    // Object to highlight (see picking.rs)
+    fn set_selected(&mut self, selected: Option<usize>);
+    // Draw the ground grid and axes over the scene
+    fn set_guides(&mut self, guides: bool);
+    // Toon shading for every scene; None leaves it to each scene's [environment]
+    fn set_toon(&mut self, toon: Option<Toon>);
+    // Edge detection over the image (see edges.rs)
+    fn set_edges(&mut self, edges: Edges) -> Result<(), String>;
+    // Volumetric light shafts (see shafts.rs); None turns them off
+    fn set_shafts(&mut self, shafts: Option<Shafts>) -> Result<(), String>;
+    // Supersampling only where the image needs it (see adaptive.rs); None turns it off
+    fn set_adaptive(&mut self, adaptive: Option<Adaptive>) -> Result<(), String>;
+    // Marching each tile of the image without the objects it can't see (experimental, Metal)
+    fn set_culling(&mut self, on: bool) -> Result<(), String>;
+    // Triangles to draw over the image (gizmos)
+    fn set_overlay(&mut self, vertices: &[OverlayVertex]);
+    // Current lighting and animated parameter values of the scene
+    fn set_scene(&mut self, scene: &Scene);
+    // Regenerate the shader after the scene's structure changed
+    fn rebuild(&mut self, scene: &Scene) -> Result<(), String>;
+    // Switch to a custom shader (as --shader, from its source), keeping the current one if it
+    // fails to build
+    fn set_shader(&mut self, template: String, scene: &Scene) -> Result<(), String>;
+    // The template of the shader drawn now, for the shader editor (None without custom shaders)
+    fn shader_template(&self) -> Option<String>;
+    // Cross-fade to another scene (see sequence.rs): build it, draw it over the current one
+    // with the opacity given to `set_crossfade`, then make it the current one (`keep`) or
+    // drop it
+    fn begin_crossfade(&mut self, scene: &Scene) -> Result<(), String>;
+    fn set_crossfade(&mut self, scene: &Scene, camera: &Camera, opacity: f32);
+    fn end_crossfade(&mut self, keep: bool);
+    // Progressively path trace the scene instead of raymarching it (see the shader's
+    // pathtrace_main); the image converges while nothing but time moves
+    fn set_path_tracing(&mut self, on: bool) -> Result<(), String>;
+    // Paths per pixel averaged so far (None when not path tracing)
+    fn samples(&self) -> Option<u32>;
+    // Show path-traced images through the denoiser
+    fn set_denoise(&mut self, denoise: bool);
+    // Seed for the sampling and the noise of `scene` and those after it (see NoiseTable::of),
+    // so repeated runs draw the same images
+    fn set_seed(&mut self, seed: u32, scene: &Scene);
+    // Move to another GPU if the current one was removed (see device_watch.rs), carrying on
+    // with `scene`; true when it did, for the caller to redo what else lived on the old one
+    // (a cross-fade). Call before each frame.
+    fn recover(&mut self, _scene: &Scene) -> bool {
+        false
+    }
+    // The GPU and its driver, and the uniforms of the last frame drawn (None before the
+    // first), for crash and error reports (see report.rs)
+    fn describe_device(&self) -> String;
+    fn describe_uniforms(&self) -> Option<String>;
+    // A frame the GPU failed to draw (a timeout or a fault) since the last call; wgpu reports
+    // its errors by panicking instead
+    fn gpu_error(&mut self) -> Option<String> {
+        None
+    }
+    // GPU time of each pass of a recent frame, in seconds (empty where the GPU can't tell),
+    // and the GPU memory the backend has allocated, if known, for monitoring (see metrics.rs)
+    fn pass_times(&self) -> Vec<(&'static str, f64)>;
+    fn gpu_memory(&self) -> Option<u64> {
+        None
+    }
+    // Run the shader test kernel for `scene` at `probes` (see shader_test.rs)
+    fn test_shader(&mut self, scene: &Scene, probes: &[[f32; 4]]) -> Result<Vec<[f32; 4]>, String>;
+    // Pull the latest video/screen frames; call once per frame
+    fn upload_inputs(&mut self) {}
+    // New size of the drawable (window size times the render scale)
+    fn resize(&mut self, drawable_size: PhysicalSize<u32>);
+    // Show the next frame drawn no sooner than `at` rather than as soon as it's done (Metal;
+    // elsewhere frames are shown when done)
+    fn present_at(&mut self, _at: Instant) {}
+    // Draw a frame to the window; false when there was nothing to draw into (no drawable yet,
+    // or the swapchain being rebuilt), for the caller to wait a little before trying again
+    fn render(&mut self, camera: &Camera, time: f32) -> bool;
+    // Draw a frame offscreen, wait for it and read it back as RGBA8
+    #[cfg(feature = "offline")]
+    fn render_to_rgba(&mut self, size: PhysicalSize<u32>, camera: &Camera, time: f32) -> Vec<u8>;
+    // Draw a frame offscreen as `render_to_rgba` does, without waiting for it: the pixels come
+    // back from `readbacks` under the number returned here once the GPU is done, a frame or
+    // so later. For reading frames back while others are being shown.
+    #[cfg(feature = "offline")]
+    fn request_readback(&mut self, size: PhysicalSize<u32>, camera: &Camera, time: f32) -> u64;
+    // The readbacks finished since the last call, oldest first, as RGBA8 (an error where the
+    // GPU failed to draw the frame)
+    #[cfg(feature = "offline")]
+    fn readbacks(&mut self) -> Vec<(u64, Result<Vec<u8>, String>)>;
+    // Read back the intermediate textures of the last frame, in the order they were drawn
+    // (the frame must have finished, as after `render_to_rgba`)
+    #[cfg(feature = "offline")]
+    fn read_passes(&mut self) -> Vec<PassImage>;
+}
+
+// An intermediate texture read back as RGBA floats, rows top first
+#[cfg(feature = "offline")]
+pub struct PassImage {
+    pub name: &'static str,
+    pub size: PhysicalSize<u32>,
+    pub pixels: Vec<f32>,
+}
+
+// Create the backend; `window` is None for offline rendering
+pub fn create(
+    kind: BackendKind,
+    window: Option<&Window>,
+    config: &Config,
+    scene: &Scene,
+) -> Result<Box<dyn Backend>, String> {
+    match kind {
+        #[cfg(any(target_os = "macos", target_os = "ios"))]
+        BackendKind::Metal => Ok(Box::new(crate::metal_backend::MetalBackend::new(
+            window, config, scene,
+        )?)),
+        #[cfg(feature = "wgpu")]
+        BackendKind::Wgpu => Ok(Box::new(crate::wgpu_backend::WgpuBackend::new(
+            window, config, scene,
+        )?)),
+        #[allow(unreachable_patterns)]
+        kind => {
+            let _ = (window, config, scene);
+            Err(format!(
+                "The {:?} backend is not available in this build",
+                kind
+            ))
+        }
+    }
+}
@@ -0,0 +1,106 @@
+// Base64 (RFC 4648): the standard alphabet with padding for the WebSocket handshake, and the
+// URL-safe one without padding for shared views (see share.rs)
+const STANDARD: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const URL_SAFE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+pub fn encode(data: &[u8]) -> String {
+    encode_with(data, STANDARD, true)
+}
+
+pub fn encode_url(data: &[u8]) -> String {
+    encode_with(data, URL_SAFE, false)
+}
+
+// Bytes of URL-safe `text`, padded or not (None if it isn't base64)
+pub fn decode_url(text: &str) -> Option<Vec<u8>> {
+    let digits = text.trim_end_matches('=').as_bytes();
+    let mut data = Vec::with_capacity(digits.len() * 3 / 4);
+    for chunk in digits.chunks(4) {
+        if chunk.len() == 1 {
+            return None; // A lone digit carries less than a byte
+        }
+        let mut bits = 0u32;
+        for (i, &digit) in chunk.iter().enumerate() {
+            let value = URL_SAFE.iter().position(|&d| d == digit)? as u32;
+            bits |= value << (18 - 6 * i);
+        }
+        data.extend_from_slice(&bits.to_be_bytes()[1..chunk.len()]);
+    }
+    Some(data)
+}
+
+fn encode_with(data: &[u8], digits: &[u8; 64], pad: bool) -> String {
+    let mut text = String::new();
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| {
+            bits | (byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(digits[(bits >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else if pad {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 4648's test vectors
+    const VECTORS: [(&str, &str); 7] = [
+        ("", ""),
+        ("f", "Zg=="),
+        ("fo", "Zm8="),
+        ("foo", "Zm9v"),
+        ("foob", "Zm9vYg=="),
+        ("fooba", "Zm9vYmE="),
+        ("foobar", "Zm9vYmFy"),
+    ];
+
+    #[test]
+    fn standard_encoding_is_padded() {
+        for (data, text) in VECTORS {
+            assert_eq!(encode(data.as_bytes()), text);
+        }
+        assert_eq!(encode(&[0xFB, 0xFF, 0xBF]), "+/+/");
+    }
+
+    #[test]
+    fn url_encoding_is_unpadded_and_url_safe() {
+        for (data, text) in VECTORS {
+            assert_eq!(encode_url(data.as_bytes()), text.trim_end_matches('='));
+        }
+        assert_eq!(encode_url(&[0xFB, 0xFF, 0xBF]), "-_-_");
+    }
+
+    #[test]
+    fn url_decoding_takes_padded_and_unpadded_text() {
+        for (data, text) in VECTORS {
+            assert_eq!(decode_url(text).as_deref(), Some(data.as_bytes()));
+            let unpadded = text.trim_end_matches('=');
+            assert_eq!(decode_url(unpadded).as_deref(), Some(data.as_bytes()));
+        }
+    }
+
+    #[test]
+    fn url_encoding_round_trips() {
+        let data: Vec<u8> = (0..=255).collect();
+        for len in 0..data.len() {
+            let text = encode_url(&data[..len]);
+            assert_eq!(decode_url(&text).as_deref(), Some(&data[..len]));
+        }
+    }
+
+    #[test]
+    fn invalid_text_is_refused() {
+        for text in [
+            "Z", "Zm9vY", "Zm9v+g", "Zm9v/g", "Zm=v", "Zm9 ", "Zm9v€", "Zm9v\n",
+        ] {
+            assert_eq!(decode_url(text), None, "{:?}", text);
+        }
+    }
+}
@@ -0,0 +1,138 @@
+// Sculpting brush. With the brush on (B: add, then subtract, then off), clicking a surface
+// drops a sphere where the cursor hits it, and dragging lays capsules along the stroke at the
+// depth it started from. Every dab is a plain scene object ("sculpt1", "sculpt2", ...) added
+// to the group of the object it was painted on and blended into it with a smooth union or a
+// smooth subtraction, so sculpted scenes save, export and edit like any other.
+//
+// Size is the dab's radius and blend the smooth k, both in world units ([brush] in the config,
+// changed live with [ ] and - =).
+use crate::history::Command;
+use crate::scene::{CsgOp, Node, Scene, SceneObject, Shape};
+use cgmath::Vector3;
+use serde::{Deserialize, Serialize};
+
+const MIN_SIZE: f32 = 0.02;
+const MIN_BLEND: f32 = 0.01;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BrushConfig {
+    pub size: f32,
+    pub blend: f32,
+}
+
+impl Default for BrushConfig {
+    fn default() -> Self {
+        Self {
+            size: 0.3,
+            blend: 0.2,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BrushMode {
+    #[default]
+    Off,
+    Add,
+    Subtract,
+}
+
+impl BrushMode {
+    pub fn next(self) -> Self {
+        match self {
+            BrushMode::Off => BrushMode::Add,
+            BrushMode::Add => BrushMode::Subtract,
+            BrushMode::Subtract => BrushMode::Off,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Brush {
+    pub mode: BrushMode,
+    pub size: f32,
+    pub blend: f32,
+}
+
+impl Brush {
+    pub fn new(config: &BrushConfig) -> Self {
+        Self {
+            mode: BrushMode::Off,
+            size: config.size.max(MIN_SIZE),
+            blend: config.blend.max(MIN_BLEND),
+        }
+    }
+
+    pub fn scale_size(&mut self, factor: f32) {
+        self.size = (self.size * factor).max(MIN_SIZE);
+    }
+
+    pub fn scale_blend(&mut self, factor: f32) {
+        self.blend = (self.blend * factor).max(MIN_BLEND);
+    }
+
+    // Sphere at `center` on object `id`; returns the edit (see history.rs)
+    pub fn dab(&self, scene: &mut Scene, id: usize, center: Vector3<f32>) -> Option<Command> {
+        self.add(scene, id, center, Shape::Sphere { radius: self.size })
+    }
+
+    // Capsule from `from` to `to` on object `id`; returns the edit (see history.rs)
+    pub fn stroke(
+        &self,
+        scene: &mut Scene,
+        id: usize,
+        from: Vector3<f32>,
+        to: Vector3<f32>,
+    ) -> Option<Command> {
+        self.add(
+            scene,
+            id,
+            from,
+            Shape::Capsule {
+                a: [0.0; 3],
+                b: (to - from).into(),
+                radius: self.size,
+            },
+        )
+    }
+
+    fn add(
+        &self,
+        scene: &mut Scene,
+        id: usize,
+        position: Vector3<f32>,
+        shape: Shape,
+    ) -> Option<Command> {
+        let op = match self.mode {
+            BrushMode::Off => return None,
+            BrushMode::Add => CsgOp::SmoothUnion { k: self.blend },
+            BrushMode::Subtract => CsgOp::SmoothSubtract { k: self.blend },
+        };
+        let color = scene
+            .objects()
+            .get(id)
+            .map_or([0.7; 3], |object| object.color);
+        let name = scene.unused_name("sculpt");
+        let node = Node::Object(SceneObject {
+            name,
+            shape,
+            position: position.into(),
+            rotation: [0.0; 3],
+            scale: 1.0,
+            color,
+            op,
+            textured: false,
+            glass: None,
+            bump: None,
+            noise: None,
+            iridescence: None,
+            specular: None,
+            emission: 0.0,
+            repeat: None,
+            cells: None,
+        });
+        let path = scene.add_beside(id, node.clone());
+        Some(Command::Add { path, node })
+    }
+}
@@ -0,0 +1,270 @@
+use cgmath::{InnerSpace, Vector2, Vector3};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+// Where the scene is viewed from
+#[derive(Clone, Copy, Debug)]
+pub struct Camera {
+    pub position: Vector3<f32>,
+    pub target: Vector3<f32>, // Point the camera looks at
+    pub lens: Lens,
+    pub stereo: Stereo,
+    pub region: Region, // Part of the view drawn
+}
+
+// How the view spreads rays over the image: a plain pinhole, an equidistant or equisolid
+// fisheye, a Panini projection (keeps verticals and the center straight at wide angles) or a
+// cylinder (straight verticals, angles across)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Projection {
+    #[default]
+    Pinhole,
+    Fisheye,
+    Equisolid,
+    Panini,
+    Cylindrical,
+}
+
+// The projection and how far it goes from the pinhole view: 0 looks like a pinhole, 1 is the
+// full projection and more keeps widening the view. All of them agree at the center.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Lens {
+    pub projection: Projection,
+    pub amount: f32,
+}
+
+impl Default for Lens {
+    fn default() -> Self {
+        Self {
+            projection: Projection::Pinhole,
+            amount: 1.0,
+        }
+    }
+}
+
+// Stereoscopic views: two eyes `ipd` apart, aimed to meet at `convergence` in front of the
+// camera, drawn as a red/cyan anaglyph or side by side (each eye's view squeezed into half the
+// width, the layout VR video players take)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum StereoMode {
+    #[default]
+    Off,
+    Anaglyph,
+    #[value(name = "sbs")]
+    #[serde(rename = "sbs")]
+    SideBySide,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Stereo {
+    pub mode: StereoMode,
+    pub ipd: f32,         // Distance between the eyes
+    pub convergence: f32, // Distance that appears at the screen, with no parallax
+}
+
+impl Default for Stereo {
+    fn default() -> Self {
+        Self {
+            mode: StereoMode::Off,
+            ipd: 0.2,
+            convergence: 8.0,
+        }
+    }
+}
+
+// The part of a larger view an image shows, as shares of the view's width and height from its
+// top left corner: the whole view, or one screen's tile of a video wall drawn by several
+// instances (see sync.rs). The aspect ratio and pixel size come from the whole view, so tiles
+// of the same size and resolution line up edge to edge.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Region {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Region {
+    // Tile `column`, `row` (from the top left, from 0) of a grid of `columns` by `rows`
+    pub fn tile(column: u32, row: u32, columns: u32, rows: u32) -> Self {
+        Self {
+            x: column as f32 / columns as f32,
+            y: row as f32 / rows as f32,
+            width: 1.0 / columns as f32,
+            height: 1.0 / rows as f32,
+        }
+    }
+
+    // As the shaders take it: from the bottom left, like their uv
+    pub fn flipped(&self) -> [f32; 4] {
+        [self.x, 1.0 - self.y - self.height, self.width, self.height]
+    }
+}
+
+impl Default for Region {
+    fn default() -> Self {
+        Self {
+            x: 0.0,
+            y: 0.0,
+            width: 1.0,
+            height: 1.0,
+        }
+    }
+}
+
+impl Lens {
+    // Direction through `uv` in the view frame (x right, y up, z forward), as the shaders'
+    // lensDirection
+    pub fn direction(&self, uv: Vector2<f32>) -> Vector3<f32> {
+        let a = self.amount;
+        match self.projection {
+            Projection::Pinhole => Vector3::new(uv.x, uv.y, 1.0).normalize(),
+            Projection::Fisheye | Projection::Equisolid => {
+                let r = uv.magnitude();
+                let theta = self.fisheye_angle(r);
+                let side = if r > 0.0 { uv / r } else { uv };
+                (side * theta.sin()).extend(theta.cos())
+            }
+            Projection::Panini => {
+                // Onto a cylinder seen from `a` behind its axis, then out from the axis
+                let view_dist = 1.0 + a;
+                let hyp = uv.x * uv.x + view_dist * view_dist;
+                let isect = uv.x * a;
+                let discrim = (hyp - isect * isect).max(0.0);
+                let cos = (-isect * uv.x + view_dist * discrim.sqrt()) / hyp;
+                let scale = (cos + a) / view_dist;
+                Vector3::new(uv.x * scale, uv.y * scale, cos).normalize()
+            }
+            Projection::Cylindrical => {
+                let (longitude, height) = cylinder(uv.x, a);
+                Vector3::new(longitude.sin(), uv.y / height, longitude.cos()).normalize()
+            }
+        }
+    }
+
+    // Where a view-frame direction lands, inverting `direction` (None outside the image)
+    pub fn view(&self, dir: Vector3<f32>) -> Option<Vector2<f32>> {
+        let a = self.amount;
+        match self.projection {
+            Projection::Pinhole => (dir.z > 1e-3).then(|| Vector2::new(dir.x, dir.y) / dir.z),
+            Projection::Fisheye | Projection::Equisolid => {
+                let side = Vector2::new(dir.x, dir.y);
+                let theta = side.magnitude().atan2(dir.z);
+                let r = solve(theta, |r| self.fisheye_angle(r))?;
+                let length = side.magnitude();
+                Some(if length > 0.0 {
+                    side * (r / length)
+                } else {
+                    side
+                })
+            }
+            Projection::Panini => {
+                let across = (dir.x * dir.x + dir.z * dir.z).sqrt();
+                let longitude = dir.x.atan2(dir.z);
+                let distance = a + longitude.cos();
+                (distance > 1e-3 && across > 1e-6).then(|| {
+                    let scale = (1.0 + a) / distance;
+                    Vector2::new(longitude.sin(), dir.y / across) * scale
+                })
+            }
+            Projection::Cylindrical => {
+                let across = (dir.x * dir.x + dir.z * dir.z).sqrt();
+                let longitude = dir.x.atan2(dir.z);
+                let sign = longitude.signum();
+                let x = sign * solve(longitude.abs(), |x| cylinder(x, a).0)?;
+                (across > 1e-6).then(|| Vector2::new(x, dir.y / across * cylinder(x, a).1))
+            }
+        }
+    }
+
+    // Angle off the view axis at distance `r` from the center of a fisheye
+    fn fisheye_angle(&self, r: f32) -> f32 {
+        let full = match self.projection {
+            Projection::Equisolid => 2.0 * (r * 0.5).min(1.0).asin(),
+            _ => r,
+        };
+        (r.atan() + (full - r.atan()) * self.amount).min(PI)
+    }
+}
+
+// Angle around a cylindrical view at `x` across it, and how far the cylinder is from the
+// eye there (y is divided by it)
+fn cylinder(x: f32, amount: f32) -> (f32, f32) {
+    let longitude = x.atan() + (x - x.atan()) * amount;
+    let height = (1.0 + x * x).sqrt() + (1.0 - (1.0 + x * x).sqrt()) * amount;
+    (longitude, height)
+}
+
+// The input (0..8) where an increasing `f` reaches `value`, by bisection
+fn solve(value: f32, f: impl Fn(f32) -> f32) -> Option<f32> {
+    let (mut low, mut high) = (0.0, 8.0);
+    if f(high) < value {
+        return None;
+    }
+    for _ in 0..32 {
+        let middle = (low + high) * 0.5;
+        if f(middle) < value {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    Some((low + high) * 0.5)
+}
+
+impl Camera {
+    // This camera (lens, stereo and region settings) placed where `other` is
+    pub fn moved_to(&self, other: &Camera) -> Camera {
+        Camera {
+            position: other.position,
+            target: other.target,
+            ..*self
+        }
+    }
+
+    // The shader's view frame: forward, right and up
+    pub fn basis(&self) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>) {
+        let forward = (self.target - self.position).normalize();
+        let right = Vector3::unit_y().cross(forward).normalize();
+        let up = forward.cross(right);
+        (forward, right, up)
+    }
+
+    // Direction of the ray through `uv`: -1..1 from the bottom to the top of the view and
+    // -aspect..aspect from left to right, as the shaders cast them
+    pub fn ray(&self, uv: Vector2<f32>) -> Vector3<f32> {
+        let (forward, right, up) = self.basis();
+        let dir = self.lens.direction(uv);
+        (right * dir.x + up * dir.y + forward * dir.z).normalize()
+    }
+
+    // Where `point` appears in the view, in the same coordinates as `ray` takes (None when it
+    // is behind the camera or outside what the lens sees)
+    pub fn project(&self, point: Vector3<f32>) -> Option<Vector2<f32>> {
+        let (forward, right, up) = self.basis();
+        let offset = point - self.position;
+        if offset.magnitude() < 1e-3 {
+            return None;
+        }
+        let dir = Vector3::new(offset.dot(right), offset.dot(up), offset.dot(forward));
+        self.lens.view(dir.normalize())
+    }
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            position: Vector3::new(0.0, 2.0, -8.0),
+            target: Vector3::new(0.0, 0.0, 0.0),
+            lens: Lens::default(),
+            stereo: Stereo::default(),
+            region: Region::default(),
+        }
+    }
+}
@@ -0,0 +1,92 @@
+// Voronoi cells cut into objects. An object with `cells = { width = 0.02, count = 24 }` is
+// split along the borders of the cells around a set of sites, like stone cracked into
+// blocks; `style = "foam"` keeps only walls `width` either side of the borders instead, like
+// a froth of bubbles. The sites are listed in the object's own space (`sites = [[x, y, z],
+// ...]`) or `count` of them are scattered within `spread` of its origin along each axis, picked
+// by `seed`. With `drift`, each site wanders up to that far on a loop of its own.
+//
+// The sites of every object are filled in on the CPU each frame, into the uniforms' `sites`
+// (one range per object with cells, in object order), so they move without regenerating the
+// shader. The shader measures how far a point is from the border of the cell it is in (see
+// codegen.rs's cellBorder<id>, and `border` below for the CPU's copy).
+use crate::noise::pcg;
+use crate::scene::{Cells, SceneObject};
+use cgmath::{InnerSpace, Vector3};
+use std::f32::consts::TAU;
+
+// Sites the uniforms hold, for all objects together
+pub const MAX_SITES: usize = 64;
+
+// How many sites `cells` has
+pub fn count(cells: &Cells) -> usize {
+    match cells.sites.len() {
+        0 => cells.count,
+        listed => listed,
+    }
+}
+
+// Where the sites of `cells` are `time` seconds in (shader time); they start where they rest
+pub fn sites(cells: &Cells, time: f32) -> Vec<[f32; 3]> {
+    let random = |site: usize, n: u32| {
+        let seed = pcg(cells.seed ^ pcg(site as u32 * 8 + n));
+        seed as f32 / u32::MAX as f32
+    };
+    (0..count(cells))
+        .map(|site| {
+            let rest = match cells.sites.get(site) {
+                Some(position) => *position,
+                None => [0, 1, 2].map(|axis| (random(site, axis) * 2.0 - 1.0) * cells.spread),
+            };
+            if cells.drift == 0.0 {
+                return rest;
+            }
+            let mut position = rest;
+            for (axis, value) in position.iter_mut().enumerate() {
+                let speed = 0.5 + random(site, 3 + axis as u32);
+                let phase = random(site, 6) * TAU + axis as f32;
+                *value += cells.drift * ((time * speed + phase).sin() - phase.sin());
+            }
+            position
+        })
+        .collect()
+}
+
+// The first site of each of `objects` in the uniforms (for those with cells)
+pub fn first_sites(objects: &[&SceneObject]) -> Vec<usize> {
+    let mut first = 0;
+    objects
+        .iter()
+        .map(|object| {
+            let at = first;
+            first += object.cells.as_deref().map_or(0, count);
+            at
+        })
+        .collect()
+}
+
+// The uniforms' `sites`: those of every object with cells at `time`, in object order
+pub fn pack(cells: &[Cells], time: f32) -> [[f32; 4]; MAX_SITES] {
+    let mut packed = [[0.0; 4]; MAX_SITES];
+    let sites = cells.iter().flat_map(|cells| sites(cells, time));
+    for (slot, [x, y, z]) in packed.iter_mut().zip(sites) {
+        *slot = [x, y, z, 0.0];
+    }
+    packed
+}
+
+// How far `p` is from the border of the cell it is in: from the plane halfway between its
+// nearest site and whichever other site's is closest
+pub fn border(p: Vector3<f32>, sites: &[[f32; 3]]) -> f32 {
+    let sites = sites.iter().map(|&site| Vector3::from(site));
+    let Some(a) = sites
+        .clone()
+        .min_by(|a, b| (p - a).magnitude2().total_cmp(&(p - b).magnitude2()))
+    else {
+        return f32::MAX;
+    };
+    sites
+        .map(|b| b - a)
+        .filter(|n| n.magnitude2() > 0.0)
+        .map(|n| (a + 0.5 * n - p).dot(n) / n.magnitude())
+        .fold(f32::MAX, f32::min)
+}
@@ -0,0 +1,281 @@
+// Generated city blocks, a showcase for repetition and many lights. A scene's [city] table
+// lays out a grid of buildings with streets between them, added to its nodes and lights when
+// the scene is loaded:
+//
+//   [city]
+//   blocks = [4, 3]            # Buildings along x and z
+//   lot = 4.0                  # Width of the square each building stands in
+//   street = 2.0               # Width of the streets between lots
+//   height = [3.0, 12.0]       # Lowest and tallest building
+//   storey = 1.0               # Height of a floor, and the spacing of the windows
+//   glow = 1.2                 # Emission of the lit rooms behind the windows
+//   lamp = 3.0                 # Intensity of the street lights at the crossings (0 for none)
+//   seed = 7                   # Picks the buildings' footprints, heights and colors
+//
+// The city stands on y = 0, centered on the origin. Each building is a group: a box with two
+// repeated boxes subtracted for rows of windows (one for the walls facing z, one for those
+// facing x), and a glowing box of rooms just inside that shows through them. A street light
+// is a post, an emissive bulb and a point light. Nothing is drawn per window or per floor, so
+// the cost grows with the buildings and lamps alone.
+//
+// A sequence entry with `tour = true` flies the camera through its scene's city (see
+// sequence.rs and `tour` below).
+use crate::noise::pcg;
+use crate::scene::{CsgOp, Group, Node, PointLight, Repeat, SceneObject, Shape};
+use crate::sequence::CameraKey;
+use serde::{Deserialize, Serialize};
+
+// Most buildings a city may have: each adds four objects to every distance the shader takes
+pub const MAX_BUILDINGS: u32 = 64;
+
+// How deep the windows are set into the walls
+const RECESS: f32 = 0.08;
+
+const LAMP_HEIGHT: f32 = 2.5;
+
+// Height the tour flies down the street at
+const EYE_HEIGHT: f32 = 1.4;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct City {
+    pub blocks: [u32; 2],
+    pub lot: f32,
+    pub street: f32,
+    pub height: [f32; 2],
+    pub storey: f32,
+    pub glow: f32,
+    pub lamp: f32,
+    pub seed: u32,
+}
+
+impl Default for City {
+    fn default() -> Self {
+        Self {
+            blocks: [4, 3],
+            lot: 4.0,
+            street: 2.0,
+            height: [3.0, 12.0],
+            storey: 1.0,
+            glow: 1.2,
+            lamp: 3.0,
+            seed: 0,
+        }
+    }
+}
+
+impl City {
+    // Check the city can be laid out: some buildings but not too many, each of them in a lot
+    // and at least a storey high
+    pub fn validate(&self) -> Result<(), String> {
+        let [x, z] = self.blocks;
+        if x == 0 || z == 0 {
+            return Err("blocks need at least one building along x and z".to_string());
+        }
+        if x.saturating_mul(z) > MAX_BUILDINGS {
+            return Err(format!(
+                "{} buildings, at most {} are supported",
+                x.saturating_mul(z),
+                MAX_BUILDINGS
+            ));
+        }
+        if self.lot <= 0.0 || self.street < 0.0 || self.storey <= 0.0 {
+            return Err("lot and storey must be above 0, and street not below".to_string());
+        }
+        if self.height[0] < self.storey || self.height[1] < self.height[0] {
+            return Err("height must run from at least a storey up".to_string());
+        }
+        Ok(())
+    }
+
+    // Distance between the centers of neighbouring lots
+    fn pitch(&self) -> f32 {
+        self.lot + self.street
+    }
+
+    // Where line `k` of `count` lots' edges is along an axis: the middle of the street there
+    fn street_at(&self, k: u32, count: u32) -> f32 {
+        (k as f32 - count as f32 / 2.0) * self.pitch()
+    }
+
+    // A number in [0, 1) for property `n` of building or lamp `index`
+    fn random(&self, index: u32, n: u32) -> f32 {
+        pcg(self.seed ^ pcg(index * 8 + n)) as f32 / u32::MAX as f32
+    }
+}
+
+// The nodes and point lights of `city`: the buildings, then the street lights
+pub fn generate(city: &City) -> (Vec<Node>, Vec<PointLight>) {
+    let [count_x, count_z] = city.blocks;
+    let mut nodes = Vec::new();
+    for (index, (i, j)) in (0..count_z)
+        .flat_map(|j| (0..count_x).map(move |i| (i, j)))
+        .enumerate()
+    {
+        let center = [
+            city.street_at(i, count_x) + city.pitch() / 2.0,
+            city.street_at(j, count_z) + city.pitch() / 2.0,
+        ];
+        nodes.push(building(city, index as u32, center));
+    }
+
+    let mut lights = Vec::new();
+    if city.lamp > 0.0 {
+        // On a corner of each crossing, out of the middle of the streets
+        let corner = city.street * 0.35;
+        for j in 0..=count_z {
+            for i in 0..=count_x {
+                let n = lights.len() + 1;
+                let x = city.street_at(i, count_x) + corner;
+                let z = city.street_at(j, count_z) + corner;
+                let name = format!("streetlight{}", n);
+                nodes.push(Node::Object(object(
+                    format!("{}_post", name),
+                    Shape::Capsule {
+                        a: [0.0, 0.0, 0.0],
+                        b: [0.0, LAMP_HEIGHT, 0.0],
+                        radius: 0.05,
+                    },
+                    [x, 0.0, z],
+                    [0.15, 0.15, 0.17],
+                )));
+                let bulb = SceneObject {
+                    emission: 4.0,
+                    ..object(
+                        format!("{}_bulb", name),
+                        Shape::Sphere { radius: 0.15 },
+                        [x, LAMP_HEIGHT, z],
+                        [1.0, 0.85, 0.6],
+                    )
+                };
+                nodes.push(Node::Object(bulb));
+                lights.push(PointLight {
+                    color: [1.0, 0.8, 0.55],
+                    intensity: city.lamp,
+                    radius: 0.15,
+                    ..PointLight::new(name, [x, LAMP_HEIGHT - 0.2, z])
+                });
+            }
+        }
+    }
+    (nodes, lights)
+}
+
+// Building `index` in the lot around `center` (x and z): walls with windows cut in rows and
+// columns, and rooms inside
+fn building(city: &City, index: u32, center: [f32; 2]) -> Node {
+    let random = |n| city.random(index, n);
+    let half = [0, 1].map(|n| city.lot / 2.0 * (0.7 + 0.25 * random(n)));
+    let height = city.height[0] + (city.height[1] - city.height[0]) * random(2);
+    let floors = (height / city.storey).round().max(1.0);
+    let height = floors * city.storey;
+    let size = [half[0], height / 2.0, half[1]];
+    let position = [center[0], height / 2.0, center[1]];
+    let grey = 0.45 + 0.3 * random(3);
+    let walls = [grey, grey * 0.97, grey * 0.93];
+
+    // Windows about a storey apart across each wall, a row to a floor; two copies put a window
+    // on opposite walls
+    let columns = [size[0], size[2]].map(|half| (2.0 * half / city.storey).floor().max(1.0));
+    let across = [0, 1].map(|n| 2.0 * [size[0], size[2]][n] / columns[n]);
+    let floors = floors as u32;
+    let front = (
+        [across[0] * 0.3, city.storey * 0.3, RECESS],
+        Repeat {
+            spacing: [across[0], city.storey, 2.0 * size[2]],
+            count: [columns[0] as u32, floors, 2],
+        },
+    );
+    let side = (
+        [RECESS, city.storey * 0.3, across[1] * 0.3],
+        Repeat {
+            spacing: [2.0 * size[0], city.storey, across[1]],
+            count: [2, floors, columns[1] as u32],
+        },
+    );
+
+    let name = format!("building{}", index + 1);
+    let mut children = vec![Node::Object(object(
+        format!("{}_walls", name),
+        Shape::Box { size },
+        position,
+        walls,
+    ))];
+    for (suffix, (size, repeat)) in [("front", front), ("side", side)] {
+        let cut = SceneObject {
+            op: CsgOp::Subtract,
+            repeat: Some(Box::new(repeat)),
+            ..object(
+                format!("{}_{}_windows", name, suffix),
+                Shape::Box { size },
+                position,
+                walls,
+            )
+        };
+        children.push(Node::Object(cut));
+    }
+    let rooms = SceneObject {
+        emission: city.glow * (0.5 + random(4)),
+        ..object(
+            format!("{}_rooms", name),
+            Shape::Box {
+                size: [
+                    size[0] - RECESS / 2.0,
+                    size[1] - RECESS / 2.0,
+                    size[2] - RECESS / 2.0,
+                ],
+            },
+            position,
+            [1.0, 0.8, 0.5],
+        )
+    };
+    children.push(Node::Object(rooms));
+    Node::Group(Group {
+        name,
+        op: CsgOp::Union,
+        children,
+    })
+}
+
+fn object(name: String, shape: Shape, position: [f32; 3], color: [f32; 3]) -> SceneObject {
+    SceneObject {
+        name,
+        shape,
+        position,
+        rotation: [0.0; 3],
+        scale: 1.0,
+        color,
+        op: CsgOp::Union,
+        textured: false,
+        glass: None,
+        bump: None,
+        noise: None,
+        iridescence: None,
+        specular: None,
+        emission: 0.0,
+        repeat: None,
+        cells: None,
+    }
+}
+
+// Camera keys flying through `city` over `duration` seconds: down the middle street along x at
+// head height, then up over the far corner and back above the rooftops, looking down on it
+pub fn tour(city: &City, duration: f32) -> Vec<CameraKey> {
+    let [count_x, count_z] = city.blocks;
+    let z = city.street_at(count_z / 2, count_z);
+    let end = city.street_at(count_x, count_x);
+    let pitch = city.pitch();
+    let top = city.height[1] * 1.3;
+    let key = |share: f32, position: [f32; 3], target: [f32; 3]| CameraKey {
+        time: share * duration,
+        position,
+        target,
+    };
+    vec![
+        key(0.0, [-end - pitch, EYE_HEIGHT, z], [-end, EYE_HEIGHT, z]),
+        key(0.25, [0.0, EYE_HEIGHT, z], [pitch, EYE_HEIGHT, z]),
+        key(0.5, [end, EYE_HEIGHT, z], [end + pitch, EYE_HEIGHT, z]),
+        key(0.75, [end + pitch, top, z + pitch], [0.0, 0.0, 0.0]),
+        key(1.0, [0.0, top * 1.5, end + 2.0 * pitch], [0.0, 0.0, 0.0]),
+    ]
+}
@@ -0,0 +1,346 @@
+use crate::backend::BackendKind;
+#[cfg(feature = "offline")]
+use crate::control::Control;
+use crate::livecode;
+use crate::sync;
+use clap::Parser;
+use metal_raymarcher::camera::{Projection, Region, StereoMode};
+use metal_raymarcher::edges::EdgeMode;
+use metal_raymarcher::quality::{AaMode, QualityPreset};
+use std::net::SocketAddr;
+#[cfg(feature = "offline")]
+use std::ops::Range;
+use std::path::PathBuf;
+
+// Command line options; anything left unset falls back to the config file, then defaults
+#[derive(Parser, Debug)]
+#[command(
+    name = "metal-raymarcher",
+    version,
+    about = "Raymarched SDF scenes rendered with Metal"
+)]
+pub struct Cli {
+    /// Window width (logical pixels), or output width for --render
+    #[arg(long)]
+    pub width: Option<u32>,
+
+    /// Window height (logical pixels), or output height for --render
+    #[arg(long)]
+    pub height: Option<u32>,
+
+    /// Start in borderless fullscreen
+    #[arg(long)]
+    pub fullscreen: bool,
+
+    /// Start without the window geometry, camera, panels and scene the last run ended with
+    #[arg(long)]
+    pub fresh: bool,
+
+    /// Scene description file (TOML)
+    #[arg(long, value_name = "FILE")]
+    pub scene: Option<PathBuf>,
+
+    /// Metal or WGSL shader to use instead of the built-in one ("// @scene@" is replaced by the
+    /// scene code, "// @noise@" by the noise functions)
+    #[arg(long, value_name = "FILE")]
+    pub shader: Option<PathBuf>,
+
+    /// Render offline to PNG files in DIR instead of opening a window
+    #[cfg(feature = "offline")]
+    #[arg(long, value_name = "DIR")]
+    pub render: Option<PathBuf>,
+
+    /// Check the shader's distance, normal and noise functions against the CPU's on the GPU
+    /// for the scene (or each scene of a --sequence), then exit
+    #[arg(long)]
+    pub test_shaders: bool,
+
+    /// Print each capture's scene, camera and renderer settings over its bottom left corner
+    /// (they are written into the file's metadata either way)
+    #[cfg(feature = "offline")]
+    #[arg(long)]
+    pub stamp: bool,
+
+    /// Number of frames to render with --render (default 1, or all of a --sequence)
+    #[cfg(feature = "offline")]
+    #[arg(long, requires = "render")]
+    pub frames: Option<u32>,
+
+    /// Render only frames START to END (END not included) of --render, after bringing the
+    /// scene up to date through the frames before them
+    #[cfg(feature = "offline")]
+    #[arg(long, value_name = "START..END", requires = "render", value_parser = frame_range)]
+    pub frame_range: Option<Range<usize>>,
+
+    /// With --render, hand the frames out to --worker processes that connect on ADDRESS (a port
+    /// alone for this machine only, or e.g. 0.0.0.0:7000) and collect them in DIR, instead of
+    /// rendering them here
+    #[cfg(feature = "offline")]
+    #[arg(long, value_name = "ADDRESS", requires = "render", value_parser = livecode::address)]
+    pub distribute: Option<SocketAddr>,
+
+    /// Take JSON-RPC calls (set_param, load_scene, render_frame, save_image, ...) one per line
+    /// on stdin and answer them, with events, on stdout, instead of opening a window
+    #[cfg(feature = "offline")]
+    #[arg(long, value_enum, conflicts_with_all = ["render", "worker"])]
+    pub control: Option<Control>,
+
+    /// Render frames for the --distribute coordinator at HOST:PORT until it has them all
+    #[cfg(feature = "offline")]
+    #[arg(long, value_name = "HOST:PORT", conflicts_with = "render")]
+    pub worker: Option<String>,
+
+    /// GPU to use, by index or part of its name
+    #[arg(long, value_name = "NAME|INDEX")]
+    pub gpu: Option<String>,
+
+    /// GPU backend (default: metal on macOS, wgpu elsewhere)
+    #[arg(long, value_enum)]
+    pub backend: Option<BackendKind>,
+
+    /// Render resolution scale relative to the window size
+    #[arg(long)]
+    pub scale: Option<f32>,
+
+    /// Quality preset (default: chosen for the GPU)
+    #[arg(long, value_enum)]
+    pub quality: Option<QualityPreset>,
+
+    /// Anti-aliasing: a grid of rays per pixel (the preset's) or cone tracing
+    #[arg(long, value_enum)]
+    pub aa: Option<AaMode>,
+
+    /// Supersample only the tiles of the image with edges or fine detail (the config's
+    /// [adaptive], or the defaults: 3x3 rays in 16-pixel tiles), one ray per pixel elsewhere
+    #[arg(long)]
+    pub adaptive: bool,
+
+    /// Side in pixels of --adaptive's tiles
+    #[arg(long, value_name = "PX", value_parser = clap::value_parser!(u32).range(1..))]
+    pub adaptive_tile: Option<u32>,
+
+    /// Rays per pixel per axis in --adaptive's detailed tiles, 0 for a cone
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(0..=8))]
+    pub adaptive_samples: Option<u32>,
+
+    /// Experimental: find the 16-pixel tiles each top-level object or group can be seen in by
+    /// drawing its bounding sphere with mesh shaders, and march each tile without the rest
+    /// (Metal on macOS 13 or later; not with --adaptive or path tracing)
+    #[arg(long)]
+    pub culling: bool,
+
+    /// Camera projection, for wide-angle shots
+    #[arg(long, value_enum)]
+    pub projection: Option<Projection>,
+
+    /// How far the projection goes from the pinhole view (0 = pinhole, 1 = full)
+    #[arg(long, value_name = "AMOUNT")]
+    pub lens_amount: Option<f32>,
+
+    /// Stereoscopic 3D: a red/cyan anaglyph or the two eyes side by side
+    #[arg(long, value_enum)]
+    pub stereo: Option<StereoMode>,
+
+    /// Distance between the eyes for --stereo, in scene units
+    #[arg(long)]
+    pub ipd: Option<f32>,
+
+    /// Distance from the camera that appears at the screen for --stereo
+    #[arg(long)]
+    pub convergence: Option<f32>,
+
+    /// Draw only part of the view, as shares of its width and height from the top left (e.g.
+    /// 0.5,0,0.5,1 for the right half), for one screen of a video wall
+    #[arg(long, value_name = "X,Y,W,H", value_parser = region, conflicts_with = "tile")]
+    pub region: Option<Region>,
+
+    /// Draw tile COL, ROW (from 0 at the top left) of a grid of COLS by ROWS screens
+    #[arg(long, value_name = "COL,ROW,COLS,ROWS", value_parser = tile)]
+    pub tile: Option<Region>,
+
+    /// Toon shading for every scene (the config's [toon], or the defaults)
+    #[arg(long)]
+    pub toon: bool,
+
+    /// Lit steps of --toon shading
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub toon_bands: Option<u32>,
+
+    /// Width of --toon ink outlines in pixels (0 for none)
+    #[arg(long, value_name = "PX")]
+    pub outline: Option<f32>,
+
+    /// Lines where the scene's depth or normals change sharply, over the image or alone
+    #[arg(long, value_enum)]
+    pub edges: Option<EdgeMode>,
+
+    /// Width of --edges lines in pixels
+    #[arg(long, value_name = "PX")]
+    pub edge_width: Option<f32>,
+
+    /// Volumetric light shafts through the scene's fog (the config's [shafts], or the defaults)
+    #[arg(long)]
+    pub shafts: bool,
+
+    /// March steps per pixel of --shafts
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub shaft_samples: Option<u32>,
+
+    /// Brightness of --shafts
+    #[arg(long, value_name = "AMOUNT")]
+    pub shaft_intensity: Option<f32>,
+
+    /// Seed for the path tracer's random numbers and the light shafts' jitter; the same seed,
+    /// scene and camera draw the same image. It also offsets every scene's noise_seed, so 0
+    /// keeps the scene's own noise.
+    #[arg(long, value_name = "N")]
+    pub seed: Option<u32>,
+
+    /// Accept remote-control WebSocket connections from this machine on PORT
+    #[arg(long, value_name = "PORT")]
+    pub remote: Option<u16>,
+
+    /// Serve the latest frame, stats, parameters and metrics over HTTP to this machine on PORT
+    #[arg(long, value_name = "PORT")]
+    pub http: Option<u16>,
+
+    /// Open a shared view (metal-raymarcher://view/..., see Cmd+C)
+    #[arg(long, value_name = "LINK")]
+    pub view: Option<String>,
+
+    /// Take shader source pushed over TCP or WebSocket on ADDRESS and switch to it once it
+    /// builds: a port alone for this machine only (e.g. 7000), or e.g. 0.0.0.0:7000
+    #[arg(long, value_name = "ADDRESS", value_parser = livecode::address)]
+    pub livecode: Option<SocketAddr>,
+
+    /// Take Art-Net DMX from a lighting desk and drive parameters with it, mapped by FILE
+    #[arg(long, value_name = "FILE")]
+    pub artnet: Option<PathBuf>,
+
+    /// Send each frame's time and camera over UDP to ADDRESS (e.g. 192.168.1.255:7400), for
+    /// --sync-follow instances to draw the same frames
+    #[arg(long, value_name = "ADDRESS", conflicts_with = "sync_follow")]
+    pub sync_master: Option<SocketAddr>,
+
+    /// Draw the frames a --sync-master sends to UDP PORT instead of keeping time (or to
+    /// ADDRESS, e.g. [::]:7400 for IPv6)
+    #[arg(long, value_name = "PORT", value_parser = sync::follow_address)]
+    pub sync_follow: Option<SocketAddr>,
+
+    /// Keep input-to-photon latency down at the cost of frame rate: two drawables, and each
+    /// frame finished before the next starts
+    #[arg(long)]
+    pub low_latency: bool,
+
+    /// Draw frames at exactly FPS (e.g. 30 or 60) scene-time steps, each presented at its own
+    /// time, for screen recordings without judder
+    #[arg(long, value_name = "FPS", value_parser = rate)]
+    pub present_rate: Option<f32>,
+
+    /// Load scene (.toml) and shader (.metal, .wgsl) files as they are written into DIR
+    #[arg(long, value_name = "DIR")]
+    pub watch: Option<PathBuf>,
+
+    /// Start in progressive path-tracing mode (toggle with P)
+    #[arg(long)]
+    pub path_trace: bool,
+
+    /// Save the path-traced image to the capture directory once it has N paths per pixel
+    #[cfg(feature = "offline")]
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..=4096))]
+    pub samples: Option<u32>,
+
+    /// Config file (TOML); defaults to config.toml in the platform config directory
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Video texture source: "camera" or a video file path
+    #[arg(long, value_name = "camera|FILE")]
+    pub video: Option<String>,
+
+    /// Camera to use with --video camera (part of its name)
+    #[arg(long, value_name = "NAME")]
+    pub video_device: Option<String>,
+
+    /// Screen capture target: "display", "display:<index>" or "window:<title>"
+    #[arg(long, value_name = "TARGET")]
+    pub screen: Option<String>,
+
+    /// Audio input for audio-reactive scenes: "default" or part of a device name
+    #[arg(long, value_name = "default|NAME")]
+    pub audio: Option<String>,
+
+    /// Tempo of the musical clock in beats per minute (tap tempo with T)
+    #[arg(long, value_name = "BPM")]
+    pub bpm: Option<f32>,
+
+    /// Record the session (per-frame camera, inputs and parameters) to FILE
+    #[arg(long, value_name = "FILE")]
+    pub record: Option<PathBuf>,
+
+    /// Play back a recorded session; with --render, re-render each of its frames
+    #[arg(long, value_name = "FILE", conflicts_with = "record")]
+    pub replay: Option<PathBuf>,
+
+    /// Start from a saved workspace (without FILE: the one saved on the last exit)
+    #[arg(
+        long,
+        value_name = "FILE",
+        num_args = 0..=1,
+        conflicts_with_all = ["scene", "replay", "sequence"]
+    )]
+    pub restore: Option<Option<PathBuf>>,
+
+    /// Play a demo sequence of scenes (see scenes/demo.toml) instead of --scene
+    #[arg(long, value_name = "FILE", conflicts_with = "replay")]
+    pub sequence: Option<PathBuf>,
+}
+
+// START..END for --frame-range
+#[cfg(feature = "offline")]
+fn frame_range(text: &str) -> Result<Range<usize>, String> {
+    let parsed = text
+        .split_once("..")
+        .and_then(|(start, end)| Some(start.trim().parse().ok()?..end.trim().parse().ok()?));
+    match parsed {
+        Some(range) if !range.is_empty() => Ok(range),
+        Some(_) => Err("END must be after START".to_string()),
+        None => Err("expected START..END, e.g. 0..100".to_string()),
+    }
+}
+
+// Four comma-separated numbers
+fn four<T: std::str::FromStr>(text: &str, example: &str) -> Result<[T; 4], String> {
+    let numbers: Option<Vec<T>> = (text.split(',')).map(|n| n.trim().parse().ok()).collect();
+    (numbers.and_then(|numbers| numbers.try_into().ok()))
+        .ok_or_else(|| format!("expected four numbers, e.g. {}", example))
+}
+
+fn region(text: &str) -> Result<Region, String> {
+    let [x, y, width, height] = four::<f32>(text, "0.5,0,0.5,1")?;
+    if [x, y, width, height].iter().any(|n| !n.is_finite()) || width <= 0.0 || height <= 0.0 {
+        return Err("the width and height must be over 0".to_string());
+    }
+    Ok(Region {
+        x,
+        y,
+        width,
+        height,
+    })
+}
+
+// Frames per second for --present-rate
+fn rate(text: &str) -> Result<f32, String> {
+    match text.trim().parse::<f32>() {
+        Ok(rate) if rate.is_finite() && rate > 0.0 => Ok(rate),
+        _ => Err("expected frames per second over 0, e.g. 30".to_string()),
+    }
+}
+
+fn tile(text: &str) -> Result<Region, String> {
+    let [column, row, columns, rows] = four::<u32>(text, "1,0,3,2")?;
+    if column >= columns || row >= rows {
+        return Err("the tile must be inside the grid".to_string());
+    }
+    Ok(Region::tile(column, row, columns, rows))
+}
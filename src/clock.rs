@@ -0,0 +1,182 @@
+// Musical clock for live performance: a tempo (fixed in the config's [clock] section or
+// with --bpm, or tapped with T) and the beat and bar phase it gives at any time. Shaders
+// read it as `uniforms.clock` (beats since the start, beat phase, bar phase, bpm),
+// expressions as `clock.beats`, `clock.beat`, `clock.bar` and `clock.bpm`, and scripts
+// through the `clock` map.
+//
+// Scenes can define cues, fired with the number keys 1-9 on the next beat or bar:
+//
+//   [[cues]]
+//   name = "red"
+//   quantize = "beat"                       # "now", "beat" or "bar" (the default)
+//   set = { "ball.color" = [1.0, 0.1, 0.1], "ball.radius" = "0.8 + 0.2 * clock.beat" }
+//
+//   [[cues]]
+//   scene = "pulse.toml"                    # Switch scenes, relative to the scene file
+//
+// `set` takes the same values and expressions as [expressions], evaluated once when the
+// cue fires.
+use crate::expression::{Binding, Variables};
+use crate::scene::Scene;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+// Taps further apart than this start a new tempo
+const TAP_TIMEOUT: f32 = 2.0;
+// Taps averaged for the tempo
+const MAX_TAPS: usize = 8;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClockConfig {
+    pub bpm: f32,
+    pub beats_per_bar: u32,
+}
+
+impl Default for ClockConfig {
+    fn default() -> Self {
+        Self {
+            bpm: 120.0,
+            beats_per_bar: 4,
+        }
+    }
+}
+
+// The clock at one moment
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ClockPhase {
+    pub beats: f32, // Beats since the first downbeat, fractional
+    pub beat: f32,  // 0..1 through the current beat
+    pub bar: f32,   // 0..1 through the current bar
+    pub bpm: f32,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Quantize {
+    Now,
+    Beat,
+    #[default]
+    Bar,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Cue {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub quantize: Quantize,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub set: BTreeMap<String, Binding>, // Parameter address -> value or expressions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scene: Option<PathBuf>, // Scene to switch to
+}
+
+impl Cue {
+    // Write the cue's values into `scene`. Returns true when a parameter that was baked
+    // into the shader became live and the shader must be regenerated.
+    pub fn apply(&self, scene: &mut Scene, variables: &Variables) -> Result<bool, String> {
+        let mut rebuild = false;
+        for (target, binding) in &self.set {
+            if scene.parameter(target).is_none() {
+                return Err(format!("cue target \"{}\" not found", target));
+            }
+            rebuild |= scene.make_live(target)?;
+            if let Some(parameter) = scene.parameter_mut(target) {
+                for (value, expression) in parameter.iter_mut().zip(binding.components()) {
+                    *value = expression.eval(variables);
+                }
+            }
+        }
+        Ok(rebuild)
+    }
+}
+
+pub struct Clock {
+    bpm: f32,
+    beats_per_bar: u32,
+    origin: f32,    // Time of the first downbeat
+    taps: Vec<f32>, // Recent taps of the current tempo
+    tap_count: u32, // Taps since the tempo started
+}
+
+impl Clock {
+    pub fn new(config: &ClockConfig) -> Self {
+        Self {
+            bpm: config.bpm.clamp(1.0, 999.0),
+            beats_per_bar: config.beats_per_bar.max(1),
+            origin: 0.0,
+            taps: Vec::new(),
+            tap_count: 0,
+        }
+    }
+
+    pub fn bpm(&self) -> f32 {
+        self.bpm
+    }
+
+    // Time of the first downbeat
+    pub fn downbeat(&self) -> f32 {
+        self.origin
+    }
+
+    // Continue at `bpm` from a downbeat at `downbeat` (a restored workspace's tempo)
+    pub fn set_tempo(&mut self, bpm: f32, downbeat: f32) {
+        self.bpm = bpm.clamp(1.0, 999.0);
+        self.origin = downbeat;
+        self.taps.clear();
+        self.tap_count = 0;
+    }
+
+    pub fn phase(&self, time: f32) -> ClockPhase {
+        let beats = self.beats(time);
+        ClockPhase {
+            beats,
+            beat: beats.rem_euclid(1.0),
+            bar: (beats / self.beats_per_bar as f32).rem_euclid(1.0),
+            bpm: self.bpm,
+        }
+    }
+
+    // Tap tempo. The first tap after a pause restarts the bar on it; the following ones set
+    // the tempo from their average interval and keep the downbeat on the first tap.
+    pub fn tap(&mut self, time: f32) {
+        if self
+            .taps
+            .last()
+            .is_none_or(|&last| time - last > TAP_TIMEOUT || time <= last)
+        {
+            self.taps.clear();
+            self.tap_count = 0;
+        }
+        self.taps.push(time);
+        self.tap_count += 1;
+        if self.taps.len() > MAX_TAPS {
+            self.taps.remove(0);
+        }
+
+        if let [first, .., last] = self.taps[..] {
+            self.bpm = 60.0 * (self.taps.len() - 1) as f32 / (last - first);
+        }
+        self.origin = time - (self.tap_count - 1) as f32 * 60.0 / self.bpm;
+    }
+
+    // When a change quantized to `quantize` and requested at `time` takes effect
+    pub fn next(&self, time: f32, quantize: Quantize) -> f32 {
+        let beats = self.beats(time);
+        let beat = match quantize {
+            Quantize::Now => return time,
+            Quantize::Beat => beats.ceil(),
+            Quantize::Bar => {
+                let bar = self.beats_per_bar as f32;
+                (beats / bar).ceil() * bar
+            }
+        };
+        self.origin + beat * 60.0 / self.bpm
+    }
+
+    fn beats(&self, time: f32) -> f32 {
+        (time - self.origin) * self.bpm / 60.0
+    }
+}
@@ -0,0 +1,401 @@
+// Point clouds, for the cloud shape (see scene.rs): scans read from PLY or XYZ files and drawn
+// as small spheres blended into one surface, each in the color it was scanned in.
+//
+// XYZ files have a point to a line, "x y z" optionally followed by "r g b" (0 to 255, or 0 to
+// 1 when none is above 1). PLY files may be ASCII or binary, and give their points as the
+// vertex element's x, y and z, with red, green and blue if they have them; faces and other
+// elements are skipped. Points without a color are white, so the object's color shows.
+//
+// The points are fitted like an SVG outline (centered, the longest side 2 units) and thinned
+// to the shape's budget by averaging those sharing a cell of a grid, its cells grown until
+// few enough are filled. The shader visits every point for every distance it takes (see
+// codegen.rs's sdCloud<id>), so the budget is the price of the cloud.
+use std::collections::BTreeMap;
+use std::path::Path;
+
+// Most points a cloud may keep
+pub const MAX_POINTS: usize = 512;
+
+// Most times thinning grows its cells, each time by a fifth (over 100,000 times in all)
+const MAX_PASSES: i32 = 64;
+
+// Radius of the spheres against the points' average spacing, so neighbours overlap
+const OVERLAP: f32 = 0.75;
+
+// A point: position, then color
+pub type Point = [f32; 6];
+
+// Read the cloud in the file at `path` (by its extension), fitted and thinned to `budget`
+pub fn load(path: &Path, budget: usize) -> Result<Vec<Point>, String> {
+    if budget == 0 {
+        return Err(format!(
+            "Point cloud {} needs a budget of at least 1",
+            path.display()
+        ));
+    }
+    let bytes =
+        std::fs::read(path).map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    let points = match extension.as_deref() {
+        Some("ply") => parse_ply(&bytes),
+        Some("xyz") => Ok(parse_xyz(&String::from_utf8_lossy(&bytes))),
+        _ => Err("not a .ply or .xyz file".to_string()),
+    }
+    .map_err(|err| format!("Invalid point cloud {}: {}", path.display(), err))?;
+    let points: Vec<Point> = (points.into_iter())
+        .filter(|point| point.iter().all(|value| value.is_finite()))
+        .collect();
+    if points.is_empty() {
+        return Err(format!("Point cloud {} has no points", path.display()));
+    }
+    Ok(thin(fit(points), budget.min(MAX_POINTS)))
+}
+
+// The points of an XYZ file, skipping lines that don't start with three numbers
+pub fn parse_xyz(text: &str) -> Vec<Point> {
+    let mut points = Vec::new();
+    for line in text.lines() {
+        let numbers: Vec<f32> = line
+            .split(|c: char| c.is_whitespace() || c == ',' || c == ';')
+            .filter(|word| !word.is_empty())
+            .map_while(|word| word.parse().ok())
+            .collect();
+        match numbers[..] {
+            [x, y, z, r, g, b, ..] => points.push([x, y, z, r, g, b]),
+            [x, y, z, ..] => points.push([x, y, z, 1.0, 1.0, 1.0]),
+            _ => {}
+        }
+    }
+    // Colors from 0 to 255 unless none goes above 1
+    if points
+        .iter()
+        .any(|point| point[3..].iter().any(|&c| c > 1.0))
+    {
+        for point in &mut points {
+            for c in &mut point[3..] {
+                *c /= 255.0;
+            }
+        }
+    }
+    points
+}
+
+// Type of a PLY property: its size in bytes, and whether it is an integer of that many bytes
+// (colors in integers run up to their largest value)
+#[derive(Clone, Copy)]
+enum Scalar {
+    Int { size: usize, signed: bool },
+    Float { size: usize },
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Result<Self, String> {
+        Ok(match name {
+            "char" | "int8" => Scalar::Int {
+                size: 1,
+                signed: true,
+            },
+            "uchar" | "uint8" => Scalar::Int {
+                size: 1,
+                signed: false,
+            },
+            "short" | "int16" => Scalar::Int {
+                size: 2,
+                signed: true,
+            },
+            "ushort" | "uint16" => Scalar::Int {
+                size: 2,
+                signed: false,
+            },
+            "int" | "int32" => Scalar::Int {
+                size: 4,
+                signed: true,
+            },
+            "uint" | "uint32" => Scalar::Int {
+                size: 4,
+                signed: false,
+            },
+            "float" | "float32" => Scalar::Float { size: 4 },
+            "double" | "float64" => Scalar::Float { size: 8 },
+            _ => return Err(format!("unknown property type \"{}\"", name)),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::Int { size, .. } | Scalar::Float { size } => size,
+        }
+    }
+
+    // Largest value of an unsigned integer, 1 for anything else
+    fn range(self) -> f32 {
+        match self {
+            Scalar::Int {
+                size,
+                signed: false,
+            } => (256f64.powi(size as i32) - 1.0) as f32,
+            _ => 1.0,
+        }
+    }
+
+    // The value in `bytes` (as many as the size), little or big endian
+    fn read(self, bytes: &[u8], little: bool) -> f64 {
+        let mut raw = [0u8; 8];
+        let size = self.size();
+        raw[..size].copy_from_slice(&bytes[..size]);
+        if !little {
+            raw[..size].reverse();
+        }
+        let unsigned = u64::from_le_bytes(raw);
+        match self {
+            Scalar::Int { signed: false, .. } => unsigned as f64,
+            Scalar::Int { size, signed: true } => {
+                let shift = 64 - 8 * size as u32;
+                ((unsigned << shift) as i64 >> shift) as f64
+            }
+            Scalar::Float { size: 4 } => f32::from_bits(unsigned as u32) as f64,
+            Scalar::Float { .. } => f64::from_bits(unsigned),
+        }
+    }
+}
+
+enum Property {
+    Scalar(String, Scalar),
+    List(Scalar, Scalar), // Type of the count, then of the items
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+// The vertices of a PLY file, ASCII or binary
+pub fn parse_ply(bytes: &[u8]) -> Result<Vec<Point>, String> {
+    let end = b"end_header";
+    let header_end = bytes
+        .windows(end.len())
+        .position(|window| window == end)
+        .ok_or("no end_header")?;
+    let header = String::from_utf8_lossy(&bytes[..header_end]);
+    let mut body = &bytes[header_end + end.len()..];
+    // The header ends with a line break
+    if body.starts_with(b"\r") {
+        body = &body[1..];
+    }
+    if body.starts_with(b"\n") {
+        body = &body[1..];
+    }
+
+    let mut lines = header.lines().map(str::trim);
+    if lines.next() != Some("ply") {
+        return Err("not a PLY file".to_string());
+    }
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            ["format", name, ..] => format = Some(name.to_string()),
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| format!("bad count in \"{}\"", line))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, _] => elements
+                .last_mut()
+                .ok_or("property before any element")?
+                .properties
+                .push(Property::List(Scalar::parse(count)?, Scalar::parse(item)?)),
+            ["property", kind, name] => elements
+                .last_mut()
+                .ok_or("property before any element")?
+                .properties
+                .push(Property::Scalar(name.to_string(), Scalar::parse(kind)?)),
+            _ => {}
+        }
+    }
+    let little = match format.as_deref() {
+        Some("ascii") => return ply_ascii(&String::from_utf8_lossy(body), &elements),
+        Some("binary_little_endian") => true,
+        Some("binary_big_endian") => false,
+        _ => return Err("unknown format".to_string()),
+    };
+
+    let truncated = || "file ends early".to_string();
+    let mut at = 0;
+    for element in &elements {
+        let mut points = Vec::new();
+        for _ in 0..element.count {
+            let mut values = Vec::new();
+            for property in &element.properties {
+                match property {
+                    Property::Scalar(_, scalar) => {
+                        let bytes = body.get(at..at + scalar.size()).ok_or_else(truncated)?;
+                        values.push(scalar.read(bytes, little));
+                        at += scalar.size();
+                    }
+                    Property::List(count, item) => {
+                        let bytes = body.get(at..at + count.size()).ok_or_else(truncated)?;
+                        let items = count.read(bytes, little) as usize;
+                        at += count.size() + items * item.size();
+                        values.push(0.0);
+                    }
+                }
+            }
+            if element.name == "vertex" {
+                points.push(vertex(element, &values)?);
+            }
+        }
+        if element.name == "vertex" {
+            return Ok(points);
+        }
+    }
+    Err("no vertex element".to_string())
+}
+
+fn ply_ascii(body: &str, elements: &[Element]) -> Result<Vec<Point>, String> {
+    let mut lines = body.lines().filter(|line| !line.trim().is_empty());
+    for element in elements {
+        if element.name != "vertex" {
+            lines.by_ref().take(element.count).for_each(drop);
+            continue;
+        }
+        let mut points = Vec::new();
+        for line in lines.by_ref().take(element.count) {
+            let values: Vec<f64> = line
+                .split_whitespace()
+                .map(|word| word.parse().map_err(|_| format!("bad number \"{}\"", word)))
+                .collect::<Result<_, _>>()?;
+            points.push(vertex(element, &values)?);
+        }
+        return Ok(points);
+    }
+    Err("no vertex element".to_string())
+}
+
+// A point from the values of a vertex's properties, in order
+fn vertex(element: &Element, values: &[f64]) -> Result<Point, String> {
+    let mut point = [0.0, 0.0, 0.0, 1.0, 1.0, 1.0];
+    let mut found = [false; 3];
+    for (property, value) in element.properties.iter().zip(values) {
+        let Property::Scalar(name, scalar) = property else {
+            continue;
+        };
+        let slot = match name.as_str() {
+            "x" => 0,
+            "y" => 1,
+            "z" => 2,
+            "red" | "r" | "diffuse_red" => 3,
+            "green" | "g" | "diffuse_green" => 4,
+            "blue" | "b" | "diffuse_blue" => 5,
+            _ => continue,
+        };
+        point[slot] = match slot {
+            0..=2 => {
+                found[slot] = true;
+                *value as f32
+            }
+            _ => (*value as f32 / scalar.range()).clamp(0.0, 1.0),
+        };
+    }
+    if found != [true; 3] {
+        return Err("vertices need x, y and z".to_string());
+    }
+    Ok(point)
+}
+
+// Centered on the origin, with the longest side of their bounds 2 units
+fn fit(mut points: Vec<Point>) -> Vec<Point> {
+    let (mut min, mut max) = ([f32::MAX; 3], [f32::MIN; 3]);
+    for point in &points {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(point[axis]);
+            max[axis] = max[axis].max(point[axis]);
+        }
+    }
+    let side = (0..3)
+        .map(|axis| max[axis] - min[axis])
+        .fold(f32::EPSILON, f32::max);
+    for point in &mut points {
+        for axis in 0..3 {
+            point[axis] = (point[axis] - (min[axis] + max[axis]) / 2.0) * 2.0 / side;
+        }
+    }
+    points
+}
+
+// At most `budget` points: the average of those in each cell of a grid, coarsened until few
+// enough cells have any (or, after MAX_PASSES, those of the first `budget` cells)
+fn thin(points: Vec<Point>, budget: usize) -> Vec<Point> {
+    if points.len() <= budget {
+        return points;
+    }
+    // Scans are surfaces, so their cells fill roughly with the square of the resolution
+    let mut cell = 2.0 / (budget as f32).sqrt();
+    for pass in 0..=MAX_PASSES {
+        let mut cells: BTreeMap<[i32; 3], (Point, f32)> = BTreeMap::new();
+        for point in &points {
+            let key = [0, 1, 2].map(|axis| (point[axis] / cell).floor() as i32);
+            let (sum, count) = cells.entry(key).or_insert(([0.0; 6], 0.0));
+            for (total, value) in sum.iter_mut().zip(point) {
+                *total += value;
+            }
+            *count += 1.0;
+        }
+        if cells.len() <= budget || pass == MAX_PASSES {
+            return (cells.into_values().take(budget))
+                .map(|(sum, count)| sum.map(|total| total / count))
+                .collect();
+        }
+        cell *= 1.2;
+    }
+    unreachable!()
+}
+
+// Radius of the spheres drawn for `points`: a little over half the average distance from a
+// point to its nearest neighbour, so the spheres join up
+pub fn fitted_radius(points: &[Point]) -> f32 {
+    if points.len() < 2 {
+        return 0.1;
+    }
+    let distance = |a: &Point, b: &Point| {
+        (0..3)
+            .map(|axis| (a[axis] - b[axis]).powi(2))
+            .sum::<f32>()
+            .sqrt()
+    };
+    let total: f32 = (points.iter().enumerate())
+        .map(|(i, a)| {
+            (points.iter().enumerate())
+                .filter(|(j, _)| *j != i)
+                .map(|(_, b)| distance(a, b))
+                .fold(f32::MAX, f32::min)
+        })
+        .sum();
+    OVERLAP * total / points.len() as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thinning_keeps_to_small_budgets() {
+        let grid = (0..1000).map(|i| [(i % 10) as f32, (i / 10 % 10) as f32, (i / 100) as f32]);
+        let points = fit(grid.map(|[x, y, z]| [x, y, z, 1.0, 1.0, 1.0]).collect());
+        for budget in 1..10 {
+            let thinned = thin(points.clone(), budget);
+            assert!(!thinned.is_empty() && thinned.len() <= budget, "{}", budget);
+        }
+        let path = std::env::temp_dir().join("thinning_keeps_to_small_budgets.xyz");
+        std::fs::write(&path, "0 0 0\n1 1 1\n").unwrap();
+        assert!(load(&path, 0).is_err());
+        assert_eq!(load(&path, 1).unwrap().len(), 1);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,1892 @@
+// Generates the scene functions used by the shaders from a `Scene`:
+//   sceneMap(p)       -> (distance, object id); in MSL, top-level nodes whose bit is clear in
+//                        an optional `cull` mask are left out (see proxies)
+//   sceneObject(p, id) -> distance to one object on its own (for the selection outline)
+//   sceneColor(id)    -> base color of an object
+//   sceneColorAt(p, id, color) -> the color of an object at p from its base color: a point
+//                        cloud's points' colors times it, the base color for anything else
+//   sceneLight(p, n)  -> diffuse light from the scene's point lights at p (normal n)
+//   sceneLightSphere(i), sceneLightPower(i) -> center and radius, and color times intensity,
+//                        of light i of SCENE_LIGHTS (for the path tracer's light sampling)
+//   sceneGlass(id)    -> index of refraction and density of a glass object, zero otherwise
+//   sceneBump(id)     -> normal map intensity and tiling of an object, zero without `bump`
+//   sceneNoise(id)    -> color noise amount, scale and kind of an object, zero without `noise`
+//   sceneFilm(id)     -> thin film thickness, amount, variation and noise scale of an object,
+//                        zero without `iridescence`
+//   sceneSpecular(id) -> highlight intensity, roughness and anisotropy of an object, zero
+//                        without `specular`
+//   sceneEmission(id) -> light an object gives off by itself, zero without `emission`
+//   sceneTangent(p, id) -> direction the object is brushed along at p (not normalized)
+//   scenePortal(ro, rd), portalDirection(d, side), portalPoint(p, side) -> the portals
+//   sceneTextured(id) -> whether the object receives the video/screen textures
+//   sceneFloor(id)    -> whether the id is the scene's floor, unioned into sceneMap after the
+//                        objects with the next id
+// The same tree is emitted as MSL for the renderer and as GLSL/WGSL for export.
+// Primitive and CSG helper functions (sdSphere, opUnion, ...) live in the templates; the
+// ones only some scenes need (opRotate, opRepeat, the 4D shapes, portalCrossing) are emitted
+// here.
+//
+// Parameters in a `ParamLayout` (the animated, bound and scripted ones) are read from
+// `uniforms.params` instead of being baked in as literals; in MSL, every scene function but
+// the portal ones, sceneTextured and sceneFloor, and the sdPlant and cellBorder helpers of
+// plants and cells, take the uniforms as their last argument for that.
+use crate::cells;
+use crate::heightfield;
+use crate::plant;
+use crate::scene::{
+    CellStyle, Cells, CsgOp, Node, PortalSide, RotationPlane, Scene, SceneObject, Shape, Tangent,
+};
+use cgmath::{Deg, Matrix, Matrix3};
+use std::fmt::Write;
+use std::mem;
+
+// Marker in the shader templates replaced by the generated scene code
+pub const SCENE_MARKER: &str = "// @scene@";
+
+// Floats available to parameters that change at runtime (`params` in the uniforms, as vec4s)
+pub const MAX_PARAMS: usize = 64;
+
+// Top-level nodes that can have a bit of sceneMap's cull mask (a uint)
+pub const MAX_PROXIES: usize = 32;
+
+// Uniform slots of the scene's animated parameters, in the order their tracks appear,
+// followed by those bound to expressions and those set by the scene's script. Parameters
+// that do not fit in MAX_PARAMS stay baked into the shader. Cell sites (see cells.rs) and the
+// time plants sway with are read from the uniforms too, except with the default layout, which
+// bakes everything in (cells at rest, plants still).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ParamLayout {
+    slots: Vec<(String, usize)>, // Target and its first slot
+    used: usize,
+    live: bool, // Reads the uniforms' sites and time
+}
+
+// Targets of the parameters that change at runtime, possibly repeated
+fn live_targets(scene: &Scene) -> impl Iterator<Item = &str> {
+    scene
+        .animations
+        .iter()
+        .map(|track| track.target.as_str())
+        .chain(scene.orbits.iter().map(|orbit| orbit.target.as_str()))
+        .chain(scene.expressions.keys().map(String::as_str))
+        .chain(scene.scripted.iter().map(String::as_str))
+}
+
+impl ParamLayout {
+    pub fn of(scene: &Scene) -> Self {
+        let mut layout = Self {
+            live: true,
+            ..Self::default()
+        };
+        for target in live_targets(scene) {
+            if layout.slot(target).is_some() {
+                continue;
+            }
+            let Some(len) = scene.parameter(target).map(<[f32]>::len) else {
+                continue;
+            };
+            if layout.used + len > MAX_PARAMS {
+                continue;
+            }
+            layout.slots.push((target.to_string(), layout.used));
+            layout.used += len;
+        }
+        layout
+    }
+
+    // Floats needed by every animated, bound or scripted parameter, whether or not they fit
+    pub fn required(scene: &Scene) -> usize {
+        let mut targets: Vec<&str> = live_targets(scene).collect();
+        targets.sort_unstable();
+        targets.dedup();
+        targets
+            .iter()
+            .filter_map(|target| scene.parameter(target))
+            .map(<[f32]>::len)
+            .sum()
+    }
+
+    // Laid out targets, in slot order
+    pub fn targets(&self) -> impl Iterator<Item = &str> {
+        self.slots.iter().map(|(target, _)| target.as_str())
+    }
+
+    pub fn slot(&self, target: &str) -> Option<usize> {
+        self.slots
+            .iter()
+            .find(|(name, _)| name == target)
+            .map(|&(_, slot)| slot)
+    }
+
+    // Current values of the laid out parameters, packed as the uniforms expect
+    pub fn values(&self, scene: &Scene) -> [[f32; 4]; MAX_PARAMS / 4] {
+        let mut params = [[0.0; 4]; MAX_PARAMS / 4];
+        for (target, slot) in &self.slots {
+            for (i, value) in scene.parameter(target).unwrap_or(&[]).iter().enumerate() {
+                params[(slot + i) / 4][(slot + i) % 4] = *value;
+            }
+        }
+        params
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dialect {
+    Msl,
+    Glsl,
+    Wgsl,
+}
+
+impl Dialect {
+    fn vec2(self) -> &'static str {
+        match self {
+            Dialect::Msl => "float2",
+            Dialect::Glsl => "vec2",
+            Dialect::Wgsl => "vec2<f32>",
+        }
+    }
+
+    fn vec3(self) -> &'static str {
+        match self {
+            Dialect::Msl => "float3",
+            Dialect::Glsl => "vec3",
+            Dialect::Wgsl => "vec3<f32>",
+        }
+    }
+
+    fn vec4(self) -> &'static str {
+        match self {
+            Dialect::Msl => "float4",
+            Dialect::Glsl => "vec4",
+            Dialect::Wgsl => "vec4<f32>",
+        }
+    }
+
+    fn function(self, name: &str, param: &str, param_type: &str, return_type: &str) -> String {
+        match self {
+            Dialect::Wgsl => format!(
+                "fn {}({}: {}) -> {} {{",
+                name, param, param_type, return_type
+            ),
+            _ => format!("{} {}({} {}) {{", return_type, name, param_type, param),
+        }
+    }
+
+    // As `function`, for a function that reads `uniforms`
+    fn uniforms_function(
+        self,
+        name: &str,
+        param: &str,
+        param_type: &str,
+        return_type: &str,
+    ) -> String {
+        match self {
+            Dialect::Msl => format!(
+                "{} {}({} {}{}) {{",
+                return_type,
+                name,
+                param_type,
+                param,
+                self.uniforms_param()
+            ),
+            _ => self.function(name, param, param_type, return_type),
+        }
+    }
+
+    fn var(self, name: &str, value: &str) -> String {
+        match self {
+            Dialect::Wgsl => format!("var {} = {};", name, value),
+            _ => format!("{} {} = {};", self.vec2(), name, value),
+        }
+    }
+
+    fn object_index(self) -> &'static str {
+        match self {
+            Dialect::Wgsl => "let i = i32(id + 0.5);",
+            _ => "int i = int(id + 0.5);",
+        }
+    }
+
+    fn float_type(self) -> &'static str {
+        match self {
+            Dialect::Wgsl => "f32",
+            _ => "float",
+        }
+    }
+
+    // Extra parameter for functions that read `uniforms` (a global outside MSL)
+    fn uniforms_param(self) -> &'static str {
+        match self {
+            Dialect::Msl => ", constant Uniforms& uniforms",
+            _ => "",
+        }
+    }
+
+    // Passes `uniforms` on as uniforms_param takes it
+    fn uniforms_arg(self) -> &'static str {
+        match self {
+            Dialect::Msl => ", uniforms",
+            _ => "",
+        }
+    }
+}
+
+// Component `slot` of the animated parameters
+fn param(slot: usize) -> String {
+    format!(
+        "uniforms.params[{}].{}",
+        slot / 4,
+        ["x", "y", "z", "w"][slot % 4]
+    )
+}
+
+// A scalar parameter: read from the uniforms when it is laid out, else a literal
+fn float_param(layout: &ParamLayout, target: &str, value: f32) -> String {
+    match layout.slot(target) {
+        Some(slot) => param(slot),
+        None => lit(value),
+    }
+}
+
+fn vec4_param(dialect: Dialect, layout: &ParamLayout, target: &str, value: [f32; 4]) -> String {
+    match layout.slot(target) {
+        Some(slot) => format!(
+            "{}({}, {}, {}, {})",
+            dialect.vec4(),
+            param(slot),
+            param(slot + 1),
+            param(slot + 2),
+            param(slot + 3)
+        ),
+        None => vec4_lit(dialect, value),
+    }
+}
+
+fn vec3_param(dialect: Dialect, layout: &ParamLayout, target: &str, value: [f32; 3]) -> String {
+    match layout.slot(target) {
+        Some(slot) => format!(
+            "{}({}, {}, {})",
+            dialect.vec3(),
+            param(slot),
+            param(slot + 1),
+            param(slot + 2)
+        ),
+        None => vec3_lit(dialect, value),
+    }
+}
+
+// Float literal that always parses as floating point in every dialect
+fn lit(value: f32) -> String {
+    let text = format!("{:?}", value);
+    if text.contains('.') || text.contains('e') || text.contains("inf") || text.contains("NaN") {
+        text
+    } else {
+        format!("{}.0", text)
+    }
+}
+
+fn vec3_lit(dialect: Dialect, v: [f32; 3]) -> String {
+    format!(
+        "{}({}, {}, {})",
+        dialect.vec3(),
+        lit(v[0]),
+        lit(v[1]),
+        lit(v[2])
+    )
+}
+
+fn vec4_lit(dialect: Dialect, v: [f32; 4]) -> String {
+    format!(
+        "{}({}, {}, {}, {})",
+        dialect.vec4(),
+        lit(v[0]),
+        lit(v[1]),
+        lit(v[2]),
+        lit(v[3])
+    )
+}
+
+// Generate the scene functions in the given dialect with every parameter baked in
+pub fn scene_source(scene: &Scene, dialect: Dialect) -> String {
+    scene_source_with(scene, dialect, &ParamLayout::default())
+}
+
+// Generate the scene functions, reading the parameters in `layout` from the uniforms
+pub fn scene_source_with(scene: &Scene, dialect: Dialect, layout: &ParamLayout) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "// Generated from scene {:?}", scene.name);
+    let objects = scene.objects();
+    if objects
+        .iter()
+        .any(|object| rotated(layout, object).is_some())
+    {
+        emit_rotate(&mut out, dialect);
+    }
+    if objects.iter().any(|object| object.repeat.is_some()) {
+        emit_repeat(&mut out, dialect);
+    }
+    if objects.iter().any(|object| object.shape.is_4d()) {
+        emit_4d(&mut out, dialect);
+    }
+    for (id, object) in objects.iter().enumerate() {
+        emit_outline(&mut out, dialect, id, &object.shape);
+        emit_plant(&mut out, dialect, layout, id, &object.shape);
+        emit_cloud(&mut out, dialect, id, &object.shape);
+        emit_heightfield(&mut out, dialect, id, &object.shape);
+    }
+    for ((id, object), first) in objects.iter().enumerate().zip(cells::first_sites(&objects)) {
+        if let Some(cells) = &object.cells {
+            emit_cells(&mut out, dialect, layout, id, cells, first);
+        }
+    }
+
+    // sceneMap
+    let header = dialect.uniforms_function("sceneMap", "p", dialect.vec3(), dialect.vec2());
+    let mut next = Counters::default();
+    if dialect == Dialect::Msl {
+        let _ = writeln!(
+            out,
+            "{}",
+            header.replace(") {", ", uint cull = 0xffffffff) {")
+        );
+        next.proxied = (proxies(scene, layout).iter())
+            .map(|proxy| proxy[3] >= 0.0)
+            .collect();
+    } else {
+        let _ = writeln!(out, "{}", header);
+    }
+    emit_nodes(&mut out, dialect, layout, &scene.nodes, "res", 1, &mut next);
+    if let Some(floor) = scene.ground() {
+        let height = float_param(layout, "floor.height", floor.height);
+        let _ = writeln!(
+            out,
+            "    res = opUnion(res, {}(p.y - {}, {}));",
+            dialect.vec2(),
+            height,
+            lit(objects.len() as f32)
+        );
+    }
+    let _ = writeln!(out, "    return res;\n}}\n");
+
+    // sceneObject
+    let float = dialect.float_type();
+    let header = match dialect {
+        Dialect::Wgsl => format!("fn sceneObject(p: {}, id: f32) -> f32 {{", dialect.vec3()),
+        _ => format!(
+            "float sceneObject({} p, float id{}) {{",
+            dialect.vec3(),
+            dialect.uniforms_param()
+        ),
+    };
+    let _ = writeln!(out, "{}", header);
+    let _ = writeln!(out, "    {}", dialect.object_index());
+    for (i, object) in objects.iter().enumerate() {
+        let _ = writeln!(
+            out,
+            "    if (i == {}) {{ return {}; }}",
+            i,
+            object_distance(dialect, layout, i, object)
+        );
+    }
+    let _ = writeln!(out, "    return 1e10;\n}}\n");
+
+    // sceneColor
+    let _ = writeln!(
+        out,
+        "{}",
+        dialect.uniforms_function("sceneColor", "id", float, dialect.vec3())
+    );
+    let _ = writeln!(out, "    {}", dialect.object_index());
+    for (i, object) in objects.iter().enumerate() {
+        let target = format!("{}.color", object.name);
+        let _ = writeln!(
+            out,
+            "    if (i == {}) {{ return {}; }}",
+            i,
+            vec3_param(dialect, layout, &target, object.color)
+        );
+    }
+    let _ = writeln!(out, "    return {}(0.5, 0.5, 0.5);\n}}\n", dialect.vec3());
+
+    // sceneColorAt
+    let header = match dialect {
+        Dialect::Wgsl => format!(
+            "fn sceneColorAt(p: {0}, id: f32, color: {0}) -> {0} {{",
+            dialect.vec3()
+        ),
+        _ => format!(
+            "{0} sceneColorAt({0} p, float id, {0} color{1}) {{",
+            dialect.vec3(),
+            dialect.uniforms_param()
+        ),
+    };
+    let _ = writeln!(out, "{}", header);
+    let _ = writeln!(out, "    {}", dialect.object_index());
+    for (i, object) in objects.iter().enumerate() {
+        if let Shape::Cloud { .. } = object.shape {
+            let (local, _) = object_local(dialect, layout, object);
+            let _ = writeln!(
+                out,
+                "    if (i == {}) {{ return color * cloudColor{}({}); }}",
+                i, i, local
+            );
+        }
+    }
+    let _ = writeln!(out, "    return color;\n}}\n");
+
+    // sceneLight
+    let vec3 = dialect.vec3();
+    let header = match dialect {
+        Dialect::Wgsl => format!("fn sceneLight(p: {0}, n: {0}) -> {0} {{", vec3),
+        _ => format!(
+            "{0} sceneLight({0} p, {0} n{1}) {{",
+            vec3,
+            dialect.uniforms_param()
+        ),
+    };
+    let _ = writeln!(out, "{}", header);
+    let mut sum = format!("{}(0.0)", vec3);
+    for light in &scene.lights {
+        let target = |property: &str| format!("{}.{}", light.name, property);
+        let _ = write!(
+            sum,
+            "\n        + pointLight(p, n, {}, {}, {})",
+            vec3_param(dialect, layout, &target("position"), light.position),
+            vec3_param(dialect, layout, &target("color"), light.color),
+            float_param(layout, &target("intensity"), light.intensity)
+        );
+    }
+    let _ = writeln!(out, "    return {};\n}}\n", sum);
+
+    // SCENE_LIGHTS, sceneLightSphere and sceneLightPower
+    let count = scene.lights.len();
+    let _ = match dialect {
+        Dialect::Msl => writeln!(out, "constant int SCENE_LIGHTS = {};\n", count),
+        Dialect::Glsl => writeln!(out, "const int SCENE_LIGHTS = {};\n", count),
+        Dialect::Wgsl => writeln!(out, "const SCENE_LIGHTS: i32 = {};\n", count),
+    };
+    let _ = writeln!(
+        out,
+        "{}",
+        dialect.uniforms_function("sceneLightSphere", "id", float, dialect.vec4())
+    );
+    let _ = writeln!(out, "    {}", dialect.object_index());
+    for (i, light) in scene.lights.iter().enumerate() {
+        let target = |property: &str| format!("{}.{}", light.name, property);
+        let _ = writeln!(
+            out,
+            "    if (i == {}) {{ return {}({}, {}); }}",
+            i,
+            dialect.vec4(),
+            vec3_param(dialect, layout, &target("position"), light.position),
+            float_param(layout, &target("radius"), light.radius)
+        );
+    }
+    let _ = writeln!(out, "    return {}(0.0);\n}}\n", dialect.vec4());
+    let _ = writeln!(
+        out,
+        "{}",
+        dialect.uniforms_function("sceneLightPower", "id", float, dialect.vec3())
+    );
+    let _ = writeln!(out, "    {}", dialect.object_index());
+    for (i, light) in scene.lights.iter().enumerate() {
+        let target = |property: &str| format!("{}.{}", light.name, property);
+        let _ = writeln!(
+            out,
+            "    if (i == {}) {{ return {} * {}; }}",
+            i,
+            vec3_param(dialect, layout, &target("color"), light.color),
+            float_param(layout, &target("intensity"), light.intensity)
+        );
+    }
+    let _ = writeln!(out, "    return {}(0.0);\n}}\n", dialect.vec3());
+
+    // sceneGlass: (index of refraction, density), or zero for an opaque object
+    let _ = writeln!(
+        out,
+        "{}",
+        dialect.uniforms_function("sceneGlass", "id", float, dialect.vec2())
+    );
+    let _ = writeln!(out, "    {}", dialect.object_index());
+    for (i, object) in objects.iter().enumerate() {
+        let Some(glass) = object.glass else {
+            continue;
+        };
+        let target = |property: &str| format!("{}.{}", object.name, property);
+        let _ = writeln!(
+            out,
+            "    if (i == {}) {{ return {}({}, {}); }}",
+            i,
+            dialect.vec2(),
+            float_param(layout, &target("ior"), glass.ior),
+            float_param(layout, &target("density"), glass.density)
+        );
+    }
+    let _ = writeln!(out, "    return {}(0.0);\n}}\n", dialect.vec2());
+
+    // sceneBump: (intensity, tiling), or zero for a smooth object
+    let _ = writeln!(
+        out,
+        "{}",
+        dialect.uniforms_function("sceneBump", "id", float, dialect.vec2())
+    );
+    let _ = writeln!(out, "    {}", dialect.object_index());
+    for (i, object) in objects.iter().enumerate() {
+        let Some(bump) = object.bump else {
+            continue;
+        };
+        let _ = writeln!(
+            out,
+            "    if (i == {}) {{ return {}({}, {}); }}",
+            i,
+            dialect.vec2(),
+            float_param(layout, &format!("{}.bump", object.name), bump.intensity),
+            lit(bump.tiling)
+        );
+    }
+    let _ = writeln!(out, "    return {}(0.0);\n}}\n", dialect.vec2());
+
+    // sceneNoise: (amount, scale, kind), or zero for a plain color
+    let _ = writeln!(
+        out,
+        "{}",
+        dialect.uniforms_function("sceneNoise", "id", float, dialect.vec3())
+    );
+    let _ = writeln!(out, "    {}", dialect.object_index());
+    for (i, object) in objects.iter().enumerate() {
+        let Some(noise) = object.noise else {
+            continue;
+        };
+        let _ = writeln!(
+            out,
+            "    if (i == {}) {{ return {}({}, {}, {}); }}",
+            i,
+            dialect.vec3(),
+            float_param(layout, &format!("{}.noise", object.name), noise.amount),
+            lit(noise.scale),
+            lit(noise.kind as u32 as f32)
+        );
+    }
+    let _ = writeln!(out, "    return {}(0.0);\n}}\n", dialect.vec3());
+
+    // sceneFilm: (thickness, amount, variation, scale), or zero for no iridescence
+    let _ = writeln!(
+        out,
+        "{}",
+        dialect.uniforms_function("sceneFilm", "id", float, dialect.vec4())
+    );
+    let _ = writeln!(out, "    {}", dialect.object_index());
+    for (i, object) in objects.iter().enumerate() {
+        let Some(film) = object.iridescence else {
+            continue;
+        };
+        let _ = writeln!(
+            out,
+            "    if (i == {}) {{ return {}({}, {}, {}, {}); }}",
+            i,
+            dialect.vec4(),
+            float_param(layout, &format!("{}.film", object.name), film.thickness),
+            lit(film.amount),
+            lit(film.variation),
+            lit(film.scale)
+        );
+    }
+    let _ = writeln!(out, "    return {}(0.0);\n}}\n", dialect.vec4());
+
+    // sceneSpecular: (intensity, roughness, anisotropy, 0), or zero for a matte object
+    let _ = writeln!(
+        out,
+        "{}",
+        dialect.uniforms_function("sceneSpecular", "id", float, dialect.vec4())
+    );
+    let _ = writeln!(out, "    {}", dialect.object_index());
+    for (i, object) in objects.iter().enumerate() {
+        let Some(specular) = object.specular else {
+            continue;
+        };
+        let _ = writeln!(
+            out,
+            "    if (i == {}) {{ return {}({}, {}, {}, 0.0); }}",
+            i,
+            dialect.vec4(),
+            lit(specular.intensity),
+            lit(specular.roughness),
+            float_param(
+                layout,
+                &format!("{}.anisotropy", object.name),
+                specular.anisotropy
+            )
+        );
+    }
+    let _ = writeln!(out, "    return {}(0.0);\n}}\n", dialect.vec4());
+
+    // sceneEmission: the object's color times its emission, or zero for one that doesn't glow
+    let _ = writeln!(
+        out,
+        "{}",
+        dialect.uniforms_function("sceneEmission", "id", float, vec3)
+    );
+    let _ = writeln!(out, "    {}", dialect.object_index());
+    for (i, object) in objects.iter().enumerate() {
+        let target = |property: &str| format!("{}.{}", object.name, property);
+        if object.emission == 0.0 && layout.slot(&target("emission")).is_none() {
+            continue;
+        }
+        let _ = writeln!(
+            out,
+            "    if (i == {}) {{ return {} * {}; }}",
+            i,
+            vec3_param(dialect, layout, &target("color"), object.color),
+            float_param(layout, &target("emission"), object.emission)
+        );
+    }
+    let _ = writeln!(out, "    return {}(0.0);\n}}\n", vec3);
+
+    // sceneTangent
+    let header = match dialect {
+        Dialect::Wgsl => format!("fn sceneTangent(p: {0}, id: f32) -> {0} {{", vec3),
+        _ => format!(
+            "{0} sceneTangent({0} p, {1} id{2}) {{",
+            vec3,
+            dialect.float_type(),
+            dialect.uniforms_param()
+        ),
+    };
+    let _ = writeln!(out, "{}", header);
+    let _ = writeln!(out, "    {}", dialect.object_index());
+    for (i, object) in objects.iter().enumerate() {
+        let Some(specular) = object.specular else {
+            continue;
+        };
+        let _ = writeln!(
+            out,
+            "    if (i == {}) {{ return {}; }}",
+            i,
+            tangent(dialect, layout, object, specular.tangent)
+        );
+    }
+    let _ = writeln!(out, "    return {}(1.0, 0.0, 0.0);\n}}\n", vec3);
+
+    // scenePortal, portalPoint and portalDirection
+    emit_portals(&mut out, dialect, scene);
+
+    // sceneTextured
+    let bool_type = "bool";
+    let _ = writeln!(
+        out,
+        "{}",
+        dialect.function("sceneTextured", "id", float, bool_type)
+    );
+    let _ = writeln!(out, "    {}", dialect.object_index());
+    let textured: Vec<String> = objects
+        .iter()
+        .enumerate()
+        .filter(|(_, object)| object.textured)
+        .map(|(i, _)| format!("i == {}", i))
+        .collect();
+    if textured.is_empty() {
+        let _ = writeln!(out, "    return false;\n}}");
+    } else {
+        let _ = writeln!(out, "    return {};\n}}", textured.join(" || "));
+    }
+
+    // sceneFloor
+    let _ = writeln!(
+        out,
+        "\n{}",
+        dialect.function("sceneFloor", "id", float, bool_type)
+    );
+    match scene.ground() {
+        Some(_) => {
+            let _ = writeln!(out, "    {}", dialect.object_index());
+            let _ = writeln!(out, "    return i == {};\n}}", objects.len());
+        }
+        None => {
+            let _ = writeln!(out, "    return false;\n}}");
+        }
+    }
+    out
+}
+
+// Next object id and group variable number while emitting sceneMap, and the top-level nodes
+// the cull mask can leave out (taken by the outermost emit_nodes)
+#[derive(Default)]
+struct Counters {
+    object: usize,
+    group: usize,
+    proxied: Vec<bool>,
+}
+
+// Fold a list of nodes into `target`; the first node initializes it, the rest apply their op
+fn emit_nodes(
+    out: &mut String,
+    dialect: Dialect,
+    layout: &ParamLayout,
+    nodes: &[Node],
+    target: &str,
+    indent: usize,
+    next: &mut Counters,
+) {
+    let pad = "    ".repeat(indent);
+    let _ = writeln!(
+        out,
+        "{}{}",
+        pad,
+        dialect.var(target, &format!("{}(1e10, -1.0)", dialect.vec2()))
+    );
+    let proxied = mem::take(&mut next.proxied);
+
+    for (index, node) in nodes.iter().enumerate() {
+        let cull = (proxied.get(index) == Some(&true))
+            .then(|| format!("(cull & {}u) != 0", 1u32 << index));
+        let (pad, indent) = match &cull {
+            Some(test) => {
+                let _ = writeln!(out, "{}if ({}) {{", pad, test);
+                (format!("{}    ", pad), indent + 1)
+            }
+            None => (pad.clone(), indent),
+        };
+        let value = match node {
+            Node::Object(object) => {
+                let id = next.object;
+                next.object += 1;
+                format!(
+                    "{}({}, {})",
+                    dialect.vec2(),
+                    object_distance(dialect, layout, id, object),
+                    lit(id as f32)
+                )
+            }
+            Node::Group(group) => {
+                let name = format!("g{}", next.group);
+                next.group += 1;
+                let _ = writeln!(out, "{}// group {:?}", pad, group.name);
+                emit_nodes(out, dialect, layout, &group.children, &name, indent, next);
+                name
+            }
+        };
+
+        if index == 0 {
+            let _ = writeln!(out, "{}{} = {};", pad, target, value);
+        } else {
+            let _ = writeln!(
+                out,
+                "{}{} = {};",
+                pad,
+                target,
+                combine(layout, node, target, &value)
+            );
+        }
+        if cull.is_some() {
+            let _ = writeln!(out, "{}}}", &pad[4..]);
+        }
+    }
+}
+
+// Bounding spheres (center and radius, -1 for none) of the first MAX_PROXIES top-level nodes,
+// which the culling pass projects onto the screen (see raymarcher.rs) so that sceneMap leaves
+// each out of the rays of tiles it can't be seen in. Only the first node and union members
+// get one, as the rest still carve into the scene where they can't be seen, and only when
+// their bounds are fixed. Each is widened by the largest smooth union's blend distance: a
+// node further than that from a ray's points can't change the surface the ray finds.
+pub fn proxies(scene: &Scene, layout: &ParamLayout) -> Vec<[f32; 4]> {
+    let blend = (scene.nodes.iter())
+        .map(|node| match node.op() {
+            CsgOp::SmoothUnion { k } => k.abs(),
+            _ => 0.0,
+        })
+        .fold(0.0, f32::max);
+    (scene.nodes.iter().take(MAX_PROXIES).enumerate())
+        .map(|(index, node)| {
+            let union = index == 0 || matches!(node.op(), CsgOp::Union | CsgOp::SmoothUnion { .. });
+            match node_bounds(layout, node).filter(|_| union) {
+                Some(([x, y, z], radius)) => [x, y, z, radius + blend],
+                None => [0.0, 0.0, 0.0, -1.0],
+            }
+        })
+        .collect()
+}
+
+// A sphere that `node`'s distance is never nearer than, unless a parameter can move or grow it.
+// Smooth ops inside a group may bulge past their members by a quarter of their blend distance.
+fn node_bounds(layout: &ParamLayout, node: &Node) -> Option<([f32; 3], f32)> {
+    let name = match node {
+        Node::Object(object) => &object.name,
+        Node::Group(group) => &group.name,
+    };
+    let prefix = format!("{}.", name);
+    if layout
+        .slots
+        .iter()
+        .any(|(target, _)| target.starts_with(&prefix))
+    {
+        return None;
+    }
+    match node {
+        Node::Object(object) => {
+            let length = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+            let radius = match &object.shape {
+                Shape::Sphere { radius } | Shape::Hypersphere { radius, .. } => *radius,
+                Shape::Box { size } => length(size),
+                Shape::Capsule { a, b, radius } => length(a).max(length(b)) + radius,
+                Shape::Plane { .. } => return None,
+                Shape::Tesseract { size, .. } => 2.0 * size,
+                // The filled Julia set lies within max(2, |c|) of the origin
+                Shape::Julia { c, .. } => length(c).max(2.0),
+                Shape::Extrusion { outline, depth, .. } => {
+                    let far = outline
+                        .iter()
+                        .flatten()
+                        .map(|p| length(p))
+                        .fold(0.0, f32::max);
+                    length(&[far, *depth])
+                }
+                Shape::Revolution { outline, .. } => outline
+                    .iter()
+                    .flatten()
+                    .map(|p| length(p))
+                    .fold(0.0, f32::max),
+                // Blending swells the spheres by at most blend / 4
+                Shape::Cloud {
+                    radius,
+                    blend,
+                    points,
+                    ..
+                } => {
+                    points
+                        .iter()
+                        .map(|point| length(&point[..3]))
+                        .fold(0.0, f32::max)
+                        + radius
+                        + blend / 4.0
+                }
+                Shape::Heightfield {
+                    size,
+                    height,
+                    exaggeration,
+                    base,
+                    ..
+                } => length(&[size[0], (height * exaggeration).abs().max(*base), size[1]]),
+                // Blending swells the joins by at most blend / 4, and the wind moves an end at
+                // height h by up to 2 * wind * h²
+                Shape::Plant { blend, wind, .. } => {
+                    let segments = plant::segments(&object.shape).unwrap_or_default();
+                    let ends = segments
+                        .iter()
+                        .flat_map(|s| [(s.a, s.radius), (s.b, s.radius)]);
+                    let sway = |y: f32| match layout.live {
+                        true => 2.0 * wind.abs() * y.max(0.0).powi(2),
+                        false => 0.0,
+                    };
+                    ends.map(|(end, radius)| length(&end) + radius + sway(end[1]))
+                        .fold(0.0, f32::max)
+                        + blend / 4.0
+                }
+            };
+            // The furthest copy is half the grid away
+            let grid = object.repeat.as_deref().map_or(0.0, |repeat| {
+                let extent = [0, 1, 2].map(|axis| {
+                    repeat.spacing[axis] * repeat.count[axis].saturating_sub(1) as f32 / 2.0
+                });
+                length(&extent)
+            });
+            Some((object.position, (radius + grid) * object.scale.abs()))
+        }
+        Node::Group(group) => {
+            let children = group
+                .children
+                .iter()
+                .map(|child| node_bounds(layout, child))
+                .collect::<Option<Vec<_>>>()?;
+            let count = children.len().max(1) as f32;
+            let mut center = [0.0; 3];
+            for (c, _) in &children {
+                for axis in 0..3 {
+                    center[axis] += c[axis] / count;
+                }
+            }
+            let slack: f32 = group
+                .children
+                .iter()
+                .skip(1)
+                .map(|child| match child.op() {
+                    CsgOp::SmoothUnion { k } | CsgOp::SmoothSubtract { k } => k.abs() * 0.25,
+                    _ => 0.0,
+                })
+                .sum();
+            let radius = children
+                .iter()
+                .map(|(c, r)| {
+                    let d = (0..3).map(|i| (c[i] - center[i]).powi(2)).sum::<f32>();
+                    d.sqrt() + r
+                })
+                .fold(0.0, f32::max);
+            Some((center, radius + slack))
+        }
+    }
+}
+
+// Combine `b` (the value of `node`) into `a` with the node's op
+fn combine(layout: &ParamLayout, node: &Node, a: &str, b: &str) -> String {
+    let name = match node {
+        Node::Object(object) => &object.name,
+        Node::Group(group) => &group.name,
+    };
+    match node.op() {
+        CsgOp::Union => format!("opUnion({}, {})", a, b),
+        CsgOp::SmoothUnion { k } => format!(
+            "opSmoothUnion({}, {}, {})",
+            a,
+            b,
+            float_param(layout, &format!("{}.k", name), k)
+        ),
+        CsgOp::Subtract => format!("opSubtract({}, {})", a, b),
+        CsgOp::SmoothSubtract { k } => format!(
+            "opSmoothSubtract({}, {}, {})",
+            a,
+            b,
+            float_param(layout, &format!("{}.k", name), k)
+        ),
+        CsgOp::Intersect => format!("opIntersect({}, {})", a, b),
+    }
+}
+
+// opRotate(p, degrees): undo an object's rotation (X, then Y, then Z), taking a point from
+// the object's parent space into its own
+fn emit_rotate(out: &mut String, dialect: Dialect) {
+    let vec3 = dialect.vec3();
+    let (header, declare) = match dialect {
+        Dialect::Wgsl => (
+            format!("fn opRotate(p: {0}, degrees: {0}) -> {0} {{", vec3),
+            "var",
+        ),
+        _ => (format!("{0} opRotate({0} p, {0} degrees) {{", vec3), vec3),
+    };
+    let _ = writeln!(out, "{}", header);
+    let _ = writeln!(out, "    {} a = -degrees * 0.017453292;", declare);
+    let _ = writeln!(out, "    {} q = p;", declare);
+    for (angle, rotated) in [
+        ("a.z", "q.x * C - q.y * S, q.x * S + q.y * C, q.z"),
+        ("a.y", "q.x * C + q.z * S, q.y, q.z * C - q.x * S"),
+        ("a.x", "q.x, q.y * C - q.z * S, q.y * S + q.z * C"),
+    ] {
+        let rotated = rotated
+            .replace('C', &format!("cos({})", angle))
+            .replace('S', &format!("sin({})", angle));
+        let _ = writeln!(out, "    q = {}({});", vec3, rotated);
+    }
+    let _ = writeln!(out, "    return q;\n}}\n");
+}
+
+// opRepeat(p, spacing, count): the point in the nearest of `count` copies `spacing` apart along
+// each axis, centered on the origin
+fn emit_repeat(out: &mut String, dialect: Dialect) {
+    let vec3 = dialect.vec3();
+    let (header, declare) = match dialect {
+        Dialect::Wgsl => (
+            format!("fn opRepeat(p: {0}, s: {0}, n: {0}) -> {0} {{", vec3),
+            "let",
+        ),
+        _ => (format!("{0} opRepeat({0} p, {0} s, {0} n) {{", vec3), vec3),
+    };
+    let _ = writeln!(out, "{}", header);
+    let _ = writeln!(out, "    {} c = 0.5 * (n - 1.0);", declare);
+    let _ = writeln!(
+        out,
+        "    return p - s * (clamp(round(p / s + c), {}(0.0), n - 1.0) - c);\n}}\n",
+        vec3
+    );
+}
+
+// scenePortal(ro, rd): (distance, side) of the first portal side the ray passes through, the
+// side -1 for none. portalDirection(d, side) and portalPoint(p, side) take a direction and a
+// point through to the other side of the pair; sides 2i and 2i + 1 are portal i's a and b.
+fn emit_portals(out: &mut String, dialect: Dialect, scene: &Scene) {
+    let vec2 = dialect.vec2();
+    let vec3 = dialect.vec3();
+    if !scene.portals.is_empty() {
+        emit_portal_crossing(out, dialect);
+    }
+
+    let header = match dialect {
+        Dialect::Wgsl => format!("fn scenePortal(ro: {0}, rd: {0}) -> {1} {{", vec3, vec2),
+        _ => format!("{1} scenePortal({0} ro, {0} rd) {{", vec3, vec2),
+    };
+    let _ = writeln!(out, "{}", header);
+    let _ = writeln!(
+        out,
+        "    {}",
+        dialect.var("best", &format!("{}(1e10, -1.0)", vec2))
+    );
+    let sides = scene
+        .portals
+        .iter()
+        .flat_map(|portal| [(portal.a, portal.size), (portal.b, portal.size)]);
+    for (index, (side, size)) in sides.enumerate() {
+        let (position, rotation) = (side.position, side_rotation(&side));
+        let _ = writeln!(
+            out,
+            "    best = opUnion(best, portalCrossing(ro, rd, {}, {}, {}, {}));",
+            vec3_lit(dialect, position),
+            vec3_lit(dialect, (rotation.x * size[0]).into()),
+            vec3_lit(dialect, (rotation.y * size[1]).into()),
+            lit(index as f32)
+        );
+    }
+    let _ = writeln!(
+        out,
+        "    return best;
+}}
+"
+    );
+
+    // Through side a: into a's local space, half a turn about Y (so what went in its front
+    // comes out of b's front), and out of b's; the same the other way
+    let turn = Matrix3::from_angle_y(Deg(180.0));
+    let links: Vec<(PortalSide, Matrix3<f32>)> = scene
+        .portals
+        .iter()
+        .flat_map(|portal| [(portal.a, portal.b), (portal.b, portal.a)])
+        .map(|(from, to)| {
+            let matrix = side_rotation(&to) * turn * side_rotation(&from).transpose();
+            (from, matrix)
+        })
+        .collect();
+    let id_type = dialect.float_type();
+    for (name, param) in [("portalDirection", "d"), ("portalPoint", "p")] {
+        let header = match dialect {
+            Dialect::Wgsl => format!("fn {0}({1}: {2}, id: f32) -> {2} {{", name, param, vec3),
+            _ => format!("{2} {0}({2} {1}, {3} id) {{", name, param, vec3, id_type),
+        };
+        let _ = writeln!(out, "{}", header);
+        let _ = writeln!(out, "    {}", dialect.object_index());
+        for (index, (from, matrix)) in links.iter().enumerate() {
+            let value = if name == "portalPoint" {
+                let to = &scene.portals[index / 2];
+                let to = if index % 2 == 0 { to.b } else { to.a };
+                format!(
+                    "{} + portalDirection(p - {}, id)",
+                    vec3_lit(dialect, to.position),
+                    vec3_lit(dialect, from.position)
+                )
+            } else {
+                let row = |r: usize| vec3_lit(dialect, matrix.row(r).into());
+                format!(
+                    "{}(dot({}, d), dot({}, d), dot({}, d))",
+                    vec3,
+                    row(0),
+                    row(1),
+                    row(2)
+                )
+            };
+            let _ = writeln!(out, "    if (i == {}) {{ return {}; }}", index, value);
+        }
+        let _ = writeln!(
+            out,
+            "    return {};
+}}
+",
+            param
+        );
+    }
+}
+
+// portalCrossing(ro, rd, center, x, y, side): (distance, side) where the ray passes through
+// the rectangle at `center` spanned by the half extents `x` and `y` (perpendicular), or
+// (1e10, -1) where it doesn't. Rays starting on it, just out of its twin, don't count.
+fn emit_portal_crossing(out: &mut String, dialect: Dialect) {
+    let vec2 = dialect.vec2();
+    let vec3 = dialect.vec3();
+    let float = dialect.float_type();
+    let (header, declare_vec3, declare_float) = match dialect {
+        Dialect::Wgsl => (
+            format!(
+                "fn portalCrossing(ro: {0}, rd: {0}, center: {0}, x: {0}, y: {0}, side: f32) -> {1} {{",
+                vec3, vec2
+            ),
+            "let",
+            "let",
+        ),
+        _ => (
+            format!(
+                "{1} portalCrossing({0} ro, {0} rd, {0} center, {0} x, {0} y, {2} side) {{",
+                vec3, vec2, float
+            ),
+            vec3,
+            float,
+        ),
+    };
+    let _ = writeln!(out, "{}", header);
+    let _ = writeln!(out, "    {} n = cross(x, y);", declare_vec3);
+    let _ = writeln!(out, "    {} facing = dot(rd, n);", declare_float);
+    let _ = writeln!(
+        out,
+        "    {} t = dot(center - ro, n) / facing;",
+        declare_float
+    );
+    let _ = writeln!(out, "    {} q = ro + rd * t - center;", declare_vec3);
+    let _ = writeln!(
+        out,
+        "    if (abs(facing) < 1e-8 || t < 0.001 || abs(dot(q, x)) > dot(x, x) || abs(dot(q, y)) > dot(y, y)) {{"
+    );
+    let _ = writeln!(
+        out,
+        "        return {}(1e10, -1.0);
+    }}",
+        vec2
+    );
+    let _ = writeln!(
+        out,
+        "    return {}(t, side);
+}}
+",
+        vec2
+    );
+}
+
+// Rotation of a portal side (X, then Y, then Z, as opRotate undoes for objects)
+fn side_rotation(side: &PortalSide) -> Matrix3<f32> {
+    let [x, y, z] = side.rotation;
+    Matrix3::from_angle_z(Deg(z)) * Matrix3::from_angle_y(Deg(y)) * Matrix3::from_angle_x(Deg(x))
+}
+
+// 4D helpers: opRotate4(q, axis, degrees) undoes a rotation in the plane between w and the
+// unit `axis`; sdTesseract(q, size) and sdJulia(q, c) are the 4D box and quaternion Julia set
+// (the distance estimate from the escape time and the derivative's growth).
+const HELPERS_4D_WGSL: &str = "\
+fn opRotate4(q: VEC4, axis: VEC4, degrees: f32) -> VEC4 {
+    let a = -degrees * 0.017453292;
+    let u = dot(q, axis);
+    return q + axis * (u * cos(a) - q.w * sin(a) - u)
+        + VEC4(0.0, 0.0, 0.0, u * sin(a) + q.w * cos(a) - q.w);
+}
+
+fn sdTesseract(q: VEC4, size: f32) -> f32 {
+    let d = abs(q) - VEC4(size);
+    return length(max(d, VEC4(0.0))) + min(max(max(d.x, d.y), max(d.z, d.w)), 0.0);
+}
+
+fn sdJulia(q: VEC4, c: VEC4) -> f32 {
+    var z = q;
+    var z2 = dot(z, z);
+    var dz2 = 1.0;
+    for (var i = 0; i < 11; i++) {
+        dz2 *= 4.0 * z2;
+        z = VEC4(z.x * z.x - dot(z.yzw, z.yzw), 2.0 * z.x * z.yzw) + c;
+        z2 = dot(z, z);
+        if (z2 > 256.0) {
+            break;
+        }
+    }
+    return 0.25 * sqrt(z2 / dz2) * log(z2);
+}
+";
+
+const HELPERS_4D_C: &str = "\
+VEC4 opRotate4(VEC4 q, VEC4 axis, float degrees) {
+    float a = -degrees * 0.017453292;
+    float u = dot(q, axis);
+    return q + axis * (u * cos(a) - q.w * sin(a) - u)
+        + VEC4(0.0, 0.0, 0.0, u * sin(a) + q.w * cos(a) - q.w);
+}
+
+float sdTesseract(VEC4 q, float size) {
+    VEC4 d = abs(q) - VEC4(size);
+    return length(max(d, VEC4(0.0))) + min(max(max(d.x, d.y), max(d.z, d.w)), 0.0);
+}
+
+float sdJulia(VEC4 q, VEC4 c) {
+    VEC4 z = q;
+    float z2 = dot(z, z);
+    float dz2 = 1.0;
+    for (int i = 0; i < 11; i++) {
+        dz2 *= 4.0 * z2;
+        z = VEC4(z.x * z.x - dot(z.yzw, z.yzw), 2.0 * z.x * z.yzw) + c;
+        z2 = dot(z, z);
+        if (z2 > 256.0) {
+            break;
+        }
+    }
+    return 0.25 * sqrt(z2 / dz2) * log(z2);
+}
+";
+
+fn emit_4d(out: &mut String, dialect: Dialect) {
+    let helpers = match dialect {
+        Dialect::Wgsl => HELPERS_4D_WGSL,
+        _ => HELPERS_4D_C,
+    };
+    let _ = writeln!(out, "{}", helpers.replace("VEC4", dialect.vec4()));
+}
+
+// sdOutline<id>: the distance to an extrusion's or revolution's outline, from its edges in
+// outline<id> as (x0, y0, x1, y1). The nearest edge gives the distance, and the edges a ray
+// along +x from the point crosses give its side (even-odd, so loops within loops are holes).
+const OUTLINE_WGSL: &str = "\
+fn NAME(q: VEC3PARAMS) -> f32 {
+    let p = POINT;
+    var d = 1e10;
+    var s = 1.0;
+    for (var i = 0; i < COUNT; i++) {
+        let edge = EDGES[i];
+        let e = edge.zw - edge.xy;
+        let w = p - edge.xy;
+        let b = w - e * clamp(dot(w, e) / dot(e, e), 0.0, 1.0);
+        d = min(d, dot(b, b));
+        let up = p.y >= edge.y;
+        let down = p.y < edge.w;
+        let left = e.x * w.y > e.y * w.x;
+        if ((up && down && left) || !(up || down || left)) {
+            s = -s;
+        }
+    }
+";
+
+const OUTLINE_C: &str = "\
+float NAME(VEC3 qPARAMS) {
+    VEC2 p = POINT;
+    float d = 1e10;
+    float s = 1.0;
+    for (int i = 0; i < COUNT; i++) {
+        VEC4 edge = EDGES[i];
+        VEC2 e = edge.zw - edge.xy;
+        VEC2 w = p - edge.xy;
+        VEC2 b = w - e * clamp(dot(w, e) / dot(e, e), 0.0, 1.0);
+        d = min(d, dot(b, b));
+        bool up = p.y >= edge.y;
+        bool down = p.y < edge.w;
+        bool left = e.x * w.y > e.y * w.x;
+        if ((up && down && left) || !(up || down || left)) {
+            s = -s;
+        }
+    }
+";
+
+// A constant array of vec4s named `name`
+fn emit_array(out: &mut String, dialect: Dialect, name: &str, values: &[[f32; 4]]) {
+    let count = values.len();
+    let array = format!("array<{}, {}>", dialect.vec4(), count);
+    let declaration = match dialect {
+        Dialect::Msl => format!("constant float4 {}[{}] = {{", name, count),
+        Dialect::Glsl => format!("const vec4 {0}[{1}] = vec4[{1}](", name, count),
+        Dialect::Wgsl => format!("var<private> {}: {} = {}(", name, array, array),
+    };
+    let values: Vec<String> = (values.iter())
+        .map(|value| format!("    {}", vec4_lit(dialect, *value)))
+        .collect();
+    let end = match dialect {
+        Dialect::Msl => "};",
+        _ => ");",
+    };
+    let _ = writeln!(out, "{}\n{}\n{}\n", declaration, values.join(",\n"), end);
+}
+
+// The edges and distance function of `shape` with object id `id`, if it is made from an
+// outline. An extrusion measures the outline in the xy plane and rounds off the edges of its
+// depth; a revolution measures it at the point's distance from the y axis and height.
+fn emit_outline(out: &mut String, dialect: Dialect, id: usize, shape: &Shape) {
+    let Some(outline) = shape.outline() else {
+        return;
+    };
+    let edges: Vec<[f32; 4]> = outline
+        .iter()
+        .flat_map(|points| (0..points.len()).map(|i| (points[i], points[(i + 1) % points.len()])))
+        .filter(|(a, b)| a != b)
+        .map(|(a, b)| [a[0], a[1], b[0], b[1]])
+        .collect();
+    let name = format!("outline{}", id);
+    emit_array(out, dialect, &name, &edges);
+
+    let (params, point, tail) = match (shape, dialect) {
+        (Shape::Extrusion { .. }, Dialect::Wgsl) => (
+            ", depth: f32, rounding: f32",
+            "q.xy",
+            "    let h = VEC2(s * sqrt(d) + rounding, abs(q.z) - depth + rounding);\n",
+        ),
+        (Shape::Extrusion { .. }, _) => (
+            ", float depth, float rounding",
+            "q.xy",
+            "    VEC2 h = VEC2(s * sqrt(d) + rounding, abs(q.z) - depth + rounding);\n",
+        ),
+        _ => ("", "VEC2(length(q.xz), q.y)", ""),
+    };
+    let result = match shape {
+        Shape::Extrusion { .. } => "min(max(h.x, h.y), 0.0) + length(max(h, VEC2(0.0))) - rounding",
+        _ => "s * sqrt(d)",
+    };
+    let template = match dialect {
+        Dialect::Wgsl => OUTLINE_WGSL,
+        _ => OUTLINE_C,
+    };
+    let function = format!("{}{}    return {};\n}}\n", template, tail, result)
+        .replace("PARAMS", params)
+        .replace("POINT", point)
+        .replace("NAME", &format!("sdOutline{}", id))
+        .replace("EDGES", &name)
+        .replace("COUNT", &edges.len().to_string())
+        .replace("VEC2", dialect.vec2())
+        .replace("VEC3", dialect.vec3())
+        .replace("VEC4", dialect.vec4());
+    let _ = writeln!(out, "{}", function);
+}
+
+// sdPlant<id>: the plant's segments (pairs of ends, the first holding the radius) joined by
+// JOIN, with SWAY moving their ends in the wind first
+const PLANT_WGSL: &str = "\
+fn NAME(p: VEC3) -> f32 {
+    var d = 1e10;
+    for (var i = 0; i < COUNT; i++) {
+        var a = SEGMENTS[2 * i].xyz;
+        var b = SEGMENTS[2 * i + 1].xyz;
+SWAY        d = JOIN;
+    }
+    return d;
+}
+";
+
+const PLANT_C: &str = "\
+float NAME(VEC3 pUNIFORMS) {
+    float d = 1e10;
+    for (int i = 0; i < COUNT; i++) {
+        VEC3 a = SEGMENTS[2 * i].xyz;
+        VEC3 b = SEGMENTS[2 * i + 1].xyz;
+SWAY        d = JOIN;
+    }
+    return d;
+}
+";
+
+// How fast plants sway in the wind, in radians per second
+const WIND_SPEED: f32 = 1.5;
+
+// The segments and distance function of `shape` with object id `id`, if it is a plant. In the
+// wind, each end moves along x by wind * h² * (sin(speed * time - h) + sin(h)) at height h, so
+// the tips sway furthest and a little behind the trunk, and shared ends stay together; plants
+// are still with a layout that bakes everything in.
+fn emit_plant(out: &mut String, dialect: Dialect, layout: &ParamLayout, id: usize, shape: &Shape) {
+    let Shape::Plant { blend, wind, .. } = shape else {
+        return;
+    };
+    let segments = plant::segments(shape).unwrap_or_default();
+    if segments.is_empty() {
+        let _ = writeln!(
+            out,
+            "{}\n    return 1e10;\n}}\n",
+            dialect.uniforms_function(
+                &format!("sdPlant{}", id),
+                "p",
+                dialect.vec3(),
+                dialect.float_type()
+            )
+        );
+        return;
+    }
+    let ends: Vec<[f32; 4]> = (segments.iter())
+        .flat_map(|segment| {
+            let [ax, ay, az] = segment.a;
+            let [bx, by, bz] = segment.b;
+            [[ax, ay, az, segment.radius], [bx, by, bz, 0.0]]
+        })
+        .collect();
+    let name = format!("plant{}", id);
+    emit_array(out, dialect, &name, &ends);
+
+    let sway = if layout.live && *wind != 0.0 {
+        let declare = match dialect {
+            Dialect::Wgsl => "let",
+            _ => "VEC2",
+        };
+        format!(
+            "        {0} h = max(VEC2(a.y, b.y), VEC2(0.0));\n        \
+             {0} s = {1} * h * h * (sin({2} * uniforms.time - h) + sin(h));\n        \
+             a.x += s.x;\n        b.x += s.y;\n",
+            declare,
+            lit(*wind),
+            lit(WIND_SPEED)
+        )
+    } else {
+        String::new()
+    };
+    let capsule = format!("sdCapsule(p, a, b, {}[2 * i].w)", name);
+    let join = match *blend {
+        0.0 => format!("min(d, {})", capsule),
+        blend => format!("smin(d, {}, {})", capsule, lit(blend)),
+    };
+    let template = match dialect {
+        Dialect::Wgsl => PLANT_WGSL,
+        _ => PLANT_C,
+    };
+    let function = template
+        .replace("SWAY", &sway)
+        .replace("JOIN", &join)
+        .replace("NAME", &format!("sdPlant{}", id))
+        .replace("UNIFORMS", dialect.uniforms_param())
+        .replace("SEGMENTS", &name)
+        .replace("COUNT", &segments.len().to_string())
+        .replace("VEC2", dialect.vec2())
+        .replace("VEC3", dialect.vec3())
+        .replace("VEC4", dialect.vec4());
+    let _ = writeln!(out, "{}", function);
+}
+
+// sdCloud<id>: the cloud's points (each followed by its color) as spheres joined by JOIN;
+// cloudColor<id>: their colors weighted by how near each sphere is
+const CLOUD_WGSL: &str = "\
+fn NAME(p: VEC3) -> f32 {
+    var d = 1e10;
+    for (var i = 0; i < COUNT; i++) {
+        d = JOIN;
+    }
+    return d;
+}
+
+fn COLOR(p: VEC3) -> VEC3 {
+    var color = VEC3(0.0);
+    var total = 0.0;
+    for (var i = 0; i < COUNT; i++) {
+        let s = max(length(p - POINTS[2 * i].xyz) - RADIUS, 0.0);
+        let w = 1.0 / (1e-4 + s * s);
+        color += w * POINTS[2 * i + 1].xyz;
+        total += w;
+    }
+    return color / total;
+}
+";
+
+const CLOUD_C: &str = "\
+float NAME(VEC3 p) {
+    float d = 1e10;
+    for (int i = 0; i < COUNT; i++) {
+        d = JOIN;
+    }
+    return d;
+}
+
+VEC3 COLOR(VEC3 p) {
+    VEC3 color = VEC3(0.0);
+    float total = 0.0;
+    for (int i = 0; i < COUNT; i++) {
+        float s = max(length(p - POINTS[2 * i].xyz) - RADIUS, 0.0);
+        float w = 1.0 / (1e-4 + s * s);
+        color += w * POINTS[2 * i + 1].xyz;
+        total += w;
+    }
+    return color / total;
+}
+";
+
+// The points and functions of `shape` with object id `id`, if it is a point cloud
+fn emit_cloud(out: &mut String, dialect: Dialect, id: usize, shape: &Shape) {
+    let Shape::Cloud {
+        radius,
+        blend,
+        points,
+        ..
+    } = shape
+    else {
+        return;
+    };
+    let values: Vec<[f32; 4]> = (points.iter())
+        .flat_map(|&[x, y, z, r, g, b]| [[x, y, z, 0.0], [r, g, b, 0.0]])
+        .collect();
+    let name = format!("cloud{}", id);
+    emit_array(out, dialect, &name, &values);
+
+    let sphere = format!("length(p - {}[2 * i].xyz) - {}", name, lit(*radius));
+    let join = match *blend {
+        0.0 => format!("min(d, {})", sphere),
+        blend => format!("smin(d, {}, {})", sphere, lit(blend)),
+    };
+    let template = match dialect {
+        Dialect::Wgsl => CLOUD_WGSL,
+        _ => CLOUD_C,
+    };
+    let functions = template
+        .replace("JOIN", &join)
+        .replace("NAME", &format!("sdCloud{}", id))
+        .replace("COLOR", &format!("cloudColor{}", id))
+        .replace("POINTS", &name)
+        .replace("RADIUS", &lit(*radius))
+        .replace("COUNT", &points.len().to_string())
+        .replace("VEC3", dialect.vec3());
+    let _ = writeln!(out, "{}", functions);
+}
+
+// heightAt<id>: a heightfield's height at a grid point, four to each entry of HEIGHTS;
+// sdHeightfield<id>: the terrain filtered between the points around p and raised to `height`,
+// its distance shrunk by how steeply it can rise and cut to its footprint and base
+const HEIGHTFIELD_WGSL: &str = "\
+fn AT(i: i32) -> f32 {
+    return HEIGHTS[i / 4][i % 4];
+}
+
+fn NAME(p: VEC3, height: f32) -> f32 {
+    let size = SIZE;
+    let g = clamp((p.xz / size * 0.5 + 0.5) * LAST, VEC2(0.0), LAST);
+    let cell = min(floor(g), LAST - 1.0);
+    let f = g - cell;
+    let i = i32(cell.y) * COLUMNS + i32(cell.x);
+    let h = height * mix(mix(AT(i), AT(i + 1), f.x),
+                         mix(AT(i + COLUMNS), AT(i + COLUMNS + 1), f.x), f.y);
+    let top = max(height, 0.0);
+    let bounds = sdBox(p - VEC3(0.0, 0.5 * (top - BASE), 0.0), VEC3(size.x, 0.5 * (top + BASE), size.y));
+    return max((p.y - h) / sqrt(1.0 + height * height * SLOPE * SLOPE), bounds);
+}
+";
+
+const HEIGHTFIELD_C: &str = "\
+float AT(int i) {
+    return HEIGHTS[i / 4][i % 4];
+}
+
+float NAME(VEC3 p, float height) {
+    VEC2 size = SIZE;
+    VEC2 g = clamp((p.xz / size * 0.5 + 0.5) * LAST, VEC2(0.0), LAST);
+    VEC2 cell = min(floor(g), LAST - 1.0);
+    VEC2 f = g - cell;
+    int i = int(cell.y) * COLUMNS + int(cell.x);
+    float h = height * mix(mix(AT(i), AT(i + 1), f.x),
+                           mix(AT(i + COLUMNS), AT(i + COLUMNS + 1), f.x), f.y);
+    float top = max(height, 0.0);
+    float bounds = sdBox(p - VEC3(0.0, 0.5 * (top - BASE), 0.0), VEC3(size.x, 0.5 * (top + BASE), size.y));
+    return max((p.y - h) / sqrt(1.0 + height * height * SLOPE * SLOPE), bounds);
+}
+";
+
+// The heights and functions of `shape` with object id `id`, if it is a heightfield
+fn emit_heightfield(out: &mut String, dialect: Dialect, id: usize, shape: &Shape) {
+    let Shape::Heightfield {
+        size,
+        base,
+        heights,
+        ..
+    } = shape
+    else {
+        return;
+    };
+    let (rows, columns) = (heights.len(), heights.first().map_or(0, Vec::len));
+    let values: Vec<[f32; 4]> = (heights.concat().chunks(4))
+        .map(|chunk| {
+            let mut value = [0.0; 4];
+            value[..chunk.len()].copy_from_slice(chunk);
+            value
+        })
+        .collect();
+    let name = format!("heightfield{}", id);
+    emit_array(out, dialect, &name, &values);
+
+    let vec2 = |x: f32, y: f32| format!("{}({}, {})", dialect.vec2(), lit(x), lit(y));
+    let template = match dialect {
+        Dialect::Wgsl => HEIGHTFIELD_WGSL,
+        _ => HEIGHTFIELD_C,
+    };
+    let functions = template
+        .replace("NAME", &format!("sdHeightfield{}", id))
+        .replace("AT", &format!("heightAt{}", id))
+        .replace("HEIGHTS", &name)
+        .replace("SIZE", &vec2(size[0], size[1]))
+        .replace("LAST", &vec2((columns - 1) as f32, (rows - 1) as f32))
+        .replace("COLUMNS", &columns.to_string())
+        .replace("BASE", &lit(*base))
+        .replace("SLOPE", &lit(heightfield::slope(heights, *size)))
+        .replace("VEC2", dialect.vec2())
+        .replace("VEC3", dialect.vec3());
+    let _ = writeln!(out, "{}", functions);
+}
+
+// cellBorder<id>: how far a point in the object's space is from the border of its Voronoi
+// cell, the plane halfway between its nearest site and the one beyond that plane closest to it
+const CELLS_WGSL: &str = "\
+fn NAME(p: VEC3) -> f32 {
+    var nearest = 0;
+    var best = 1e10;
+    for (var i = 0; i < COUNT; i++) {
+        let d = p - SITES[FIRSTi].xyz;
+        if (dot(d, d) < best) {
+            best = dot(d, d);
+            nearest = i;
+        }
+    }
+    let a = SITES[FIRSTnearest].xyz;
+    var border = 1e10;
+    for (var i = 0; i < COUNT; i++) {
+        let n = SITES[FIRSTi].xyz - a;
+        if (i != nearest && dot(n, n) > 0.0) {
+            border = min(border, dot(a + 0.5 * n - p, n) / length(n));
+        }
+    }
+    return border;
+}
+";
+
+const CELLS_C: &str = "\
+float NAME(VEC3 pUNIFORMS) {
+    int nearest = 0;
+    float best = 1e10;
+    for (int i = 0; i < COUNT; i++) {
+        VEC3 d = p - SITES[FIRSTi].xyz;
+        if (dot(d, d) < best) {
+            best = dot(d, d);
+            nearest = i;
+        }
+    }
+    VEC3 a = SITES[FIRSTnearest].xyz;
+    float border = 1e10;
+    for (int i = 0; i < COUNT; i++) {
+        VEC3 n = SITES[FIRSTi].xyz - a;
+        if (i != nearest && dot(n, n) > 0.0) {
+            border = min(border, dot(a + 0.5 * n - p, n) / length(n));
+        }
+    }
+    return border;
+}
+";
+
+// The border distance of object `id`'s cells, whose sites start at `first` in the uniforms'
+// `sites`; a layout that bakes everything in gets the sites where they rest as a constant
+fn emit_cells(
+    out: &mut String,
+    dialect: Dialect,
+    layout: &ParamLayout,
+    id: usize,
+    cells: &Cells,
+    first: usize,
+) {
+    let count = cells::count(cells);
+    let (sites, first) = if layout.live {
+        ("uniforms.sites".to_string(), format!("{} + ", first))
+    } else {
+        let name = format!("sites{}", id);
+        let points: Vec<[f32; 4]> = (cells::sites(cells, 0.0).into_iter())
+            .map(|[x, y, z]| [x, y, z, 0.0])
+            .collect();
+        emit_array(out, dialect, &name, &points);
+        (name, String::new())
+    };
+    let template = match dialect {
+        Dialect::Wgsl => CELLS_WGSL,
+        _ => CELLS_C,
+    };
+    let function = template
+        .replace("NAME", &format!("cellBorder{}", id))
+        .replace("UNIFORMS", dialect.uniforms_param())
+        .replace("SITES", &sites)
+        .replace("FIRST", &first)
+        .replace("COUNT", &count.to_string())
+        .replace("VEC3", dialect.vec3());
+    let _ = writeln!(out, "{}", function);
+}
+
+// The 4D point of a 4D shape's slice through `local`, turned in its rotation plane
+fn slice_4d(
+    dialect: Dialect,
+    layout: &ParamLayout,
+    object: &SceneObject,
+    local: &str,
+    (w, plane, angle): (f32, RotationPlane, f32),
+) -> String {
+    let target = |property: &str| format!("{}.{}", object.name, property);
+    let q = format!(
+        "{}({}, {})",
+        dialect.vec4(),
+        local,
+        float_param(layout, &target("w"), w)
+    );
+    if layout.slot(&target("angle")).is_none() && angle == 0.0 {
+        return q;
+    }
+    format!(
+        "opRotate4({}, {}, {})",
+        q,
+        vec4_lit(dialect, plane.axis()),
+        float_param(layout, &target("angle"), angle)
+    )
+}
+
+// The rotation to undo for `object`, unless it is never rotated
+fn rotated(layout: &ParamLayout, object: &SceneObject) -> Option<String> {
+    let target = format!("{}.rotation", object.name);
+    (layout.slot(&target).is_some() || object.rotation != [0.0; 3]).then_some(target)
+}
+
+// World space direction `object` is brushed along at p: one of its axes turned with it, or
+// around its y axis for radial brushing. Component i of a turned axis is its dot product with
+// world axis i taken into the object's space, which opRotate does.
+fn tangent(
+    dialect: Dialect,
+    layout: &ParamLayout,
+    object: &SceneObject,
+    tangent: Tangent,
+) -> String {
+    let local = match tangent {
+        Tangent::X => [1.0, 0.0, 0.0],
+        Tangent::Y | Tangent::Radial => [0.0, 1.0, 0.0],
+        Tangent::Z => [0.0, 0.0, 1.0],
+    };
+    let mut axis = vec3_lit(dialect, local);
+    if let Some(rotation) = rotated(layout, object) {
+        let degrees = vec3_param(dialect, layout, &rotation, object.rotation);
+        let components: Vec<String> = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]
+            .into_iter()
+            .map(|world| {
+                let world = vec3_lit(dialect, world);
+                format!("dot(opRotate({}, {}), {})", world, degrees, axis)
+            })
+            .collect();
+        axis = format!("{}({})", dialect.vec3(), components.join(", "));
+    }
+    match tangent {
+        Tangent::Radial => {
+            let target = format!("{}.position", object.name);
+            let center = vec3_param(dialect, layout, &target, object.position);
+            format!("cross({}, p - {})", axis, center)
+        }
+        _ => axis,
+    }
+}
+
+// The point p in the object's own space, and the scale it was divided by if any
+fn object_local(
+    dialect: Dialect,
+    layout: &ParamLayout,
+    object: &SceneObject,
+) -> (String, Option<String>) {
+    let target = |property: &str| format!("{}.{}", object.name, property);
+    let mut local = format!(
+        "p - {}",
+        vec3_param(dialect, layout, &target("position"), object.position)
+    );
+    if let Some(rotation) = rotated(layout, object) {
+        local = format!(
+            "opRotate({}, {})",
+            local,
+            vec3_param(dialect, layout, &rotation, object.rotation)
+        );
+    }
+    // Uniform scale: measure in the object's units, then scale the distance back
+    let scale = (layout.slot(&target("scale")).is_some() || object.scale != 1.0)
+        .then(|| float_param(layout, &target("scale"), object.scale));
+    if let Some(scale) = &scale {
+        local = format!("({}) / {}", local, scale);
+    }
+    if let Some(repeat) = &object.repeat {
+        // Axes without copies divide by 1 rather than a spacing that may be 0
+        let spacing = [0, 1, 2].map(|axis| match repeat.count[axis] {
+            1 => 1.0,
+            _ => repeat.spacing[axis],
+        });
+        local = format!(
+            "opRepeat({}, {}, {})",
+            local,
+            vec3_lit(dialect, spacing),
+            vec3_lit(dialect, repeat.count.map(|n| n as f32))
+        );
+    }
+    (local, scale)
+}
+
+fn object_distance(
+    dialect: Dialect,
+    layout: &ParamLayout,
+    id: usize,
+    object: &SceneObject,
+) -> String {
+    let target = |property: &str| format!("{}.{}", object.name, property);
+    let (local, scale) = object_local(dialect, layout, object);
+    let mut distance = object_shape(dialect, layout, id, object, &local);
+    if let Some(cells) = &object.cells {
+        let border = format!("cellBorder{}({}{})", id, local, dialect.uniforms_arg());
+        let width = float_param(layout, &target("cells"), cells.width);
+        distance = match cells.style {
+            CellStyle::Cracks => format!("max({}, {} - {})", distance, width, border),
+            CellStyle::Foam => format!("max({}, {} - {})", distance, border, width),
+        };
+    }
+    match scale {
+        Some(scale) => format!("{} * {}", distance, scale),
+        None => distance,
+    }
+}
+
+fn object_shape(
+    dialect: Dialect,
+    layout: &ParamLayout,
+    id: usize,
+    object: &SceneObject,
+    local: &str,
+) -> String {
+    let target = |property: &str| format!("{}.{}", object.name, property);
+    match &object.shape {
+        Shape::Sphere { radius } => format!(
+            "sdSphere({}, {})",
+            local,
+            float_param(layout, &target("radius"), *radius)
+        ),
+        Shape::Box { size } => format!(
+            "sdBox({}, {})",
+            local,
+            vec3_param(dialect, layout, &target("size"), *size)
+        ),
+        Shape::Plane { normal, height } => format!(
+            "sdPlane({}, {}, {})",
+            local,
+            vec3_param(dialect, layout, &target("normal"), *normal),
+            float_param(layout, &target("height"), *height)
+        ),
+        Shape::Capsule { a, b, radius } => format!(
+            "sdCapsule({}, {}, {}, {})",
+            local,
+            vec3_lit(dialect, *a),
+            vec3_lit(dialect, *b),
+            float_param(layout, &target("radius"), *radius)
+        ),
+        Shape::Tesseract {
+            size,
+            w,
+            plane,
+            angle,
+        } => format!(
+            "sdTesseract({}, {})",
+            slice_4d(dialect, layout, object, local, (*w, *plane, *angle)),
+            float_param(layout, &target("size"), *size)
+        ),
+        Shape::Hypersphere {
+            radius,
+            w,
+            plane,
+            angle,
+        } => format!(
+            "(length({}) - {})",
+            slice_4d(dialect, layout, object, local, (*w, *plane, *angle)),
+            float_param(layout, &target("radius"), *radius)
+        ),
+        Shape::Julia { c, w, plane, angle } => format!(
+            "sdJulia({}, {})",
+            slice_4d(dialect, layout, object, local, (*w, *plane, *angle)),
+            vec4_param(dialect, layout, &target("c"), *c)
+        ),
+        Shape::Extrusion {
+            depth, rounding, ..
+        } => format!(
+            "sdOutline{}({}, {}, {})",
+            id,
+            local,
+            float_param(layout, &target("depth"), *depth),
+            float_param(layout, &target("rounding"), *rounding)
+        ),
+        Shape::Revolution { .. } => format!("sdOutline{}({})", id, local),
+        Shape::Plant { .. } => format!("sdPlant{}({}{})", id, local, dialect.uniforms_arg()),
+        Shape::Cloud { .. } => format!("sdCloud{}({})", id, local),
+        Shape::Heightfield {
+            height,
+            exaggeration,
+            ..
+        } => format!(
+            "sdHeightfield{}({}, {} * {})",
+            id,
+            local,
+            float_param(layout, &target("height"), *height),
+            float_param(layout, &target("exaggeration"), *exaggeration)
+        ),
+    }
+}
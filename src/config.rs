@@ -0,0 +1,411 @@
+use crate::backend::BackendKind;
+use crate::cli::Cli;
+use crate::livecode;
+use crate::sync;
+use metal_raymarcher::adaptive::Adaptive;
+use metal_raymarcher::audio::AudioConfig;
+use metal_raymarcher::brush::BrushConfig;
+use metal_raymarcher::camera::{Lens, Region, Stereo};
+use metal_raymarcher::clock::ClockConfig;
+use metal_raymarcher::edges::Edges;
+use metal_raymarcher::inputs::{ScreenConfig, VideoConfig};
+use metal_raymarcher::quality::{AaMode, QualityConfig, QualityPreset};
+use metal_raymarcher::shafts::Shafts;
+use metal_raymarcher::toon::Toon;
+use metal_raymarcher::tween::Easing;
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+// Startup settings: loaded from the config file, then overridden by the command line.
+// The file lives in the platform config directory (or at --config) and is rewritten on exit
+// with what the session ended with: the window's geometry and monitor, the camera, the panels
+// shown and the last scene, which the next run starts from unless it is given --fresh.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub width: u32, // Logical window size
+    pub height: u32,
+    pub position: Option<[i32; 2]>, // Window position (physical pixels)
+    pub fullscreen: bool,
+    pub monitor: Option<String>,  // Name of the display the window was on
+    pub scene: Option<PathBuf>,   // Last scene file
+    pub camera: Option<[f32; 2]>, // Last orbit angle and distance
+    pub panels: Panels,
+    pub shader: Option<PathBuf>,
+    pub gpu: Option<String>,
+    pub backend: Option<BackendKind>, // None uses the platform default
+    pub scale: f32,
+    pub keybindings: Option<PathBuf>, // Keybindings file (see keybindings.rs)
+    pub capture_dir: PathBuf,         // Where exports and captures are written
+    pub stamp: bool,                  // Captures have their metadata printed over them
+    pub notify: bool,                 // Long jobs post a notification when they end (macOS)
+    pub preset: Option<QualityPreset>, // None picks one for the GPU
+    pub quality: Option<QualityConfig>, // Custom settings instead of the preset's
+    pub aa_mode: Option<AaMode>,      // Replaces the preset's or custom settings' one
+    pub adaptive: Option<Adaptive>,   // Supersampling only where the image needs it
+    pub culling: bool,                // Tiles marched without the objects they can't see
+    pub lens: Lens,                   // Camera projection
+    pub stereo: Stereo,
+    pub region: Region, // Part of the view drawn, for one screen of a video wall
+    pub toon: Option<Toon>, // Toon shading for every scene instead of each scene's choice
+    pub edges: Edges,   // Edge detection over the image
+    pub shafts: Option<Shafts>, // Volumetric light shafts
+    pub seed: u32,      // For the path tracer's and light shafts' sampling, and offsets noise_seed
+    pub remote: Option<u16>, // Port of the remote-control server (see remote.rs)
+    pub http: Option<u16>, // Port of the monitoring server (see http.rs)
+    pub watch: Option<PathBuf>, // Drop folder for scenes and shaders (see drop_folder.rs)
+    #[serde(deserialize_with = "livecode_address")]
+    pub livecode: Option<SocketAddr>, // Where shader pushes are taken (see livecode.rs)
+    pub sync: SyncConfig,
+    pub present: PresentConfig,
+    pub artnet: Option<PathBuf>, // Art-Net channel mapping file (see artnet.rs)
+    pub video: VideoConfig,
+    pub screen: ScreenConfig,
+    pub audio: AudioConfig,
+    pub clock: ClockConfig,
+    pub tween: TweenConfig,
+    pub brush: BrushConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            width: 1024,
+            height: 768,
+            position: None,
+            fullscreen: false,
+            monitor: None,
+            scene: None,
+            camera: None,
+            panels: Panels::default(),
+            shader: None,
+            gpu: None,
+            backend: None,
+            scale: 1.0,
+            keybindings: None,
+            capture_dir: PathBuf::from("exports"),
+            stamp: false,
+            notify: true,
+            preset: None,
+            quality: None,
+            aa_mode: None,
+            adaptive: None,
+            culling: false,
+            lens: Lens::default(),
+            stereo: Stereo::default(),
+            region: Region::default(),
+            toon: None,
+            edges: Edges::default(),
+            shafts: None,
+            seed: 0,
+            remote: None,
+            http: None,
+            watch: None,
+            livecode: None,
+            sync: SyncConfig::default(),
+            present: PresentConfig::default(),
+            artnet: None,
+            video: VideoConfig::default(),
+            screen: ScreenConfig::default(),
+            audio: AudioConfig::default(),
+            clock: ClockConfig::default(),
+            tween: TweenConfig::default(),
+            brush: BrushConfig::default(),
+        }
+    }
+}
+
+// Which panels were open when the last session ended
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Panels {
+    pub inspector: bool,
+    pub guides: bool, // Ground grid and axes
+}
+
+// How long the transitions started by each action take, in seconds (0 snaps)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TweenConfig {
+    pub reset_camera: f32,
+    pub cycle_quality: f32,
+    pub easing: Easing,
+}
+
+impl Default for TweenConfig {
+    fn default() -> Self {
+        Self {
+            reset_camera: 0.6,
+            cycle_quality: 0.4,
+            easing: Easing::EaseInOut,
+        }
+    }
+}
+
+// Frame sync between instances drawing a video wall ([sync] in the config file, see sync.rs):
+// send this instance's frames to `master`, or draw the ones arriving at `follow`
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyncConfig {
+    pub master: Option<SocketAddr>,
+    #[serde(deserialize_with = "follow_address")]
+    pub follow: Option<SocketAddr>,
+}
+
+// How frames reach the screen ([present] in the config file; the CAMetalLayer's settings, so
+// Metal only but for `low_latency`). Low latency is for installations where input-to-photon
+// latency matters more than throughput: two drawables, and each frame is finished on the GPU
+// before the next is started, so none queue up behind it
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PresentConfig {
+    pub drawables: u32,    // Most drawables in flight, 2 or 3 (maximumDrawableCount)
+    pub transaction: bool, // Present in step with Core Animation (presentsWithTransaction)
+    pub framebuffer_only: bool, // false lets screen capture tools read the drawables
+    pub low_latency: bool,
+    pub rate: Option<f32>, // Frames per second on a fixed grid of scene times, for capture
+}
+
+impl Default for PresentConfig {
+    fn default() -> Self {
+        Self {
+            drawables: 3,
+            transaction: false,
+            framebuffer_only: true,
+            low_latency: false,
+            rate: None,
+        }
+    }
+}
+
+impl Config {
+    // ~/Library/Application Support/metal-raymarcher/config.toml on macOS
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("metal-raymarcher").join("config.toml"))
+    }
+
+    // A missing file is not an error: first runs start from the defaults
+    pub fn load(path: &Path) -> Result<Self, String> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+        toml::from_str(&text).map_err(|err| format!("Invalid config {}: {}", path.display(), err))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|err| format!("Failed to create {}: {}", dir.display(), err))?;
+        }
+        let text = toml::to_string_pretty(self).map_err(|err| err.to_string())?;
+        std::fs::write(path, text)
+            .map_err(|err| format!("Failed to write {}: {}", path.display(), err))
+    }
+
+    // Resolve the settings for this run. Returns the merged config and the config as
+    // stored in the file, which is what gets updated and saved on exit.
+    pub fn from_cli(cli: &Cli) -> Result<(Self, Self), String> {
+        let stored = match cli.config.clone().or_else(Self::default_path) {
+            Some(path) => Self::load(&path)?,
+            None => Self::default(),
+        };
+        let mut config = stored.clone();
+        if cli.fresh {
+            config.forget_session();
+        }
+        config.apply_cli(cli);
+        Ok((config, stored))
+    }
+
+    // Where this run's config is saved (None when there is no config directory)
+    pub fn save_path(cli: &Cli) -> Option<PathBuf> {
+        cli.config.clone().or_else(Self::default_path)
+    }
+
+    // Start over from the defaults for what the last session left behind
+    fn forget_session(&mut self) {
+        let defaults = Self::default();
+        self.width = defaults.width;
+        self.height = defaults.height;
+        self.position = defaults.position;
+        self.monitor = defaults.monitor;
+        self.scene = defaults.scene;
+        self.camera = defaults.camera;
+        self.panels = defaults.panels;
+    }
+
+    fn apply_cli(&mut self, cli: &Cli) {
+        if let Some(width) = cli.width {
+            self.width = width;
+        }
+        if let Some(height) = cli.height {
+            self.height = height;
+        }
+        self.fullscreen |= cli.fullscreen;
+        #[cfg(feature = "offline")]
+        {
+            self.stamp |= cli.stamp;
+        }
+        if cli.scene.is_some() {
+            self.scene = cli.scene.clone();
+        }
+        if cli.shader.is_some() {
+            self.shader = cli.shader.clone();
+        }
+        if cli.gpu.is_some() {
+            self.gpu = cli.gpu.clone();
+        }
+        if cli.quality.is_some() {
+            self.preset = cli.quality;
+            self.quality = None;
+        }
+        if cli.aa.is_some() {
+            self.aa_mode = cli.aa;
+        }
+        if cli.adaptive || cli.adaptive_tile.is_some() || cli.adaptive_samples.is_some() {
+            let adaptive = self.adaptive.get_or_insert_with(Adaptive::default);
+            if let Some(tile) = cli.adaptive_tile {
+                adaptive.tile = tile;
+            }
+            if let Some(samples) = cli.adaptive_samples {
+                adaptive.samples = samples;
+            }
+        }
+        if cli.culling {
+            self.culling = true;
+        }
+        if let Some(projection) = cli.projection {
+            self.lens.projection = projection;
+        }
+        if let Some(amount) = cli.lens_amount {
+            self.lens.amount = amount;
+        }
+        if let Some(mode) = cli.stereo {
+            self.stereo.mode = mode;
+        }
+        if let Some(ipd) = cli.ipd {
+            self.stereo.ipd = ipd;
+        }
+        if let Some(convergence) = cli.convergence {
+            self.stereo.convergence = convergence;
+        }
+        if let Some(region) = cli.region.or(cli.tile) {
+            self.region = region;
+        }
+        if cli.toon || cli.toon_bands.is_some() || cli.outline.is_some() {
+            let toon = self.toon.get_or_insert_with(Toon::default);
+            if let Some(bands) = cli.toon_bands {
+                toon.bands = bands;
+            }
+            if let Some(outline) = cli.outline {
+                toon.outline = outline;
+            }
+        }
+        if let Some(mode) = cli.edges {
+            self.edges.mode = mode;
+        }
+        if let Some(width) = cli.edge_width {
+            self.edges.width = width;
+        }
+        if cli.shafts || cli.shaft_samples.is_some() || cli.shaft_intensity.is_some() {
+            let shafts = self.shafts.get_or_insert_with(Shafts::default);
+            if let Some(samples) = cli.shaft_samples {
+                shafts.samples = samples;
+            }
+            if let Some(intensity) = cli.shaft_intensity {
+                shafts.intensity = intensity;
+            }
+        }
+        if let Some(seed) = cli.seed {
+            self.seed = seed;
+        }
+        if cli.remote.is_some() {
+            self.remote = cli.remote;
+        }
+        if cli.http.is_some() {
+            self.http = cli.http;
+        }
+        if cli.livecode.is_some() {
+            self.livecode = cli.livecode;
+        }
+        if cli.sync_master.is_some() {
+            self.sync = SyncConfig {
+                master: cli.sync_master,
+                follow: None,
+            };
+        }
+        if cli.sync_follow.is_some() {
+            self.sync = SyncConfig {
+                master: None,
+                follow: cli.sync_follow,
+            };
+        }
+        if cli.watch.is_some() {
+            self.watch = cli.watch.clone();
+        }
+        if cli.artnet.is_some() {
+            self.artnet = cli.artnet.clone();
+        }
+        self.present.low_latency |= cli.low_latency;
+        if cli.present_rate.is_some() {
+            self.present.rate = cli.present_rate;
+        }
+        if cli.backend.is_some() {
+            self.backend = cli.backend;
+        }
+        if let Some(scale) = cli.scale {
+            self.scale = scale;
+        }
+        if cli.video.is_some() {
+            self.video.source = cli.video.clone();
+        }
+        if cli.video_device.is_some() {
+            self.video.device = cli.video_device.clone();
+        }
+        if cli.screen.is_some() {
+            self.screen.target = cli.screen.clone();
+        }
+        if cli.audio.is_some() {
+            self.audio.input = cli.audio.clone();
+        }
+        if let Some(bpm) = cli.bpm {
+            self.clock.bpm = bpm;
+        }
+        self.scale = self.scale.clamp(0.1, 4.0);
+    }
+}
+
+// A port alone or an address, as --livecode and --sync-follow take them
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Address {
+    Port(u16),
+    Text(String),
+}
+
+fn address<'de, D: Deserializer<'de>>(
+    deserializer: D,
+    parse: fn(&str) -> Result<SocketAddr, String>,
+) -> Result<Option<SocketAddr>, D::Error> {
+    match Option::<Address>::deserialize(deserializer)? {
+        Some(Address::Port(port)) => parse(&port.to_string()).map(Some),
+        Some(Address::Text(text)) => parse(&text).map(Some),
+        None => Ok(None),
+    }
+    .map_err(de::Error::custom)
+}
+
+fn livecode_address<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<SocketAddr>, D::Error> {
+    address(deserializer, livecode::address)
+}
+
+fn follow_address<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<SocketAddr>, D::Error> {
+    address(deserializer, sync::follow_address)
+}
@@ -0,0 +1,298 @@
+// Control over stdin/stdout (--control stdio): another program runs the renderer as a child
+// process, with no window, and drives it with newline-delimited JSON-RPC 2.0, one call per
+// line (or a batch, as an array). Parameters are passed by name:
+//
+//   {"jsonrpc": "2.0", "id": 1, "method": "load_scene", "params": {"path": "scenes/orb.toml"}}
+//   {"jsonrpc": "2.0", "id": 2, "method": "set_param", "params": {"target": "orb.radius",
+//    "value": 1.5}}   (on one line)
+//   {"jsonrpc": "2.0", "id": 3, "method": "render_frame", "params": {"time": 2.0}}
+//   {"jsonrpc": "2.0", "id": 4, "method": "save_image", "params": {"path": "out.png"}}
+//
+//   get_param {target}               -> {"value": [...]}
+//   set_param {target, value}        -> {"ok": true}   (a number or a list; undoable)
+//   list_params                      -> {"ball.radius": [1.0], ...}
+//   load_scene {path}                -> {"ok": true}
+//   get_camera                       -> {"angle": 0.3, "distance": 8.0}
+//   set_camera {angle?, distance?}   -> {"ok": true}
+//   set_size {width, height}         -> {"ok": true}   (pixels, before --scale)
+//   render_frame {time?}             -> {"frame": 0, "time": 0.0}   (time: a 60th after the last)
+//   save_image {path}                -> {"path": "..."}   (the last frame rendered, as a PNG)
+//   action {name}                    -> {"ok": true}   (any keybindings.rs name)
+//   quit                             -> {"ok": true}, then exits (as does closing stdin)
+//
+// Failures are JSON-RPC errors: -32700 for a line that isn't JSON, -32600 for one that isn't a
+// call, -32601 for an unknown method, -32602 for bad params and -32000 for anything else. Calls
+// without an id are notifications and get no answer. Besides answers, stdout carries events,
+// as notifications: "ready" (with the scene and image size) once before the first call, and
+// "log" (level "info" or "error", and the message) for every line the renderer prints. Other
+// output goes to stderr (on Unix), so every line on stdout is JSON.
+use crate::json::Json;
+use crate::keybindings::Action;
+use crate::remote::{self, Request};
+use crate::{offline, scaled_size, App};
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use winit::dpi::PhysicalSize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Control {
+    Stdio,
+}
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const FAILED: i64 = -32000;
+
+// Events go out on stdout (only while controlled over it)
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+// Where answers and events are written: stdout as it was before other output was moved off it
+static OUTPUT: Mutex<Option<Box<dyn Write + Send>>> = Mutex::new(None);
+
+// Send a printed line as a "log" event; false when not controlled over stdio
+pub fn log(level: &str, line: &str) -> bool {
+    if !ACTIVE.load(Ordering::Relaxed) {
+        return false;
+    }
+    notify(
+        "log",
+        Json::object([
+            ("level", Json::String(level.to_string())),
+            ("message", Json::String(line.to_string())),
+        ]),
+    );
+    true
+}
+
+fn notify(method: &str, params: Json) {
+    write(&Json::object([
+        ("jsonrpc", Json::String("2.0".to_string())),
+        ("method", Json::String(method.to_string())),
+        ("params", params),
+    ]));
+}
+
+fn write(message: &Json) {
+    let mut output = OUTPUT
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let _ = match output.as_mut() {
+        Some(output) => writeln!(output, "{}", message).and_then(|()| output.flush()),
+        None => writeln!(io::stdout(), "{}", message),
+    };
+}
+
+// What went wrong with a call: its JSON-RPC error code and message
+type Failure = (i64, String);
+
+// The state of a session beyond the app's
+struct Session {
+    frame: u64,                                                     // Frames rendered
+    next_time: f32, // Of the next frame, unless one is given
+    image: Option<(PhysicalSize<u32>, Vec<u8>, offline::Metadata)>, // The last one rendered
+    quit: bool,
+}
+
+// Keep stdout for answers and events from now on, sending everything else printed (by the
+// app, scripts or libraries) to stderr instead
+pub fn take_stdout() {
+    ACTIVE.store(true, Ordering::Relaxed);
+    let mut output = OUTPUT
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    *output = Some(Box::new(io::stdout()));
+    #[cfg(unix)]
+    {
+        use std::os::fd::{AsFd, AsRawFd};
+        extern "C" {
+            fn dup2(from: i32, to: i32) -> i32;
+        }
+        if let Ok(stdout) = io::stdout().as_fd().try_clone_to_owned() {
+            *output = Some(Box::new(std::fs::File::from(stdout)));
+            // SAFETY: both are open descriptors; stdout becomes a copy of stderr
+            unsafe { dup2(io::stderr().as_raw_fd(), io::stdout().as_raw_fd()) };
+        }
+    }
+}
+
+// Answer calls on stdin until it closes or a client calls quit
+pub fn serve(app: &mut App) {
+    let size = scaled_size(app.window_size, app.render_scale);
+    notify(
+        "ready",
+        Json::object([
+            ("scene", Json::String(app.scene.name.clone())),
+            ("width", Json::Number(size.width.into())),
+            ("height", Json::Number(size.height.into())),
+        ]),
+    );
+    let mut session = Session {
+        frame: 0,
+        next_time: 0.0,
+        image: None,
+        quit: false,
+    };
+    for line in io::stdin().lock().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let answer = match Json::parse(&line) {
+            Ok(Json::Array(calls)) if calls.is_empty() => Some(failure(
+                Json::Null,
+                (INVALID_REQUEST, "empty batch".to_string()),
+            )),
+            Ok(Json::Array(calls)) => {
+                let answers: Vec<Json> = (calls.iter())
+                    .filter_map(|message| call(app, &mut session, message))
+                    .collect();
+                (!answers.is_empty()).then_some(Json::Array(answers))
+            }
+            Ok(message) => call(app, &mut session, &message),
+            Err(err) => Some(failure(Json::Null, (PARSE_ERROR, err))),
+        };
+        if let Some(answer) = answer {
+            write(&answer);
+        }
+        if session.quit {
+            break;
+        }
+    }
+}
+
+// Carry out one call; its answer, None for a notification
+fn call(app: &mut App, session: &mut Session, message: &Json) -> Option<Json> {
+    let id = message.get("id").cloned();
+    let method = (message.get("jsonrpc").and_then(Json::as_str) == Some("2.0"))
+        .then(|| message.get("method").and_then(Json::as_str))
+        .flatten();
+    let Some(method) = method else {
+        let invalid = (INVALID_REQUEST, "not a JSON-RPC 2.0 call".to_string());
+        return Some(failure(id.unwrap_or(Json::Null), invalid));
+    };
+    let result = match message.get("params") {
+        None | Some(Json::Object(_)) => dispatch(app, session, method, message.get("params")),
+        Some(_) => Err((INVALID_PARAMS, "params are taken by name".to_string())),
+    };
+    let id = id?;
+    Some(match result {
+        Ok(result) => Json::object([
+            ("jsonrpc", Json::String("2.0".to_string())),
+            ("result", result),
+            ("id", id),
+        ]),
+        Err(err) => failure(id, err),
+    })
+}
+
+fn failure(id: Json, (code, message): Failure) -> Json {
+    Json::object([
+        ("jsonrpc", Json::String("2.0".to_string())),
+        (
+            "error",
+            Json::object([
+                ("code", Json::Number(code as f64)),
+                ("message", Json::String(message)),
+            ]),
+        ),
+        ("id", id),
+    ])
+}
+
+fn dispatch(
+    app: &mut App,
+    session: &mut Session,
+    method: &str,
+    params: Option<&Json>,
+) -> Result<Json, Failure> {
+    let done = Json::object([("ok", Json::Bool(true))]);
+    let param = |key: &str| params.and_then(|params| params.get(key));
+    let text = |key: &str| {
+        (param(key).and_then(Json::as_str))
+            .ok_or((INVALID_PARAMS, format!("\"{}\" takes a string", key)))
+    };
+    let number = |key: &str| match param(key) {
+        Some(value) => (value.as_f64())
+            .map(|value| Some(value as f32))
+            .ok_or((INVALID_PARAMS, format!("\"{}\" takes a number", key))),
+        None => Ok(None),
+    };
+    let remote = |app: &mut App, request| app.remote(request).map_err(|err| (FAILED, err));
+    match method {
+        "get_param" => remote(app, Request::Get(text("target")?.to_string())),
+        "set_param" => {
+            let values = remote::values(param("value")).ok_or((
+                INVALID_PARAMS,
+                "\"value\" takes a number or a list of numbers".to_string(),
+            ))?;
+            remote(app, Request::Set(text("target")?.to_string(), values))
+        }
+        "list_params" => Ok(app.parameters()),
+        "load_scene" => remote(app, Request::Load(PathBuf::from(text("path")?))),
+        "get_camera" => remote(app, Request::Get("camera".to_string())),
+        "set_camera" => remote(
+            app,
+            Request::Camera {
+                angle: number("angle")?,
+                distance: number("distance")?,
+            },
+        ),
+        "set_size" => {
+            let side = |key| match number(key)? {
+                Some(side) if (1.0..=16384.0).contains(&side) => Ok(side as u32),
+                _ => Err((INVALID_PARAMS, format!("\"{}\" takes 1-16384 pixels", key))),
+            };
+            app.resize(PhysicalSize::new(side("width")?, side("height")?));
+            Ok(done)
+        }
+        "render_frame" => {
+            let time = number("time")?.unwrap_or(session.next_time);
+            if !time.is_finite() {
+                return Err((INVALID_PARAMS, "\"time\" must be finite".to_string()));
+            }
+            app.update_at(time);
+            let size = scaled_size(app.window_size, app.render_scale);
+            let mut pixels = app.backend.render_to_rgba(size, &app.camera, app.time);
+            let metadata = app.stamped(size, &mut pixels);
+            app.stats.frame();
+            session.image = Some((size, pixels, metadata));
+            let frame = session.frame;
+            session.frame += 1;
+            session.next_time = time + 1.0 / offline::RENDER_FPS;
+            Ok(Json::object([
+                ("frame", Json::Number(frame as f64)),
+                ("time", time.into()),
+            ]))
+        }
+        "save_image" => {
+            let path = PathBuf::from(text("path")?);
+            let Some((size, pixels, metadata)) = &session.image else {
+                return Err((FAILED, "no frame rendered yet".to_string()));
+            };
+            offline::write_png(&path, size.width, size.height, pixels, metadata)
+                .map_err(|err| (FAILED, err))?;
+            Ok(Json::object([(
+                "path",
+                Json::String(path.display().to_string()),
+            )]))
+        }
+        "action" => {
+            let name = text("name")?;
+            let action = Action::deserialize(toml::Value::String(name.to_string()))
+                .map_err(|_| (INVALID_PARAMS, format!("unknown action \"{}\"", name)))?;
+            remote(app, Request::Action(action))
+        }
+        "quit" => {
+            session.quit = true;
+            Ok(done)
+        }
+        _ => Err((METHOD_NOT_FOUND, format!("no method \"{}\"", method))),
+    }
+}
@@ -0,0 +1,67 @@
+// Notice when the GPU being drawn with goes away: an eGPU unplugged, or the user asking to
+// disconnect it from the menu bar (which waits until its apps let go of it). The renderer
+// then moves to another GPU instead of stalling on a device that no longer draws.
+// macOS only; iOS devices have a single GPU that stays.
+use block2::RcBlock;
+use metal::DeviceRef;
+use objc2::rc::Retained;
+use objc2::runtime::{AnyObject, NSObject, ProtocolObject};
+use objc2_metal::{
+    MTLDevice, MTLDeviceNotificationHandler, MTLDeviceNotificationName, MTLRemoveDeviceObserver,
+};
+use std::ptr::{self, NonNull};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+extern "C" {
+    // Not bound by objc2-metal: the devices (retained) and an observer (autoreleased) that
+    // calls `handler` as devices come and go, until passed to MTLRemoveDeviceObserver
+    fn MTLCopyAllDevicesWithObserver(
+        observer: *mut *mut NSObject,
+        handler: MTLDeviceNotificationHandler,
+    ) -> *mut AnyObject;
+}
+
+pub struct DeviceWatch {
+    observer: Retained<NSObject>,
+    gone: Arc<AtomicBool>, // Set from Metal's notification queue
+}
+
+// The observer is only used to unregister, which Metal allows from any thread
+unsafe impl Send for DeviceWatch {}
+
+impl DeviceWatch {
+    // Watch `device` (any notification about it is a removal: it was added long ago)
+    pub fn new(device: &DeviceRef) -> Self {
+        let gone = Arc::new(AtomicBool::new(false));
+        let registry_id = device.registry_id();
+        let flag = gone.clone();
+        let handler = RcBlock::new(
+            move |device: NonNull<ProtocolObject<dyn MTLDevice>>,
+                  _name: NonNull<MTLDeviceNotificationName>| {
+                if unsafe { device.as_ref().registryID() } == registry_id {
+                    flag.store(true, Ordering::Release);
+                }
+            },
+        );
+        let mut observer = ptr::null_mut();
+        // Metal copies the handler, so the block may be dropped after this
+        let devices = unsafe {
+            MTLCopyAllDevicesWithObserver(&mut observer, &*handler as *const _ as *mut _)
+        };
+        drop(unsafe { Retained::from_raw(devices) });
+        let observer = unsafe { Retained::retain(observer) }.expect("No Metal device observer");
+        Self { observer, gone }
+    }
+
+    // Whether the device was removed, or its removal requested, since the watch began
+    pub fn gone(&self) -> bool {
+        self.gone.load(Ordering::Acquire)
+    }
+}
+
+impl Drop for DeviceWatch {
+    fn drop(&mut self) {
+        unsafe { MTLRemoveDeviceObserver(&self.observer) };
+    }
+}
@@ -0,0 +1,201 @@
+// Frame pacing by the display (macOS): a CVDisplayLink calls back on a Core Video thread ahead
+// of each refresh of the display the window is on, and the render thread waits for that
+// before drawing, rather than drawing as fast as drawables come back and blocking on the next.
+// Each frame starts right after a refresh and is timed for when it will be shown, so motion
+// advances in even steps. A frame that took longer than a refresh is followed at once by one
+// for the latest refresh: the refreshes missed are skipped, not caught up on. Between frames
+// the render thread sleeps. iOS has no CVDisplayLink; frames there are paced by drawables.
+use std::ffi::c_void;
+use std::ptr;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+type CVDisplayLinkRef = *mut c_void;
+type CVReturn = i32;
+
+#[repr(C)]
+struct CVSMPTETime {
+    subframes: i16,
+    subframe_divisor: i16,
+    counter: u32,
+    kind: u32,
+    flags: u32,
+    hours: i16,
+    minutes: i16,
+    seconds: i16,
+    frames: i16,
+}
+
+#[repr(C)]
+struct CVTimeStamp {
+    version: u32,
+    video_time_scale: i32,
+    video_time: i64,
+    host_time: u64, // mach_absolute_time units
+    rate_scalar: f64,
+    video_refresh_period: i64, // In video_time_scale units
+    smpte_time: CVSMPTETime,
+    flags: u64,
+    reserved: u64,
+}
+
+#[repr(C)]
+struct MachTimebase {
+    numer: u32,
+    denom: u32,
+}
+
+type OutputCallback = extern "C" fn(
+    CVDisplayLinkRef,
+    *const CVTimeStamp, // Now
+    *const CVTimeStamp, // When the frame drawn for this refresh will be shown
+    u64,
+    *mut u64,
+    *mut c_void,
+) -> CVReturn;
+
+#[link(name = "CoreVideo", kind = "framework")]
+extern "C" {
+    fn CVDisplayLinkCreateWithActiveCGDisplays(link: *mut CVDisplayLinkRef) -> CVReturn;
+    fn CVDisplayLinkSetOutputCallback(
+        link: CVDisplayLinkRef,
+        callback: OutputCallback,
+        context: *mut c_void,
+    ) -> CVReturn;
+    fn CVDisplayLinkSetCurrentCGDisplay(link: CVDisplayLinkRef, display: u32) -> CVReturn;
+    fn CVDisplayLinkStart(link: CVDisplayLinkRef) -> CVReturn;
+    fn CVDisplayLinkStop(link: CVDisplayLinkRef) -> CVReturn;
+    fn CVDisplayLinkRelease(link: CVDisplayLinkRef);
+}
+
+extern "C" {
+    fn mach_timebase_info(info: *mut MachTimebase) -> i32;
+}
+
+// The latest refresh called back for
+#[derive(Clone, Copy)]
+struct Refresh {
+    count: u64, // Refreshes since the link started
+    shown: Instant,
+    period: Option<Duration>, // Between refreshes, when the display reports it
+}
+
+// Between the Core Video thread and the render thread
+struct Shared {
+    latest: Mutex<Option<Refresh>>,
+    refreshed: Condvar,
+    nanos_per_tick: f64, // Of host time
+}
+
+pub struct DisplayLink {
+    link: CVDisplayLinkRef,
+    shared: Arc<Shared>, // Outlives the link, which calls back with a pointer to it
+    seen: u64,           // The last refresh a frame was drawn for
+}
+
+// CVDisplayLink is thread-safe; only the render thread uses this one
+unsafe impl Send for DisplayLink {}
+
+// The refresh to draw the next frame for
+pub struct Frame {
+    pub shown: Instant,           // When it will be on screen
+    pub period: Option<Duration>, // Between refreshes of the display
+}
+
+impl DisplayLink {
+    // Start calling back at every refresh (of the main display until `set_display`)
+    pub fn new() -> Result<Self, String> {
+        let mut timebase = MachTimebase { numer: 1, denom: 1 };
+        unsafe { mach_timebase_info(&mut timebase) };
+        let shared = Arc::new(Shared {
+            latest: Mutex::new(None),
+            refreshed: Condvar::new(),
+            nanos_per_tick: timebase.numer as f64 / timebase.denom.max(1) as f64,
+        });
+        let mut link = ptr::null_mut();
+        let status = unsafe { CVDisplayLinkCreateWithActiveCGDisplays(&mut link) };
+        if status != 0 || link.is_null() {
+            return Err(format!(
+                "Failed to create a display link (CVReturn {})",
+                status
+            ));
+        }
+        // SAFETY: `shared` is kept alive by the DisplayLink until the link is stopped
+        let status = unsafe {
+            CVDisplayLinkSetOutputCallback(link, refreshed, Arc::as_ptr(&shared) as *mut c_void);
+            CVDisplayLinkStart(link)
+        };
+        if status != 0 {
+            unsafe { CVDisplayLinkRelease(link) };
+            return Err(format!(
+                "Failed to start the display link (CVReturn {})",
+                status
+            ));
+        }
+        Ok(Self {
+            link,
+            shared,
+            seen: 0,
+        })
+    }
+
+    // Follow the refreshes of `display` (a CGDirectDisplayID), for a window moved onto it
+    pub fn set_display(&mut self, display: u32) {
+        unsafe { CVDisplayLinkSetCurrentCGDisplay(self.link, display) };
+    }
+
+    // Wait for a refresh after the last one drawn for, returning at once if one went by while
+    // that frame was being drawn; None if none comes within `timeout` (the display is asleep)
+    pub fn wait(&mut self, timeout: Duration) -> Option<Frame> {
+        let seen = self.seen;
+        let latest = (self.shared.latest.lock()).unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (latest, _) = (self.shared.refreshed)
+            .wait_timeout_while(latest, timeout, |latest| {
+                latest.is_none_or(|refresh| refresh.count <= seen)
+            })
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let refresh = latest.filter(|refresh| refresh.count > seen)?;
+        self.seen = refresh.count;
+        Some(Frame {
+            shown: refresh.shown,
+            period: refresh.period,
+        })
+    }
+}
+
+impl Drop for DisplayLink {
+    fn drop(&mut self) {
+        // Stopping waits for a callback under way, so `shared` isn't used after this
+        unsafe {
+            CVDisplayLinkStop(self.link);
+            CVDisplayLinkRelease(self.link);
+        }
+    }
+}
+
+extern "C" fn refreshed(
+    _link: CVDisplayLinkRef,
+    now: *const CVTimeStamp,
+    output: *const CVTimeStamp,
+    _flags_in: u64,
+    _flags_out: *mut u64,
+    context: *mut c_void,
+) -> CVReturn {
+    // SAFETY: the context is the link's Shared (see DisplayLink::new), and Core Video passes
+    // timestamps valid for the call
+    let (shared, now, output) = unsafe { (&*(context as *const Shared), &*now, &*output) };
+    let ahead = output.host_time.saturating_sub(now.host_time) as f64 * shared.nanos_per_tick;
+    let shown = Instant::now() + Duration::from_nanos(ahead as u64);
+    let period = (output.video_time_scale > 0 && output.video_refresh_period > 0).then(|| {
+        Duration::from_secs_f64(output.video_refresh_period as f64 / output.video_time_scale as f64)
+    });
+    let mut latest = (shared.latest.lock()).unwrap_or_else(|poisoned| poisoned.into_inner());
+    let count = latest.map_or(1, |refresh| refresh.count + 1);
+    *latest = Some(Refresh {
+        count,
+        shown,
+        period,
+    });
+    shared.refreshed.notify_one();
+    0
+}
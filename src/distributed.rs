@@ -0,0 +1,593 @@
+// Distributed offline rendering. A coordinator (--render DIR --distribute ADDRESS) splits the
+// frames of the render into chunks of CHUNK_FRAMES and hands them to workers (--worker
+// HOST:PORT) as they connect and finish earlier ones. A worker renders each chunk headlessly
+// with its own GPU, in a child process running the coordinator's command line and config with
+// --frame-range, and streams every frame back as soon as it is written; the coordinator saves
+// them in DIR as a plain --render would. When a worker disconnects, its render fails or it sends
+// nothing for FRAME_TIMEOUT, the frames it hadn't sent yet go back to be handed out again, up
+// to MAX_ATTEMPTS times.
+//
+// Only the options in PASSED_ON, which change what is rendered, are passed on, and the worker
+// checks them again: a coordinator can't start servers or write files on a worker elsewhere
+// than its own render folder. Given a port alone the coordinator listens on this machine only;
+// 0.0.0.0:PORT takes workers from other machines, and anyone who can reach it can send frames,
+// so keep it to a trusted network.
+//
+// Workers resolve the scene, sequence, replay and workspace paths of the command line against
+// their own working directory, so they need the same files there (the same checkout, or a
+// shared drive).
+//
+// Over TCP, each message is a JSON header and a body of bytes, each preceded by its length as
+// a big-endian u32:
+//   coordinator -> worker  {"args": [...], "config": "<TOML>"}   once, when the worker connects
+//                          {"frames": [start, end]}              a chunk to render (end excluded)
+//                          {"done": true}                        every frame has been received
+//                          {"waiting": true}                     every KEEPALIVE while all the
+//                                                                frames left are with others
+//   worker -> coordinator  {"frame": n} with the PNG as the body, for each frame of a chunk
+//                          {"failed": "..."}                     the chunk's render failed
+use crate::cli::Cli;
+use crate::config::{Config, SyncConfig};
+use crate::json::Json;
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CHUNK_FRAMES: usize = 10;
+const MAX_ATTEMPTS: u32 = 3; // Of a frame, before the render is given up on
+const MAX_HEADER: usize = 1 << 20;
+const MAX_BODY: usize = 1 << 30;
+const READ_TIMEOUT: Duration = Duration::from_secs(30); // Between the coordinator's messages
+const KEEPALIVE: Duration = Duration::from_secs(10);
+const FRAME_TIMEOUT: Duration = Duration::from_secs(600); // Between a worker's frames
+
+// Options of the coordinator's command line passed on to workers: those that change what is
+// rendered. Where frames go, which ones, the config and the GPU are the worker's to pick, and
+// servers, sync, recordings and the rest of a live session are the coordinator's.
+const PASSED_ON: [&str; 41] = [
+    "--width",
+    "--height",
+    "--fresh",
+    "--scene",
+    "--shader",
+    "--stamp",
+    "--frames",
+    "--backend",
+    "--scale",
+    "--quality",
+    "--aa",
+    "--adaptive",
+    "--adaptive-tile",
+    "--adaptive-samples",
+    "--culling",
+    "--projection",
+    "--lens-amount",
+    "--stereo",
+    "--ipd",
+    "--convergence",
+    "--region",
+    "--tile",
+    "--toon",
+    "--toon-bands",
+    "--outline",
+    "--edges",
+    "--edge-width",
+    "--shafts",
+    "--shaft-samples",
+    "--shaft-intensity",
+    "--seed",
+    "--path-trace",
+    "--samples",
+    "--video",
+    "--video-device",
+    "--screen",
+    "--audio",
+    "--bpm",
+    "--replay",
+    "--restore",
+    "--sequence",
+];
+
+// What's left to do, shared between the threads talking to workers
+struct Progress {
+    pending: VecDeque<Range<usize>>,
+    received: usize,
+    total: usize,
+    attempts: HashMap<usize, u32>, // Failed attempts per frame
+    failed: Option<String>,        // Why the render was given up on
+}
+
+impl Progress {
+    fn finished(&self) -> bool {
+        self.received == self.total || self.failed.is_some()
+    }
+}
+
+// Hand `frames` out to workers connecting on `address` and write them to `dir`
+pub fn coordinate(
+    address: SocketAddr,
+    config: &Config,
+    dir: &Path,
+    frames: Range<usize>,
+) -> Result<(), String> {
+    std::fs::create_dir_all(dir)
+        .map_err(|err| format!("Failed to create {}: {}", dir.display(), err))?;
+    let listener = TcpListener::bind(address)
+        .map_err(|err| format!("Failed to listen on {}: {}", address, err))?;
+
+    let args: Vec<String> = passed_on(std::env::args().skip(1));
+    let config = toml::to_string(&rendering(config.clone())).map_err(|err| err.to_string())?;
+    let job = Json::object([
+        (
+            "args",
+            Json::Array(args.into_iter().map(Json::String).collect()),
+        ),
+        ("config", Json::String(config)),
+    ]);
+
+    let total = frames.len();
+    let pending = (frames.clone().step_by(CHUNK_FRAMES))
+        .map(|start| start..(start + CHUNK_FRAMES).min(frames.end))
+        .collect();
+    let shared = Arc::new((
+        Mutex::new(Progress {
+            pending,
+            received: 0,
+            total,
+            attempts: HashMap::new(),
+            failed: None,
+        }),
+        Condvar::new(),
+    ));
+    println!(
+        "Waiting for workers on {} to render {} frames",
+        address, total
+    );
+
+    let handles = Arc::new(Mutex::new(Vec::new()));
+    {
+        let (shared, handles, dir) = (shared.clone(), handles.clone(), dir.to_path_buf());
+        thread::Builder::new()
+            .name("coordinator".to_string())
+            .spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let (shared, dir, job) = (shared.clone(), dir.clone(), job.clone());
+                    let spawned = thread::Builder::new()
+                        .name("worker".to_string())
+                        .spawn(move || serve_worker(stream, &shared, &dir, &job));
+                    match spawned {
+                        Ok(handle) => lock(&handles).push(handle),
+                        Err(err) => eprintln!("Failed to serve a worker: {}", err),
+                    }
+                }
+            })
+            .map_err(|err| err.to_string())?;
+    }
+
+    let (progress, changed) = &*shared;
+    let mut progress = lock(progress);
+    while !progress.finished() {
+        progress = changed
+            .wait(progress)
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+    }
+    if let Some(err) = progress.failed.take() {
+        return Err(err);
+    }
+    drop(progress);
+    // Idle workers are told there is nothing left, so they can exit
+    for handle in lock(&handles).drain(..) {
+        let _ = handle.join();
+    }
+    Ok(())
+}
+
+// Give chunks to the worker on `stream` until every frame is in, or it fails
+fn serve_worker(
+    mut stream: TcpStream,
+    shared: &(Mutex<Progress>, Condvar),
+    dir: &Path,
+    job: &Json,
+) {
+    let worker = (stream.peer_addr()).map_or("a worker".to_string(), |addr| addr.to_string());
+    println!("Worker {} connected", worker);
+    let timeouts = (stream.set_read_timeout(Some(FRAME_TIMEOUT)))
+        .and_then(|()| stream.set_write_timeout(Some(READ_TIMEOUT)));
+    if let Err(err) = timeouts
+        .map_err(|err| err.to_string())
+        .and_then(|()| send(&mut stream, job, &[]))
+    {
+        eprintln!("Worker {}: {}", worker, err);
+        return;
+    }
+    let (progress, changed) = shared;
+    loop {
+        let mut told = Instant::now(); // When the worker last heard from us
+        let chunk = loop {
+            let mut progress = lock(progress);
+            if progress.finished() {
+                drop(progress);
+                let _ = send(
+                    &mut stream,
+                    &Json::object([("done", Json::Bool(true))]),
+                    &[],
+                );
+                return;
+            }
+            if let Some(chunk) = progress.pending.pop_front() {
+                break chunk;
+            }
+            // Everything left is with other workers, which may yet fail
+            let (progress, _) = changed
+                .wait_timeout(progress, KEEPALIVE.saturating_sub(told.elapsed()))
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            drop(progress);
+            if told.elapsed() >= KEEPALIVE {
+                let waiting = Json::object([("waiting", Json::Bool(true))]);
+                if let Err(err) = send(&mut stream, &waiting, &[]) {
+                    eprintln!("Worker {}: {}", worker, err);
+                    return;
+                }
+                told = Instant::now();
+            }
+        };
+
+        let mut next = chunk.start;
+        let rendered = render_chunk(&mut stream, shared, dir, &worker, &chunk, &mut next);
+        if let Err(err) = rendered {
+            let mut progress = lock(progress);
+            let attempts = progress.attempts.entry(next).or_default();
+            *attempts += 1;
+            if *attempts >= MAX_ATTEMPTS {
+                progress.failed = Some(format!(
+                    "Frame {} failed {} times, last on {}: {}",
+                    next, MAX_ATTEMPTS, worker, err
+                ));
+            } else {
+                eprintln!(
+                    "Worker {} failed on frames {}..{}, handing them out again: {}",
+                    worker, next, chunk.end, err
+                );
+                progress.pending.push_front(next..chunk.end);
+            }
+            changed.notify_all();
+            return;
+        }
+    }
+}
+
+// Have the worker render `chunk`, saving its frames as they come; `next` is left at the first
+// frame that didn't
+fn render_chunk(
+    stream: &mut TcpStream,
+    shared: &(Mutex<Progress>, Condvar),
+    dir: &Path,
+    worker: &str,
+    chunk: &Range<usize>,
+    next: &mut usize,
+) -> Result<(), String> {
+    let frames = Json::Array(vec![
+        Json::Number(chunk.start as f64),
+        Json::Number(chunk.end as f64),
+    ]);
+    send(stream, &Json::object([("frames", frames)]), &[])?;
+    while *next < chunk.end {
+        let (header, png) = receive(stream)?;
+        if let Some(err) = header.get("failed") {
+            return Err(err.as_str().unwrap_or_default().to_string());
+        }
+        if header.get("frame").and_then(Json::as_f64) != Some(*next as f64) {
+            return Err(format!("expected frame {}, got {}", next, header));
+        }
+        let path = dir.join(format!("frame_{:04}.png", next));
+        std::fs::write(&path, png).map_err(|err| format!("{}: {}", path.display(), err))?;
+        *next += 1;
+
+        let (progress, changed) = shared;
+        let mut progress = lock(progress);
+        progress.received += 1;
+        println!(
+            "Received {} from {} ({}/{})",
+            path.display(),
+            worker,
+            progress.received,
+            progress.total
+        );
+        changed.notify_all();
+    }
+    Ok(())
+}
+
+// Render chunks for the coordinator at `address` until it has every frame
+pub fn work(address: &str, cli: &Cli) -> Result<(), String> {
+    let mut stream = TcpStream::connect(address)
+        .map_err(|err| format!("Failed to connect to {}: {}", address, err))?;
+    println!("Connected to {}", address);
+    // The coordinator sends a chunk or KEEPALIVE at least every READ_TIMEOUT
+    (stream.set_read_timeout(Some(READ_TIMEOUT)))
+        .and_then(|()| stream.set_write_timeout(Some(READ_TIMEOUT)))
+        .map_err(|err| err.to_string())?;
+    let (job, _) = receive(&mut stream)?;
+    // Checked again, for a coordinator that isn't this one
+    let args: Vec<String> = match job.get("args") {
+        Some(Json::Array(args)) => {
+            passed_on((args.iter()).filter_map(|arg| arg.as_str().map(str::to_string)))
+        }
+        _ => return Err("the coordinator sent no command line".to_string()),
+    };
+    let text = job.get("config").and_then(Json::as_str).unwrap_or_default();
+    let config: Config =
+        toml::from_str(text).map_err(|err| format!("the coordinator's config: {}", err))?;
+    let text = toml::to_string(&rendering(config)).map_err(|err| err.to_string())?;
+    let dir = std::env::temp_dir().join(format!("metal-raymarcher-worker-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)
+        .map_err(|err| format!("Failed to create {}: {}", dir.display(), err))?;
+    let config = dir.join("config.toml");
+    std::fs::write(&config, text).map_err(|err| format!("{}: {}", config.display(), err))?;
+
+    let worked = loop {
+        let (message, _) = match receive(&mut stream) {
+            Ok(message) => message,
+            Err(err) => break Err(err),
+        };
+        if message.get("done").is_some() {
+            println!("The coordinator has every frame");
+            break Ok(());
+        }
+        if message.get("waiting").is_some() {
+            continue;
+        }
+        let range = match message.get("frames") {
+            Some(Json::Array(ends)) => match ends.as_slice() {
+                [Json::Number(start), Json::Number(end)] => *start as usize..*end as usize,
+                _ => break Err(format!("bad chunk {}", message)),
+            },
+            _ => break Err(format!("unexpected message {}", message)),
+        };
+        println!("Rendering frames {}..{}", range.start, range.end);
+        let mut child = vec!["--config".to_string(), config.display().to_string()];
+        child.extend(["--render".to_string(), dir.display().to_string()]);
+        child.extend([
+            "--frame-range".to_string(),
+            format!("{}..{}", range.start, range.end),
+        ]);
+        if let Some(gpu) = &cli.gpu {
+            child.extend(["--gpu".to_string(), gpu.clone()]);
+        }
+        if let Err(err) = render(&mut stream, &args, &child, &dir, range) {
+            let failed = Json::object([("failed", Json::String(err.clone()))]);
+            let _ = send(&mut stream, &failed, &[]);
+            break Err(err);
+        }
+    };
+    let _ = std::fs::remove_dir_all(&dir);
+    worked
+}
+
+// Run the coordinator's command line `args` with the worker's `own` options in a child
+// process, sending each frame of `range` it writes to `dir` on as it comes
+fn render(
+    stream: &mut TcpStream,
+    args: &[String],
+    own: &[String],
+    dir: &Path,
+    range: Range<usize>,
+) -> Result<(), String> {
+    let exe = std::env::current_exe().map_err(|err| err.to_string())?;
+    let mut child = Command::new(exe)
+        .args(args)
+        .args(own)
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("Failed to start the render: {}", err))?;
+    let output = child.stdout.take().map(BufReader::new);
+
+    // Frames are written in order, each followed by a "Rendered" line
+    let mut next = range.start;
+    let mut sent = Ok(());
+    for line in output
+        .into_iter()
+        .flat_map(BufRead::lines)
+        .map_while(Result::ok)
+    {
+        println!("{}", line);
+        if !line.starts_with("Rendered ") || next == range.end {
+            continue;
+        }
+        let path: PathBuf = dir.join(format!("frame_{:04}.png", next));
+        sent = std::fs::read(&path)
+            .map_err(|err| format!("{}: {}", path.display(), err))
+            .and_then(|png| {
+                let _ = std::fs::remove_file(&path);
+                send(
+                    stream,
+                    &Json::object([("frame", Json::Number(next as f64))]),
+                    &png,
+                )
+            });
+        if sent.is_err() {
+            let _ = child.kill();
+            break;
+        }
+        next += 1;
+    }
+    let status = child.wait().map_err(|err| err.to_string())?;
+    sent?;
+    if !status.success() || next < range.end {
+        return Err(format!("the render stopped at frame {} ({})", next, status));
+    }
+    Ok(())
+}
+
+fn send(stream: &mut impl Write, header: &Json, body: &[u8]) -> Result<(), String> {
+    let header = header.to_string();
+    let mut message = Vec::with_capacity(8 + header.len() + body.len());
+    message.extend_from_slice(&(header.len() as u32).to_be_bytes());
+    message.extend_from_slice(header.as_bytes());
+    message.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    message.extend_from_slice(body);
+    stream
+        .write_all(&message)
+        .map_err(|err| format!("Connection lost: {}", err))
+}
+
+fn receive(stream: &mut impl Read) -> Result<(Json, Vec<u8>), String> {
+    let lost = |err: std::io::Error| match err.kind() {
+        ErrorKind::UnexpectedEof => "Connection closed".to_string(),
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => "Timed out".to_string(),
+        _ => format!("Connection lost: {}", err),
+    };
+    let mut part = |max: usize| {
+        let mut len = [0; 4];
+        stream.read_exact(&mut len).map_err(lost)?;
+        let len = u32::from_be_bytes(len) as usize;
+        if len > max {
+            return Err(format!("message part of {} bytes, over {}", len, max));
+        }
+        let mut bytes = vec![0; len];
+        stream.read_exact(&mut bytes).map_err(lost)?;
+        Ok(bytes)
+    };
+    let header = part(MAX_HEADER)?;
+    let body = part(MAX_BODY)?;
+    let header = String::from_utf8(header).map_err(|_| "header is not UTF-8".to_string())?;
+    Ok((Json::parse(&header)?, body))
+}
+
+// The options of `args` in PASSED_ON, each with its value after "=" so that no value can be
+// taken for an option. The others are left out, with their value if they have one.
+fn passed_on(args: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut args = args.into_iter().peekable();
+    let mut kept = Vec::new();
+    while let Some(arg) = args.next() {
+        let (name, mut value) = match arg.split_once('=') {
+            Some((name, value)) => (name.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        // There are no positional arguments: anything after an option that isn't one is its
+        // value
+        if value.is_none() && args.peek().is_some_and(|next| !next.starts_with("--")) {
+            value = args.next();
+        }
+        if PASSED_ON.contains(&name.as_str()) {
+            kept.push(match value {
+                Some(value) => format!("{}={}", name, value),
+                None => name,
+            });
+        }
+    }
+    kept
+}
+
+// `config` without what the coordinator or the worker keeps for itself: the GPU, servers, the
+// drop folder, sync, Art-Net and notifications
+fn rendering(mut config: Config) -> Config {
+    (config.gpu, config.http, config.remote, config.livecode) = (None, None, None, None);
+    (config.watch, config.artnet) = (None, None);
+    config.sync = SyncConfig::default();
+    config.notify = false; // The coordinator does, for the whole render
+    config
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let header = Json::object([("frame", Json::Number(7.0))]);
+        let mut data = Vec::new();
+        send(&mut data, &header, b"png").unwrap();
+        send(&mut data, &Json::object([("done", Json::Bool(true))]), &[]).unwrap();
+        let mut reader = data.as_slice();
+        assert_eq!(receive(&mut reader), Ok((header, b"png".to_vec())));
+        let (done, body) = receive(&mut reader).unwrap();
+        assert!(done.get("done").is_some() && body.is_empty());
+        assert_eq!(receive(&mut reader), Err("Connection closed".to_string()));
+    }
+
+    #[test]
+    fn oversized_and_short_messages_are_rejected() {
+        let mut header = Vec::new();
+        send(&mut header, &Json::object([]), &[]).unwrap();
+        let oversized = ((MAX_HEADER + 1) as u32).to_be_bytes();
+        let mut body = header[..header.len() - 4].to_vec();
+        body.extend_from_slice(&((MAX_BODY + 1) as u32).to_be_bytes());
+        for message in [&oversized[..], &body] {
+            let err = receive(&mut &message[..]).unwrap_err();
+            assert!(err.contains("over"), "{}", err);
+        }
+        for len in 1..header.len() {
+            let short = receive(&mut &header[..len]);
+            assert_eq!(short, Err("Connection closed".to_string()), "{}", len);
+        }
+    }
+
+    #[test]
+    fn only_render_options_are_passed_on() {
+        let args = strings(&[
+            "--scene",
+            "scenes/blobs.toml",
+            "--render",
+            "out",
+            "--toon",
+            "--livecode=7000",
+            "--fullscreen",
+            "--seed=3",
+            "--view",
+            "mr://abc",
+            "--restore",
+            "--sync-follow",
+            "7400",
+            "--artnet",
+            "map.toml",
+        ]);
+        assert_eq!(
+            passed_on(args),
+            strings(&[
+                "--scene=scenes/blobs.toml",
+                "--toon",
+                "--seed=3",
+                "--restore"
+            ])
+        );
+        // A value that looks like an option stays a value
+        let args = strings(&["--scene", "x", "--config", "--scene=--render=/tmp"]);
+        assert_eq!(
+            passed_on(args),
+            strings(&["--scene=x", "--scene=--render=/tmp"])
+        );
+    }
+
+    #[test]
+    fn workers_get_no_servers() {
+        let config = Config {
+            http: Some(8080),
+            remote: Some(9000),
+            livecode: Some(SocketAddr::from(([0, 0, 0, 0], 7000))),
+            watch: Some(PathBuf::from("/")),
+            seed: 5,
+            ..Config::default()
+        };
+        let config = rendering(config);
+        assert!(config.http.is_none() && config.remote.is_none() && config.livecode.is_none());
+        assert!(config.watch.is_none() && !config.notify);
+        assert_eq!(config.seed, 5);
+    }
+}
@@ -0,0 +1,88 @@
+// Drop folder (--watch DIR): scene (.toml) and shader (.metal, .wgsl) files written into the
+// directory are loaded as they arrive, so an editor on another machine saving into a shared
+// folder can drive a display box. Files already there at startup are left alone. A file is
+// picked up once it has stayed the same size and age for a whole poll, so one still being
+// copied in isn't read half written.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+pub enum Dropped {
+    Scene(PathBuf),
+    Shader(PathBuf),
+}
+
+pub struct DropFolder {
+    dir: PathBuf,
+    seen: HashMap<PathBuf, Stamp>, // Files as they were last loaded (or found at startup)
+    settling: HashMap<PathBuf, Stamp>, // New or changed since, as of the last poll
+    polled: Instant,
+}
+
+// What tells a file's versions apart
+type Stamp = (Option<SystemTime>, u64);
+
+impl DropFolder {
+    pub fn new(dir: &Path) -> Self {
+        let mut folder = Self {
+            dir: dir.to_path_buf(),
+            seen: HashMap::new(),
+            settling: HashMap::new(),
+            polled: Instant::now(),
+        };
+        match std::fs::create_dir_all(dir) {
+            Ok(()) => println!("Watching {} for scenes and shaders", dir.display()),
+            Err(err) => eprintln!("Failed to create {}: {}", dir.display(), err),
+        }
+        folder.seen = folder.files().collect();
+        folder
+    }
+
+    // Files that have arrived or changed and settled since the last call, oldest first (at
+    // most every POLL_INTERVAL)
+    pub fn poll(&mut self) -> Vec<Dropped> {
+        if self.polled.elapsed() < POLL_INTERVAL {
+            return Vec::new();
+        }
+        self.polled = Instant::now();
+
+        let mut settled = Vec::new();
+        let mut settling = HashMap::new();
+        for (path, stamp) in self.files() {
+            if self.seen.get(&path) == Some(&stamp) {
+                continue;
+            }
+            if self.settling.get(&path) == Some(&stamp) {
+                self.seen.insert(path.clone(), stamp);
+                settled.push((stamp.0, path));
+            } else {
+                settling.insert(path, stamp);
+            }
+        }
+        self.settling = settling;
+        settled.sort();
+        (settled.into_iter())
+            .filter_map(|(_, path)| {
+                match path.extension().and_then(|extension| extension.to_str()) {
+                    Some("toml") => Some(Dropped::Scene(path)),
+                    Some("metal" | "wgsl") => Some(Dropped::Shader(path)),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    fn files(&self) -> impl Iterator<Item = (PathBuf, Stamp)> {
+        let entries = std::fs::read_dir(&self.dir).into_iter().flatten().flatten();
+        entries.filter_map(|entry| {
+            // Editors' hidden swap and backup files
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                return None;
+            }
+            let meta = entry.metadata().ok().filter(|meta| meta.is_file())?;
+            Some((entry.path(), (meta.modified().ok(), meta.len())))
+        })
+    }
+}
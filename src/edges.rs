@@ -0,0 +1,49 @@
+// Edge detection post pass: once the image is drawn, each pixel's first hit (its normal and
+// distance) is rendered into a buffer (gbuffer_main in the shaders), and a Sobel filter over
+// that buffer (edges_main) draws lines where either changes sharply: silhouettes, creases and
+// objects in front of others. `over` draws them over the image (with toon shading, a comic
+// look); `blueprint` draws them alone on a plain background.
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum EdgeMode {
+    #[default]
+    Off,
+    Over,
+    Blueprint,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Edges {
+    pub mode: EdgeMode,
+    pub color: Option<[f32; 3]>, // Lines; black over the image, white on a blueprint
+    pub paper: [f32; 3],         // Blueprint background
+    pub width: f32,              // Line width in pixels
+    pub depth: f32,              // Change in distance (relative to it) that makes a line
+    pub normal: f32,             // Change in the normal that makes a line
+}
+
+impl Default for Edges {
+    fn default() -> Self {
+        Self {
+            mode: EdgeMode::Off,
+            color: None,
+            paper: [0.08, 0.22, 0.45],
+            width: 2.0,
+            depth: 1.0,
+            normal: 1.0,
+        }
+    }
+}
+
+impl Edges {
+    pub fn line_color(&self) -> [f32; 3] {
+        self.color.unwrap_or(match self.mode {
+            EdgeMode::Blueprint => [1.0, 1.0, 1.0],
+            EdgeMode::Off | EdgeMode::Over => [0.0, 0.0, 0.0],
+        })
+    }
+}
@@ -0,0 +1,407 @@
+// Shader editor: a pane over the right half of the window holding the shader template being
+// drawn (the built-in one, or the custom one from --shader, the drop folder or a livecoding
+// push), MSL or WGSL. Cmd+E (Ctrl+E outside macOS) shows and hides it, and while it is shown
+// keys are text: the arrows, Page Up/Down, Home and End move the cursor, Escape hides it, and
+// Cmd+Enter applies the text through Backend::set_shader, which keeps the current shader if it
+// fails to build. The error, or that it was applied, shows at the bottom of the pane.
+//
+// Edits are kept while the pane is hidden; until the text is edited, showing it again picks up
+// whatever shader is running by then. Like the palette it is drawn with the overlay triangles
+// and the 3x5 pixel font, highlighting comments, keywords, types, numbers and the markers
+// where the noise functions, scene code and mesh functions go.
+use crate::gizmo::{OverlayVertex, View};
+use crate::inspector::{self, ADVANCE, DOT, PADDING};
+use std::cell::{Cell, RefCell};
+
+const MARGIN: f32 = 10.0; // Pixels from the window's edges
+const LINE_HEIGHT: f32 = 7.0 * DOT;
+const NUMBER_CHARS: usize = 5; // Line numbers, with a space after
+const ERROR_LINES: usize = 4; // Most lines of an error shown
+const TAB: usize = 4;
+
+const BACKGROUND: [f32; 4] = [0.05, 0.05, 0.08, 0.88];
+const PLAIN: [f32; 4] = [0.9, 0.9, 0.9, 1.0];
+const DIM: [f32; 4] = [0.45, 0.45, 0.5, 1.0];
+const COMMENT: [f32; 4] = [0.45, 0.6, 0.45, 1.0];
+const KEYWORD: [f32; 4] = [0.8, 0.55, 1.0, 1.0];
+const TYPE: [f32; 4] = [0.4, 0.8, 1.0, 1.0];
+const NUMBER: [f32; 4] = [1.0, 0.7, 0.35, 1.0];
+const MARKER: [f32; 4] = [1.0, 0.8, 0.2, 1.0];
+const ERROR: [f32; 4] = [1.0, 0.4, 0.35, 1.0];
+const APPLIED: [f32; 4] = [0.5, 0.9, 0.5, 1.0];
+
+// Lines highlighted as markers
+const MARKERS: &[&str] = &["// @scene@", "// @noise@", "// @meshes@"];
+
+const KEYWORDS: &[&str] = &[
+    "break",
+    "case",
+    "const",
+    "constant",
+    "constexpr",
+    "continue",
+    "default",
+    "device",
+    "discard",
+    "else",
+    "false",
+    "fn",
+    "for",
+    "fragment",
+    "if",
+    "inline",
+    "kernel",
+    "let",
+    "loop",
+    "override",
+    "return",
+    "sampler",
+    "static",
+    "struct",
+    "switch",
+    "thread",
+    "true",
+    "typedef",
+    "using",
+    "var",
+    "vertex",
+    "while",
+];
+const TYPES: &[&str] = &[
+    "bool",
+    "f32",
+    "float",
+    "half",
+    "i32",
+    "int",
+    "mat2x2",
+    "mat3x3",
+    "mat4x4",
+    "texture_2d",
+    "texture_3d",
+    "texture2d",
+    "texture3d",
+    "u32",
+    "uint",
+    "vec2",
+    "vec3",
+    "vec4",
+    "void",
+];
+
+// Ways the cursor moves
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Motion {
+    Up,
+    Down,
+    Left,
+    Right,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+}
+
+#[derive(Default)]
+pub struct Editor {
+    pub shown: bool,
+    lines: Vec<String>,
+    cursor: (usize, usize),             // Line and character
+    edited: bool,                       // Since the text was last loaded
+    result: Option<Result<(), String>>, // Of the last apply, until the next edit
+    top: Cell<usize>, // First line shown, moved at draw time to keep the cursor in view
+    left: Cell<usize>, // First character shown of each line, likewise
+    rows: Cell<usize>, // Lines that fit, as last drawn
+    // Whether a block comment is open at the start of each line, as far down as drawn since
+    // the last edit above it
+    open: RefCell<Vec<bool>>,
+}
+
+impl Editor {
+    // Show the pane, with `template` in it unless the text has been edited
+    pub fn show(&mut self, template: &str) {
+        if !self.edited || self.lines.is_empty() {
+            self.lines = template
+                .lines()
+                .map(|line| line.replace('\t', "    "))
+                .collect();
+            if self.lines.is_empty() {
+                self.lines.push(String::new());
+            }
+            self.cursor = (0, 0);
+            self.open.get_mut().clear();
+            self.top.set(0);
+            self.left.set(0);
+            self.result = None;
+        }
+        self.shown = true;
+    }
+
+    pub fn text(&self) -> String {
+        self.lines.join("\n") + "\n"
+    }
+
+    // How applying the text went: the error shows until the next edit
+    pub fn applied(&mut self, result: Result<(), String>) {
+        if result.is_ok() {
+            self.edited = false;
+        }
+        self.result = Some(result);
+    }
+
+    // Typed text: characters go in at the cursor, Enter keeps the line's indentation, Tab
+    // indents to the next stop and Escape hides the pane
+    pub fn type_char(&mut self, c: char) {
+        let (line, column) = self.cursor;
+        match c {
+            '\u{1b}' => {
+                self.shown = false;
+                return;
+            }
+            '\r' | '\n' => {
+                let at = byte(&self.lines[line], column);
+                let rest = self.lines[line].split_off(at);
+                let indent: String = (self.lines[line].chars())
+                    .take_while(|&c| c == ' ')
+                    .collect();
+                self.cursor = (line + 1, indent.len());
+                self.lines.insert(line + 1, indent + rest.as_str());
+            }
+            '\t' => {
+                let spaces = TAB - column % TAB;
+                let at = byte(&self.lines[line], column);
+                self.lines[line].insert_str(at, &" ".repeat(spaces));
+                self.cursor.1 += spaces;
+            }
+            '\u{8}' | '\u{7f}' if column > 0 => {
+                let at = byte(&self.lines[line], column - 1);
+                self.lines[line].remove(at);
+                self.cursor.1 -= 1;
+            }
+            '\u{8}' | '\u{7f}' if line > 0 => {
+                let joined = self.lines.remove(line);
+                let previous = &mut self.lines[line - 1];
+                self.cursor = (line - 1, previous.chars().count());
+                previous.push_str(&joined);
+            }
+            c if !c.is_control() => {
+                let at = byte(&self.lines[line], column);
+                self.lines[line].insert(at, c);
+                self.cursor.1 += 1;
+            }
+            _ => return,
+        }
+        // Lines after the one edited (or joined onto) may start in a comment or out of one
+        self.open.get_mut().truncate(self.cursor.0.min(line) + 1);
+        self.edited = true;
+        self.result = None;
+    }
+
+    pub fn move_cursor(&mut self, motion: Motion) {
+        let (mut line, mut column) = self.cursor;
+        let last = self.lines.len() - 1;
+        let page = self.rows.get().max(1);
+        let length = |line: usize| self.lines[line].chars().count();
+        match motion {
+            Motion::Up => line = line.saturating_sub(1),
+            Motion::Down => line = (line + 1).min(last),
+            Motion::PageUp => line = line.saturating_sub(page),
+            Motion::PageDown => line = (line + page).min(last),
+            Motion::Left if column > 0 => column -= 1,
+            Motion::Left if line > 0 => (line, column) = (line - 1, length(line - 1)),
+            Motion::Right if column < length(line) => column += 1,
+            Motion::Right if line < last => (line, column) = (line + 1, 0),
+            Motion::Home => column = 0,
+            Motion::End => column = length(line),
+            Motion::Left | Motion::Right => {}
+        }
+        self.cursor = (line, column.min(length(line)));
+    }
+
+    pub fn vertices(&self, view: &View) -> Vec<OverlayVertex> {
+        let mut out = Vec::new();
+        if !self.shown {
+            return out;
+        }
+        let width = (view.width as f32 / 2.0 - MARGIN).max(ADVANCE * 20.0);
+        let height = view.height as f32 - MARGIN * 2.0;
+        let left = view.width as f32 - MARGIN - width;
+        inspector::rectangle(&mut out, view, [left, MARGIN], [width, height], BACKGROUND);
+        let row_top = |row: usize| MARGIN + PADDING + row as f32 * LINE_HEIGHT;
+        let text_left = left + PADDING + NUMBER_CHARS as f32 * ADVANCE;
+        let columns = (((width - PADDING * 2.0) / ADVANCE) as usize).saturating_sub(NUMBER_CHARS);
+
+        // A title row, the text, then what applying it gave
+        let message: Vec<(&str, [f32; 4])> = match &self.result {
+            Some(Ok(())) => vec![("Applied", APPLIED)],
+            Some(Err(err)) => (err.lines().filter(|line| !line.trim().is_empty()))
+                .take(ERROR_LINES)
+                .map(|line| (line, ERROR))
+                .collect(),
+            None => Vec::new(),
+        };
+        let fit = ((height - PADDING * 2.0) / LINE_HEIGHT) as usize;
+        let rows = fit.saturating_sub(1 + message.len()).max(1);
+        self.rows.set(rows);
+        let command = match cfg!(any(target_os = "macos", target_os = "ios")) {
+            true => "Cmd",
+            false => "Ctrl",
+        };
+        let title = format!(
+            "Shader{}   {}+Enter applies, Esc hides",
+            if self.edited { " (edited)" } else { "" },
+            command
+        );
+        inspector::text(
+            &mut out,
+            view,
+            [left + PADDING, row_top(0)],
+            &title,
+            usize::MAX,
+            DIM,
+        );
+
+        // Scroll to keep the cursor in view
+        let (line, column) = self.cursor;
+        let top = self
+            .top
+            .get()
+            .min(line)
+            .max((line + 1).saturating_sub(rows));
+        let first = self
+            .left
+            .get()
+            .min(column)
+            .max((column + 1).saturating_sub(columns));
+        self.top.set(top);
+        self.left.set(first);
+
+        let mut open = self.open.borrow_mut();
+        if open.is_empty() {
+            open.push(false);
+        }
+        while open.len() < (top + rows).min(self.lines.len()) {
+            let last = open.len() - 1;
+            let next = highlight(&self.lines[last], open[last]).1;
+            open.push(next);
+        }
+        for (row, (number, text)) in (1..).zip(self.lines.iter().enumerate().skip(top).take(rows)) {
+            inspector::text(
+                &mut out,
+                view,
+                [left + PADDING, row_top(row)],
+                &format!("{:>4}", number + 1),
+                NUMBER_CHARS,
+                DIM,
+            );
+            let (spans, _) = highlight(text, open[number]);
+            for (start, end, color) in spans {
+                let (start, end) = (start.max(first), end.min(first + columns));
+                if start >= end {
+                    continue;
+                }
+                let span: String = text.chars().skip(start).take(end - start).collect();
+                let at = [text_left + (start - first) as f32 * ADVANCE, row_top(row)];
+                inspector::text(&mut out, view, at, &span, usize::MAX, color);
+            }
+            if number == line {
+                let at = [
+                    text_left + (column - first) as f32 * ADVANCE - DOT,
+                    row_top(row) - DOT,
+                ];
+                inspector::rectangle(&mut out, view, at, [DOT / 2.0, 7.0 * DOT], MARKER);
+            }
+        }
+        let chars = (width - PADDING * 2.0) / ADVANCE;
+        for (row, (text, color)) in (rows + 1..).zip(message) {
+            inspector::text(
+                &mut out,
+                view,
+                [left + PADDING, row_top(row)],
+                text,
+                chars as usize,
+                color,
+            );
+        }
+        out
+    }
+}
+
+// Byte offset of character `column` in `text`
+fn byte(text: &str, column: usize) -> usize {
+    text.char_indices()
+        .nth(column)
+        .map_or(text.len(), |(at, _)| at)
+}
+
+// Whether `chars` has `prefix` at `at`
+fn starts_with(chars: &[char], at: usize, prefix: &str) -> bool {
+    (prefix.chars().enumerate()).all(|(i, c)| chars.get(at + i) == Some(&c))
+}
+
+// Whether `chars` spell one of `words`
+fn one_of(words: &[&str], chars: &[char]) -> bool {
+    (words.iter()).any(|word| word.chars().eq(chars.iter().copied()))
+}
+
+// Colored runs of `text` as character ranges, and whether a block comment is still open at
+// its end (`open` being whether one was at its start)
+fn highlight(text: &str, mut open: bool) -> (Vec<(usize, usize, [f32; 4])>, bool) {
+    let chars: Vec<char> = text.chars().collect();
+    let mut spans = Vec::new();
+    let mut at = 0;
+    while at < chars.len() {
+        let start = at;
+        let color = if open || starts_with(&chars, at, "/*") {
+            if !open {
+                at += 2;
+            }
+            open = true;
+            while at < chars.len() {
+                if starts_with(&chars, at, "*/") {
+                    at += 2;
+                    open = false;
+                    break;
+                }
+                at += 1;
+            }
+            COMMENT
+        } else if starts_with(&chars, at, "//") {
+            let end = (chars.iter())
+                .rposition(|c| !c.is_whitespace())
+                .map_or(at, |i| i + 1);
+            at = chars.len();
+            match one_of(MARKERS, &chars[start..end]) {
+                true => MARKER,
+                false => COMMENT,
+            }
+        } else if chars[at].is_ascii_digit() {
+            while at < chars.len() && (chars[at].is_ascii_alphanumeric() || chars[at] == '.') {
+                at += 1;
+            }
+            NUMBER
+        } else if chars[at].is_alphabetic() || chars[at] == '_' {
+            while at < chars.len() && (chars[at].is_alphanumeric() || chars[at] == '_') {
+                at += 1;
+            }
+            let word = &chars[start..at];
+            // float3, vec3<f32> and the like count as their base type
+            let base = word.len() - word.iter().rev().take_while(|c| c.is_ascii_digit()).count();
+            if one_of(KEYWORDS, word) {
+                KEYWORD
+            } else if one_of(TYPES, word) || one_of(TYPES, &word[..base]) {
+                TYPE
+            } else {
+                PLAIN
+            }
+        } else {
+            at += 1;
+            PLAIN
+        };
+        match spans.last_mut() {
+            Some((_, end, last)) if *last == color => *end = at,
+            _ => spans.push((start, at, color)),
+        }
+    }
+    (spans, open)
+}
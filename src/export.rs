@@ -0,0 +1,25 @@
+// Export the current scene as standalone GLSL (Shadertoy) and WGSL shaders
+use crate::codegen::{scene_source, Dialect, SCENE_MARKER};
+use crate::scene::Scene;
+use std::path::{Path, PathBuf};
+
+const SHADERTOY_TEMPLATE: &str = include_str!("export/shadertoy.glsl");
+const WGSL_TEMPLATE: &str = include_str!("export/webgpu.wgsl");
+
+pub fn to_glsl(scene: &Scene) -> String {
+    SHADERTOY_TEMPLATE.replace(SCENE_MARKER, &scene_source(scene, Dialect::Glsl))
+}
+
+pub fn to_wgsl(scene: &Scene) -> String {
+    WGSL_TEMPLATE.replace(SCENE_MARKER, &scene_source(scene, Dialect::Wgsl))
+}
+
+// Write `<scene name>.glsl` and `<scene name>.wgsl` into `dir`, returning the paths
+pub fn write_all(scene: &Scene, dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(dir)?;
+    let glsl_path = dir.join(format!("{}.glsl", scene.name));
+    let wgsl_path = dir.join(format!("{}.wgsl", scene.name));
+    std::fs::write(&glsl_path, to_glsl(scene))?;
+    std::fs::write(&wgsl_path, to_wgsl(scene))?;
+    Ok(vec![glsl_path, wgsl_path])
+}
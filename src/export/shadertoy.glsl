@@ -0,0 +1,100 @@
+// Exported from metal-raymarcher (Shadertoy layout: paste into the Image tab)
+
+float sdSphere(vec3 p, float radius) {
+    return length(p) - radius;
+}
+
+float sdBox(vec3 p, vec3 size) {
+    vec3 q = abs(p) - size;
+    return length(max(q, 0.0)) + min(max(q.x, max(q.y, q.z)), 0.0);
+}
+
+float sdPlane(vec3 p, vec3 n, float h) {
+    return dot(p, n) + h;
+}
+
+float sdCapsule(vec3 p, vec3 a, vec3 b, float r) {
+    vec3 pa = p - a, ba = b - a;
+    float h = clamp(dot(pa, ba) / max(dot(ba, ba), 1e-8), 0.0, 1.0);
+    return length(pa - ba * h) - r;
+}
+
+float smin(float a, float b, float k) {
+    float h = clamp(0.5 + 0.5*(b-a)/k, 0.0, 1.0);
+    return mix(b, a, h) - k*h*(1.0-h);
+}
+
+vec2 opUnion(vec2 a, vec2 b) {
+    return a.x < b.x ? a : b;
+}
+
+vec2 opSmoothUnion(vec2 a, vec2 b, float k) {
+    return vec2(smin(a.x, b.x, k), a.x < b.x ? a.y : b.y);
+}
+
+vec2 opSubtract(vec2 a, vec2 b) {
+    return vec2(max(a.x, -b.x), a.y);
+}
+
+vec2 opSmoothSubtract(vec2 a, vec2 b, float k) {
+    float h = clamp(0.5 - 0.5*(a.x+b.x)/k, 0.0, 1.0);
+    return vec2(mix(a.x, -b.x, h) + k*h*(1.0-h), a.y);
+}
+
+vec2 opIntersect(vec2 a, vec2 b) {
+    return a.x > b.x ? a : b;
+}
+
+// Diffuse light a point light gives a surface at p (normal n), falling off with distance
+vec3 pointLight(vec3 p, vec3 n, vec3 position, vec3 color, float intensity) {
+    vec3 l = position - p;
+    float d2 = max(dot(l, l), 1e-6);
+    return color * intensity * max(dot(n, l * inversesqrt(d2)), 0.0) / (1.0 + d2);
+}
+
+// @scene@
+
+vec3 calcNormal(vec3 p) {
+    vec2 e = vec2(0.001, 0.0);
+    return normalize(vec3(
+        sceneMap(p + e.xyy).x - sceneMap(p - e.xyy).x,
+        sceneMap(p + e.yxy).x - sceneMap(p - e.yxy).x,
+        sceneMap(p + e.yyx).x - sceneMap(p - e.yyx).x
+    ));
+}
+
+vec3 rayMarch(vec3 ro, vec3 rd) {
+    float t = 0.0;
+    for (int i = 0; i < 100; i++) {
+        vec3 p = ro + rd * t;
+        vec2 hit = sceneMap(p);
+        if (hit.x < 0.001) {
+            vec3 normal = calcNormal(p);
+            vec3 lightDir = normalize(vec3(0.7, 0.7, -0.5));
+            float diffuse = max(0.0, dot(normal, lightDir));
+            vec3 ambient = vec3(0.15, 0.15, 0.2);
+            vec3 color = sceneColorAt(p, hit.y, sceneColor(hit.y));
+            return ambient + color * (diffuse + sceneLight(p, normal));
+        }
+        if (t > 50.0) {
+            break;
+        }
+        t += hit.x * 0.8;
+    }
+    return mix(vec3(0.2, 0.3, 0.5), vec3(0.7, 0.8, 0.9), rd.y * 0.5 + 0.5);
+}
+
+void mainImage(out vec4 fragColor, in vec2 fragCoord) {
+    vec2 uv = (fragCoord / iResolution.xy - 0.5) * 2.0;
+    uv.x *= iResolution.x / iResolution.y;
+
+    // Orbiting camera (drag horizontally to rotate)
+    float angle = iMouse.z > 0.0 ? (iMouse.x / iResolution.x * 2.0 - 1.0) * 3.14159265 : iTime * 0.6;
+    vec3 ro = vec3(cos(angle) * 8.0, 2.0, sin(angle) * 8.0);
+    vec3 forward = normalize(-ro);
+    vec3 right = normalize(cross(vec3(0.0, 1.0, 0.0), forward));
+    vec3 up = cross(forward, right);
+    vec3 rd = normalize(forward + uv.x * right + uv.y * up);
+
+    fragColor = vec4(rayMarch(ro, rd), 1.0);
+}
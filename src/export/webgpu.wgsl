@@ -0,0 +1,116 @@
+// Exported from metal-raymarcher (WGSL fragment shader for a fullscreen triangle/quad).
+// Bind a uniform buffer with the Shadertoy-style inputs at group 0, binding 0.
+
+struct Inputs {
+    resolution: vec2<f32>, // iResolution.xy
+    time: f32,             // iTime
+    _pad: f32,
+    mouse: vec4<f32>,      // iMouse
+};
+
+@group(0) @binding(0) var<uniform> inputs: Inputs;
+
+fn sdSphere(p: vec3<f32>, radius: f32) -> f32 {
+    return length(p) - radius;
+}
+
+fn sdBox(p: vec3<f32>, size: vec3<f32>) -> f32 {
+    let q = abs(p) - size;
+    return length(max(q, vec3<f32>(0.0))) + min(max(q.x, max(q.y, q.z)), 0.0);
+}
+
+fn sdPlane(p: vec3<f32>, n: vec3<f32>, h: f32) -> f32 {
+    return dot(p, n) + h;
+}
+
+fn sdCapsule(p: vec3<f32>, a: vec3<f32>, b: vec3<f32>, r: f32) -> f32 {
+    let pa = p - a;
+    let ba = b - a;
+    let h = clamp(dot(pa, ba) / max(dot(ba, ba), 1e-8), 0.0, 1.0);
+    return length(pa - ba * h) - r;
+}
+
+fn smin(a: f32, b: f32, k: f32) -> f32 {
+    let h = clamp(0.5 + 0.5 * (b - a) / k, 0.0, 1.0);
+    return mix(b, a, h) - k * h * (1.0 - h);
+}
+
+fn opUnion(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    return select(b, a, a.x < b.x);
+}
+
+fn opSmoothUnion(a: vec2<f32>, b: vec2<f32>, k: f32) -> vec2<f32> {
+    return vec2<f32>(smin(a.x, b.x, k), select(b.y, a.y, a.x < b.x));
+}
+
+fn opSubtract(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(max(a.x, -b.x), a.y);
+}
+
+fn opSmoothSubtract(a: vec2<f32>, b: vec2<f32>, k: f32) -> vec2<f32> {
+    let h = clamp(0.5 - 0.5 * (a.x + b.x) / k, 0.0, 1.0);
+    return vec2<f32>(mix(a.x, -b.x, h) + k * h * (1.0 - h), a.y);
+}
+
+fn opIntersect(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    return select(b, a, a.x > b.x);
+}
+
+// Diffuse light a point light gives a surface at p (normal n), falling off with distance
+fn pointLight(p: vec3<f32>, n: vec3<f32>, position: vec3<f32>, color: vec3<f32>, intensity: f32) -> vec3<f32> {
+    let l = position - p;
+    let d2 = max(dot(l, l), 1e-6);
+    return color * intensity * max(dot(n, l * inverseSqrt(d2)), 0.0) / (1.0 + d2);
+}
+
+// @scene@
+
+fn calcNormal(p: vec3<f32>) -> vec3<f32> {
+    let e = vec2<f32>(0.001, 0.0);
+    return normalize(vec3<f32>(
+        sceneMap(p + e.xyy).x - sceneMap(p - e.xyy).x,
+        sceneMap(p + e.yxy).x - sceneMap(p - e.yxy).x,
+        sceneMap(p + e.yyx).x - sceneMap(p - e.yyx).x
+    ));
+}
+
+fn rayMarch(ro: vec3<f32>, rd: vec3<f32>) -> vec3<f32> {
+    var t = 0.0;
+    for (var i = 0; i < 100; i++) {
+        let p = ro + rd * t;
+        let hit = sceneMap(p);
+        if (hit.x < 0.001) {
+            let normal = calcNormal(p);
+            let lightDir = normalize(vec3<f32>(0.7, 0.7, -0.5));
+            let diffuse = max(0.0, dot(normal, lightDir));
+            let ambient = vec3<f32>(0.15, 0.15, 0.2);
+            let color = sceneColorAt(p, hit.y, sceneColor(hit.y));
+            return ambient + color * (diffuse + sceneLight(p, normal));
+        }
+        if (t > 50.0) {
+            break;
+        }
+        t += hit.x * 0.8;
+    }
+    return mix(vec3<f32>(0.2, 0.3, 0.5), vec3<f32>(0.7, 0.8, 0.9), rd.y * 0.5 + 0.5);
+}
+
+@fragment
+fn fs_main(@builtin(position) frag_coord: vec4<f32>) -> @location(0) vec4<f32> {
+    // Flip y so the image is upright like Shadertoy's bottom-left origin
+    let coord = vec2<f32>(frag_coord.x, inputs.resolution.y - frag_coord.y);
+    var uv = (coord / inputs.resolution - 0.5) * 2.0;
+    uv.x *= inputs.resolution.x / inputs.resolution.y;
+
+    var angle = inputs.time * 0.6;
+    if (inputs.mouse.z > 0.0) {
+        angle = (inputs.mouse.x / inputs.resolution.x * 2.0 - 1.0) * 3.14159265;
+    }
+    let ro = vec3<f32>(cos(angle) * 8.0, 2.0, sin(angle) * 8.0);
+    let forward = normalize(-ro);
+    let right = normalize(cross(vec3<f32>(0.0, 1.0, 0.0), forward));
+    let up = cross(forward, right);
+    let rd = normalize(forward + uv.x * right + uv.y * up);
+
+    return vec4<f32>(rayMarch(ro, rd), 1.0);
+}
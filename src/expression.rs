@@ -0,0 +1,409 @@
+// Parameters bound to expressions, evaluated on the CPU every frame. A scene binds them in
+// its [expressions] table, by parameter address (see scene.rs):
+//
+//   [expressions]
+//   "ball.radius" = "1.0 + 0.5 * sin(time * 2.0)"
+//   "ball.color" = ["mouse.x", 0.8, "1.0 - mouse.y"]   # one entry per component
+//
+// Expressions use + - * / %, parentheses, numbers, the variables `time`, `mouse.x`,
+// `mouse.y`, `pi`, `audio.bass`/`mid`/`high`/`level`/`beat` (see audio.rs) and
+// `clock.beats`/`beat`/`bar`/`bpm` (see clock.rs), and the functions in FUNCTIONS below.
+// Like animated parameters, bound ones are read by the shader from the uniforms. Expressions
+// are at most MAX_LENGTH characters long and nest parentheses, calls and minus signs at most
+// MAX_DEPTH deep, so parsing and evaluating them can't run out of stack.
+use crate::audio::AudioLevels;
+use crate::clock::ClockPhase;
+use crate::scene::Scene;
+use cgmath::Vector2;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+pub const MAX_LENGTH: usize = 4096;
+pub const MAX_DEPTH: usize = 64;
+
+// Values an expression can refer to
+#[derive(Clone, Copy, Debug)]
+pub struct Variables {
+    pub time: f32,
+    pub mouse: Vector2<f32>, // Normalized cursor position
+    pub audio: AudioLevels,
+    pub clock: ClockPhase,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Variable {
+    Time,
+    MouseX,
+    MouseY,
+    Pi,
+    Audio(fn(&AudioLevels) -> f32),
+    Clock(fn(&ClockPhase) -> f32),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Number(f32),
+    Variable(Variable),
+    Negate(Box<Node>),
+    Binary(Op, Box<Node>, Box<Node>),
+    Call(usize, Vec<Node>), // Index into FUNCTIONS
+}
+
+type Function = fn(&[f32]) -> f32;
+
+// Name, argument count and implementation (GLSL semantics)
+const FUNCTIONS: &[(&str, usize, Function)] = &[
+    ("sin", 1, |a| a[0].sin()),
+    ("cos", 1, |a| a[0].cos()),
+    ("tan", 1, |a| a[0].tan()),
+    ("asin", 1, |a| a[0].asin()),
+    ("acos", 1, |a| a[0].acos()),
+    ("atan", 1, |a| a[0].atan()),
+    ("abs", 1, |a| a[0].abs()),
+    ("sign", 1, |a| a[0].signum()),
+    ("floor", 1, |a| a[0].floor()),
+    ("ceil", 1, |a| a[0].ceil()),
+    ("fract", 1, |a| a[0] - a[0].floor()),
+    ("sqrt", 1, |a| a[0].sqrt()),
+    ("exp", 1, |a| a[0].exp()),
+    ("log", 1, |a| a[0].ln()),
+    ("pow", 2, |a| a[0].powf(a[1])),
+    ("min", 2, |a| a[0].min(a[1])),
+    ("max", 2, |a| a[0].max(a[1])),
+    ("mod", 2, |a| a[0] - a[1] * (a[0] / a[1]).floor()),
+    ("step", 2, |a| if a[1] < a[0] { 0.0 } else { 1.0 }),
+    ("clamp", 3, |a| a[0].max(a[1]).min(a[2])),
+    ("mix", 3, |a| a[0] + (a[1] - a[0]) * a[2]),
+    ("smoothstep", 3, |a| {
+        let t = ((a[2] - a[0]) / (a[1] - a[0])).clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    }),
+];
+
+// A parsed expression; compares and serializes as its source text
+#[derive(Clone, Debug)]
+pub struct Expression {
+    source: String,
+    root: Node,
+}
+
+impl PartialEq for Expression {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Expression {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        if source.len() > MAX_LENGTH {
+            return Err(format!("expression longer than {} characters", MAX_LENGTH));
+        }
+        let mut parser = Parser {
+            source,
+            chars: source.char_indices().peekable(),
+            depth: 0,
+        };
+        let root = parser.expression()?;
+        parser.skip_whitespace();
+        if let Some((at, c)) = parser.chars.next() {
+            return Err(parser.error(at, &format!("unexpected '{}'", c)));
+        }
+        Ok(Self {
+            source: source.to_string(),
+            root,
+        })
+    }
+
+    pub fn constant(value: f32) -> Self {
+        Self {
+            source: format!("{}", value),
+            root: Node::Number(value),
+        }
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn eval(&self, variables: &Variables) -> f32 {
+        eval(&self.root, variables)
+    }
+}
+
+fn eval(node: &Node, variables: &Variables) -> f32 {
+    match node {
+        Node::Number(value) => *value,
+        Node::Variable(Variable::Time) => variables.time,
+        Node::Variable(Variable::MouseX) => variables.mouse.x,
+        Node::Variable(Variable::MouseY) => variables.mouse.y,
+        Node::Variable(Variable::Pi) => std::f32::consts::PI,
+        Node::Variable(Variable::Audio(band)) => band(&variables.audio),
+        Node::Variable(Variable::Clock(phase)) => phase(&variables.clock),
+        Node::Negate(node) => -eval(node, variables),
+        Node::Binary(op, a, b) => {
+            let (a, b) = (eval(a, variables), eval(b, variables));
+            match op {
+                Op::Add => a + b,
+                Op::Sub => a - b,
+                Op::Mul => a * b,
+                Op::Div => a / b,
+                Op::Rem => a % b,
+            }
+        }
+        Node::Call(function, args) => {
+            let args: Vec<f32> = args.iter().map(|arg| eval(arg, variables)).collect();
+            (FUNCTIONS[*function].2)(&args)
+        }
+    }
+}
+
+// Recursive descent over
+//   expression := term (('+' | '-') term)*
+//   term       := unary (('*' | '/' | '%') unary)*
+//   unary      := '-' unary | atom
+//   atom       := number | name | name '(' expression (',' expression)* ')' | '(' expression ')'
+struct Parser<'a> {
+    source: &'a str,
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+    depth: usize, // Parentheses, calls and minus signs around the current position
+}
+
+impl Parser<'_> {
+    fn error(&self, at: usize, message: &str) -> String {
+        format!("{} at column {} of \"{}\"", message, at + 1, self.source)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+    }
+
+    // Next non-space character, without consuming it
+    fn peek(&mut self) -> Option<(usize, char)> {
+        self.skip_whitespace();
+        self.chars.peek().copied()
+    }
+
+    fn expect(&mut self, wanted: char) -> Result<(), String> {
+        match self.peek() {
+            Some((_, c)) if c == wanted => {
+                self.chars.next();
+                Ok(())
+            }
+            Some((at, c)) => Err(self.error(at, &format!("expected '{}', found '{}'", wanted, c))),
+            None => Err(self.error(self.source.len(), &format!("expected '{}'", wanted))),
+        }
+    }
+
+    // `parse` one level further in, at `at`
+    fn nested<T>(
+        &mut self,
+        at: usize,
+        parse: impl FnOnce(&mut Self) -> Result<T, String>,
+    ) -> Result<T, String> {
+        if self.depth >= MAX_DEPTH {
+            return Err(self.error(at, "nested too deep"));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn expression(&mut self) -> Result<Node, String> {
+        let mut node = self.term()?;
+        while let Some((_, c @ ('+' | '-'))) = self.peek() {
+            self.chars.next();
+            let op = if c == '+' { Op::Add } else { Op::Sub };
+            node = Node::Binary(op, Box::new(node), Box::new(self.term()?));
+        }
+        Ok(node)
+    }
+
+    fn term(&mut self) -> Result<Node, String> {
+        let mut node = self.unary()?;
+        while let Some((_, c @ ('*' | '/' | '%'))) = self.peek() {
+            self.chars.next();
+            let op = match c {
+                '*' => Op::Mul,
+                '/' => Op::Div,
+                _ => Op::Rem,
+            };
+            node = Node::Binary(op, Box::new(node), Box::new(self.unary()?));
+        }
+        Ok(node)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        if let Some((at, '-')) = self.peek() {
+            self.chars.next();
+            return Ok(Node::Negate(Box::new(self.nested(at, Self::unary)?)));
+        }
+        self.atom()
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        let Some((at, c)) = self.peek() else {
+            return Err(self.error(self.source.len(), "unexpected end"));
+        };
+        if c == '(' {
+            self.chars.next();
+            let node = self.nested(at, Self::expression)?;
+            self.expect(')')?;
+            return Ok(node);
+        }
+        if c.is_ascii_digit() || c == '.' {
+            let text = self.take_while(|c| c.is_ascii_digit() || c == '.');
+            return text
+                .parse()
+                .map(Node::Number)
+                .map_err(|_| self.error(at, &format!("invalid number \"{}\"", text)));
+        }
+        if !(c.is_ascii_alphabetic() || c == '_') {
+            return Err(self.error(at, &format!("unexpected '{}'", c)));
+        }
+
+        let name = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+        if let Some((_, '(')) = self.peek() {
+            self.chars.next();
+            let function = FUNCTIONS
+                .iter()
+                .position(|(function, _, _)| *function == name)
+                .ok_or_else(|| self.error(at, &format!("unknown function \"{}\"", name)))?;
+            let mut args = vec![self.nested(at, Self::expression)?];
+            while let Some((_, ',')) = self.peek() {
+                self.chars.next();
+                args.push(self.nested(at, Self::expression)?);
+            }
+            self.expect(')')?;
+            let arity = FUNCTIONS[function].1;
+            if args.len() != arity {
+                return Err(self.error(
+                    at,
+                    &format!("{} takes {} arguments, got {}", name, arity, args.len()),
+                ));
+            }
+            return Ok(Node::Call(function, args));
+        }
+        let variable = match name.as_str() {
+            "time" => Variable::Time,
+            "mouse.x" => Variable::MouseX,
+            "mouse.y" => Variable::MouseY,
+            "pi" => Variable::Pi,
+            "audio.bass" => Variable::Audio(|audio| audio.bass),
+            "audio.mid" => Variable::Audio(|audio| audio.mid),
+            "audio.high" => Variable::Audio(|audio| audio.high),
+            "audio.level" => Variable::Audio(|audio| audio.level),
+            "audio.beat" => Variable::Audio(|audio| audio.beat),
+            "clock.beats" => Variable::Clock(|clock| clock.beats),
+            "clock.beat" => Variable::Clock(|clock| clock.beat),
+            "clock.bar" => Variable::Clock(|clock| clock.bar),
+            "clock.bpm" => Variable::Clock(|clock| clock.bpm),
+            _ => return Err(self.error(at, &format!("unknown variable \"{}\"", name))),
+        };
+        Ok(Node::Variable(variable))
+    }
+
+    fn take_while(&mut self, accept: impl Fn(char) -> bool) -> String {
+        let mut text = String::new();
+        while let Some((_, c)) = self.chars.next_if(|&(_, c)| accept(c)) {
+            text.push(c);
+        }
+        text
+    }
+}
+
+impl Serialize for Expression {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+// Expressions for the components of one parameter: a single one for scalars, a list for
+// vectors. Plain numbers are accepted as constant components.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Binding {
+    Scalar(Expression),
+    Vector(Vec<Expression>),
+}
+
+impl Binding {
+    pub fn components(&self) -> &[Expression] {
+        match self {
+            Binding::Scalar(expression) => std::slice::from_ref(expression),
+            Binding::Vector(expressions) => expressions,
+        }
+    }
+
+    // Check the bound parameter exists and has one component per expression
+    pub fn validate(&self, target: &str, scene: &Scene) -> Result<(), String> {
+        let len = scene
+            .parameter(target)
+            .ok_or_else(|| format!("expression target \"{}\" not found", target))?
+            .len();
+        if self.components().len() != len {
+            return Err(format!(
+                "\"{}\" has {} components, {} expressions are bound to it",
+                target,
+                len,
+                self.components().len()
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl<'de> Deserialize<'de> for Binding {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Component {
+            Number(f32),
+            Text(String),
+        }
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Scalar(Component),
+            Vector(Vec<Component>),
+        }
+        let parse = |component| match component {
+            // Saved as its text, which has to parse again
+            Component::Number(value) if !value.is_finite() => {
+                Err(D::Error::custom(format!("{} isn't a finite number", value)))
+            }
+            Component::Number(value) => Ok(Expression::constant(value)),
+            Component::Text(source) => Expression::parse(&source).map_err(D::Error::custom),
+        };
+        match Raw::deserialize(deserializer)? {
+            Raw::Scalar(component) => parse(component).map(Binding::Scalar),
+            Raw::Vector(components) => components
+                .into_iter()
+                .map(parse)
+                .collect::<Result<_, _>>()
+                .map(Binding::Vector),
+        }
+    }
+}
+
+// Evaluate every bound expression and write the values into the scene's parameters
+pub fn apply(scene: &mut Scene, variables: &Variables) {
+    let expressions = std::mem::take(&mut scene.expressions);
+    for (target, binding) in &expressions {
+        if let Some(parameter) = scene.parameter_mut(target) {
+            for (value, expression) in parameter.iter_mut().zip(binding.components()) {
+                *value = expression.eval(variables);
+            }
+        }
+    }
+    scene.expressions = expressions;
+}
@@ -0,0 +1,223 @@
+// Shared plumbing for external frame sources (camera, video files, screen capture):
+// CoreVideo pixel buffers handed from capture queues to the render thread and
+// uploaded into BGRA textures.
+use cocoa::base::id;
+use cocoa::foundation::NSString;
+use metal::*;
+use objc::declare::ClassDecl;
+use objc::runtime::{Class, Object, Sel};
+use objc::{class, msg_send, sel, sel_impl};
+use std::ffi::{c_void, CStr};
+use std::os::raw::c_char;
+use std::sync::{Arc, Mutex};
+
+pub type CVPixelBufferRef = *mut c_void;
+pub type CMSampleBufferRef = *mut c_void;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CMTime {
+    pub value: i64,
+    pub timescale: i32,
+    pub flags: u32,
+    pub epoch: i64,
+}
+
+impl CMTime {
+    #[cfg(feature = "screen-capture")]
+    pub fn new(value: i64, timescale: i32) -> Self {
+        Self {
+            value,
+            timescale,
+            flags: 1, // kCMTimeFlags_Valid
+            epoch: 0,
+        }
+    }
+}
+
+pub const K_CV_PIXEL_FORMAT_TYPE_32_BGRA: u32 = 0x4247_5241; // 'BGRA'
+const K_CV_PIXEL_BUFFER_LOCK_READ_ONLY: u64 = 1;
+
+#[link(name = "CoreMedia", kind = "framework")]
+extern "C" {
+    fn CMSampleBufferGetImageBuffer(sbuf: CMSampleBufferRef) -> CVPixelBufferRef;
+}
+
+#[link(name = "CoreVideo", kind = "framework")]
+extern "C" {
+    #[cfg(feature = "video")]
+    pub static kCVPixelBufferPixelFormatTypeKey: id; // CFStringRef, toll-free bridged
+    fn CVPixelBufferLockBaseAddress(pixel_buffer: CVPixelBufferRef, flags: u64) -> i32;
+    fn CVPixelBufferUnlockBaseAddress(pixel_buffer: CVPixelBufferRef, flags: u64) -> i32;
+    fn CVPixelBufferGetBaseAddress(pixel_buffer: CVPixelBufferRef) -> *mut c_void;
+    fn CVPixelBufferGetBytesPerRow(pixel_buffer: CVPixelBufferRef) -> usize;
+    fn CVPixelBufferGetWidth(pixel_buffer: CVPixelBufferRef) -> usize;
+    fn CVPixelBufferGetHeight(pixel_buffer: CVPixelBufferRef) -> usize;
+    fn CVPixelBufferRetain(pixel_buffer: CVPixelBufferRef) -> CVPixelBufferRef;
+    fn CVPixelBufferRelease(pixel_buffer: CVPixelBufferRef);
+}
+
+// Serial queues that capture callbacks are delivered on
+extern "C" {
+    pub fn dispatch_queue_create(label: *const c_char, attr: *mut c_void) -> id;
+    pub fn dispatch_release(object: id);
+    #[cfg(feature = "video")]
+    fn dispatch_sync_f(queue: id, context: *mut c_void, work: extern "C" fn(*mut c_void));
+}
+
+// Wait for the callbacks already on the serial `queue` to finish
+#[cfg(feature = "video")]
+pub unsafe fn drain(queue: id) {
+    extern "C" fn nothing(_context: *mut c_void) {}
+    dispatch_sync_f(queue, std::ptr::null_mut(), nothing);
+}
+
+// Owned (+1) reference to a CVPixelBuffer, released on drop
+pub struct PixelBuffer(CVPixelBufferRef);
+
+// CVPixelBuffers are reference counted and safe to hand between threads
+unsafe impl Send for PixelBuffer {}
+
+impl PixelBuffer {
+    // Take ownership of a buffer that is already +1 (e.g. from a copy* call)
+    #[cfg(feature = "video")]
+    pub unsafe fn from_owned(pixel_buffer: CVPixelBufferRef) -> Option<Self> {
+        (!pixel_buffer.is_null()).then_some(Self(pixel_buffer))
+    }
+
+    // Retain the image inside a sample buffer (frames without an image yield None)
+    pub unsafe fn from_sample_buffer(sample_buffer: CMSampleBufferRef) -> Option<Self> {
+        let image = CMSampleBufferGetImageBuffer(sample_buffer);
+        (!image.is_null()).then(|| Self(CVPixelBufferRetain(image)))
+    }
+}
+
+impl Drop for PixelBuffer {
+    fn drop(&mut self) {
+        unsafe { CVPixelBufferRelease(self.0) };
+    }
+}
+
+// Latest frame handed over by a capture queue, taken by the render loop
+pub type FrameSlot = Mutex<Option<PixelBuffer>>;
+
+// BGRA texture fed from pixel buffers, resized to follow the source
+pub struct FrameTexture {
+    texture: Texture,
+}
+
+impl FrameTexture {
+    pub fn new(device: &DeviceRef) -> Self {
+        Self {
+            texture: new_bgra_texture(device, 1, 1),
+        }
+    }
+
+    pub fn texture(&self) -> &TextureRef {
+        &self.texture
+    }
+
+    // Upload the pending frame from the slot, if there is one
+    pub fn upload_from(&mut self, device: &DeviceRef, slot: &FrameSlot) {
+        let frame = match slot.lock() {
+            Ok(mut latest) => latest.take(),
+            Err(_) => None,
+        };
+        if let Some(frame) = frame {
+            self.upload(device, &frame);
+        }
+    }
+
+    pub fn upload(&mut self, device: &DeviceRef, frame: &PixelBuffer) {
+        unsafe {
+            if CVPixelBufferLockBaseAddress(frame.0, K_CV_PIXEL_BUFFER_LOCK_READ_ONLY) != 0 {
+                return;
+            }
+            let width = CVPixelBufferGetWidth(frame.0) as u64;
+            let height = CVPixelBufferGetHeight(frame.0) as u64;
+            let bytes_per_row = CVPixelBufferGetBytesPerRow(frame.0) as u64;
+            let base = CVPixelBufferGetBaseAddress(frame.0);
+
+            // Frame size changed (first frame, or a different source format)
+            if self.texture.width() != width || self.texture.height() != height {
+                self.texture = new_bgra_texture(device, width, height);
+            }
+            if !base.is_null() {
+                self.texture.replace_region(
+                    MTLRegion::new_2d(0, 0, width, height),
+                    0,
+                    base,
+                    bytes_per_row,
+                );
+            }
+            CVPixelBufferUnlockBaseAddress(frame.0, K_CV_PIXEL_BUFFER_LOCK_READ_ONLY);
+        }
+    }
+}
+
+pub fn new_bgra_texture(device: &DeviceRef, width: u64, height: u64) -> Texture {
+    let descriptor = TextureDescriptor::new();
+    descriptor.set_pixel_format(MTLPixelFormat::BGRA8Unorm);
+    descriptor.set_width(width);
+    descriptor.set_height(height);
+    descriptor.set_usage(MTLTextureUsage::ShaderRead);
+    descriptor.set_storage_mode(MTLStorageMode::Managed);
+    device.new_texture(&descriptor)
+}
+
+// Start declaring an NSObject subclass that owns an Arc<FrameSlot> in its `_slot` ivar.
+// The reference is released in dealloc, so capture queues holding the object stay valid.
+pub fn frame_sink_class_decl(name: &str) -> ClassDecl {
+    let mut decl = ClassDecl::new(name, class!(NSObject))
+        .unwrap_or_else(|| panic!("{} already registered", name));
+    decl.add_ivar::<*const c_void>("_slot");
+
+    extern "C" fn dealloc(this: &Object, _sel: Sel) {
+        unsafe {
+            let slot: *const c_void = *this.get_ivar("_slot");
+            if !slot.is_null() {
+                drop(Arc::from_raw(slot as *const FrameSlot));
+            }
+            let superclass: &Class = class!(NSObject);
+            let _: () = msg_send![super(this, superclass), dealloc];
+        }
+    }
+
+    unsafe {
+        decl.add_method(sel!(dealloc), dealloc as extern "C" fn(&Object, Sel));
+    }
+    decl
+}
+
+// Create an instance of a frame sink class (+1) pointing at the slot
+pub unsafe fn new_frame_sink(class: &Class, slot: &Arc<FrameSlot>) -> id {
+    let sink: id = msg_send![class, new];
+    (*sink).set_ivar("_slot", Arc::into_raw(slot.clone()) as *const c_void);
+    sink
+}
+
+// Hand a frame to the slot of a frame sink (called from the capture queue)
+pub unsafe fn deliver_frame(sink: &Object, frame: PixelBuffer) {
+    let slot: *const c_void = *sink.get_ivar("_slot");
+    if slot.is_null() {
+        return;
+    }
+    if let Ok(mut latest) = (*(slot as *const FrameSlot)).lock() {
+        *latest = Some(frame);
+    }
+}
+
+#[cfg(feature = "video")]
+pub unsafe fn ns_string(s: &str) -> id {
+    let string: id = NSString::alloc(cocoa::base::nil).init_str(s);
+    msg_send![string, autorelease]
+}
+
+pub unsafe fn string_from_ns(string: id) -> String {
+    if string.is_null() {
+        return String::new();
+    }
+    CStr::from_ptr(string.UTF8String())
+        .to_string_lossy()
+        .into_owned()
+}
@@ -0,0 +1,501 @@
+// Scene gallery: Cmd+O (Ctrl+O outside macOS) shows every scene file in the current scene's
+// directory (scenes/ when it came from nowhere) as a grid of thumbnails over the window.
+// Clicking one, or picking it with the arrows and Enter, loads it; Escape hides the gallery.
+//
+// Thumbnails are rendered on a thread of their own with a backend of its own and no window,
+// one scene at a time at low quality, and cached as small PNGs under the platform cache
+// directory, so they come back at once on the next run. A cached thumbnail older than its
+// scene file is rendered again, and while the gallery is shown the directory is looked at
+// every second so new and changed scenes get theirs. Files that don't load as scenes (such as
+// sequences) are left out. Rendering them needs the "offline" feature; without it the gallery
+// lists the scenes by name.
+use crate::config::Config;
+use cgmath::Vector2;
+use metal_raymarcher::editor::Motion;
+use metal_raymarcher::gizmo::{OverlayVertex, View};
+use metal_raymarcher::inspector::{self, ADVANCE, DOT, PADDING, ROW_HEIGHT};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, Sender};
+use std::time::{Duration, Instant, SystemTime};
+
+const THUMBNAIL: [usize; 2] = [32, 24]; // Cells across and down
+const CELL: f32 = 4.0; // Pixels per cell
+const GAP: f32 = 12.0; // Pixels between cards
+const TOP: f32 = 40.0; // Pixels from the top of the window to the first row
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+type Thumbnail = Vec<[u8; 3]>; // THUMBNAIL cells, rows top first
+
+// A rendered thumbnail of the scene at `path`, as it was when modified at `modified`
+type Rendered = (PathBuf, Option<SystemTime>, Result<Thumbnail, String>);
+
+struct Entry {
+    path: PathBuf,
+    name: String,
+    modified: Option<SystemTime>, // Of the file, when its thumbnail was asked for
+    thumbnail: Option<Thumbnail>, // None until rendered
+}
+
+pub struct Gallery {
+    pub shown: bool,
+    config: Config, // For the thumbnail renderer
+    dir: PathBuf,
+    current: Option<PathBuf>, // Scene shown in the window
+    entries: Vec<Entry>,
+    highlighted: usize,
+    first_row: usize, // Scrolled to
+    polled: Option<Instant>,
+    worker: Option<(Sender<PathBuf>, Receiver<Rendered>)>, // Started when first shown
+}
+
+impl Gallery {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            shown: false,
+            config: config.clone(),
+            dir: PathBuf::from("scenes"),
+            current: None,
+            entries: Vec::new(),
+            highlighted: 0,
+            first_row: 0,
+            polled: None,
+            worker: None,
+        }
+    }
+
+    // Show the scenes next to `current`, the scene file shown now
+    pub fn show(&mut self, current: Option<&Path>) {
+        let dir = current.and_then(Path::parent).filter(|dir| dir.is_dir());
+        let dir = dir.map_or_else(|| PathBuf::from("scenes"), Path::to_path_buf);
+        if dir != self.dir {
+            self.entries.clear();
+            self.dir = dir;
+        }
+        self.current = current.map(Path::to_path_buf);
+        self.highlighted = (self.entries.iter())
+            .position(|entry| Some(entry.path.as_path()) == current)
+            .unwrap_or(0);
+        self.first_row = 0;
+        self.polled = None;
+        self.shown = true;
+        self.poll();
+    }
+
+    // Pick up new and changed scene files and the thumbnails rendered since the last call;
+    // call every frame
+    pub fn poll(&mut self) {
+        if !self.shown {
+            return;
+        }
+        if let Some((_, rendered)) = &self.worker {
+            for (path, modified, thumbnail) in rendered.try_iter() {
+                let index = self.entries.iter().position(|entry| entry.path == path);
+                match (index, thumbnail) {
+                    (Some(index), Ok(thumbnail)) if self.entries[index].modified == modified => {
+                        self.entries[index].thumbnail = Some(thumbnail);
+                    }
+                    (Some(index), Err(err)) => {
+                        eprintln!("Leaving {} out of the gallery: {}", path.display(), err);
+                        self.entries.remove(index);
+                        self.highlighted = self.highlighted.min(self.entries.len().max(1) - 1);
+                    }
+                    _ => {} // Changed again, or gone, since
+                }
+            }
+        }
+        if self
+            .polled
+            .is_some_and(|polled| polled.elapsed() < POLL_INTERVAL)
+        {
+            return;
+        }
+        self.polled = Some(Instant::now());
+        let Ok(files) = std::fs::read_dir(&self.dir) else {
+            return;
+        };
+        let mut paths: Vec<PathBuf> = (files.filter_map(|file| file.ok()))
+            .map(|file| file.path())
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "toml")
+            })
+            .collect();
+        paths.sort();
+        for path in paths {
+            let modified = modified(&path);
+            let index = match self.entries.iter().position(|entry| entry.path == path) {
+                Some(index) if self.entries[index].modified == modified => continue,
+                Some(index) => index,
+                None => {
+                    let name = path.file_stem().unwrap_or_default().to_string_lossy();
+                    self.entries.push(Entry {
+                        name: name.into_owned(),
+                        path: path.clone(),
+                        modified: None,
+                        thumbnail: None,
+                    });
+                    self.entries.len() - 1
+                }
+            };
+            self.entries[index].modified = modified;
+            self.request(path);
+        }
+        self.entries.retain(|entry| entry.path.exists());
+        self.highlighted = self.highlighted.min(self.entries.len().max(1) - 1);
+    }
+
+    // Ask the worker for the thumbnail of the scene at `path`, starting it if need be
+    fn request(&mut self, path: PathBuf) {
+        if self.worker.is_none() {
+            self.worker = start(self.config.clone());
+        }
+        if let Some((requests, _)) = &self.worker {
+            let _ = requests.send(path);
+        }
+    }
+
+    // Typed text: Enter gives the highlighted scene to load, Escape hides the gallery
+    pub fn type_char(&mut self, c: char) -> Option<PathBuf> {
+        match c {
+            '\r' | '\n' => {
+                let path = self.entries.get(self.highlighted)?.path.clone();
+                self.shown = false;
+                Some(path)
+            }
+            '\u{1b}' => {
+                self.shown = false;
+                None
+            }
+            _ => None,
+        }
+    }
+
+    pub fn move_highlight(&mut self, motion: Motion, view: &View) {
+        let columns = columns(view) as isize;
+        let by = match motion {
+            Motion::Left => -1,
+            Motion::Right => 1,
+            Motion::Up => -columns,
+            Motion::Down => columns,
+            Motion::PageUp => -columns * rows(view) as isize,
+            Motion::PageDown => columns * rows(view) as isize,
+            Motion::Home => -(self.highlighted as isize),
+            Motion::End => self.entries.len() as isize,
+        };
+        let last = self.entries.len().saturating_sub(1) as isize;
+        self.highlighted = (self.highlighted as isize + by).clamp(0, last) as usize;
+        // Scroll to keep it in view
+        let row = self.highlighted / columns as usize;
+        let shown = rows(view).max(1);
+        self.first_row = self.first_row.min(row).max((row + 1).saturating_sub(shown));
+    }
+
+    // Scroll by `lines` (the mouse wheel, up being positive)
+    pub fn scroll(&mut self, lines: f32, view: &View) {
+        let total = self.entries.len().div_ceil(columns(view));
+        let last = total.saturating_sub(rows(view).max(1));
+        let row = self.first_row as isize - lines.round() as isize;
+        self.first_row = row.clamp(0, last as isize) as usize;
+    }
+
+    // Highlight the scene under `mouse`; clicking it gives it to load
+    pub fn hover(&mut self, view: &View, mouse: Vector2<f32>) {
+        if let Some(index) = self.entry_at(view, mouse) {
+            self.highlighted = index;
+        }
+    }
+
+    pub fn click(&mut self, view: &View, mouse: Vector2<f32>) -> Option<PathBuf> {
+        let index = self.entry_at(view, mouse)?;
+        self.shown = false;
+        Some(self.entries[index].path.clone())
+    }
+
+    fn entry_at(&self, view: &View, mouse: Vector2<f32>) -> Option<usize> {
+        let (x, y) = (
+            mouse.x * view.width as f32,
+            (1.0 - mouse.y) * view.height as f32,
+        );
+        let [width, height] = card_size();
+        let left = x - grid_left(view);
+        let top = y - TOP - ROW_HEIGHT;
+        if left < 0.0 || top < 0.0 {
+            return None;
+        }
+        let (column, row) = (left / (width + GAP), top / (height + GAP));
+        // Not in the gap after a card
+        if column.fract() * (width + GAP) > width || row.fract() * (height + GAP) > height {
+            return None;
+        }
+        let column = column as usize;
+        (column < columns(view))
+            .then(|| (self.first_row + row as usize) * columns(view) + column)
+            .filter(|&index| index < self.entries.len())
+    }
+
+    pub fn vertices(&self, view: &View) -> Vec<OverlayVertex> {
+        let mut out = Vec::new();
+        if !self.shown {
+            return out;
+        }
+        let screen = [view.width as f32, view.height as f32];
+        inspector::rectangle(&mut out, view, [0.0, 0.0], screen, [0.02, 0.02, 0.04, 0.85]);
+        let title = match self.entries.len() {
+            0 => format!("No scenes in {}", self.dir.display()),
+            _ => format!("Scenes in {}   Esc hides", self.dir.display()),
+        };
+        let left = grid_left(view);
+        let title_top = TOP + (ROW_HEIGHT - 5.0 * DOT) / 2.0 - ROW_HEIGHT;
+        let chars = ((screen[0] - left) / ADVANCE) as usize;
+        inspector::text(
+            &mut out,
+            view,
+            [left, title_top],
+            &title,
+            chars,
+            [0.7, 0.7, 0.75, 1.0],
+        );
+
+        let [width, height] = card_size();
+        let columns = columns(view);
+        let shown = self.entries.iter().enumerate();
+        let shown = shown
+            .skip(self.first_row * columns)
+            .take(rows(view) * columns);
+        for (index, entry) in shown {
+            let slot = index - self.first_row * columns;
+            let at = [
+                left + (slot % columns) as f32 * (width + GAP),
+                TOP + ROW_HEIGHT + (slot / columns) as f32 * (height + GAP),
+            ];
+            let background = match index == self.highlighted {
+                true => [0.3, 0.3, 0.36, 1.0],
+                false => [0.1, 0.1, 0.13, 1.0],
+            };
+            inspector::rectangle(&mut out, view, at, [width, height], background);
+            let image = [at[0] + PADDING, at[1] + PADDING];
+            match &entry.thumbnail {
+                Some(thumbnail) => {
+                    for (cell, rgb) in thumbnail.iter().enumerate() {
+                        let (x, y) = (cell % THUMBNAIL[0], cell / THUMBNAIL[0]);
+                        let color = rgb.map(|channel| channel as f32 / 255.0);
+                        inspector::rectangle(
+                            &mut out,
+                            view,
+                            [image[0] + x as f32 * CELL, image[1] + y as f32 * CELL],
+                            [CELL, CELL],
+                            [color[0], color[1], color[2], 1.0],
+                        );
+                    }
+                }
+                None => inspector::text(
+                    &mut out,
+                    view,
+                    [image[0] + ADVANCE, image[1] + ADVANCE],
+                    "...",
+                    3,
+                    [0.5, 0.5, 0.55, 1.0],
+                ),
+            }
+            let current = self.current.as_deref() == Some(entry.path.as_path());
+            let name_top = image[1] + THUMBNAIL[1] as f32 * CELL + (ROW_HEIGHT - 5.0 * DOT) / 2.0;
+            inspector::text(
+                &mut out,
+                view,
+                [image[0], name_top],
+                &entry.name,
+                (THUMBNAIL[0] as f32 * CELL / ADVANCE) as usize,
+                match current {
+                    true => [1.0, 0.8, 0.2, 1.0],
+                    false => [1.0, 1.0, 1.0, 1.0],
+                },
+            );
+        }
+        out
+    }
+}
+
+// A card: the thumbnail with the scene's name under it
+fn card_size() -> [f32; 2] {
+    [
+        THUMBNAIL[0] as f32 * CELL + PADDING * 2.0,
+        THUMBNAIL[1] as f32 * CELL + ROW_HEIGHT + PADDING * 2.0,
+    ]
+}
+
+fn columns(view: &View) -> usize {
+    let width = card_size()[0] + GAP;
+    (((view.width as f32 - GAP * 2.0 + GAP) / width) as usize).max(1)
+}
+
+fn rows(view: &View) -> usize {
+    let height = card_size()[1] + GAP;
+    ((view.height as f32 - TOP - ROW_HEIGHT + GAP) / height) as usize
+}
+
+// The grid is centered across the window
+fn grid_left(view: &View) -> f32 {
+    let width = columns(view) as f32 * (card_size()[0] + GAP) - GAP;
+    ((view.width as f32 - width) / 2.0).max(0.0)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+// The thumbnail renderer's thread, taking scene paths and sending back their thumbnails
+#[cfg(feature = "offline")]
+fn start(config: Config) -> Option<(Sender<PathBuf>, Receiver<Rendered>)> {
+    let (requests, requested) = std::sync::mpsc::channel::<PathBuf>();
+    let (rendered, results) = std::sync::mpsc::channel();
+    let spawned = std::thread::Builder::new()
+        .name("thumbnails".to_string())
+        .spawn(move || {
+            let mut renderer = None;
+            for path in requested {
+                let modified = modified(&path);
+                let thumbnail = thumbnail(&mut renderer, &config, &path, modified);
+                if rendered.send((path, modified, thumbnail)).is_err() {
+                    return;
+                }
+            }
+        });
+    match spawned {
+        Ok(_) => Some((requests, results)),
+        Err(err) => {
+            eprintln!("Failed to start the thumbnail renderer: {}", err);
+            None
+        }
+    }
+}
+
+#[cfg(not(feature = "offline"))]
+fn start(_config: Config) -> Option<(Sender<PathBuf>, Receiver<Rendered>)> {
+    None
+}
+
+// The cached thumbnail of the scene at `path`, or a new one rendered with `renderer` (made on
+// first use)
+#[cfg(feature = "offline")]
+fn thumbnail(
+    renderer: &mut Option<Box<dyn crate::backend::Backend>>,
+    config: &Config,
+    path: &Path,
+    modified: Option<SystemTime>,
+) -> Result<Thumbnail, String> {
+    use crate::backend::{self, BackendKind};
+    use cgmath::Vector3;
+    use metal_raymarcher::quality::QualityPreset;
+    use metal_raymarcher::{Camera, Scene};
+
+    let cached = cache_path(path);
+    let fresh = cached.as_deref().map(self::modified);
+    if let (Some(Some(cached_at)), Some(modified)) = (fresh, modified) {
+        if cached_at >= modified {
+            if let Some(thumbnail) = cached.as_deref().and_then(read_cached) {
+                return Ok(thumbnail);
+            }
+        }
+    }
+
+    let scene = Scene::load(path)?;
+    let backend = match renderer {
+        Some(backend) => {
+            backend.rebuild(&scene)?;
+            backend
+        }
+        None => {
+            // The built-in shader with no inputs: only the scene itself
+            let mut config = config.clone();
+            config.shader = None;
+            config.video = Default::default();
+            config.screen = Default::default();
+            let kind = config.backend.unwrap_or_else(BackendKind::platform_default);
+            let mut backend = backend::create(kind, None, &config, &scene)?;
+            backend.set_quality(QualityPreset::Low.settings());
+            renderer.insert(backend)
+        }
+    };
+    backend.set_scene(&scene);
+    // Where the window's camera starts
+    let camera = Camera {
+        position: Vector3::new(8.0, 2.0, 0.0),
+        ..Camera::default()
+    };
+    let [columns, rows] = THUMBNAIL;
+    const SAMPLES: usize = 4; // Pixels rendered per cell across and down
+    let size = winit::dpi::PhysicalSize::new((columns * SAMPLES) as u32, (rows * SAMPLES) as u32);
+    let rgba = backend.render_to_rgba(size, &camera, 0.0);
+    let row_bytes = columns * SAMPLES * 4;
+    let thumbnail: Thumbnail = (0..columns * rows)
+        .map(|cell| {
+            let (x, y) = (cell % columns * SAMPLES, cell / columns * SAMPLES);
+            let mut total = [0u32; 3];
+            for dy in 0..SAMPLES {
+                for dx in 0..SAMPLES {
+                    let at = (y + dy) * row_bytes + (x + dx) * 4;
+                    for (channel, sum) in total.iter_mut().enumerate() {
+                        *sum += rgba[at + channel] as u32;
+                    }
+                }
+            }
+            total.map(|sum| (sum / (SAMPLES * SAMPLES) as u32) as u8)
+        })
+        .collect();
+
+    if let Some(cached) = &cached {
+        let rgba: Vec<u8> = thumbnail
+            .iter()
+            .flat_map(|&[r, g, b]| [r, g, b, 255])
+            .collect();
+        let written = (cached.parent().map(std::fs::create_dir_all).transpose())
+            .map_err(|err| err.to_string())
+            .and_then(|_| {
+                crate::offline::write_png(cached, columns as u32, rows as u32, &rgba, &[])
+            });
+        if let Err(err) = written {
+            eprintln!("Failed to cache a thumbnail: {}", err);
+        }
+    }
+    Ok(thumbnail)
+}
+
+// <cache dir>/metal-raymarcher/thumbnails/<name>-<hash of the full path>.png
+#[cfg(feature = "offline")]
+fn cache_path(path: &Path) -> Option<PathBuf> {
+    use std::hash::{Hash, Hasher};
+    let full = path.canonicalize().ok()?;
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    full.hash(&mut hasher);
+    let name = path.file_stem()?.to_string_lossy();
+    let file = format!("{}-{:016x}.png", name, hasher.finish());
+    Some(
+        dirs::cache_dir()?
+            .join("metal-raymarcher")
+            .join("thumbnails")
+            .join(file),
+    )
+}
+
+#[cfg(feature = "offline")]
+fn read_cached(path: &Path) -> Option<Thumbnail> {
+    let file = std::fs::File::open(path).ok()?;
+    let mut decoder = png::Decoder::new(std::io::BufReader::new(file));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().ok()?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).ok()?;
+    if [info.width as usize, info.height as usize] != THUMBNAIL
+        || info.color_type != png::ColorType::Rgba
+    {
+        return None;
+    }
+    Some(
+        buffer
+            .chunks_exact(4)
+            .map(|rgba| [rgba[0], rgba[1], rgba[2]])
+            .collect(),
+    )
+}
@@ -0,0 +1,365 @@
+// Gizmos for the selected object: axis arrows to move it, rings to rotate it and axis
+// handles to scale it. They are built on the CPU as screen-space triangles (`OverlayVertex`)
+// that the renderers draw over the raymarched image, and handles are hit-tested and dragged
+// against the mouse in pixels.
+//
+// Point lights get a small sun icon at their position, which can be clicked like a handle.
+//
+// Rings turn the object's Euler angle for that axis, so once an object is rotated about
+// several axes the rings (which stay aligned with the world) only approximate its turn.
+use crate::camera::Camera;
+use cgmath::{InnerSpace, Vector2, Vector3};
+
+// Most vertices an overlay can have; renderers drop any beyond
+pub const MAX_OVERLAY_VERTICES: usize = 131072; // Enough for a screenful of shader text
+
+// Handle length as a fraction of the distance to the camera, so gizmos keep their size
+const SIZE: f32 = 0.18;
+// How close (in pixels) the cursor must be to grab a handle
+const GRAB_RADIUS: f32 = 8.0;
+const LINE_WIDTH: f32 = 3.0; // Pixels
+const RING_SEGMENTS: usize = 64;
+const LIGHT_RADIUS: f32 = 7.0; // Pixels
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct OverlayVertex {
+    pub position: [f32; 2], // Normalized device coordinates
+    pub color: [f32; 4],    // Blended over the image by its alpha
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GizmoMode {
+    #[default]
+    Translate,
+    Rotate,
+    Scale,
+}
+
+impl GizmoMode {
+    pub fn next(self) -> Self {
+        match self {
+            GizmoMode::Translate => GizmoMode::Rotate,
+            GizmoMode::Rotate => GizmoMode::Scale,
+            GizmoMode::Scale => GizmoMode::Translate,
+        }
+    }
+
+    // Object property the gizmo edits
+    pub fn property(self) -> &'static str {
+        match self {
+            GizmoMode::Translate => "position",
+            GizmoMode::Rotate => "rotation",
+            GizmoMode::Scale => "scale",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl Axis {
+    pub const ALL: [Axis; 3] = [Axis::X, Axis::Y, Axis::Z];
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn vector(self) -> Vector3<f32> {
+        match self {
+            Axis::X => Vector3::unit_x(),
+            Axis::Y => Vector3::unit_y(),
+            Axis::Z => Vector3::unit_z(),
+        }
+    }
+
+    fn color(self) -> [f32; 3] {
+        match self {
+            Axis::X => [0.9, 0.2, 0.2],
+            Axis::Y => [0.3, 0.85, 0.2],
+            Axis::Z => [0.2, 0.4, 1.0],
+        }
+    }
+}
+
+// The camera and viewport a gizmo is drawn in
+#[derive(Clone, Copy, Debug)]
+pub struct View {
+    pub camera: Camera,
+    pub width: u32, // Pixels
+    pub height: u32,
+}
+
+impl View {
+    fn aspect(&self) -> f32 {
+        self.width as f32 / self.height.max(1) as f32
+    }
+
+    // Screen position in pixels from the center, y up (None behind the camera)
+    fn project(&self, point: Vector3<f32>) -> Option<Vector2<f32>> {
+        Some(self.camera.project(point)? * (self.height as f32 / 2.0))
+    }
+
+    // The app's normalized mouse position (0..1, y up) in the same pixels
+    fn mouse(&self, mouse: Vector2<f32>) -> Vector2<f32> {
+        Vector2::new(
+            (mouse.x - 0.5) * self.width as f32,
+            (mouse.y - 0.5) * self.height as f32,
+        )
+    }
+
+    fn ndc(&self, pixels: Vector2<f32>) -> [f32; 2] {
+        [
+            pixels.x / (self.height as f32 / 2.0) / self.aspect(),
+            pixels.y / (self.height as f32 / 2.0),
+        ]
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Gizmo {
+    pub mode: GizmoMode,
+    pub origin: Vector3<f32>, // The object's position
+    pub hovered: Option<Axis>,
+    pub active: Option<Axis>, // Being dragged
+}
+
+impl Gizmo {
+    fn size(&self, view: &View) -> f32 {
+        (self.origin - view.camera.position).magnitude() * SIZE
+    }
+
+    // The handle of `axis` as a polyline in world space
+    fn handle(&self, axis: Axis, view: &View) -> Vec<Vector3<f32>> {
+        let size = self.size(view);
+        match self.mode {
+            GizmoMode::Translate | GizmoMode::Scale => {
+                vec![self.origin, self.origin + axis.vector() * size]
+            }
+            GizmoMode::Rotate => {
+                let (u, v) = match axis {
+                    Axis::X => (Vector3::unit_y(), Vector3::unit_z()),
+                    Axis::Y => (Vector3::unit_z(), Vector3::unit_x()),
+                    Axis::Z => (Vector3::unit_x(), Vector3::unit_y()),
+                };
+                (0..=RING_SEGMENTS)
+                    .map(|i| {
+                        let angle = std::f32::consts::TAU * i as f32 / RING_SEGMENTS as f32;
+                        self.origin + (u * angle.cos() + v * angle.sin()) * size
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    // The handle of `axis` on screen, in pixels (points behind the camera are dropped)
+    fn screen_handle(&self, axis: Axis, view: &View) -> Vec<Vector2<f32>> {
+        self.handle(axis, view)
+            .into_iter()
+            .filter_map(|point| view.project(point))
+            .collect()
+    }
+
+    // Handle under `mouse` (normalized, y up), if any
+    pub fn hit(&self, view: &View, mouse: Vector2<f32>) -> Option<Axis> {
+        let mouse = view.mouse(mouse);
+        Axis::ALL
+            .into_iter()
+            .filter_map(|axis| {
+                let points = self.screen_handle(axis, view);
+                let distance = points
+                    .windows(2)
+                    .map(|pair| segment_distance(mouse, pair[0], pair[1]))
+                    .fold(f32::INFINITY, f32::min);
+                (distance < GRAB_RADIUS).then_some((axis, distance))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(axis, _)| axis)
+    }
+
+    // How far dragging the handle of `axis` from `from` to `to` (normalized mouse positions)
+    // takes it: world units along the axis to move, degrees to turn, or the factor to scale by
+    pub fn drag(&self, axis: Axis, view: &View, from: Vector2<f32>, to: Vector2<f32>) -> f32 {
+        let (from, to) = (view.mouse(from), view.mouse(to));
+        let Some(center) = view.project(self.origin) else {
+            return self.unchanged();
+        };
+        match self.mode {
+            GizmoMode::Translate | GizmoMode::Scale => {
+                let size = self.size(view);
+                let Some(end) = view.project(self.origin + axis.vector() * size) else {
+                    return self.unchanged();
+                };
+                let along = end - center;
+                if along.magnitude2() < 1.0 {
+                    return self.unchanged(); // The axis points at the camera
+                }
+                let amount = (to - from).dot(along) / along.magnitude2();
+                match self.mode {
+                    GizmoMode::Scale => (1.0 + amount).max(0.01),
+                    _ => amount * size,
+                }
+            }
+            GizmoMode::Rotate => {
+                let (a, b) = (from - center, to - center);
+                let turn = a.perp_dot(b).atan2(a.dot(b)).to_degrees();
+                // Counterclockwise on screen is a positive turn about axes facing away
+                let (forward, _, _) = view.camera.basis();
+                if axis.vector().dot(forward) > 0.0 {
+                    turn
+                } else {
+                    -turn
+                }
+            }
+        }
+    }
+
+    fn unchanged(&self) -> f32 {
+        match self.mode {
+            GizmoMode::Scale => 1.0,
+            _ => 0.0,
+        }
+    }
+
+    // Triangles drawing the gizmo over the image
+    pub fn vertices(&self, view: &View) -> Vec<OverlayVertex> {
+        let mut out = Vec::new();
+        for axis in Axis::ALL {
+            let [r, g, b] = axis.color();
+            let color = if self.active == Some(axis) {
+                [1.0, 0.85, 0.2, 1.0]
+            } else if self.hovered == Some(axis) {
+                [
+                    r + (1.0 - r) * 0.5,
+                    g + (1.0 - g) * 0.5,
+                    b + (1.0 - b) * 0.5,
+                    1.0,
+                ]
+            } else {
+                [r, g, b, 0.85]
+            };
+            let points = self.screen_handle(axis, view);
+            for pair in points.windows(2) {
+                line(&mut out, view, pair[0], pair[1], color);
+            }
+            let [.., before, end] = points[..] else {
+                continue;
+            };
+            let direction = (end - before).normalize();
+            let side = Vector2::new(-direction.y, direction.x);
+            match self.mode {
+                GizmoMode::Translate => triangle(
+                    &mut out,
+                    view,
+                    [end + direction * 12.0, end + side * 6.0, end - side * 6.0],
+                    color,
+                ),
+                GizmoMode::Scale => {
+                    let corner = |x: f32, y: f32| end + direction * x + side * y;
+                    triangle(
+                        &mut out,
+                        view,
+                        [corner(-5.0, -5.0), corner(5.0, -5.0), corner(5.0, 5.0)],
+                        color,
+                    );
+                    triangle(
+                        &mut out,
+                        view,
+                        [corner(-5.0, -5.0), corner(5.0, 5.0), corner(-5.0, 5.0)],
+                        color,
+                    );
+                }
+                GizmoMode::Rotate => {}
+            }
+        }
+        out
+    }
+}
+
+// How far (in pixels) `mouse` is from the icon of a light at `position`, when close enough to
+// grab it
+pub fn light_hit(view: &View, position: Vector3<f32>, mouse: Vector2<f32>) -> Option<f32> {
+    let center = view.project(position)?;
+    let distance = (view.mouse(mouse) - center).magnitude();
+    (distance < LIGHT_RADIUS + GRAB_RADIUS).then_some(distance)
+}
+
+// Icon of a light at `position`: a ring in its color with short rays around it
+pub fn light_icon(
+    view: &View,
+    position: Vector3<f32>,
+    color: [f32; 3],
+    selected: bool,
+) -> Vec<OverlayVertex> {
+    let mut out = Vec::new();
+    let Some(center) = view.project(position) else {
+        return out;
+    };
+    let [r, g, b] = color.map(|c| c.clamp(0.0, 1.0));
+    let color = if selected {
+        [1.0, 0.85, 0.2, 1.0]
+    } else {
+        [r, g, b, 0.9]
+    };
+    let at = |angle: f32, radius: f32| center + Vector2::new(angle.cos(), angle.sin()) * radius;
+    let segments = 16;
+    for i in 0..segments {
+        let angle = |i: usize| std::f32::consts::TAU * i as f32 / segments as f32;
+        line(
+            &mut out,
+            view,
+            at(angle(i), LIGHT_RADIUS),
+            at(angle(i + 1), LIGHT_RADIUS),
+            color,
+        );
+        if i % 2 == 0 {
+            let ray = at(angle(i), 1.0) - center;
+            line(
+                &mut out,
+                view,
+                center + ray * (LIGHT_RADIUS * 1.5),
+                center + ray * (LIGHT_RADIUS * 2.2),
+                color,
+            );
+        }
+    }
+    out
+}
+
+fn line(
+    out: &mut Vec<OverlayVertex>,
+    view: &View,
+    a: Vector2<f32>,
+    b: Vector2<f32>,
+    color: [f32; 4],
+) {
+    let along = b - a;
+    if along.magnitude2() == 0.0 {
+        return;
+    }
+    let side = Vector2::new(-along.y, along.x).normalize() * (LINE_WIDTH / 2.0);
+    triangle(out, view, [a - side, b - side, b + side], color);
+    triangle(out, view, [a - side, b + side, a + side], color);
+}
+
+fn triangle(out: &mut Vec<OverlayVertex>, view: &View, points: [Vector2<f32>; 3], color: [f32; 4]) {
+    out.extend(points.map(|point| OverlayVertex {
+        position: view.ndc(point),
+        color,
+    }));
+}
+
+// Distance from `p` to the segment from `a` to `b`
+fn segment_distance(p: Vector2<f32>, a: Vector2<f32>, b: Vector2<f32>) -> f32 {
+    let along = b - a;
+    let t = if along.magnitude2() > 0.0 {
+        ((p - a).dot(along) / along.magnitude2()).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    (p - (a + along * t)).magnitude()
+}
@@ -0,0 +1,153 @@
+// Terrain from height images, for the heightfield shape (see scene.rs): a grayscale PNG such as
+// an exported DEM tile or a depth map, brighter being higher. 16-bit images keep their full
+// precision; color ones are read by the average of their channels.
+//
+// The image is stretched so its lowest pixel is 0 and its highest 1, then averaged down to a
+// grid at most MAX_SIDE points along its longer side. The shader looks heights up in that grid
+// with bilinear filtering (see codegen.rs's sdHeightfield<id>), so like a cloud's budget the
+// resolution is what the terrain costs. PNG decoding comes with the "offline" feature.
+use std::path::Path;
+
+// Most grid points a heightfield may have along a side
+pub const MAX_SIDE: usize = 64;
+
+// The heights in the image at `path` as rows of 0 to 1, from the top of the image, with at
+// most `resolution` points along the longer side. `invert` reads darker as higher, for depth
+// maps that are brighter the further away.
+#[cfg(feature = "offline")]
+pub fn load(path: &Path, resolution: usize, invert: bool) -> Result<Vec<Vec<f32>>, String> {
+    let error = |err: &dyn std::fmt::Display| {
+        format!("Failed to read height image {}: {}", path.display(), err)
+    };
+    let file = std::fs::File::open(path).map_err(|err| error(&err))?;
+    let mut decoder = png::Decoder::new(std::io::BufReader::new(file));
+    // Palettes and bit depths under 8 become 8-bit, 16-bit stays as it is
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(|err| error(&err))?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(|err| error(&err))?;
+    buffer.truncate(info.buffer_size());
+
+    let samples: Vec<f32> = match info.bit_depth {
+        png::BitDepth::Sixteen => (buffer.chunks_exact(2))
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]) as f32)
+            .collect(),
+        _ => buffer.iter().map(|&sample| sample as f32).collect(),
+    };
+    // Grays, or the average of red, green and blue; alpha is ignored
+    let (channels, colors) = match info.color_type {
+        png::ColorType::Grayscale | png::ColorType::Indexed => (1, 1),
+        png::ColorType::GrayscaleAlpha => (2, 1),
+        png::ColorType::Rgb => (3, 3),
+        png::ColorType::Rgba => (4, 3),
+    };
+    let pixels: Vec<f32> = (samples.chunks_exact(channels))
+        .map(|pixel| pixel[..colors].iter().sum::<f32>() / colors as f32)
+        .collect();
+    let (width, height) = (info.width as usize, info.height as usize);
+    if width < 2 || height < 2 || pixels.len() < width * height {
+        return Err(error(&"the image needs to be at least 2 by 2 pixels"));
+    }
+    Ok(grid(&pixels, width, height, resolution, invert))
+}
+
+#[cfg(not(feature = "offline"))]
+pub fn load(path: &Path, _resolution: usize, _invert: bool) -> Result<Vec<Vec<f32>>, String> {
+    Err(format!(
+        "Can't read height image {}: built without the \"offline\" feature (PNG decoding)",
+        path.display()
+    ))
+}
+
+// `pixels` (rows of `width`) averaged down to at most `resolution` points along the longer
+// side, and stretched to run from 0 to 1
+#[cfg_attr(not(feature = "offline"), allow(dead_code))]
+fn grid(
+    pixels: &[f32],
+    width: usize,
+    height: usize,
+    resolution: usize,
+    invert: bool,
+) -> Vec<Vec<f32>> {
+    let side = resolution.clamp(2, MAX_SIDE) as f32 / width.max(height) as f32;
+    let columns = ((width as f32 * side).round() as usize).clamp(2, width);
+    let rows = ((height as f32 * side).round() as usize).clamp(2, height);
+    // Each point is the average of the pixels in its share of the image
+    let span = |i: usize, count: usize, pixels: usize| {
+        let start = i * pixels / count;
+        start..((i + 1) * pixels / count).max(start + 1)
+    };
+    let mut heights: Vec<Vec<f32>> = (0..rows)
+        .map(|row| {
+            (0..columns)
+                .map(|column| {
+                    let (ys, xs) = (span(row, rows, height), span(column, columns, width));
+                    let count = (ys.len() * xs.len()) as f32;
+                    let total: f32 = (ys.flat_map(|y| xs.clone().map(move |x| (x, y))))
+                        .map(|(x, y)| pixels[y * width + x])
+                        .sum();
+                    total / count
+                })
+                .collect()
+        })
+        .collect();
+    let (low, high) = (heights.iter().flatten()).fold((f32::MAX, f32::MIN), |(low, high), &h| {
+        (low.min(h), high.max(h))
+    });
+    let range = (high - low).max(f32::EPSILON);
+    for h in heights.iter_mut().flatten() {
+        *h = (*h - low) / range;
+        if invert {
+            *h = 1.0 - *h;
+        }
+    }
+    heights
+}
+
+// Height from 0 to 1 at (x, z) over a grid spanning `size` either side of the origin, filtered
+// between the four points around it as the shader does
+pub fn sample(heights: &[Vec<f32>], size: [f32; 2], x: f32, z: f32) -> f32 {
+    let (rows, columns) = (heights.len(), heights.first().map_or(0, Vec::len));
+    if rows < 2 || columns < 2 {
+        return 0.0;
+    }
+    let last = [(columns - 1) as f32, (rows - 1) as f32];
+    let g = [(x, 0), (z, 1)]
+        .map(|(t, axis)| ((t / size[axis] * 0.5 + 0.5) * last[axis]).clamp(0.0, last[axis]));
+    let cell = [0, 1].map(|axis| g[axis].floor().min(last[axis] - 1.0));
+    let [fx, fz] = [0, 1].map(|axis| g[axis] - cell[axis]);
+    let [i, j] = cell.map(|c| c as usize);
+    let mix = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    mix(
+        mix(heights[j][i], heights[j][i + 1], fx),
+        mix(heights[j + 1][i], heights[j + 1][i + 1], fx),
+        fz,
+    )
+}
+
+// Steepest rise of `heights` per unit across, over a grid spanning `size` either side of the
+// origin: how much a height of 1 can bend the surface away from a flat one
+pub fn slope(heights: &[Vec<f32>], size: [f32; 2]) -> f32 {
+    let (rows, columns) = (heights.len(), heights.first().map_or(0, Vec::len));
+    if rows < 2 || columns < 2 {
+        return 0.0;
+    }
+    let spacing = [
+        2.0 * size[0] / (columns - 1) as f32,
+        2.0 * size[1] / (rows - 1) as f32,
+    ];
+    let mut steepest: f32 = 0.0;
+    for j in 0..rows - 1 {
+        for i in 0..columns - 1 {
+            // Bilinear cells slope most along their edges
+            let dx = (heights[j][i + 1] - heights[j][i])
+                .abs()
+                .max((heights[j + 1][i + 1] - heights[j + 1][i]).abs());
+            let dz = (heights[j + 1][i] - heights[j][i])
+                .abs()
+                .max((heights[j + 1][i + 1] - heights[j][i + 1]).abs());
+            steepest = steepest.max((dx / spacing[0]).hypot(dz / spacing[1]));
+        }
+    }
+    steepest
+}
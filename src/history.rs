@@ -0,0 +1,175 @@
+// Undo and redo for edits made in the viewport (gizmo drags, brush strokes, deleting objects,
+// placing lights). Each edit is recorded as a command that has already been applied and knows
+// how to apply and revert itself. Only the last MAX_HISTORY edits are kept, and making a new
+// edit drops anything that was undone.
+//
+// Animations, expressions, scripts and cues are not recorded: they keep running on the scene
+// and can overwrite or reshape what an undo restores. Structural commands check the node is
+// still where they left it, and do nothing when it is not.
+use crate::scene::{Node, PointLight, Scene};
+
+const MAX_HISTORY: usize = 100;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    // A parameter (see scene.rs) changed from `before` to `after`
+    Set {
+        target: String,
+        before: Vec<f32>,
+        after: Vec<f32>,
+    },
+    // `node` was added at `path` (see Scene::object_path)
+    Add {
+        path: Vec<usize>,
+        node: Node,
+    },
+    // `node` was removed from `path`
+    Remove {
+        path: Vec<usize>,
+        node: Node,
+    },
+    // `light` was added to the scene's lights at `index`
+    AddLight {
+        index: usize,
+        light: PointLight,
+    },
+    // `light` was removed from `index`
+    RemoveLight {
+        index: usize,
+        light: PointLight,
+    },
+    // Several edits made as one, in order
+    Batch(Vec<Command>),
+}
+
+impl Command {
+    // Make the edit again. Returns whether the scene's shader needs rebuilding.
+    pub fn apply(&self, scene: &mut Scene) -> bool {
+        match self {
+            Command::Set { target, after, .. } => set(scene, target, after),
+            Command::Add { path, node } => insert(scene, path, node),
+            Command::Remove { path, node } => remove(scene, path, node),
+            Command::AddLight { index, light } => insert_light(scene, *index, light),
+            Command::RemoveLight { index, light } => remove_light(scene, *index, light),
+            Command::Batch(commands) => commands
+                .iter()
+                .fold(false, |rebuild, command| command.apply(scene) | rebuild),
+        }
+    }
+
+    // Undo the edit. Returns whether the scene's shader needs rebuilding.
+    pub fn revert(&self, scene: &mut Scene) -> bool {
+        match self {
+            Command::Set { target, before, .. } => set(scene, target, before),
+            Command::Add { path, node } => remove(scene, path, node),
+            Command::Remove { path, node } => insert(scene, path, node),
+            Command::AddLight { index, light } => remove_light(scene, *index, light),
+            Command::RemoveLight { index, light } => insert_light(scene, *index, light),
+            Command::Batch(commands) => commands
+                .iter()
+                .rev()
+                .fold(false, |rebuild, command| command.revert(scene) | rebuild),
+        }
+    }
+
+    // Short description for the console
+    pub fn describe(&self) -> String {
+        let name = |node: &Node| match node {
+            Node::Object(object) if !object.name.is_empty() => object.name.clone(),
+            Node::Group(group) if !group.name.is_empty() => group.name.clone(),
+            Node::Object(_) => "object".to_string(),
+            Node::Group(_) => "group".to_string(),
+        };
+        match self {
+            Command::Set { target, .. } => target.clone(),
+            Command::Add { node, .. } => format!("add {}", name(node)),
+            Command::Remove { node, .. } => format!("remove {}", name(node)),
+            Command::AddLight { light, .. } => format!("add {}", light.name),
+            Command::RemoveLight { light, .. } => format!("remove {}", light.name),
+            Command::Batch(commands) => commands
+                .iter()
+                .map(Command::describe)
+                .collect::<Vec<_>>()
+                .join(", "),
+        }
+    }
+}
+
+// Parameters an edit touches are made live, so later changes to them need no rebuild
+fn set(scene: &mut Scene, target: &str, value: &[f32]) -> bool {
+    let rebuild = scene.make_live(target).unwrap_or(true);
+    match scene.parameter_mut(target) {
+        Some(parameter) if parameter.len() == value.len() => parameter.copy_from_slice(value),
+        _ => return false,
+    }
+    rebuild
+}
+
+fn insert(scene: &mut Scene, path: &[usize], node: &Node) -> bool {
+    scene.insert_at(path, node.clone())
+}
+
+fn remove(scene: &mut Scene, path: &[usize], node: &Node) -> bool {
+    if scene.node_at(path) != Some(node) {
+        return false;
+    }
+    scene.remove_at(path).is_some()
+}
+
+fn insert_light(scene: &mut Scene, index: usize, light: &PointLight) -> bool {
+    if index > scene.lights.len() {
+        return false;
+    }
+    scene.lights.insert(index, light.clone());
+    true
+}
+
+fn remove_light(scene: &mut Scene, index: usize, light: &PointLight) -> bool {
+    if scene.lights.get(index) != Some(light) {
+        return false;
+    }
+    scene.lights.remove(index);
+    true
+}
+
+#[derive(Debug, Default)]
+pub struct History {
+    undo: Vec<Command>,
+    redo: Vec<Command>,
+}
+
+impl History {
+    // Record an edit that has just been made
+    pub fn push(&mut self, command: Command) {
+        if command == Command::Batch(Vec::new()) {
+            return;
+        }
+        self.undo.push(command);
+        if self.undo.len() > MAX_HISTORY {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+    }
+
+    // Revert the last edit; returns it and whether the shader needs rebuilding
+    pub fn undo(&mut self, scene: &mut Scene) -> Option<(&Command, bool)> {
+        let command = self.undo.pop()?;
+        let rebuild = command.revert(scene);
+        self.redo.push(command);
+        self.redo.last().map(|command| (command, rebuild))
+    }
+
+    // Make the last undone edit again; returns it and whether the shader needs rebuilding
+    pub fn redo(&mut self, scene: &mut Scene) -> Option<(&Command, bool)> {
+        let command = self.redo.pop()?;
+        let rebuild = command.apply(scene);
+        self.undo.push(command);
+        self.undo.last().map(|command| (command, rebuild))
+    }
+
+    // Forget everything, e.g. when the scene is replaced
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
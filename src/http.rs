@@ -0,0 +1,271 @@
+// Monitoring over HTTP (--http PORT), for keeping an eye on long offline renders and for quick
+// integrations. The server listens on localhost only (tunnel it, e.g. with ssh -L, to watch
+// from another machine) and answers GET requests:
+//
+//   /frame.png    the latest frame (offline: the one last rendered; in a window: the current
+//                 view, read back when asked, without the gizmos)
+//   /stats.json   the scene and its time, frames drawn and the rate they're drawn at, the
+//                 resolution, quality and GPU, path tracing samples, and an offline render's
+//                 progress
+//   /params       every parameter of the scene (see scene.rs) with its current value, as JSON
+//   /metrics      frame and GPU pass times, dropped frames, render scale and memory, for
+//                 Prometheus to scrape (see metrics.rs)
+//
+// Requests are answered on the render thread between frames, or by an offline render between
+// the frames it writes. At most MAX_CLIENTS are served at once, and one that hasn't sent its
+// request within READ_TIMEOUT is dropped.
+use crate::json::Json;
+use crate::render_thread::Message;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const MAX_HEAD: usize = 16 << 10; // Of a request's line and headers
+const MAX_CLIENTS: usize = 8;
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub enum Endpoint {
+    Frame,
+    Stats,
+    Params,
+    Metrics,
+}
+
+pub struct Response {
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn json(json: Json) -> Self {
+        Self {
+            content_type: "application/json",
+            body: json.to_string().into_bytes(),
+        }
+    }
+}
+
+// The answer to a request, or what went wrong
+pub type Answer = Result<Response, String>;
+
+// Frames drawn, for /stats.json and /metrics
+pub struct Stats {
+    pub started: Instant,
+    pub frames: u64,
+    pub fps: f32,        // Over the last whole second (or frame, if longer)
+    pub frame_time: f32, // Seconds between the last two frames
+    pub slowest: f32,    // The longest of those over the last whole second
+    // Display refreshes that went by without a new frame (when the refresh rate is known)
+    pub dropped: u64,
+    pub refresh: Option<f32>, // Seconds between display refreshes, live
+    pub progress: Option<(usize, usize)>, // Offline: frames written out of how many
+    last: Option<Instant>,    // When the last frame was drawn (None after a pause)
+    second: Instant,          // When the current second started
+    in_second: u32,           // Frames drawn in it
+    slowest_in_second: f32,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            frames: 0,
+            fps: 0.0,
+            frame_time: 0.0,
+            slowest: 0.0,
+            dropped: 0,
+            refresh: None,
+            progress: None,
+            last: None,
+            second: Instant::now(),
+            in_second: 0,
+            slowest_in_second: 0.0,
+        }
+    }
+}
+
+impl Stats {
+    // Count a frame drawn
+    pub fn frame(&mut self) {
+        let now = Instant::now();
+        if let Some(last) = self.last {
+            self.frame_time = (now - last).as_secs_f32();
+            self.slowest_in_second = self.slowest_in_second.max(self.frame_time);
+            // Half a refresh late or more is a frame missed
+            if let Some(refresh) = self.refresh.filter(|&refresh| refresh > 0.0) {
+                self.dropped += ((self.frame_time / refresh).round() as u64).saturating_sub(1);
+            }
+        }
+        self.last = Some(now);
+        self.frames += 1;
+        self.in_second += 1;
+        let elapsed = (now - self.second).as_secs_f32();
+        if elapsed >= 1.0 {
+            self.fps = self.in_second as f32 / elapsed;
+            self.slowest = self.slowest_in_second;
+            self.second = now;
+            self.in_second = 0;
+            self.slowest_in_second = 0.0;
+        }
+    }
+
+    // No frame could be drawn (the window is minimized or hidden): the wait until the next one
+    // isn't frames dropped
+    pub fn pause(&mut self) {
+        self.last = None;
+    }
+}
+
+// The start of an HTTP request (its body, if any, is left unread)
+pub struct Head {
+    pub method: String,
+    pub path: String, // Without the query
+    pub headers: Vec<(String, String)>,
+}
+
+impl Head {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+// Read a request's line and headers from `reader`
+pub fn read_head(reader: &mut impl BufRead) -> Result<Head, String> {
+    let mut lines = Vec::new();
+    let mut read = 0;
+    loop {
+        let mut line = String::new();
+        read += (reader.by_ref())
+            .take((MAX_HEAD - read) as u64)
+            .read_line(&mut line)
+            .map_err(|err| err.to_string())?;
+        if !line.ends_with('\n') {
+            return Err("incomplete or oversized request".to_string());
+        }
+        let line = line.trim_end().to_string();
+        if line.is_empty() {
+            break;
+        }
+        lines.push(line);
+    }
+    let mut lines = lines.into_iter();
+    let request = lines.next().unwrap_or_default();
+    let mut words = request.split_whitespace();
+    let (Some(method), Some(target)) = (words.next(), words.next()) else {
+        return Err(format!("bad request line \"{}\"", request));
+    };
+    let headers = lines
+        .filter_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            Some((name.trim().to_string(), value.trim().to_string()))
+        })
+        .collect();
+    Ok(Head {
+        method: method.to_string(),
+        path: target.split('?').next().unwrap_or_default().to_string(),
+        headers,
+    })
+}
+
+// Listen on `port` and hand requests to the renderer through `sender`
+pub fn serve(port: u16, sender: Sender<Message>) -> Result<(), String> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+        .map_err(|err| format!("Failed to listen on port {}: {}", port, err))?;
+    println!("Monitoring on http://localhost:{}", port);
+    thread::Builder::new()
+        .name("http".to_string())
+        .spawn(move || {
+            let clients = Arc::new(AtomicUsize::new(0));
+            for stream in listener.incoming().flatten() {
+                if clients.fetch_add(1, Ordering::SeqCst) >= MAX_CLIENTS {
+                    clients.fetch_sub(1, Ordering::SeqCst);
+                    eprintln!("HTTP: over {} clients, turning one away", MAX_CLIENTS);
+                    continue;
+                }
+                let sender = sender.clone();
+                let served = clients.clone();
+                let spawned = thread::Builder::new()
+                    .name("http client".to_string())
+                    .spawn(move || {
+                        if let Err(err) = respond(stream, &sender) {
+                            eprintln!("HTTP: {}", err);
+                        }
+                        served.fetch_sub(1, Ordering::SeqCst);
+                    });
+                if let Err(err) = spawned {
+                    clients.fetch_sub(1, Ordering::SeqCst);
+                    eprintln!("HTTP: {}", err);
+                }
+            }
+        })
+        .map_err(|err| err.to_string())?;
+    Ok(())
+}
+
+// Answer one request, then close the connection
+fn respond(stream: TcpStream, sender: &Sender<Message>) -> Result<(), String> {
+    stream
+        .set_read_timeout(Some(READ_TIMEOUT))
+        .map_err(|err| err.to_string())?;
+    let head = read_head(&mut BufReader::new(&stream))?;
+    let endpoint = match head.path.as_str() {
+        "/frame.png" => Some(Endpoint::Frame),
+        "/stats.json" => Some(Endpoint::Stats),
+        "/params" => Some(Endpoint::Params),
+        "/metrics" => Some(Endpoint::Metrics),
+        _ => None,
+    };
+    let (status, response) = match (head.method.as_str(), endpoint) {
+        ("GET" | "HEAD", Some(endpoint)) => match ask(sender, endpoint) {
+            Ok(response) => ("200 OK", response),
+            Err(err) => ("500 Internal Server Error", text(err)),
+        },
+        ("GET" | "HEAD", None) if head.path == "/" => (
+            "200 OK",
+            text("/frame.png  /stats.json  /params  /metrics\n".to_string()),
+        ),
+        ("GET" | "HEAD", None) => ("404 Not Found", text(format!("No {}\n", head.path))),
+        _ => ("405 Method Not Allowed", text("Only GET\n".to_string())),
+    };
+
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+         Cache-Control: no-store\r\nAccess-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n",
+        status,
+        response.content_type,
+        response.body.len()
+    )
+    .map_err(|err| err.to_string())?;
+    if head.method != "HEAD" {
+        stream
+            .write_all(&response.body)
+            .map_err(|err| err.to_string())?;
+    }
+    Ok(())
+}
+
+fn ask(sender: &Sender<Message>, endpoint: Endpoint) -> Answer {
+    let (answer, receiver) = mpsc::channel();
+    sender
+        .send(Message::Http(endpoint, answer))
+        .map_err(|_| "the renderer has stopped".to_string())?;
+    receiver
+        .recv()
+        .map_err(|_| "the renderer has stopped".to_string())?
+}
+
+fn text(text: String) -> Response {
+    Response {
+        content_type: "text/plain; charset=utf-8",
+        body: text.into_bytes(),
+    }
+}
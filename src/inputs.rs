@@ -0,0 +1,157 @@
+// Optional external textures sampled by the shader: camera/video file and screen capture.
+// The backends are behind cargo features ("video", "screen-capture"); their settings are
+// always parsed so one config file works with any build. Capture is macOS (Metal) only.
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+use metal::{DeviceRef, TextureRef};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+// A texture fed from outside the renderer (Send so the renderer can move to a render thread)
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+pub trait TextureSource: Send {
+    // Upload the newest frame (if any); call once per frame
+    fn upload(&mut self, device: &DeviceRef);
+    fn texture(&self) -> &TextureRef;
+}
+
+// Where video frames come from
+#[derive(Clone, Debug)]
+pub enum VideoSourceKind {
+    Camera { device: Option<String> }, // None picks the system default camera
+    File(PathBuf),
+}
+
+// Video input settings ([video] in the config file)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VideoConfig {
+    pub source: Option<String>, // "camera" or a path to a video file
+    pub device: Option<String>, // Part of the camera name; None picks the default camera
+    pub mix: f32,               // 0 = plain material, 1 = video fully replaces the object color
+}
+
+impl Default for VideoConfig {
+    fn default() -> Self {
+        Self {
+            source: None,
+            device: None,
+            mix: 1.0,
+        }
+    }
+}
+
+impl VideoConfig {
+    pub fn kind(&self) -> Option<VideoSourceKind> {
+        match self.source.as_deref() {
+            Some("camera") => Some(VideoSourceKind::Camera {
+                device: self.device.clone(),
+            }),
+            Some(path) if !path.is_empty() => Some(VideoSourceKind::File(PathBuf::from(path))),
+            _ => None,
+        }
+    }
+}
+
+// What to capture
+#[derive(Clone, Debug)]
+pub enum ScreenTarget {
+    Display(usize), // Index into the shareable display list (0 = main display)
+    Window(String), // Part of the window title or owning application name
+}
+
+// Screen capture settings ([screen] in the config file)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScreenConfig {
+    pub target: Option<String>, // "display", "display:<index>" or "window:<title>"
+    pub mix: f32,               // 0 = off, 1 = textured objects fully refract the captured screen
+}
+
+impl Default for ScreenConfig {
+    fn default() -> Self {
+        Self {
+            target: None,
+            mix: 1.0,
+        }
+    }
+}
+
+impl ScreenConfig {
+    pub fn target(&self) -> Option<ScreenTarget> {
+        let value = self.target.as_deref()?;
+        let target = parse_target(value);
+        if target.is_none() {
+            eprintln!("Ignoring invalid screen capture target \"{}\"", value);
+        }
+        target
+    }
+}
+
+pub fn parse_target(value: &str) -> Option<ScreenTarget> {
+    match value.split_once(':') {
+        Some(("display", index)) => index.parse().ok().map(ScreenTarget::Display),
+        Some(("window", title)) if !title.is_empty() => {
+            Some(ScreenTarget::Window(title.to_string()))
+        }
+        None if value == "display" => Some(ScreenTarget::Display(0)),
+        _ => None,
+    }
+}
+
+// Start the configured video input, if any
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+pub fn open_video(device: &DeviceRef, config: &VideoConfig) -> Option<Box<dyn TextureSource>> {
+    let kind = config.kind()?;
+    #[cfg(all(target_os = "macos", feature = "video"))]
+    {
+        objc::rc::autoreleasepool(|| match crate::video::VideoInput::new(device, &kind) {
+            Ok(video) => Some(Box::new(video) as Box<dyn TextureSource>),
+            Err(err) => {
+                eprintln!("Video input disabled: {}", err);
+                None
+            }
+        })
+    }
+    #[cfg(not(feature = "video"))]
+    {
+        let _ = (device, kind);
+        eprintln!("Video input disabled: built without the \"video\" feature");
+        None
+    }
+    #[cfg(all(target_os = "ios", feature = "video"))]
+    {
+        let _ = (device, kind);
+        eprintln!("Video input disabled: not supported on iOS");
+        None
+    }
+}
+
+// Start the configured screen capture, if any
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+pub fn open_screen(device: &DeviceRef, config: &ScreenConfig) -> Option<Box<dyn TextureSource>> {
+    let target = config.target()?;
+    #[cfg(all(target_os = "macos", feature = "screen-capture"))]
+    {
+        objc::rc::autoreleasepool(
+            || match crate::screen::ScreenCapture::new(device, &target) {
+                Ok(screen) => Some(Box::new(screen) as Box<dyn TextureSource>),
+                Err(err) => {
+                    eprintln!("Screen capture disabled: {}", err);
+                    None
+                }
+            },
+        )
+    }
+    #[cfg(not(feature = "screen-capture"))]
+    {
+        let _ = (device, target);
+        eprintln!("Screen capture disabled: built without the \"screen-capture\" feature");
+        None
+    }
+    #[cfg(all(target_os = "ios", feature = "screen-capture"))]
+    {
+        let _ = (device, target);
+        eprintln!("Screen capture disabled: not supported on iOS");
+        None
+    }
+}
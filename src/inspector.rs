@@ -0,0 +1,489 @@
+// Property inspector: a panel in the top left corner listing the selected object's values
+// (shape, transform, CSG operation and blend, material), shown and hidden with I. Dragging a
+// value left or right changes it, and clicking it without dragging lets you type a new one
+// (Enter sets it, Escape cancels). Clicking the operation cycles through the CSG operations,
+// and clicking "textured" toggles it.
+//
+// Values are the object's parameters (see scene.rs), so only named objects can be edited, and
+// changes reach the GPU through the uniforms like a gizmo drag. Changing the operation or
+// texturing regenerates the shader. The panel is drawn with the gizmos' overlay triangles,
+// its text in a built-in 3x5 pixel font (also used by the command palette and the shader
+// editor, see palette.rs and editor.rs).
+use crate::gizmo::{OverlayVertex, View};
+use crate::scene::{CsgOp, Scene, SceneObject, Shape};
+use cgmath::Vector2;
+
+const MARGIN: f32 = 10.0; // Pixels from the window's corner
+pub const PADDING: f32 = 6.0;
+pub const ROW_HEIGHT: f32 = 16.0;
+pub const DOT: f32 = 2.0; // Pixels per font pixel
+pub const ADVANCE: f32 = 4.0 * DOT; // Glyphs are 3 dots wide with a dot between
+const LABEL_CHARS: usize = 12;
+const VALUE_CHARS: usize = 15;
+const DRAG_THRESHOLD: f32 = 3.0; // Pixels the mouse moves before a click becomes a drag
+
+// Object properties shown, in order, when the object's shape has them
+const SHAPE: [&str; 10] = [
+    "radius",
+    "size",
+    "normal",
+    "height",
+    "exaggeration",
+    "c",
+    "w",
+    "angle",
+    "depth",
+    "rounding",
+];
+const TRANSFORM: [&str; 3] = ["position", "rotation", "scale"];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FieldKind {
+    // Component `component` of the parameter `target`
+    Value {
+        target: String,
+        component: usize,
+        value: f32,
+    },
+    Op(CsgOp),
+    Textured(bool),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Field {
+    pub label: String,
+    pub kind: FieldKind,
+}
+
+impl Field {
+    fn text(&self) -> String {
+        match &self.kind {
+            FieldKind::Value { value, .. } => format!("{:.3}", value),
+            FieldKind::Op(op) => op_name(op).to_string(),
+            FieldKind::Textured(on) => (if *on { "on" } else { "off" }).to_string(),
+        }
+    }
+}
+
+// A value being dragged
+#[derive(Clone, Debug)]
+pub struct Slide {
+    pub field: usize,
+    pub target: String,
+    pub component: usize,
+    pub from: f32,       // Mouse x in pixels where the drag started
+    pub start: Vec<f32>, // The parameter before the drag
+    pub moved: bool,     // Still a click until the mouse moves a few pixels
+}
+
+impl Slide {
+    // Value for the mouse at `x` pixels, or None while it is still a click
+    pub fn value(&mut self, x: f32) -> Option<f32> {
+        self.moved |= (x - self.from).abs() >= DRAG_THRESHOLD;
+        let start = *self.start.get(self.component)?;
+        self.moved
+            .then(|| limit(&self.target, start + (x - self.from) * step(&self.target)))
+    }
+}
+
+// A value being typed in
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub field: usize,
+    pub text: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Inspector {
+    pub shown: bool,
+    pub title: String,
+    pub fields: Vec<Field>, // Of the selected object, refreshed every frame
+    pub hovered: Option<usize>,
+    pub slide: Option<Slide>,
+    pub entry: Option<Entry>,
+}
+
+impl Inspector {
+    // Read the fields of `object` (None when nothing is selected)
+    pub fn refresh(&mut self, scene: &Scene, object: Option<&SceneObject>) {
+        self.fields.clear();
+        let Some(object) = object else {
+            self.title.clear();
+            return;
+        };
+        if object.name.is_empty() {
+            self.title = format!("{} - unnamed", shape_name(&object.shape));
+            return;
+        }
+        self.title = format!("{} - {}", object.name, shape_name(&object.shape));
+        for property in SHAPE.iter().chain(&TRANSFORM) {
+            self.add_values(scene, &object.name, property, "xyz");
+        }
+        self.fields.push(Field {
+            label: "op".to_string(),
+            kind: FieldKind::Op(object.op),
+        });
+        self.add_values(scene, &object.name, "k", "");
+        self.add_values(scene, &object.name, "color", "rgb");
+        self.add_values(scene, &object.name, "bump", "");
+        self.add_values(scene, &object.name, "noise", "");
+        self.add_values(scene, &object.name, "film", "");
+        self.add_values(scene, &object.name, "anisotropy", "");
+        self.add_values(scene, &object.name, "emission", "");
+        self.fields.push(Field {
+            label: "textured".to_string(),
+            kind: FieldKind::Textured(object.textured),
+        });
+    }
+
+    fn add_values(&mut self, scene: &Scene, name: &str, property: &str, components: &str) {
+        let target = format!("{}.{}", name, property);
+        let Some(values) = scene.parameter(&target) else {
+            return;
+        };
+        for (component, &value) in values.iter().enumerate() {
+            let label = match components.chars().nth(component) {
+                Some(letter) if values.len() > 1 => format!("{} {}", property, letter),
+                _ => property.to_string(),
+            };
+            self.fields.push(Field {
+                label,
+                kind: FieldKind::Value {
+                    target: target.clone(),
+                    component,
+                    value,
+                },
+            });
+        }
+    }
+
+    // Whether `mouse` (normalized, y up) is over the panel
+    pub fn contains(&self, view: &View, mouse: Vector2<f32>) -> bool {
+        if !self.shown || self.title.is_empty() {
+            return false;
+        }
+        let (x, y) = pixels(view, mouse);
+        let (width, height) = self.size();
+        (MARGIN..MARGIN + width).contains(&x) && (MARGIN..MARGIN + height).contains(&y)
+    }
+
+    // Field under `mouse`, if any
+    pub fn field_at(&self, view: &View, mouse: Vector2<f32>) -> Option<usize> {
+        if !self.contains(view, mouse) {
+            return None;
+        }
+        let (_, y) = pixels(view, mouse);
+        let row = ((y - MARGIN - PADDING) / ROW_HEIGHT).floor();
+        // Row 0 is the title
+        (row >= 1.0)
+            .then(|| row as usize - 1)
+            .filter(|&field| field < self.fields.len())
+    }
+
+    fn size(&self) -> (f32, f32) {
+        let width = PADDING * 2.0 + (LABEL_CHARS + VALUE_CHARS) as f32 * ADVANCE;
+        let height = PADDING * 2.0 + (self.fields.len() + 1) as f32 * ROW_HEIGHT;
+        (width, height)
+    }
+
+    pub fn vertices(&self, view: &View) -> Vec<OverlayVertex> {
+        let mut out = Vec::new();
+        if !self.shown || self.title.is_empty() {
+            return out;
+        }
+        let (width, height) = self.size();
+        rectangle(
+            &mut out,
+            view,
+            [MARGIN, MARGIN],
+            [width, height],
+            [0.05, 0.05, 0.08, 0.75],
+        );
+        let left = MARGIN + PADDING;
+        let row_top = |row: usize| MARGIN + PADDING + row as f32 * ROW_HEIGHT;
+        let text_top = |row: usize| row_top(row) + (ROW_HEIGHT - 5.0 * DOT) / 2.0;
+        text(
+            &mut out,
+            view,
+            [left, text_top(0)],
+            &self.title,
+            LABEL_CHARS + VALUE_CHARS,
+            [1.0, 0.8, 0.2, 1.0],
+        );
+        let active = self
+            .slide
+            .as_ref()
+            .map(|slide| slide.field)
+            .or(self.entry.as_ref().map(|entry| entry.field));
+        for (index, field) in self.fields.iter().enumerate() {
+            let row = index + 1;
+            if active.or(self.hovered) == Some(index) {
+                rectangle(
+                    &mut out,
+                    view,
+                    [MARGIN, row_top(row)],
+                    [width, ROW_HEIGHT],
+                    [1.0, 1.0, 1.0, 0.12],
+                );
+            }
+            let label_color = [0.7, 0.7, 0.75, 1.0];
+            text(
+                &mut out,
+                view,
+                [left, text_top(row)],
+                &field.label,
+                LABEL_CHARS,
+                label_color,
+            );
+            let (value, color) = match &self.entry {
+                Some(entry) if entry.field == index => {
+                    (format!("{}_", entry.text), [1.0, 0.8, 0.2, 1.0])
+                }
+                _ => (field.text(), [1.0, 1.0, 1.0, 1.0]),
+            };
+            let value_left = left + LABEL_CHARS as f32 * ADVANCE;
+            text(
+                &mut out,
+                view,
+                [value_left, text_top(row)],
+                &value,
+                VALUE_CHARS,
+                color,
+            );
+        }
+        out
+    }
+}
+
+// A line of text on a dark panel in the top right corner (the path tracer's sample count)
+pub fn status(view: &View, line: &str) -> Vec<OverlayVertex> {
+    let mut out = Vec::new();
+    let width = PADDING * 2.0 + line.chars().count() as f32 * ADVANCE;
+    let left = view.width as f32 - MARGIN - width;
+    rectangle(
+        &mut out,
+        view,
+        [left, MARGIN],
+        [width, ROW_HEIGHT + PADDING * 2.0],
+        [0.05, 0.05, 0.08, 0.75],
+    );
+    let top = MARGIN + PADDING + (ROW_HEIGHT - 5.0 * DOT) / 2.0;
+    text(
+        &mut out,
+        view,
+        [left + PADDING, top],
+        line,
+        usize::MAX,
+        [1.0, 1.0, 1.0, 1.0],
+    );
+    out
+}
+
+// The operation after `op` when clicking through them; smooth ones start with a 0.3 blend
+pub fn next_op(op: CsgOp) -> CsgOp {
+    const BLEND: f32 = 0.3;
+    match op {
+        CsgOp::Union => CsgOp::SmoothUnion { k: BLEND },
+        CsgOp::SmoothUnion { .. } => CsgOp::Subtract,
+        CsgOp::Subtract => CsgOp::SmoothSubtract { k: BLEND },
+        CsgOp::SmoothSubtract { .. } => CsgOp::Intersect,
+        CsgOp::Intersect => CsgOp::Union,
+    }
+}
+
+// Keep values in range: colors 0 to 1, sizes and blends above zero
+pub fn limit(target: &str, value: f32) -> f32 {
+    match property(target) {
+        "color" => value.clamp(0.0, 1.0),
+        "radius" | "size" | "scale" | "k" | "depth" => value.max(0.001),
+        "rounding" | "exaggeration" => value.max(0.0),
+        _ => value,
+    }
+}
+
+// Change per pixel dragged
+fn step(target: &str) -> f32 {
+    match property(target) {
+        "rotation" | "angle" => 0.5, // Degrees
+        "color" => 0.005,
+        _ => 0.01,
+    }
+}
+
+fn property(target: &str) -> &str {
+    target
+        .rsplit_once('.')
+        .map_or(target, |(_, property)| property)
+}
+
+fn shape_name(shape: &Shape) -> &'static str {
+    match shape {
+        Shape::Sphere { .. } => "sphere",
+        Shape::Box { .. } => "box",
+        Shape::Plane { .. } => "plane",
+        Shape::Capsule { .. } => "capsule",
+        Shape::Tesseract { .. } => "tesseract",
+        Shape::Hypersphere { .. } => "hypersphere",
+        Shape::Julia { .. } => "julia",
+        Shape::Extrusion { .. } => "extrusion",
+        Shape::Revolution { .. } => "revolution",
+        Shape::Plant { .. } => "plant",
+        Shape::Cloud { .. } => "cloud",
+        Shape::Heightfield { .. } => "heightfield",
+    }
+}
+
+fn op_name(op: &CsgOp) -> &'static str {
+    match op {
+        CsgOp::Union => "union",
+        CsgOp::SmoothUnion { .. } => "smooth union",
+        CsgOp::Subtract => "subtract",
+        CsgOp::SmoothSubtract { .. } => "smooth subtract",
+        CsgOp::Intersect => "intersect",
+    }
+}
+
+// The app's normalized mouse position (0..1, y up) in pixels from the top left
+fn pixels(view: &View, mouse: Vector2<f32>) -> (f32, f32) {
+    (
+        mouse.x * view.width as f32,
+        (1.0 - mouse.y) * view.height as f32,
+    )
+}
+
+// Rectangle from `at` (pixels from the top left)
+pub fn rectangle(
+    out: &mut Vec<OverlayVertex>,
+    view: &View,
+    at: [f32; 2],
+    size: [f32; 2],
+    color: [f32; 4],
+) {
+    let ndc = |x: f32, y: f32| {
+        [
+            x / view.width.max(1) as f32 * 2.0 - 1.0,
+            1.0 - y / view.height.max(1) as f32 * 2.0,
+        ]
+    };
+    let [x0, y0] = at;
+    let [x1, y1] = [x0 + size[0], y0 + size[1]];
+    for position in [
+        ndc(x0, y0),
+        ndc(x1, y0),
+        ndc(x1, y1),
+        ndc(x0, y0),
+        ndc(x1, y1),
+        ndc(x0, y1),
+    ] {
+        out.push(OverlayVertex { position, color });
+    }
+}
+
+// `text` from `at` (its top left, in pixels), cut to `max_chars`. Each run of lit dots in a
+// glyph's row is one rectangle.
+pub fn text(
+    out: &mut Vec<OverlayVertex>,
+    view: &View,
+    at: [f32; 2],
+    text: &str,
+    max_chars: usize,
+    color: [f32; 4],
+) {
+    for (index, c) in text.chars().take(max_chars).enumerate() {
+        let left = at[0] + index as f32 * ADVANCE;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            let top = at[1] + row as f32 * DOT;
+            let mut column = 0;
+            while column < 3 {
+                if bits & (0b100 >> column) == 0 {
+                    column += 1;
+                    continue;
+                }
+                let start = column;
+                while column < 3 && bits & (0b100 >> column) != 0 {
+                    column += 1;
+                }
+                rectangle(
+                    out,
+                    view,
+                    [left + start as f32 * DOT, top],
+                    [(column - start) as f32 * DOT, DOT],
+                    color,
+                );
+            }
+        }
+    }
+}
+
+// Rows of a 3x5 glyph, top first, the high bit on the left. Letters are all capitals.
+pub fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_lowercase() {
+        ' ' => [0b000, 0b000, 0b000, 0b000, 0b000],
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'a' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'b' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'c' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'd' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'e' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'f' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'g' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'h' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'i' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'j' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'k' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'l' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'm' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'n' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'o' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'p' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'r' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        's' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        't' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'u' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'v' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'w' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'x' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '>' => [0b100, 0b010, 0b001, 0b010, 0b100],
+        '[' => [0b110, 0b100, 0b100, 0b100, 0b110],
+        ']' => [0b011, 0b001, 0b001, 0b001, 0b011],
+        '(' => [0b010, 0b100, 0b100, 0b100, 0b010],
+        ')' => [0b010, 0b001, 0b001, 0b001, 0b010],
+        '{' => [0b011, 0b010, 0b100, 0b010, 0b011],
+        '}' => [0b110, 0b010, 0b001, 0b010, 0b110],
+        '<' => [0b001, 0b010, 0b100, 0b010, 0b001],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        ';' => [0b000, 0b010, 0b000, 0b010, 0b100],
+        '*' => [0b000, 0b101, 0b010, 0b101, 0b000],
+        '&' => [0b010, 0b101, 0b010, 0b101, 0b011],
+        '|' => [0b010, 0b010, 0b010, 0b010, 0b010],
+        '!' => [0b010, 0b010, 0b010, 0b000, 0b010],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        '#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+        '"' => [0b101, 0b101, 0b000, 0b000, 0b000],
+        '\'' => [0b010, 0b010, 0b000, 0b000, 0b000],
+        '^' => [0b010, 0b101, 0b000, 0b000, 0b000],
+        '~' => [0b000, 0b011, 0b110, 0b000, 0b000],
+        '@' => [0b111, 0b101, 0b111, 0b100, 0b111],
+        '\\' => [0b100, 0b100, 0b010, 0b001, 0b001],
+        _ => [0b111, 0b001, 0b010, 0b000, 0b010], // ?
+    }
+}
@@ -0,0 +1,342 @@
+// Keyboard shortcuts. Defaults can be remapped from a TOML file (path set in the config):
+//
+//   reset_camera = "Space"
+//   export_shaders = "E"
+//   cycle_quality = "Q"
+//   tap_tempo = "T"
+//   save_workspace = "S"
+//   cycle_gizmo = "G"
+//   cycle_brush = "B"
+//   brush_smaller = "LBracket"
+//   brush_larger = "RBracket"
+//   blend_less = "Minus"
+//   blend_more = "Equals"
+//   delete_selected = "Delete"
+//   move_earlier = "Comma"
+//   move_later = "Period"
+//   toggle_guides = "H"
+//   toggle_snap = "N"
+//   toggle_inspector = "I"
+//   toggle_path_tracing = "P"
+//   toggle_denoise = "O"
+//   dump_passes = "F12"
+//
+// Key names are winit's VirtualKeyCode names. The number keys 1-9 fire the scene's cues, and
+// with Cmd (Ctrl outside macOS) Z / Shift+Z undo and redo edits, D duplicates the selected
+// object, [ and ] move it earlier or later in its group as move_earlier and move_later do,
+// S saves the scene file, C / V copy
+// and open shared views (see share.rs), K opens the command palette (see palette.rs), where
+// Up and Down pick a command, and E shows the shader editor (see editor.rs), where Enter
+// applies the shader and the arrows, Page Up/Down, Home and End move the cursor.
+use metal_raymarcher::editor::Motion;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use winit::event::{ModifiersState, VirtualKeyCode};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    ResetCamera,
+    ExportShaders,
+    CycleQuality,
+    TapTempo,
+    SaveWorkspace,
+    CycleGizmo,
+    CycleBrush,
+    BrushSmaller,
+    BrushLarger,
+    BlendLess,
+    BlendMore,
+    DeleteSelected,
+    MoveEarlier,
+    MoveLater,
+    ToggleGuides,
+    ToggleSnap,
+    ToggleInspector,
+    TogglePathTracing,
+    ToggleDenoise,
+    DumpPasses,
+    #[serde(skip)]
+    Cue(usize), // Index into the scene's cues
+    #[serde(skip)]
+    Undo,
+    #[serde(skip)]
+    Redo,
+    #[serde(skip)]
+    Duplicate,
+    #[serde(skip)]
+    SaveScene,
+    #[serde(skip)]
+    CopyView,
+    #[serde(skip)]
+    PasteView,
+    #[serde(skip)]
+    OpenPalette,
+    #[serde(skip)]
+    ToggleEditor,
+    #[serde(skip)]
+    ApplyShader,
+    #[serde(skip)]
+    ToggleGallery,
+    #[serde(skip)]
+    Move(Motion), // The palette's or gallery's highlight, or the editor's cursor
+}
+
+impl Action {
+    // Remappable actions
+    pub const ALL: [Action; 20] = [
+        Action::ResetCamera,
+        Action::ExportShaders,
+        Action::CycleQuality,
+        Action::TapTempo,
+        Action::SaveWorkspace,
+        Action::CycleGizmo,
+        Action::CycleBrush,
+        Action::BrushSmaller,
+        Action::BrushLarger,
+        Action::BlendLess,
+        Action::BlendMore,
+        Action::DeleteSelected,
+        Action::MoveEarlier,
+        Action::MoveLater,
+        Action::ToggleGuides,
+        Action::ToggleSnap,
+        Action::ToggleInspector,
+        Action::TogglePathTracing,
+        Action::ToggleDenoise,
+        Action::DumpPasses,
+    ];
+
+    // Commands on Cmd, listed in the command palette after the remappable ones
+    pub const EDITING: [Action; 9] = [
+        Action::Undo,
+        Action::Redo,
+        Action::Duplicate,
+        Action::SaveScene,
+        Action::CopyView,
+        Action::PasteView,
+        Action::ToggleEditor,
+        Action::ApplyShader,
+        Action::ToggleGallery,
+    ];
+
+    fn default_key(self) -> VirtualKeyCode {
+        match self {
+            Action::ResetCamera => VirtualKeyCode::Space,
+            Action::ExportShaders => VirtualKeyCode::E,
+            Action::CycleQuality => VirtualKeyCode::Q,
+            Action::TapTempo => VirtualKeyCode::T,
+            Action::SaveWorkspace => VirtualKeyCode::S,
+            Action::CycleGizmo => VirtualKeyCode::G,
+            Action::CycleBrush => VirtualKeyCode::B,
+            Action::BrushSmaller => VirtualKeyCode::LBracket,
+            Action::BrushLarger => VirtualKeyCode::RBracket,
+            Action::BlendLess => VirtualKeyCode::Minus,
+            Action::BlendMore => VirtualKeyCode::Equals,
+            Action::DeleteSelected => VirtualKeyCode::Delete,
+            Action::MoveEarlier => VirtualKeyCode::Comma,
+            Action::MoveLater => VirtualKeyCode::Period,
+            Action::ToggleGuides => VirtualKeyCode::H,
+            Action::ToggleSnap => VirtualKeyCode::N,
+            Action::ToggleInspector => VirtualKeyCode::I,
+            Action::TogglePathTracing => VirtualKeyCode::P,
+            Action::ToggleDenoise => VirtualKeyCode::O,
+            Action::DumpPasses => VirtualKeyCode::F12,
+            Action::Cue(_) => unreachable!("cues are on the number keys"),
+            Action::Undo
+            | Action::Redo
+            | Action::Duplicate
+            | Action::SaveScene
+            | Action::CopyView
+            | Action::PasteView
+            | Action::OpenPalette
+            | Action::ToggleEditor
+            | Action::ApplyShader
+            | Action::ToggleGallery => unreachable!("editing commands are on Cmd"),
+            Action::Move(_) => unreachable!("moving is on the arrows"),
+        }
+    }
+
+    // Its name in the command palette
+    pub fn label(self) -> &'static str {
+        match self {
+            Action::ResetCamera => "Reset camera",
+            Action::ExportShaders => "Export scene shaders",
+            Action::CycleQuality => "Next quality preset",
+            Action::TapTempo => "Tap tempo",
+            Action::SaveWorkspace => "Save workspace",
+            Action::CycleGizmo => "Next gizmo mode",
+            Action::CycleBrush => "Next brush mode",
+            Action::BrushSmaller => "Brush smaller",
+            Action::BrushLarger => "Brush larger",
+            Action::BlendLess => "Blend less",
+            Action::BlendMore => "Blend more",
+            Action::DeleteSelected => "Delete selected",
+            Action::ToggleGuides => "Toggle guides",
+            Action::ToggleSnap => "Toggle surface snapping",
+            Action::ToggleInspector => "Toggle inspector",
+            Action::TogglePathTracing => "Toggle path tracing",
+            Action::ToggleDenoise => "Toggle denoising",
+            Action::DumpPasses => "Dump render passes",
+            Action::Cue(_) => "Fire cue",
+            Action::Undo => "Undo",
+            Action::Redo => "Redo",
+            Action::Duplicate => "Duplicate selected",
+            Action::MoveEarlier => "Move selected earlier",
+            Action::MoveLater => "Move selected later",
+            Action::SaveScene => "Save scene",
+            Action::CopyView => "Copy view link",
+            Action::PasteView => "Open view link",
+            Action::OpenPalette => "Command palette",
+            Action::ToggleEditor => "Shader editor",
+            Action::ApplyShader => "Apply edited shader",
+            Action::ToggleGallery => "Scene gallery",
+            Action::Move(_) => "Move",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Keybindings {
+    keys: HashMap<VirtualKeyCode, Action>,
+}
+
+impl Default for Keybindings {
+    fn default() -> Self {
+        Self {
+            keys: Action::ALL
+                .iter()
+                .map(|&action| (action.default_key(), action))
+                .collect(),
+        }
+    }
+}
+
+impl Keybindings {
+    // Defaults, with any bindings from `path` replacing the default key of their action
+    pub fn load(path: Option<&Path>) -> Self {
+        let mut bindings = Self::default();
+        let Some(path) = path else {
+            return bindings;
+        };
+
+        let overrides = std::fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|text| {
+                toml::from_str::<HashMap<Action, VirtualKeyCode>>(&text)
+                    .map_err(|err| err.to_string())
+            });
+        match overrides {
+            Ok(overrides) => {
+                for (action, key) in overrides {
+                    bindings.keys.retain(|_, bound| *bound != action);
+                    bindings.keys.insert(key, action);
+                }
+            }
+            Err(err) => eprintln!("Ignoring keybindings file {}: {}", path.display(), err),
+        }
+        bindings
+    }
+
+    // The keys that run `action`, e.g. "Cmd+Shift+Z", or None when no key does
+    pub fn shortcut(&self, action: Action) -> Option<String> {
+        let command = match cfg!(any(target_os = "macos", target_os = "ios")) {
+            true => "Cmd",
+            false => "Ctrl",
+        };
+        let key = match action {
+            Action::Undo => "Z",
+            Action::Redo => "Shift+Z",
+            Action::Duplicate => "D",
+            Action::MoveEarlier => "[",
+            Action::MoveLater => "]",
+            Action::SaveScene => "S",
+            Action::CopyView => "C",
+            Action::PasteView => "V",
+            Action::OpenPalette => "K",
+            Action::ToggleEditor => "E",
+            Action::ApplyShader => "Enter",
+            Action::ToggleGallery => "O",
+            Action::Cue(index) if index < 9 => return Some((index + 1).to_string()),
+            _ => {
+                let (key, _) = self.keys.iter().find(|(_, &bound)| bound == action)?;
+                return Some(key_name(*key));
+            }
+        };
+        Some(format!("{}+{}", command, key))
+    }
+
+    pub fn action(&self, key: VirtualKeyCode, modifiers: ModifiersState) -> Option<Action> {
+        if command(modifiers) {
+            return match key {
+                VirtualKeyCode::Z if modifiers.shift() => Some(Action::Redo),
+                VirtualKeyCode::Z => Some(Action::Undo),
+                VirtualKeyCode::D => Some(Action::Duplicate),
+                VirtualKeyCode::LBracket => Some(Action::MoveEarlier),
+                VirtualKeyCode::RBracket => Some(Action::MoveLater),
+                VirtualKeyCode::S => Some(Action::SaveScene),
+                VirtualKeyCode::C => Some(Action::CopyView),
+                VirtualKeyCode::V => Some(Action::PasteView),
+                VirtualKeyCode::K => Some(Action::OpenPalette),
+                VirtualKeyCode::E => Some(Action::ToggleEditor),
+                VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => Some(Action::ApplyShader),
+                VirtualKeyCode::O => Some(Action::ToggleGallery),
+                _ => None,
+            };
+        }
+        let motion = match key {
+            VirtualKeyCode::Up => Some(Motion::Up),
+            VirtualKeyCode::Down => Some(Motion::Down),
+            VirtualKeyCode::Left => Some(Motion::Left),
+            VirtualKeyCode::Right => Some(Motion::Right),
+            VirtualKeyCode::PageUp => Some(Motion::PageUp),
+            VirtualKeyCode::PageDown => Some(Motion::PageDown),
+            VirtualKeyCode::Home => Some(Motion::Home),
+            VirtualKeyCode::End => Some(Motion::End),
+            _ => None,
+        };
+        if let Some(motion) = motion {
+            return Some(Action::Move(motion));
+        }
+        const CUE_KEYS: [VirtualKeyCode; 9] = [
+            VirtualKeyCode::Key1,
+            VirtualKeyCode::Key2,
+            VirtualKeyCode::Key3,
+            VirtualKeyCode::Key4,
+            VirtualKeyCode::Key5,
+            VirtualKeyCode::Key6,
+            VirtualKeyCode::Key7,
+            VirtualKeyCode::Key8,
+            VirtualKeyCode::Key9,
+        ];
+        self.keys.get(&key).copied().or_else(|| {
+            CUE_KEYS
+                .iter()
+                .position(|&cue_key| cue_key == key)
+                .map(Action::Cue)
+        })
+    }
+}
+
+// Cmd on Apple platforms, Ctrl elsewhere
+pub fn command(modifiers: ModifiersState) -> bool {
+    if cfg!(any(target_os = "macos", target_os = "ios")) {
+        modifiers.logo()
+    } else {
+        modifiers.ctrl()
+    }
+}
+
+// How a key is shown in the command palette: its name, or the character it types
+fn key_name(key: VirtualKeyCode) -> String {
+    match key {
+        VirtualKeyCode::LBracket => "[".to_string(),
+        VirtualKeyCode::RBracket => "]".to_string(),
+        VirtualKeyCode::Minus => "-".to_string(),
+        VirtualKeyCode::Equals => "=".to_string(),
+        VirtualKeyCode::Comma => ",".to_string(),
+        VirtualKeyCode::Period => ".".to_string(),
+        key => format!("{:?}", key),
+    }
+}
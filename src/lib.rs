@@ -0,0 +1,77 @@
+// Metal raymarcher as a library: build a `Raymarcher` for a scene and draw it into your own
+// textures, without a window or event loop:
+//
+//   let mut raymarcher = Raymarcher::new(&device, &Scene::default(), MTLPixelFormat::BGRA8Unorm)?;
+//   raymarcher.render_into(&texture, &Camera::default(), time);
+//
+// or encode it into an existing command buffer with `Raymarcher::encode`. `MetalLayer`
+// attaches a CAMetalLayer to any window that provides a raw window handle.
+// With the "wgpu" feature, `WgpuRaymarcher` offers the same on any wgpu device.
+pub mod adaptive;
+pub mod animation;
+pub mod audio;
+#[cfg(feature = "audio")]
+pub mod audio_input;
+pub mod brush;
+pub mod camera;
+pub mod cells;
+pub mod city;
+pub mod clock;
+pub mod cloud;
+pub mod codegen;
+#[cfg(target_os = "macos")]
+pub mod device_watch;
+pub mod edges;
+pub mod editor;
+pub mod export;
+pub mod expression;
+#[cfg(all(
+    target_os = "macos",
+    any(feature = "video", feature = "screen-capture")
+))]
+mod frames;
+pub mod gizmo;
+pub mod heightfield;
+pub mod history;
+pub mod inputs;
+pub mod inspector;
+pub mod mesh;
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+pub mod metal_layer;
+pub mod noise;
+pub mod normal_map;
+pub mod palette;
+pub mod picking;
+pub mod plant;
+pub mod quality;
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+pub mod raymarcher;
+pub mod scene;
+#[cfg(all(target_os = "macos", feature = "screen-capture"))]
+pub mod screen;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod sequence;
+pub mod shader_test;
+pub mod shafts;
+pub mod sun;
+pub mod svg;
+pub mod toon;
+pub mod tween;
+mod uniforms;
+#[cfg(all(target_os = "macos", feature = "video"))]
+pub mod video;
+pub mod volume;
+#[cfg(feature = "wgpu")]
+pub mod wgpu_raymarcher;
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+pub mod wgsl;
+
+pub use camera::Camera;
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+pub use metal_layer::MetalLayer;
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+pub use raymarcher::Raymarcher;
+pub use scene::Scene;
+#[cfg(feature = "wgpu")]
+pub use wgpu_raymarcher::WgpuRaymarcher;
@@ -0,0 +1,134 @@
+// Livecoding from another machine (--livecode ADDRESS): shader source pushed over TCP replaces
+// the running shader once it has built and drawn a test frame (see Backend::set_shader); while
+// it builds, and if it fails, the current one carries on. Pushes come either way on one port:
+//
+//   plain TCP   send the whole template and close the sending side, e.g.
+//                 nc -N performance-box 7000 < shader.metal
+//               and get one line back: "ok", or "error: " and what went wrong
+//   WebSocket   ws://ADDRESS, each text message a whole template, answered with {"ok": true}
+//               or {"error": "..."}, for editors that push on every save
+//
+// A template is a shader like --shader's, MSL or WGSL, with "// @scene@" where the scene code
+// goes (Metal only). Given a port alone the server listens on this machine only; 0.0.0.0:PORT
+// takes pushes from other machines, and anyone who can reach it can replace the shader, so keep
+// it to a trusted network. At most MAX_CLIENTS are served at once, and one that sends nothing
+// for a while is dropped: a plain push after READ_TIMEOUT, a WebSocket after IDLE_TIMEOUT.
+use crate::json::Json;
+use crate::render_thread::Message;
+use crate::websocket::WebSocket;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const MAX_SOURCE: u64 = 1 << 20;
+const MAX_CLIENTS: usize = 8;
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+const IDLE_TIMEOUT: Duration = Duration::from_secs(600); // Between an editor's saves
+
+// ADDRESS for --livecode and the config: a port alone (on this machine only), e.g. 7000, or
+// an address and port, e.g. 0.0.0.0:7000
+pub fn address(text: &str) -> Result<SocketAddr, String> {
+    if let Ok(port) = text.trim().parse::<u16>() {
+        return Ok(SocketAddr::from((Ipv4Addr::LOCALHOST, port)));
+    }
+    text.trim().parse().map_err(|_| {
+        "expected a port, e.g. 7000, or an address and port, e.g. 0.0.0.0:7000".to_string()
+    })
+}
+
+// Listen on `address` and hand pushed shaders to the render thread through `sender`
+pub fn serve(address: SocketAddr, sender: Sender<Message>) -> Result<(), String> {
+    let listener = TcpListener::bind(address)
+        .map_err(|err| format!("Failed to listen on {}: {}", address, err))?;
+    println!("Taking shader pushes on {}", address);
+    thread::Builder::new()
+        .name("livecode".to_string())
+        .spawn(move || {
+            let clients = Arc::new(AtomicUsize::new(0));
+            for stream in listener.incoming().flatten() {
+                if clients.fetch_add(1, Ordering::SeqCst) >= MAX_CLIENTS {
+                    clients.fetch_sub(1, Ordering::SeqCst);
+                    eprintln!("Livecode: over {} clients, turning one away", MAX_CLIENTS);
+                    continue;
+                }
+                let sender = sender.clone();
+                let served = clients.clone();
+                let spawned = thread::Builder::new()
+                    .name("livecode client".to_string())
+                    .spawn(move || {
+                        if let Err(err) = take(stream, &sender) {
+                            eprintln!("Livecode: {}", err);
+                        }
+                        served.fetch_sub(1, Ordering::SeqCst);
+                    });
+                if let Err(err) = spawned {
+                    clients.fetch_sub(1, Ordering::SeqCst);
+                    eprintln!("Livecode: {}", err);
+                }
+            }
+        })
+        .map_err(|err| err.to_string())?;
+    Ok(())
+}
+
+// Take a client's pushes until it goes away
+fn take(stream: TcpStream, sender: &Sender<Message>) -> Result<(), String> {
+    stream
+        .set_read_timeout(Some(READ_TIMEOUT))
+        .map_err(|err| err.to_string())?;
+    let mut start = [0; 4];
+    let peeked = stream.peek(&mut start).map_err(|err| err.to_string())?;
+    if start[..peeked] == *b"GET " {
+        // The same socket as the one the WebSocket takes over, so its timeout too
+        let idle = stream.try_clone().map_err(|err| err.to_string())?;
+        let mut socket = WebSocket::accept(stream)?;
+        idle.set_read_timeout(Some(IDLE_TIMEOUT))
+            .map_err(|err| err.to_string())?;
+        while let Some(source) = socket.read()? {
+            let answer = match push(sender, source) {
+                Ok(()) => Json::object([("ok", Json::Bool(true))]),
+                Err(err) => Json::object([("error", Json::String(err))]),
+            };
+            socket.send(&answer.to_string())?;
+        }
+        return Ok(());
+    }
+
+    let mut source = Vec::new();
+    (&stream)
+        .take(MAX_SOURCE + 1)
+        .read_to_end(&mut source)
+        .map_err(|err| err.to_string())?;
+    let pushed = if source.len() as u64 > MAX_SOURCE {
+        // Read (some of) the rest, for the answer to get there rather than a reset
+        let _ = io::copy(&mut (&stream).take(16 * MAX_SOURCE), &mut io::sink());
+        Err(format!("shader over {} bytes", MAX_SOURCE))
+    } else {
+        String::from_utf8(source)
+            .map_err(|_| "shader isn't UTF-8".to_string())
+            .and_then(|source| push(sender, source))
+    };
+    let mut stream = stream;
+    match pushed {
+        Ok(()) => writeln!(stream, "ok"),
+        Err(err) => writeln!(stream, "error: {}", err),
+    }
+    .map_err(|err| err.to_string())?;
+    let _ = stream.shutdown(Shutdown::Write);
+    Ok(())
+}
+
+// Have the render thread switch to `source`, and wait for how it went
+fn push(sender: &Sender<Message>, source: String) -> Result<(), String> {
+    let (answer, receiver) = mpsc::channel();
+    sender
+        .send(Message::Shader(source, answer))
+        .map_err(|_| "the renderer has stopped".to_string())?;
+    receiver
+        .recv()
+        .map_err(|_| "the renderer has stopped".to_string())?
+}
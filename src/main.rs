@@ -1,167 +1,459 @@
-use cgmath::{Vector2, Vector3, Vector4};
-use foreign_types::ForeignType;
-use metal::*;
-use objc::rc::autoreleasepool;
-use objc::runtime::{Object, YES};
-use objc::{class, msg_send, sel, sel_impl};
-use std::mem;
-use std::time::Instant;
-use winit::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
+use cgmath::{InnerSpace, Vector2, Vector3};
+use clap::Parser;
+use std::f32::consts::{PI, TAU};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use winit::event::{ElementState, Event, KeyboardInput, ModifiersState, MouseButton, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
-use winit::platform::macos::WindowExtMacOS;
-use winit::window::WindowBuilder;
-
-// CGSize struct for Objective-C interop
-#[repr(C)]
-struct CGSize {
-    width: f64,
-    height: f64,
+use winit::window::{Fullscreen, WindowBuilder};
+
+// What the app prints is also kept for crash and error reports (see report.rs), and sent as
+// events when it is controlled over stdio (see control.rs)
+macro_rules! println {
+    ($($arg:tt)*) => {{
+        let line = format!($($arg)*);
+        $crate::report::log(&line);
+        // Off stdout while it carries control answers and events
+        #[cfg(feature = "offline")]
+        let sent = $crate::control::log("info", &line);
+        #[cfg(not(feature = "offline"))]
+        let sent = false;
+        if sent {
+            std::eprintln!("{}", line);
+        } else {
+            std::println!("{}", line);
+        }
+    }};
 }
+macro_rules! eprintln {
+    ($($arg:tt)*) => {{
+        let line = format!($($arg)*);
+        $crate::report::log(&line);
+        #[cfg(feature = "offline")]
+        $crate::control::log("error", &line);
+        std::eprintln!("{}", line);
+    }};
+}
+
+mod artnet;
+mod backend;
+mod base64;
+mod cli;
+mod config;
+#[cfg(feature = "offline")]
+mod control;
+#[cfg(target_os = "macos")]
+mod display_link;
+#[cfg(feature = "offline")]
+mod distributed;
+mod drop_folder;
+mod gallery;
+mod http;
+mod json;
+mod keybindings;
+mod livecode;
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+mod metal_backend;
+mod metrics;
+#[cfg(feature = "offline")]
+mod offline;
+mod remote;
+mod render_thread;
+mod report;
+mod session;
+mod share;
+mod status;
+mod sync;
+mod touch;
+mod websocket;
+#[cfg(feature = "wgpu")]
+mod wgpu_backend;
+mod workspace;
 
-// Uniform buffer structure matching the shader
-#[repr(C)]
-#[derive(Clone, Copy, Debug)] // Added Debug for easier inspection
-struct Uniforms {
-    resolution: Vector2<f32>, // Offset 0,  Size 8
-    time: f32,                // Offset 8,  Size 4
-    _padding0: [f32; 1],      // Offset 12, Size 4 (to align mouse to 16)
-    mouse: Vector2<f32>,      // Offset 16, Size 8
-    _padding1: [f32; 2],      // Offset 24, Size 8 (to align camera_pos to 32)
-    camera_pos: Vector3<f32>, // Offset 32, Size 12
-    _padding: f32,            // Offset 44, Size 4 (matches the explicit _padding in shader)
-} // Total size: 48 bytes
+#[cfg(not(any(target_os = "macos", target_os = "ios", feature = "wgpu")))]
+compile_error!("Outside macOS and iOS, build with the \"wgpu\" feature");
+
+use artnet::ArtNet;
+use backend::{Backend, BackendKind};
+use cli::Cli;
+use config::{Config, Panels, TweenConfig};
+use drop_folder::{DropFolder, Dropped};
+use gallery::Gallery;
+use http::{Answer, Endpoint, Response, Stats};
+use json::Json;
+use keybindings::{Action, Keybindings};
+use metal_raymarcher::audio::AudioLevels;
+#[cfg(feature = "audio")]
+use metal_raymarcher::audio_input::AudioInput;
+use metal_raymarcher::brush::{Brush, BrushMode};
+use metal_raymarcher::clock::{Clock, Cue};
+use metal_raymarcher::codegen::{self, Dialect, ParamLayout};
+use metal_raymarcher::editor::Editor;
+use metal_raymarcher::editor::Motion;
+use metal_raymarcher::expression::{self, Variables};
+use metal_raymarcher::gizmo::{self, Axis, Gizmo, GizmoMode, View};
+use metal_raymarcher::history::{Command, History};
+use metal_raymarcher::inspector::{self, FieldKind, Inspector, Slide};
+use metal_raymarcher::palette::{self, Palette};
+use metal_raymarcher::picking;
+use metal_raymarcher::quality::{AaMode, QualityConfig, QualityPreset};
+use metal_raymarcher::scene::{Node, PointLight, SceneObject};
+#[cfg(feature = "scripting")]
+use metal_raymarcher::script::Script;
+use metal_raymarcher::sequence::Sequence;
+use metal_raymarcher::shader_test;
+use metal_raymarcher::tween::Tween;
+use metal_raymarcher::{animation, export, Camera, Scene};
+use remote::{Capture, Reply, Request};
+use render_thread::{Message, RenderThread};
+use session::{Recorder, Replay, Session};
+use share::SharedView;
+use sync::{FrameSync, Tick};
+use touch::{Gesture, Touches};
+use workspace::{Layout, Workspace};
 
 struct App {
-    device: Device,
-    command_queue: CommandQueue,
-    pipeline_state: RenderPipelineState,
-    vertex_buffer: Buffer,
-    uniform_buffer: Buffer,
-    layer: *mut Object,
+    backend: Box<dyn Backend>,
+    render_scale: f32, // Drawable size relative to the window size
+    base_scale: f32,   // Configured render scale, before the preset's factor
+    window_size: winit::dpi::PhysicalSize<u32>,
+    preset: QualityPreset,
+    quality: QualityConfig, // Settings the backend has now
+    quality_tween: Option<Tween<QualityConfig>>, // Easing into a new preset
+    aa_mode: Option<AaMode>, // Kept through preset changes
+    tween: TweenConfig,
+    capture_dir: PathBuf, // Where exports are written
+    #[cfg(feature = "offline")]
+    stamp: bool, // Captures have their metadata printed over them
+    #[cfg(feature = "offline")]
+    backend_kind: BackendKind, // For the captures' metadata
+    #[cfg(feature = "offline")]
+    seed: u32,
+    scene: Scene, // With the animated and bound parameters at the current time
+    selected: Option<usize>, // Object clicked on, by index in scene.objects()
+    selected_light: Option<usize>, // Light clicked on, by index in scene.lights
+    gizmo_mode: GizmoMode,
+    hovered: Option<Axis>, // Gizmo handle under the cursor
+    drag: Option<Drag>,
+    snap: bool, // Dragging the selected object slides it over surfaces
+    snapping: Option<Snap>,
+    brush: Brush,
+    stroke: Option<Stroke>,
+    history: History, // Edits made in the viewport
+    inspector: Inspector,
+    palette: Palette<Action>,
+    editor: Editor,
+    gallery: Gallery,
+    keybindings: Keybindings,    // For the shortcuts the palette lists
+    scene_path: Option<PathBuf>, // File the scene was loaded from, where Cmd+S saves it
+    guides: bool,                // Ground grid and axes shown
+    path_tracing: bool,          // Path traced progressively instead of raymarched
+    denoise: bool,               // Path-traced images are denoised
+    target_samples: Option<u32>, // Paths per pixel at which the path-traced image is saved
+    #[cfg(feature = "offline")]
+    saved: bool, // The current path-traced image was saved
+    #[cfg(feature = "offline")]
+    sampling_since: Instant, // When the current path-traced image started
+    #[cfg(feature = "offline")]
+    notify: bool, // Saving it posts a notification (see status.rs)
+    #[cfg(feature = "offline")]
+    readbacks: Vec<(u64, winit::dpi::PhysicalSize<u32>, Readback)>, // Requested, the GPU not done yet
+    #[cfg(feature = "scripting")]
+    script: Option<Script>, // Runs on the scene every frame
+    #[cfg(feature = "audio")]
+    audio: Option<AudioInput>,
+    clock: Clock,
+    cues: Vec<(f32, Cue)>, // Cues waiting for their beat or bar, with the time they fire
+    recorder: Option<Recorder>,
+    replay: Option<Replay>, // Recorded frames instead of the live scene and inputs
+    sequencer: Option<Sequencer>,
     start_time: Instant,
+    camera: Camera,
+    time: f32, // Scene time of the frame being drawn
     mouse_pos: Vector2<f32>,
     camera_distance: f32,
     camera_angle: f32,
+    camera_tween: Option<Tween<[f32; 2]>>, // Angle and distance, while resetting
+    reported: bool,                        // A GPU error was reported (only the first is)
+    stats: Stats,                          // Frames drawn, for monitoring (see http.rs)
+    drop_folder: Option<DropFolder>,       // Scenes and shaders to load as they arrive
+    frame_sync: Option<FrameSync>,         // Leading or following other instances' frames
+    artnet: Option<ArtNet>,                // DMX from a lighting desk, mapped onto parameters
+}
+
+// A gizmo handle being dragged
+struct Drag {
+    mode: GizmoMode,
+    axis: Axis,
+    target: String,       // Parameter it edits
+    mouse: Vector2<f32>,  // Where the drag started
+    origin: Vector3<f32>, // Gizmo position when it started
+    start: Vec<f32>,      // Parameter value when it started
+}
+
+// The selected object being slid over the surfaces under the cursor (snap mode)
+struct Snap {
+    id: usize,
+    name: String,
+    start: [[f32; 3]; 2], // Position and rotation when it started
+}
+
+// A brush stroke in progress
+struct Stroke {
+    id: usize,           // Object it was started on
+    last: Vector3<f32>,  // Where the last dab went
+    depth: f32,          // Distance in front of the camera the stroke is drawn at
+    edits: Vec<Command>, // Objects it has added
+}
+
+// Playback state of --sequence
+struct Sequencer {
+    sequence: Sequence,
+    scenes: Vec<Scene>, // Each entry's scene as loaded
+    entry: usize,       // Entry the app's scene belongs to
+    fade: Option<Fade>,
+}
+
+// The entry fading in during a cross-fade
+struct Fade {
+    entry: usize,
+    scene: Scene, // Animated on the entry's own time
+    built: bool,  // The backend is drawing it
 }
 
 impl App {
-    fn new(window: &winit::window::Window) -> Self {
-        // Initialize Metal
-        let device = Device::system_default().expect("No Metal device found");
-        let command_queue = device.new_command_queue();
-
-        // Create CAMetalLayer
-        let layer = autoreleasepool(|| {
-            let layer: *mut Object = unsafe { msg_send![class!(CAMetalLayer), layer] };
-            let ns_window = window.ns_window() as *mut Object;
-            let ns_view: *mut Object = unsafe { msg_send![ns_window, contentView] };
-            unsafe {
-                let _: () = msg_send![ns_view, setLayer: layer];
-                let _: () = msg_send![ns_view, setWantsLayer: YES];
-                let _: () = msg_send![layer, setDevice: device.as_ptr()];
-                let _: () = msg_send![layer, setPixelFormat: MTLPixelFormat::BGRA8Unorm as u64];
-                let size = window.inner_size();
-                let _: () = msg_send![layer, setDrawableSize: CGSize {
-                    width: size.width as f64,
-                    height: size.height as f64
-                }];
-            }
-            layer
+    fn new(window: Option<&winit::window::Window>, config: &Config, scene: Scene) -> Self {
+        let kind = config.backend.unwrap_or_else(BackendKind::platform_default);
+        let mut backend = backend::create(kind, window, config, &scene).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            std::process::exit(1);
         });
 
-        // Create shaders
-        let shader_source = include_str!("shaders.metal");
-        let library = device
-            .new_library_with_source(shader_source, &CompileOptions::new())
-            .expect("Failed to compile shaders");
-
-        let vertex_fn = library.get_function("vertex_main", None).unwrap();
-        let fragment_fn = library.get_function("fragment_main", None).unwrap();
-
-        // Create pipeline
-        let pipeline_descriptor = RenderPipelineDescriptor::new();
-        pipeline_descriptor.set_vertex_function(Some(&vertex_fn));
-        pipeline_descriptor.set_fragment_function(Some(&fragment_fn));
-        pipeline_descriptor
-            .color_attachments()
-            .object_at(0)
-            .unwrap()
-            .set_pixel_format(MTLPixelFormat::BGRA8Unorm);
-
-        let pipeline_state = device
-            .new_render_pipeline_state(&pipeline_descriptor)
-            .expect("Failed to create pipeline state");
-
-        // Create fullscreen quad vertices
-        let vertices: [[f32; 2]; 6] = [
-            [-1.0, -1.0],
-            [1.0, -1.0],
-            [-1.0, 1.0], // First triangle
-            [1.0, -1.0],
-            [1.0, 1.0],
-            [-1.0, 1.0], // Second triangle
-        ];
-
-        let vertex_buffer = device.new_buffer_with_data(
-            vertices.as_ptr() as *const _,
-            (vertices.len() * mem::size_of::<[f32; 2]>()) as u64,
-            MTLResourceOptions::StorageModeShared,
-        );
+        let preset = config.preset.unwrap_or_else(|| backend.default_preset());
+        let mut quality = config.quality.clone().unwrap_or_else(|| preset.settings());
+        if let Some(aa_mode) = config.aa_mode {
+            quality.aa_mode = aa_mode;
+        }
+        backend.set_quality(quality.clone());
+        backend.set_toon(config.toon);
+        backend.set_seed(config.seed, &scene);
+        if let Err(err) = backend.set_edges(config.edges) {
+            eprintln!("{}", err);
+        }
+        if let Err(err) = backend.set_shafts(config.shafts) {
+            eprintln!("{}", err);
+        }
+        if let Err(err) = backend.set_adaptive(config.adaptive) {
+            eprintln!("{}", err);
+        }
+        if let Err(err) = backend.set_culling(config.culling) {
+            eprintln!("{}", err);
+        }
+        println!("Quality: {:?}", preset);
 
-        // Create uniform buffer
-        let window_size = window.inner_size();
-        let uniforms = Uniforms {
-            resolution: Vector2::new(window_size.width as f32, window_size.height as f32),
-            time: 0.0,
-            _padding0: [0.0; 1], // Initialize padding
-            mouse: Vector2::new(0.5, 0.5),
-            _padding1: [0.0; 2], // Initialize padding
-            camera_pos: Vector3::new(0.0, 2.0, -8.0),
-            _padding: 0.0, // This is the shader's _padding field
+        let render_scale = config.scale * preset.render_scale();
+        let window_size = match window {
+            Some(window) => window.inner_size(),
+            None => winit::dpi::PhysicalSize::new(config.width, config.height),
         };
+        backend.resize(scaled_size(window_size, render_scale));
 
-        let uniform_buffer = device.new_buffer(
-            mem::size_of::<Uniforms>() as u64,
-            MTLResourceOptions::StorageModeShared,
-        );
+        let mut stats = Stats::default();
+        stats.refresh = (window.and_then(|window| window.current_monitor()))
+            .and_then(|monitor| monitor.refresh_rate_millihertz())
+            .map(|millihertz| 1000.0 / millihertz as f32);
 
-        unsafe {
-            std::ptr::copy_nonoverlapping(
-                &uniforms as *const Uniforms,
-                uniform_buffer.contents() as *mut Uniforms,
-                1,
-            );
+        #[cfg(feature = "scripting")]
+        let script = scene.script.as_ref().map(|path| {
+            Script::load(path, &scene).unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            })
+        });
+
+        #[cfg(feature = "audio")]
+        let audio = metal_raymarcher::audio::open(&config.audio);
+        #[cfg(not(feature = "audio"))]
+        if config.audio.input.is_some() {
+            eprintln!("Audio input disabled: built without the \"audio\" feature");
         }
 
         Self {
-            device,
-            command_queue,
-            pipeline_state,
-            vertex_buffer,
-            uniform_buffer,
-            layer,
+            backend,
+            render_scale,
+            base_scale: config.scale,
+            window_size,
+            preset,
+            quality,
+            quality_tween: None,
+            aa_mode: config.aa_mode,
+            tween: config.tween.clone(),
+            capture_dir: config.capture_dir.clone(),
+            #[cfg(feature = "offline")]
+            stamp: config.stamp,
+            #[cfg(feature = "offline")]
+            backend_kind: kind,
+            #[cfg(feature = "offline")]
+            seed: config.seed,
+            scene,
+            selected: None,
+            selected_light: None,
+            gizmo_mode: GizmoMode::default(),
+            hovered: None,
+            drag: None,
+            snap: false,
+            snapping: None,
+            brush: Brush::new(&config.brush),
+            stroke: None,
+            history: History::default(),
+            inspector: Inspector::default(),
+            palette: Palette::default(),
+            editor: Editor::default(),
+            gallery: Gallery::new(config),
+            keybindings: Keybindings::default(),
+            scene_path: None,
+            guides: false,
+            path_tracing: false,
+            denoise: true,
+            target_samples: None,
+            #[cfg(feature = "offline")]
+            saved: false,
+            #[cfg(feature = "offline")]
+            sampling_since: Instant::now(),
+            #[cfg(feature = "offline")]
+            notify: config.notify,
+            #[cfg(feature = "offline")]
+            readbacks: Vec::new(),
+            #[cfg(feature = "scripting")]
+            script,
+            #[cfg(feature = "audio")]
+            audio,
+            clock: Clock::new(&config.clock),
+            cues: Vec::new(),
+            recorder: None,
+            replay: None,
+            sequencer: None,
             start_time: Instant::now(),
+            camera: Camera {
+                lens: config.lens,
+                stereo: config.stereo,
+                region: config.region,
+                ..Camera::default()
+            },
+            time: 0.0,
             mouse_pos: Vector2::new(0.5, 0.5),
             camera_distance: 8.0,
             camera_angle: 0.0,
+            camera_tween: None,
+            reported: false,
+            stats,
+            drop_folder: None,
+            frame_sync: None,
+            artnet: None,
+        }
+    }
+
+    // Step to the frame that will be shown at `shown`
+    fn update(&mut self, shown: Instant) {
+        self.load_dropped();
+        self.gallery.poll();
+        let tick = match self.frame_sync.as_mut() {
+            Some(FrameSync::Follower(follower)) => follower.poll(),
+            _ => None,
+        };
+        match tick {
+            // The master's frame instead of one of our own (see sync.rs)
+            Some(tick) => {
+                [self.camera_angle, self.camera_distance] = tick.camera;
+                self.mouse_pos = tick.mouse.into();
+                self.update_at(tick.time);
+                // Carrying on from there should the master go away
+                self.set_time(tick.time);
+            }
+            None => {
+                let elapsed = shown.saturating_duration_since(self.start_time);
+                self.update_at(elapsed.as_secs_f32());
+            }
+        }
+        if let Some(FrameSync::Master(master)) = self.frame_sync.as_mut() {
+            master.send(Tick {
+                frame: 0,
+                time: self.time,
+                camera: [self.camera_angle, self.camera_distance],
+                mouse: self.mouse_pos.into(),
+            });
+        }
+    }
+
+    // Switch to a shader template pushed by a livecoding client (see livecode.rs)
+    fn push_shader(&mut self, template: String) -> Result<(), String> {
+        let pushed = self.backend.set_shader(template, &self.scene);
+        match &pushed {
+            Ok(()) => println!("Shader pushed"),
+            Err(err) => eprintln!("Ignoring a pushed shader: {}", err),
         }
+        pushed
     }
 
-    fn update(&mut self, window_size: winit::dpi::PhysicalSize<u32>) {
-        let elapsed = self.start_time.elapsed().as_secs_f32();
+    // Load the scenes and shaders that have arrived in the drop folder
+    fn load_dropped(&mut self) {
+        let Some(folder) = self.drop_folder.as_mut() else {
+            return;
+        };
+        for dropped in folder.poll() {
+            let loaded = match &dropped {
+                Dropped::Scene(path) => self.switch_scene(path),
+                Dropped::Shader(path) => std::fs::read_to_string(path)
+                    .map_err(|err| err.to_string())
+                    .and_then(|template| self.backend.set_shader(template, &self.scene))
+                    .map(|()| println!("Shader: {}", path.display())),
+            };
+            if let Err(err) = loaded {
+                let (Dropped::Scene(path) | Dropped::Shader(path)) = &dropped;
+                eprintln!("Ignoring {}: {}", path.display(), err);
+            }
+        }
+    }
+
+    // Advance the camera and inputs to an explicit time (offline rendering)
+    fn update_at(&mut self, elapsed: f32) {
+        if self.replay.is_some() {
+            self.replay_at(elapsed);
+            return;
+        }
 
         // camera_angle is now updated by handle_mouse_move
-        self.camera_angle += 0.01; // Remove automatic rotation if mouse controls it
+        // The path tracer only converges while the camera holds still, and a follower's
+        // camera is the master's
+        let following = matches!(self.frame_sync, Some(FrameSync::Follower(_)));
+        if !self.path_tracing && !following {
+            self.camera_angle += 0.01; // Remove automatic rotation if mouse controls it
+        }
+        if let Some(tween) = &self.camera_tween {
+            [self.camera_angle, self.camera_distance] = tween.at(elapsed);
+            if tween.finished(elapsed) {
+                self.camera_tween = None;
+            }
+        }
+        if let Some(tween) = &self.quality_tween {
+            self.quality = tween.at(elapsed);
+            self.backend.set_quality(self.quality.clone());
+            if tween.finished(elapsed) {
+                self.quality_tween = None;
+            }
+        }
 
         let camera_y_height = 2.0; // Keep a fixed Y height for the camera for now
         let camera_x = self.camera_angle.cos() * self.camera_distance;
         let camera_z = self.camera_angle.sin() * self.camera_distance;
 
+        // Pull the latest video/screen frames into their textures
+        self.backend.upload_inputs();
+
         // Debug print (can be less frequent)
-        let now = Instant::now();
         // Example: Print if more than 0.5 seconds passed since last print, or if values changed significantly
         // For now, let's use the original periodic print.
-        if (elapsed as u64) % 2 == 0 && (elapsed - (elapsed as u64) as f32) < 0.05 {
+        if (elapsed as u64).is_multiple_of(2) && (elapsed - (elapsed as u64) as f32) < 0.05 {
             // Approx every 2 seconds
             println!(
                  "Time: {:.2}, Mouse: ({:.2},{:.2}), CamAngle: {:.2}rad, CamDist: {:.2}, CamPos: ({:.2}, {:.2}, {:.2})",
@@ -170,70 +462,697 @@ impl App {
              );
         }
 
-        let uniforms = Uniforms {
-            resolution: Vector2::new(window_size.width as f32, window_size.height as f32),
+        let dt = elapsed - self.time;
+        self.time = elapsed;
+        self.camera.position = Vector3::new(camera_x, camera_y_height, camera_z);
+        let audio = self.audio_levels(dt);
+        self.backend.set_audio(audio);
+        let clock = self.clock.phase(elapsed);
+        self.backend.set_clock(clock);
+
+        let mut variables = Variables {
             time: elapsed,
-            _padding0: [0.0; 1],
-            mouse: self.mouse_pos, // Send normalized mouse (can be used in shader for other effects)
-            _padding1: [0.0; 2],
-            camera_pos: Vector3::new(camera_x, camera_y_height, camera_z),
-            _padding: 0.0,
-        };
-
-        unsafe {
-            std::ptr::copy_nonoverlapping(
-                &uniforms as *const Uniforms,
-                self.uniform_buffer.contents() as *mut Uniforms,
-                1,
+            mouse: self.mouse_pos,
+            audio,
+            clock,
+        };
+        // A sequence entry runs on its own time
+        variables.time = self.advance_sequence(&variables);
+        animation::apply(&mut self.scene, variables.time);
+        expression::apply(&mut self.scene, &variables);
+        if let Some(artnet) = self.artnet.as_mut() {
+            if artnet.apply(&mut self.scene) {
+                self.rebuild();
+            }
+        }
+        self.fire_cues(elapsed, &variables);
+        #[cfg(feature = "scripting")]
+        self.run_script(&variables);
+        self.backend.set_scene(&self.scene);
+        // Normalized mouse (can be used in shader for other effects)
+        self.backend.set_mouse(self.mouse_pos);
+        // Light icons show with the grid, or while their light is selected
+        let view = self.view();
+        let mut overlay = Vec::new();
+        for (index, light) in self.scene.lights.iter().enumerate() {
+            let selected = self.selected_light == Some(index);
+            if self.guides || selected {
+                let position = light.position.into();
+                overlay.extend(gizmo::light_icon(&view, position, light.color, selected));
+            }
+        }
+        if let Some(gizmo) = self.gizmo() {
+            overlay.extend(gizmo.vertices(&view));
+        }
+        let selected = self
+            .selected
+            .and_then(|id| self.scene.objects().get(id).copied());
+        self.inspector.refresh(&self.scene, selected);
+        overlay.extend(self.inspector.vertices(&view));
+        overlay.extend(self.editor.vertices(&view));
+        overlay.extend(self.gallery.vertices(&view));
+        overlay.extend(self.palette.vertices(&view));
+        if let Some(samples) = self.backend.samples() {
+            let line = match self.target_samples {
+                Some(target) => format!("{}/{} spp", samples.min(target), target),
+                None => format!("{} spp", samples),
+            };
+            overlay.extend(inspector::status(&view, &line));
+        }
+        self.backend.set_overlay(&overlay);
+
+        if let Some(recorder) = self.recorder.as_mut() {
+            let recorded = recorder.record(
+                &self.scene,
+                self.time,
+                &self.camera,
+                self.mouse_pos,
+                &audio,
+                &clock,
             );
+            if let Err(err) = recorded {
+                eprintln!("Recording stopped: {}", err);
+                self.recorder = None;
+                status::report_recording(false);
+            }
         }
     }
 
-    fn render(&self) {
-        autoreleasepool(|| {
-            let drawable: *mut Object = unsafe { msg_send![self.layer, nextDrawable] };
-            if !drawable.is_null() {
-                let command_buffer = self.command_queue.new_command_buffer();
+    // --record / --replay
+    fn start_session(&mut self, cli: &Cli, replay: Option<Replay>) {
+        self.replay = replay;
+        if cli.path_trace {
+            self.set_path_tracing(true);
+        }
+        #[cfg(feature = "offline")]
+        {
+            self.target_samples = cli.samples;
+        }
+        if let Some(path) = &cli.record {
+            self.recorder = Some(Recorder::create(path).unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }));
+        }
+        status::report_recording(self.recorder.is_some());
+        status::report_replaying(self.replay.is_some());
+    }
+
+    // Show the recorded frame for `elapsed`
+    fn replay_at(&mut self, elapsed: f32) {
+        let Some(replay) = self.replay.as_mut() else {
+            return;
+        };
+        let (frame, rebuild) = replay.seek(elapsed, &mut self.scene);
+        if rebuild {
+            if let Err(err) = self.backend.rebuild(&self.scene) {
+                eprintln!("{}", err);
+            }
+        }
+        self.time = frame.time;
+        self.camera = self.camera.moved_to(&frame.camera);
+        self.mouse_pos = frame.mouse;
+        self.backend.upload_inputs();
+        self.backend.set_audio(frame.audio);
+        self.backend.set_clock(frame.clock);
+        self.backend.set_scene(&self.scene);
+        self.backend.set_mouse(self.mouse_pos);
+    }
 
-                let render_pass_descriptor = RenderPassDescriptor::new();
-                let color_attachment = render_pass_descriptor
-                    .color_attachments()
-                    .object_at(0)
-                    .unwrap();
+    // Follow the sequence to `variables.time`: switch entries as they come up, animate the
+    // one fading in and place the camera on the entry's path. Returns the time into the
+    // current entry (`variables.time` without a sequence).
+    fn advance_sequence(&mut self, variables: &Variables) -> f32 {
+        let Some(sequencer) = self.sequencer.as_mut() else {
+            return variables.time;
+        };
+        let moment = sequencer.sequence.at(variables.time);
 
-                let texture: *mut MTLTexture = unsafe { msg_send![drawable, texture] };
-                color_attachment.set_texture(Some(unsafe { &*(texture as *const _) }));
-                color_attachment.set_load_action(MTLLoadAction::Clear);
-                color_attachment.set_clear_color(MTLClearColor {
-                    red: 0.0,
-                    green: 0.0,
-                    blue: 0.0,
-                    alpha: 1.0,
-                });
-                color_attachment.set_store_action(MTLStoreAction::Store);
+        if moment.entry != sequencer.entry {
+            match sequencer.fade.take() {
+                Some(fade) if fade.built && fade.entry == moment.entry => {
+                    self.backend.end_crossfade(true);
+                    self.scene = fade.scene;
+                }
+                _ => {
+                    self.backend.end_crossfade(false);
+                    self.scene = sequencer.scenes[moment.entry].clone();
+                    if let Err(err) = self.backend.rebuild(&self.scene) {
+                        eprintln!("{}", err);
+                    }
+                }
+            }
+            sequencer.entry = moment.entry;
+            self.scene_path = None;
+            self.history.clear();
+            self.selected = None;
+            self.selected_light = None;
+            self.backend.set_selected(None);
+            #[cfg(feature = "scripting")]
+            {
+                self.script = load_script(&self.scene);
+            }
+            println!("Sequence: {}", self.scene.name);
+        }
+
+        match moment.incoming {
+            Some(incoming) => {
+                let fade = match &mut sequencer.fade {
+                    Some(fade) if fade.entry == incoming.entry => fade,
+                    fade => {
+                        let scene = sequencer.scenes[incoming.entry].clone();
+                        let built = self
+                            .backend
+                            .begin_crossfade(&scene)
+                            .map_err(|err| eprintln!("{}", err))
+                            .is_ok();
+                        fade.insert(Fade {
+                            entry: incoming.entry,
+                            scene,
+                            built,
+                        })
+                    }
+                };
+                let variables = Variables {
+                    time: incoming.time,
+                    ..*variables
+                };
+                animation::apply(&mut fade.scene, incoming.time);
+                expression::apply(&mut fade.scene, &variables);
+                let camera = sequencer.sequence.entries[incoming.entry]
+                    .camera_at(incoming.time)
+                    .map_or(self.camera, |camera| self.camera.moved_to(&camera));
+                self.backend
+                    .set_crossfade(&fade.scene, &camera, incoming.opacity);
+            }
+            // Cut short (the sequence started over): back to the current entry alone
+            None => {
+                if sequencer.fade.take().is_some() {
+                    self.backend.end_crossfade(false);
+                }
+            }
+        }
+
+        match sequencer.sequence.entries[moment.entry].camera_at(moment.time) {
+            Some(camera) => self.camera = self.camera.moved_to(&camera),
+            None => self.camera.target = Camera::default().target,
+        }
+        moment.time
+    }
+
+    // Fire the queued cues whose beat or bar has come
+    fn fire_cues(&mut self, elapsed: f32, variables: &Variables) {
+        let (due, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut self.cues)
+            .into_iter()
+            .partition(|(at, _)| *at <= elapsed);
+        self.cues = waiting;
+        for (_, cue) in due {
+            if !cue.name.is_empty() {
+                println!("Cue: {}", cue.name);
+            }
+            if let Some(path) = &cue.scene {
+                if let Err(err) = self.switch_scene(path) {
+                    eprintln!("{}", err);
+                }
+            }
+            match cue.apply(&mut self.scene, variables) {
+                Ok(false) => {}
+                Ok(true) => {
+                    if let Err(err) = self.backend.rebuild(&self.scene) {
+                        eprintln!("{}", err);
+                    }
+                }
+                Err(err) => eprintln!("Cue error: {}", err),
+            }
+        }
+    }
+
+    // Replace the scene (and its script), dropping the old scene's queued cues
+    fn switch_scene(&mut self, path: &Path) -> Result<(), String> {
+        let scene = Scene::load(path)?;
+        #[cfg(feature = "scripting")]
+        {
+            self.script = load_script(&scene);
+        }
+        self.scene = scene;
+        self.scene_path = Some(path.to_path_buf());
+        self.cues.clear();
+        self.history.clear();
+        self.select(None);
+        if let Err(err) = self.backend.rebuild(&self.scene) {
+            eprintln!("{}", err);
+        }
+        println!("Scene: {}", self.scene.name);
+        Ok(())
+    }
+
+    // Load the scene picked in the gallery
+    fn open_scene(&mut self, path: &Path) {
+        if let Err(err) = self.switch_scene(path) {
+            eprintln!("{}", err);
+        }
+    }
+
+    // Levels of the audio input (all zero without one)
+    fn audio_levels(&mut self, dt: f32) -> AudioLevels {
+        #[cfg(feature = "audio")]
+        if let Some(audio) = self.audio.as_mut() {
+            return audio.levels(dt);
+        }
+        let _ = dt;
+        AudioLevels::default()
+    }
+
+    #[cfg(feature = "scripting")]
+    fn run_script(&mut self, variables: &Variables) {
+        let Some(script) = self.script.as_mut() else {
+            return;
+        };
+        match script.run(&mut self.scene, variables) {
+            Ok(false) => {}
+            Ok(true) => {
+                if let Err(err) = self.backend.rebuild(&self.scene) {
+                    eprintln!("{}", err);
+                }
+            }
+            Err(err) => eprintln!("Script error: {}", err),
+        }
+    }
+
+    // Draw a frame; false when none could be drawn (see Backend::render), including while the
+    // window is minimized to nothing
+    fn render(&mut self) -> bool {
+        #[cfg(feature = "offline")]
+        self.finish_readbacks();
+        if self.window_size.width == 0 || self.window_size.height == 0 {
+            self.stats.pause();
+            return false;
+        }
+        if self.backend.recover(&self.scene) {
+            // The sequence's cross-fade was left on the GPU that went away
+            if let Some(fade) = self.sequencer.as_mut().and_then(|s| s.fade.as_mut()) {
+                fade.built = (self.backend.begin_crossfade(&fade.scene))
+                    .map_err(|err| eprintln!("{}", err))
+                    .is_ok();
+            }
+        }
+        if !self.backend.render(&self.camera, self.time) {
+            self.stats.pause();
+            return false;
+        }
+        self.stats.frame();
+        status::report_fps(self.stats.fps);
+        status::report_scene(&self.scene.name);
+        if let Some(err) = self.backend.gpu_error() {
+            eprintln!("{}", err);
+            if !self.reported {
+                self.reported = true;
+                report::write(&err, Some(self));
+            }
+        }
+        #[cfg(feature = "offline")]
+        self.save_converged();
+        true
+    }
+
+    // Save the path-traced image once it reaches the target sample count, again after each
+    // time it starts over and gets there
+    #[cfg(feature = "offline")]
+    fn save_converged(&mut self) {
+        let (Some(target), Some(samples)) = (self.target_samples, self.backend.samples()) else {
+            return;
+        };
+        if samples < target {
+            // Started over, or saved and then started over
+            if samples <= 1 || self.saved {
+                self.sampling_since = Instant::now();
+            }
+            self.saved = false;
+            return;
+        }
+        if self.saved {
+            return;
+        }
+        self.saved = true;
+        let path = (self.capture_dir).join(format!("{}-{}spp.png", self.scene.name, target));
+        self.read_back(Readback::Converged(path));
+    }
+
+    // Draw the current view offscreen for `readback`, without the gizmos (the next frame draws
+    // them again), and carry on: the GPU hands it back to `finish_readbacks` when done
+    #[cfg(feature = "offline")]
+    fn read_back(&mut self, readback: Readback) {
+        self.backend.set_overlay(&[]);
+        let size = scaled_size(self.window_size, self.render_scale);
+        let ticket = self.backend.request_readback(size, &self.camera, self.time);
+        self.readbacks.push((ticket, size, readback));
+    }
+
+    // Deliver the frames read back since the last call
+    #[cfg(feature = "offline")]
+    fn finish_readbacks(&mut self) {
+        for (ticket, pixels) in self.backend.readbacks() {
+            let Some(index) = self.readbacks.iter().position(|(t, ..)| *t == ticket) else {
+                continue;
+            };
+            let (_, size, readback) = self.readbacks.remove(index);
+            match readback {
+                // The client may have gone while waiting
+                Readback::Capture(reply) => {
+                    let saved = pixels.and_then(|pixels| self.save_capture(size, pixels));
+                    let path = |path: PathBuf| Json::String(path.display().to_string());
+                    drop(reply.send(saved.map(|p| Json::object([("path", path(p))]))));
+                }
+                Readback::Frame(answer) => {
+                    drop(answer.send(pixels.and_then(|pixels| png_response(size, &pixels))));
+                }
+                Readback::Converged(path) => {
+                    let saved = pixels.and_then(|mut pixels| {
+                        std::fs::create_dir_all(&self.capture_dir)
+                            .map_err(|err| format!("{}: {}", self.capture_dir.display(), err))?;
+                        let metadata = self.stamped(size, &mut pixels);
+                        offline::write_png(&path, size.width, size.height, &pixels, &metadata)
+                    });
+                    match &saved {
+                        Ok(()) => println!("Saved {}", path.display()),
+                        Err(err) => eprintln!("Failed to save the path-traced image: {}", err),
+                    }
+                    if self.notify {
+                        let job = format!("Path tracing of {}", self.scene.name);
+                        let outcome = saved.as_ref().map(|()| path.as_path());
+                        status::report_done(
+                            &job,
+                            self.sampling_since,
+                            outcome.map_err(String::as_str),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    // Draw a frame offscreen and save its intermediate textures as they came out of the GPU
+    // (numbered in the order they were drawn, as OpenEXR) and the frame itself (PNG last) to
+    // <capture_dir>/<scene>-passes, replacing the last dump of the scene. A folder of that name
+    // without MARKER in it wasn't made here and is left alone.
+    #[cfg(feature = "offline")]
+    fn dump_passes(&mut self) -> Result<std::path::PathBuf, String> {
+        const MARKER: &str = ".passes";
+        let dir = (self.capture_dir).join(format!("{}-passes", self.scene.name));
+        let error = |err: std::io::Error| format!("{}: {}", dir.display(), err);
+        if dir.exists() {
+            if !dir.join(MARKER).is_file() {
+                return Err(format!("{} is not a dump of passes", dir.display()));
+            }
+            std::fs::remove_dir_all(&dir).map_err(error)?;
+        }
+        std::fs::create_dir_all(&dir).map_err(error)?;
+        std::fs::write(dir.join(MARKER), "").map_err(error)?;
 
-                let render_encoder =
-                    command_buffer.new_render_command_encoder(&render_pass_descriptor);
+        let size = scaled_size(self.window_size, self.render_scale);
+        let mut frame = self.backend.render_to_rgba(size, &self.camera, self.time);
+        let passes = self.backend.read_passes();
+        let metadata = self.metadata(size);
+        for (index, pass) in passes.iter().enumerate() {
+            let path = dir.join(format!("{}-{}.exr", index + 1, pass.name));
+            let (width, height) = (pass.size.width, pass.size.height);
+            offline::write_exr(&path, width, height, &pass.pixels, &metadata)?;
+        }
+        let path = dir.join(format!("{}-frame.png", passes.len() + 1));
+        let metadata = self.stamped(size, &mut frame);
+        offline::write_png(&path, size.width, size.height, &frame, &metadata)?;
+        Ok(dir)
+    }
 
-                render_encoder.set_render_pipeline_state(&self.pipeline_state);
-                render_encoder.set_vertex_buffer(0, Some(&self.vertex_buffer), 0);
-                render_encoder.set_fragment_buffer(0, Some(&self.uniform_buffer), 0);
-                render_encoder.draw_primitives(MTLPrimitiveType::Triangle, 0, 6);
-                render_encoder.end_encoding();
+    // Carry out a remote-control request (see remote.rs)
+    fn remote(&mut self, request: Request) -> Reply {
+        let done = Json::object([("ok", Json::Bool(true))]);
+        match request {
+            Request::Get(target) if target == "camera" => Ok(Json::object([
+                ("angle", self.camera_angle.into()),
+                ("distance", self.camera_distance.into()),
+            ])),
+            Request::Get(target) => match self.scene.parameter(&target) {
+                Some(values) => Ok(Json::object([("value", values.into())])),
+                None => Err(format!("no parameter \"{}\"", target)),
+            },
+            Request::Set(target, values) => self.set_parameter(&target, &values).map(|()| done),
+            Request::Load(path) => self.switch_scene(&path).map(|()| done),
+            Request::Camera { angle, distance } => {
+                if angle
+                    .into_iter()
+                    .chain(distance)
+                    .any(|value| !value.is_finite())
+                {
+                    return Err("angle and distance must be finite".to_string());
+                }
+                self.camera_tween = None;
+                if let Some(angle) = angle {
+                    self.camera_angle = angle;
+                }
+                if let Some(distance) = distance {
+                    self.camera_distance = distance.clamp(1.0, 20.0);
+                }
+                Ok(done)
+            }
+            Request::Capture(capture) => self
+                .capture(capture)
+                .map(|path| Json::object([("path", Json::String(path.display().to_string()))])),
+            Request::Action(action) => {
+                self.perform(action);
+                Ok(done)
+            }
+        }
+    }
 
-                command_buffer.present_drawable(unsafe { &*(drawable as *const _) });
-                command_buffer.commit();
+    // Answer a monitoring request (see http.rs)
+    fn http(&mut self, endpoint: Endpoint) -> Answer {
+        match endpoint {
+            Endpoint::Frame => {
+                #[cfg(feature = "offline")]
+                {
+                    self.backend.set_overlay(&[]);
+                    let size = scaled_size(self.window_size, self.render_scale);
+                    let pixels = self.backend.render_to_rgba(size, &self.camera, self.time);
+                    png_response(size, &pixels)
+                }
+                #[cfg(not(feature = "offline"))]
+                Err("Frames need the \"offline\" feature".to_string())
+            }
+            Endpoint::Stats => {
+                let size = scaled_size(self.window_size, self.render_scale);
+                let count = |count: u64| Json::Number(count as f64);
+                let mut stats = vec![
+                    ("scene", Json::String(self.scene.name.clone())),
+                    ("time", self.time.into()),
+                    ("frames", count(self.stats.frames)),
+                    ("fps", self.stats.fps.into()),
+                    ("uptime", self.stats.started.elapsed().as_secs_f32().into()),
+                    (
+                        "resolution",
+                        Json::Array(vec![count(size.width.into()), count(size.height.into())]),
+                    ),
+                    (
+                        "quality",
+                        Json::String(format!("{:?}", self.preset).to_lowercase()),
+                    ),
+                    ("gpu", Json::String(self.backend.describe_device())),
+                    ("path_tracing", Json::Bool(self.path_tracing)),
+                    (
+                        "samples",
+                        (self.backend.samples()).map_or(Json::Null, |n| count(n.into())),
+                    ),
+                ];
+                if let Some((frame, frames)) = self.stats.progress {
+                    stats.push((
+                        "progress",
+                        Json::object([
+                            ("frame", count(frame as u64)),
+                            ("frames", count(frames as u64)),
+                        ]),
+                    ));
+                }
+                Ok(Response::json(Json::Object(
+                    (stats.into_iter())
+                        .map(|(key, value)| (key.to_string(), value))
+                        .collect(),
+                )))
             }
+            Endpoint::Metrics => Ok(Response {
+                content_type: "text/plain; version=0.0.4; charset=utf-8",
+                body: metrics::scrape(self, scaled_size(self.window_size, self.render_scale))
+                    .into_bytes(),
+            }),
+            Endpoint::Params => Ok(Response::json(self.parameters())),
+        }
+    }
+
+    // Every parameter of the scene with its values, by address
+    fn parameters(&self) -> Json {
+        Json::Object(
+            (self.scene.parameters().into_iter())
+                .filter_map(|target| {
+                    let value = self.scene.parameter(&target)?.into();
+                    Some((target, value))
+                })
+                .collect(),
+        )
+    }
+
+    // Set `target` to `values` (within the inspector's limits) as an undoable edit
+    fn set_parameter(&mut self, target: &str, values: &[f32]) -> Result<(), String> {
+        let len = (self.scene.parameter(target))
+            .ok_or_else(|| format!("no parameter \"{}\"", target))?
+            .len();
+        if values.len() != len {
+            return Err(format!(
+                "\"{}\" has {} components, {} values were given",
+                target,
+                len,
+                values.len()
+            ));
+        }
+        if values.iter().any(|value| !value.is_finite()) {
+            return Err("values must be finite".to_string());
+        }
+        let rebuild = self.scene.make_live(target)?;
+        let Some(parameter) = self.scene.parameter_mut(target) else {
+            return Err(format!("no parameter \"{}\"", target));
+        };
+        let before = parameter.to_vec();
+        for (slot, &value) in parameter.iter_mut().zip(values) {
+            *slot = inspector::limit(target, value);
+        }
+        let after = parameter.to_vec();
+        self.history.push(Command::Set {
+            target: target.to_string(),
+            before,
+            after,
         });
+        if rebuild {
+            self.rebuild();
+        }
+        Ok(())
+    }
+
+    // Save the current view as <capture_dir>/<scene>-<n>.png (the first n not taken), or dump
+    // its passes; where it went
+    fn capture(&mut self, capture: Capture) -> Result<PathBuf, String> {
+        #[cfg(feature = "offline")]
+        match capture {
+            Capture::Passes => self.dump_passes(),
+            Capture::Frame => {
+                // Without the gizmos; the next frame draws them again
+                self.backend.set_overlay(&[]);
+                let size = scaled_size(self.window_size, self.render_scale);
+                let pixels = self.backend.render_to_rgba(size, &self.camera, self.time);
+                self.save_capture(size, pixels)
+            }
+        }
+        #[cfg(not(feature = "offline"))]
+        {
+            let _ = capture;
+            Err("Captures need the \"offline\" feature".to_string())
+        }
+    }
+
+    // Save a captured frame as <capture_dir>/<scene>-<n>.png, the first n not taken
+    #[cfg(feature = "offline")]
+    fn save_capture(
+        &self,
+        size: winit::dpi::PhysicalSize<u32>,
+        mut pixels: Vec<u8>,
+    ) -> Result<PathBuf, String> {
+        std::fs::create_dir_all(&self.capture_dir)
+            .map_err(|err| format!("{}: {}", self.capture_dir.display(), err))?;
+        let path = (1..)
+            .map(|n| (self.capture_dir).join(format!("{}-{}.png", self.scene.name, n)))
+            .find(|path| !path.exists())
+            .unwrap_or_default();
+        let metadata = self.stamped(size, &mut pixels);
+        offline::write_png(&path, size.width, size.height, &pixels, &metadata)?;
+        println!("Saved {}", path.display());
+        Ok(path)
+    }
+
+    // What drew the current frame, to trace a capture of it back to: the scene, seeds, camera
+    // and renderer settings (see offline.rs)
+    #[cfg(feature = "offline")]
+    fn metadata(&self, size: winit::dpi::PhysicalSize<u32>) -> offline::Metadata {
+        let vector = |v: Vector3<f32>| format!("{:.3} {:.3} {:.3}", v.x, v.y, v.z);
+        let mut metadata = vec![
+            (
+                "Software",
+                format!("metal-raymarcher {}", env!("CARGO_PKG_VERSION")),
+            ),
+            ("Scene", self.scene.name.clone()),
+        ];
+        if let Some(path) = &self.scene_path {
+            metadata.push(("Scene file", path.display().to_string()));
+        }
+        let (lens, quality) = (self.camera.lens, &self.quality);
+        metadata.extend([
+            ("Time", format!("{:.4}", self.time)),
+            ("Noise seed", self.scene.noise_seed.to_string()),
+            ("Seed", self.seed.to_string()),
+            ("Camera position", vector(self.camera.position)),
+            ("Camera target", vector(self.camera.target)),
+            (
+                "Camera orbit",
+                format!(
+                    "angle {:.4} distance {:.4}",
+                    self.camera_angle, self.camera_distance
+                ),
+            ),
+            ("Lens", format!("{:?} {}", lens.projection, lens.amount)),
+            ("Renderer", format!("{:?}", self.backend_kind)),
+            (
+                "Size",
+                format!(
+                    "{}x{} (scale {})",
+                    size.width, size.height, self.render_scale
+                ),
+            ),
+            ("Quality", format!("{:?}", self.preset)),
+            (
+                "Raymarching",
+                format!(
+                    "{} steps, epsilon {}, distance {}, {} shadow steps, {} AO samples, AA {} {:?}",
+                    quality.max_steps,
+                    quality.epsilon,
+                    quality.max_distance,
+                    quality.shadow_steps,
+                    quality.ao_samples,
+                    quality.aa,
+                    quality.aa_mode
+                ),
+            ),
+        ]);
+        if self.path_tracing {
+            let samples = self.backend.samples().unwrap_or(0);
+            let denoised = if self.denoise { ", denoised" } else { "" };
+            metadata.push(("Path tracing", format!("{} spp{}", samples, denoised)));
+        }
+        metadata
+    }
+
+    // The metadata of a capture of the current frame, printed over `pixels` when stamping
+    #[cfg(feature = "offline")]
+    fn stamped(&self, size: winit::dpi::PhysicalSize<u32>, pixels: &mut [u8]) -> offline::Metadata {
+        let metadata = self.metadata(size);
+        if self.stamp {
+            offline::stamp(size.width, size.height, pixels, &metadata);
+        }
+        metadata
     }
 
     fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
-        autoreleasepool(|| unsafe {
-            let _: () = msg_send![self.layer, setDrawableSize: CGSize {
-                width: new_size.width as f64,
-                height: new_size.height as f64
-            }];
-        });
+        self.window_size = new_size;
+        // Minimized: keep the drawables as they are until the window comes back
+        if new_size.width > 0 && new_size.height > 0 {
+            self.backend
+                .resize(scaled_size(new_size, self.render_scale));
+        }
     }
 
     fn handle_mouse_move(
@@ -253,68 +1172,1514 @@ impl App {
         // Clamp mouse_pos to [0,1]
         self.mouse_pos.x = self.mouse_pos.x.clamp(0.0, 1.0);
         self.mouse_pos.y = self.mouse_pos.y.clamp(0.0, 1.0);
+        // The gallery covers the window, holding the camera still
+        if self.gallery.shown {
+            self.gallery.hover(&self.view(), self.mouse_pos);
+            return;
+        }
+
+        // Dragging a gizmo handle or the brush holds the camera still
+        if self.stroke.is_some() {
+            self.stroke_to(self.mouse_pos);
+            return;
+        }
+        if self.drag.is_some() {
+            self.drag_to(self.mouse_pos);
+            return;
+        }
+        if self.snapping.is_some() {
+            self.snap_to(self.mouse_pos);
+            return;
+        }
+        if self.inspector.slide.is_some() {
+            self.slide_to(self.mouse_pos);
+            return;
+        }
+        self.inspector.hovered = self.inspector.field_at(&self.view(), self.mouse_pos);
+        self.hovered = self
+            .gizmo()
+            .and_then(|gizmo| gizmo.hit(&self.view(), self.mouse_pos));
 
-        // Update camera_angle based on mouse_pos.x
+        // Update camera_angle based on mouse_pos.x (taking over from a reset in progress)
+        self.camera_tween = None;
         // Map mouse_pos.x from [0, 1] to a desired angle range, e.g., [0, 2*PI] or [-PI, PI]
         // Let's map it to [-PI, PI] so 0.5 is straight ahead (angle 0)
-        self.camera_angle = (self.mouse_pos.x * 2.0 - 1.0) * std::f32::consts::PI;
+        self.camera_angle = (self.mouse_pos.x * 2.0 - 1.0) * PI;
 
         // Optional: Print for debugging
         // println!("Mouse: ({:.2}, {:.2}), Camera Angle: {:.2} rad", self.mouse_pos.x, self.mouse_pos.y, self.camera_angle);
     }
 
-    fn handle_scroll(&mut self, delta: f32) {
-        self.camera_distance = (self.camera_distance - delta * 0.5).max(1.0).min(20.0);
-        // Inverted delta for natural scroll
-        // println!("Scroll: {:.2}, Camera Distance: {:.2}", delta, self.camera_distance);
+    // Grab the gizmo handle under the cursor, else select the light or object under it (or
+    // nothing when clicking the background). With the brush on, start a stroke instead, with
+    // Alt held place a light, and in snap mode start sliding the selected object when it is
+    // the one clicked. Clicks on the inspector go to its fields, and on the gallery load the
+    // scene clicked.
+    fn click(&mut self, alt: bool) {
+        self.inspector.entry = None;
+        if self.gallery.shown {
+            if let Some(path) = self.gallery.click(&self.view(), self.mouse_pos) {
+                self.open_scene(&path);
+            }
+            return;
+        }
+        if self.inspector.contains(&self.view(), self.mouse_pos) {
+            self.press_field();
+            return;
+        }
+        if alt {
+            self.place_light();
+            return;
+        }
+        if self.brush.mode != BrushMode::Off {
+            self.start_stroke();
+            return;
+        }
+        if let Some(axis) = self.hovered {
+            self.start_drag(axis);
+            return;
+        }
+        if let Some(index) = self.light_under_cursor() {
+            self.select(None);
+            self.selected_light = Some(index);
+            println!("Selected: {}", self.scene.lights[index].name);
+            return;
+        }
+        let aspect = self.window_size.width as f32 / self.window_size.height.max(1) as f32;
+        let hit = picking::pick(
+            &self.scene,
+            &self.camera,
+            self.mouse_pos,
+            aspect,
+            self.quality.max_distance,
+        );
+        if self.snap && hit.is_some() && hit == self.selected {
+            self.start_snap();
+            return;
+        }
+        self.select(hit);
+        match self.selected_object() {
+            Some(object) if !object.name.is_empty() => println!("Selected: {}", object.name),
+            Some(_) => println!("Selected: object {}", hit.unwrap_or_default()),
+            None => println!("Selection cleared"),
+        }
     }
-}
 
-fn main() {
-    let event_loop = EventLoop::new();
-    let window = WindowBuilder::new()
-        .with_title("Metal Ray Marcher")
-        .with_inner_size(winit::dpi::LogicalSize::new(1024, 768))
-        // .with_inner_size(winit::dpi::LogicalSize::new(1024, 768))
-        .build(&event_loop)
-        .unwrap();
+    // Light whose icon is under the cursor (icons can only be clicked with the grid shown)
+    fn light_under_cursor(&self) -> Option<usize> {
+        if !self.guides {
+            return None;
+        }
+        let view = self.view();
+        self.scene
+            .lights
+            .iter()
+            .enumerate()
+            .filter_map(|(index, light)| {
+                let distance = gizmo::light_hit(&view, light.position.into(), self.mouse_pos)?;
+                Some((index, distance))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(index, _)| index)
+    }
 
-    let mut app = App::new(&window);
+    // Alt+click: add a point light just off the surface under the cursor
+    fn place_light(&mut self) {
+        const OFFSET: f32 = 0.5; // From the surface, along its normal
+        let aspect = self.window_size.width as f32 / self.window_size.height.max(1) as f32;
+        let Some((_, point)) = picking::pick_point(
+            &self.scene,
+            &self.camera,
+            self.mouse_pos,
+            aspect,
+            self.quality.max_distance,
+        ) else {
+            return;
+        };
+        let position = point + picking::normal(&self.scene, point) * OFFSET;
+        let light = PointLight::new(self.scene.unused_name("light"), position.into());
+        let index = self.scene.lights.len();
+        self.scene.lights.push(light.clone());
+        println!("Placed {} at {:?}", light.name, light.position);
+        self.history.push(Command::AddLight { index, light });
+        self.rebuild();
+        self.select(None);
+        self.selected_light = Some(index);
+    }
 
-    event_loop.run(move |event, _, control_flow| {
-        *control_flow = ControlFlow::Poll;
+    fn select(&mut self, selected: Option<usize>) {
+        self.selected = selected;
+        self.selected_light = None;
+        self.hovered = None;
+        self.drag = None;
+        self.snapping = None;
+        self.inspector.slide = None;
+        self.inspector.entry = None;
+        self.backend.set_selected(selected);
+    }
 
-        match event {
-            Event::WindowEvent { event, .. } => match event {
-                WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
-                WindowEvent::Resized(size) => app.resize(size),
-                WindowEvent::CursorMoved { position, .. } => {
-                    app.handle_mouse_move(position, window.inner_size());
-                }
-                WindowEvent::MouseWheel { delta, .. } => {
-                    if let winit::event::MouseScrollDelta::LineDelta(_, y) = delta {
-                        app.handle_scroll(y);
-                    }
-                }
-                WindowEvent::KeyboardInput {
-                    input:
-                        KeyboardInput {
-                            state: ElementState::Pressed,
-                            virtual_keycode: Some(VirtualKeyCode::Space),
-                            ..
-                        },
-                    ..
-                } => {
-                    app.camera_angle = 0.0;
-                    app.camera_distance = 5.0;
-                    println!("Reset camera");
+    fn view(&self) -> View {
+        View {
+            camera: self.camera,
+            width: self.window_size.width,
+            height: self.window_size.height,
+        }
+    }
+
+    // Gizmo of the selected object or light
+    fn gizmo(&self) -> Option<Gizmo> {
+        let (_, position) = self.gizmo_target()?;
+        Some(Gizmo {
+            mode: self.drag.as_ref().map_or(self.mode(), |drag| drag.mode),
+            origin: self
+                .drag
+                .as_ref()
+                .map_or(position.into(), |drag| drag.origin),
+            hovered: self.hovered,
+            active: self.drag.as_ref().map(|drag| drag.axis),
+        })
+    }
+
+    // Name and position of the selected light or object
+    fn gizmo_target(&self) -> Option<(&str, [f32; 3])> {
+        match self.selected_light() {
+            Some(light) => Some((&light.name, light.position)),
+            None => self
+                .selected_object()
+                .map(|object| (object.name.as_str(), object.position)),
+        }
+    }
+
+    // Gizmo mode for the selection: lights can be moved and scaled (in intensity), not turned
+    fn mode(&self) -> GizmoMode {
+        match self.gizmo_mode {
+            GizmoMode::Rotate if self.selected_light().is_some() => GizmoMode::Translate,
+            mode => mode,
+        }
+    }
+
+    fn start_drag(&mut self, axis: Axis) {
+        let Some((name, position)) = self.gizmo_target() else {
+            return;
+        };
+        if name.is_empty() {
+            println!("Only named objects can be edited");
+            return;
+        }
+        let mode = self.mode();
+        let property = match mode {
+            GizmoMode::Scale if self.selected_light().is_some() => "intensity",
+            mode => mode.property(),
+        };
+        let target = format!("{}.{}", name, property);
+        let origin = position.into();
+        match self.scene.make_live(&target) {
+            Ok(false) => {}
+            Ok(true) => {
+                if let Err(err) = self.backend.rebuild(&self.scene) {
+                    eprintln!("{}", err);
+                }
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                return;
+            }
+        }
+        let start = self.scene.parameter(&target).unwrap_or(&[]).to_vec();
+        self.drag = Some(Drag {
+            mode,
+            axis,
+            target,
+            mouse: self.mouse_pos,
+            origin,
+            start,
+        });
+    }
+
+    // Write the dragged handle's change into the scene
+    fn drag_to(&mut self, mouse: Vector2<f32>) {
+        let (Some(gizmo), Some(drag)) = (self.gizmo(), self.drag.as_ref()) else {
+            return;
+        };
+        let amount = gizmo.drag(drag.axis, &self.view(), drag.mouse, mouse);
+        let axis = drag.axis.index();
+        let values: Vec<f32> = match drag.mode {
+            // Units or degrees along the axis
+            GizmoMode::Translate | GizmoMode::Rotate => drag
+                .start
+                .iter()
+                .enumerate()
+                .map(|(i, value)| if i == axis { value + amount } else { *value })
+                .collect(),
+            GizmoMode::Scale => drag.start.iter().map(|value| value * amount).collect(),
+        };
+        if let Some(parameter) = self.scene.parameter_mut(&drag.target) {
+            if parameter.len() == values.len() {
+                parameter.copy_from_slice(&values);
+            }
+        }
+    }
+
+    fn start_stroke(&mut self) {
+        let aspect = self.window_size.width as f32 / self.window_size.height.max(1) as f32;
+        let Some((id, point)) = picking::pick_point(
+            &self.scene,
+            &self.camera,
+            self.mouse_pos,
+            aspect,
+            self.quality.max_distance,
+        ) else {
+            return;
+        };
+        // Dabs change object ids, which the selection would no longer match
+        self.select(None);
+        let (forward, _, _) = self.camera.basis();
+        let mut stroke = Stroke {
+            id,
+            last: point,
+            depth: (point - self.camera.position).dot(forward),
+            edits: Vec::new(),
+        };
+        stroke
+            .edits
+            .extend(self.brush.dab(&mut self.scene, id, point));
+        self.stroke = Some(stroke);
+        self.rebuild();
+    }
+
+    // Carry the stroke to `mouse`, laying a capsule each time it has moved half a brush size
+    fn stroke_to(&mut self, mouse: Vector2<f32>) {
+        let Some(stroke) = self.stroke.as_mut() else {
+            return;
+        };
+        let aspect = self.window_size.width as f32 / self.window_size.height.max(1) as f32;
+        let rd = self.camera.ray(Vector2::new(
+            (mouse.x - 0.5) * 2.0 * aspect,
+            (mouse.y - 0.5) * 2.0,
+        ));
+        let (forward, _, _) = self.camera.basis();
+        let point = self.camera.position + rd * (stroke.depth / rd.dot(forward));
+        if (point - stroke.last).magnitude() < self.brush.size * 0.5 {
+            return;
+        }
+        let edit = self
+            .brush
+            .stroke(&mut self.scene, stroke.id, stroke.last, point);
+        stroke.edits.extend(edit);
+        stroke.last = point;
+        self.rebuild();
+    }
+
+    fn rebuild(&mut self) {
+        if let Err(err) = self.backend.rebuild(&self.scene) {
+            eprintln!("{}", err);
+        }
+    }
+
+    fn set_path_tracing(&mut self, on: bool) {
+        match self.backend.set_path_tracing(on) {
+            Ok(()) => {
+                self.path_tracing = on;
+                println!("Path tracing: {}", if on { "on" } else { "off" });
+            }
+            Err(err) => eprintln!("{}", err),
+        }
+    }
+
+    // Take the selected light or object out of the scene (the last object stays)
+    fn delete_selected(&mut self) {
+        if let Some(index) = self.selected_light.filter(|&i| i < self.scene.lights.len()) {
+            let light = self.scene.lights.remove(index);
+            let command = Command::RemoveLight { index, light };
+            println!("Deleted: {}", command.describe());
+            self.history.push(command);
+            self.edited(true);
+            return;
+        }
+        let Some(path) = self.selected.and_then(|id| self.scene.object_path(id)) else {
+            return;
+        };
+        if self.scene.objects().len() < 2 {
+            println!("The last object can't be deleted");
+            return;
+        }
+        let Some(node) = self.scene.remove_at(&path) else {
+            return;
+        };
+        let command = Command::Remove { path, node };
+        println!("Deleted: {}", command.describe());
+        self.history.push(command);
+        self.edited(true);
+    }
+
+    // Copy the selected object in beside it, one unit along x, and select the copy. The copy
+    // is a node the shader has no code for yet, so unlike a move it always rebuilds it.
+    fn duplicate_selected(&mut self) {
+        let Some(mut path) = self.selected.and_then(|id| self.scene.object_path(id)) else {
+            return;
+        };
+        let Some(Node::Object(object)) = self.scene.node_at(&path) else {
+            return;
+        };
+        let mut copy = object.clone();
+        if !copy.name.is_empty() {
+            copy.name = self
+                .scene
+                .unused_name(copy.name.trim_end_matches(|c: char| c.is_ascii_digit()));
+        }
+        copy.position[0] += 1.0;
+        let node = Node::Object(copy);
+        *path.last_mut().unwrap() += 1;
+        if !self.scene.insert_at(&path, node.clone()) {
+            return;
+        }
+        let id = self.scene.object_id(&path);
+        let command = Command::Add { path, node };
+        println!("Duplicated: {}", command.describe());
+        self.history.push(command);
+        self.edited(true);
+        self.select(id);
+    }
+
+    // Swap the selected object with its neighbour in its group (`offset` -1 or 1), changing
+    // the order the group's CSG operations apply in. The shader is rebuilt only when its code
+    // changes: two nodes alike but for values read from the uniforms swap them, which
+    // set_scene passes on with the next frame.
+    fn move_selected(&mut self, offset: isize) {
+        let Some(path) = self.selected.and_then(|id| self.scene.object_path(id)) else {
+            return;
+        };
+        let code = self.scene_code();
+        let mut to = path.clone();
+        let last = to.last_mut().unwrap();
+        match last.checked_add_signed(offset) {
+            Some(index) => *last = index,
+            None => return,
+        }
+        if self.scene.node_at(&to).is_none() {
+            return;
+        }
+        let Some(node) = self.scene.remove_at(&path) else {
+            return;
+        };
+        self.scene.insert_at(&to, node.clone());
+        let id = self.scene.object_id(&to);
+        let command = Command::Batch(vec![
+            Command::Remove {
+                path,
+                node: node.clone(),
+            },
+            Command::Add { path: to, node },
+        ]);
+        println!("Moved: {}", command.describe());
+        self.history.push(command);
+        self.edited(self.scene_code() != code);
+        self.select(id);
+    }
+
+    // The scene's part of the shader, to tell whether an edit changed it
+    fn scene_code(&self) -> String {
+        codegen::scene_source_with(&self.scene, Dialect::Wgsl, &ParamLayout::of(&self.scene))
+    }
+
+    // The current view, to share (see share.rs)
+    fn shared_view(&self) -> SharedView {
+        // As the scene file has them, to leave out the parameters left alone
+        let file = (self.scene_path.as_ref()).and_then(|path| Scene::load(path).ok());
+        let parameters = (self.scene.parameters().into_iter())
+            .filter_map(|target| {
+                let values = self.scene.parameter(&target)?;
+                let unchanged = file.as_ref().and_then(|file| file.parameter(&target));
+                (unchanged != Some(values)).then(|| (target, values.to_vec()))
+            })
+            .collect();
+        SharedView {
+            scene: self.scene.name.clone(),
+            path: (self.scene_path.as_ref())
+                .map_or(String::new(), |path| path.display().to_string()),
+            camera: [self.camera_angle, self.camera_distance],
+            mouse: self.mouse_pos.into(),
+            time: self.time,
+            parameters,
+        }
+    }
+
+    // Go to a shared view: its scene file afresh if it is here, then its camera, mouse, time
+    // and parameters
+    fn open_view(&mut self, view: &SharedView) -> Result<(), String> {
+        let path = Path::new(&view.path);
+        if !view.path.is_empty() && path.is_file() {
+            self.switch_scene(path)?;
+            if self.scene.name != view.scene {
+                eprintln!(
+                    "The view is of scene \"{}\", {} is now \"{}\"",
+                    view.scene, view.path, self.scene.name
+                );
+            }
+        } else if self.scene.name != view.scene {
+            return Err(format!(
+                "the view is of scene \"{}\" ({}); open it first",
+                view.scene,
+                if view.path.is_empty() {
+                    "not from a file"
+                } else {
+                    &view.path
+                }
+            ));
+        }
+
+        let mut rebuild = false;
+        for (target, values) in &view.parameters {
+            let fits = self.scene.parameter(target).map(<[f32]>::len) == Some(values.len());
+            if !fits {
+                eprintln!("The scene has no parameter {} like the view's", target);
+                continue;
+            }
+            match self.scene.make_live(target) {
+                Ok(changed) => rebuild |= changed,
+                Err(err) => {
+                    eprintln!("{}", err);
+                    continue;
+                }
+            }
+            // Within the inspector's limits, as set_parameter does
+            if let Some(parameter) = self.scene.parameter_mut(target) {
+                for (slot, &value) in parameter.iter_mut().zip(values) {
+                    *slot = inspector::limit(target, value);
+                }
+            }
+        }
+        if rebuild {
+            self.rebuild();
+        }
+        self.camera_tween = None;
+        self.camera_angle = view.camera[0];
+        self.camera_distance = view.camera[1].clamp(1.0, 20.0);
+        self.mouse_pos = view.mouse.into();
+        self.set_time(view.time);
+        println!("View of {} at {:.2}s", self.scene.name, view.time);
+        Ok(())
+    }
+
+    // Open the shared view in `link` (from --view or the clipboard)
+    fn open_link(&mut self, link: &str) {
+        if let Err(err) = SharedView::decode(link).and_then(|view| self.open_view(&view)) {
+            eprintln!("Couldn't open the view: {}", err);
+        }
+    }
+
+    fn save_scene(&self) {
+        let Some(path) = &self.scene_path else {
+            println!("This scene has no file to save to (S saves a workspace)");
+            return;
+        };
+        match self.scene.save(path) {
+            Ok(()) => println!("Saved scene to {}", path.display()),
+            Err(err) => eprintln!("Failed to save scene: {}", err),
+        }
+    }
+
+    // After an undo, redo or delete. When the shader needs rebuilding the scene's structure
+    // may have changed and object ids moved, so whatever refers to them is dropped.
+    fn edited(&mut self, rebuild: bool) {
+        if rebuild {
+            self.stroke = None;
+            self.select(None);
+            self.rebuild();
+        }
+    }
+
+    fn start_snap(&mut self) {
+        let (Some(id), Some(object)) = (self.selected, self.selected_object()) else {
+            return;
+        };
+        if object.name.is_empty() {
+            println!("Only named objects can be edited");
+            return;
+        }
+        let name = object.name.clone();
+        let start = [object.position, object.rotation];
+        let mut rebuild = false;
+        for property in ["position", "rotation"] {
+            match self.scene.make_live(&format!("{}.{}", name, property)) {
+                Ok(baked) => rebuild |= baked,
+                Err(err) => {
+                    eprintln!("{}", err);
+                    return;
+                }
+            }
+        }
+        if rebuild {
+            self.rebuild();
+        }
+        self.snapping = Some(Snap { id, name, start });
+    }
+
+    // Rest the snapping object on the surface under `mouse`, turned to its normal
+    fn snap_to(&mut self, mouse: Vector2<f32>) {
+        let Some(snap) = &self.snapping else {
+            return;
+        };
+        let Some(mut object) = self.scene.objects().get(snap.id).map(|&o| o.clone()) else {
+            return;
+        };
+        // Pick against everything but the object itself
+        let mut others = self.scene.clone();
+        if let Some(path) = others.object_path(snap.id) {
+            others.remove_at(&path);
+        }
+        let aspect = self.window_size.width as f32 / self.window_size.height.max(1) as f32;
+        let Some((_, point)) = picking::pick_point(
+            &others,
+            &self.camera,
+            mouse,
+            aspect,
+            self.quality.max_distance,
+        ) else {
+            return;
+        };
+        let normal = picking::normal(&others, point);
+        object.position = point.into();
+        object.rotation = picking::surface_rotation(normal);
+        let position: [f32; 3] = (point + normal * picking::extent(&object, -normal)).into();
+        for (property, value) in [("position", position), ("rotation", object.rotation)] {
+            let target = format!("{}.{}", snap.name, property);
+            if let Some(parameter) = self.scene.parameter_mut(&target) {
+                parameter.copy_from_slice(&value);
+            }
+        }
+    }
+
+    // Start dragging the inspector field under the cursor, or cycle the operation or toggle
+    // texturing when it is one of those
+    fn press_field(&mut self) {
+        let Some(field) = self.inspector.field_at(&self.view(), self.mouse_pos) else {
+            return;
+        };
+        match self.inspector.fields[field].kind.clone() {
+            FieldKind::Value {
+                target, component, ..
+            } => {
+                match self.scene.make_live(&target) {
+                    Ok(false) => {}
+                    Ok(true) => self.rebuild(),
+                    Err(err) => {
+                        eprintln!("{}", err);
+                        return;
+                    }
+                }
+                let start = self.scene.parameter(&target).unwrap_or(&[]).to_vec();
+                self.inspector.slide = Some(Slide {
+                    field,
+                    target,
+                    component,
+                    from: self.mouse_pos.x * self.window_size.width as f32,
+                    start,
+                    moved: false,
+                });
+            }
+            FieldKind::Op(op) => self.replace_selected(|object| object.op = inspector::next_op(op)),
+            FieldKind::Textured(on) => self.replace_selected(|object| object.textured = !on),
+        }
+    }
+
+    fn slide_to(&mut self, mouse: Vector2<f32>) {
+        let Some(slide) = self.inspector.slide.as_mut() else {
+            return;
+        };
+        let Some(value) = slide.value(mouse.x * self.window_size.width as f32) else {
+            return;
+        };
+        if let Some(parameter) = self.scene.parameter_mut(&slide.target) {
+            if let Some(component) = parameter.get_mut(slide.component) {
+                *component = value;
+            }
+        }
+    }
+
+    // Change the selected object in a way that regenerates the shader (its operation or
+    // texturing), as one undoable edit
+    fn replace_selected(&mut self, change: impl FnOnce(&mut SceneObject)) {
+        let Some(id) = self.selected else {
+            return;
+        };
+        let Some(path) = self.scene.object_path(id) else {
+            return;
+        };
+        let Some(Node::Object(mut object)) = self.scene.remove_at(&path) else {
+            return;
+        };
+        let before = Node::Object(object.clone());
+        change(&mut object);
+        let after = Node::Object(object);
+        self.scene.insert_at(&path, after.clone());
+        let command = Command::Batch(vec![
+            Command::Remove {
+                path: path.clone(),
+                node: before,
+            },
+            Command::Add { path, node: after },
+        ]);
+        println!("Changed: {}", command.describe());
+        self.history.push(command);
+        self.edited(true);
+        self.select(Some(id));
+    }
+
+    // Typed text goes to the command palette while it is open, else to the shader editor or
+    // the gallery while shown, else to the inspector value being entered, if any
+    fn type_char(&mut self, c: char) {
+        if self.palette.is_open() {
+            if let Some(action) = self.palette.type_char(c) {
+                self.perform(action);
+            }
+            return;
+        }
+        if self.editor.shown {
+            self.editor.type_char(c);
+            return;
+        }
+        if self.gallery.shown {
+            if let Some(path) = self.gallery.type_char(c) {
+                self.open_scene(&path);
+            }
+            return;
+        }
+        let Some(entry) = self.inspector.entry.as_mut() else {
+            return;
+        };
+        match c {
+            '\r' | '\n' => self.finish_entry(),
+            '\u{1b}' => self.inspector.entry = None,
+            '\u{8}' | '\u{7f}' => {
+                entry.text.pop();
+            }
+            '0'..='9' | '.' | '-' => entry.text.push(c),
+            _ => {}
+        }
+    }
+
+    // Set the value typed into the inspector
+    fn finish_entry(&mut self) {
+        let Some(entry) = self.inspector.entry.take() else {
+            return;
+        };
+        let Some(FieldKind::Value {
+            target, component, ..
+        }) = self
+            .inspector
+            .fields
+            .get(entry.field)
+            .map(|field| field.kind.clone())
+        else {
+            return;
+        };
+        let Ok(value) = entry.text.parse::<f32>() else {
+            println!("Not a number: {}", entry.text);
+            return;
+        };
+        let rebuild = match self.scene.make_live(&target) {
+            Ok(rebuild) => rebuild,
+            Err(err) => {
+                eprintln!("{}", err);
+                return;
+            }
+        };
+        let Some(parameter) = self.scene.parameter_mut(&target) else {
+            return;
+        };
+        let before = parameter.to_vec();
+        if let Some(slot) = parameter.get_mut(component) {
+            *slot = inspector::limit(&target, value);
+        }
+        let after = parameter.to_vec();
+        println!("{} = {:?}", target, after);
+        self.history.push(Command::Set {
+            target,
+            before,
+            after,
+        });
+        if rebuild {
+            self.rebuild();
+        }
+    }
+
+    // Mouse button released: finish dragging or the brush stroke. A click on an inspector
+    // value that did not drag it starts typing a new one.
+    fn release(&mut self) {
+        if let Some(slide) = self.inspector.slide.take() {
+            let value = self.scene.parameter(&slide.target).unwrap_or(&[]).to_vec();
+            if !slide.moved {
+                self.inspector.entry = Some(inspector::Entry {
+                    field: slide.field,
+                    text: String::new(),
+                });
+            } else if value != slide.start {
+                println!("{} = {:?}", slide.target, value);
+                self.history.push(Command::Set {
+                    target: slide.target,
+                    before: slide.start,
+                    after: value,
+                });
+            }
+        }
+        if let Some(stroke) = self.stroke.take() {
+            let command = Command::Batch(stroke.edits);
+            println!("Sculpted: {}", command.describe());
+            self.history.push(command);
+        }
+        if let Some(snap) = self.snapping.take() {
+            let mut edits = Vec::new();
+            for (property, before) in ["position", "rotation"].into_iter().zip(snap.start) {
+                let target = format!("{}.{}", snap.name, property);
+                let after = self.scene.parameter(&target).unwrap_or(&[]).to_vec();
+                println!("{} = {:?}", target, after);
+                if after != before {
+                    edits.push(Command::Set {
+                        target,
+                        before: before.to_vec(),
+                        after,
+                    });
+                }
+            }
+            if !edits.is_empty() {
+                self.history.push(Command::Batch(edits));
+            }
+        }
+        if let Some(drag) = self.drag.take() {
+            let value = self.scene.parameter(&drag.target).unwrap_or(&[]).to_vec();
+            println!("{} = {:?}", drag.target, value);
+            if value != drag.start {
+                self.history.push(Command::Set {
+                    target: drag.target,
+                    before: drag.start,
+                    after: value,
+                });
+            }
+        }
+    }
+
+    // The light clicked on, if it is still in the scene
+    fn selected_light(&self) -> Option<&PointLight> {
+        self.scene.lights.get(self.selected_light?)
+    }
+
+    // The object clicked on, if it is still in the scene
+    fn selected_object(&self) -> Option<&SceneObject> {
+        self.scene.objects().get(self.selected?).copied()
+    }
+
+    fn perform(&mut self, action: Action) {
+        match action {
+            Action::OpenPalette if self.palette.is_open() => self.palette.close(),
+            Action::OpenPalette => {
+                self.inspector.entry = None;
+                self.palette.open(self.palette_commands());
+            }
+            Action::Move(Motion::Up) if self.palette.is_open() => self.palette.select(-1),
+            Action::Move(Motion::Down) if self.palette.is_open() => self.palette.select(1),
+            Action::Move(_) if self.palette.is_open() => {}
+            Action::Move(motion) if self.editor.shown => self.editor.move_cursor(motion),
+            Action::Move(motion) if self.gallery.shown => {
+                self.gallery.move_highlight(motion, &self.view());
+            }
+            Action::ToggleGallery if self.gallery.shown => self.gallery.shown = false,
+            Action::ToggleGallery => {
+                self.inspector.entry = None;
+                self.gallery.show(self.scene_path.as_deref());
+            }
+            Action::ToggleEditor if self.editor.shown => self.editor.shown = false,
+            Action::ToggleEditor => match self.backend.shader_template() {
+                Some(template) => {
+                    self.inspector.entry = None;
+                    self.editor.show(&template);
+                }
+                None => println!("The shader editor needs the Metal backend"),
+            },
+            Action::ApplyShader if self.editor.shown => {
+                let applied = self.backend.set_shader(self.editor.text(), &self.scene);
+                match &applied {
+                    Ok(()) => println!("Shader applied"),
+                    Err(err) => eprintln!("Keeping the current shader: {}", err),
+                }
+                self.editor.applied(applied);
+            }
+            _ => {}
+        }
+        if self.typing() {
+            return;
+        }
+        match action {
+            Action::ResetCamera => {
+                // Turn back the short way round
+                let angle = (self.camera_angle + PI).rem_euclid(TAU) - PI;
+                self.camera_tween = Some(Tween::new(
+                    [angle, self.camera_distance],
+                    [0.0, 5.0],
+                    self.time,
+                    self.tween.reset_camera,
+                    self.tween.easing,
+                ));
+                println!("Reset camera");
+            }
+            Action::ExportShaders => match export::write_all(&self.scene, &self.capture_dir) {
+                Ok(paths) => println!("Exported scene shaders: {:?}", paths),
+                Err(err) => eprintln!("Shader export failed: {}", err),
+            },
+            Action::CycleQuality => self.set_preset(self.preset.next()),
+            Action::CycleGizmo => {
+                self.gizmo_mode = self.gizmo_mode.next();
+                self.hovered = None;
+                println!("Gizmo: {:?}", self.gizmo_mode);
+            }
+            Action::CycleBrush => {
+                self.brush.mode = self.brush.mode.next();
+                self.stroke = None;
+                println!("Brush: {:?}", self.brush.mode);
+            }
+            Action::BrushSmaller | Action::BrushLarger => {
+                let factor = if action == Action::BrushLarger {
+                    1.25
+                } else {
+                    0.8
+                };
+                self.brush.scale_size(factor);
+                println!("Brush size: {:.3}", self.brush.size);
+            }
+            Action::BlendLess | Action::BlendMore => {
+                let factor = if action == Action::BlendMore {
+                    1.25
+                } else {
+                    0.8
+                };
+                self.brush.scale_blend(factor);
+                println!("Brush blend: {:.3}", self.brush.blend);
+            }
+            Action::DeleteSelected => self.delete_selected(),
+            Action::ToggleInspector => {
+                self.inspector.shown = !self.inspector.shown;
+                self.inspector.slide = None;
+                self.inspector.entry = None;
+            }
+            Action::ToggleSnap => {
+                self.snap = !self.snap;
+                self.snapping = None;
+                println!("Snap to surfaces: {}", if self.snap { "on" } else { "off" });
+            }
+            Action::TogglePathTracing => self.set_path_tracing(!self.path_tracing),
+            #[cfg(feature = "offline")]
+            Action::DumpPasses => match self.dump_passes() {
+                Ok(dir) => println!("Dumped the frame's passes to {}", dir.display()),
+                Err(err) => eprintln!("Failed to dump the passes: {}", err),
+            },
+            #[cfg(not(feature = "offline"))]
+            Action::DumpPasses => eprintln!("Dumping passes needs the \"offline\" feature"),
+            Action::ToggleDenoise => {
+                self.denoise = !self.denoise;
+                self.backend.set_denoise(self.denoise);
+                println!("Denoise: {}", if self.denoise { "on" } else { "off" });
+            }
+            Action::ToggleGuides => {
+                self.guides = !self.guides;
+                self.backend.set_guides(self.guides);
+                println!("Grid: {}", if self.guides { "on" } else { "off" });
+            }
+            Action::Undo => match self.history.undo(&mut self.scene) {
+                Some((command, rebuild)) => {
+                    println!("Undo: {}", command.describe());
+                    self.edited(rebuild);
+                }
+                None => println!("Nothing to undo"),
+            },
+            Action::Redo => match self.history.redo(&mut self.scene) {
+                Some((command, rebuild)) => {
+                    println!("Redo: {}", command.describe());
+                    self.edited(rebuild);
+                }
+                None => println!("Nothing to redo"),
+            },
+            Action::Duplicate => self.duplicate_selected(),
+            Action::MoveEarlier => self.move_selected(-1),
+            Action::MoveLater => self.move_selected(1),
+            Action::SaveScene => self.save_scene(),
+            Action::CopyView => {
+                let link = self.shared_view().encode();
+                println!("View: {}", link);
+                match share::copy(&link) {
+                    Ok(()) => println!("Copied the view to the clipboard"),
+                    Err(err) => eprintln!("Couldn't copy the view: {}", err),
+                }
+            }
+            Action::PasteView => match share::paste() {
+                Ok(text) => self.open_link(&text),
+                Err(err) => eprintln!("Couldn't paste a view: {}", err),
+            },
+            Action::SaveWorkspace => self.save_workspace(None),
+            Action::TapTempo => {
+                self.clock.tap(self.start_time.elapsed().as_secs_f32());
+                println!("Tempo: {:.1} BPM", self.clock.bpm());
+            }
+            Action::OpenPalette
+            | Action::ToggleEditor
+            | Action::ApplyShader
+            | Action::ToggleGallery
+            | Action::Move(_) => {}
+            Action::Cue(index) => {
+                let Some(cue) = self.scene.cues.get(index) else {
+                    return;
+                };
+                // Due on the scene's clock, which fire_cues goes by
+                self.cues
+                    .push((self.clock.next(self.time, cue.quantize), cue.clone()));
+            }
+        }
+    }
+
+    // Keys are text while a value, a command or the shader is being typed in, or a scene picked
+    fn typing(&self) -> bool {
+        self.inspector.entry.is_some()
+            || self.palette.is_open()
+            || self.editor.shown
+            || self.gallery.shown
+    }
+
+    // Every command, for the palette: the scene's cues (switching scenes or setting values),
+    // then the keyboard's actions
+    fn palette_commands(&self) -> Vec<palette::Command<Action>> {
+        let cues = self.scene.cues.iter().enumerate().map(|(index, cue)| {
+            let label = match (&cue.scene, cue.name.as_str()) {
+                (Some(scene), _) => format!("Switch to scene {}", scene.display()),
+                (None, "") => format!("Cue {}", index + 1),
+                (None, name) => format!("Cue {}", name),
+            };
+            (Action::Cue(index), label)
+        });
+        let actions = (Action::ALL.iter().chain(&Action::EDITING))
+            .map(|&action| (action, action.label().to_string()));
+        cues.chain(actions)
+            .map(|(action, label)| palette::Command {
+                action,
+                label,
+                shortcut: self.keybindings.shortcut(action).unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    // Everything needed to pick up from here, with the window's layout when there is one
+    fn workspace(&self, layout: Option<Layout>) -> Workspace {
+        Workspace {
+            time: self.time,
+            camera_angle: self.camera_angle,
+            camera_distance: self.camera_distance,
+            mouse: self.mouse_pos.into(),
+            preset: self.preset,
+            bpm: self.clock.bpm(),
+            downbeat: self.clock.downbeat(),
+            layout,
+            live: self.scene.scripted.clone(),
+            scene: self.scene.clone(),
+        }
+    }
+
+    // S: save to <capture_dir>/<scene>.workspace.toml
+    fn save_workspace(&self, layout: Option<Layout>) {
+        let path = self
+            .capture_dir
+            .join(format!("{}.workspace.toml", self.scene.name));
+        match self.workspace(layout).save(&path) {
+            Ok(()) => println!("Saved workspace to {}", path.display()),
+            Err(err) => eprintln!("Failed to save workspace: {}", err),
+        }
+    }
+
+    // Continue a saved workspace (its scene, quality and layout are set up before App::new)
+    fn restore(&mut self, workspace: &Workspace) {
+        self.set_time(workspace.time);
+        self.camera_angle = workspace.camera_angle;
+        self.camera_distance = workspace.camera_distance.clamp(1.0, 20.0);
+        self.mouse_pos = workspace.mouse.into();
+        self.clock.set_tempo(workspace.bpm, workspace.downbeat);
+    }
+
+    // Pick up the camera and panels the last session ended with (see config.rs). A camera that
+    // isn't finite is left at the default.
+    fn recall(&mut self, config: &Config) {
+        let camera = (config.camera).filter(|camera| camera.iter().all(|value| value.is_finite()));
+        if let Some([angle, distance]) = camera {
+            self.camera_angle = angle;
+            self.camera_distance = distance.clamp(1.0, 20.0);
+        }
+        self.inspector.shown = config.panels.inspector;
+        self.guides = config.panels.guides;
+        self.backend.set_guides(self.guides);
+    }
+
+    // Carry on from scene time `time`, or from now when the clock can't go back that far
+    fn set_time(&mut self, time: f32) {
+        self.time = time;
+        self.start_time = Duration::try_from_secs_f32(time.max(0.0))
+            .ok()
+            .and_then(|elapsed| Instant::now().checked_sub(elapsed))
+            .unwrap_or_else(Instant::now);
+    }
+
+    fn set_preset(&mut self, preset: QualityPreset) {
+        self.preset = preset;
+        let mut settings = preset.settings();
+        if let Some(aa_mode) = self.aa_mode {
+            settings.aa_mode = aa_mode;
+        }
+        self.quality_tween = Some(Tween::new(
+            self.quality.clone(),
+            settings,
+            self.time,
+            self.tween.cycle_quality,
+            self.tween.easing,
+        ));
+        self.render_scale = self.base_scale * preset.render_scale();
+        self.resize(self.window_size);
+        println!("Quality: {:?}", preset);
+    }
+
+    fn handle_scroll(&mut self, delta: f32) {
+        if self.gallery.shown {
+            self.gallery.scroll(delta, &self.view());
+            return;
+        }
+        self.camera_tween = None;
+        self.camera_distance = (self.camera_distance - delta * 0.5).clamp(1.0, 20.0);
+        // Inverted delta for natural scroll
+        // println!("Scroll: {:.2}, Camera Distance: {:.2}", delta, self.camera_distance);
+    }
+}
+
+// The scene's script, if it has one that loads
+#[cfg(feature = "scripting")]
+fn load_script(scene: &Scene) -> Option<Script> {
+    let path = scene.script.as_ref()?;
+    Script::load(path, scene)
+        .map_err(|err| eprintln!("{}", err))
+        .ok()
+}
+
+// Window size scaled by the render scale (what the drawable and shader resolution use)
+fn scaled_size(size: winit::dpi::PhysicalSize<u32>, scale: f32) -> winit::dpi::PhysicalSize<u32> {
+    winit::dpi::PhysicalSize::new(
+        ((size.width as f32 * scale).round() as u32).max(1),
+        ((size.height as f32 * scale).round() as u32).max(1),
+    )
+}
+
+// What a frame read back without waiting for the GPU is for (see App::read_back)
+#[cfg(feature = "offline")]
+enum Readback {
+    Capture(std::sync::mpsc::Sender<Reply>), // A remote client's capture, answered with its path
+    Frame(std::sync::mpsc::Sender<Answer>),  // /frame.png
+    Converged(PathBuf),                      // The path-traced image (see save_converged)
+}
+
+// A frame served over HTTP
+#[cfg(feature = "offline")]
+fn png_response(size: winit::dpi::PhysicalSize<u32>, pixels: &[u8]) -> Answer {
+    offline::encode_png(size.width, size.height, pixels, &[]).map(|png| Response {
+        content_type: "image/png",
+        body: png,
+    })
+}
+
+// The window's size and position as a workspace saves them
+fn layout(window: &winit::window::Window) -> Layout {
+    let size = window.inner_size().to_logical::<u32>(window.scale_factor());
+    Layout {
+        width: size.width,
+        height: size.height,
+        position: window.outer_position().ok().map(|p| [p.x, p.y]),
+        fullscreen: window.fullscreen().is_some(),
+    }
+}
+
+// Where to open the window: where it last was while that is still on a display, otherwise on
+// the display it was on if that is still connected (None leaves it to the system)
+fn window_position(
+    event_loop: &EventLoop<()>,
+    config: &Config,
+) -> Option<winit::dpi::PhysicalPosition<i32>> {
+    let monitors: Vec<_> = event_loop.available_monitors().collect();
+    let inside = |monitor: &winit::monitor::MonitorHandle, [x, y]: [i32; 2]| {
+        let (origin, size) = (monitor.position(), monitor.size());
+        (origin.x..origin.x + size.width as i32).contains(&x)
+            && (origin.y..origin.y + size.height as i32).contains(&y)
+    };
+    let named = (config.monitor.as_ref())
+        .and_then(|name| monitors.iter().find(|m| m.name().as_ref() == Some(name)));
+    match (config.position, named) {
+        (Some(position), _) if monitors.iter().any(|m| inside(m, position)) => {
+            Some(position.into())
+        }
+        (_, Some(monitor)) => {
+            let origin = monitor.position();
+            Some(winit::dpi::PhysicalPosition::new(
+                origin.x + 50,
+                origin.y + 50,
+            ))
+        }
+        _ => None,
+    }
+}
+
+// Run the shader test (see shader_test.rs) for each of `scenes`, reporting differences;
+// whether they all passed
+fn test_shaders(config: &Config, scenes: &[Scene]) -> bool {
+    let kind = config.backend.unwrap_or_else(BackendKind::platform_default);
+    let mut backend = backend::create(kind, None, config, &scenes[0]).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+    let probes = shader_test::probes();
+    let mut passed = true;
+    for scene in scenes {
+        let differences = match backend.test_shader(scene, &probes) {
+            Ok(results) => shader_test::compare(scene, &probes, &results),
+            Err(err) => vec![err],
+        };
+        if differences.is_empty() {
+            println!("{}: passed", scene.name);
+        } else {
+            println!("{}: failed", scene.name);
+            for difference in differences {
+                println!("  {}", difference);
+            }
+            passed = false;
+        }
+    }
+    passed
+}
+
+fn main() {
+    let cli = Cli::parse();
+    // Before anything is printed
+    #[cfg(feature = "offline")]
+    if cli.control.is_some() {
+        control::take_stdout();
+    }
+    #[cfg(feature = "offline")]
+    if let Some(address) = &cli.worker {
+        if let Err(err) = distributed::work(address, &cli) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let (mut config, mut stored) = Config::from_cli(&cli).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+    report::install(&config.capture_dir);
+
+    let workspace = cli.restore.as_ref().map(|path| {
+        let path = path
+            .clone()
+            .or_else(|| Workspace::exit_path(Config::save_path(&cli).as_deref()))
+            .unwrap_or_else(|| {
+                eprintln!("No workspace saved on exit to restore");
+                std::process::exit(1);
+            });
+        let workspace = Workspace::load(&path).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        });
+        println!("Restoring workspace {}", path.display());
+        config.preset = Some(workspace.preset);
+        config.quality = None;
+        if let Some(layout) = &workspace.layout {
+            config.width = layout.width;
+            config.height = layout.height;
+            config.position = layout.position;
+            config.fullscreen = layout.fullscreen;
+        }
+        workspace
+    });
+    let replay = cli.replay.as_ref().map(|path| {
+        Replay::new(Session::load(path).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }))
+    });
+    let sequencer = cli.sequence.as_ref().map(|path| {
+        let mut sequence = Sequence::load(path).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        });
+        let scenes = sequence.scenes().unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        });
+        println!(
+            "Sequence {}: {} entries, {:.1}s",
+            sequence.name,
+            scenes.len(),
+            sequence.duration()
+        );
+        Sequencer {
+            sequence,
+            scenes,
+            entry: 0,
+            fade: None,
+        }
+    });
+    let (scene, scene_path) = match (&workspace, &replay, &sequencer, &config.scene) {
+        (Some(workspace), _, _, _) => (workspace.scene.clone(), None),
+        (None, Some(replay), _, _) => (replay.first_scene().clone(), None),
+        (None, None, Some(sequencer), _) => (sequencer.scenes[0].clone(), None),
+        (None, None, None, Some(path)) => {
+            let scene = Scene::load(path).unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            });
+            (scene, Some(path.clone()))
+        }
+        (None, None, None, None) => (Scene::default(), None),
+    };
+    #[cfg(not(feature = "scripting"))]
+    if let Some(path) = &scene.script {
+        eprintln!(
+            "Ignoring script {} (built without the \"scripting\" feature)",
+            path.display()
+        );
+    }
+
+    if cli.test_shaders {
+        let scenes = match &sequencer {
+            Some(sequencer) => sequencer.scenes.clone(),
+            None => vec![scene],
+        };
+        std::process::exit(if test_shaders(&config, &scenes) { 0 } else { 1 });
+    }
+
+    // Offline rendering and control over stdio: no window or event loop
+    #[cfg(feature = "offline")]
+    if cli.render.is_some() || cli.control.is_some() {
+        let started = Instant::now();
+        let job = format!("Render of {}", scene.name);
+        let report_done = |outcome: Result<&Path, &str>| {
+            if config.notify {
+                status::report_done(&job, started, outcome);
+            }
+        };
+        if let (Some(dir), Some(address)) = (&cli.render, cli.distribute) {
+            let sequence = sequencer.as_ref().map(|sequencer| &sequencer.sequence);
+            let frames = offline::frame_times(sequence, replay.as_ref(), cli.frames).len();
+            let range = cli.frame_range.clone().unwrap_or(0..frames);
+            let distributed = if range.end > frames {
+                Err(format!("There are only {} frames to render", frames))
+            } else {
+                distributed::coordinate(address, &config, dir, range)
+            };
+            report_done(
+                distributed
+                    .as_ref()
+                    .map(|()| dir.as_path())
+                    .map_err(String::as_str),
+            );
+            if let Err(err) = distributed {
+                eprintln!("Distributed render failed: {}", err);
+                std::process::exit(1);
+            }
+            return;
+        }
+        let mut app = App::new(None, &config, scene);
+        app.scene_path = scene_path.clone();
+        if let Some(workspace) = &workspace {
+            app.restore(workspace);
+        }
+        app.start_session(&cli, replay);
+        app.sequencer = sequencer;
+        if let Some(link) = &cli.view {
+            app.open_link(link);
+        }
+        let Some(dir) = &cli.render else {
+            report::guard(&mut app, control::serve);
+            return;
+        };
+        let requests = config.http.and_then(|port| {
+            let (sender, receiver) = std::sync::mpsc::channel();
+            (http::serve(port, sender))
+                .map_err(|err| eprintln!("{}", err))
+                .ok()
+                .map(|()| receiver)
+        });
+        let rendered = report::guard(&mut app, |app| {
+            let range = cli.frame_range.clone();
+            offline::render_frames(app, &config, dir, cli.frames, range, requests.as_ref())
+        });
+        report_done(
+            rendered
+                .as_ref()
+                .map(|()| dir.as_path())
+                .map_err(String::as_str),
+        );
+        if let Err(err) = rendered {
+            eprintln!("Offline render failed: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let event_loop = EventLoop::new();
+    let mut builder = WindowBuilder::new()
+        .with_title(status::APP_NAME)
+        .with_window_icon(status::window_icon())
+        .with_inner_size(winit::dpi::LogicalSize::new(config.width, config.height))
+        .with_fullscreen(config.fullscreen.then_some(Fullscreen::Borderless(None)));
+    if let Some(position) = window_position(&event_loop, &config) {
+        builder = builder.with_position(position);
+    }
+    let window = builder.build(&event_loop).unwrap();
+    status::set_app_icon();
+
+    let keybindings = Keybindings::load(config.keybindings.as_deref());
+    let mut touches = Touches::default();
+    let mut modifiers = ModifiersState::empty();
+    let save_path = Config::save_path(&cli);
+    let workspace_path = Workspace::exit_path(save_path.as_deref());
+
+    let mut app = App::new(Some(&window), &config, scene);
+    app.scene_path = scene_path;
+    app.recall(&config);
+    app.keybindings = keybindings.clone();
+    app.drop_folder = config.watch.as_deref().map(DropFolder::new);
+    app.frame_sync = FrameSync::start(&config.sync);
+    app.artnet = (config.artnet.as_deref())
+        .and_then(|path| ArtNet::new(path).map_err(|err| eprintln!("{}", err)).ok());
+    if let Some(workspace) = &workspace {
+        app.restore(workspace);
+    }
+    app.start_session(&cli, replay);
+    app.sequencer = sequencer;
+    if let Some(link) = &cli.view {
+        app.open_link(link);
+    }
+    let start_preset = app.preset;
+    let mut renderer = Some(RenderThread::spawn(app, config.present.rate));
+    // The display the window is on, for the render thread to pace frames by
+    #[cfg(target_os = "macos")]
+    let mut display = None;
+    if let (Some(port), Some(render_thread)) = (config.remote, &renderer) {
+        if let Err(err) = remote::serve(port, render_thread.sender()) {
+            eprintln!("{}", err);
+        }
+    }
+    if let (Some(port), Some(render_thread)) = (config.http, &renderer) {
+        if let Err(err) = http::serve(port, render_thread.sender()) {
+            eprintln!("{}", err);
+        }
+    }
+    if let (Some(address), Some(render_thread)) = (config.livecode, &renderer) {
+        if let Err(err) = livecode::serve(address, render_thread.sender()) {
+            eprintln!("{}", err);
+        }
+    }
+
+    event_loop.run(move |event, _, control_flow| {
+        // Woken now and then to keep the title's status current
+        *control_flow = ControlFlow::WaitUntil(Instant::now() + status::TITLE_INTERVAL);
+        let Some(render_thread) = &renderer else {
+            return;
+        };
+        if let Some(title) = status::title() {
+            window.set_title(&title);
+        }
+        #[cfg(target_os = "macos")]
+        {
+            use winit::platform::macos::MonitorHandleExtMacOS;
+            let current = window.current_monitor().map(|monitor| monitor.native_id());
+            if let Some(id) = current.filter(|&id| display != Some(id)) {
+                display = Some(id);
+                render_thread.send(Message::Display(id));
+            }
+        }
+        let Event::WindowEvent { event, .. } = event else {
+            return;
+        };
+
+        match event {
+            WindowEvent::CloseRequested => {
+                let app = renderer.take().unwrap().stop();
+                // Remember this session's window, camera, panels and scene for the next run
+                if let Some(path) = &save_path {
+                    let size = window.inner_size().to_logical::<u32>(window.scale_factor());
+                    stored.width = size.width;
+                    stored.height = size.height;
+                    stored.position = window.outer_position().ok().map(|p| [p.x, p.y]);
+                    stored.monitor = window.current_monitor().and_then(|m| m.name());
+                    stored.scene = app.scene_path.clone().or_else(|| config.scene.clone());
+                    stored.camera = Some([app.camera_angle, app.camera_distance]);
+                    stored.panels = Panels {
+                        inspector: app.inspector.shown,
+                        guides: app.guides,
+                    };
+                    if app.preset != start_preset {
+                        stored.preset = Some(app.preset);
+                        stored.quality = None;
+                    }
+                    if let Err(err) = stored.save(path) {
+                        eprintln!("Failed to save config: {}", err);
+                    }
+                }
+                if let Some(path) = &workspace_path {
+                    if let Err(err) = app.workspace(Some(layout(&window))).save(path) {
+                        eprintln!("Failed to save workspace: {}", err);
+                    }
+                }
+                *control_flow = ControlFlow::Exit;
+            }
+            WindowEvent::ModifiersChanged(state) => modifiers = state,
+            WindowEvent::Resized(size) => render_thread.send(Message::Resize(size)),
+            WindowEvent::CursorMoved { position, .. } => {
+                render_thread.send(Message::CursorMoved(position));
+            }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => render_thread.send(match state {
+                ElementState::Pressed => Message::Click {
+                    alt: modifiers.alt(),
+                },
+                ElementState::Released => Message::Release,
+            }),
+            WindowEvent::MouseWheel {
+                delta: winit::event::MouseScrollDelta::LineDelta(_, y),
+                ..
+            } => render_thread.send(Message::Scroll(y)),
+            // Not with Cmd held, where the key is a shortcut (Cmd+Enter in the shader editor)
+            WindowEvent::ReceivedCharacter(c) if !keybindings::command(modifiers) => {
+                render_thread.send(Message::Char(c));
+            }
+            WindowEvent::Touch(touch) => match touches.handle(&touch) {
+                Some(Gesture::Point(position)) => {
+                    render_thread.send(Message::CursorMoved(position));
+                }
+                Some(Gesture::Zoom(lines)) => render_thread.send(Message::Scroll(lines)),
+                None => {}
+            },
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } => {
+                match keybindings.action(key, modifiers) {
+                    // The render thread can't see the window, so pass its layout along
+                    Some(Action::SaveWorkspace) => {
+                        render_thread.send(Message::SaveWorkspace(layout(&window)));
+                    }
+                    Some(action) => render_thread.send(Message::Action(action)),
+                    None => {}
                 }
-                _ => {}
-            },
-            Event::MainEventsCleared => {
-                app.update(window.inner_size());
-                app.render();
-                window.request_redraw();
             }
             _ => {}
         }
@@ -0,0 +1,244 @@
+// Triangle meshes drawn among the SDF shapes, by the scene's [[meshes]] tables:
+//
+//   [[meshes]]
+//   file = "teapot.obj"          # Wavefront OBJ
+//   position = [0.0, 0.5, 0.0]
+//   rotation = [0.0, 45.0, 0.0]  # Degrees, applied X, then Y, then Z, like objects
+//   scale = 0.5                  # Uniform
+//   color = [0.8, 0.6, 0.3]
+//
+// They aren't marched: the Metal renderer puts the triangles of every mesh, placed in the
+// world, into one acceleration structure and ray traces it in hardware (see meshes.metal,
+// spliced into the built-in shader only). Each of a pixel's camera rays, every one of its
+// anti-aliasing grid or four across a cone traced one, is intersected with it after the scene
+// is marched, and where a triangle is nearer than the scene's first surface the ray sees the
+// mesh, flat shaded by the sun (shadowed by the scene and the meshes) and the point lights,
+// and fogged like the scene. Meshes are seen along the camera's own rays, so not through
+// portals, glass or reflections, and a volume is drawn over them; they cast no shadows on the
+// scene. Path tracing and the wgpu renderer leave them out, as do devices
+// without ray tracing.
+//
+// Only the file's vertices (`v`) and faces (`f`) are read, faces with more than three corners
+// fanned into triangles; normals, texture coordinates, groups and materials are skipped. A
+// corner may index its vertex from the end (-1 for the last read), and the vertex may be
+// followed by its texture coordinate and normal ("3/1/2", "3//2"), which are ignored.
+use cgmath::{Deg, Matrix3, Vector3};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+// Most triangles all of a scene's meshes may have
+pub const MAX_TRIANGLES: usize = 1 << 20;
+
+// The corners of a triangle
+pub type Triangle = [[f32; 3]; 3];
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Mesh {
+    pub file: PathBuf, // Relative to the scene file
+    pub position: [f32; 3],
+    pub rotation: [f32; 3], // Euler angles in degrees, applied X, then Y, then Z
+    pub scale: f32,         // Uniform
+    pub color: [f32; 3],
+}
+
+impl Default for Mesh {
+    fn default() -> Self {
+        Self {
+            file: PathBuf::new(),
+            position: [0.0; 3],
+            rotation: [0.0; 3],
+            scale: 1.0,
+            color: [0.8, 0.8, 0.8],
+        }
+    }
+}
+
+impl Mesh {
+    // Check the mesh can be placed: a file and a finite placement at a size above 0
+    pub fn validate(&self) -> Result<(), String> {
+        if self.file.as_os_str().is_empty() {
+            return Err("needs a file".to_string());
+        }
+        let mut values = (self.position.iter())
+            .chain(&self.rotation)
+            .chain(&self.color)
+            .chain([&self.scale]);
+        if values.any(|value| !value.is_finite()) {
+            return Err("position, rotation, scale and color must be finite".to_string());
+        }
+        if self.scale <= 0.0 {
+            return Err("scale must be above 0".to_string());
+        }
+        Ok(())
+    }
+
+    // The triangles of the mesh's file, placed in the world
+    pub fn load(&self) -> Result<Vec<Triangle>, String> {
+        let path = &self.file;
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+        let triangles =
+            parse_obj(&text).map_err(|err| format!("Invalid mesh {}: {}", path.display(), err))?;
+        let [x, y, z] = self.rotation;
+        let rotation = Matrix3::from_angle_z(Deg(z))
+            * Matrix3::from_angle_y(Deg(y))
+            * Matrix3::from_angle_x(Deg(x));
+        let position = Vector3::from(self.position);
+        let place =
+            |corner: [f32; 3]| (rotation * Vector3::from(corner) * self.scale + position).into();
+        Ok((triangles.into_iter())
+            .map(|triangle| triangle.map(place))
+            .collect())
+    }
+}
+
+// The triangles of an OBJ file's faces, as they are in the file
+pub fn parse_obj(text: &str) -> Result<Vec<Triangle>, String> {
+    let mut vertices: Vec<[f32; 3]> = Vec::new();
+    let mut triangles = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let error = |err: &str| format!("line {}: {}", number + 1, err);
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("v") => {
+                let coordinates: Vec<f32> = (words.take(3))
+                    .map(|word| word.parse().map_err(|_| error("invalid vertex")))
+                    .collect::<Result<_, _>>()?;
+                match coordinates[..] {
+                    [x, y, z] if coordinates.iter().all(|c| c.is_finite()) => {
+                        vertices.push([x, y, z])
+                    }
+                    _ => return Err(error("a vertex needs three finite coordinates")),
+                }
+            }
+            Some("f") => {
+                let corners: Vec<[f32; 3]> = words
+                    .map(|word| {
+                        let index = word.split('/').next().unwrap_or_default();
+                        let index: i64 = index.parse().map_err(|_| error("invalid face"))?;
+                        let index = match index {
+                            1.. => index - 1,
+                            ..=-1 => vertices.len() as i64 + index,
+                            0 => -1,
+                        };
+                        (usize::try_from(index).ok())
+                            .and_then(|index| vertices.get(index).copied())
+                            .ok_or_else(|| error("a face names a vertex not read yet"))
+                    })
+                    .collect::<Result<_, _>>()?;
+                if corners.len() < 3 {
+                    return Err(error("a face needs at least three corners"));
+                }
+                for pair in corners[1..].windows(2) {
+                    triangles.push([corners[0], pair[0], pair[1]]);
+                }
+                if triangles.len() > MAX_TRIANGLES {
+                    return Err(format!(
+                        "over {} triangles, the most supported",
+                        MAX_TRIANGLES
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+    if triangles.is_empty() {
+        return Err("no faces".to_string());
+    }
+    Ok(triangles)
+}
+
+// The triangles of a scene's meshes as the renderer uploads them
+#[derive(Default)]
+pub struct MeshData {
+    pub triangles: Vec<Triangle>, // In the world
+    pub colors: Vec<[f32; 3]>,    // Of each triangle
+}
+
+impl MeshData {
+    // The scene's meshes, leaving out any that can't be read, up to MAX_TRIANGLES in all
+    pub fn of(meshes: &[Mesh]) -> Self {
+        let mut data = Self::default();
+        for mesh in meshes {
+            match mesh.load() {
+                Ok(triangles) if data.triangles.len() + triangles.len() > MAX_TRIANGLES => {
+                    eprintln!(
+                        "Mesh {} left out: the scene's meshes have over {} triangles",
+                        mesh.file.display(),
+                        MAX_TRIANGLES
+                    );
+                }
+                Ok(triangles) => {
+                    let colors = std::iter::repeat_n(mesh.color, triangles.len());
+                    data.colors.extend(colors);
+                    data.triangles.extend(triangles);
+                }
+                Err(err) => eprintln!("{}", err),
+            }
+        }
+        data
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.triangles.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn faces_are_fanned_into_triangles() {
+        let text = "\
+# A unit square and a triangle, indexed from the start and the end
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vn 0 0 1
+f 1/1/1 2/1/1 3//1 4
+f -4 -3 -1
+";
+        let triangles = parse_obj(text).unwrap();
+        assert_eq!(
+            triangles,
+            vec![
+                [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0]],
+                [[0.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]],
+                [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+            ]
+        );
+        for text in [
+            "v 0 0 0\nf 1 2 3\n",
+            "v 0 0\n",
+            "v 0 0 0\nf 1 1\n",
+            "f 0 1 2\n",
+            "",
+        ] {
+            assert!(parse_obj(text).is_err(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn meshes_are_placed_like_objects() {
+        let path = std::env::temp_dir().join("meshes_are_placed_like_objects.obj");
+        std::fs::write(&path, "v 1 0 0\nv 0 1 0\nv 0 0 1\nf 1 2 3\n").unwrap();
+        let mesh = Mesh {
+            file: path.clone(),
+            position: [0.0, 1.0, 0.0],
+            rotation: [0.0, 90.0, 0.0],
+            scale: 2.0,
+            ..Mesh::default()
+        };
+        let [a, b, c] = mesh.load().unwrap()[0];
+        std::fs::remove_file(path).unwrap();
+        // Turning 90 degrees about Y takes +X to -Z and +Z to +X
+        let close = |p: [f32; 3], q: [f32; 3]| p.iter().zip(q).all(|(p, q)| (p - q).abs() < 1e-5);
+        assert!(close(a, [0.0, 1.0, -2.0]), "{:?}", a);
+        assert!(close(b, [0.0, 3.0, 0.0]), "{:?}", b);
+        assert!(close(c, [2.0, 1.0, 0.0]), "{:?}", c);
+    }
+}
@@ -0,0 +1,81 @@
+// Triangle meshes (see mesh.rs), spliced into the built-in shader when the scene has any and
+// the device can ray trace from render passes (TRACE_MESHES, see raymarcher.rs). The renderer
+// builds an acceleration structure of every mesh's triangles, placed in the world, and binds
+// it to the image's passes: each of a pixel's rays is intersected with it in hardware after the
+// scene is marched, and where a triangle is nearer than the scene's first surface the ray sees
+// the mesh instead. MESH_PARAMS, MESH_ARGS and MESH_BUFFERS carry it down from the entry points
+// to the march; without meshes they are empty (see shaders.metal).
+#ifdef TRACE_MESHES
+#include <metal_raytracing>
+
+#define MESH_PARAMS \
+    , raytracing::primitive_acceleration_structure meshes, \
+    device const packed_float3* corners, device const packed_float3* colors
+#define MESH_ARGS , meshes, corners, colors
+#define MESH_BUFFERS \
+    , raytracing::primitive_acceleration_structure meshes [[buffer(2)]], \
+    device const packed_float3* corners [[buffer(3)]], \
+    device const packed_float3* colors [[buffer(4)]]
+
+// The first triangle along the ray before `far`, or with `any` whichever is found first
+raytracing::intersection_result<raytracing::triangle_data> meshHit(
+    float3 ro, float3 rd, float far, bool any,
+    raytracing::primitive_acceleration_structure meshes) {
+    raytracing::ray ray(ro, rd, 0.0, far);
+    raytracing::intersector<raytracing::triangle_data> intersector;
+    intersector.accept_any_intersection(any);
+    return intersector.intersect(ray, meshes);
+}
+
+// The mesh the ray meets before `far` (the scene's first surface, or -1 for none) and how far
+// along it (xyz and w), or w -1 for none. Flat shaded, facing the ray whichever way the
+// triangle is wound: the sun shadowed by the scene and the meshes, the point lights and the
+// ambient of withVolume's iso-surfaces, then fogged.
+float4 meshSurface(float3 ro, float3 rd, float far, constant Uniforms& uniforms,
+                   texture2d<float> screenTex MESH_PARAMS) {
+    auto hit = meshHit(ro, rd, far >= 0.0 ? far : uniforms.max_distance, false, meshes);
+    if (hit.type != raytracing::intersection_type::triangle) {
+        return float4(0.0, 0.0, 0.0, -1.0);
+    }
+    uint corner = hit.primitive_id * 3;
+    float3 a = float3(corners[corner]);
+    float3 n = normalize(cross(float3(corners[corner + 1]) - a, float3(corners[corner + 2]) - a));
+    n = dot(n, rd) > 0.0 ? -n : n;
+    float3 p = ro + rd * hit.distance;
+    float3 lightDir = uniforms.sun_dir;
+    float diffuse = max(0.0, dot(n, lightDir));
+    if (diffuse > 0.0) {
+        float3 origin = p + n * 0.01;
+        auto blocker = meshHit(origin, lightDir, 10.0, true, meshes);
+        if (blocker.type == raytracing::intersection_type::triangle) {
+            diffuse = 0.0;
+        } else if (uniforms.shadow_steps > 0) {
+            diffuse *= softShadow(origin, lightDir, 0.02, 10.0, uniforms);
+        }
+    }
+    float3 light = float3(0.15, 0.15, 0.2) + diffuse * float3(uniforms.light_color) +
+                   sceneLight(p, n, uniforms);
+    float3 color = float3(colors[hit.primitive_id]) * light;
+    return float4(fogged(color, ro, rd, hit.distance, uniforms, screenTex), hit.distance);
+}
+
+// The meshes over a cone traced pixel (see coneMarch) whose surface is `far` along the ray:
+// a 2x2 grid of rays spread over the pixel, `pixel` wide a unit along it. The color of those
+// meeting a mesh nearer, premultiplied by the share of them (a).
+float4 meshCone(float3 ro, float3 rd, float pixel, float far, constant Uniforms& uniforms,
+                texture2d<float> screenTex MESH_PARAMS) {
+    float3 axis = abs(rd.y) < 0.99 ? float3(0.0, 1.0, 0.0) : float3(1.0, 0.0, 0.0);
+    float3 side = normalize(cross(axis, rd));
+    float3 up = cross(rd, side);
+    float4 covered = float4(0.0);
+    for (uint i = 0; i < 4; i++) {
+        float2 offset = (float2(i % 2, i / 2) - 0.5) * 0.5 * pixel;
+        float3 dir = normalize(rd + side * offset.x + up * offset.y);
+        float4 mesh = meshSurface(ro, dir, far, uniforms, screenTex MESH_ARGS);
+        if (mesh.w >= 0.0) {
+            covered += float4(mesh.rgb, 1.0) * 0.25;
+        }
+    }
+    return covered;
+}
+#endif
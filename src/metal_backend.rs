@@ -0,0 +1,645 @@
+// Native Metal backend: the library's Raymarcher drawing into a CAMetalLayer (macOS and iOS)
+use crate::backend::Backend;
+#[cfg(feature = "offline")]
+use crate::backend::PassImage;
+use crate::config::{Config, PresentConfig};
+use cgmath::Vector2;
+use metal::*;
+use metal_raymarcher::adaptive::Adaptive;
+use metal_raymarcher::audio::AudioLevels;
+use metal_raymarcher::clock::ClockPhase;
+#[cfg(target_os = "macos")]
+use metal_raymarcher::device_watch::DeviceWatch;
+use metal_raymarcher::edges::Edges;
+use metal_raymarcher::gizmo::OverlayVertex;
+#[cfg(target_os = "macos")]
+use metal_raymarcher::inputs::{ScreenConfig, VideoConfig};
+use metal_raymarcher::mesh::MeshData;
+use metal_raymarcher::metal_layer::MetalLayer;
+use metal_raymarcher::noise::NoiseTable;
+use metal_raymarcher::normal_map::NormalMap;
+use metal_raymarcher::quality::{QualityConfig, QualityPreset};
+use metal_raymarcher::shafts::Shafts;
+use metal_raymarcher::toon::Toon;
+use metal_raymarcher::volume::VolumeData;
+use metal_raymarcher::{inputs, raymarcher, Camera, Raymarcher, Scene};
+use objc::rc::autoreleasepool;
+use objc::{msg_send, sel, sel_impl};
+#[cfg(feature = "offline")]
+use std::collections::VecDeque;
+use std::time::Instant;
+use winit::dpi::PhysicalSize;
+use winit::window::Window;
+
+pub struct MetalBackend {
+    raymarcher: Raymarcher,
+    template: Option<String>, // Custom shader (--shader), regenerated with the scene
+    layer: Option<MetalLayer>, // None when rendering offline without a window
+    present: PresentConfig,
+    present_at: Option<Instant>, // For the next frame (see Backend::present_at)
+    #[cfg(target_os = "macos")]
+    watch: DeviceWatch,
+    #[cfg(target_os = "macos")]
+    video: VideoConfig, // Reopened on the new GPU if the current one is removed
+    #[cfg(target_os = "macos")]
+    screen: ScreenConfig,
+    #[cfg(target_os = "macos")]
+    stranded: bool, // The GPU was removed and couldn't be replaced (said once)
+    checked: Option<CommandBuffer>, // A recent frame, looked at for GPU errors (see gpu_error)
+    #[cfg(feature = "offline")]
+    offline_target: Option<Texture>, // Reused while the output size stays the same
+    #[cfg(feature = "offline")]
+    readbacks: Readbacks,
+}
+
+// Frames read back without waiting for the GPU (see Backend::request_readback): each is blitted
+// into a shared-storage buffer, then the event is signaled to its ticket, which is polled for
+#[cfg(feature = "offline")]
+struct Readbacks {
+    event: SharedEvent,
+    ticket: u64, // The last one handed out
+    pending: VecDeque<Readback>,
+    spare: Vec<Buffer>, // Buffers of finished readbacks, for the next ones
+}
+
+#[cfg(feature = "offline")]
+struct Readback {
+    ticket: u64,
+    size: PhysicalSize<u32>,
+    buffer: Buffer,
+    command_buffer: CommandBuffer,
+}
+
+// Readbacks in flight at most; one more waits for the oldest
+#[cfg(feature = "offline")]
+const READBACKS: usize = 3;
+
+#[cfg(feature = "offline")]
+impl Readbacks {
+    fn new(device: &DeviceRef) -> Self {
+        Self {
+            event: device.new_shared_event(),
+            ticket: 0,
+            pending: VecDeque::new(),
+            spare: Vec::new(),
+        }
+    }
+}
+
+#[link(name = "QuartzCore", kind = "framework")]
+extern "C" {
+    fn CACurrentMediaTime() -> f64;
+}
+
+// Metal objects are thread-safe (see Raymarcher) and MetalLayer is Send
+unsafe impl Send for MetalBackend {}
+
+impl MetalBackend {
+    pub fn new(window: Option<&Window>, config: &Config, scene: &Scene) -> Result<Self, String> {
+        // Initialize Metal
+        let device = select_device(config.gpu.as_deref());
+        println!("Using GPU: {}", device.name());
+        let template = match &config.shader {
+            Some(path) => Some(
+                std::fs::read_to_string(path)
+                    .map_err(|err| format!("Failed to read shader {}: {}", path.display(), err))?,
+            ),
+            None => None,
+        };
+        let mut raymarcher = Raymarcher::from_source(
+            &device,
+            &raymarcher::shader_source(template.as_deref(), scene)?,
+            MTLPixelFormat::BGRA8Unorm,
+        )?;
+        raymarcher.set_scene(scene);
+        raymarcher.set_normal_map(&NormalMap::of(scene));
+        raymarcher.set_noise(&NoiseTable::of(scene, 0));
+        raymarcher.set_volume(&VolumeData::of(scene.volume.as_ref()));
+        if let Err(err) = raymarcher.set_meshes(&MeshData::of(&scene.meshes)) {
+            eprintln!("{}", err);
+        }
+
+        // Optional video texture source (camera or file) and screen/window capture
+        raymarcher.set_video(inputs::open_video(&device, &config.video), config.video.mix);
+        raymarcher.set_screen(
+            inputs::open_screen(&device, &config.screen),
+            config.screen.mix,
+        );
+
+        // Create CAMetalLayer (sized by the first resize)
+        let layer = window
+            .map(|window| MetalLayer::attach(window, &device, MTLPixelFormat::BGRA8Unorm))
+            .transpose()?;
+        let present = &config.present;
+        let drawables = if present.low_latency {
+            2
+        } else {
+            present.drawables
+        };
+        if let Some(layer) = &layer {
+            layer.set_present(drawables, present.transaction, present.framebuffer_only);
+        }
+
+        Ok(Self {
+            #[cfg(target_os = "macos")]
+            watch: DeviceWatch::new(&device),
+            #[cfg(target_os = "macos")]
+            video: config.video.clone(),
+            #[cfg(target_os = "macos")]
+            screen: config.screen.clone(),
+            #[cfg(target_os = "macos")]
+            stranded: false,
+            checked: None,
+            raymarcher,
+            template,
+            layer,
+            present: present.clone(),
+            present_at: None,
+            #[cfg(feature = "offline")]
+            offline_target: None,
+            #[cfg(feature = "offline")]
+            readbacks: Readbacks::new(&device),
+        })
+    }
+}
+
+impl Backend for MetalBackend {
+    fn default_preset(&self) -> QualityPreset {
+        QualityPreset::for_gpu(self.raymarcher.device().is_low_power())
+    }
+
+    fn set_quality(&mut self, quality: QualityConfig) {
+        self.raymarcher.set_quality(quality);
+    }
+
+    fn set_mouse(&mut self, mouse: Vector2<f32>) {
+        self.raymarcher.set_mouse(mouse);
+    }
+
+    fn set_audio(&mut self, audio: AudioLevels) {
+        self.raymarcher.set_audio(audio);
+    }
+
+    fn set_clock(&mut self, clock: ClockPhase) {
+        self.raymarcher.set_clock(clock);
+    }
+
+    fn set_selected(&mut self, selected: Option<usize>) {
+        self.raymarcher.set_selected(selected);
+    }
+
+    fn set_guides(&mut self, guides: bool) {
+        self.raymarcher.set_guides(guides);
+    }
+
+    fn set_toon(&mut self, toon: Option<Toon>) {
+        self.raymarcher.set_toon(toon);
+    }
+
+    fn set_edges(&mut self, edges: Edges) -> Result<(), String> {
+        self.raymarcher.set_edges(edges)
+    }
+
+    fn set_shafts(&mut self, shafts: Option<Shafts>) -> Result<(), String> {
+        self.raymarcher.set_shafts(shafts)
+    }
+
+    fn set_adaptive(&mut self, adaptive: Option<Adaptive>) -> Result<(), String> {
+        self.raymarcher.set_adaptive(adaptive)
+    }
+
+    fn set_culling(&mut self, on: bool) -> Result<(), String> {
+        self.raymarcher.set_culling(on)
+    }
+
+    fn set_overlay(&mut self, vertices: &[OverlayVertex]) {
+        self.raymarcher.set_overlay(vertices);
+    }
+
+    fn set_scene(&mut self, scene: &Scene) {
+        self.raymarcher.set_scene(scene);
+    }
+
+    fn rebuild(&mut self, scene: &Scene) -> Result<(), String> {
+        self.raymarcher
+            .rebuild(&raymarcher::shader_source(self.template.as_deref(), scene)?)?;
+        self.raymarcher.set_normal_map(&NormalMap::of(scene));
+        let noise = NoiseTable::of(scene, self.raymarcher.seed());
+        self.raymarcher.set_noise(&noise);
+        self.raymarcher
+            .set_volume(&VolumeData::of(scene.volume.as_ref()));
+        if let Err(err) = self.raymarcher.set_meshes(&MeshData::of(&scene.meshes)) {
+            eprintln!("{}", err);
+        }
+        Ok(())
+    }
+
+    fn set_shader(&mut self, template: String, scene: &Scene) -> Result<(), String> {
+        self.raymarcher
+            .rebuild(&raymarcher::shader_source(Some(&template), scene)?)?;
+        self.template = Some(template);
+        Ok(())
+    }
+
+    fn shader_template(&self) -> Option<String> {
+        let template = self.template.as_deref();
+        Some(template.unwrap_or(raymarcher::BUILTIN_SHADER).to_string())
+    }
+
+    fn begin_crossfade(&mut self, scene: &Scene) -> Result<(), String> {
+        let source = raymarcher::shader_source(self.template.as_deref(), scene)?;
+        self.raymarcher.begin_crossfade(&source, scene)
+    }
+
+    fn set_crossfade(&mut self, scene: &Scene, camera: &Camera, opacity: f32) {
+        self.raymarcher.set_crossfade(scene, camera, opacity);
+    }
+
+    fn end_crossfade(&mut self, keep: bool) {
+        self.raymarcher.end_crossfade(keep);
+    }
+
+    fn set_path_tracing(&mut self, on: bool) -> Result<(), String> {
+        self.raymarcher.set_path_tracing(on)
+    }
+
+    fn samples(&self) -> Option<u32> {
+        self.raymarcher.samples()
+    }
+
+    fn set_denoise(&mut self, denoise: bool) {
+        self.raymarcher.set_denoise(denoise);
+    }
+
+    fn set_seed(&mut self, seed: u32, scene: &Scene) {
+        self.raymarcher.set_seed(seed);
+        self.raymarcher.set_noise(&NoiseTable::of(scene, seed));
+    }
+
+    #[cfg(target_os = "macos")]
+    fn recover(&mut self, scene: &Scene) -> bool {
+        if !self.watch.gone() {
+            return false;
+        }
+        let removed = self.raymarcher.device();
+        let (removed_id, removed_name) = (removed.registry_id(), removed.name().to_string());
+        // Rather a built-in GPU, which can't be unplugged in turn
+        let mut devices: Vec<_> = (Device::all().into_iter())
+            .filter(|device| device.registry_id() != removed_id)
+            .collect();
+        devices.sort_by_key(|device| device.is_removable());
+        let moved = match devices.into_iter().next() {
+            Some(device) => {
+                let source = raymarcher::shader_source(self.template.as_deref(), scene);
+                match source.and_then(|source| self.raymarcher.move_to(&device, &source, scene)) {
+                    Ok(()) => Ok(device),
+                    Err(err) => Err(format!("moving to {} failed: {}", device.name(), err)),
+                }
+            }
+            None => Err("there is no other".to_string()),
+        };
+        let device = match moved {
+            Ok(device) => device,
+            Err(err) => {
+                if !self.stranded {
+                    eprintln!("GPU {} was removed and {}", removed_name, err);
+                    self.stranded = true;
+                }
+                return false;
+            }
+        };
+        println!(
+            "GPU {} was removed, moved to {}",
+            removed_name,
+            device.name()
+        );
+
+        self.raymarcher
+            .set_video(inputs::open_video(&device, &self.video), self.video.mix);
+        self.raymarcher
+            .set_screen(inputs::open_screen(&device, &self.screen), self.screen.mix);
+        if let Some(layer) = &self.layer {
+            layer.set_device(&device);
+        }
+        #[cfg(feature = "offline")]
+        {
+            // Readbacks on the old GPU never finish; their requests go unanswered
+            self.offline_target = None;
+            self.readbacks = Readbacks::new(&device);
+        }
+        self.watch = DeviceWatch::new(&device);
+        self.stranded = false;
+        self.checked = None;
+        true
+    }
+
+    fn describe_device(&self) -> String {
+        let device = self.raymarcher.device();
+        // Only Macs have more than one GPU, some of them removable or headless
+        let placement = if cfg!(target_os = "macos") {
+            format!(
+                ", removable: {}, headless: {}",
+                device.is_removable(),
+                device.is_headless()
+            )
+        } else {
+            String::new()
+        };
+        format!(
+            "Metal: {} (registry id {:#x}), low power: {}, recommended working set: {} MB{}",
+            device.name(),
+            device.registry_id(),
+            device.is_low_power(),
+            device.recommended_max_working_set_size() >> 20,
+            placement
+        )
+    }
+
+    fn describe_uniforms(&self) -> Option<String> {
+        self.raymarcher.describe_uniforms()
+    }
+
+    fn gpu_error(&mut self) -> Option<String> {
+        match self.checked.as_ref()?.status() {
+            MTLCommandBufferStatus::Error => {
+                self.checked = None;
+                Some("The GPU failed to draw a frame (its command buffer ended in an error)".into())
+            }
+            MTLCommandBufferStatus::Completed => {
+                self.checked = None;
+                None
+            }
+            _ => None, // Still on the GPU
+        }
+    }
+
+    fn pass_times(&self) -> Vec<(&'static str, f64)> {
+        self.raymarcher.pass_times().to_vec()
+    }
+
+    fn gpu_memory(&self) -> Option<u64> {
+        Some(self.raymarcher.allocated_size())
+    }
+
+    fn test_shader(&mut self, scene: &Scene, probes: &[[f32; 4]]) -> Result<Vec<[f32; 4]>, String> {
+        raymarcher::run_shader_test(self.raymarcher.device(), scene, probes)
+    }
+
+    fn upload_inputs(&mut self) {
+        self.raymarcher.upload_inputs();
+    }
+
+    fn resize(&mut self, drawable_size: PhysicalSize<u32>) {
+        if let Some(layer) = &self.layer {
+            layer.resize(drawable_size.width, drawable_size.height);
+        }
+    }
+
+    fn present_at(&mut self, at: Instant) {
+        self.present_at = Some(at);
+    }
+
+    fn render(&mut self, camera: &Camera, time: f32) -> bool {
+        let Some(layer) = &self.layer else {
+            return false;
+        };
+        autoreleasepool(|| {
+            let Some(drawable) = layer.next_drawable() else {
+                return false;
+            };
+            let command_buffer = self
+                .raymarcher
+                .command_queue()
+                .new_command_buffer()
+                .to_owned();
+            self.raymarcher
+                .encode(&command_buffer, drawable.texture(), camera, time);
+            // Scheduled presentation takes host time, in seconds as Core Animation counts it
+            let at = (self.present_at.take()).map(|at| {
+                let ahead = at.saturating_duration_since(Instant::now());
+                let now = unsafe { CACurrentMediaTime() };
+                now + ahead.as_secs_f64()
+            });
+            let drawable = drawable.as_drawable();
+            if !self.present.transaction {
+                match at {
+                    Some(at) => unsafe {
+                        let _: () = msg_send![command_buffer.as_ref(), presentDrawable: drawable atTime: at];
+                    },
+                    None => command_buffer.present_drawable(drawable),
+                }
+            }
+            command_buffer.commit();
+            if self.present.transaction {
+                // With the Core Animation transaction, once the GPU has the frame
+                command_buffer.wait_until_scheduled();
+                match at {
+                    Some(at) => unsafe {
+                        let _: () = msg_send![drawable, presentAtTime: at];
+                    },
+                    None => drawable.present(),
+                }
+            }
+            if self.present.low_latency {
+                command_buffer.wait_until_completed();
+            }
+            // Frames are checked one at a time; a failing GPU fails more than one
+            self.checked.get_or_insert(command_buffer);
+            true
+        })
+    }
+
+    #[cfg(feature = "offline")]
+    fn render_to_rgba(&mut self, size: PhysicalSize<u32>, camera: &Camera, time: f32) -> Vec<u8> {
+        let (width, height) = (size.width as u64, size.height as u64);
+        let target = &self.offline_target(size);
+
+        autoreleasepool(|| {
+            let command_buffer = self
+                .raymarcher
+                .command_queue()
+                .new_command_buffer()
+                .to_owned();
+            self.raymarcher
+                .encode(&command_buffer, target, camera, time);
+
+            // Managed textures must be synchronized before the CPU can read them
+            #[cfg(target_os = "macos")]
+            {
+                let blit = command_buffer.new_blit_command_encoder();
+                blit.synchronize_resource(target);
+                blit.end_encoding();
+            }
+
+            command_buffer.commit();
+            command_buffer.wait_until_completed();
+        });
+
+        let mut pixels = vec![0u8; (width * height * 4) as usize];
+        target.get_bytes(
+            pixels.as_mut_ptr() as *mut _,
+            width * 4,
+            MTLRegion::new_2d(0, 0, width, height),
+            0,
+        );
+        // BGRA -> RGBA
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+        pixels
+    }
+
+    #[cfg(feature = "offline")]
+    fn request_readback(&mut self, size: PhysicalSize<u32>, camera: &Camera, time: f32) -> u64 {
+        if self.readbacks.pending.len() >= READBACKS {
+            // Asked for faster than the GPU draws them
+            self.readbacks.pending[0]
+                .command_buffer
+                .wait_until_completed();
+        }
+        let (width, height) = (size.width as u64, size.height as u64);
+        let target = self.offline_target(size);
+        let length = width * height * 4;
+        let spare = (self.readbacks.spare.iter()).position(|buffer| buffer.length() == length);
+        let buffer = match spare {
+            Some(index) => self.readbacks.spare.swap_remove(index),
+            None => {
+                (self.raymarcher.device()).new_buffer(length, MTLResourceOptions::StorageModeShared)
+            }
+        };
+        self.readbacks.ticket += 1;
+        let ticket = self.readbacks.ticket;
+
+        autoreleasepool(|| {
+            let command_buffer = self
+                .raymarcher
+                .command_queue()
+                .new_command_buffer()
+                .to_owned();
+            self.raymarcher
+                .encode(&command_buffer, &target, camera, time);
+            let blit = command_buffer.new_blit_command_encoder();
+            blit.copy_from_texture_to_buffer(
+                &target,
+                0,
+                0,
+                MTLOrigin { x: 0, y: 0, z: 0 },
+                MTLSize::new(width, height, 1),
+                &buffer,
+                0,
+                width * 4,
+                length,
+                MTLBlitOption::empty(),
+            );
+            blit.end_encoding();
+            command_buffer.encode_signal_event(&self.readbacks.event, ticket);
+            command_buffer.commit();
+            self.readbacks.pending.push_back(Readback {
+                ticket,
+                size,
+                buffer,
+                command_buffer,
+            });
+        });
+        ticket
+    }
+
+    #[cfg(feature = "offline")]
+    fn readbacks(&mut self) -> Vec<(u64, Result<Vec<u8>, String>)> {
+        let signaled = self.readbacks.event.signaled_value();
+        let mut done = Vec::new();
+        while let Some(readback) = self.readbacks.pending.front() {
+            let failed = readback.command_buffer.status() == MTLCommandBufferStatus::Error;
+            if readback.ticket > signaled && !failed {
+                break;
+            }
+            let readback = self.readbacks.pending.pop_front().unwrap();
+            if failed {
+                let err = "The GPU failed to draw a frame being read back".to_string();
+                done.push((readback.ticket, Err(err)));
+                continue;
+            }
+            let length = (readback.size.width * readback.size.height * 4) as usize;
+            let mut pixels = unsafe {
+                std::slice::from_raw_parts(readback.buffer.contents() as *const u8, length)
+            }
+            .to_vec();
+            // BGRA -> RGBA
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+            if self.readbacks.spare.len() < READBACKS {
+                self.readbacks.spare.push(readback.buffer);
+            }
+            done.push((readback.ticket, Ok(pixels)));
+        }
+        done
+    }
+
+    #[cfg(feature = "offline")]
+    fn read_passes(&mut self) -> Vec<PassImage> {
+        (self.raymarcher.passes().into_iter())
+            .map(|(name, texture)| PassImage {
+                name,
+                size: PhysicalSize::new(texture.width() as u32, texture.height() as u32),
+                pixels: self.raymarcher.read_pass(texture),
+            })
+            .collect()
+    }
+}
+
+#[cfg(feature = "offline")]
+impl MetalBackend {
+    // The offscreen target for frames of `size`, made again when the size changes
+    fn offline_target(&mut self, size: PhysicalSize<u32>) -> Texture {
+        let (width, height) = (size.width as u64, size.height as u64);
+        let reuse = matches!(&self.offline_target,
+            Some(target) if target.width() == width && target.height() == height);
+        if !reuse {
+            self.offline_target = Some(new_render_target(self.raymarcher.device(), width, height));
+        }
+        self.offline_target.clone().unwrap()
+    }
+}
+
+#[cfg(feature = "offline")]
+fn new_render_target(device: &DeviceRef, width: u64, height: u64) -> Texture {
+    let descriptor = TextureDescriptor::new();
+    descriptor.set_pixel_format(MTLPixelFormat::BGRA8Unorm);
+    descriptor.set_width(width);
+    descriptor.set_height(height);
+    descriptor.set_usage(MTLTextureUsage::RenderTarget | MTLTextureUsage::ShaderRead);
+    // iOS has unified memory and no managed storage
+    #[cfg(target_os = "macos")]
+    descriptor.set_storage_mode(MTLStorageMode::Managed);
+    #[cfg(target_os = "ios")]
+    descriptor.set_storage_mode(MTLStorageMode::Shared);
+    device.new_texture(&descriptor)
+}
+
+// Pick a GPU by index or (case-insensitive) part of its name, defaulting to the system GPU
+fn select_device(gpu: Option<&str>) -> Device {
+    let Some(wanted) = gpu else {
+        return Device::system_default().expect("No Metal device found");
+    };
+    let devices = Device::all();
+    let found = match wanted.parse::<usize>() {
+        Ok(index) => devices.get(index).cloned(),
+        Err(_) => devices
+            .iter()
+            .find(|device| {
+                device
+                    .name()
+                    .to_lowercase()
+                    .contains(&wanted.to_lowercase())
+            })
+            .cloned(),
+    };
+    found.unwrap_or_else(|| {
+        eprintln!("No GPU matching \"{}\". Available:", wanted);
+        for (index, device) in devices.iter().enumerate() {
+            eprintln!("  {}: {}", index, device.name());
+        }
+        std::process::exit(1);
+    })
+}
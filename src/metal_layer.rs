@@ -0,0 +1,118 @@
+// The CAMetalLayer a window is drawn through, created from a raw window handle so any
+// handle provider works (winit, tao, SDL2, or a host application's own NSView/UIView).
+// On macOS it becomes the backing layer of the NSView, on iOS a sublayer of the UIView
+// that has to follow the view's bounds (rotation, split view).
+use foreign_types::ForeignTypeRef;
+use metal::{DeviceRef, DrawableRef, MTLPixelFormat, TextureRef};
+use objc2::rc::Retained;
+use objc2::runtime::ProtocolObject;
+use objc2_foundation::CGSize;
+use objc2_metal::{MTLDevice, MTLTexture};
+use objc2_quartz_core::{CAMetalDrawable, CAMetalLayer};
+use raw_window_handle::{HasRawWindowHandle, RawWindowHandle};
+
+#[cfg(target_os = "macos")]
+use objc2_app_kit::NSView;
+#[cfg(target_os = "ios")]
+use objc2_ui_kit::UIView;
+
+pub struct MetalLayer {
+    layer: Retained<CAMetalLayer>,
+}
+
+// Core Animation layers may be used from any thread (changes go through implicit
+// transactions), which is what lets rendering run off the main thread
+unsafe impl Send for MetalLayer {}
+
+// A drawable acquired from the layer, with its texture kept alive alongside it
+pub struct Drawable {
+    drawable: Retained<ProtocolObject<dyn CAMetalDrawable>>,
+    texture: Retained<ProtocolObject<dyn MTLTexture>>,
+}
+
+impl MetalLayer {
+    // Create a layer for `device` and attach it to the window's view (sized by the first resize)
+    pub fn attach(
+        window: &impl HasRawWindowHandle,
+        device: &DeviceRef,
+        pixel_format: MTLPixelFormat,
+    ) -> Result<Self, String> {
+        // metal-rs and objc2 wrap the same id<MTLDevice>
+        let device = unsafe { &*(device.as_ptr() as *const ProtocolObject<dyn MTLDevice>) };
+        let layer = unsafe { CAMetalLayer::new() };
+        unsafe {
+            layer.setDevice(Some(device));
+            layer.setPixelFormat(objc2_metal::MTLPixelFormat(pixel_format as usize));
+        }
+
+        match window.raw_window_handle() {
+            #[cfg(target_os = "macos")]
+            RawWindowHandle::AppKit(handle) => {
+                let view = unsafe { Retained::retain(handle.ns_view as *mut NSView) }
+                    .ok_or("Window handle has no NSView")?;
+                unsafe { view.setLayer(Some(&layer)) };
+                view.setWantsLayer(true);
+                Ok(Self { layer })
+            }
+            #[cfg(target_os = "ios")]
+            RawWindowHandle::UiKit(handle) => {
+                let view = unsafe { Retained::retain(handle.ui_view as *mut UIView) }
+                    .ok_or("Window handle has no UIView")?;
+                layer.setContentsScale(view.contentScaleFactor());
+                view.layer().addSublayer(&layer);
+                Ok(Self { layer })
+            }
+            other => Err(format!("Unsupported window handle for Metal: {:?}", other)),
+        }
+    }
+
+    // Draw with another device (the one the layer was made for was removed)
+    pub fn set_device(&self, device: &DeviceRef) {
+        let device = unsafe { &*(device.as_ptr() as *const ProtocolObject<dyn MTLDevice>) };
+        unsafe { self.layer.setDevice(Some(device)) };
+    }
+
+    // How drawables are handed out and presented: at most `drawables` in flight (2 or 3),
+    // presented with the Core Animation transaction (see Drawable::present) or on their own,
+    // and only rendered into or also readable
+    pub fn set_present(&self, drawables: u32, transaction: bool, framebuffer_only: bool) {
+        unsafe {
+            self.layer
+                .setMaximumDrawableCount(drawables.clamp(2, 3) as usize);
+            self.layer.setPresentsWithTransaction(transaction);
+            self.layer.setFramebufferOnly(framebuffer_only);
+        }
+    }
+
+    // Size of the drawables in pixels
+    pub fn resize(&self, width: u32, height: u32) {
+        // On iOS, follow the view through its backing layer (UIKit itself is main thread only)
+        #[cfg(target_os = "ios")]
+        if let Some(superlayer) = self.layer.superlayer() {
+            self.layer.setFrame(superlayer.bounds());
+        }
+        unsafe {
+            self.layer
+                .setDrawableSize(CGSize::new(width as f64, height as f64));
+        }
+    }
+
+    // Next drawable to render into, None when none is available (call inside an autorelease
+    // pool)
+    pub fn next_drawable(&self) -> Option<Drawable> {
+        let drawable = unsafe { self.layer.nextDrawable() }?;
+        let texture = unsafe { drawable.texture() };
+        Some(Drawable { drawable, texture })
+    }
+}
+
+impl Drawable {
+    // The drawable's texture, as metal-rs sees it
+    pub fn texture(&self) -> &TextureRef {
+        unsafe { TextureRef::from_ptr(Retained::as_ptr(&self.texture) as *mut _) }
+    }
+
+    pub fn as_drawable(&self) -> &DrawableRef {
+        unsafe { DrawableRef::from_ptr(Retained::as_ptr(&self.drawable) as *mut _) }
+    }
+}
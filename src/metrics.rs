@@ -0,0 +1,243 @@
+// Metrics for unattended installations, in the Prometheus text format, on /metrics of the
+// monitoring server (see http.rs):
+//
+//   raymarcher_info{scene,gpu,quality}     1, labelled with what is showing and on what
+//   raymarcher_uptime_seconds
+//   raymarcher_frames_total                frames drawn
+//   raymarcher_dropped_frames_total        display refreshes missed (live, when the display's
+//                                          refresh rate is known)
+//   raymarcher_frame_seconds               between the last two frames
+//   raymarcher_frame_max_seconds           the longest of those over the last whole second
+//   raymarcher_fps
+//   raymarcher_gpu_pass_seconds{pass}      GPU time of each pass of a recent frame (where the
+//                                          GPU can time them): tiles, paths, gbuffer, shafts, image
+//   raymarcher_render_scale                drawable size relative to the window size
+//   raymarcher_render_width_pixels, raymarcher_render_height_pixels
+//   raymarcher_path_tracing_samples        paths per pixel so far, when path tracing
+//   raymarcher_offline_frames_written, raymarcher_offline_frames   of an offline render
+//   raymarcher_gpu_memory_bytes            allocated by the renderer (Metal)
+//   process_resident_memory_bytes          (macOS, iOS and Linux)
+use crate::App;
+use std::fmt::Write;
+use winit::dpi::PhysicalSize;
+
+#[derive(Clone, Copy)]
+pub enum Kind {
+    Counter,
+    Gauge,
+}
+
+// The text of a scrape, written a metric at a time
+#[derive(Default)]
+pub struct Metrics {
+    text: String,
+}
+
+impl Metrics {
+    // A metric's HELP and TYPE lines and its samples, each with its labels
+    pub fn metric(
+        &mut self,
+        name: &str,
+        kind: Kind,
+        help: &str,
+        samples: &[(&[(&str, &str)], f64)],
+    ) {
+        let kind = match kind {
+            Kind::Counter => "counter",
+            Kind::Gauge => "gauge",
+        };
+        let _ = writeln!(self.text, "# HELP {} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE {} {}", name, kind);
+        for (labels, value) in samples {
+            self.text.push_str(name);
+            if !labels.is_empty() {
+                let labels: Vec<String> = (labels.iter())
+                    .map(|(label, value)| format!("{}=\"{}\"", label, escape(value)))
+                    .collect();
+                let _ = write!(self.text, "{{{}}}", labels.join(","));
+            }
+            let _ = writeln!(self.text, " {}", number(*value));
+        }
+    }
+
+    pub fn gauge(&mut self, name: &str, help: &str, value: f64) {
+        self.metric(name, Kind::Gauge, help, &[(&[], value)]);
+    }
+
+    pub fn counter(&mut self, name: &str, help: &str, value: f64) {
+        self.metric(name, Kind::Counter, help, &[(&[], value)]);
+    }
+
+    pub fn into_text(self) -> String {
+        self.text
+    }
+}
+
+// Everything above, for the app as it is now; `size` is the size it draws at
+pub fn scrape(app: &App, size: PhysicalSize<u32>) -> String {
+    let stats = &app.stats;
+    let mut metrics = Metrics::default();
+    let quality = format!("{:?}", app.preset).to_lowercase();
+    let gpu = app.backend.describe_device();
+    let info = [
+        ("scene", app.scene.name.as_str()),
+        ("gpu", gpu.as_str()),
+        ("quality", quality.as_str()),
+    ];
+    metrics.metric(
+        "raymarcher_info",
+        Kind::Gauge,
+        "The scene showing, the GPU drawing it and the quality preset.",
+        &[(&info, 1.0)],
+    );
+    metrics.gauge(
+        "raymarcher_uptime_seconds",
+        "Seconds since the renderer started.",
+        stats.started.elapsed().as_secs_f64(),
+    );
+    metrics.counter(
+        "raymarcher_frames_total",
+        "Frames drawn.",
+        stats.frames as f64,
+    );
+    if stats.refresh.is_some() {
+        metrics.counter(
+            "raymarcher_dropped_frames_total",
+            "Display refreshes that went by without a new frame.",
+            stats.dropped as f64,
+        );
+    }
+    metrics.gauge(
+        "raymarcher_frame_seconds",
+        "Seconds between the last two frames.",
+        stats.frame_time.into(),
+    );
+    metrics.gauge(
+        "raymarcher_frame_max_seconds",
+        "The longest time between two frames over the last whole second.",
+        stats.slowest.into(),
+    );
+    metrics.gauge(
+        "raymarcher_fps",
+        "Frames drawn per second over the last whole second.",
+        stats.fps.into(),
+    );
+    let passes = app.backend.pass_times();
+    if !passes.is_empty() {
+        let labels: Vec<[(&str, &str); 1]> =
+            passes.iter().map(|&(pass, _)| [("pass", pass)]).collect();
+        let samples: Vec<(&[(&str, &str)], f64)> = (labels.iter().zip(&passes))
+            .map(|(labels, &(_, seconds))| (labels.as_slice(), seconds))
+            .collect();
+        metrics.metric(
+            "raymarcher_gpu_pass_seconds",
+            Kind::Gauge,
+            "GPU time of each pass of a recent frame.",
+            &samples,
+        );
+    }
+    metrics.gauge(
+        "raymarcher_render_scale",
+        "Size drawn at relative to the window size.",
+        app.render_scale.into(),
+    );
+    metrics.gauge(
+        "raymarcher_render_width_pixels",
+        "Width drawn at.",
+        size.width.into(),
+    );
+    metrics.gauge(
+        "raymarcher_render_height_pixels",
+        "Height drawn at.",
+        size.height.into(),
+    );
+    if let Some(samples) = app.backend.samples() {
+        metrics.gauge(
+            "raymarcher_path_tracing_samples",
+            "Paths per pixel averaged so far.",
+            samples.into(),
+        );
+    }
+    if let Some((written, frames)) = stats.progress {
+        metrics.gauge(
+            "raymarcher_offline_frames_written",
+            "Frames of the offline render written so far.",
+            written as f64,
+        );
+        metrics.gauge(
+            "raymarcher_offline_frames",
+            "Frames in the offline render.",
+            frames as f64,
+        );
+    }
+    if let Some(bytes) = app.backend.gpu_memory() {
+        metrics.gauge(
+            "raymarcher_gpu_memory_bytes",
+            "GPU memory allocated by the renderer.",
+            bytes as f64,
+        );
+    }
+    if let Some(bytes) = resident_memory() {
+        metrics.gauge(
+            "process_resident_memory_bytes",
+            "Resident memory size in bytes.",
+            bytes as f64,
+        );
+    }
+    metrics.into_text()
+}
+
+fn escape(value: &str) -> String {
+    (value.replace('\\', "\\\\"))
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn number(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+// The process's resident memory, in bytes
+#[cfg(target_os = "linux")]
+fn resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kilobytes * 1024)
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn resident_memory() -> Option<u64> {
+    // mach_task_basic_info, packed to 4 bytes as in <mach/task_info.h>
+    #[repr(C, packed(4))]
+    #[derive(Default)]
+    struct TaskBasicInfo {
+        virtual_size: u64,
+        resident_size: u64,
+        resident_size_max: u64,
+        user_time: [i32; 2],
+        system_time: [i32; 2],
+        policy: i32,
+        suspend_count: i32,
+    }
+    const MACH_TASK_BASIC_INFO: i32 = 20;
+    extern "C" {
+        static mach_task_self_: u32;
+        fn task_info(task: u32, flavor: i32, info: *mut TaskBasicInfo, count: *mut u32) -> i32;
+    }
+    let mut info = TaskBasicInfo::default();
+    let mut count = (std::mem::size_of::<TaskBasicInfo>() / 4) as u32;
+    let result = unsafe { task_info(mach_task_self_, MACH_TASK_BASIC_INFO, &mut info, &mut count) };
+    (result == 0).then_some(info.resident_size)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "ios")))]
+fn resident_memory() -> Option<u64> {
+    None
+}
@@ -0,0 +1,115 @@
+// Seeded noise shared by all scenes (see noise.rs): lattice points are hashed through the
+// host's permutation/gradient table, passed as noiseTex. Spliced into shaders.metal (and
+// shaders given with --shader that have the marker) before the scene code.
+
+float4 noiseEntry(int i, texture2d<float> noiseTex) {
+    return noiseTex.read(uint2(uint(i & 255), 0));
+}
+
+// Table entry picked by a lattice point, 0..255
+int noiseHash(int3 c, texture2d<float> noiseTex) {
+    int a = int(noiseEntry(c.x, noiseTex).r * 255.0 + 0.5);
+    int b = int(noiseEntry(a + c.y, noiseTex).r * 255.0 + 0.5);
+    return int(noiseEntry(b + c.z, noiseTex).r * 255.0 + 0.5);
+}
+
+float3 noiseGradient(int3 c, texture2d<float> noiseTex) {
+    return noiseEntry(noiseHash(c, noiseTex), noiseTex).gba * 2.0 - 1.0;
+}
+
+float noiseValue(int3 c, texture2d<float> noiseTex) {
+    return float(noiseHash(c, noiseTex)) / 127.5 - 1.0;
+}
+
+// Quintic ease between lattice points (continuous second derivative)
+float3 noiseFade(float3 f) {
+    return f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+}
+
+// Value noise: random values at the lattice points, smoothly blended (-1..1)
+float valueNoise(float3 p, texture2d<float> noiseTex) {
+    int3 i = int3(floor(p));
+    float3 u = noiseFade(fract(p));
+    float a = mix(noiseValue(i, noiseTex), noiseValue(i + int3(1, 0, 0), noiseTex), u.x);
+    float b = mix(noiseValue(i + int3(0, 1, 0), noiseTex), noiseValue(i + int3(1, 1, 0), noiseTex), u.x);
+    float c = mix(noiseValue(i + int3(0, 0, 1), noiseTex), noiseValue(i + int3(1, 0, 1), noiseTex), u.x);
+    float d = mix(noiseValue(i + int3(0, 1, 1), noiseTex), noiseValue(i + int3(1, 1, 1), noiseTex), u.x);
+    return mix(mix(a, b, u.y), mix(c, d, u.y), u.z);
+}
+
+float perlinCorner(int3 i, float3 f, int3 o, texture2d<float> noiseTex) {
+    return dot(noiseGradient(i + o, noiseTex), f - float3(o));
+}
+
+// Perlin (gradient) noise: random slopes at the lattice points, zero on them (about -1..1)
+float perlinNoise(float3 p, texture2d<float> noiseTex) {
+    int3 i = int3(floor(p));
+    float3 f = fract(p);
+    float3 u = noiseFade(f);
+    float a = mix(perlinCorner(i, f, int3(0, 0, 0), noiseTex),
+                  perlinCorner(i, f, int3(1, 0, 0), noiseTex), u.x);
+    float b = mix(perlinCorner(i, f, int3(0, 1, 0), noiseTex),
+                  perlinCorner(i, f, int3(1, 1, 0), noiseTex), u.x);
+    float c = mix(perlinCorner(i, f, int3(0, 0, 1), noiseTex),
+                  perlinCorner(i, f, int3(1, 0, 1), noiseTex), u.x);
+    float d = mix(perlinCorner(i, f, int3(0, 1, 1), noiseTex),
+                  perlinCorner(i, f, int3(1, 1, 1), noiseTex), u.x);
+    return mix(mix(a, b, u.y), mix(c, d, u.y), u.z);
+}
+
+float simplexCorner(float3 c, float3 x, texture2d<float> noiseTex) {
+    float t = max(0.6 - dot(x, x), 0.0);
+    return t * t * t * t * dot(noiseGradient(int3(c), noiseTex), x);
+}
+
+// Simplex noise: gradients on a tetrahedral lattice, four corners per sample and no
+// axis-aligned artifacts (about -1..1)
+float simplexNoise(float3 p, texture2d<float> noiseTex) {
+    float3 s = floor(p + dot(p, float3(1.0 / 3.0)));
+    float3 x0 = p - s + dot(s, float3(1.0 / 6.0));
+    // The simplex holding p: step along the axes in order of x0's components
+    float3 g = step(x0.yzx, x0);
+    float3 l = 1.0 - g;
+    float3 i1 = min(g, l.zxy);
+    float3 i2 = max(g, l.zxy);
+    float3 x1 = x0 - i1 + 1.0 / 6.0;
+    float3 x2 = x0 - i2 + 1.0 / 3.0;
+    float3 x3 = x0 - 0.5;
+    return 42.0 * (simplexCorner(s, x0, noiseTex) + simplexCorner(s + i1, x1, noiseTex) +
+                   simplexCorner(s + i2, x2, noiseTex) + simplexCorner(s + 1.0, x3, noiseTex));
+}
+
+// Fractal Brownian motion: octaves of Perlin noise, each twice the frequency and half the
+// amplitude of the last (about -1..1)
+float fbm(float3 p, int octaves, texture2d<float> noiseTex) {
+    float sum = 0.0;
+    float amplitude = 0.5;
+    float total = 0.0;
+    for (int i = 0; i < octaves; i++) {
+        sum += amplitude * perlinNoise(p, noiseTex);
+        total += amplitude;
+        amplitude *= 0.5;
+        // Shifted so the octaves' lattices don't all meet at the origin
+        p = p * 2.0 + float3(17.3, -31.7, 5.1);
+    }
+    return sum / max(total, 1e-6);
+}
+
+float3 curlPotential(float3 p, texture2d<float> noiseTex) {
+    return float3(perlinNoise(p, noiseTex),
+                  perlinNoise(p + float3(31.4, -12.7, 5.9), noiseTex),
+                  perlinNoise(p + float3(-8.3, 27.1, 19.6), noiseTex));
+}
+
+// Curl noise: the curl of three Perlin noises, a swirling flow that never converges or
+// spreads (divergence-free), e.g. for advecting particles or warping coordinates
+float3 curlNoise(float3 p, texture2d<float> noiseTex) {
+    float e = 0.01;
+    float3 dx = curlPotential(p + float3(e, 0.0, 0.0), noiseTex) -
+                curlPotential(p - float3(e, 0.0, 0.0), noiseTex);
+    float3 dy = curlPotential(p + float3(0.0, e, 0.0), noiseTex) -
+                curlPotential(p - float3(0.0, e, 0.0), noiseTex);
+    float3 dz = curlPotential(p + float3(0.0, 0.0, e), noiseTex) -
+                curlPotential(p - float3(0.0, 0.0, e), noiseTex);
+    return float3(dy.z - dz.y, dz.x - dx.z, dx.y - dy.x) / (2.0 * e);
+}
@@ -0,0 +1,111 @@
+// Seeded noise shared by every scene. The shader functions (value, Perlin and simplex noise,
+// fBm and curl noise) live in noise.metal and noise.wgsl, spliced into the templates at
+// NOISE_MARKER; they hash lattice points through a table the host generates here from the
+// scene's `noise_seed` (offset by --seed), so the same seed gives the same noise in every
+// scene and renderer.
+// NoiseTable::value and ::perlin compute two of them on the CPU, to check the shaders against
+// (see shader_test.rs).
+//
+// The table is a 256x1 RGBA8 texture: texel i holds entry i of a permutation of 0..256 in
+// red, and a random unit gradient in green, blue and alpha (mapped from -1..1 to 0..1).
+use crate::scene::Scene;
+use cgmath::Vector3;
+
+// Marker in the shader templates replaced by the noise functions (before the scene code)
+pub const NOISE_MARKER: &str = "// @noise@";
+pub const MSL_SOURCE: &str = include_str!("noise.metal");
+pub const WGSL_SOURCE: &str = include_str!("noise.wgsl");
+
+// Entries in the table, the width of its texture
+pub const TABLE_SIZE: u32 = 256;
+
+pub struct NoiseTable {
+    pub pixels: Vec<u8>, // RGBA8, TABLE_SIZE wide and 1 high
+}
+
+impl NoiseTable {
+    pub fn new(seed: u32) -> Self {
+        let mut state = seed;
+        let mut random = || {
+            state = pcg(state);
+            state
+        };
+
+        // Fisher-Yates shuffle
+        let mut permutation: Vec<u8> = (0..TABLE_SIZE).map(|i| i as u8).collect();
+        for i in (1..permutation.len()).rev() {
+            permutation.swap(i, random() as usize % (i + 1));
+        }
+
+        let mut pixels = Vec::with_capacity(TABLE_SIZE as usize * 4);
+        for &entry in &permutation {
+            // Uniform on the sphere: an even height and an even angle around it
+            let z = random() as f32 / u32::MAX as f32 * 2.0 - 1.0;
+            let angle = random() as f32 / u32::MAX as f32 * std::f32::consts::TAU;
+            let radius = (1.0 - z * z).max(0.0).sqrt();
+            let gradient = [radius * angle.cos(), radius * angle.sin(), z];
+            pixels.push(entry);
+            pixels.extend(gradient.map(|g| ((g * 0.5 + 0.5) * 255.0).round() as u8));
+        }
+        Self { pixels }
+    }
+
+    // The scene's table in a run with `seed` (--seed), which offsets its noise_seed: the same
+    // seed gives the same patterns, and 0 those the scene picked
+    pub fn of(scene: &Scene, seed: u32) -> Self {
+        Self::new(scene.noise_seed.wrapping_add(seed))
+    }
+
+    // Value noise at `p`, as the shader's valueNoise computes it from this table
+    pub fn value(&self, p: Vector3<f32>) -> f32 {
+        let (i, f) = lattice(p);
+        self.blend(f, |[x, y, z]| {
+            self.hash([i[0] + x, i[1] + y, i[2] + z]) as f32 / 127.5 - 1.0
+        })
+    }
+
+    // Perlin noise at `p`, as the shader's perlinNoise computes it from this table
+    pub fn perlin(&self, p: Vector3<f32>) -> f32 {
+        let (i, f) = lattice(p);
+        self.blend(f, |[x, y, z]| {
+            let entry = self.entry(self.hash([i[0] + x, i[1] + y, i[2] + z]));
+            let gradient = [1, 2, 3].map(|c| entry[c] as f32 / 255.0 * 2.0 - 1.0);
+            let offset = [f[0] - x as f32, f[1] - y as f32, f[2] - z as f32];
+            (0..3).map(|c| gradient[c] * offset[c]).sum()
+        })
+    }
+
+    fn entry(&self, i: i32) -> &[u8] {
+        let i = (i & 255) as usize * 4;
+        &self.pixels[i..i + 4]
+    }
+
+    // Table entry picked by a lattice point, 0..255 (the shader's noiseHash)
+    fn hash(&self, [x, y, z]: [i32; 3]) -> i32 {
+        let a = self.entry(x)[0] as i32;
+        let b = self.entry(a + y)[0] as i32;
+        self.entry(b + z)[0] as i32
+    }
+
+    // The values at the 8 corners of a lattice cell blended with the quintic fade at `f`
+    fn blend(&self, f: [f32; 3], corner: impl Fn([i32; 3]) -> f32) -> f32 {
+        let u = f.map(|f| f * f * f * (f * (f * 6.0 - 15.0) + 10.0));
+        let mix = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let edge = |y, z| mix(corner([0, y, z]), corner([1, y, z]), u[0]);
+        let face = |z| mix(edge(0, z), edge(1, z), u[1]);
+        mix(face(0), face(1), u[2])
+    }
+}
+
+// Lattice cell of `p` and the position within it
+fn lattice(p: Vector3<f32>) -> ([i32; 3], [f32; 3]) {
+    let p: [f32; 3] = p.into();
+    (p.map(|c| c.floor() as i32), p.map(|c| c - c.floor()))
+}
+
+// Same hash as the path tracer's random numbers
+pub(crate) fn pcg(v: u32) -> u32 {
+    let state = v.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
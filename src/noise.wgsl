@@ -0,0 +1,111 @@
+// Seeded noise shared by all scenes (see noise.rs): lattice points are hashed through the
+// host's permutation/gradient table, noiseTable. Spliced into shaders.wgsl before the scene
+// code, which can call it too.
+
+fn noiseEntry(i: i32) -> vec4<f32> {
+    return textureLoad(noiseTable, vec2<i32>(i & 255, 0), 0);
+}
+
+// Table entry picked by a lattice point, 0..255
+fn noiseHash(c: vec3<i32>) -> i32 {
+    let a = i32(noiseEntry(c.x).r * 255.0 + 0.5);
+    let b = i32(noiseEntry(a + c.y).r * 255.0 + 0.5);
+    return i32(noiseEntry(b + c.z).r * 255.0 + 0.5);
+}
+
+fn noiseGradient(c: vec3<i32>) -> vec3<f32> {
+    return noiseEntry(noiseHash(c)).gba * 2.0 - 1.0;
+}
+
+fn noiseValue(c: vec3<i32>) -> f32 {
+    return f32(noiseHash(c)) / 127.5 - 1.0;
+}
+
+// Quintic ease between lattice points (continuous second derivative)
+fn noiseFade(f: vec3<f32>) -> vec3<f32> {
+    return f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+}
+
+// Value noise: random values at the lattice points, smoothly blended (-1..1)
+fn valueNoise(p: vec3<f32>) -> f32 {
+    let i = vec3<i32>(floor(p));
+    let u = noiseFade(fract(p));
+    let a = mix(noiseValue(i), noiseValue(i + vec3<i32>(1, 0, 0)), u.x);
+    let b = mix(noiseValue(i + vec3<i32>(0, 1, 0)), noiseValue(i + vec3<i32>(1, 1, 0)), u.x);
+    let c = mix(noiseValue(i + vec3<i32>(0, 0, 1)), noiseValue(i + vec3<i32>(1, 0, 1)), u.x);
+    let d = mix(noiseValue(i + vec3<i32>(0, 1, 1)), noiseValue(i + vec3<i32>(1, 1, 1)), u.x);
+    return mix(mix(a, b, u.y), mix(c, d, u.y), u.z);
+}
+
+fn perlinCorner(i: vec3<i32>, f: vec3<f32>, o: vec3<i32>) -> f32 {
+    return dot(noiseGradient(i + o), f - vec3<f32>(o));
+}
+
+// Perlin (gradient) noise: random slopes at the lattice points, zero on them (about -1..1)
+fn perlinNoise(p: vec3<f32>) -> f32 {
+    let i = vec3<i32>(floor(p));
+    let f = fract(p);
+    let u = noiseFade(f);
+    let a = mix(perlinCorner(i, f, vec3<i32>(0, 0, 0)), perlinCorner(i, f, vec3<i32>(1, 0, 0)), u.x);
+    let b = mix(perlinCorner(i, f, vec3<i32>(0, 1, 0)), perlinCorner(i, f, vec3<i32>(1, 1, 0)), u.x);
+    let c = mix(perlinCorner(i, f, vec3<i32>(0, 0, 1)), perlinCorner(i, f, vec3<i32>(1, 0, 1)), u.x);
+    let d = mix(perlinCorner(i, f, vec3<i32>(0, 1, 1)), perlinCorner(i, f, vec3<i32>(1, 1, 1)), u.x);
+    return mix(mix(a, b, u.y), mix(c, d, u.y), u.z);
+}
+
+fn simplexCorner(c: vec3<f32>, x: vec3<f32>) -> f32 {
+    let t = max(0.6 - dot(x, x), 0.0);
+    return t * t * t * t * dot(noiseGradient(vec3<i32>(c)), x);
+}
+
+// Simplex noise: gradients on a tetrahedral lattice, four corners per sample and no
+// axis-aligned artifacts (about -1..1)
+fn simplexNoise(p: vec3<f32>) -> f32 {
+    let s = floor(p + dot(p, vec3<f32>(1.0 / 3.0)));
+    let x0 = p - s + dot(s, vec3<f32>(1.0 / 6.0));
+    // The simplex holding p: step along the axes in order of x0's components
+    let g = step(x0.yzx, x0);
+    let l = 1.0 - g;
+    let i1 = min(g, l.zxy);
+    let i2 = max(g, l.zxy);
+    let x1 = x0 - i1 + 1.0 / 6.0;
+    let x2 = x0 - i2 + 1.0 / 3.0;
+    let x3 = x0 - 0.5;
+    return 42.0 * (simplexCorner(s, x0) + simplexCorner(s + i1, x1) + simplexCorner(s + i2, x2)
+        + simplexCorner(s + 1.0, x3));
+}
+
+// Fractal Brownian motion: octaves of Perlin noise, each twice the frequency and half the
+// amplitude of the last (about -1..1)
+fn fbm(p: vec3<f32>, octaves: i32) -> f32 {
+    var sum = 0.0;
+    var amplitude = 0.5;
+    var total = 0.0;
+    var q = p;
+    for (var i = 0; i < octaves; i++) {
+        sum += amplitude * perlinNoise(q);
+        total += amplitude;
+        amplitude *= 0.5;
+        // Shifted so the octaves' lattices don't all meet at the origin
+        q = q * 2.0 + vec3<f32>(17.3, -31.7, 5.1);
+    }
+    return sum / max(total, 1e-6);
+}
+
+fn curlPotential(p: vec3<f32>) -> vec3<f32> {
+    return vec3<f32>(
+        perlinNoise(p),
+        perlinNoise(p + vec3<f32>(31.4, -12.7, 5.9)),
+        perlinNoise(p + vec3<f32>(-8.3, 27.1, 19.6))
+    );
+}
+
+// Curl noise: the curl of three Perlin noises, a swirling flow that never converges or
+// spreads (divergence-free), e.g. for advecting particles or warping coordinates
+fn curlNoise(p: vec3<f32>) -> vec3<f32> {
+    let e = 0.01;
+    let dx = curlPotential(p + vec3<f32>(e, 0.0, 0.0)) - curlPotential(p - vec3<f32>(e, 0.0, 0.0));
+    let dy = curlPotential(p + vec3<f32>(0.0, e, 0.0)) - curlPotential(p - vec3<f32>(0.0, e, 0.0));
+    let dz = curlPotential(p + vec3<f32>(0.0, 0.0, e)) - curlPotential(p - vec3<f32>(0.0, 0.0, e));
+    return vec3<f32>(dy.z - dz.y, dz.x - dx.z, dx.y - dy.x) / (2.0 * e);
+}
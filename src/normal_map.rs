@@ -0,0 +1,73 @@
+// The scene's tangent-space normal map (see Scene::normal_map) as RGBA8 pixels, ready to be
+// uploaded by the renderers. PNG decoding comes with the "offline" feature (the png crate).
+use crate::scene::Scene;
+use std::path::Path;
+
+pub struct NormalMap {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>, // RGBA8, rows from the top
+}
+
+impl NormalMap {
+    // A single texel pointing straight out of the surface, bound when there is no map
+    pub fn flat() -> Self {
+        Self {
+            width: 1,
+            height: 1,
+            pixels: vec![128, 128, 255, 255],
+        }
+    }
+
+    // The scene's normal map, or the flat one when it has none or it can't be read
+    pub fn of(scene: &Scene) -> Self {
+        let Some(path) = &scene.normal_map else {
+            return Self::flat();
+        };
+        Self::load(path).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            Self::flat()
+        })
+    }
+
+    #[cfg(feature = "offline")]
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let error = |err: &dyn std::fmt::Display| {
+            format!("Failed to read normal map {}: {}", path.display(), err)
+        };
+        let file = std::fs::File::open(path).map_err(|err| error(&err))?;
+        let mut decoder = png::Decoder::new(std::io::BufReader::new(file));
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(|err| error(&err))?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).map_err(|err| error(&err))?;
+        buffer.truncate(info.buffer_size());
+
+        let pixels = match info.color_type {
+            png::ColorType::Rgba => buffer,
+            png::ColorType::Rgb => buffer
+                .chunks_exact(3)
+                .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => buffer
+                .chunks_exact(2)
+                .flat_map(|ga| [ga[0], ga[0], ga[0], ga[1]])
+                .collect(),
+            png::ColorType::Grayscale => buffer.iter().flat_map(|&g| [g, g, g, 255]).collect(),
+            png::ColorType::Indexed => return Err(error(&"unexpected palette image")),
+        };
+        Ok(Self {
+            width: info.width,
+            height: info.height,
+            pixels,
+        })
+    }
+
+    #[cfg(not(feature = "offline"))]
+    pub fn load(path: &Path) -> Result<Self, String> {
+        Err(format!(
+            "Can't read normal map {}: built without the \"offline\" feature (PNG decoding)",
+            path.display()
+        ))
+    }
+}
@@ -0,0 +1,253 @@
+// Offline rendering: draw frames offscreen with the backend and save them as PNGs (and the
+// intermediate textures of a frame as OpenEXR files, see App::dump_passes). Every file saved
+// carries the state that drew it (see App::metadata) as PNG text chunks or OpenEXR string
+// attributes, and with `stamp` in the config file (or --stamp) it is also printed over the image.
+use crate::config::Config;
+use crate::http::{Endpoint, Response};
+use crate::render_thread::Message;
+use crate::session::Replay;
+use crate::status;
+use crate::{scaled_size, App};
+use metal_raymarcher::inspector;
+use metal_raymarcher::sequence::Sequence;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::mpsc::Receiver;
+
+pub const RENDER_FPS: f32 = 60.0; // Time step between rendered frames
+
+// Named values describing how an image was drawn, in the order they are written
+pub type Metadata = Vec<(&'static str, String)>;
+
+pub fn render_frames(
+    app: &mut App,
+    config: &Config,
+    dir: &Path,
+    frames: Option<u32>,
+    range: Option<Range<usize>>, // Of the frames to write (all by default)
+    requests: Option<&Receiver<Message>>, // Monitoring requests (see http.rs)
+) -> Result<(), String> {
+    std::fs::create_dir_all(dir)
+        .map_err(|err| format!("Failed to create {}: {}", dir.display(), err))?;
+
+    let size = scaled_size(
+        winit::dpi::PhysicalSize::new(config.width, config.height),
+        app.render_scale,
+    );
+    let times = frame_times(
+        app.sequencer.as_ref().map(|sequencer| &sequencer.sequence),
+        app.replay.as_ref(),
+        frames,
+    );
+    let frames = times.len();
+    let range = range.unwrap_or(0..frames);
+    if range.end > frames {
+        return Err(format!(
+            "Frames {}..{} asked for, but there are only {}",
+            range.start, range.end, frames
+        ));
+    }
+    for (frame, time) in times.into_iter().enumerate().take(range.end) {
+        app.update_at(time);
+        // Earlier frames are only updated, so scripts and tweens reach the first one drawn
+        // in the state they would have after drawing them all
+        if frame < range.start {
+            continue;
+        }
+        let mut pixels = app.backend.render_to_rgba(size, &app.camera, app.time);
+
+        let path = dir.join(format!("frame_{:04}.png", frame));
+        let metadata = app.stamped(size, &mut pixels);
+        write_png(&path, size.width, size.height, &pixels, &metadata)?;
+        println!("Rendered {} ({}/{})", path.display(), frame + 1, frames);
+        app.stats.frame();
+        app.stats.progress = Some((frame + 1, frames));
+        status::report_progress(frame + 1, frames);
+
+        // The frame just written stands for the latest one until the next is
+        for message in requests.into_iter().flat_map(Receiver::try_iter) {
+            if let Message::Http(endpoint, answer) = message {
+                let response = match endpoint {
+                    Endpoint::Frame => {
+                        encode_png(size.width, size.height, &pixels, &[]).map(|png| Response {
+                            content_type: "image/png",
+                            body: png,
+                        })
+                    }
+                    endpoint => app.http(endpoint),
+                };
+                drop(answer.send(response));
+            }
+        }
+    }
+    status::report_finished();
+    Ok(())
+}
+
+// When each frame of an offline render is: `frames` steps of 1 / RENDER_FPS seconds, by default
+// one or, for a sequence, enough to play it to its end (once, if it repeats); or for a replay,
+// every recorded frame at its own time
+pub fn frame_times(
+    sequence: Option<&Sequence>,
+    replay: Option<&Replay>,
+    frames: Option<u32>,
+) -> Vec<f32> {
+    if let Some(replay) = replay {
+        return (replay.session.frames.iter())
+            .map(|frame| frame.time)
+            .collect();
+    }
+    let frames = frames.unwrap_or_else(|| match sequence {
+        Some(sequence) => (sequence.duration() * RENDER_FPS).ceil() as u32,
+        None => 1,
+    });
+    (0..frames).map(|frame| frame as f32 / RENDER_FPS).collect()
+}
+
+pub fn write_png(
+    path: &Path,
+    width: u32,
+    height: u32,
+    rgba: &[u8],
+    metadata: &[(&str, String)],
+) -> Result<(), String> {
+    let png = encode_png(width, height, rgba, metadata)
+        .map_err(|err| format!("{}: {}", path.display(), err))?;
+    std::fs::write(path, png).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+// `metadata` goes in iTXt chunks (UTF-8 text), ahead of the image data
+pub fn encode_png(
+    width: u32,
+    height: u32,
+    rgba: &[u8],
+    metadata: &[(&str, String)],
+) -> Result<Vec<u8>, String> {
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    for (key, value) in metadata {
+        (encoder.add_itxt_chunk(key.to_string(), value.clone())).map_err(|err| err.to_string())?;
+    }
+    let mut writer = encoder.write_header().map_err(|err| err.to_string())?;
+    writer
+        .write_image_data(rgba)
+        .map_err(|err| err.to_string())?;
+    writer.finish().map_err(|err| err.to_string())?;
+    Ok(png)
+}
+
+// Write RGBA floats, unclamped, as an uncompressed OpenEXR file (32-bit float channels), with
+// `metadata` as string attributes in its header
+pub fn write_exr(
+    path: &Path,
+    width: u32,
+    height: u32,
+    rgba: &[f32],
+    metadata: &[(&str, String)],
+) -> Result<(), String> {
+    fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+        header.extend_from_slice(name.as_bytes());
+        header.push(0);
+        header.extend_from_slice(kind.as_bytes());
+        header.push(0);
+        header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+        header.extend_from_slice(value);
+    }
+    // Channels are stored in alphabetical order, each as a run of one row's values
+    const CHANNELS: [(&str, usize); 4] = [("A", 3), ("B", 2), ("G", 1), ("R", 0)];
+
+    let mut channels = Vec::new();
+    for (name, _) in CHANNELS {
+        channels.extend_from_slice(name.as_bytes());
+        channels.push(0);
+        channels.extend_from_slice(&2i32.to_le_bytes()); // FLOAT
+        channels.extend_from_slice(&[0; 4]); // Not linear, reserved
+        channels.extend_from_slice(&1i32.to_le_bytes()); // No subsampling
+        channels.extend_from_slice(&1i32.to_le_bytes());
+    }
+    channels.push(0);
+    let window: Vec<u8> = [0, 0, width as i32 - 1, height as i32 - 1]
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect();
+
+    let mut out = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]; // Magic number, version 2
+    attribute(&mut out, "channels", "chlist", &channels);
+    attribute(&mut out, "compression", "compression", &[0]);
+    attribute(&mut out, "dataWindow", "box2i", &window);
+    attribute(&mut out, "displayWindow", "box2i", &window);
+    attribute(&mut out, "lineOrder", "lineOrder", &[0]); // Top row first
+    attribute(&mut out, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+    attribute(&mut out, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(&mut out, "screenWindowWidth", "float", &1f32.to_le_bytes());
+    for (key, value) in metadata {
+        attribute(&mut out, key, "string", value.as_bytes());
+    }
+    out.push(0);
+
+    // A table of where each row starts, then the rows
+    let row_bytes = width as usize * CHANNELS.len() * 4;
+    let rows_start = out.len() + height as usize * 8;
+    for y in 0..height as usize {
+        let offset = rows_start + y * (8 + row_bytes);
+        out.extend_from_slice(&(offset as u64).to_le_bytes());
+    }
+    for (y, row) in rgba.chunks_exact(width as usize * 4).enumerate() {
+        out.extend_from_slice(&(y as i32).to_le_bytes());
+        out.extend_from_slice(&(row_bytes as i32).to_le_bytes());
+        for (_, channel) in CHANNELS {
+            for pixel in row.chunks_exact(4) {
+                out.extend_from_slice(&pixel[channel].to_le_bytes());
+            }
+        }
+    }
+    std::fs::write(path, out).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+// Print `metadata` over the bottom of an RGBA image, on a dark band across it, in the
+// inspector's font: "key: value" entries one after another, wrapped to the image's width. The
+// font grows with the image, a dot being a pixel up to 1280 pixels across.
+pub fn stamp(width: u32, height: u32, rgba: &mut [u8], metadata: &[(&str, String)]) {
+    let (width, height) = (width as usize, height as usize);
+    let dot = width / 1280 + 1;
+    let columns = (width / dot).saturating_sub(4) / 4; // Glyphs are 3 dots wide with a dot between
+    let mut lines: Vec<String> = Vec::new();
+    for (key, value) in metadata {
+        let entry = format!("{}: {}", key, value);
+        match lines.last_mut() {
+            Some(line) if line.len() + 3 + entry.len() <= columns => {
+                line.push_str("   ");
+                line.push_str(&entry);
+            }
+            _ => lines.push(entry),
+        }
+    }
+    let line_height = 7 * dot; // 5 dots of glyph and 2 between lines
+    let band = (lines.len() * line_height + dot * 2).min(height);
+    for y in height - band..height {
+        for pixel in rgba[y * width * 4..(y + 1) * width * 4].chunks_exact_mut(4) {
+            for channel in &mut pixel[..3] {
+                *channel /= 4;
+            }
+        }
+    }
+    for (line, text) in lines.iter().enumerate() {
+        let top = height - band + dot * 2 + line * line_height;
+        for (index, c) in text.chars().take(columns).enumerate() {
+            let left = dot * 2 + index * 4 * dot;
+            for (row, bits) in inspector::glyph(c).iter().enumerate() {
+                for column in (0..3).filter(|column| bits & (0b100 >> column) != 0) {
+                    let (x, y) = (left + column * dot, top + row * dot);
+                    for y in (y..y + dot).take_while(|&y| y < height) {
+                        for x in (x..x + dot).take_while(|&x| x < width) {
+                            let at = (y * width + x) * 4;
+                            rgba[at..at + 3].fill(255);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,25 @@
+// Overlay pass: screen-space triangles (gizmos) blended over the raymarched image
+#include <metal_stdlib>
+using namespace metal;
+
+struct OverlayVertex {
+    packed_float2 position; // Normalized device coordinates
+    packed_float4 color;
+};
+
+struct OverlayOut {
+    float4 position [[position]];
+    float4 color;
+};
+
+vertex OverlayOut overlay_vertex(uint vertexID [[vertex_id]],
+                                 constant OverlayVertex* vertices [[buffer(0)]]) {
+    OverlayOut out;
+    out.position = float4(float2(vertices[vertexID].position), 0.0, 1.0);
+    out.color = float4(vertices[vertexID].color);
+    return out;
+}
+
+fragment float4 overlay_fragment(OverlayOut in [[stage_in]]) {
+    return in.color;
+}
@@ -0,0 +1,24 @@
+// Overlay pass: screen-space triangles (gizmos) blended over the raymarched image
+
+struct VertexIn {
+    @location(0) position: vec2<f32>, // Normalized device coordinates
+    @location(1) color: vec4<f32>,
+};
+
+struct VertexOut {
+    @builtin(position) position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+};
+
+@vertex
+fn overlay_vertex(in: VertexIn) -> VertexOut {
+    var out: VertexOut;
+    out.position = vec4<f32>(in.position, 0.0, 1.0);
+    out.color = in.color;
+    return out;
+}
+
+@fragment
+fn overlay_fragment(in: VertexOut) -> @location(0) vec4<f32> {
+    return in.color;
+}
@@ -0,0 +1,189 @@
+// Command palette: Cmd+K (Ctrl+K outside macOS) opens a box at the top of the window listing
+// every command with its shortcut. Typing narrows the list by fuzzy matching (the letters typed
+// appear in the command's name in order, runs of them and word starts ranking higher), Up and
+// Down move the highlight, Enter runs the highlighted command and Escape closes the box.
+//
+// The commands are whatever the app gives `open`, each carrying its own value to run. The box
+// is drawn like the inspector, with the gizmos' overlay triangles and the 3x5 pixel font.
+use crate::gizmo::{OverlayVertex, View};
+use crate::inspector::{self, ADVANCE, DOT, PADDING, ROW_HEIGHT};
+
+const TOP: f32 = 40.0; // Pixels from the top of the window
+const LABEL_CHARS: usize = 32;
+const SHORTCUT_CHARS: usize = 14;
+const ROWS: usize = 12; // Matches listed at once, scrolling to keep the highlight in view
+
+pub struct Command<T> {
+    pub action: T,
+    pub label: String,
+    pub shortcut: String, // Empty when it has none
+}
+
+pub struct Palette<T> {
+    pub commands: Vec<Command<T>>, // Empty while closed
+    pub query: String,
+    matches: Vec<usize>, // Indices into commands, best first
+    selected: usize,     // Index into matches
+}
+
+impl<T> Default for Palette<T> {
+    fn default() -> Self {
+        Self {
+            commands: Vec::new(),
+            query: String::new(),
+            matches: Vec::new(),
+            selected: 0,
+        }
+    }
+}
+
+impl<T: Clone> Palette<T> {
+    pub fn is_open(&self) -> bool {
+        !self.commands.is_empty()
+    }
+
+    pub fn open(&mut self, commands: Vec<Command<T>>) {
+        self.commands = commands;
+        self.query.clear();
+        self.refilter();
+    }
+
+    pub fn close(&mut self) {
+        *self = Self::default();
+    }
+
+    // Typed text: Enter gives the highlighted command's action and closes the palette
+    pub fn type_char(&mut self, c: char) -> Option<T> {
+        match c {
+            '\r' | '\n' => {
+                let chosen = self.matches.get(self.selected).copied();
+                let action = chosen.map(|index| self.commands[index].action.clone());
+                self.close();
+                return action;
+            }
+            '\u{1b}' => self.close(),
+            '\u{8}' | '\u{7f}' => {
+                self.query.pop();
+                self.refilter();
+            }
+            c if !c.is_control() => {
+                self.query.push(c);
+                self.refilter();
+            }
+            _ => {}
+        }
+        None
+    }
+
+    // Move the highlight `by` matches, wrapping around
+    pub fn select(&mut self, by: isize) {
+        let count = self.matches.len() as isize;
+        if count > 0 {
+            self.selected = (self.selected as isize + by).rem_euclid(count) as usize;
+        }
+    }
+
+    fn refilter(&mut self) {
+        let mut scored: Vec<(i32, usize)> = (self.commands.iter().enumerate())
+            .filter_map(|(index, command)| {
+                score(&self.query, &command.label).map(|score| (score, index))
+            })
+            .collect();
+        // Stable, so equal scores keep the order they were given in
+        scored.sort_by_key(|&(score, _)| -score);
+        self.matches = scored.into_iter().map(|(_, index)| index).collect();
+        self.selected = 0;
+    }
+
+    pub fn vertices(&self, view: &View) -> Vec<OverlayVertex> {
+        let mut out = Vec::new();
+        if !self.is_open() {
+            return out;
+        }
+        let rows = self.matches.len().min(ROWS);
+        let width = PADDING * 2.0 + (LABEL_CHARS + 1 + SHORTCUT_CHARS) as f32 * ADVANCE;
+        let height = PADDING * 2.0 + (rows + 1) as f32 * ROW_HEIGHT;
+        let left = ((view.width as f32 - width) / 2.0).max(0.0);
+        inspector::rectangle(
+            &mut out,
+            view,
+            [left, TOP],
+            [width, height],
+            [0.05, 0.05, 0.08, 0.9],
+        );
+        let row_top = |row: usize| TOP + PADDING + row as f32 * ROW_HEIGHT;
+        let text_top = |row: usize| row_top(row) + (ROW_HEIGHT - 5.0 * DOT) / 2.0;
+        let query = match rows {
+            0 => format!("> {}_  no matches", self.query),
+            _ => format!("> {}_", self.query),
+        };
+        inspector::text(
+            &mut out,
+            view,
+            [left + PADDING, text_top(0)],
+            &query,
+            LABEL_CHARS + 1 + SHORTCUT_CHARS,
+            [1.0, 0.8, 0.2, 1.0],
+        );
+        let first = self.selected.saturating_sub(ROWS - 1);
+        let shown = self.matches.iter().enumerate().skip(first).take(ROWS);
+        for (row, (index, &command)) in (1..).zip(shown) {
+            let command = &self.commands[command];
+            if index == self.selected {
+                inspector::rectangle(
+                    &mut out,
+                    view,
+                    [left, row_top(row)],
+                    [width, ROW_HEIGHT],
+                    [1.0, 1.0, 1.0, 0.12],
+                );
+            }
+            inspector::text(
+                &mut out,
+                view,
+                [left + PADDING, text_top(row)],
+                &command.label,
+                LABEL_CHARS,
+                [1.0, 1.0, 1.0, 1.0],
+            );
+            // Right-aligned
+            let chars = command.shortcut.chars().count().min(SHORTCUT_CHARS);
+            let shortcut_left = left + width - PADDING - chars as f32 * ADVANCE;
+            inspector::text(
+                &mut out,
+                view,
+                [shortcut_left, text_top(row)],
+                &command.shortcut,
+                SHORTCUT_CHARS,
+                [0.7, 0.7, 0.75, 1.0],
+            );
+        }
+        out
+    }
+}
+
+// How well `query` matches `label`, if every character of it appears there in order (ignoring
+// case and spaces): each match scores a point, more right after another match or at the start
+// of a word, and shorter labels rank higher. Everything matches nothing typed, equally.
+fn score(query: &str, label: &str) -> Option<i32> {
+    if query.trim().is_empty() {
+        return Some(0);
+    }
+    let label: Vec<char> = label.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut at = 0;
+    let mut previous = None;
+    for c in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let found = at + label[at..].iter().position(|&l| l == c)?;
+        score += 1;
+        if previous == Some(found.wrapping_sub(1)) {
+            score += 4;
+        }
+        if found == 0 || !label[found - 1].is_alphanumeric() {
+            score += 3;
+        }
+        previous = Some(found);
+        at = found + 1;
+    }
+    Some(score * 8 - label.len() as i32)
+}
@@ -0,0 +1,352 @@
+// Picking objects under the cursor. The scene's distance function is evaluated on the CPU the
+// same way the generated sceneMap does it (see codegen.rs), and marched along the ray the
+// shader casts through that pixel. Objects are identified by their index in
+// `Scene::objects()`, the object id the shader sees. The floor stops the ray but is not one.
+use crate::camera::Camera;
+use crate::cells;
+use crate::heightfield;
+use crate::plant;
+use crate::scene::{CellStyle, CsgOp, Node, RotationPlane, Scene, SceneObject, Shape};
+use cgmath::{InnerSpace, Vector2, Vector3, Vector4};
+
+const MAX_STEPS: u32 = 256;
+const EPSILON: f32 = 0.001;
+
+// Object under `mouse` (normalized, y up, as the app tracks it) in a view of `aspect`
+// (width / height), if the ray hits one within `max_distance`
+pub fn pick(
+    scene: &Scene,
+    camera: &Camera,
+    mouse: Vector2<f32>,
+    aspect: f32,
+    max_distance: f32,
+) -> Option<usize> {
+    pick_point(scene, camera, mouse, aspect, max_distance).map(|(id, _)| id)
+}
+
+// As `pick`, along with the point on the surface that was hit
+pub fn pick_point(
+    scene: &Scene,
+    camera: &Camera,
+    mouse: Vector2<f32>,
+    aspect: f32,
+    max_distance: f32,
+) -> Option<(usize, Vector3<f32>)> {
+    let rd = camera.ray(Vector2::new(
+        (mouse.x - 0.5) * 2.0 * aspect,
+        (mouse.y - 0.5) * 2.0,
+    ));
+
+    let mut t = 0.0;
+    for _ in 0..MAX_STEPS {
+        let p = camera.position + rd * t;
+        let (d, id) = distance(scene, p);
+        if d < EPSILON {
+            return id.map(|id| (id, p));
+        }
+        if t > max_distance {
+            break;
+        }
+        t += d * 0.8; // As conservative as the shader
+    }
+    None
+}
+
+// Surface normal at `p`, from the gradient of the distance
+pub fn normal(scene: &Scene, p: Vector3<f32>) -> Vector3<f32> {
+    let d = |x: f32, y: f32, z: f32| distance(scene, p + Vector3::new(x, y, z)).0;
+    let e = EPSILON;
+    Vector3::new(
+        d(e, 0.0, 0.0) - d(-e, 0.0, 0.0),
+        d(0.0, e, 0.0) - d(0.0, -e, 0.0),
+        d(0.0, 0.0, e) - d(0.0, 0.0, -e),
+    )
+    .normalize()
+}
+
+// Euler angles (degrees, as SceneObject::rotation) that turn an object's up axis (+Y) to
+// `normal`: a turn about X, then one about Z
+pub fn surface_rotation(normal: Vector3<f32>) -> [f32; 3] {
+    let n = normal.normalize();
+    let x = n.z.clamp(-1.0, 1.0).asin();
+    let z = (-n.x).atan2(n.y);
+    [x.to_degrees(), 0.0, z.to_degrees()]
+}
+
+// How far the surface of `object` reaches from its position in `direction` (0 when it does
+// not reach out that way)
+pub fn extent(object: &SceneObject, direction: Vector3<f32>) -> f32 {
+    const REACH: f32 = 100.0; // Marched in from this far out
+    let origin = Vector3::from(object.position);
+    let direction = direction.normalize();
+    let mut t = 0.0;
+    for _ in 0..MAX_STEPS {
+        let d = object_distance(object, origin + direction * (REACH - t));
+        if d < EPSILON {
+            return REACH - t;
+        }
+        t += d;
+        if t >= REACH {
+            break;
+        }
+    }
+    0.0
+}
+
+// Distance from `p` to the scene and the id of the nearest object (None for an empty scene or
+// the floor)
+pub fn distance(scene: &Scene, p: Vector3<f32>) -> (f32, Option<usize>) {
+    let mut next = 0;
+    let res = fold(&scene.nodes, p, &mut next);
+    match scene.ground() {
+        Some(floor) => combine(CsgOp::Union, res, (p.y - floor.height, None)),
+        None => res,
+    }
+}
+
+// Fold a list of nodes like emit_nodes does, counting object ids in traversal order
+fn fold(nodes: &[Node], p: Vector3<f32>, next: &mut usize) -> (f32, Option<usize>) {
+    let mut res = (1e10, None);
+    for (index, node) in nodes.iter().enumerate() {
+        let value = match node {
+            Node::Object(object) => {
+                let id = *next;
+                *next += 1;
+                (object_distance(object, p), Some(id))
+            }
+            Node::Group(group) => fold(&group.children, p, next),
+        };
+        res = if index == 0 {
+            value
+        } else {
+            combine(node.op(), res, value)
+        };
+    }
+    res
+}
+
+fn combine(op: CsgOp, a: (f32, Option<usize>), b: (f32, Option<usize>)) -> (f32, Option<usize>) {
+    match op {
+        CsgOp::Union => {
+            if a.0 < b.0 {
+                a
+            } else {
+                b
+            }
+        }
+        CsgOp::SmoothUnion { k } => {
+            let h = (0.5 + 0.5 * (b.0 - a.0) / k).clamp(0.0, 1.0);
+            let d = b.0 + (a.0 - b.0) * h - k * h * (1.0 - h);
+            (d, if a.0 < b.0 { a.1 } else { b.1 })
+        }
+        CsgOp::Subtract => (a.0.max(-b.0), a.1),
+        CsgOp::SmoothSubtract { k } => {
+            let h = (0.5 - 0.5 * (a.0 + b.0) / k).clamp(0.0, 1.0);
+            (a.0 + (-b.0 - a.0) * h + k * h * (1.0 - h), a.1)
+        }
+        CsgOp::Intersect => {
+            if a.0 > b.0 {
+                a
+            } else {
+                b
+            }
+        }
+    }
+}
+
+fn object_distance(object: &SceneObject, p: Vector3<f32>) -> f32 {
+    let mut local = unrotate(p - Vector3::from(object.position), object.rotation) / object.scale;
+    // In the nearest copy, as opRepeat
+    if let Some(repeat) = &object.repeat {
+        for axis in 0..3 {
+            let (n, spacing) = (repeat.count[axis] as f32, repeat.spacing[axis]);
+            if n > 1.0 {
+                let c = 0.5 * (n - 1.0);
+                local[axis] -=
+                    spacing * ((local[axis] / spacing + c).round().clamp(0.0, n - 1.0) - c);
+            }
+        }
+    }
+    let mut d = match &object.shape {
+        Shape::Sphere { radius } => local.magnitude() - radius,
+        Shape::Box { size } => {
+            let q = local.map(f32::abs) - Vector3::from(*size);
+            q.map(|c| c.max(0.0)).magnitude() + q.x.max(q.y.max(q.z)).min(0.0)
+        }
+        Shape::Plane { normal, height } => local.dot(Vector3::from(*normal)) + height,
+        Shape::Capsule { a, b, radius } => {
+            let (a, b) = (Vector3::from(*a), Vector3::from(*b));
+            let (pa, ba) = (local - a, b - a);
+            let h = if ba.magnitude2() > 0.0 {
+                (pa.dot(ba) / ba.magnitude2()).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            (pa - ba * h).magnitude() - radius
+        }
+        Shape::Tesseract {
+            size,
+            w,
+            plane,
+            angle,
+        } => {
+            let d = slice(local, *w, *plane, *angle).map(f32::abs)
+                - Vector4::new(*size, *size, *size, *size);
+            d.map(|c| c.max(0.0)).magnitude() + d.x.max(d.y).max(d.z.max(d.w)).min(0.0)
+        }
+        Shape::Hypersphere {
+            radius,
+            w,
+            plane,
+            angle,
+        } => slice(local, *w, *plane, *angle).magnitude() - radius,
+        Shape::Julia { c, w, plane, angle } => {
+            julia(slice(local, *w, *plane, *angle), Vector4::from(*c))
+        }
+        Shape::Extrusion {
+            outline: loops,
+            depth,
+            rounding,
+            ..
+        } => {
+            let h = Vector2::new(
+                outline(loops, local.truncate()) + rounding,
+                local.z.abs() - depth + rounding,
+            );
+            h.x.max(h.y).min(0.0) + h.map(|c| c.max(0.0)).magnitude() - rounding
+        }
+        Shape::Revolution { outline: loops, .. } => {
+            outline(loops, Vector2::new(local.x.hypot(local.z), local.y))
+        }
+        // Still, as the shader draws it before the wind blows
+        Shape::Plant { blend, .. } => {
+            let segments = plant::segments(&object.shape).unwrap_or_default();
+            segments.iter().fold(1e10, |d: f32, segment| {
+                let (a, b) = (Vector3::from(segment.a), Vector3::from(segment.b));
+                let (pa, ba) = (local - a, b - a);
+                let h = (pa.dot(ba) / ba.magnitude2().max(1e-8)).clamp(0.0, 1.0);
+                let capsule = (pa - ba * h).magnitude() - segment.radius;
+                join(d, capsule, *blend)
+            })
+        }
+        Shape::Cloud {
+            radius,
+            blend,
+            points,
+            ..
+        } => points.iter().fold(1e10, |d: f32, point| {
+            let center = Vector3::new(point[0], point[1], point[2]);
+            join(d, (local - center).magnitude() - radius, *blend)
+        }),
+        Shape::Heightfield {
+            size,
+            height,
+            exaggeration,
+            base,
+            heights,
+            ..
+        } => {
+            let height = height * exaggeration;
+            let h = height * heightfield::sample(heights, *size, local.x, local.z);
+            let top = height.max(0.0);
+            let q = Vector3::new(
+                local.x.abs() - size[0],
+                (local.y - 0.5 * (top - base)).abs() - 0.5 * (top + base),
+                local.z.abs() - size[1],
+            );
+            let bounds = q.map(|c| c.max(0.0)).magnitude() + q.x.max(q.y.max(q.z)).min(0.0);
+            let slope = height * heightfield::slope(heights, *size);
+            ((local.y - h) / (1.0 + slope * slope).sqrt()).max(bounds)
+        }
+    };
+    // With the cell sites where they rest: picking doesn't follow their drift
+    if let Some(cells) = &object.cells {
+        let border = cells::border(local, &cells::sites(cells, 0.0));
+        d = match cells.style {
+            CellStyle::Cracks => d.max(cells.width - border),
+            CellStyle::Foam => d.max(border - cells.width),
+        };
+    }
+    d * object.scale
+}
+
+// Union of two distances, smooth over `k` as smin
+fn join(a: f32, b: f32, k: f32) -> f32 {
+    if k == 0.0 {
+        return a.min(b);
+    }
+    let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+    b + (a - b) * h - k * h * (1.0 - h)
+}
+
+// The 4D point of a 4D shape's slice at `local`, turned back in its rotation plane, as
+// opRotate4 does
+fn slice(local: Vector3<f32>, w: f32, plane: RotationPlane, degrees: f32) -> Vector4<f32> {
+    let q = local.extend(w);
+    let axis = Vector4::from(plane.axis());
+    let (sin, cos) = (-degrees.to_radians()).sin_cos();
+    let u = q.dot(axis);
+    q + axis * (u * cos - q.w * sin - u) + Vector4::new(0.0, 0.0, 0.0, u * sin + q.w * cos - q.w)
+}
+
+// Signed distance to the even-odd fill of `loops`, as sdOutline<id>
+fn outline(loops: &[Vec<[f32; 2]>], p: Vector2<f32>) -> f32 {
+    let mut d = f32::MAX;
+    let mut s = 1.0;
+    let edges = loops
+        .iter()
+        .flat_map(|points| (0..points.len()).map(|i| (points[i], points[(i + 1) % points.len()])));
+    for (a, b) in edges.filter(|(a, b)| a != b) {
+        let (a, b) = (Vector2::from(a), Vector2::from(b));
+        let (e, w) = (b - a, p - a);
+        let q = w - e * (w.dot(e) / e.magnitude2()).clamp(0.0, 1.0);
+        d = d.min(q.magnitude2());
+        let crossing = [p.y >= a.y, p.y < b.y, e.x * w.y > e.y * w.x];
+        if crossing.iter().all(|&c| c) || crossing.iter().all(|&c| !c) {
+            s = -s;
+        }
+    }
+    s * d.sqrt()
+}
+
+// Distance estimate to the quaternion Julia set of z * z + c, as sdJulia
+fn julia(q: Vector4<f32>, c: Vector4<f32>) -> f32 {
+    let mut z = q;
+    let mut z2 = z.magnitude2();
+    let mut dz2 = 1.0;
+    for _ in 0..11 {
+        dz2 *= 4.0 * z2;
+        let v = z.truncate_n(0);
+        z = Vector4::new(
+            z.x * z.x - v.magnitude2(),
+            2.0 * z.x * v.x,
+            2.0 * z.x * v.y,
+            2.0 * z.x * v.z,
+        ) + c;
+        z2 = z.magnitude2();
+        if z2 > 256.0 {
+            break;
+        }
+    }
+    0.25 * (z2 / dz2).sqrt() * z2.ln()
+}
+
+// Undo a rotation of `degrees` (X, then Y, then Z), as opRotate does
+fn unrotate(p: Vector3<f32>, degrees: [f32; 3]) -> Vector3<f32> {
+    let [x, y, z] = degrees.map(|angle| -angle.to_radians());
+    let q = Vector3::new(
+        p.x * z.cos() - p.y * z.sin(),
+        p.x * z.sin() + p.y * z.cos(),
+        p.z,
+    );
+    let q = Vector3::new(
+        q.x * y.cos() + q.z * y.sin(),
+        q.y,
+        q.z * y.cos() - q.x * y.sin(),
+    );
+    Vector3::new(
+        q.x,
+        q.y * x.cos() - q.z * x.sin(),
+        q.y * x.sin() + q.z * x.cos(),
+    )
+}
@@ -0,0 +1,158 @@
+// Plants grown from L-systems, for the plant shape (see scene.rs). The axiom is rewritten by
+// the rules `iterations` times, and the result drives a 3D turtle that starts at the origin
+// heading up +y:
+//
+//   F      draw a segment `length` long        f      move that far without drawing
+//   + -    turn left / right by `angle`        & ^    pitch down / up by `angle`
+//   \ /    roll left / right by `angle`        |      turn around
+//   [ ]    start / end a branch, whose segments are `taper` times as long and thick
+//
+// Other symbols only take part in the rewriting. Each segment is a capsule, and the shader
+// blends them into one surface (see codegen.rs's sdPlant<id>); segments that carry straight
+// on from the one before are merged, so "F" -> "FF" rules don't multiply them.
+use crate::scene::Shape;
+use cgmath::{Deg, InnerSpace, Matrix3, Vector3};
+
+// Most segments a plant may have: the shader visits each for every distance it takes
+pub const MAX_SEGMENTS: usize = 256;
+
+// Longest the rewritten string may grow; past this, more iterations are an error
+const MAX_SYMBOLS: usize = 100_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Segment {
+    pub a: [f32; 3],
+    pub b: [f32; 3],
+    pub radius: f32,
+}
+
+// Where the turtle is and which way it faces
+#[derive(Clone, Copy)]
+struct Turtle {
+    position: Vector3<f32>,
+    heading: Vector3<f32>,
+    left: Vector3<f32>,
+    up: Vector3<f32>,
+    length: f32,
+    radius: f32,
+    last: Option<usize>, // Segment ending where the turtle is, which straight lines extend
+}
+
+// The segments of a plant shape (none for other shapes), or why it can't be grown
+pub fn segments(shape: &Shape) -> Result<Vec<Segment>, String> {
+    let Shape::Plant {
+        axiom,
+        rules,
+        iterations,
+        angle,
+        length,
+        radius,
+        taper,
+        ..
+    } = shape
+    else {
+        return Ok(Vec::new());
+    };
+    let mut symbols = axiom.clone();
+    for _ in 0..*iterations {
+        let mut next = String::new();
+        for symbol in symbols.chars() {
+            match rules.get(symbol.encode_utf8(&mut [0; 4]) as &str) {
+                Some(replacement) => next.push_str(replacement),
+                None => next.push(symbol),
+            }
+            if next.len() > MAX_SYMBOLS {
+                return Err(format!(
+                    "plant grows past {} symbols; use fewer iterations",
+                    MAX_SYMBOLS
+                ));
+            }
+        }
+        symbols = next;
+    }
+
+    let mut turtle = Turtle {
+        position: Vector3::new(0.0, 0.0, 0.0),
+        heading: Vector3::unit_y(),
+        left: -Vector3::unit_x(),
+        up: Vector3::unit_z(),
+        length: *length,
+        radius: *radius,
+        last: None,
+    };
+    let mut stack = Vec::new();
+    let mut segments: Vec<Segment> = Vec::new();
+    let turn = |degrees: f32, axis: Vector3<f32>, vectors: [&mut Vector3<f32>; 2]| {
+        let rotation = Matrix3::from_axis_angle(axis, Deg(degrees));
+        for vector in vectors {
+            *vector = (rotation * *vector).normalize();
+        }
+    };
+    for symbol in symbols.chars() {
+        let Turtle {
+            heading, left, up, ..
+        } = turtle;
+        match symbol {
+            'F' => {
+                let end = turtle.position + turtle.heading * turtle.length;
+                // Straight on from the last segment at the same thickness: make it longer
+                let straight = turtle.last.filter(|&last| {
+                    let segment = segments[last];
+                    let direction = Vector3::from(segment.b) - Vector3::from(segment.a);
+                    segment.radius == turtle.radius
+                        && direction.normalize().dot(turtle.heading) > 0.9999
+                });
+                match straight {
+                    Some(last) => segments[last].b = end.into(),
+                    None => {
+                        if segments.len() == MAX_SEGMENTS {
+                            return Err(format!(
+                                "plant grows more than {} segments; use fewer iterations",
+                                MAX_SEGMENTS
+                            ));
+                        }
+                        segments.push(Segment {
+                            a: turtle.position.into(),
+                            b: end.into(),
+                            radius: turtle.radius,
+                        });
+                        turtle.last = Some(segments.len() - 1);
+                    }
+                }
+                turtle.position = end;
+            }
+            'f' => {
+                turtle.position += turtle.heading * turtle.length;
+                turtle.last = None;
+            }
+            '+' | '-' => {
+                let sign = if symbol == '+' { 1.0 } else { -1.0 };
+                turn(sign * angle, up, [&mut turtle.heading, &mut turtle.left]);
+            }
+            '&' | '^' => {
+                let sign = if symbol == '&' { 1.0 } else { -1.0 };
+                turn(sign * angle, left, [&mut turtle.heading, &mut turtle.up]);
+            }
+            '\\' | '/' => {
+                let sign = if symbol == '\\' { 1.0 } else { -1.0 };
+                turn(sign * angle, heading, [&mut turtle.left, &mut turtle.up]);
+            }
+            '|' => turn(180.0, up, [&mut turtle.heading, &mut turtle.left]),
+            '[' => {
+                stack.push(turtle);
+                turtle.length *= taper;
+                turtle.radius *= taper;
+            }
+            ']' => {
+                if let Some(saved) = stack.pop() {
+                    turtle = saved;
+                }
+            }
+            _ => {}
+        }
+    }
+    if segments.is_empty() {
+        return Err("plant has no segments; its symbols draw no F".to_string());
+    }
+    Ok(segments)
+}
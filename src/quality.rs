@@ -0,0 +1,106 @@
+// Quality presets: bundles of raymarching settings plus a render scale, picked with
+// --quality / `preset` in the config and cycled at runtime (Q by default).
+// Without an explicit choice, low-power GPUs (e.g. integrated Intel graphics) start on Medium.
+use crate::tween::Lerp;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum QualityPreset {
+    Low,
+    Medium,
+    High,
+    Ultra,
+}
+
+// How edges are anti-aliased: `aa` x `aa` rays per pixel, or one ray marched as a cone as
+// wide as the pixel that blends in the surfaces it passes by (its shadows soften the same way)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum AaMode {
+    #[default]
+    Supersample,
+    Cone,
+}
+
+// Raymarching settings ([quality] in the config file overrides the preset's values)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QualityConfig {
+    pub max_steps: u32,    // March iterations per ray
+    pub epsilon: f32,      // Hit threshold
+    pub max_distance: f32, // Rays give up (sky) beyond this distance
+    pub shadow_steps: u32, // Soft shadow march iterations (0 = no shadows)
+    pub ao_samples: u32,   // Ambient occlusion samples (0 = no AO)
+    pub aa: u32,           // Supersampling grid per axis (1 = off, 2 = 4 rays per pixel)
+    pub aa_mode: AaMode,
+}
+
+impl Default for QualityConfig {
+    fn default() -> Self {
+        QualityPreset::High.settings()
+    }
+}
+
+// Blends every setting, so a preset change can ease in (see tween.rs)
+impl Lerp for QualityConfig {
+    fn lerp(&self, to: &Self, t: f32) -> Self {
+        QualityConfig {
+            max_steps: self.max_steps.lerp(&to.max_steps, t),
+            epsilon: self.epsilon.lerp(&to.epsilon, t),
+            max_distance: self.max_distance.lerp(&to.max_distance, t),
+            shadow_steps: self.shadow_steps.lerp(&to.shadow_steps, t),
+            ao_samples: self.ao_samples.lerp(&to.ao_samples, t),
+            aa: self.aa.lerp(&to.aa, t).max(1),
+            aa_mode: if t < 0.5 { self.aa_mode } else { to.aa_mode },
+        }
+    }
+}
+
+impl QualityPreset {
+    // Default preset for a GPU that is (or is not) a low-power/integrated one
+    pub fn for_gpu(low_power: bool) -> Self {
+        if low_power {
+            QualityPreset::Medium
+        } else {
+            QualityPreset::High
+        }
+    }
+
+    pub fn settings(self) -> QualityConfig {
+        let (max_steps, epsilon, max_distance, shadow_steps, ao_samples, aa) = match self {
+            QualityPreset::Low => (48, 0.004, 30.0, 0, 0, 1),
+            QualityPreset::Medium => (80, 0.002, 40.0, 16, 0, 1),
+            QualityPreset::High => (100, 0.001, 50.0, 16, 5, 1),
+            QualityPreset::Ultra => (200, 0.0005, 80.0, 32, 8, 2),
+        };
+        QualityConfig {
+            max_steps,
+            epsilon,
+            max_distance,
+            shadow_steps,
+            ao_samples,
+            aa,
+            aa_mode: AaMode::Supersample,
+        }
+    }
+
+    // Multiplies the configured render scale
+    pub fn render_scale(self) -> f32 {
+        match self {
+            QualityPreset::Low => 0.5,
+            QualityPreset::Medium => 0.75,
+            QualityPreset::High | QualityPreset::Ultra => 1.0,
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            QualityPreset::Low => QualityPreset::Medium,
+            QualityPreset::Medium => QualityPreset::High,
+            QualityPreset::High => QualityPreset::Ultra,
+            QualityPreset::Ultra => QualityPreset::Low,
+        }
+    }
+}
@@ -0,0 +1,1940 @@
+// The Metal renderer: a fullscreen raymarching pass that can be encoded into any texture.
+// It owns no window or event loop, so other Metal apps can draw a raymarched layer with it.
+//
+// With path tracing on, each frame traces one more path per pixel into a float texture holding
+// the average so far (two of them, read from one and written to the other in turn) and draws
+// that average, through an edge-aware blur while denoising. It starts over whenever anything
+// but time, audio and the clock changes, and stops adding paths once MAX_SAMPLES are in.
+//
+// With edges on, each frame first renders the normal and distance of every pixel's first hit
+// into another float texture (the G-buffer), and draws lines found in it over the image. With
+// light shafts on, it marches the light scattered in the fog into one more (at half resolution
+// by default) and adds that over the image. With adaptive quality on, it first marks the tiles
+// of the image that need supersampling in a small texture of their own, which the image reads.
+//
+// Each of those passes goes into a command buffer of its own on a second queue, committed ahead
+// of the caller's (which the image is drawn in), so the GPU time each takes can be read back
+// (see pass_times). Their textures come in pairs used by every other frame, so a frame's passes
+// run alongside the image of the frame before instead of waiting for it (see PassOrder).
+//
+// With culling on (and adaptive quality off), the image's command buffer first draws the
+// bounding spheres of the scene's top-level nodes with mesh shaders into a mask per tile of
+// the nodes the tile can see, and the image marches each tile's rays without the rest.
+use crate::adaptive::Adaptive;
+use crate::audio::AudioLevels;
+use crate::camera::Camera;
+use crate::clock::ClockPhase;
+use crate::codegen::{self, Dialect, ParamLayout, SCENE_MARKER};
+use crate::edges::{EdgeMode, Edges};
+use crate::gizmo::{OverlayVertex, MAX_OVERLAY_VERTICES};
+use crate::inputs::TextureSource;
+use crate::mesh::MeshData;
+use crate::noise::{self, NoiseTable, NOISE_MARKER};
+use crate::normal_map::NormalMap;
+use crate::quality::QualityConfig;
+use crate::scene::Scene;
+use crate::shader_test;
+use crate::shafts::Shafts;
+use crate::toon::Toon;
+use crate::uniforms::{self, FrameInputs, SceneInputs, Uniforms};
+use crate::volume::VolumeData;
+use crate::wgsl;
+use cgmath::Vector2;
+use metal::*;
+use objc::rc::autoreleasepool;
+use objc::{msg_send, sel, sel_impl};
+use std::cell::Cell;
+use std::mem;
+
+// Built-in shader template ("// @scene@" is replaced by the scene code, "// @noise@" by the
+// noise functions and "// @meshes@" by the mesh functions)
+pub const BUILTIN_SHADER: &str = include_str!("shaders.metal");
+const OVERLAY_SHADER: &str = include_str!("overlay.metal");
+// Spliced into the built-in shader for scenes with meshes (see mesh.rs)
+const MESH_SHADER: &str = include_str!("meshes.metal");
+const MESH_MARKER: &str = "// @meshes@";
+
+// Paths per pixel after which a path-traced image is left as it is
+pub const MAX_SAMPLES: u32 = 4096;
+// Of the path tracer's average, the G-buffer, the light shafts and the tiles
+const ACCUMULATION_FORMAT: MTLPixelFormat = MTLPixelFormat::RGBA32Float;
+// Width and height of the frame a new shader draws before it replaces the current one
+const TEST_FRAME_SIZE: u64 = 16;
+// Side in pixels of the squares culling finds the nodes of (CULL_TILE in shaders.metal)
+const CULL_TILE: u64 = 16;
+
+pub struct Raymarcher {
+    device: Device,
+    command_queue: CommandQueue,
+    async_queue: CommandQueue, // For the passes ahead of the image
+    library: Library,          // Compiled shader of the current scene
+    pipeline_state: RenderPipelineState,
+    path_tracer: Option<PathTracer>,
+    edge_pass: Option<EdgePass>,
+    shaft_pass: Option<ShaftPass>,
+    tile_pass: Option<TilePass>,
+    cull_pass: Option<CullPass>,
+    transients: Option<Heap>, // Holds the passes' textures (see prepare)
+    set: usize,               // Which of each pair of them the last frame drew into
+    read: [u64; 2],           // The image pass that last read each (see PassOrder)
+    order: PassOrder,
+    pixel_format: MTLPixelFormat,
+    vertex_buffer: Buffer,
+    placeholder_texture: Texture, // Bound in place of video/screen textures that are off
+    normal_map: Texture,
+    noise: Texture,           // Table for the noise functions (see noise.rs)
+    volume: Texture,          // The scene's volume dataset (see volume.rs), 3D
+    transfer: Texture,        // Its transfer function
+    meshes: Option<MeshPass>, // The scene's meshes, unless the device can't trace them
+    quality: QualityConfig,
+    mouse: Vector2<f32>, // Normalized, passed through to the shader
+    audio: AudioLevels,
+    clock: ClockPhase,
+    selected: Option<usize>,
+    guides: bool,
+    toon: Option<Toon>, // Over the scene's own
+    denoise: bool,      // Path-traced images are drawn through denoise_main
+    seed: u32,
+    edges: Edges,
+    shafts: Option<Shafts>,
+    adaptive: Option<Adaptive>,
+    scene: SceneInputs,
+    last_uniforms: Option<Uniforms>, // Of the last frame encoded, for reports
+    video: Option<Box<dyn TextureSource>>,
+    video_mix: f32,
+    screen: Option<Box<dyn TextureSource>>,
+    screen_mix: f32,
+    crossfade: Option<Crossfade>,
+    overlay_pipeline_state: RenderPipelineState,
+    overlay: Vec<OverlayVertex>, // Drawn over the image (gizmos)
+    timer: PassTimer,
+}
+
+// The command buffers of a frame's passes, kept until they have all finished to read how long
+// the GPU spent on each (one frame at a time: the ones encoded meanwhile aren't timed)
+#[derive(Default)]
+struct PassTimer {
+    pending: Vec<(&'static str, CommandBuffer)>,
+    timing: bool,                    // Whether the frame being encoded is
+    times: Vec<(&'static str, f64)>, // Of the last frame timed, in seconds
+}
+
+impl PassTimer {
+    // Start a frame: take the times of the one being timed if it has finished, and time this
+    // one if none is
+    fn begin(&mut self) {
+        let done = |buffer: &CommandBuffer| {
+            matches!(
+                buffer.status(),
+                MTLCommandBufferStatus::Completed | MTLCommandBufferStatus::Error
+            )
+        };
+        if self.pending.iter().all(|(_, buffer)| done(buffer)) {
+            let failed = (self.pending.iter())
+                .any(|(_, buffer)| buffer.status() == MTLCommandBufferStatus::Error);
+            if !self.pending.is_empty() && !failed {
+                self.times = (self.pending.drain(..))
+                    .map(|(name, buffer)| {
+                        let (start, end): (f64, f64) = unsafe {
+                            (
+                                msg_send![buffer, GPUStartTime],
+                                msg_send![buffer, GPUEndTime],
+                            )
+                        };
+                        (name, (end - start).max(0.0))
+                    })
+                    .collect();
+            }
+            self.pending.clear();
+        }
+        self.timing = self.pending.is_empty();
+    }
+
+    fn record(&mut self, name: &'static str, buffer: &CommandBufferRef) {
+        if self.timing {
+            self.pending.push((name, buffer.to_owned()));
+        }
+    }
+}
+
+// A second scene drawn over the first with some opacity, with its own camera
+struct Crossfade {
+    library: Library,
+    pipeline_state: RenderPipelineState,
+    // When edges, shafts, adaptive quality and culling are on, for when it replaces the current
+    // scene
+    edges: Option<(RenderPipelineState, RenderPipelineState)>,
+    shafts: Option<(RenderPipelineState, RenderPipelineState)>,
+    tiles: Option<(RenderPipelineState, RenderPipelineState)>,
+    culling: Option<(RenderPipelineState, RenderPipelineState)>,
+    scene: SceneInputs,
+    normal_map: Texture,
+    noise: Texture,
+    volume: (Texture, Texture),
+    meshes: Option<MeshPass>,
+    camera: Camera,
+    opacity: f32,
+}
+
+struct PathTracer {
+    trace: RenderPipelineState, // pathtrace_main, into an accumulation texture
+    resolve: RenderPipelineState, // resolve_main, the average into the target
+    denoise: RenderPipelineState, // denoise_main, the average blurred into the target
+    accumulation: Vec<Texture>, // Two, made at the target's size
+    samples: u32,
+    key: Vec<u8>,   // Uniforms the average was traced with (see Uniforms::still_key)
+    read: [u64; 2], // The image pass that last read each accumulation texture (see PassOrder)
+}
+
+// The passes' textures are untracked (see float_descriptor), and the passes ahead of the image
+// run on a queue of their own, so Metal doesn't order the command buffers. Each pass signals a
+// shared event of its own once it has finished, and waits only for the passes whose textures it
+// touches: the image for the tiles, G-buffer and light shafts of its frame, those for the image
+// that last read the textures of the pair they draw into (two frames back, or the last one when
+// `prepare` laid them out again over the old ones' memory), and the path tracer for its last
+// paths and the image that last read the accumulation texture it writes. Each event is signaled
+// in order, as each signal waits for the pass's one before.
+struct PassOrder {
+    tiles: PassEvent,
+    gbuffer: PassEvent,
+    shafts: PassEvent,
+    paths: PassEvent,
+    image: PassEvent, // The image pass or the path tracer's resolve
+}
+
+impl PassOrder {
+    fn new(device: &DeviceRef) -> Self {
+        PassOrder {
+            tiles: PassEvent::new(device),
+            gbuffer: PassEvent::new(device),
+            shafts: PassEvent::new(device),
+            paths: PassEvent::new(device),
+            image: PassEvent::new(device),
+        }
+    }
+
+    // The passes the image reads
+    fn wait_inputs(&self, command_buffer: &CommandBufferRef) {
+        self.tiles.wait(command_buffer);
+        self.gbuffer.wait(command_buffer);
+        self.shafts.wait(command_buffer);
+    }
+
+    fn wait_all(&self, command_buffer: &CommandBufferRef) {
+        self.wait_inputs(command_buffer);
+        self.paths.wait(command_buffer);
+        self.image.wait(command_buffer);
+    }
+}
+
+struct PassEvent {
+    event: SharedEvent,
+    signaled: Cell<u64>, // The last value the event is signaled to
+}
+
+impl PassEvent {
+    fn new(device: &DeviceRef) -> Self {
+        PassEvent {
+            event: device.new_shared_event(),
+            signaled: Cell::new(0),
+        }
+    }
+
+    // Wait for the pass's last command buffer
+    fn wait(&self, command_buffer: &CommandBufferRef) {
+        self.wait_for(command_buffer, self.signaled.get());
+    }
+
+    fn wait_for(&self, command_buffer: &CommandBufferRef, value: u64) {
+        command_buffer.encode_wait_for_event(&self.event, value);
+    }
+
+    // Signal once `command_buffer` has finished and the pass's last one has; the value it
+    // signals
+    fn done(&self, command_buffer: &CommandBufferRef) -> u64 {
+        self.wait(command_buffer);
+        self.signaled.set(self.signaled.get() + 1);
+        command_buffer.encode_signal_event(&self.event, self.signaled.get());
+        self.signaled.get()
+    }
+}
+
+struct EdgePass {
+    gbuffer: RenderPipelineState, // gbuffer_main, into the G-buffer
+    edges: RenderPipelineState,   // edges_main, blended over the target
+    textures: Vec<Texture>,       // The G-buffers, a pair made at the target's size
+}
+
+struct ShaftPass {
+    trace: RenderPipelineState,     // shafts_main, into the light texture
+    composite: RenderPipelineState, // shafts_composite_main, added over the target
+    textures: Vec<Texture>,         // The light, a pair made at Shafts::size of the target's
+}
+
+// The scene's meshes, traced by the image's passes (see meshes.metal)
+struct MeshPass {
+    accel: AccelerationStructure, // Of the triangles
+    triangles: Buffer,            // Their corners, in the world
+    colors: Buffer,               // A color per triangle
+}
+
+struct TilePass {
+    classify: RenderPipelineState, // tiles_main, into the tile texture
+    image: RenderPipelineState,    // adaptive_main, in place of fragment_main
+    textures: Vec<Texture>,        // The tiles, a pair made at Adaptive::size of the target's
+}
+
+struct CullPass {
+    proxies: RenderPipelineState, // proxies_object, _mesh and _fragment, into the masks
+    image: RenderPipelineState,   // culled_main, in place of fragment_main
+    masks: Option<Buffer>,        // A u32 per tile, made at the target's size by encode_culling
+}
+
+// Metal devices, queues, pipelines and resources are thread-safe, and the texture sources
+// are Send, so a Raymarcher can be built on one thread and render on another
+unsafe impl Send for Raymarcher {}
+
+// Shader template (the built-in one if None) with the noise functions and the generated scene
+// code spliced in. A WGSL template gets them in WGSL and is translated (see wgsl.rs).
+pub fn shader_source(template: Option<&str>, scene: &Scene) -> Result<String, String> {
+    let layout = ParamLayout::of(scene);
+    let template = template.unwrap_or(BUILTIN_SHADER);
+    if wgsl::is_wgsl(template) {
+        let source = template.replace(NOISE_MARKER, noise::WGSL_SOURCE).replace(
+            SCENE_MARKER,
+            &codegen::scene_source_with(scene, Dialect::Wgsl, &layout),
+        );
+        return wgsl::to_msl(&source);
+    }
+    let mut source = template.replace(NOISE_MARKER, noise::MSL_SOURCE).replace(
+        SCENE_MARKER,
+        &codegen::scene_source_with(scene, Dialect::Msl, &layout),
+    );
+    if template == BUILTIN_SHADER && !scene.meshes.is_empty() {
+        source = source.replace(MESH_MARKER, MESH_SHADER);
+    }
+    Ok(source)
+}
+
+impl Raymarcher {
+    // Renderer for `scene` drawing into textures of `pixel_format`
+    pub fn new(
+        device: &DeviceRef,
+        scene: &Scene,
+        pixel_format: MTLPixelFormat,
+    ) -> Result<Self, String> {
+        let mut raymarcher = Self::from_source(device, &shader_source(None, scene)?, pixel_format)?;
+        raymarcher.set_scene(scene);
+        raymarcher.set_normal_map(&NormalMap::of(scene));
+        raymarcher.set_noise(&NoiseTable::of(scene, 0));
+        raymarcher.set_volume(&VolumeData::of(scene.volume.as_ref()));
+        if let Err(err) = raymarcher.set_meshes(&MeshData::of(&scene.meshes)) {
+            eprintln!("{}", err);
+        }
+        Ok(raymarcher)
+    }
+
+    // Renderer using complete MSL source (see `shader_source`); call `set_scene`,
+    // `set_normal_map`, `set_noise`, `set_volume` and `set_meshes` with the scene it was
+    // generated from
+    pub fn from_source(
+        device: &DeviceRef,
+        source: &str,
+        pixel_format: MTLPixelFormat,
+    ) -> Result<Self, String> {
+        let command_queue = device.new_command_queue();
+        let async_queue = device.new_command_queue();
+
+        let library = library(device, source)?;
+        let pipeline_state = pipeline_state(device, &library, pixel_format)?;
+        let overlay_pipeline_state = overlay_pipeline_state(device, pixel_format)?;
+        let meshes = mesh_pass(device, &command_queue, &MeshData::default())?;
+
+        // Create fullscreen quad vertices
+        let vertices: [[f32; 2]; 6] = [
+            [-1.0, -1.0],
+            [1.0, -1.0],
+            [-1.0, 1.0], // First triangle
+            [1.0, -1.0],
+            [1.0, 1.0],
+            [-1.0, 1.0], // Second triangle
+        ];
+
+        let vertex_buffer = device.new_buffer_with_data(
+            vertices.as_ptr() as *const _,
+            (vertices.len() * mem::size_of::<[f32; 2]>()) as u64,
+            MTLResourceOptions::StorageModeShared,
+        );
+
+        let placeholder_descriptor = TextureDescriptor::new();
+        placeholder_descriptor.set_pixel_format(MTLPixelFormat::BGRA8Unorm);
+        placeholder_descriptor.set_width(1);
+        placeholder_descriptor.set_height(1);
+        let placeholder_texture = device.new_texture(&placeholder_descriptor);
+        let normal_map = normal_map_texture(device, &NormalMap::flat());
+        let noise = noise_texture(device, &NoiseTable::new(0));
+        let (volume, transfer) = volume_textures(device, &VolumeData::empty());
+
+        Ok(Self {
+            device: device.to_owned(),
+            command_queue,
+            async_queue,
+            library,
+            pipeline_state,
+            path_tracer: None,
+            edge_pass: None,
+            shaft_pass: None,
+            tile_pass: None,
+            cull_pass: None,
+            transients: None,
+            set: 0,
+            read: [0; 2],
+            order: PassOrder::new(device),
+            pixel_format,
+            vertex_buffer,
+            placeholder_texture,
+            normal_map,
+            noise,
+            volume,
+            transfer,
+            meshes,
+            quality: QualityConfig::default(),
+            mouse: Vector2::new(0.5, 0.5),
+            audio: AudioLevels::default(),
+            clock: ClockPhase::default(),
+            selected: None,
+            guides: false,
+            toon: None,
+            denoise: true,
+            seed: 0,
+            last_uniforms: None,
+            edges: Edges::default(),
+            shafts: None,
+            adaptive: None,
+            scene: SceneInputs::of(&Scene::default()),
+            video: None,
+            video_mix: 1.0,
+            screen: None,
+            screen_mix: 1.0,
+            crossfade: None,
+            overlay_pipeline_state,
+            overlay: Vec::new(),
+            timer: PassTimer::default(),
+        })
+    }
+
+    pub fn device(&self) -> &DeviceRef {
+        &self.device
+    }
+
+    pub fn command_queue(&self) -> &CommandQueueRef {
+        &self.command_queue
+    }
+
+    // Carry on with another device (the current one was removed): remake every Metal object
+    // on `device` from `source`, generated from `scene`, keeping the settings and the scene's
+    // current values. Video and screen sources and a cross-fade belong to the old device and
+    // are dropped; a path-traced image starts over.
+    pub fn move_to(
+        &mut self,
+        device: &DeviceRef,
+        source: &str,
+        scene: &Scene,
+    ) -> Result<(), String> {
+        let mut moved = Self::from_source(device, source, self.pixel_format)?;
+        moved.set_normal_map(&NormalMap::of(scene));
+        moved.set_noise(&NoiseTable::of(scene, self.seed));
+        moved.set_volume(&VolumeData::of(scene.volume.as_ref()));
+        if let Err(err) = moved.set_meshes(&MeshData::of(&scene.meshes)) {
+            eprintln!("{}", err);
+        }
+        moved.set_edges(self.edges)?;
+        moved.set_shafts(self.shafts)?;
+        moved.set_adaptive(self.adaptive)?;
+        moved.set_culling(self.cull_pass.is_some())?;
+        moved.set_path_tracing(self.path_tracer.is_some())?;
+        moved.scene = self.scene.clone();
+        moved.quality = self.quality.clone();
+        moved.mouse = self.mouse;
+        moved.audio = self.audio;
+        moved.clock = self.clock;
+        moved.selected = self.selected;
+        moved.guides = self.guides;
+        moved.toon = self.toon;
+        moved.denoise = self.denoise;
+        moved.seed = self.seed;
+        moved.overlay = mem::take(&mut self.overlay);
+        *self = moved;
+        Ok(())
+    }
+
+    // Recompile with new source, e.g. after the scene's structure changed; keeps the inputs
+    // and settings, the meshes among them. Every pipeline is built and a test frame drawn
+    // before any is replaced, so on an error the current shader carries on.
+    pub fn rebuild(&mut self, source: &str) -> Result<(), String> {
+        let library = library(&self.device, source)?;
+        let pipeline = pipeline_state(&self.device, &library, self.pixel_format)?;
+        let paths = (self.path_tracer.as_ref())
+            .map(|_| path_pipelines(&self.device, &library, self.pixel_format))
+            .transpose()?;
+        let edges = (self.edge_pass.as_ref())
+            .map(|_| edge_pipelines(&self.device, &library, self.pixel_format))
+            .transpose()?;
+        let shafts = (self.shaft_pass.as_ref())
+            .map(|_| shaft_pipelines(&self.device, &library, self.pixel_format))
+            .transpose()?;
+        let tiles = (self.tile_pass.as_ref())
+            .map(|_| tile_pipelines(&self.device, &library, self.pixel_format))
+            .transpose()?;
+        let culling = (self.cull_pass.as_ref())
+            .map(|_| cull_pipelines(&self.device, &library, self.pixel_format))
+            .transpose()?;
+        self.test_frame(&pipeline)?;
+
+        self.pipeline_state = pipeline;
+        if let (Some(tracer), Some(paths)) = (self.path_tracer.as_mut(), paths) {
+            (tracer.trace, tracer.resolve, tracer.denoise) = paths;
+            tracer.samples = 0;
+        }
+        if let (Some(pass), Some(edges)) = (self.edge_pass.as_mut(), edges) {
+            (pass.gbuffer, pass.edges) = edges;
+        }
+        if let (Some(pass), Some(shafts)) = (self.shaft_pass.as_mut(), shafts) {
+            (pass.trace, pass.composite) = shafts;
+        }
+        if let (Some(pass), Some(tiles)) = (self.tile_pass.as_mut(), tiles) {
+            (pass.classify, pass.image) = tiles;
+        }
+        if let (Some(pass), Some(culling)) = (self.cull_pass.as_mut(), culling) {
+            (pass.proxies, pass.image) = culling;
+        }
+        self.library = library;
+        Ok(())
+    }
+
+    // Intermediate textures of the last frame, in the order they were drawn: the tiles marked
+    // for adaptive quality (1 where detailed), the G-buffer (normal and distance of each
+    // pixel's first hit), the light shafts and the path tracer's average. Read them with
+    // `read_pass` once the frame has finished.
+    pub fn passes(&self) -> Vec<(&'static str, &TextureRef)> {
+        let mut passes: Vec<(&'static str, &TextureRef)> = Vec::new();
+        if let Some(texture) = self
+            .tile_pass
+            .as_ref()
+            .and_then(|pass| in_set(&pass.textures, self.set))
+        {
+            passes.push(("tiles", texture));
+        }
+        if let Some(texture) = self
+            .edge_pass
+            .as_ref()
+            .and_then(|pass| in_set(&pass.textures, self.set))
+        {
+            passes.push(("gbuffer", texture));
+        }
+        if let Some(texture) = self
+            .shaft_pass
+            .as_ref()
+            .and_then(|pass| in_set(&pass.textures, self.set))
+        {
+            passes.push(("shafts", texture));
+        }
+        if let Some(tracer) = &self.path_tracer {
+            if let Some(texture) = tracer.accumulation.get(tracer.samples as usize % 2) {
+                passes.push(("paths", texture));
+            }
+        }
+        passes
+    }
+
+    // Copy one of `passes` (private to the GPU) back as RGBA floats, waiting for the copy
+    pub fn read_pass(&self, texture: &TextureRef) -> Vec<f32> {
+        let (width, height) = (texture.width(), texture.height());
+        let row_bytes = width * 4 * mem::size_of::<f32>() as u64;
+        let buffer =
+            (self.device).new_buffer(row_bytes * height, MTLResourceOptions::StorageModeShared);
+        autoreleasepool(|| {
+            let command_buffer = self.command_queue.new_command_buffer();
+            self.order.wait_all(command_buffer);
+            let blit = command_buffer.new_blit_command_encoder();
+            blit.copy_from_texture_to_buffer(
+                texture,
+                0,
+                0,
+                MTLOrigin { x: 0, y: 0, z: 0 },
+                MTLSize::new(width, height, 1),
+                &buffer,
+                0,
+                row_bytes,
+                row_bytes * height,
+                MTLBlitOption::empty(),
+            );
+            blit.end_encoding();
+            command_buffer.commit();
+            command_buffer.wait_until_completed();
+        });
+        let floats = (width * height * 4) as usize;
+        unsafe { std::slice::from_raw_parts(buffer.contents() as *const f32, floats) }.to_vec()
+    }
+
+    // Draw a small frame with `pipeline` off screen and wait for it, so a shader that faults
+    // on the GPU, or runs until the GPU's watchdog stops it, fails here rather than blanking
+    // or stalling the frames that are shown
+    fn test_frame(&self, pipeline: &RenderPipelineStateRef) -> Result<(), String> {
+        let descriptor = TextureDescriptor::new();
+        descriptor.set_pixel_format(self.pixel_format);
+        descriptor.set_width(TEST_FRAME_SIZE);
+        descriptor.set_height(TEST_FRAME_SIZE);
+        descriptor.set_storage_mode(MTLStorageMode::Private);
+        descriptor.set_usage(MTLTextureUsage::RenderTarget);
+        let target = self.device.new_texture(&descriptor);
+        let uniforms = self.uniforms(&target, &Camera::default(), 0.0, &self.scene, false);
+
+        autoreleasepool(|| {
+            let command_buffer = self.command_queue.new_command_buffer();
+            let encoder = command_buffer.new_render_command_encoder(clear_pass(&target));
+            encoder.set_render_pipeline_state(pipeline);
+            encoder.set_vertex_buffer(0, Some(&self.vertex_buffer), 0);
+            encoder.set_fragment_bytes(
+                0,
+                mem::size_of::<Uniforms>() as u64,
+                &uniforms as *const Uniforms as *const _,
+            );
+            encoder.set_fragment_texture(0, Some(&self.placeholder_texture));
+            encoder.set_fragment_texture(1, Some(&self.placeholder_texture));
+            encoder.set_fragment_texture(3, Some(&self.normal_map));
+            encoder.set_fragment_texture(4, Some(&self.noise));
+            encoder.set_fragment_texture(5, Some(&self.volume));
+            encoder.set_fragment_texture(6, Some(&self.transfer));
+            if let Some(pass) = &self.meshes {
+                pass.bind(encoder);
+            }
+            encoder.set_blend_color(0.0, 0.0, 0.0, 1.0);
+            encoder.draw_primitives(MTLPrimitiveType::Triangle, 0, 6);
+            encoder.end_encoding();
+            command_buffer.commit();
+            command_buffer.wait_until_completed();
+            match command_buffer.status() {
+                MTLCommandBufferStatus::Completed => Ok(()),
+                status => Err(format!(
+                    "The new shader failed its test frame on the GPU ({:?})",
+                    status
+                )),
+            }
+        })
+    }
+
+    // Switch between raymarching and progressive path tracing (the shader needs the built-in
+    // one's pathtrace_main, resolve_main and denoise_main)
+    pub fn set_path_tracing(&mut self, on: bool) -> Result<(), String> {
+        if !on {
+            self.path_tracer = None;
+            return Ok(());
+        }
+        if self.path_tracer.is_some() {
+            return Ok(());
+        }
+        let (trace, resolve, denoise) =
+            path_pipelines(&self.device, &self.library, self.pixel_format)?;
+        self.path_tracer = Some(PathTracer {
+            trace,
+            resolve,
+            denoise,
+            accumulation: Vec::new(),
+            samples: 0,
+            key: Vec::new(),
+            read: [0; 2],
+        });
+        Ok(())
+    }
+
+    // Edge detection over the image (see edges.rs; the shader needs the built-in one's
+    // gbuffer_main and edges_main)
+    pub fn set_edges(&mut self, edges: Edges) -> Result<(), String> {
+        self.edges = edges;
+        if edges.mode == EdgeMode::Off {
+            self.edge_pass = None;
+            return Ok(());
+        }
+        if self.edge_pass.is_some() {
+            return Ok(());
+        }
+        let (gbuffer, edges) = edge_pipelines(&self.device, &self.library, self.pixel_format)?;
+        self.edge_pass = Some(EdgePass {
+            gbuffer,
+            edges,
+            textures: Vec::new(),
+        });
+        Ok(())
+    }
+
+    // Volumetric light shafts (see shafts.rs; the shader needs the built-in one's shafts_main
+    // and shafts_composite_main)
+    pub fn set_shafts(&mut self, shafts: Option<Shafts>) -> Result<(), String> {
+        self.shafts = shafts;
+        if shafts.is_none() {
+            self.shaft_pass = None;
+            return Ok(());
+        }
+        if self.shaft_pass.is_some() {
+            return Ok(());
+        }
+        let (trace, composite) = shaft_pipelines(&self.device, &self.library, self.pixel_format)?;
+        self.shaft_pass = Some(ShaftPass {
+            trace,
+            composite,
+            textures: Vec::new(),
+        });
+        Ok(())
+    }
+
+    // Adaptive quality (see adaptive.rs; the shader needs the built-in one's tiles_main and
+    // adaptive_main). Path-traced images leave it out.
+    pub fn set_adaptive(&mut self, adaptive: Option<Adaptive>) -> Result<(), String> {
+        self.adaptive = adaptive;
+        if adaptive.is_none() {
+            self.tile_pass = None;
+            return Ok(());
+        }
+        if self.tile_pass.is_some() {
+            return Ok(());
+        }
+        let (classify, image) = tile_pipelines(&self.device, &self.library, self.pixel_format)?;
+        self.tile_pass = Some(TilePass {
+            classify,
+            image,
+            textures: Vec::new(),
+        });
+        Ok(())
+    }
+
+    // Culling (see codegen::proxies; the shader needs the built-in one's proxies_object,
+    // proxies_mesh, proxies_fragment and culled_main, and the device mesh shaders). Adaptive
+    // quality and path-traced images leave it out.
+    pub fn set_culling(&mut self, on: bool) -> Result<(), String> {
+        if !on {
+            self.cull_pass = None;
+            return Ok(());
+        }
+        if self.cull_pass.is_some() {
+            return Ok(());
+        }
+        if !self.device.supports_family(MTLGPUFamily::Metal3) {
+            return Err(format!(
+                "{} has no mesh shaders, so culling is off",
+                self.device.name()
+            ));
+        }
+        let (proxies, image) = cull_pipelines(&self.device, &self.library, self.pixel_format)?;
+        self.cull_pass = Some(CullPass {
+            proxies,
+            image,
+            masks: None,
+        });
+        Ok(())
+    }
+
+    // Paths per pixel in the path-traced image so far (None when not path tracing)
+    pub fn samples(&self) -> Option<u32> {
+        self.path_tracer.as_ref().map(|tracer| tracer.samples)
+    }
+
+    // Start cross-fading to another scene (`source` generated from `scene`); it is drawn over
+    // the current one with the opacity given to `set_crossfade`
+    pub fn begin_crossfade(&mut self, source: &str, scene: &Scene) -> Result<(), String> {
+        let library = library(&self.device, source)?;
+        let pipeline = pipeline_state(&self.device, &library, self.pixel_format)?;
+        self.test_frame(&pipeline)?;
+        let edges = (self.edge_pass.as_ref())
+            .map(|_| edge_pipelines(&self.device, &library, self.pixel_format))
+            .transpose()?;
+        let shafts = (self.shaft_pass.as_ref())
+            .map(|_| shaft_pipelines(&self.device, &library, self.pixel_format))
+            .transpose()?;
+        let tiles = (self.tile_pass.as_ref())
+            .map(|_| tile_pipelines(&self.device, &library, self.pixel_format))
+            .transpose()?;
+        let culling = (self.cull_pass.as_ref())
+            .map(|_| cull_pipelines(&self.device, &library, self.pixel_format))
+            .transpose()?;
+        let meshes = mesh_pass(
+            &self.device,
+            &self.command_queue,
+            &MeshData::of(&scene.meshes),
+        )
+        .unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            None
+        });
+        self.crossfade = Some(Crossfade {
+            pipeline_state: pipeline,
+            edges,
+            shafts,
+            tiles,
+            culling,
+            library,
+            scene: SceneInputs::of(scene),
+            normal_map: normal_map_texture(&self.device, &NormalMap::of(scene)),
+            noise: noise_texture(&self.device, &NoiseTable::of(scene, self.seed)),
+            volume: volume_textures(&self.device, &VolumeData::of(scene.volume.as_ref())),
+            meshes,
+            camera: Camera::default(),
+            opacity: 0.0,
+        });
+        Ok(())
+    }
+
+    // Parameter values, camera and opacity (0..1) of the scene being faded in
+    pub fn set_crossfade(&mut self, scene: &Scene, camera: &Camera, opacity: f32) {
+        if let Some(crossfade) = self.crossfade.as_mut() {
+            crossfade.scene = SceneInputs::of(scene);
+            crossfade.camera = *camera;
+            crossfade.opacity = opacity.clamp(0.0, 1.0);
+        }
+    }
+
+    // End the cross-fade; with `keep` the scene faded in replaces the current one (a path
+    // tracer keeps the current one's pipelines until the next rebuild). Edges, light shafts,
+    // adaptive quality and culling are for the current scene only: the one fading in is drawn
+    // under the edges and shafts, with the preset's supersampling and every node.
+    pub fn end_crossfade(&mut self, keep: bool) {
+        match self.crossfade.take() {
+            Some(crossfade) if keep => {
+                self.library = crossfade.library;
+                self.pipeline_state = crossfade.pipeline_state;
+                self.scene = crossfade.scene;
+                if let (Some(pass), Some(edges)) = (self.edge_pass.as_mut(), crossfade.edges) {
+                    (pass.gbuffer, pass.edges) = edges;
+                }
+                if let (Some(pass), Some(shafts)) = (self.shaft_pass.as_mut(), crossfade.shafts) {
+                    (pass.trace, pass.composite) = shafts;
+                }
+                if let (Some(pass), Some(tiles)) = (self.tile_pass.as_mut(), crossfade.tiles) {
+                    (pass.classify, pass.image) = tiles;
+                }
+                if let (Some(pass), Some(culling)) = (self.cull_pass.as_mut(), crossfade.culling) {
+                    (pass.proxies, pass.image) = culling;
+                }
+                self.normal_map = crossfade.normal_map;
+                self.noise = crossfade.noise;
+                (self.volume, self.transfer) = crossfade.volume;
+                self.meshes = crossfade.meshes;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn set_quality(&mut self, quality: QualityConfig) {
+        self.quality = quality;
+    }
+
+    pub fn set_mouse(&mut self, mouse: Vector2<f32>) {
+        self.mouse = mouse;
+    }
+
+    // Audio levels for this frame (see audio.rs)
+    pub fn set_audio(&mut self, audio: AudioLevels) {
+        self.audio = audio;
+    }
+
+    // Musical clock for this frame (see clock.rs)
+    pub fn set_clock(&mut self, clock: ClockPhase) {
+        self.clock = clock;
+    }
+
+    // Object to highlight, by its index in `Scene::objects()` (see picking.rs)
+    pub fn set_selected(&mut self, selected: Option<usize>) {
+        self.selected = selected;
+    }
+
+    // Draw the ground grid and axes over the scene
+    pub fn set_guides(&mut self, guides: bool) {
+        self.guides = guides;
+    }
+
+    // Toon shading for every scene; None leaves it to each scene (see toon.rs)
+    pub fn set_toon(&mut self, toon: Option<Toon>) {
+        self.toon = toon;
+    }
+
+    // Blur path-traced images of few samples without blurring over edges (on by default)
+    pub fn set_denoise(&mut self, denoise: bool) {
+        self.denoise = denoise;
+    }
+
+    // Offset the path tracer's random numbers and the light shafts' jitter, which otherwise
+    // follow each pixel's position and the sample count alone, and the noise of scenes faded
+    // to: the same seed, scene, camera and time draw the same image. The current scene's noise
+    // takes the seed through `set_noise`, given NoiseTable::of(scene, seed).
+    pub fn set_seed(&mut self, seed: u32) {
+        self.seed = seed;
+    }
+
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    // How long the GPU spent on each pass of the latest frame timed (a frame or two behind the
+    // one being drawn), in seconds, named as in `passes` with "image" for the pass drawing
+    // into the target
+    pub fn pass_times(&self) -> &[(&'static str, f64)] {
+        &self.timer.times
+    }
+
+    // Bytes of memory the device has allocated, for all the app's Metal objects
+    pub fn allocated_size(&self) -> u64 {
+        self.device.current_allocated_size()
+    }
+
+    // The uniforms of the last frame encoded, spelled out for a crash or error report
+    pub fn describe_uniforms(&self) -> Option<String> {
+        self.last_uniforms
+            .map(|uniforms| format!("{:#?}", uniforms))
+    }
+
+    // Triangles drawn over the image in normalized device coordinates (see gizmo.rs)
+    pub fn set_overlay(&mut self, vertices: &[OverlayVertex]) {
+        self.overlay.clear();
+        self.overlay
+            .extend_from_slice(&vertices[..vertices.len().min(MAX_OVERLAY_VERTICES)]);
+    }
+
+    // Lighting and animated parameter values of `scene` (the one the shader was generated
+    // from, possibly with different values); call whenever they change
+    pub fn set_scene(&mut self, scene: &Scene) {
+        self.scene = SceneInputs::of(scene);
+    }
+
+    // Tangent-space normal map for objects with `bump` (see Scene::normal_map)
+    pub fn set_normal_map(&mut self, normal_map: &NormalMap) {
+        self.normal_map = normal_map_texture(&self.device, normal_map);
+    }
+
+    // Permutation/gradient table for the noise functions (see noise.rs)
+    pub fn set_noise(&mut self, table: &NoiseTable) {
+        self.noise = noise_texture(&self.device, table);
+    }
+
+    // Voxels and transfer function of the scene's volume (see volume.rs)
+    pub fn set_volume(&mut self, volume: &VolumeData) {
+        (self.volume, self.transfer) = volume_textures(&self.device, volume);
+    }
+
+    // Triangles of the scene's meshes (see mesh.rs), drawn when the shader was generated from
+    // a scene with meshes and the device can ray trace them; none for a scene without, or
+    // after an error
+    pub fn set_meshes(&mut self, meshes: &MeshData) -> Result<(), String> {
+        self.meshes = mesh_pass(&self.device, &self.command_queue, meshes)?;
+        Ok(())
+    }
+
+    // Texture projected onto textured objects; `mix` blends it with their color
+    pub fn set_video(&mut self, source: Option<Box<dyn TextureSource>>, mix: f32) {
+        self.video = source;
+        self.video_mix = mix;
+    }
+
+    // Texture refracted through textured objects and used as the backdrop
+    pub fn set_screen(&mut self, source: Option<Box<dyn TextureSource>>, mix: f32) {
+        self.screen = source;
+        self.screen_mix = mix;
+    }
+
+    // Pull the latest frames from the video/screen sources; call once per frame
+    pub fn upload_inputs(&mut self) {
+        if let Some(video) = self.video.as_mut() {
+            video.upload(&self.device);
+        }
+        if let Some(screen) = self.screen.as_mut() {
+            screen.upload(&self.device);
+        }
+    }
+
+    // Render into `target` on the raymarcher's own queue (does not wait for the GPU)
+    pub fn render_into(&mut self, target: &TextureRef, camera: &Camera, time: f32) {
+        autoreleasepool(|| {
+            let command_buffer = self.command_queue.new_command_buffer().to_owned();
+            self.encode(&command_buffer, target, camera, time);
+            command_buffer.commit();
+        });
+    }
+
+    // Encode the raymarching pass (and the passes around it) into `target` as part of the
+    // caller's command buffer. Later frames' passes wait for it (see PassOrder), so it has to be
+    // committed before the next frame's command buffers are; one dropped without being committed
+    // leaves every later frame waiting forever.
+    pub fn encode(
+        &mut self,
+        command_buffer: &CommandBufferRef,
+        target: &TextureRef,
+        camera: &Camera,
+        time: f32,
+    ) {
+        let uniforms = self.uniforms(target, camera, time, &self.scene, true);
+        self.last_uniforms = Some(uniforms);
+        self.set = 1 - self.set;
+        self.prepare(target.width(), target.height());
+        self.timer.begin();
+        let adaptive = self.path_tracer.is_none() && self.encode_tiles(&uniforms);
+        self.encode_gbuffer(&uniforms);
+        self.encode_shafts(&uniforms);
+        if self.path_tracer.is_some() {
+            self.encode_path_traced(command_buffer, target, uniforms);
+            self.timer.record("image", command_buffer);
+            return;
+        }
+
+        self.order.wait_inputs(command_buffer);
+        let culled = !adaptive && self.encode_culling(command_buffer, target, &uniforms);
+        let render_encoder = command_buffer.new_render_command_encoder(clear_pass(target));
+
+        let tiles = self.tile_pass.as_ref().filter(|_| adaptive);
+        let culling = self.cull_pass.as_ref().filter(|_| culled);
+        match (tiles, culling) {
+            (Some(pass), _) => {
+                render_encoder.set_render_pipeline_state(&pass.image);
+                render_encoder.set_fragment_texture(2, in_set(&pass.textures, self.set));
+            }
+            (None, Some(pass)) => {
+                render_encoder.set_render_pipeline_state(&pass.image);
+                render_encoder.set_fragment_buffer(1, pass.masks.as_deref(), 0);
+            }
+            (None, None) => render_encoder.set_render_pipeline_state(&self.pipeline_state),
+        }
+        render_encoder.set_vertex_buffer(0, Some(&self.vertex_buffer), 0);
+        // Uniforms are copied into the command stream, so each pass keeps its own camera/time
+        render_encoder.set_fragment_bytes(
+            0,
+            mem::size_of::<Uniforms>() as u64,
+            &uniforms as *const Uniforms as *const _,
+        );
+        let video_texture = match &self.video {
+            Some(video) => video.texture(),
+            None => &self.placeholder_texture,
+        };
+        render_encoder.set_fragment_texture(0, Some(video_texture));
+        let screen_texture = match &self.screen {
+            Some(screen) => screen.texture(),
+            None => &self.placeholder_texture,
+        };
+        render_encoder.set_fragment_texture(1, Some(screen_texture));
+        render_encoder.set_fragment_texture(3, Some(&self.normal_map));
+        render_encoder.set_fragment_texture(4, Some(&self.noise));
+        render_encoder.set_fragment_texture(5, Some(&self.volume));
+        render_encoder.set_fragment_texture(6, Some(&self.transfer));
+        if let Some(pass) = &self.meshes {
+            pass.bind(render_encoder);
+        }
+        render_encoder.set_blend_color(0.0, 0.0, 0.0, 1.0);
+        render_encoder.draw_primitives(MTLPrimitiveType::Triangle, 0, 6);
+        self.encode_shaft_composite(render_encoder);
+        self.encode_edges(render_encoder);
+
+        if let Some(crossfade) = self.crossfade.as_ref().filter(|fade| fade.opacity > 0.0) {
+            let uniforms = self.uniforms(target, &crossfade.camera, time, &crossfade.scene, false);
+            render_encoder.set_render_pipeline_state(&crossfade.pipeline_state);
+            render_encoder.set_fragment_bytes(
+                0,
+                mem::size_of::<Uniforms>() as u64,
+                &uniforms as *const Uniforms as *const _,
+            );
+            render_encoder.set_fragment_texture(3, Some(&crossfade.normal_map));
+            render_encoder.set_fragment_texture(4, Some(&crossfade.noise));
+            render_encoder.set_fragment_texture(5, Some(&crossfade.volume.0));
+            render_encoder.set_fragment_texture(6, Some(&crossfade.volume.1));
+            if let Some(pass) = &crossfade.meshes {
+                pass.bind(render_encoder);
+            }
+            render_encoder.set_blend_color(0.0, 0.0, 0.0, crossfade.opacity);
+            render_encoder.draw_primitives(MTLPrimitiveType::Triangle, 0, 6);
+        }
+
+        self.encode_overlay(render_encoder);
+        render_encoder.end_encoding();
+        self.read[self.set] = self.order.image.done(command_buffer);
+        self.timer.record("image", command_buffer);
+    }
+
+    // Trace another path per pixel (unless the image is done) and draw the average so far
+    fn encode_path_traced(
+        &mut self,
+        command_buffer: &CommandBufferRef,
+        target: &TextureRef,
+        mut uniforms: Uniforms,
+    ) {
+        let Some(tracer) = self.path_tracer.as_mut() else {
+            return;
+        };
+        let key = uniforms.still_key();
+        if key != tracer.key {
+            tracer.key = key;
+            tracer.samples = 0;
+        }
+
+        uniforms.set_samples(tracer.samples);
+        if tracer.samples < MAX_SAMPLES {
+            let read = tracer.samples as usize % 2;
+            let paths = self.async_queue.new_command_buffer();
+            self.order.paths.wait(paths);
+            self.order.image.wait_for(paths, tracer.read[1 - read]);
+            let encoder =
+                paths.new_render_command_encoder(clear_pass(&tracer.accumulation[1 - read]));
+            encoder.set_render_pipeline_state(&tracer.trace);
+            encoder.set_vertex_buffer(0, Some(&self.vertex_buffer), 0);
+            encoder.set_fragment_bytes(
+                0,
+                mem::size_of::<Uniforms>() as u64,
+                &uniforms as *const Uniforms as *const _,
+            );
+            let screen_texture = match &self.screen {
+                Some(screen) => screen.texture(),
+                None => &self.placeholder_texture,
+            };
+            encoder.set_fragment_texture(1, Some(screen_texture));
+            encoder.set_fragment_texture(2, Some(&tracer.accumulation[read]));
+            encoder.set_fragment_texture(3, Some(&self.normal_map));
+            encoder.set_fragment_texture(4, Some(&self.noise));
+            encoder.draw_primitives(MTLPrimitiveType::Triangle, 0, 6);
+            encoder.end_encoding();
+            self.order.paths.done(paths);
+            paths.commit();
+            self.timer.record("paths", paths);
+            tracer.samples += 1;
+        }
+
+        self.order.paths.wait(command_buffer);
+        self.order.wait_inputs(command_buffer);
+        let encoder = command_buffer.new_render_command_encoder(clear_pass(target));
+        if self.denoise {
+            encoder.set_render_pipeline_state(&tracer.denoise);
+        } else {
+            encoder.set_render_pipeline_state(&tracer.resolve);
+        }
+        encoder.set_vertex_buffer(0, Some(&self.vertex_buffer), 0);
+        encoder.set_fragment_bytes(
+            0,
+            mem::size_of::<Uniforms>() as u64,
+            &uniforms as *const Uniforms as *const _,
+        );
+        let latest = tracer.samples as usize % 2;
+        encoder.set_fragment_texture(2, Some(&tracer.accumulation[latest]));
+        encoder.draw_primitives(MTLPrimitiveType::Triangle, 0, 6);
+        self.encode_shaft_composite(encoder);
+        self.encode_edges(encoder);
+        self.encode_overlay(encoder);
+        encoder.end_encoding();
+        let read = self.order.image.done(command_buffer);
+        self.read[self.set] = read;
+        if let Some(tracer) = self.path_tracer.as_mut() {
+            tracer.read[latest] = read;
+        }
+    }
+
+    // Make the path tracer's, edge pass's, light shafts' and tiles' textures for a `width` x
+    // `height` target, keeping the ones that already fit (the path tracer starts over when it
+    // changed). The path tracer's carry over from frame to frame and have memory of their own;
+    // the others, a pair of each for alternate frames, are drawn and read within a frame and
+    // are placed together in one heap, laid out again over the old ones' memory when any
+    // changes, so a resize allocates nothing while the heap is big enough (it is made again
+    // when it is too small, or over twice the size needed). They can't share memory with each
+    // other, as the image pass reads a frame's all and the next frame's are drawn meanwhile.
+    fn prepare(&mut self, width: u64, height: u64) {
+        if let Some(tracer) = self.path_tracer.as_mut() {
+            if tracer.accumulation.len() != 2
+                || !tracer.accumulation.iter().all(fits(width, height))
+            {
+                let descriptor = float_descriptor(width, height);
+                tracer.accumulation = (0..2)
+                    .map(|_| self.device.new_texture(&descriptor))
+                    .collect();
+                tracer.samples = 0;
+            }
+        }
+        let target = [width as u32, height as u32];
+        let mut wanted: Vec<(&mut Vec<Texture>, [u64; 2])> = Vec::new();
+        if let Some(pass) = self.edge_pass.as_mut() {
+            wanted.push((&mut pass.textures, [width, height]));
+        }
+        if let (Some(pass), Some(shafts)) = (self.shaft_pass.as_mut(), self.shafts) {
+            wanted.push((&mut pass.textures, shafts.size(target).map(u64::from)));
+        }
+        if let (Some(pass), Some(adaptive)) = (self.tile_pass.as_mut(), self.adaptive) {
+            wanted.push((&mut pass.textures, adaptive.size(target).map(u64::from)));
+        }
+        let fit = |(textures, [width, height]): &(&mut Vec<Texture>, [u64; 2])| {
+            textures.len() == 2 && textures.iter().all(fits(*width, *height))
+        };
+        if wanted.iter().all(fit) {
+            return;
+        }
+
+        let descriptors: Vec<_> = (wanted.iter())
+            .map(|(_, [width, height])| float_descriptor(*width, *height))
+            .collect();
+        let size = (descriptors.iter())
+            .map(|descriptor| self.device.heap_texture_size_and_align(descriptor))
+            .map(|placement| placement.size.next_multiple_of(placement.align))
+            .sum::<u64>()
+            * 2;
+        let reuse = (self.transients.as_ref())
+            .is_some_and(|heap| heap.size() >= size && heap.size() <= size * 2);
+        if !reuse {
+            self.transients = None;
+        }
+        for (textures, _) in wanted.iter_mut() {
+            textures.clear();
+        }
+        let heap = (self.transients).get_or_insert_with(|| transient_heap(&self.device, size));
+        for ((textures, _), descriptor) in wanted.into_iter().zip(&descriptors) {
+            // Placement can still fail on alignment; a texture of its own does then
+            *textures = (0..2)
+                .map(|_| {
+                    (heap.new_texture(descriptor))
+                        .unwrap_or_else(|| self.device.new_texture(descriptor))
+                })
+                .collect();
+        }
+        // Either pair may lie over memory the last image read
+        self.read = [self.order.image.signaled.get(); 2];
+    }
+
+    // Mark the tiles needing supersampling in their texture (made by `prepare`), when adaptive
+    // quality is on; whether it was
+    fn encode_tiles(&mut self, uniforms: &Uniforms) -> bool {
+        let Some(pass) = self.tile_pass.as_ref() else {
+            return false;
+        };
+        let Some(texture) = in_set(&pass.textures, self.set) else {
+            return false;
+        };
+        let command_buffer = self.async_queue.new_command_buffer();
+        self.wait_for_reads(command_buffer);
+        let encoder = command_buffer.new_render_command_encoder(clear_pass(texture));
+        encoder.set_render_pipeline_state(&pass.classify);
+        encoder.set_vertex_buffer(0, Some(&self.vertex_buffer), 0);
+        encoder.set_fragment_bytes(
+            0,
+            mem::size_of::<Uniforms>() as u64,
+            uniforms as *const Uniforms as *const _,
+        );
+        let video_texture = match &self.video {
+            Some(video) => video.texture(),
+            None => &self.placeholder_texture,
+        };
+        encoder.set_fragment_texture(0, Some(video_texture));
+        let screen_texture = match &self.screen {
+            Some(screen) => screen.texture(),
+            None => &self.placeholder_texture,
+        };
+        encoder.set_fragment_texture(1, Some(screen_texture));
+        encoder.set_fragment_texture(3, Some(&self.normal_map));
+        encoder.set_fragment_texture(4, Some(&self.noise));
+        encoder.draw_primitives(MTLPrimitiveType::Triangle, 0, 6);
+        encoder.end_encoding();
+        self.order.tiles.done(command_buffer);
+        command_buffer.commit();
+        self.timer.record("tiles", command_buffer);
+        true
+    }
+
+    // Mark the nodes each tile of `target` can see in the masks (made here), when culling is on
+    // and the scene has nodes it can leave out; whether it did. It goes in the image's command
+    // buffer, ahead of it, and Metal orders a frame's use of the masks after the last one's.
+    fn encode_culling(
+        &mut self,
+        command_buffer: &CommandBufferRef,
+        target: &TextureRef,
+        uniforms: &Uniforms,
+    ) -> bool {
+        let Some(pass) = self.cull_pass.as_mut() else {
+            return false;
+        };
+        let proxies = &self.scene.proxies;
+        if proxies.iter().all(|proxy| proxy[3] < 0.0) {
+            return false;
+        }
+        let across = target.width().div_ceil(CULL_TILE);
+        let down = target.height().div_ceil(CULL_TILE);
+        let size = across * down * mem::size_of::<u32>() as u64;
+        let masks = match pass.masks.take() {
+            Some(masks) if masks.length() >= size => masks,
+            _ => (self.device).new_buffer(size, MTLResourceOptions::StorageModePrivate),
+        };
+        let masks: &BufferRef = pass.masks.insert(masks);
+        let blit = command_buffer.new_blit_command_encoder();
+        blit.fill_buffer(masks, NSRange::new(0, size), 0);
+        blit.end_encoding();
+
+        // No attachments: a pixel per tile, each fragment setting its proxy's bit
+        let descriptor = RenderPassDescriptor::new();
+        descriptor.set_render_target_width(across);
+        descriptor.set_render_target_height(down);
+        descriptor.set_default_raster_sample_count(1);
+        let encoder = command_buffer.new_render_command_encoder(descriptor);
+        encoder.set_render_pipeline_state(&pass.proxies);
+        let uniforms_size = mem::size_of::<Uniforms>() as u64;
+        let uniforms = uniforms as *const Uniforms as *const _;
+        let count = proxies.len() as u32;
+        encoder.set_object_bytes(0, uniforms_size, uniforms);
+        encoder.set_object_bytes(
+            1,
+            mem::size_of_val(proxies.as_slice()) as u64,
+            proxies.as_ptr() as *const _,
+        );
+        encoder.set_object_bytes(
+            2,
+            mem::size_of::<u32>() as u64,
+            &count as *const u32 as *const _,
+        );
+        encoder.set_mesh_bytes(0, uniforms_size, uniforms);
+        encoder.set_fragment_bytes(0, uniforms_size, uniforms);
+        encoder.set_fragment_buffer(1, Some(masks), 0);
+        encoder.draw_mesh_threadgroups(
+            MTLSize::new(1, 1, 1),
+            MTLSize::new(codegen::MAX_PROXIES as u64, 1, 1),
+            MTLSize::new(1, 1, 1),
+        );
+        encoder.end_encoding();
+        true
+    }
+
+    // Wait for the image that last read the textures this frame's passes draw into
+    fn wait_for_reads(&self, command_buffer: &CommandBufferRef) {
+        self.order
+            .image
+            .wait_for(command_buffer, self.read[self.set]);
+    }
+
+    // Render the G-buffer (made by `prepare`), when edges are on
+    fn encode_gbuffer(&mut self, uniforms: &Uniforms) {
+        let Some(pass) = self.edge_pass.as_ref() else {
+            return;
+        };
+        let Some(texture) = in_set(&pass.textures, self.set) else {
+            return;
+        };
+        let command_buffer = self.async_queue.new_command_buffer();
+        self.wait_for_reads(command_buffer);
+        let encoder = command_buffer.new_render_command_encoder(clear_pass(texture));
+        encoder.set_render_pipeline_state(&pass.gbuffer);
+        encoder.set_vertex_buffer(0, Some(&self.vertex_buffer), 0);
+        encoder.set_fragment_bytes(
+            0,
+            mem::size_of::<Uniforms>() as u64,
+            uniforms as *const Uniforms as *const _,
+        );
+        encoder.draw_primitives(MTLPrimitiveType::Triangle, 0, 6);
+        encoder.end_encoding();
+        self.order.gbuffer.done(command_buffer);
+        command_buffer.commit();
+        self.timer.record("gbuffer", command_buffer);
+    }
+
+    // March the light shafts into their texture (made by `prepare`), when they are on
+    fn encode_shafts(&mut self, uniforms: &Uniforms) {
+        let Some(pass) = self.shaft_pass.as_ref() else {
+            return;
+        };
+        let Some(texture) = in_set(&pass.textures, self.set) else {
+            return;
+        };
+        let command_buffer = self.async_queue.new_command_buffer();
+        self.wait_for_reads(command_buffer);
+        let encoder = command_buffer.new_render_command_encoder(clear_pass(texture));
+        encoder.set_render_pipeline_state(&pass.trace);
+        encoder.set_vertex_buffer(0, Some(&self.vertex_buffer), 0);
+        encoder.set_fragment_bytes(
+            0,
+            mem::size_of::<Uniforms>() as u64,
+            uniforms as *const Uniforms as *const _,
+        );
+        encoder.draw_primitives(MTLPrimitiveType::Triangle, 0, 6);
+        encoder.end_encoding();
+        self.order.shafts.done(command_buffer);
+        command_buffer.commit();
+        self.timer.record("shafts", command_buffer);
+    }
+
+    // The light shafts added over the image (with the image's uniforms still bound)
+    fn encode_shaft_composite(&self, encoder: &RenderCommandEncoderRef) {
+        let Some(pass) = &self.shaft_pass else {
+            return;
+        };
+        encoder.set_render_pipeline_state(&pass.composite);
+        encoder.set_fragment_texture(2, in_set(&pass.textures, self.set));
+        encoder.draw_primitives(MTLPrimitiveType::Triangle, 0, 6);
+    }
+
+    // Lines from the G-buffer over the image (with the image's uniforms still bound)
+    fn encode_edges(&self, encoder: &RenderCommandEncoderRef) {
+        let Some(pass) = &self.edge_pass else {
+            return;
+        };
+        encoder.set_render_pipeline_state(&pass.edges);
+        encoder.set_fragment_texture(2, in_set(&pass.textures, self.set));
+        encoder.draw_primitives(MTLPrimitiveType::Triangle, 0, 6);
+    }
+
+    fn encode_overlay(&self, encoder: &RenderCommandEncoderRef) {
+        if self.overlay.is_empty() {
+            return;
+        }
+        // A buffer per frame, so frames still in flight keep their own vertices
+        let vertices = self.device.new_buffer_with_data(
+            self.overlay.as_ptr() as *const _,
+            mem::size_of_val(self.overlay.as_slice()) as u64,
+            MTLResourceOptions::StorageModeShared,
+        );
+        encoder.set_render_pipeline_state(&self.overlay_pipeline_state);
+        encoder.set_vertex_buffer(0, Some(&vertices), 0);
+        encoder.draw_primitives(MTLPrimitiveType::Triangle, 0, self.overlay.len() as u64);
+    }
+
+    // Uniforms for drawing `scene` into `target`; the selection and adaptive quality only go
+    // to the `current` scene (not one fading in or a test frame)
+    fn uniforms(
+        &self,
+        target: &TextureRef,
+        camera: &Camera,
+        time: f32,
+        scene: &SceneInputs,
+        current: bool,
+    ) -> Uniforms {
+        let frame = FrameInputs {
+            resolution: [target.width() as u32, target.height() as u32],
+            time,
+            mouse: self.mouse,
+            video_mix: if self.video.is_some() {
+                self.video_mix
+            } else {
+                0.0
+            },
+            screen_mix: if self.screen.is_some() {
+                self.screen_mix
+            } else {
+                0.0
+            },
+            audio: self.audio,
+            clock: self.clock,
+            selected: self.selected.filter(|_| current),
+            guides: self.guides,
+            samples: 0,
+            seed: self.seed,
+            toon: self.toon,
+            edges: self.edges,
+            shafts: self.shafts,
+            adaptive: self.adaptive.filter(|_| current),
+        };
+        Uniforms::new(&frame, camera, &self.quality, scene)
+    }
+}
+
+fn normal_map_texture(device: &DeviceRef, normal_map: &NormalMap) -> Texture {
+    rgba_texture(
+        device,
+        [normal_map.width, normal_map.height],
+        &normal_map.pixels,
+    )
+}
+
+fn noise_texture(device: &DeviceRef, table: &NoiseTable) -> Texture {
+    rgba_texture(device, [noise::TABLE_SIZE, 1], &table.pixels)
+}
+
+// The 3D texture of a volume's voxels, and the texture of its transfer function
+fn volume_textures(device: &DeviceRef, volume: &VolumeData) -> (Texture, Texture) {
+    let [width, height, depth] = volume.dims.map(u64::from);
+    let descriptor = TextureDescriptor::new();
+    descriptor.set_texture_type(MTLTextureType::D3);
+    descriptor.set_pixel_format(MTLPixelFormat::R8Unorm);
+    descriptor.set_width(width);
+    descriptor.set_height(height);
+    descriptor.set_depth(depth);
+    let texture = device.new_texture(&descriptor);
+    texture.replace_region_in_slice(
+        MTLRegion::new_3d(0, 0, 0, width, height, depth),
+        0,
+        0,
+        volume.voxels.as_ptr() as *const _,
+        width,
+        width * height,
+    );
+    let size = volume.transfer.len() as u32 / 4;
+    (texture, rgba_texture(device, [size, 1], &volume.transfer))
+}
+
+fn rgba_texture(device: &DeviceRef, [width, height]: [u32; 2], pixels: &[u8]) -> Texture {
+    let descriptor = TextureDescriptor::new();
+    descriptor.set_pixel_format(MTLPixelFormat::RGBA8Unorm);
+    descriptor.set_width(width as u64);
+    descriptor.set_height(height as u64);
+    let texture = device.new_texture(&descriptor);
+    texture.replace_region(
+        MTLRegion::new_2d(0, 0, width as u64, height as u64),
+        0,
+        pixels.as_ptr() as *const _,
+        width as u64 * 4,
+    );
+    texture
+}
+
+// Of a pass's pair of textures, the one frames drawing into `set` use
+fn in_set(textures: &[Texture], set: usize) -> Option<&TextureRef> {
+    textures.get(set).map(|texture| &**texture)
+}
+
+// Whether a texture is `width` x `height`
+fn fits(width: u64, height: u64) -> impl Fn(&Texture) -> bool {
+    move |texture| texture.width() == width && texture.height() == height
+}
+
+// A float texture to render into and read back
+fn float_descriptor(width: u64, height: u64) -> TextureDescriptor {
+    let descriptor = TextureDescriptor::new();
+    descriptor.set_pixel_format(ACCUMULATION_FORMAT);
+    descriptor.set_width(width);
+    descriptor.set_height(height);
+    // Ordered by hand (see PassOrder)
+    descriptor.set_resource_options(
+        MTLResourceOptions::StorageModePrivate | MTLResourceOptions::HazardTrackingModeUntracked,
+    );
+    descriptor.set_usage(MTLTextureUsage::RenderTarget | MTLTextureUsage::ShaderRead);
+    descriptor
+}
+
+// A heap of `size` bytes for the passes' textures (see Raymarcher::prepare), untracked like
+// them
+fn transient_heap(device: &DeviceRef, size: u64) -> Heap {
+    let descriptor = HeapDescriptor::new();
+    descriptor.set_storage_mode(MTLStorageMode::Private);
+    descriptor.set_size(size);
+    let untracked = MTLHazardTrackingMode::Untracked as u64;
+    let _: () = unsafe { msg_send![descriptor.as_ref(), setHazardTrackingMode: untracked] };
+    device.new_heap(&descriptor)
+}
+
+// Pass drawing into `texture`, cleared to black first
+fn clear_pass(texture: &TextureRef) -> &RenderPassDescriptorRef {
+    let render_pass_descriptor = RenderPassDescriptor::new();
+    let color_attachment = render_pass_descriptor
+        .color_attachments()
+        .object_at(0)
+        .unwrap();
+
+    color_attachment.set_texture(Some(texture));
+    color_attachment.set_load_action(MTLLoadAction::Clear);
+    color_attachment.set_clear_color(MTLClearColor {
+        red: 0.0,
+        green: 0.0,
+        blue: 0.0,
+        alpha: 1.0,
+    });
+    color_attachment.set_store_action(MTLStoreAction::Store);
+    render_pass_descriptor
+}
+
+// Run the shader test kernel (see shader_test.rs) for `scene` at `probes` on `device` and
+// wait for its results
+pub fn run_shader_test(
+    device: &DeviceRef,
+    scene: &Scene,
+    probes: &[[f32; 4]],
+) -> Result<Vec<[f32; 4]>, String> {
+    let library = library(
+        device,
+        &(shader_source(None, scene)? + shader_test::MSL_KERNEL),
+    )?;
+    let function = library.get_function("shader_test_main", None)?;
+    let pipeline = device.new_compute_pipeline_state_with_function(&function)?;
+
+    let uniforms = shader_test::uniforms(scene);
+    let noise = noise_texture(device, &NoiseTable::of(scene, 0));
+    let probe_buffer = device.new_buffer_with_data(
+        probes.as_ptr() as *const _,
+        mem::size_of_val(probes) as u64,
+        MTLResourceOptions::StorageModeShared,
+    );
+    let count = probes.len() * shader_test::RESULTS_PER_PROBE;
+    let results = device.new_buffer(
+        (count * mem::size_of::<[f32; 4]>()) as u64,
+        MTLResourceOptions::StorageModeShared,
+    );
+    let queue = device.new_command_queue();
+    autoreleasepool(|| {
+        let command_buffer = queue.new_command_buffer();
+        let encoder = command_buffer.new_compute_command_encoder();
+        encoder.set_compute_pipeline_state(&pipeline);
+        encoder.set_bytes(
+            0,
+            mem::size_of::<Uniforms>() as u64,
+            &uniforms as *const Uniforms as *const _,
+        );
+        encoder.set_buffer(1, Some(&probe_buffer), 0);
+        encoder.set_buffer(2, Some(&results), 0);
+        let probe_count = probes.len() as u32;
+        encoder.set_bytes(3, 4, &probe_count as *const u32 as *const _);
+        encoder.set_texture(4, Some(&noise));
+        let width = pipeline.thread_execution_width();
+        encoder.dispatch_thread_groups(
+            MTLSize::new((probes.len() as u64).div_ceil(width), 1, 1),
+            MTLSize::new(width, 1, 1),
+        );
+        encoder.end_encoding();
+        command_buffer.commit();
+        command_buffer.wait_until_completed();
+    });
+    Ok(
+        unsafe { std::slice::from_raw_parts(results.contents() as *const [f32; 4], count) }
+            .to_vec(),
+    )
+}
+
+// Meshes are traced only where the device can (see meshes.metal); the line after the
+// definition is the source's first again, so errors point at its own lines
+fn library(device: &DeviceRef, source: &str) -> Result<Library, String> {
+    let source = match traces_meshes(device) {
+        true => format!("#define TRACE_MESHES\n#line 1\n{}", source),
+        false => source.to_string(),
+    };
+    device
+        .new_library_with_source(&source, &CompileOptions::new())
+        .map_err(|err| format!("Failed to compile shaders: {}", err))
+}
+
+fn pipeline_state(
+    device: &DeviceRef,
+    library: &LibraryRef,
+    pixel_format: MTLPixelFormat,
+) -> Result<RenderPipelineState, String> {
+    let vertex_fn = library.get_function("vertex_main", None)?;
+    let fragment_fn = library.get_function("fragment_main", None)?;
+
+    // Create pipeline
+    let pipeline_descriptor = RenderPipelineDescriptor::new();
+    pipeline_descriptor.set_vertex_function(Some(&vertex_fn));
+    pipeline_descriptor.set_fragment_function(Some(&fragment_fn));
+    let attachment = pipeline_descriptor
+        .color_attachments()
+        .object_at(0)
+        .unwrap();
+    attachment.set_pixel_format(pixel_format);
+    // Weighted by the blend color's alpha: 1 draws over the target, less fades in over it
+    attachment.set_blending_enabled(true);
+    attachment.set_source_rgb_blend_factor(MTLBlendFactor::BlendAlpha);
+    attachment.set_destination_rgb_blend_factor(MTLBlendFactor::OneMinusBlendAlpha);
+    attachment.set_source_alpha_blend_factor(MTLBlendFactor::BlendAlpha);
+    attachment.set_destination_alpha_blend_factor(MTLBlendFactor::OneMinusBlendAlpha);
+
+    // Reflected to check the shader's uniforms, which the other passes share
+    let reflection = MTLPipelineOption::ArgumentInfo | MTLPipelineOption::BufferTypeInfo;
+    let (state, reflection) = device
+        .new_render_pipeline_state_with_reflection(&pipeline_descriptor, reflection)
+        .map_err(|err| format!("Failed to create pipeline state: {}", err))?;
+    check_uniforms(&reflection)?;
+    Ok(state)
+}
+
+// Fails with the differences if the Uniforms struct fragment_main reads from buffer 0 is not
+// laid out like the one the renderer fills in, rather than drawing from misplaced values.
+// Apple deprecated MTLArgument for MTLBinding, which the metal crate doesn't wrap yet.
+#[allow(deprecated)]
+fn check_uniforms(reflection: &RenderPipelineReflectionRef) -> Result<(), String> {
+    let arguments = reflection.fragment_arguments();
+    let uniforms = (0..arguments.count())
+        .filter_map(|i| arguments.object_at(i))
+        .find(|argument| argument.type_() == MTLArgumentType::Buffer && argument.index() == 0)
+        .filter(|argument| argument.buffer_data_type() == MTLDataType::Struct);
+    let Some(uniforms) = uniforms else {
+        return Ok(()); // Takes no uniforms
+    };
+    let members = uniforms.buffer_struct_type().members();
+    let members: Vec<(String, usize)> = (0..members.count())
+        .filter_map(|i| members.object_at(i))
+        .map(|member| (member.name().to_string(), member.offset() as usize))
+        .collect();
+    let mismatches = uniforms::layout_mismatches(uniforms.buffer_data_size() as usize, &members);
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "The shader's Uniforms don't match the renderer's:\n  {}",
+            mismatches.join("\n  ")
+        ))
+    }
+}
+
+// The path tracing pass into an accumulation texture and the passes showing its average, as
+// it is and denoised
+fn path_pipelines(
+    device: &DeviceRef,
+    library: &LibraryRef,
+    pixel_format: MTLPixelFormat,
+) -> Result<
+    (
+        RenderPipelineState,
+        RenderPipelineState,
+        RenderPipelineState,
+    ),
+    String,
+> {
+    let pipeline = |name: &str, format: MTLPixelFormat| {
+        post_pipeline(device, library, name, "path tracing", format, None)
+    };
+    Ok((
+        pipeline("pathtrace_main", ACCUMULATION_FORMAT)?,
+        pipeline("resolve_main", pixel_format)?,
+        pipeline("denoise_main", pixel_format)?,
+    ))
+}
+
+// The pass rendering the G-buffer and the one drawing its edges, alpha blended over the image
+fn edge_pipelines(
+    device: &DeviceRef,
+    library: &LibraryRef,
+    pixel_format: MTLPixelFormat,
+) -> Result<(RenderPipelineState, RenderPipelineState), String> {
+    let pipeline = |name: &str, format: MTLPixelFormat, blend| {
+        post_pipeline(device, library, name, "edges", format, blend)
+    };
+    Ok((
+        pipeline("gbuffer_main", ACCUMULATION_FORMAT, None)?,
+        pipeline("edges_main", pixel_format, Some(ALPHA_BLEND))?,
+    ))
+}
+
+// The pass marching the light shafts and the one adding them over the image
+fn shaft_pipelines(
+    device: &DeviceRef,
+    library: &LibraryRef,
+    pixel_format: MTLPixelFormat,
+) -> Result<(RenderPipelineState, RenderPipelineState), String> {
+    let pipeline = |name: &str, format: MTLPixelFormat, blend| {
+        post_pipeline(device, library, name, "light shafts", format, blend)
+    };
+    Ok((
+        pipeline("shafts_main", ACCUMULATION_FORMAT, None)?,
+        pipeline("shafts_composite_main", pixel_format, Some(ADDITIVE_BLEND))?,
+    ))
+}
+
+// The pass marking the tiles needing supersampling and the image pass reading them, blended
+// like pipeline_state's
+fn tile_pipelines(
+    device: &DeviceRef,
+    library: &LibraryRef,
+    pixel_format: MTLPixelFormat,
+) -> Result<(RenderPipelineState, RenderPipelineState), String> {
+    let pipeline = |name: &str, format: MTLPixelFormat, blend| {
+        post_pipeline(device, library, name, "adaptive quality", format, blend)
+    };
+    Ok((
+        pipeline("tiles_main", ACCUMULATION_FORMAT, None)?,
+        pipeline("adaptive_main", pixel_format, Some(CONSTANT_BLEND))?,
+    ))
+}
+
+// The pass drawing the nodes' bounding spheres into the tiles' masks and the image pass
+// marching each tile with its mask, blended like pipeline_state
+fn cull_pipelines(
+    device: &DeviceRef,
+    library: &LibraryRef,
+    pixel_format: MTLPixelFormat,
+) -> Result<(RenderPipelineState, RenderPipelineState), String> {
+    let function = |name: &str| {
+        library
+            .get_function(name, None)
+            .map_err(|err| format!("The shader has no {} for culling: {}", name, err))
+    };
+    let descriptor = MeshRenderPipelineDescriptor::new();
+    let object_fn = function("proxies_object")?;
+    let mesh_fn = function("proxies_mesh")?;
+    let fragment_fn = function("proxies_fragment")?;
+    descriptor.set_object_function(Some(&object_fn));
+    descriptor.set_mesh_function(Some(&mesh_fn));
+    descriptor.set_fragment_function(Some(&fragment_fn));
+    descriptor.set_max_total_threads_per_object_threadgroup(codegen::MAX_PROXIES as u64);
+    descriptor.set_max_total_threadgroups_per_mesh_grid(codegen::MAX_PROXIES as u64);
+    descriptor.set_max_total_threads_per_mesh_threadgroup(1);
+    let proxies = device
+        .new_mesh_render_pipeline_state(&descriptor)
+        .map_err(|err| format!("Failed to create the proxies pipeline state: {}", err))?;
+    let image = post_pipeline(
+        device,
+        library,
+        "culled_main",
+        "culling",
+        pixel_format,
+        Some(CONSTANT_BLEND),
+    )?;
+    Ok((proxies, image))
+}
+
+// Source and destination color factors, then source and destination alpha factors
+type BlendFactors = [MTLBlendFactor; 4];
+
+// Weighted by the blend color's alpha
+const CONSTANT_BLEND: BlendFactors = [
+    MTLBlendFactor::BlendAlpha,
+    MTLBlendFactor::OneMinusBlendAlpha,
+    MTLBlendFactor::BlendAlpha,
+    MTLBlendFactor::OneMinusBlendAlpha,
+];
+
+const ALPHA_BLEND: BlendFactors = [
+    MTLBlendFactor::SourceAlpha,
+    MTLBlendFactor::OneMinusSourceAlpha,
+    MTLBlendFactor::One,
+    MTLBlendFactor::OneMinusSourceAlpha,
+];
+// Adds the color and leaves the target's alpha as it is
+const ADDITIVE_BLEND: BlendFactors = [
+    MTLBlendFactor::One,
+    MTLBlendFactor::One,
+    MTLBlendFactor::Zero,
+    MTLBlendFactor::One,
+];
+
+// A fullscreen pass running fragment function `name` (needed for `purpose`) into `format`
+fn post_pipeline(
+    device: &DeviceRef,
+    library: &LibraryRef,
+    name: &str,
+    purpose: &str,
+    format: MTLPixelFormat,
+    blend: Option<BlendFactors>,
+) -> Result<RenderPipelineState, String> {
+    let vertex_fn = library.get_function("vertex_main", None)?;
+    let fragment_fn = library
+        .get_function(name, None)
+        .map_err(|err| format!("The shader has no {} for {}: {}", name, purpose, err))?;
+    let pipeline_descriptor = RenderPipelineDescriptor::new();
+    pipeline_descriptor.set_vertex_function(Some(&vertex_fn));
+    pipeline_descriptor.set_fragment_function(Some(&fragment_fn));
+    let attachment = pipeline_descriptor
+        .color_attachments()
+        .object_at(0)
+        .unwrap();
+    attachment.set_pixel_format(format);
+    if let Some([source_rgb, destination_rgb, source_alpha, destination_alpha]) = blend {
+        attachment.set_blending_enabled(true);
+        attachment.set_source_rgb_blend_factor(source_rgb);
+        attachment.set_destination_rgb_blend_factor(destination_rgb);
+        attachment.set_source_alpha_blend_factor(source_alpha);
+        attachment.set_destination_alpha_blend_factor(destination_alpha);
+    }
+    device
+        .new_render_pipeline_state(&pipeline_descriptor)
+        .map_err(|err| format!("Failed to create the {} pipeline state: {}", name, err))
+}
+
+// Whether the device can ray trace in render passes, which came with macOS 12, so older
+// systems don't know to ask
+fn traces_meshes(device: &DeviceRef) -> bool {
+    unsafe {
+        let known: bool = msg_send![device, respondsToSelector: sel!(supportsRaytracingFromRender)];
+        known && msg_send![device, supportsRaytracingFromRender]
+    }
+}
+
+// The image's passes trace `meshes` where the device can, or with none a placeholder triangle
+// without area that no ray meets, as their shaders take one wherever the scene has meshes.
+// The acceleration structure is built on `queue`, the image's, without waiting: Metal orders
+// the frames after it, as they use it.
+fn mesh_pass(
+    device: &DeviceRef,
+    queue: &CommandQueueRef,
+    meshes: &MeshData,
+) -> Result<Option<MeshPass>, String> {
+    if !traces_meshes(device) {
+        if meshes.is_empty() {
+            return Ok(None);
+        }
+        return Err(format!(
+            "{} can't ray trace in render passes, so the scene's meshes are left out",
+            device.name()
+        ));
+    }
+    let placeholder = MeshData {
+        triangles: vec![[[0.0; 3]; 3]],
+        colors: vec![[0.0; 3]],
+    };
+    let meshes = if meshes.is_empty() {
+        &placeholder
+    } else {
+        meshes
+    };
+    let buffer = |bytes: &[u8]| {
+        device.new_buffer_with_data(
+            bytes.as_ptr() as *const _,
+            bytes.len() as u64,
+            MTLResourceOptions::StorageModeShared,
+        )
+    };
+    let triangles = buffer(bytemuck::cast_slice(&meshes.triangles));
+    let colors = buffer(bytemuck::cast_slice(&meshes.colors));
+
+    let geometry = AccelerationStructureTriangleGeometryDescriptor::descriptor();
+    geometry.set_vertex_buffer(Some(&triangles));
+    geometry.set_vertex_stride(mem::size_of::<[f32; 3]>() as u64);
+    geometry.set_triangle_count(meshes.triangles.len() as u64);
+    geometry.set_opaque(true);
+    let descriptor = PrimitiveAccelerationStructureDescriptor::descriptor();
+    descriptor.set_geometry_descriptors(Array::from_owned_slice(&[
+        AccelerationStructureGeometryDescriptor::from(geometry),
+    ]));
+    let sizes = device.acceleration_structure_sizes_with_descriptor(&descriptor);
+    let accel = device.new_acceleration_structure_with_size(sizes.acceleration_structure_size);
+    let scratch = device.new_buffer(
+        sizes.build_scratch_buffer_size,
+        MTLResourceOptions::StorageModePrivate,
+    );
+    let command_buffer = queue.new_command_buffer();
+    let encoder = command_buffer.new_acceleration_structure_command_encoder();
+    encoder.build_acceleration_structure(&accel, &descriptor, &scratch, 0);
+    encoder.end_encoding();
+    command_buffer.commit();
+    Ok(Some(MeshPass {
+        accel,
+        triangles,
+        colors,
+    }))
+}
+
+impl MeshPass {
+    // For the image's pipelines, at the buffers of meshes.metal's MESH_BUFFERS
+    fn bind(&self, encoder: &RenderCommandEncoderRef) {
+        encoder.set_fragment_acceleration_structure(2, Some(&self.accel));
+        encoder.set_fragment_buffer(3, Some(&self.triangles), 0);
+        encoder.set_fragment_buffer(4, Some(&self.colors), 0);
+    }
+}
+
+// Screen-space triangles with per-vertex colors, alpha blended over the image
+fn overlay_pipeline_state(
+    device: &DeviceRef,
+    pixel_format: MTLPixelFormat,
+) -> Result<RenderPipelineState, String> {
+    let library = device
+        .new_library_with_source(OVERLAY_SHADER, &CompileOptions::new())
+        .map_err(|err| format!("Failed to compile the overlay shader: {}", err))?;
+    let vertex_fn = library.get_function("overlay_vertex", None)?;
+    let fragment_fn = library.get_function("overlay_fragment", None)?;
+
+    let pipeline_descriptor = RenderPipelineDescriptor::new();
+    pipeline_descriptor.set_vertex_function(Some(&vertex_fn));
+    pipeline_descriptor.set_fragment_function(Some(&fragment_fn));
+    let attachment = pipeline_descriptor
+        .color_attachments()
+        .object_at(0)
+        .unwrap();
+    attachment.set_pixel_format(pixel_format);
+    attachment.set_blending_enabled(true);
+    attachment.set_source_rgb_blend_factor(MTLBlendFactor::SourceAlpha);
+    attachment.set_destination_rgb_blend_factor(MTLBlendFactor::OneMinusSourceAlpha);
+    attachment.set_source_alpha_blend_factor(MTLBlendFactor::One);
+    attachment.set_destination_alpha_blend_factor(MTLBlendFactor::OneMinusSourceAlpha);
+
+    device
+        .new_render_pipeline_state(&pipeline_descriptor)
+        .map_err(|err| format!("Failed to create the overlay pipeline state: {}", err))
+}
@@ -0,0 +1,208 @@
+// Remote control over WebSocket (--remote PORT): external tools, or a page in a browser, drive
+// the renderer live. The server listens on localhost only. Each request is a JSON object in a
+// text message and gets one message back, repeating the request's "id" if it has one:
+//
+//   {"get": "ball.radius"}                          -> {"value": [1.0]}
+//   {"set": "ball.radius", "value": 1.5}            -> {"ok": true}   (a number or a list)
+//   {"load": "scenes/glass.toml"}                   -> {"ok": true}
+//   {"get": "camera"}                               -> {"angle": 0.3, "distance": 8.0}
+//   {"camera": {"angle": 1.2, "distance": 6.0}}     -> {"ok": true}   (either can be left out)
+//   {"capture": "frame"}                            -> {"path": "exports/blobs-1.png"}
+//   {"capture": "passes"}                           -> {"path": "exports/blobs-passes"}
+//   {"action": "toggle_path_tracing"}               -> {"ok": true}   (any keybindings.rs name)
+//
+// Parameters are addressed as in scene files (see scene.rs), and setting one is undoable like
+// an edit in the inspector. A request that fails gets {"error": "..."}. Requests are carried
+// out on the render thread between frames. At most MAX_CLIENTS are served at once, and one is
+// dropped that doesn't finish the handshake within READ_TIMEOUT or then sends nothing for
+// IDLE_TIMEOUT.
+use crate::json::Json;
+use crate::keybindings::Action;
+use crate::render_thread::Message;
+use crate::websocket::WebSocket;
+use serde::Deserialize;
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const MAX_CLIENTS: usize = 8;
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+const IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+
+pub enum Request {
+    Get(String),
+    Set(String, Vec<f32>),
+    Load(PathBuf),
+    Camera {
+        angle: Option<f32>,
+        distance: Option<f32>,
+    },
+    Capture(Capture),
+    Action(Action),
+}
+
+pub enum Capture {
+    Frame,  // The current view, as a PNG
+    Passes, // Its intermediate textures too (see App::dump_passes)
+}
+
+// The answer to a request, or what went wrong
+pub type Reply = Result<Json, String>;
+
+// Listen on `port` and hand requests to the render thread through `sender`
+pub fn serve(port: u16, sender: Sender<Message>) -> Result<(), String> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+        .map_err(|err| format!("Failed to listen on port {}: {}", port, err))?;
+    println!("Remote control on ws://localhost:{}", port);
+    thread::Builder::new()
+        .name("remote".to_string())
+        .spawn(move || {
+            let clients = Arc::new(AtomicUsize::new(0));
+            for stream in listener.incoming().flatten() {
+                if clients.fetch_add(1, Ordering::SeqCst) >= MAX_CLIENTS {
+                    clients.fetch_sub(1, Ordering::SeqCst);
+                    eprintln!(
+                        "Remote control: over {} clients, turning one away",
+                        MAX_CLIENTS
+                    );
+                    continue;
+                }
+                let sender = sender.clone();
+                let served = clients.clone();
+                let spawned = thread::Builder::new()
+                    .name("remote client".to_string())
+                    .spawn(move || {
+                        match accept(stream) {
+                            Ok(socket) => talk(socket, sender),
+                            Err(err) => eprintln!("Remote control: {}", err),
+                        }
+                        served.fetch_sub(1, Ordering::SeqCst);
+                    });
+                if let Err(err) = spawned {
+                    clients.fetch_sub(1, Ordering::SeqCst);
+                    eprintln!("Remote control: {}", err);
+                }
+            }
+        })
+        .map_err(|err| err.to_string())?;
+    Ok(())
+}
+
+// Take a client's handshake within READ_TIMEOUT, then wait up to IDLE_TIMEOUT for each request
+fn accept(stream: TcpStream) -> Result<WebSocket, String> {
+    stream
+        .set_read_timeout(Some(READ_TIMEOUT))
+        .map_err(|err| err.to_string())?;
+    // The same socket as the one the WebSocket takes over
+    let idle = stream.try_clone().map_err(|err| err.to_string())?;
+    let socket = WebSocket::accept(stream)?;
+    idle.set_read_timeout(Some(IDLE_TIMEOUT))
+        .map_err(|err| err.to_string())?;
+    Ok(socket)
+}
+
+// Answer a client's requests until it goes away
+fn talk(mut socket: WebSocket, sender: Sender<Message>) {
+    loop {
+        let text = match socket.read() {
+            Ok(Some(text)) => text,
+            Ok(None) => return,
+            Err(err) => {
+                eprintln!("Remote control: {}", err);
+                return;
+            }
+        };
+        let message = Json::parse(&text);
+        let reply = message.as_ref().map_err(String::clone).and_then(|message| {
+            let request = Request::from_json(message)?;
+            let (reply, answer) = mpsc::channel();
+            sender
+                .send(Message::Remote(request, reply))
+                .map_err(|_| "the renderer has stopped".to_string())?;
+            answer
+                .recv()
+                .map_err(|_| "the renderer has stopped".to_string())?
+        });
+        let mut answer = match reply {
+            Ok(answer) => answer,
+            Err(err) => Json::object([("error", Json::String(err))]),
+        };
+        if let (Json::Object(fields), Some(id)) = (&mut answer, message.ok().and_then(id)) {
+            fields.insert(0, ("id".to_string(), id));
+        }
+        if socket.send(&answer.to_string()).is_err() {
+            return;
+        }
+    }
+}
+
+// A parameter's values, given as a number or a list of numbers
+pub fn values(value: Option<&Json>) -> Option<Vec<f32>> {
+    let values: Option<Vec<f64>> = match value? {
+        Json::Array(items) => items.iter().map(Json::as_f64).collect(),
+        value => value.as_f64().map(|value| vec![value]),
+    };
+    Some(values?.into_iter().map(|value| value as f32).collect())
+}
+
+// The id a request carries
+fn id(message: Json) -> Option<Json> {
+    match message {
+        Json::Object(fields) => fields
+            .into_iter()
+            .find(|(key, _)| key == "id")
+            .map(|(_, id)| id),
+        _ => None,
+    }
+}
+
+impl Request {
+    fn from_json(message: &Json) -> Result<Self, String> {
+        let text = |key: &str| {
+            message
+                .get(key)
+                .map(|value| value.as_str().ok_or(format!("\"{}\" takes a string", key)))
+                .transpose()
+        };
+        if let Some(target) = text("get")? {
+            return Ok(Request::Get(target.to_string()));
+        }
+        if let Some(target) = text("set")? {
+            let values = values(message.get("value"))
+                .ok_or("\"value\" takes a number or a list of numbers")?;
+            return Ok(Request::Set(target.to_string(), values));
+        }
+        if let Some(path) = text("load")? {
+            return Ok(Request::Load(PathBuf::from(path)));
+        }
+        if let Some(camera) = message.get("camera") {
+            let number = |key: &str| match camera.get(key) {
+                Some(value) => (value.as_f64())
+                    .map(|value| Some(value as f32))
+                    .ok_or(format!("camera \"{}\" takes a number", key)),
+                None => Ok(None),
+            };
+            return Ok(Request::Camera {
+                angle: number("angle")?,
+                distance: number("distance")?,
+            });
+        }
+        if let Some(what) = text("capture")? {
+            return match what {
+                "frame" => Ok(Request::Capture(Capture::Frame)),
+                "passes" => Ok(Request::Capture(Capture::Passes)),
+                _ => Err(format!("can't capture \"{}\" (frame or passes)", what)),
+            };
+        }
+        if let Some(name) = text("action")? {
+            return Action::deserialize(toml::Value::String(name.to_string()))
+                .map(Request::Action)
+                .map_err(|_| format!("unknown action \"{}\"", name));
+        }
+        Err("expected get, set, load, camera, capture or action".to_string())
+    }
+}
@@ -0,0 +1,215 @@
+// The render thread: owns the app and runs update/encode/present in its own loop, paced by the
+// display (see display_link.rs) where it can be followed, otherwise by the drawable (vsync),
+// or at a fixed rate for screen recordings, rather than by the event loop. The winit loop
+// forwards input and resizes over a channel, so window drags, live resizes and modal dialogs
+// on the main thread never stall rendering. While no frame can be drawn (the window is
+// minimized or hidden, or the drawables are being rebuilt) it waits longer and longer between
+// tries, up to MAX_IDLE, rather than spinning.
+#[cfg(target_os = "macos")]
+use crate::display_link::DisplayLink;
+use crate::http::{Answer, Endpoint};
+use crate::keybindings::Action;
+#[cfg(feature = "offline")]
+use crate::remote::Capture;
+use crate::remote::{Reply, Request};
+use crate::report;
+use crate::workspace::Layout;
+use crate::App;
+#[cfg(feature = "offline")]
+use crate::Readback;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+
+// First and longest waits before trying to draw again after a frame couldn't be
+const MIN_IDLE: Duration = Duration::from_millis(1);
+const MAX_IDLE: Duration = Duration::from_millis(100);
+
+pub enum Message {
+    Resize(PhysicalSize<u32>),
+    CursorMoved(PhysicalPosition<f64>),
+    Scroll(f32),
+    Click {
+        alt: bool,
+    }, // Left button pressed where the cursor last moved to
+    Release,
+    Action(Action),
+    Char(char),                     // Typed text, for entering values in the inspector
+    SaveWorkspace(Layout),          // The window as it is now
+    Remote(Request, Sender<Reply>), // From a remote-control client, answered on the sender
+    Http(Endpoint, Sender<Answer>), // From a monitoring client, likewise
+    Shader(String, Sender<Result<(), String>>), // Pushed by a livecoding client, likewise
+    #[cfg(target_os = "macos")]
+    Display(u32), // The window is on another display now (a CGDirectDisplayID)
+}
+
+pub struct RenderThread {
+    sender: Sender<Message>,
+    handle: JoinHandle<App>,
+}
+
+impl RenderThread {
+    // `rate` draws frames at fixed steps of scene time (--present-rate)
+    pub fn spawn(app: App, rate: Option<f32>) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let handle = thread::Builder::new()
+            .name("render".to_string())
+            .spawn(move || run(app, receiver, rate))
+            .expect("Failed to start the render thread");
+        Self { sender, handle }
+    }
+
+    // For other threads to send messages of their own (remote control, monitoring)
+    pub fn sender(&self) -> Sender<Message> {
+        self.sender.clone()
+    }
+
+    pub fn send(&self, message: Message) {
+        // Only fails once the thread has stopped, when there is nothing left to tell it
+        let _ = self.sender.send(message);
+    }
+
+    // Stop rendering after the current frame and hand the app back
+    pub fn stop(self) -> App {
+        drop(self.sender);
+        self.handle
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    }
+}
+
+fn run(mut app: App, receiver: Receiver<Message>, rate: Option<f32>) -> App {
+    // A panic writes a report of the app's state (see report.rs)
+    report::guard(&mut app, |app| draw(app, &receiver, rate));
+    app
+}
+
+// Draw frames until the window is gone
+fn draw(app: &mut App, receiver: &Receiver<Message>, rate: Option<f32>) {
+    let mut pacing = Pacing::new(rate);
+    let mut idle = Duration::ZERO;
+    loop {
+        let shown = pacing.next(app);
+        loop {
+            match receiver.try_recv() {
+                #[cfg(target_os = "macos")]
+                Ok(Message::Display(display)) => pacing.set_display(display),
+                Ok(message) => handle(app, message),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return,
+            }
+        }
+        app.update(shown);
+        if app.render() {
+            idle = Duration::ZERO;
+        } else {
+            idle = (idle * 2).clamp(MIN_IDLE, MAX_IDLE);
+            thread::sleep(idle);
+        }
+    }
+}
+
+// When to draw the next frame
+struct Pacing {
+    rate: Option<f64>, // Fixed frames per second (--present-rate)
+    #[cfg(target_os = "macos")]
+    link: Option<DisplayLink>,
+}
+
+impl Pacing {
+    fn new(rate: Option<f32>) -> Self {
+        let rate = rate.filter(|&rate| rate > 0.0).map(f64::from);
+        Self {
+            rate,
+            // Scheduled presentation lines fixed-rate frames up with refreshes instead
+            #[cfg(target_os = "macos")]
+            link: match rate {
+                Some(_) => None,
+                None => DisplayLink::new()
+                    .map_err(|err| eprintln!("{}; pacing frames by drawables instead", err))
+                    .ok(),
+            },
+        }
+    }
+
+    // Wait until the next frame should be drawn; when it will be shown
+    fn next(&mut self, app: &mut App) -> Instant {
+        match self.rate {
+            Some(rate) => step(app, rate),
+            None => self.refresh(app),
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    fn refresh(&mut self, app: &mut App) -> Instant {
+        let Some(link) = self.link.as_mut() else {
+            return Instant::now();
+        };
+        // Without refreshes (the display is asleep) keep drawing now and then, so messages from
+        // clients are still answered
+        let Some(frame) = link.wait(MAX_IDLE) else {
+            return Instant::now();
+        };
+        if let Some(period) = frame.period {
+            app.stats.refresh = Some(period.as_secs_f32());
+        }
+        frame.shown
+    }
+
+    // Right away: presenting waits for the next drawable
+    #[cfg(not(target_os = "macos"))]
+    fn refresh(&mut self, _app: &mut App) -> Instant {
+        Instant::now()
+    }
+
+    #[cfg(target_os = "macos")]
+    fn set_display(&mut self, display: u32) {
+        if let Some(link) = self.link.as_mut() {
+            link.set_display(display);
+        }
+    }
+}
+
+// The next of `rate` steps a second of scene time, so a screen recording at that rate gets
+// one frame per step at its exact time however fast frames are drawn. Each is started a step
+// before it is shown and presented at its time; one drawn too slowly makes the next skip a
+// step rather than shift the ones after it.
+fn step(app: &mut App, rate: f64) -> Instant {
+    let period = Duration::from_secs_f64(1.0 / rate);
+    app.stats.refresh = Some(period.as_secs_f32());
+    let steps = (app.start_time.elapsed().as_secs_f64() * rate).floor() + 2.0;
+    let shown = app.start_time + Duration::from_secs_f64(steps / rate);
+    if let Some(early) = (shown - period).checked_duration_since(Instant::now()) {
+        thread::sleep(early);
+    }
+    app.backend.present_at(shown);
+    shown
+}
+
+fn handle(app: &mut App, message: Message) {
+    match message {
+        Message::Resize(size) => app.resize(size),
+        Message::CursorMoved(position) => app.handle_mouse_move(position, app.window_size),
+        Message::Scroll(lines) => app.handle_scroll(lines),
+        Message::Click { alt } => app.click(alt),
+        Message::Release => app.release(),
+        Message::Action(action) => app.perform(action),
+        Message::Char(c) => app.type_char(c),
+        Message::SaveWorkspace(layout) if !app.typing() => app.save_workspace(Some(layout)),
+        Message::SaveWorkspace(_) => {}
+        // Frames are read back without holding up the ones shown (see App::read_back)
+        #[cfg(feature = "offline")]
+        Message::Remote(Request::Capture(Capture::Frame), reply) => {
+            app.read_back(Readback::Capture(reply));
+        }
+        #[cfg(feature = "offline")]
+        Message::Http(Endpoint::Frame, answer) => app.read_back(Readback::Frame(answer)),
+        // The client may have gone while waiting
+        Message::Remote(request, reply) => drop(reply.send(app.remote(request))),
+        Message::Http(endpoint, answer) => drop(answer.send(app.http(endpoint))),
+        Message::Shader(template, answer) => drop(answer.send(app.push_shader(template))),
+        #[cfg(target_os = "macos")]
+        Message::Display(_) => {} // Taken by the pacing (see draw)
+    }
+}
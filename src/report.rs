@@ -0,0 +1,136 @@
+// Crash and error reports. When the app panics, or the GPU fails to draw a frame, a directory
+// <capture_dir>/report-<seconds since 1970>/ is written to attach to a bug report:
+//   report.txt  what went wrong, the version, system and command line, the GPU, the scene,
+//               its time, the quality settings, the camera and the last frame's uniforms
+//   scene.toml  the scene as it was drawn, with its parameters at their current values
+//   log.txt     the last LOG_LINES lines the app printed (see the print macros in main.rs)
+// A panic outside the app's frames (while starting up, or in the window's event loop) has no
+// app to describe, so its report.txt stops after the command line and there is no scene.toml.
+use crate::App;
+use std::backtrace::Backtrace;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const LOG_LINES: usize = 200;
+
+static LOG: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+static DIR: OnceLock<PathBuf> = OnceLock::new(); // Where reports go (the capture directory)
+
+thread_local! {
+    static GUARDED: Cell<bool> = const { Cell::new(false) }; // Inside `guard`
+    static PANIC: RefCell<Option<String>> = const { RefCell::new(None) }; // For `guard`
+}
+
+// Keep `line` for the next report's log
+pub fn log(line: &str) {
+    let mut log = LOG.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if log.len() == LOG_LINES {
+        log.pop_front();
+    }
+    log.push_back(line.to_string());
+}
+
+// Write reports into `dir` from now on, and one for every panic
+pub fn install(dir: &Path) {
+    let _ = DIR.set(dir.to_path_buf());
+    let default = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default(info);
+        let reason = format!(
+            "Panic in the {} thread: {}\n\n{}",
+            std::thread::current().name().unwrap_or("unnamed"),
+            info,
+            Backtrace::force_capture()
+        );
+        if GUARDED.with(Cell::get) {
+            // Left for `guard`, which can describe the app as well
+            PANIC.with(|panic| *panic.borrow_mut() = Some(reason));
+        } else {
+            write(&reason, None);
+        }
+    }));
+}
+
+// Run `f` on `app`; if it panics, write a report describing the app before panicking on
+pub fn guard<T>(app: &mut App, f: impl FnOnce(&mut App) -> T) -> T {
+    GUARDED.with(|guarded| guarded.set(true));
+    let result = panic::catch_unwind(AssertUnwindSafe(|| f(app)));
+    GUARDED.with(|guarded| guarded.set(false));
+    result.unwrap_or_else(|panic| {
+        let reason = PANIC.with(|reason| reason.borrow_mut().take());
+        write(reason.as_deref().unwrap_or("Panic"), Some(app));
+        panic::resume_unwind(panic)
+    })
+}
+
+// Write a report of `reason`, describing `app` if there is one, and say where it went
+pub fn write(reason: &str, app: Option<&App>) {
+    match write_files(reason, app) {
+        Ok(dir) => eprintln!("Wrote a report to {}", dir.display()),
+        Err(err) => eprintln!("Failed to write a report: {}", err),
+    }
+}
+
+fn write_files(reason: &str, app: Option<&App>) -> Result<PathBuf, String> {
+    let since = SystemTime::now().duration_since(UNIX_EPOCH);
+    let seconds = since.map_or(0, |since| since.as_secs());
+    let dir = (DIR.get().cloned().unwrap_or_default()).join(format!("report-{}", seconds));
+    std::fs::create_dir_all(&dir).map_err(|err| format!("{}: {}", dir.display(), err))?;
+    let save = |name: &str, text: &str| {
+        let path = dir.join(name);
+        std::fs::write(&path, text).map_err(|err| format!("{}: {}", path.display(), err))
+    };
+
+    let mut text = format!("{}\n\n", reason);
+    let _ = writeln!(
+        text,
+        "Version: {} {}",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    );
+    let _ = writeln!(
+        text,
+        "System: {} {}",
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    );
+    let args: Vec<String> = std::env::args().collect();
+    let _ = writeln!(text, "Command line: {}", args.join(" "));
+    if let Some(app) = app {
+        let _ = writeln!(text, "GPU: {}", app.backend.describe_device());
+        let _ = writeln!(
+            text,
+            "Scene: {} ({})",
+            app.scene.name,
+            (app.scene_path.as_ref())
+                .map_or("not from a file".into(), |path| path.display().to_string())
+        );
+        let _ = writeln!(text, "Scene time: {}", app.time);
+        let _ = writeln!(text, "Path tracing: {}", app.path_tracing);
+        let _ = writeln!(text, "Quality: {:?} {:#?}", app.preset, app.quality);
+        let _ = writeln!(text, "Camera: {:#?}", app.camera);
+        let uniforms = app.backend.describe_uniforms();
+        let _ = writeln!(
+            text,
+            "Uniforms: {}",
+            uniforms
+                .as_deref()
+                .unwrap_or("none yet, no frame was drawn")
+        );
+        let scene = toml::to_string(&app.scene)
+            .unwrap_or_else(|err| format!("# The scene couldn't be written: {}\n", err));
+        save("scene.toml", &scene)?;
+    }
+    save("report.txt", &text)?;
+
+    let log = LOG.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let lines: String = log.iter().map(|line| format!("{}\n", line)).collect();
+    drop(log);
+    save("log.txt", &lines)?;
+    Ok(dir)
+}
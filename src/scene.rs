@@ -0,0 +1,1703 @@
+// Scene description: a tree of SDF primitives combined with CSG operations.
+// The shader's scene function is generated from this (see codegen.rs).
+// Scene files are TOML, e.g.
+//
+//   name = "blobs"
+//
+//   [[nodes]]
+//   type = "object"
+//   name = "ball"
+//   shape = { type = "sphere", radius = 1.0 }
+//   position = [0.0, 0.5, 0.0]
+//   color = [0.9, 0.2, 0.2]
+//
+//   [[nodes]]
+//   type = "group"
+//   op = { smooth_union = { k = 0.5 } }
+//   [[nodes.children]]
+//   type = "object"
+//   shape = { type = "box", size = [0.5, 0.5, 0.5] }
+//   position = [1.5, 0.0, 0.0]
+//
+//   [[lights]]                              # Point lights, besides the environment's sun
+//   name = "lamp"
+//   position = [2.0, 3.0, -1.0]
+//   color = [1.0, 0.9, 0.7]
+//   intensity = 4.0                         # Falls off with the square of the distance
+//   radius = 0.2                            # Sphere the path tracer samples (default 0, a point)
+//
+//   [[portals]]                             # Rays into one side come out of the other
+//   size = [1.0, 1.5]                       # Half width and height of both rectangles
+//   a = { position = [0.0, 1.5, -3.0] }     # Placed like objects, facing +Z unrotated
+//   b = { position = [20.0, 1.5, 0.0], rotation = [0.0, 90.0, 0.0] }
+//
+// An object with `glass = { ior = 1.5, density = 0.5 }` is a clear solid that light passes
+// through, bending where it enters and leaves; its color is what white light turns into
+// after crossing 1 / density units of it. Subtracting an object from a glass one leaves an
+// air pocket inside, e.g. a bubble.
+//
+// `normal_map = "rock.png"` gives the scene a tangent-space normal map (relative to the scene
+// file). Objects with `bump = { intensity = 1.0, tiling = 2.0 }` take fine surface detail
+// from it: it is sampled along the three axes (`tiling` repeats per unit) and tilts the
+// shading normal by `intensity`, without changing the shape that is marched.
+//
+// Objects with `noise = { kind = "fbm", amount = 0.5, scale = 2.0 }` vary their color with
+// the noise shared by all scenes (see noise.rs): value, perlin, simplex, fbm or curl, with
+// `scale` features per unit and the color swinging by up to `amount` either way. The scene's
+// `noise_seed`, offset by --seed, picks the noise; scenes with the same seed get the same
+// patterns.
+//
+// Objects with `iridescence = { thickness = 400.0, amount = 1.0 }` are coated with a thin film,
+// like a soap bubble or oil on water: light reflected off its top and bottom interferes, so
+// the color shifts with the view angle and the film's `thickness` (in nanometres). `variation`
+// swings the thickness by up to that many nanometres with the shared noise (`scale` features
+// per unit), swirling the colors. The film colors an opaque object by `amount`, and glass
+// through its reflections, so a glass shell with an air pocket inside makes a bubble.
+//
+// Objects with `specular = { intensity = 1.0, roughness = 0.3 }` catch a glossy highlight of
+// the sun. `anisotropy` (-1 to 1) stretches it like on brushed metal: positive values spread
+// it across the `tangent`, the direction the surface is brushed along, and negative ones
+// along it. The tangent is one of the object's own axes ("x", "y" or "z", turning with it), or
+// "radial" for rings around its y axis, like a turned disc.
+//
+// Fog comes from the [environment]: `fog_density` thins the view exponentially with distance,
+// `height_fog = { density = 0.3, height = 0.0, falloff = 1.0 }` adds fog that is that dense at
+// `height` and thins by e every 1 / falloff units above it (thickening below), `fog_color`
+// tints both (the sky's color by default) and `fog_sun` makes them glow towards the sun.
+//
+// `sun = { latitude = 51.48, longitude = -0.01 }` in the [environment] puts the sun where it
+// really is over that place, by the clock or at a set `time` (see sun.rs).
+//
+// `caustics = { intensity = 1.0, sharpness = 4.0 }` in the [environment] fakes the light glass
+// focuses onto what is below it: a rippling net of bright lines in the glass's color, placed
+// where sunlight leaves the glass and strongest under its thickest parts. `sharpness` narrows
+// the lines.
+//
+// A [floor] is a ground plane that belongs to the scene rather than its objects, so it can be
+// switched off (`enabled = false`) for captures of the objects alone. It lies at `height`,
+// checkered in squares `checker` units wide (0 for plain) of its two `colors`, mirrors the
+// scene by `reflection` (more at grazing angles) and darkens within `contact` units of what
+// stands on it.
+//
+// Objects with `cells = { width = 0.03, count = 24 }` are cracked along Voronoi cell borders,
+// or with `style = "foam"` reduced to walls along them; the sites can be listed, scattered
+// and made to drift (see cells.rs).
+//
+// The 4D shapes (tesseract, hypersphere, julia) show their 3D slice at w, turned first in a
+// plane through w, e.g. shape = { type = "tesseract", size = 1.0, w = 0.3, plane = "xw",
+// angle = 30.0 }; animating or binding "<name>.w" and "<name>.angle" sweeps through them.
+//
+// Extrusions and revolutions are made from a flat outline, most easily drawn in a vector
+// editor: shape = { type = "extrusion", svg = "logo.svg", depth = 0.2, rounding = 0.05 }
+// pushes it out 0.2 either way, and shape = { type = "revolution", svg = "vase.svg" } turns
+// it around the y axis (see svg.rs for how the drawing is placed). Without `svg`, `outline`
+// lists the loops themselves, e.g. outline = [[[0.0, 1.0], [-1.0, -1.0], [1.0, -1.0]]].
+//
+// Objects with `emission = 2.0` glow with their own color that many times over, lit or not,
+// like a lamp's bulb; they light nothing else by themselves (add a point light for that).
+//
+// `repeat = { spacing = [1.0, 0.0, 1.0], count = [5, 1, 3] }` draws copies of an object in a
+// grid of its own space, `count` of them `spacing` apart along each axis and centered on its
+// position, for the price of one (domain repetition). The object should fit in its share of
+// the grid, or its copies cut each other off.
+//
+// A [volume] table draws a volume dataset, such as a CT scan, into the scene (see volume.rs).
+//
+// [[meshes]] tables place triangle meshes from OBJ files among the shapes, ray traced rather
+// than marched (see mesh.rs).
+//
+// A [city] table adds a generated city block to the scene's nodes and lights when it is loaded
+// (see city.rs).
+//
+// Point clouds are scans drawn as blended spheres: shape = { type = "cloud", file = "scan.ply",
+// budget = 300, blend = 0.02 } (see cloud.rs for the files read).
+//
+// Terrain is raised from a height image, e.g. a DEM tile or depth map: shape = { type =
+// "heightfield", file = "dem.png", size = [2.0, 2.0], height = 0.4, exaggeration = 2.0 } (see
+// heightfield.rs).
+//
+// Plants are grown from an L-system: shape = { type = "plant", axiom = "X", rules = { X =
+// "F[+X][-X]FX", F = "FF" }, iterations = 4, angle = 25.0, length = 0.1, radius = 0.02 }
+// (see plant.rs for the symbols), with `wind` to make them sway.
+//
+// Named objects and groups expose parameters that can be animated (see animation.rs) or
+// bound to expressions (see expression.rs), addressed as "<name>.<property>":
+//   object: position, rotation, scale, color, radius (sphere, capsule, hypersphere), size
+//           (box, tesseract), normal/height (plane), c (julia), w/angle (4D shapes),
+//           depth/rounding (extrusion), height/exaggeration (heightfield),
+//           k (smooth union, smooth subtract), ior/density (glass), bump (its intensity),
+//           noise (its amount), film (the iridescence's thickness), anisotropy, emission,
+//           cells (their width)
+//   group:  k (smooth union, smooth subtract)
+//   light:  position, color, intensity, radius
+// plus "light.color", "fog.density", "fog.sun" and "sun.hours" from the scene's [environment],
+// "floor.height" with a [floor], and "volume.iso", "volume.density" and "volume.window" with a
+// [volume].
+// Files nesting groups more than MAX_DEPTH deep, with numbers that aren't finite, objects
+// scaled to nothing or planes without a normal are rejected when loaded (see
+// Scene::validate), as the shader couldn't be generated from them or would draw nothing.
+// `script = "logic.rhai"` attaches a script that runs every frame (see script.rs), and
+// [[cues]] are changes fired on the beat during a performance (see clock.rs).
+use crate::animation::{Orbit, Track};
+use crate::cells;
+use crate::city::{self, City};
+use crate::clock::Cue;
+use crate::cloud;
+use crate::codegen::{ParamLayout, MAX_PARAMS};
+use crate::expression::Binding;
+use crate::heightfield;
+use crate::mesh::Mesh;
+use crate::plant;
+use crate::sun::Sun;
+use crate::svg;
+use crate::toon::Toon;
+use crate::volume::Volume;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+// Deepest nesting of groups a scene may have; the tree is walked recursively throughout
+pub const MAX_DEPTH: usize = 32;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Shape {
+    Sphere {
+        radius: f32,
+    },
+    Box {
+        size: [f32; 3],
+    }, // Half extents
+    Plane {
+        normal: [f32; 3],
+        height: f32,
+    },
+    Capsule {
+        a: [f32; 3],
+        b: [f32; 3],
+        radius: f32,
+    }, // Ends relative to the position
+    // 4D shapes are drawn as their 3D slice at `w`, after turning them by `angle` degrees in
+    // the rotation `plane` between w and one of x, y and z
+    Tesseract {
+        size: f32, // Half extent along all four axes
+        #[serde(default)]
+        w: f32,
+        #[serde(default)]
+        plane: RotationPlane,
+        #[serde(default)]
+        angle: f32,
+    },
+    Hypersphere {
+        radius: f32,
+        #[serde(default)]
+        w: f32,
+        #[serde(default)]
+        plane: RotationPlane,
+        #[serde(default)]
+        angle: f32,
+    },
+    Julia {
+        c: [f32; 4], // Quaternion Julia set of z * z + c
+        #[serde(default)]
+        w: f32,
+        #[serde(default)]
+        plane: RotationPlane,
+        #[serde(default)]
+        angle: f32,
+    },
+    // Flat outlines, filled even-odd: closed loops of points in the xy plane, or read from an
+    // SVG file (see svg.rs) when the scene is loaded. An extrusion is the outline `depth` deep
+    // either side of z = 0 with its edges rounded off by `rounding`; a revolution is the
+    // outline turned around the y axis.
+    Extrusion {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        svg: Option<PathBuf>, // Relative to the scene file
+        #[serde(default)]
+        outline: Vec<Vec<[f32; 2]>>,
+        depth: f32,
+        #[serde(default)]
+        rounding: f32,
+    },
+    Revolution {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        svg: Option<PathBuf>,
+        #[serde(default)]
+        outline: Vec<Vec<[f32; 2]>>,
+    },
+    // Branches grown from an L-system (see plant.rs): the axiom rewritten by the rules
+    // `iterations` times, drawn by a turtle as capsules `length` long and `radius` thick that
+    // are smoothly joined over `blend`. `wind` sways them, further the higher they are.
+    Plant {
+        axiom: String,
+        #[serde(default)]
+        rules: BTreeMap<String, String>, // Symbol to what replaces it
+        iterations: u32,
+        #[serde(default = "default_plant_angle")]
+        angle: f32, // Degrees per turn
+        length: f32,
+        radius: f32,
+        #[serde(default = "default_taper")]
+        taper: f32, // Scale of each branch against the one it grows from
+        #[serde(default)]
+        blend: f32,
+        #[serde(default)]
+        wind: f32,
+    },
+    // Scanned points drawn as spheres `radius` big blended over `blend`, each in its own color
+    // times the object's. They are read from a PLY or XYZ `file` when the scene is loaded,
+    // fitted and thinned to `budget` (see cloud.rs), or listed in `points` as [x, y, z, r, g,
+    // b]. A radius of 0 is fitted to the points' spacing when they are loaded.
+    Cloud {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        file: Option<PathBuf>, // Relative to the scene file
+        #[serde(default = "default_budget")]
+        budget: usize,
+        #[serde(default)]
+        radius: f32,
+        #[serde(default)]
+        blend: f32,
+        #[serde(default)]
+        points: Vec<cloud::Point>,
+    },
+    // Terrain from a grayscale height image (a DEM tile or depth map, see heightfield.rs) read
+    // from `file` when the scene is loaded at up to `resolution` points a side, or listed in
+    // `heights` as rows of 0 to 1. It covers `size` either side of the origin in x and z, the
+    // image's top row toward -z, and rises from y = 0 to `height` times `exaggeration`,
+    // standing on a base `base` deep. `invert` reads darker as higher.
+    Heightfield {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        file: Option<PathBuf>, // Relative to the scene file
+        #[serde(default = "default_resolution")]
+        resolution: usize,
+        #[serde(default = "default_extent")]
+        size: [f32; 2],
+        #[serde(default = "default_terrain_height")]
+        height: f32,
+        #[serde(default = "default_scale")]
+        exaggeration: f32,
+        #[serde(default = "default_base")]
+        base: f32,
+        #[serde(default)]
+        invert: bool,
+        #[serde(default)]
+        heights: Vec<Vec<f32>>,
+    },
+}
+
+// Plane of a 4D rotation: w turns into x, y or z
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RotationPlane {
+    #[default]
+    Xw,
+    Yw,
+    Zw,
+}
+
+impl Shape {
+    pub fn is_4d(&self) -> bool {
+        matches!(
+            self,
+            Shape::Tesseract { .. } | Shape::Hypersphere { .. } | Shape::Julia { .. }
+        )
+    }
+
+    // The loops of an extrusion or revolution
+    pub fn outline(&self) -> Option<&[Vec<[f32; 2]>]> {
+        match self {
+            Shape::Extrusion { outline, .. } | Shape::Revolution { outline, .. } => Some(outline),
+            _ => None,
+        }
+    }
+}
+
+impl RotationPlane {
+    // Unit vector along the axis w turns into
+    pub fn axis(self) -> [f32; 4] {
+        match self {
+            RotationPlane::Xw => [1.0, 0.0, 0.0, 0.0],
+            RotationPlane::Yw => [0.0, 1.0, 0.0, 0.0],
+            RotationPlane::Zw => [0.0, 0.0, 1.0, 0.0],
+        }
+    }
+}
+
+// How a node is combined with everything before it in its group
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CsgOp {
+    #[default]
+    Union,
+    SmoothUnion {
+        k: f32,
+    },
+    Subtract,
+    SmoothSubtract {
+        k: f32,
+    },
+    Intersect,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SceneObject {
+    #[serde(default)]
+    pub name: String,
+    pub shape: Shape,
+    #[serde(default)]
+    pub position: [f32; 3],
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub rotation: [f32; 3], // Euler angles in degrees, applied X, then Y, then Z
+    #[serde(default = "default_scale", skip_serializing_if = "is_one")]
+    pub scale: f32, // Uniform
+    #[serde(default = "default_color")]
+    pub color: [f32; 3],
+    #[serde(default)]
+    pub op: CsgOp,
+    #[serde(default)]
+    pub textured: bool, // Receives the video/screen textures
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub glass: Option<Glass>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bump: Option<Bump>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub noise: Option<Noise>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iridescence: Option<Iridescence>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub specular: Option<Specular>,
+    #[serde(default, skip_serializing_if = "is_dark")]
+    pub emission: f32, // Glow, in multiples of the object's color
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repeat: Option<Box<Repeat>>, // Boxed, like cells
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cells: Option<Box<Cells>>, // Boxed, as few objects have them
+}
+
+// Transparent material: light refracts through the object and is absorbed inside it
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Glass {
+    #[serde(default = "default_ior")]
+    pub ior: f32, // Index of refraction
+    #[serde(default)]
+    pub density: f32, // How strongly the object's color tints light per unit travelled; 0 is clear
+}
+
+// Surface detail from the scene's normal map
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Bump {
+    #[serde(default = "default_scale")]
+    pub intensity: f32, // How far the map tilts the normal; 0 leaves the surface smooth
+    #[serde(default = "default_scale")]
+    pub tiling: f32, // Repeats of the map per unit
+}
+
+// Color variation from the shared noise
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Noise {
+    #[serde(default)]
+    pub kind: NoiseKind,
+    #[serde(default = "default_noise_amount")]
+    pub amount: f32, // Largest change to the color, darker or brighter; 0 leaves it plain
+    #[serde(default = "default_scale")]
+    pub scale: f32, // Noise features per unit
+}
+
+// Which of the noise functions (see noise.wgsl); the order is their index in the shaders
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NoiseKind {
+    Value,
+    Perlin,
+    Simplex,
+    #[default]
+    Fbm,
+    Curl, // Tints: each channel follows one component of the flow
+}
+
+// Thin-film interference coating the object
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Iridescence {
+    #[serde(default = "default_film_thickness")]
+    pub thickness: f32, // Of the film, in nanometres
+    #[serde(default = "default_scale")]
+    pub amount: f32, // How much the film colors the object; 0 leaves it plain
+    #[serde(default)]
+    pub variation: f32, // Largest change to the thickness from the noise, in nanometres
+    #[serde(default = "default_scale")]
+    pub scale: f32, // Noise features per unit
+}
+
+// Glossy highlight of the sun, optionally stretched along a tangent direction
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Specular {
+    #[serde(default = "default_scale")]
+    pub intensity: f32,
+    #[serde(default = "default_roughness")]
+    pub roughness: f32, // 0 is a mirror-sharp highlight, 1 spreads it over the whole surface
+    #[serde(default)]
+    pub anisotropy: f32, // Positive spreads the highlight across the tangent, negative along it
+    #[serde(default)]
+    pub tangent: Tangent,
+}
+
+// Direction a surface is brushed along, in the object's own space
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Tangent {
+    #[default]
+    X,
+    Y,
+    Z,
+    Radial, // Circles around the y axis
+}
+
+fn default_ior() -> f32 {
+    1.45
+}
+
+fn default_color() -> [f32; 3] {
+    [0.7, 0.7, 0.7]
+}
+
+fn default_film_thickness() -> f32 {
+    400.0
+}
+
+// Copies of the object in a grid of its own space, centered on it
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Repeat {
+    pub spacing: [f32; 3], // Between neighbouring copies along each axis
+    pub count: [u32; 3],   // Copies along each axis, 1 for none
+}
+
+// Voronoi cells cracked into the object, or left as walls (see cells.rs)
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Cells {
+    #[serde(default)]
+    pub style: CellStyle,
+    pub width: f32, // Of the cracks, or half that of the walls
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sites: Vec<[f32; 3]>, // In the object's own space; scattered ones when none are listed
+    #[serde(default = "default_site_count")]
+    pub count: usize, // Sites scattered
+    #[serde(default = "default_scale")]
+    pub spread: f32, // Largest distance of a scattered site from the origin along each axis
+    #[serde(default)]
+    pub seed: u32,
+    #[serde(default)]
+    pub drift: f32, // How far each site wanders from where it rests
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CellStyle {
+    #[default]
+    Cracks, // The object less the borders
+    Foam, // Only the borders
+}
+
+fn default_plant_angle() -> f32 {
+    25.0
+}
+
+fn default_taper() -> f32 {
+    0.7
+}
+
+fn default_budget() -> usize {
+    256
+}
+
+fn default_resolution() -> usize {
+    heightfield::MAX_SIDE
+}
+
+fn default_extent() -> [f32; 2] {
+    [1.0, 1.0]
+}
+
+fn default_terrain_height() -> f32 {
+    0.5
+}
+
+fn default_base() -> f32 {
+    0.05
+}
+
+fn default_site_count() -> usize {
+    16
+}
+
+fn default_roughness() -> f32 {
+    0.3
+}
+
+fn default_noise_amount() -> f32 {
+    0.5
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+fn is_zero(rotation: &[f32; 3]) -> bool {
+    *rotation == [0.0; 3]
+}
+
+fn is_one(scale: &f32) -> bool {
+    *scale == 1.0
+}
+
+fn is_zero_seed(seed: &u32) -> bool {
+    *seed == 0
+}
+
+fn is_zero_sun(sun: &f32) -> bool {
+    *sun == 0.0
+}
+
+fn is_dark(emission: &f32) -> bool {
+    *emission == 0.0
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Group {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub op: CsgOp,
+    pub children: Vec<Node>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Node {
+    Object(SceneObject),
+    Group(Group),
+}
+
+impl Node {
+    pub fn op(&self) -> CsgOp {
+        match self {
+            Node::Object(object) => object.op,
+            Node::Group(group) => group.op,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PointLight {
+    pub name: String,
+    pub position: [f32; 3],
+    #[serde(default = "white")]
+    pub color: [f32; 3],
+    #[serde(default = "default_intensity")]
+    pub intensity: f32,
+    #[serde(default, skip_serializing_if = "is_point")]
+    pub radius: f32, // Soft shadows in the path tracer; raymarched lights cast no shadows
+}
+
+// A pair of linked rectangular openings: a ray passing through either side carries on from
+// the matching point of the other, turned with it, so spaces can join up in impossible ways.
+// The openings themselves are invisible.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Portal {
+    #[serde(default)]
+    pub name: String,
+    pub size: [f32; 2], // Half extents along the sides' local X and Y
+    pub a: PortalSide,
+    pub b: PortalSide,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PortalSide {
+    #[serde(default)]
+    pub position: [f32; 3],
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub rotation: [f32; 3], // Euler angles in degrees, applied X, then Y, then Z
+}
+
+fn white() -> [f32; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+fn default_intensity() -> f32 {
+    4.0
+}
+
+fn is_point(radius: &f32) -> bool {
+    *radius == 0.0
+}
+
+impl PointLight {
+    pub fn new(name: String, position: [f32; 3]) -> Self {
+        Self {
+            name,
+            position,
+            color: white(),
+            intensity: default_intensity(),
+            radius: 0.0,
+        }
+    }
+}
+
+// Lighting and atmosphere shared by the whole scene
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Environment {
+    pub light_color: [f32; 3],
+    pub fog_density: f32, // 0 = no fog
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height_fog: Option<HeightFog>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fog_color: Option<[f32; 3]>, // None fogs towards the sky
+    #[serde(skip_serializing_if = "is_zero_sun")]
+    pub fog_sun: f32, // Sunlight scattered towards the eye
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sun: Option<Sun>, // The real sun's direction instead of the fixed one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub caustics: Option<Caustics>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub toon: Option<Toon>, // Cel shading, unless the config sets it for every scene
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self {
+            light_color: [1.0, 1.0, 1.0],
+            fog_density: 0.0,
+            height_fog: None,
+            fog_color: None,
+            fog_sun: 0.0,
+            sun: None,
+            caustics: None,
+            toon: None,
+        }
+    }
+}
+
+// Fog that pools low: `density` at `height`, thinning exponentially above it
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeightFog {
+    pub density: f32,
+    pub height: f32,
+    pub falloff: f32, // Per unit of height
+}
+
+impl Default for HeightFog {
+    fn default() -> Self {
+        Self {
+            density: 0.2,
+            height: 0.0,
+            falloff: 1.0,
+        }
+    }
+}
+
+// Light focused by glass onto the surfaces the sun shines on through it
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Caustics {
+    pub intensity: f32,
+    pub sharpness: f32, // Power the pattern is raised to; higher gives thinner lines
+}
+
+impl Default for Caustics {
+    fn default() -> Self {
+        Self {
+            intensity: 1.0,
+            sharpness: 4.0,
+        }
+    }
+}
+
+// The ground, drawn after the scene's objects as object id objects().len()
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Floor {
+    pub enabled: bool,
+    pub height: f32,
+    pub checker: f32, // Width of the squares, 0 for one plain color
+    pub colors: [[f32; 3]; 2],
+    pub reflection: f32, // Share of the scene mirrored looking straight down
+    pub contact: f32,    // Distance over which objects darken it, 0 for none
+}
+
+impl Default for Floor {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            height: -1.0,
+            checker: 1.0,
+            colors: [[0.7, 0.7, 0.7], [0.35, 0.35, 0.35]],
+            reflection: 0.0,
+            contact: 0.5,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Scene {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub environment: Environment,
+    #[serde(default)]
+    pub nodes: Vec<Node>, // Top level, folded left to right like a group
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub floor: Option<Floor>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lights: Vec<PointLight>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub portals: Vec<Portal>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub animations: Vec<Track>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub orbits: Vec<Orbit>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub expressions: BTreeMap<String, Binding>, // Parameter address -> expressions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script: Option<PathBuf>, // Per-frame script (see script.rs), relative to the scene file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub normal_map: Option<PathBuf>, // PNG for objects with `bump`, relative to the scene file
+    #[serde(default, skip_serializing_if = "is_zero_seed")]
+    pub noise_seed: u32, // Seeds the noise table (see noise.rs)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cues: Vec<Cue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub volume: Option<Volume>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub meshes: Vec<Mesh>,
+    #[serde(default, skip_serializing)]
+    pub city: Option<City>, // Generated into the nodes and lights on load, which save instead
+    #[serde(skip)]
+    pub scripted: Vec<String>, // Parameters changed at runtime (script, cues), read from the uniforms
+}
+
+impl Scene {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+        let mut scene: Scene = toml::from_str(&text)
+            .map_err(|err| format!("Invalid scene {}: {}", path.display(), err))?;
+        if scene.name.is_empty() {
+            scene.name = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "scene".to_string());
+        }
+        if let Some(city) = &scene.city {
+            city.validate()
+                .map_err(|err| format!("Invalid scene {}: city: {}", path.display(), err))?;
+            let (nodes, lights) = city::generate(city);
+            scene.nodes.extend(nodes);
+            scene.lights.extend(lights);
+        }
+        if scene.objects().is_empty() {
+            return Err(format!("Scene {} has no objects", path.display()));
+        }
+        if let Some(dir) = path.parent() {
+            for path in scene.files_mut() {
+                *path = dir.join(&*path);
+            }
+        }
+        read_outlines(&mut scene.nodes)
+            .map_err(|err| format!("Invalid scene {}: {}", path.display(), err))?;
+        scene
+            .validate()
+            .map_err(|err| format!("Invalid scene {}: {}", path.display(), err))?;
+        let required = ParamLayout::required(&scene);
+        if required > MAX_PARAMS {
+            return Err(format!(
+                "Scene {} animates or binds {} values, at most {} are supported",
+                path.display(),
+                required,
+                MAX_PARAMS
+            ));
+        }
+        Ok(scene)
+    }
+
+    // Write the scene back to a scene file, with the values its parameters have now. Comments
+    // and formatting in the file are not kept.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let mut scene = self.clone();
+        // Load made the files relative to the working directory
+        if let Some(dir) = path.parent() {
+            for path in scene.files_mut() {
+                if let Ok(relative) = path.strip_prefix(dir) {
+                    *path = relative.to_path_buf();
+                }
+            }
+        }
+        let text = toml::to_string(&scene).map_err(|err| err.to_string())?;
+        std::fs::write(path, text)
+            .map_err(|err| format!("Failed to write {}: {}", path.display(), err))
+    }
+
+    // Every file the scene names: its script, normal map, volume and meshes, the scenes its
+    // cues switch to and the files shapes are read from. Relative to the scene file in the
+    // file, and to the working directory once loaded.
+    pub fn files_mut(&mut self) -> Vec<&mut PathBuf> {
+        let cues = self.cues.iter_mut().filter_map(|cue| cue.scene.as_mut());
+        let volume = self.volume.iter_mut().map(|volume| &mut volume.file);
+        let meshes = self.meshes.iter_mut().map(|mesh| &mut mesh.file);
+        let files = (self.script.iter_mut())
+            .chain(&mut self.normal_map)
+            .chain(volume)
+            .chain(meshes);
+        files
+            .chain(cues)
+            .chain(shape_files(&mut self.nodes))
+            .collect()
+    }
+
+    // Light names address their parameters, so they must be unique and not clash with anything
+    pub fn validate_lights(&self) -> Result<(), String> {
+        for (index, light) in self.lights.iter().enumerate() {
+            if light.name.is_empty() {
+                return Err("lights need a name".to_string());
+            }
+            if ["light", "fog", "sun", "floor", "volume"].contains(&light.name.as_str())
+                || self.lights[..index]
+                    .iter()
+                    .any(|other| other.name == light.name)
+                || self.node(&light.name).is_some()
+            {
+                return Err(format!("light name \"{}\" is already taken", light.name));
+            }
+        }
+        Ok(())
+    }
+
+    // Check the scene can be drawn: a name fit for file names, groups nested at most MAX_DEPTH
+    // deep, no control characters in their names, every number finite (TOML has nan and inf),
+    // objects scaled by more than 0, planes with a normal, the light names, and animations,
+    // orbits and expressions that fit their targets. Anything else a file can say only draws
+    // oddly.
+    pub fn validate(&self) -> Result<(), String> {
+        if !file_name(&self.name) {
+            return Err(format!(
+                "scene name {:?} may not hold /, \\, .. or control characters",
+                self.name
+            ));
+        }
+        if depth(&self.nodes) > MAX_DEPTH {
+            return Err(format!("groups are nested more than {} deep", MAX_DEPTH));
+        }
+        // Names end up in comments of the generated shaders (see codegen.rs)
+        let mut names: Vec<_> = (self.lights.iter()).map(|light| &*light.name).collect();
+        node_names(&self.nodes, &mut names);
+        if let Some(name) = names
+            .into_iter()
+            .find(|name| name.contains(char::is_control))
+        {
+            return Err(format!("name {:?} holds control characters", name));
+        }
+        let value = toml::Value::try_from(self).map_err(|err| err.to_string())?;
+        if let Some(at) = non_finite(&value) {
+            return Err(format!(
+                "{} is not a finite number",
+                at.trim_start_matches('.')
+            ));
+        }
+        for (id, object) in self.objects().into_iter().enumerate() {
+            let name = match object.name.as_str() {
+                "" => format!("object {}", id),
+                name => format!("\"{}\"", name),
+            };
+            if object.scale <= 0.0 {
+                return Err(format!("scale of {} is not above 0", name));
+            }
+            if matches!(object.shape, Shape::Plane { normal, .. } if normal == [0.0; 3]) {
+                return Err(format!("plane {} has no normal", name));
+            }
+            if let Some(repeat) = &object.repeat {
+                if repeat.count.contains(&0) {
+                    return Err(format!("repeat of {} has no copies along an axis", name));
+                }
+                if (repeat.count.iter().zip(repeat.spacing)).any(|(&n, s)| n > 1 && s <= 0.0) {
+                    return Err(format!(
+                        "repeat of {} needs a spacing above 0 where it makes copies",
+                        name
+                    ));
+                }
+            }
+            if object
+                .cells
+                .as_ref()
+                .is_some_and(|cells| cells::count(cells) < 2)
+            {
+                return Err(format!("cells of {} need at least 2 sites", name));
+            }
+            if let Some(outline) = object.shape.outline() {
+                let points: usize = outline.iter().map(Vec::len).sum();
+                if outline.iter().any(|points| points.len() < 3) {
+                    return Err(format!("{} has a loop of fewer than 3 points", name));
+                }
+                if points == 0 {
+                    return Err(format!("{} has no outline", name));
+                }
+                if points > svg::MAX_POINTS {
+                    return Err(format!(
+                        "outline of {} has {} points, at most {} are supported",
+                        name,
+                        points,
+                        svg::MAX_POINTS
+                    ));
+                }
+            }
+            plant::segments(&object.shape).map_err(|error| format!("{}: {}", name, error))?;
+            if let Shape::Cloud { points, .. } = &object.shape {
+                if points.is_empty() {
+                    return Err(format!("cloud {} has no points", name));
+                }
+                if points.len() > cloud::MAX_POINTS {
+                    return Err(format!(
+                        "cloud {} has {} points, at most {} are supported",
+                        name,
+                        points.len(),
+                        cloud::MAX_POINTS
+                    ));
+                }
+            }
+            if let Shape::Heightfield {
+                size,
+                base,
+                heights,
+                ..
+            } = &object.shape
+            {
+                let columns = heights.first().map_or(0, Vec::len);
+                if heights.len() < 2 || columns < 2 {
+                    return Err(format!(
+                        "heightfield {} needs at least 2 by 2 heights",
+                        name
+                    ));
+                }
+                if heights.iter().any(|row| row.len() != columns) {
+                    return Err(format!("rows of heightfield {} differ in length", name));
+                }
+                if heights.len().max(columns) > heightfield::MAX_SIDE {
+                    return Err(format!(
+                        "heightfield {} has {} by {} heights, at most {} a side are supported",
+                        name,
+                        columns,
+                        heights.len(),
+                        heightfield::MAX_SIDE
+                    ));
+                }
+                if size.iter().any(|&side| side <= 0.0) || *base < 0.0 {
+                    return Err(format!(
+                        "heightfield {} needs a size above 0 and a base of at least 0",
+                        name
+                    ));
+                }
+            }
+        }
+        let sites: usize = (self.objects().into_iter())
+            .filter_map(|object| object.cells.as_deref())
+            .map(cells::count)
+            .sum();
+        if sites > cells::MAX_SITES {
+            return Err(format!(
+                "objects have {} cell sites, at most {} are supported",
+                sites,
+                cells::MAX_SITES
+            ));
+        }
+        if let Some(volume) = &self.volume {
+            volume
+                .validate()
+                .map_err(|err| format!("volume: {}", err))?;
+        }
+        for (index, mesh) in self.meshes.iter().enumerate() {
+            mesh.validate()
+                .map_err(|err| format!("mesh {}: {}", index + 1, err))?;
+        }
+        if let Some(sun) = &self.environment.sun {
+            sun.validate().map_err(|err| format!("sun: {}", err))?;
+        }
+        self.validate_lights()?;
+        for track in &self.animations {
+            track.validate(self)?;
+        }
+        for orbit in &self.orbits {
+            orbit.validate(self)?;
+        }
+        for (target, binding) in &self.expressions {
+            binding.validate(target, self)?;
+        }
+        // Cues that switch scenes set parameters of the scene they switch to
+        let cues = self.cues.iter().filter(|cue| cue.scene.is_none());
+        for (target, binding) in cues.flat_map(|cue| &cue.set) {
+            binding
+                .validate(target, self)
+                .map_err(|err| format!("cue: {}", err))?;
+        }
+        Ok(())
+    }
+
+    // Name that no object, group or light has yet: `prefix` followed by a number from 1
+    pub fn unused_name(&self, prefix: &str) -> String {
+        (1..)
+            .map(|n| format!("{}{}", prefix, n))
+            .find(|name| self.node(name).is_none() && self.light(name).is_none())
+            .unwrap_or_default()
+    }
+
+    pub fn light(&self, name: &str) -> Option<&PointLight> {
+        self.lights.iter().find(|light| light.name == name)
+    }
+
+    // Read `target` from the uniforms from now on, so it can change without regenerating the
+    // shader. Returns true when it was baked in until now and the shader must be regenerated.
+    pub fn make_live(&mut self, target: &str) -> Result<bool, String> {
+        if ParamLayout::of(self).slot(target).is_some() {
+            return Ok(false);
+        }
+        self.scripted.push(target.to_string());
+        if ParamLayout::required(self) > MAX_PARAMS {
+            self.scripted.pop();
+            return Err(format!(
+                "cannot change \"{}\": at most {} values can change at runtime",
+                target, MAX_PARAMS
+            ));
+        }
+        Ok(true)
+    }
+
+    // Current value of an animatable parameter (see the top of this file)
+    pub fn parameter(&self, target: &str) -> Option<&[f32]> {
+        let (owner, property) = target
+            .rsplit_once('.')
+            .filter(|(owner, _)| !owner.is_empty())?;
+        match (owner, property) {
+            ("light", "color") => return Some(&self.environment.light_color),
+            ("fog", "density") => return Some(std::slice::from_ref(&self.environment.fog_density)),
+            ("fog", "sun") => return Some(std::slice::from_ref(&self.environment.fog_sun)),
+            ("sun", "hours") if self.environment.sun.is_some() => {
+                return (self.environment.sun.as_ref()).map(|sun| std::slice::from_ref(&sun.hours))
+            }
+            ("floor", "height") if self.floor.is_some() => {
+                return self
+                    .floor
+                    .as_ref()
+                    .map(|floor| std::slice::from_ref(&floor.height))
+            }
+            ("volume", _) => {
+                let volume = self.volume.as_ref()?;
+                return match property {
+                    "iso" => Some(std::slice::from_ref(&volume.iso)),
+                    "density" => Some(std::slice::from_ref(&volume.density)),
+                    "window" => Some(&volume.window),
+                    _ => None,
+                };
+            }
+            _ => {}
+        }
+        if let Some(light) = self.light(owner) {
+            return match property {
+                "position" => Some(&light.position),
+                "color" => Some(&light.color),
+                "intensity" => Some(std::slice::from_ref(&light.intensity)),
+                "radius" => Some(std::slice::from_ref(&light.radius)),
+                _ => None,
+            };
+        }
+        match find_node(&self.nodes, owner)? {
+            Node::Object(object) => match (property, &object.shape) {
+                ("position", _) => Some(&object.position),
+                ("rotation", _) => Some(&object.rotation),
+                ("scale", _) => Some(std::slice::from_ref(&object.scale)),
+                ("color", _) => Some(&object.color),
+                (
+                    "radius",
+                    Shape::Sphere { radius }
+                    | Shape::Capsule { radius, .. }
+                    | Shape::Hypersphere { radius, .. },
+                ) => Some(std::slice::from_ref(radius)),
+                ("size", Shape::Box { size }) => Some(size),
+                ("size", Shape::Tesseract { size, .. }) => Some(std::slice::from_ref(size)),
+                ("c", Shape::Julia { c, .. }) => Some(c),
+                (
+                    "w",
+                    Shape::Tesseract { w, .. }
+                    | Shape::Hypersphere { w, .. }
+                    | Shape::Julia { w, .. },
+                ) => Some(std::slice::from_ref(w)),
+                (
+                    "angle",
+                    Shape::Tesseract { angle, .. }
+                    | Shape::Hypersphere { angle, .. }
+                    | Shape::Julia { angle, .. },
+                ) => Some(std::slice::from_ref(angle)),
+                ("normal", Shape::Plane { normal, .. }) => Some(normal),
+                ("height", Shape::Plane { height, .. } | Shape::Heightfield { height, .. }) => {
+                    Some(std::slice::from_ref(height))
+                }
+                ("exaggeration", Shape::Heightfield { exaggeration, .. }) => {
+                    Some(std::slice::from_ref(exaggeration))
+                }
+                ("depth", Shape::Extrusion { depth, .. }) => Some(std::slice::from_ref(depth)),
+                ("rounding", Shape::Extrusion { rounding, .. }) => {
+                    Some(std::slice::from_ref(rounding))
+                }
+                ("k", _) => smooth_k(&object.op),
+                ("ior", _) => object
+                    .glass
+                    .as_ref()
+                    .map(|glass| std::slice::from_ref(&glass.ior)),
+                ("density", _) => object
+                    .glass
+                    .as_ref()
+                    .map(|glass| std::slice::from_ref(&glass.density)),
+                ("bump", _) => object
+                    .bump
+                    .as_ref()
+                    .map(|bump| std::slice::from_ref(&bump.intensity)),
+                ("noise", _) => object
+                    .noise
+                    .as_ref()
+                    .map(|noise| std::slice::from_ref(&noise.amount)),
+                ("film", _) => object
+                    .iridescence
+                    .as_ref()
+                    .map(|film| std::slice::from_ref(&film.thickness)),
+                ("emission", _) => Some(std::slice::from_ref(&object.emission)),
+                ("anisotropy", _) => object
+                    .specular
+                    .as_ref()
+                    .map(|specular| std::slice::from_ref(&specular.anisotropy)),
+                ("cells", _) => object
+                    .cells
+                    .as_ref()
+                    .map(|cells| std::slice::from_ref(&cells.width)),
+                _ => None,
+            },
+            Node::Group(group) => match property {
+                "k" => smooth_k(&group.op),
+                _ => None,
+            },
+        }
+    }
+
+    pub fn parameter_mut(&mut self, target: &str) -> Option<&mut [f32]> {
+        let (owner, property) = target
+            .rsplit_once('.')
+            .filter(|(owner, _)| !owner.is_empty())?;
+        match (owner, property) {
+            ("light", "color") => return Some(&mut self.environment.light_color),
+            ("fog", "density") => {
+                return Some(std::slice::from_mut(&mut self.environment.fog_density))
+            }
+            ("fog", "sun") => return Some(std::slice::from_mut(&mut self.environment.fog_sun)),
+            ("sun", "hours") if self.environment.sun.is_some() => {
+                return (self.environment.sun.as_mut())
+                    .map(|sun| std::slice::from_mut(&mut sun.hours))
+            }
+            ("floor", "height") if self.floor.is_some() => {
+                return self
+                    .floor
+                    .as_mut()
+                    .map(|floor| std::slice::from_mut(&mut floor.height))
+            }
+            ("volume", _) => {
+                let volume = self.volume.as_mut()?;
+                return match property {
+                    "iso" => Some(std::slice::from_mut(&mut volume.iso)),
+                    "density" => Some(std::slice::from_mut(&mut volume.density)),
+                    "window" => Some(&mut volume.window),
+                    _ => None,
+                };
+            }
+            _ => {}
+        }
+        if let Some(light) = self.lights.iter_mut().find(|light| light.name == owner) {
+            return match property {
+                "position" => Some(&mut light.position),
+                "color" => Some(&mut light.color),
+                "intensity" => Some(std::slice::from_mut(&mut light.intensity)),
+                "radius" => Some(std::slice::from_mut(&mut light.radius)),
+                _ => None,
+            };
+        }
+        match find_node_mut(&mut self.nodes, owner)? {
+            Node::Object(object) => match (property, &mut object.shape) {
+                ("position", _) => Some(&mut object.position),
+                ("rotation", _) => Some(&mut object.rotation),
+                ("scale", _) => Some(std::slice::from_mut(&mut object.scale)),
+                ("color", _) => Some(&mut object.color),
+                (
+                    "radius",
+                    Shape::Sphere { radius }
+                    | Shape::Capsule { radius, .. }
+                    | Shape::Hypersphere { radius, .. },
+                ) => Some(std::slice::from_mut(radius)),
+                ("size", Shape::Box { size }) => Some(size),
+                ("size", Shape::Tesseract { size, .. }) => Some(std::slice::from_mut(size)),
+                ("c", Shape::Julia { c, .. }) => Some(c),
+                (
+                    "w",
+                    Shape::Tesseract { w, .. }
+                    | Shape::Hypersphere { w, .. }
+                    | Shape::Julia { w, .. },
+                ) => Some(std::slice::from_mut(w)),
+                (
+                    "angle",
+                    Shape::Tesseract { angle, .. }
+                    | Shape::Hypersphere { angle, .. }
+                    | Shape::Julia { angle, .. },
+                ) => Some(std::slice::from_mut(angle)),
+                ("normal", Shape::Plane { normal, .. }) => Some(normal),
+                ("height", Shape::Plane { height, .. } | Shape::Heightfield { height, .. }) => {
+                    Some(std::slice::from_mut(height))
+                }
+                ("exaggeration", Shape::Heightfield { exaggeration, .. }) => {
+                    Some(std::slice::from_mut(exaggeration))
+                }
+                ("depth", Shape::Extrusion { depth, .. }) => Some(std::slice::from_mut(depth)),
+                ("rounding", Shape::Extrusion { rounding, .. }) => {
+                    Some(std::slice::from_mut(rounding))
+                }
+                ("k", _) => smooth_k_mut(&mut object.op),
+                ("ior", _) => object
+                    .glass
+                    .as_mut()
+                    .map(|glass| std::slice::from_mut(&mut glass.ior)),
+                ("density", _) => object
+                    .glass
+                    .as_mut()
+                    .map(|glass| std::slice::from_mut(&mut glass.density)),
+                ("bump", _) => object
+                    .bump
+                    .as_mut()
+                    .map(|bump| std::slice::from_mut(&mut bump.intensity)),
+                ("noise", _) => object
+                    .noise
+                    .as_mut()
+                    .map(|noise| std::slice::from_mut(&mut noise.amount)),
+                ("film", _) => object
+                    .iridescence
+                    .as_mut()
+                    .map(|film| std::slice::from_mut(&mut film.thickness)),
+                ("emission", _) => Some(std::slice::from_mut(&mut object.emission)),
+                ("anisotropy", _) => object
+                    .specular
+                    .as_mut()
+                    .map(|specular| std::slice::from_mut(&mut specular.anisotropy)),
+                ("cells", _) => object
+                    .cells
+                    .as_mut()
+                    .map(|cells| std::slice::from_mut(&mut cells.width)),
+                _ => None,
+            },
+            Node::Group(group) => match property {
+                "k" => smooth_k_mut(&mut group.op),
+                _ => None,
+            },
+        }
+    }
+
+    // Every target `parameter` answers for: the environment's, then the lights' and the named
+    // nodes' in scene order
+    pub fn parameters(&self) -> Vec<String> {
+        const PROPERTIES: [&str; 25] = [
+            "position",
+            "rotation",
+            "scale",
+            "color",
+            "intensity",
+            "radius",
+            "size",
+            "c",
+            "w",
+            "angle",
+            "normal",
+            "height",
+            "exaggeration",
+            "k",
+            "ior",
+            "density",
+            "bump",
+            "noise",
+            "film",
+            "anisotropy",
+            "emission",
+            "sun",
+            "hours",
+            "iso",
+            "window",
+        ];
+        let mut owners = vec!["light", "fog", "sun", "floor", "volume"];
+        owners.extend(self.lights.iter().map(|light| light.name.as_str()));
+        node_names(&self.nodes, &mut owners);
+        owners
+            .into_iter()
+            .filter(|owner| !owner.is_empty())
+            .flat_map(|owner| PROPERTIES.map(|property| format!("{}.{}", owner, property)))
+            .filter(|target| self.parameter(target).is_some())
+            .collect()
+    }
+
+    // Object or group with the given name (unnamed nodes cannot be looked up)
+    pub fn node(&self, name: &str) -> Option<&Node> {
+        if name.is_empty() {
+            return None;
+        }
+        find_node(&self.nodes, name)
+    }
+
+    // Take the named object or group out of the scene
+    pub fn remove_node(&mut self, name: &str) -> Option<Node> {
+        if name.is_empty() {
+            return None;
+        }
+        remove_node(&mut self.nodes, name)
+    }
+
+    // Indexes leading from the top level to object `id`, through the groups holding it
+    pub fn object_path(&self, id: usize) -> Option<Vec<usize>> {
+        fn find(nodes: &[Node], id: usize, next: &mut usize) -> Option<Vec<usize>> {
+            for (index, node) in nodes.iter().enumerate() {
+                match node {
+                    Node::Object(_) if *next == id => return Some(vec![index]),
+                    Node::Object(_) => *next += 1,
+                    Node::Group(group) => {
+                        if let Some(mut path) = find(&group.children, id, next) {
+                            path.insert(0, index);
+                            return Some(path);
+                        }
+                    }
+                }
+            }
+            None
+        }
+        find(&self.nodes, id, &mut 0)
+    }
+
+    // Id of the object at `path`, the inverse of object_path
+    pub fn object_id(&self, path: &[usize]) -> Option<usize> {
+        (0..self.objects().len()).find(|&id| self.object_path(id).as_deref() == Some(path))
+    }
+
+    // Node at `path` (see object_path)
+    pub fn node_at(&self, path: &[usize]) -> Option<&Node> {
+        let (&index, groups) = path.split_last()?;
+        let mut nodes = &self.nodes;
+        for &group in groups {
+            match nodes.get(group)? {
+                Node::Group(group) => nodes = &group.children,
+                Node::Object(_) => return None,
+            }
+        }
+        nodes.get(index)
+    }
+
+    // Put `node` at `path`, moving the nodes from there on along. Fails when the path does not
+    // lead through groups that exist.
+    pub fn insert_at(&mut self, path: &[usize], node: Node) -> bool {
+        let Some((&index, groups)) = path.split_last() else {
+            return false;
+        };
+        match self.list_mut(groups) {
+            Some(nodes) if index <= nodes.len() => {
+                nodes.insert(index, node);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn remove_at(&mut self, path: &[usize]) -> Option<Node> {
+        let (&index, groups) = path.split_last()?;
+        let nodes = self.list_mut(groups)?;
+        (index < nodes.len()).then(|| nodes.remove(index))
+    }
+
+    // Add `node` at the end of the group holding object `id` (the top level if there is no
+    // such object), so it combines with that object's neighbours. Returns where it went.
+    pub fn add_beside(&mut self, id: usize, node: Node) -> Vec<usize> {
+        let mut path = self.object_path(id).unwrap_or_default();
+        path.pop();
+        let nodes = match self.list_mut(&path) {
+            Some(nodes) => nodes,
+            None => {
+                path.clear();
+                &mut self.nodes
+            }
+        };
+        nodes.push(node);
+        path.push(nodes.len() - 1);
+        path
+    }
+
+    // Children of the group at `path` (the top level for an empty path)
+    fn list_mut(&mut self, path: &[usize]) -> Option<&mut Vec<Node>> {
+        let mut nodes = &mut self.nodes;
+        for &index in path {
+            match nodes.get_mut(index)? {
+                Node::Group(group) => nodes = &mut group.children,
+                Node::Object(_) => return None,
+            }
+        }
+        Some(nodes)
+    }
+
+    // All objects in traversal order; an object's index is its material/object id
+    pub fn objects(&self) -> Vec<&SceneObject> {
+        fn collect<'a>(nodes: &'a [Node], out: &mut Vec<&'a SceneObject>) {
+            for node in nodes {
+                match node {
+                    Node::Object(object) => out.push(object),
+                    Node::Group(group) => collect(&group.children, out),
+                }
+            }
+        }
+        let mut out = Vec::new();
+        collect(&self.nodes, &mut out);
+        out
+    }
+
+    // The floor, unless there is none or it is switched off
+    pub fn ground(&self) -> Option<&Floor> {
+        self.floor.as_ref().filter(|floor| floor.enabled)
+    }
+}
+
+// The names of `nodes` and of those in their groups, in scene order
+fn node_names<'a>(nodes: &'a [Node], out: &mut Vec<&'a str>) {
+    for node in nodes {
+        match node {
+            Node::Object(object) => out.push(&object.name),
+            Node::Group(group) => {
+                out.push(&group.name);
+                node_names(&group.children, out);
+            }
+        }
+    }
+}
+
+// Levels of groups in `nodes` (0 when it has none)
+fn depth(nodes: &[Node]) -> usize {
+    nodes
+        .iter()
+        .map(|node| match node {
+            Node::Group(group) => 1 + depth(&group.children),
+            Node::Object(_) => 0,
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+// The SVG, point cloud and height image files shapes are read from
+fn shape_files(nodes: &mut [Node]) -> Vec<&mut PathBuf> {
+    let mut paths = Vec::new();
+    for node in nodes {
+        match node {
+            Node::Object(object) => match &mut object.shape {
+                Shape::Extrusion {
+                    svg: Some(path), ..
+                }
+                | Shape::Revolution {
+                    svg: Some(path), ..
+                }
+                | Shape::Cloud {
+                    file: Some(path), ..
+                }
+                | Shape::Heightfield {
+                    file: Some(path), ..
+                } => paths.push(path),
+                _ => {}
+            },
+            Node::Group(group) => paths.extend(shape_files(&mut group.children)),
+        }
+    }
+    paths
+}
+
+// Replace the outlines of extrusions and revolutions with the SVG files they name
+fn read_outlines(nodes: &mut [Node]) -> Result<(), String> {
+    for node in nodes {
+        match node {
+            Node::Object(object) => match &mut object.shape {
+                Shape::Extrusion {
+                    svg: Some(path),
+                    outline,
+                    ..
+                } => *outline = svg::load(path, false)?,
+                Shape::Revolution {
+                    svg: Some(path),
+                    outline,
+                } => *outline = svg::load(path, true)?,
+                Shape::Cloud {
+                    file,
+                    budget,
+                    radius,
+                    points,
+                    ..
+                } => {
+                    if let Some(path) = file {
+                        *points = cloud::load(path, *budget)?;
+                    }
+                    if *radius == 0.0 {
+                        *radius = cloud::fitted_radius(points);
+                    }
+                }
+                Shape::Heightfield {
+                    file: Some(path),
+                    resolution,
+                    invert,
+                    heights,
+                    ..
+                } => *heights = heightfield::load(path, *resolution, *invert)?,
+                _ => {}
+            },
+            Node::Group(group) => read_outlines(&mut group.children)?,
+        }
+    }
+    Ok(())
+}
+
+// Whether `name` can be part of a file name: scene names name captures, pass dumps, workspaces
+// and exports (see main.rs and export.rs), which must not climb out of the folder they go in
+fn file_name(name: &str) -> bool {
+    !name.contains(['/', '\\']) && !name.contains("..") && !name.chars().any(char::is_control)
+}
+
+// Where the first number in `value` that isn't finite is, e.g. ".nodes[0].shape.radius"
+fn non_finite(value: &toml::Value) -> Option<String> {
+    match value {
+        toml::Value::Float(x) if !x.is_finite() => Some(String::new()),
+        toml::Value::Array(items) => items
+            .iter()
+            .enumerate()
+            .find_map(|(index, item)| non_finite(item).map(|at| format!("[{}]{}", index, at))),
+        toml::Value::Table(table) => table
+            .iter()
+            .find_map(|(key, item)| non_finite(item).map(|at| format!(".{}{}", key, at))),
+        _ => None,
+    }
+}
+
+fn find_node<'a>(nodes: &'a [Node], name: &str) -> Option<&'a Node> {
+    nodes.iter().find_map(|node| match node {
+        Node::Object(object) if object.name == name => Some(node),
+        Node::Group(group) if group.name == name => Some(node),
+        Node::Group(group) => find_node(&group.children, name),
+        Node::Object(_) => None,
+    })
+}
+
+fn find_node_mut<'a>(nodes: &'a mut [Node], name: &str) -> Option<&'a mut Node> {
+    for node in nodes {
+        let found = match node {
+            Node::Object(object) => object.name == name,
+            Node::Group(group) => group.name == name,
+        };
+        if found {
+            return Some(node);
+        }
+        if let Node::Group(group) = node {
+            if let Some(node) = find_node_mut(&mut group.children, name) {
+                return Some(node);
+            }
+        }
+    }
+    None
+}
+
+fn remove_node(nodes: &mut Vec<Node>, name: &str) -> Option<Node> {
+    let index = nodes.iter().position(|node| match node {
+        Node::Object(object) => object.name == name,
+        Node::Group(group) => group.name == name,
+    });
+    if let Some(index) = index {
+        return Some(nodes.remove(index));
+    }
+    nodes.iter_mut().find_map(|node| match node {
+        Node::Group(group) => remove_node(&mut group.children, name),
+        Node::Object(_) => None,
+    })
+}
+
+fn smooth_k(op: &CsgOp) -> Option<&[f32]> {
+    match op {
+        CsgOp::SmoothUnion { k } | CsgOp::SmoothSubtract { k } => Some(std::slice::from_ref(k)),
+        _ => None,
+    }
+}
+
+fn smooth_k_mut(op: &mut CsgOp) -> Option<&mut [f32]> {
+    match op {
+        CsgOp::SmoothUnion { k } | CsgOp::SmoothSubtract { k } => Some(std::slice::from_mut(k)),
+        _ => None,
+    }
+}
+
+impl Default for Scene {
+    // The original hard-coded scene: a sphere above an orange ground plane
+    fn default() -> Self {
+        Self {
+            name: "default".to_string(),
+            environment: Environment::default(),
+            nodes: vec![
+                Node::Object(SceneObject {
+                    name: "sphere".to_string(),
+                    shape: Shape::Sphere { radius: 1.5 },
+                    position: [0.0, 0.0, 0.0],
+                    rotation: [0.0; 3],
+                    scale: 1.0,
+                    color: [0.0, 0.8, 0.2],
+                    op: CsgOp::Union,
+                    textured: true,
+                    glass: None,
+                    bump: None,
+                    noise: None,
+                    iridescence: None,
+                    specular: None,
+                    emission: 0.0,
+                    repeat: None,
+                    cells: None,
+                }),
+                Node::Object(SceneObject {
+                    name: "ground".to_string(),
+                    shape: Shape::Plane {
+                        normal: [0.0, 1.0, 0.0],
+                        height: 2.0,
+                    },
+                    position: [0.0, 0.0, 0.0],
+                    rotation: [0.0; 3],
+                    scale: 1.0,
+                    color: [1.0, 0.5, 0.0],
+                    op: CsgOp::Union,
+                    textured: false,
+                    glass: None,
+                    bump: None,
+                    noise: None,
+                    iridescence: None,
+                    specular: None,
+                    emission: 0.0,
+                    repeat: None,
+                    cells: None,
+                }),
+            ],
+            floor: None,
+            lights: Vec::new(),
+            portals: Vec::new(),
+            animations: Vec::new(),
+            orbits: Vec::new(),
+            expressions: BTreeMap::new(),
+            script: None,
+            normal_map: None,
+            noise_seed: 0,
+            cues: Vec::new(),
+            volume: None,
+            meshes: Vec::new(),
+            city: None,
+            scripted: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,282 @@
+use crate::frames::*;
+use crate::inputs::{ScreenTarget, TextureSource};
+use block::ConcreteBlock;
+use cocoa::base::{id, nil};
+use metal::*;
+use objc::runtime::{Class, Object, Sel, BOOL, NO};
+use objc::{class, msg_send, sel, sel_impl};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+const SC_STREAM_OUTPUT_TYPE_SCREEN: isize = 0;
+const CAPTURE_FPS: i32 = 60;
+
+#[link(name = "ScreenCaptureKit", kind = "framework")]
+extern "C" {}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CGPoint {
+    x: f64,
+    y: f64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CGSize {
+    width: f64,
+    height: f64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CGRect {
+    origin: CGPoint,
+    size: CGSize,
+}
+
+// Object handed back from a ScreenCaptureKit completion handler (already retained)
+struct Retained(id);
+
+unsafe impl Send for Retained {}
+
+pub struct ScreenCapture {
+    stream: id,
+    output: id,
+    queue: id,
+    latest: Arc<FrameSlot>,
+    texture: FrameTexture,
+}
+
+// The stream is only messaged by the owner after setup, from one thread at a time; frames
+// arrive through the Mutex-guarded slot
+unsafe impl Send for ScreenCapture {}
+
+impl ScreenCapture {
+    pub fn new(device: &DeviceRef, target: &ScreenTarget) -> Result<Self, String> {
+        let latest: Arc<FrameSlot> = Arc::new(Mutex::new(None));
+        unsafe {
+            let content = shareable_content()?;
+            let result = start_stream(content.0, target, &latest);
+            let _: () = msg_send![content.0, release];
+            let (stream, output, queue) = result?;
+            Ok(Self {
+                stream,
+                output,
+                queue,
+                latest,
+                texture: FrameTexture::new(device),
+            })
+        }
+    }
+}
+
+impl TextureSource for ScreenCapture {
+    fn upload(&mut self, device: &DeviceRef) {
+        self.texture.upload_from(device, &self.latest);
+    }
+
+    fn texture(&self) -> &TextureRef {
+        self.texture.texture()
+    }
+}
+
+impl Drop for ScreenCapture {
+    fn drop(&mut self) {
+        unsafe {
+            let mut error: id = nil;
+            let _: BOOL = msg_send![self.stream,
+                removeStreamOutput: self.output
+                type: SC_STREAM_OUTPUT_TYPE_SCREEN
+                error: &mut error];
+            let done = ConcreteBlock::new(|_error: id| {}).copy();
+            let _: () = msg_send![self.stream, stopCaptureWithCompletionHandler: &*done];
+            let _: () = msg_send![self.stream, release];
+            let _: () = msg_send![self.output, release];
+            dispatch_release(self.queue);
+        }
+    }
+}
+
+// Block until ScreenCaptureKit reports the displays and windows we may capture.
+// This is also where the screen recording permission prompt appears.
+unsafe fn shareable_content() -> Result<Retained, String> {
+    let (sender, receiver) = mpsc::channel::<Result<Retained, String>>();
+    let handler = ConcreteBlock::new(move |content: id, error: id| {
+        let result = if content.is_null() {
+            Err(error_description(error))
+        } else {
+            let _: id = msg_send![content, retain];
+            Ok(Retained(content))
+        };
+        let _ = sender.send(result);
+    })
+    .copy();
+    let _: () =
+        msg_send![class!(SCShareableContent), getShareableContentWithCompletionHandler: &*handler];
+
+    receiver
+        .recv_timeout(Duration::from_secs(10))
+        .map_err(|_| "Timed out waiting for shareable screen content".to_string())?
+}
+
+unsafe fn start_stream(
+    content: id,
+    target: &ScreenTarget,
+    latest: &Arc<FrameSlot>,
+) -> Result<(id, id, id), String> {
+    let screen: id = msg_send![class!(NSScreen), mainScreen];
+    let scale: f64 = if screen.is_null() {
+        1.0
+    } else {
+        msg_send![screen, backingScaleFactor]
+    };
+
+    let (filter, width, height, label) = match target {
+        ScreenTarget::Display(index) => {
+            let displays: id = msg_send![content, displays];
+            let count: usize = msg_send![displays, count];
+            if *index >= count {
+                return Err(format!("Display {} not found ({} available)", index, count));
+            }
+            let display: id = msg_send![displays, objectAtIndex: *index];
+            let width: isize = msg_send![display, width];
+            let height: isize = msg_send![display, height];
+            let excluded: id = msg_send![class!(NSArray), array];
+            let filter: id = msg_send![class!(SCContentFilter), alloc];
+            let filter: id = msg_send![filter, initWithDisplay: display excludingWindows: excluded];
+            (
+                filter,
+                width as f64,
+                height as f64,
+                format!("display {}", index),
+            )
+        }
+        ScreenTarget::Window(wanted) => {
+            let window = find_window(content, wanted)
+                .ok_or_else(|| format!("No window matching \"{}\"", wanted))?;
+            let frame: CGRect = msg_send![window, frame];
+            let title: id = msg_send![window, title];
+            let filter: id = msg_send![class!(SCContentFilter), alloc];
+            let filter: id = msg_send![filter, initWithDesktopIndependentWindow: window];
+            (
+                filter,
+                frame.size.width,
+                frame.size.height,
+                format!("window \"{}\"", string_from_ns(title)),
+            )
+        }
+    };
+
+    let configuration: id = msg_send![class!(SCStreamConfiguration), new];
+    let _: () = msg_send![configuration, setWidth: (width * scale) as usize];
+    let _: () = msg_send![configuration, setHeight: (height * scale) as usize];
+    let _: () = msg_send![configuration, setPixelFormat: K_CV_PIXEL_FORMAT_TYPE_32_BGRA];
+    let _: () = msg_send![configuration, setMinimumFrameInterval: CMTime::new(1, CAPTURE_FPS)];
+    let _: () = msg_send![configuration, setShowsCursor: NO];
+
+    let stream: id = msg_send![class!(SCStream), alloc];
+    let stream: id = msg_send![stream,
+        initWithFilter: filter
+        configuration: configuration
+        delegate: nil];
+    let _: () = msg_send![filter, release];
+    let _: () = msg_send![configuration, release];
+
+    // Frames arrive on a private serial queue and are parked in the shared slot
+    let output = new_frame_sink(output_class(), latest);
+    let queue = dispatch_queue_create(c"raymarch.screen".as_ptr(), std::ptr::null_mut());
+    let mut error: id = nil;
+    let added: BOOL = msg_send![stream,
+        addStreamOutput: output
+        type: SC_STREAM_OUTPUT_TYPE_SCREEN
+        sampleHandlerQueue: queue
+        error: &mut error];
+    if added == NO {
+        let _: () = msg_send![stream, release];
+        let _: () = msg_send![output, release];
+        dispatch_release(queue);
+        return Err(error_description(error));
+    }
+
+    let started = ConcreteBlock::new(|error: id| {
+        if !error.is_null() {
+            eprintln!(
+                "Screen capture failed to start: {}",
+                error_description(error)
+            );
+        }
+    })
+    .copy();
+    let _: () = msg_send![stream, startCaptureWithCompletionHandler: &*started];
+
+    println!("Screen capture: {}", label);
+    Ok((stream, output, queue))
+}
+
+// Match against the window title first, then the owning application's name
+unsafe fn find_window(content: id, wanted: &str) -> Option<id> {
+    let windows: id = msg_send![content, windows];
+    let count: usize = msg_send![windows, count];
+    let windows: Vec<id> = (0..count)
+        .map(|i| msg_send![windows, objectAtIndex: i])
+        .collect();
+
+    let by_title = windows.iter().copied().find(|&window| {
+        let title: id = msg_send![window, title];
+        string_from_ns(title).contains(wanted)
+    });
+    by_title.or_else(|| {
+        windows.iter().copied().find(|&window| {
+            let app: id = msg_send![window, owningApplication];
+            if app.is_null() {
+                return false;
+            }
+            let name: id = msg_send![app, applicationName];
+            string_from_ns(name).contains(wanted)
+        })
+    })
+}
+
+unsafe fn error_description(error: id) -> String {
+    if error.is_null() {
+        return "unknown error".to_string();
+    }
+    let description: id = msg_send![error, localizedDescription];
+    string_from_ns(description)
+}
+
+// SCStreamOutput implementation, registered once
+fn output_class() -> &'static Class {
+    static CLASS: OnceLock<&'static Class> = OnceLock::new();
+    CLASS.get_or_init(|| {
+        let mut decl = frame_sink_class_decl("RaymarchScreenOutput");
+
+        extern "C" fn did_output(
+            this: &Object,
+            _sel: Sel,
+            _stream: id,
+            sample_buffer: CMSampleBufferRef,
+            output_type: isize,
+        ) {
+            if output_type != SC_STREAM_OUTPUT_TYPE_SCREEN {
+                return;
+            }
+            // Idle/blank frames carry no image buffer and are skipped
+            unsafe {
+                if let Some(frame) = PixelBuffer::from_sample_buffer(sample_buffer) {
+                    deliver_frame(this, frame);
+                }
+            }
+        }
+
+        unsafe {
+            decl.add_method(
+                sel!(stream:didOutputSampleBuffer:ofType:),
+                did_output as extern "C" fn(&Object, Sel, id, CMSampleBufferRef, isize),
+            );
+        }
+        decl.register()
+    })
+}
@@ -0,0 +1,334 @@
+// Per-frame scene logic in Rhai (with the "scripting" feature). A scene attaches a script
+// with `script = "logic.rhai"`; it runs before every frame with these variables:
+//
+//   time   seconds since the start
+//   dt     seconds since the previous frame
+//   mouse  normalized cursor position, [x, y]
+//   audio  audio input levels: #{ bass, mid, high, level, beat } (see audio.rs)
+//   clock  musical clock: #{ beats, beat, bar, bpm } (see clock.rs)
+//   state  object map that keeps its values from frame to frame
+//   scene  the scene, with
+//            scene.get("ball.position")             parameter value (addresses as in scene.rs)
+//            scene.set("ball.color", [1.0, 0.2, 0.2])
+//            scene.tween("ball.radius", 1.5, 0.5)          ease there over 0.5 s
+//            scene.tween("ball.radius", 1.0, 0.8, "spring") with an easing from tween.rs
+//            scene.objects                          names of the named objects
+//            scene.add(#{ name: "dot", shape: #{ type: "sphere", radius: 0.2 },
+//                           position: [0.0, 1.0, 0.0] })   fields as in the scene file
+//            scene.remove("dot")
+//
+// e.g.
+//   scene.set("ball.position", [sin(time) * 2.0, 0.5, 0.0]);
+//   if time > 5.0 && !("popped" in state) {
+//       scene.remove("dot");
+//       state.popped = true;
+//   }
+//
+// Values set by the script are read by the shader from the uniforms, like animated ones;
+// adding or removing objects regenerates the shader. The file is reloaded when it
+// changes, starting over from the scene as it was loaded.
+use crate::expression::Variables;
+use crate::scene::{Node, Scene, SceneObject};
+use crate::tween::{Easing, Tween};
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map, Scope, AST, FLOAT, INT};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// Operations a single frame may take, so a runaway loop fails instead of hanging rendering
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+pub struct Script {
+    path: PathBuf,
+    engine: Engine,
+    ast: AST,
+    modified: Option<SystemTime>,
+    initial: Scene, // Restored when the script is reloaded
+    state: Map,
+    tweens: Vec<ParameterTween>,
+    last_time: f32,
+    failed: bool, // Stopped by a runtime error until the file changes
+}
+
+// A parameter easing to a value set with `scene.tween`
+#[derive(Clone)]
+struct ParameterTween {
+    target: String,
+    tween: Tween<Vec<f32>>,
+}
+
+// The `scene` variable: the scene being edited and whether its shader must be regenerated
+#[derive(Clone)]
+struct ScriptScene {
+    scene: Scene,
+    rebuild: bool,
+    time: f32,
+    tweens: Vec<ParameterTween>,
+}
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+impl Script {
+    // Compile the script at `path` for `scene` (as loaded, before any script changes)
+    pub fn load(path: &Path, scene: &Scene) -> Result<Self, String> {
+        let engine = engine();
+        let ast = compile(&engine, path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            engine,
+            ast,
+            modified: modified(path),
+            initial: scene.clone(),
+            state: Map::new(),
+            tweens: Vec::new(),
+            last_time: 0.0,
+            failed: false,
+        })
+    }
+
+    // Run the script for the frame at `variables.time`, reloading it first if the file
+    // changed. Returns true when the scene's structure changed and the shader must be
+    // regenerated.
+    pub fn run(&mut self, scene: &mut Scene, variables: &Variables) -> Result<bool, String> {
+        let Variables {
+            time,
+            mouse,
+            audio,
+            clock,
+        } = *variables;
+        let mut rebuild = false;
+        let modified = modified(&self.path);
+        if modified != self.modified {
+            self.modified = modified;
+            self.ast = compile(&self.engine, &self.path)?;
+            self.state = Map::new();
+            self.tweens.clear();
+            self.failed = false;
+            *scene = self.initial.clone();
+            rebuild = true;
+            println!("Reloaded script {}", self.path.display());
+        }
+        if self.failed {
+            return Ok(rebuild);
+        }
+
+        let dt = (time - self.last_time).max(0.0);
+        self.last_time = time;
+
+        let mut scope = Scope::new();
+        scope.push_constant("time", time as FLOAT);
+        scope.push_constant("dt", dt as FLOAT);
+        scope.push_constant("mouse", float_array(&[mouse.x, mouse.y]));
+        let levels = [
+            ("bass", audio.bass),
+            ("mid", audio.mid),
+            ("high", audio.high),
+            ("level", audio.level),
+            ("beat", audio.beat),
+        ];
+        scope.push_constant("audio", float_map(&levels));
+        let phases = [
+            ("beats", clock.beats),
+            ("beat", clock.beat),
+            ("bar", clock.bar),
+            ("bpm", clock.bpm),
+        ];
+        scope.push_constant("clock", float_map(&phases));
+        scope.push("state", std::mem::take(&mut self.state));
+        scope.push(
+            "scene",
+            ScriptScene {
+                scene: scene.clone(),
+                rebuild: false,
+                time,
+                tweens: std::mem::take(&mut self.tweens),
+            },
+        );
+        let result = self.engine.run_ast_with_scope(&mut scope, &self.ast);
+        self.state = scope.remove::<Map>("state").unwrap_or_default();
+        let edited = scope.remove::<ScriptScene>("scene");
+
+        // Keep the scene as it was when the script fails part way through
+        let error = match (result, edited) {
+            (Ok(()), Some(edited)) => {
+                *scene = edited.scene;
+                self.tweens = edited.tweens;
+                for ParameterTween { target, tween } in &self.tweens {
+                    if let Some(parameter) = scene.parameter_mut(target) {
+                        parameter.copy_from_slice(&tween.at(time));
+                    }
+                }
+                self.tweens.retain(|tween| !tween.tween.finished(time));
+                return Ok(rebuild || edited.rebuild);
+            }
+            (Ok(()), None) => "`scene` was replaced".to_string(),
+            (Err(err), _) => err.to_string(),
+        };
+        self.failed = true;
+        Err(format!(
+            "{}: {} (stopped until the file changes)",
+            self.path.display(),
+            error
+        ))
+    }
+}
+
+fn compile(engine: &Engine, path: &Path) -> Result<AST, String> {
+    engine
+        .compile_file(path.to_path_buf())
+        .map_err(|err| format!("Failed to load script {}: {}", path.display(), err))
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine
+        .register_type_with_name::<ScriptScene>("Scene")
+        .register_fn("get", get)
+        .register_fn("set", set)
+        .register_fn("tween", tween)
+        .register_fn("tween", tween_with)
+        .register_get("objects", objects)
+        .register_fn("add", add)
+        .register_fn("remove", remove);
+    engine
+}
+
+fn get(this: &mut ScriptScene, target: &str) -> ScriptResult<Dynamic> {
+    match this.scene.parameter(target) {
+        Some([value]) => Ok(Dynamic::from_float(*value)),
+        Some(values) => Ok(float_array(values).into()),
+        None => Err(format!("no parameter \"{}\"", target).into()),
+    }
+}
+
+fn set(this: &mut ScriptScene, target: &str, value: Dynamic) -> ScriptResult<()> {
+    let values = parameter_values(this, target, value)?;
+    this.tweens.retain(|tween| tween.target != target);
+    if let Some(parameter) = this.scene.parameter_mut(target) {
+        parameter.copy_from_slice(&values);
+    }
+    Ok(())
+}
+
+fn tween(
+    this: &mut ScriptScene,
+    target: &str,
+    value: Dynamic,
+    seconds: Dynamic,
+) -> ScriptResult<()> {
+    tween_with(this, target, value, seconds, "ease_in_out")
+}
+
+fn tween_with(
+    this: &mut ScriptScene,
+    target: &str,
+    value: Dynamic,
+    seconds: Dynamic,
+    easing: &str,
+) -> ScriptResult<()> {
+    let values = parameter_values(this, target, value)?;
+    let easing = Easing::parse(easing).ok_or_else(|| format!("unknown easing \"{}\"", easing))?;
+    let from = this.scene.parameter(target).unwrap_or_default().to_vec();
+    let tween = Tween::new(from, values, this.time, number(&seconds)?, easing);
+    this.tweens.retain(|tween| tween.target != target);
+    this.tweens.push(ParameterTween {
+        target: target.to_string(),
+        tween,
+    });
+    Ok(())
+}
+
+// `value` as the values of `target`, which from now on is read from the uniforms
+fn parameter_values(
+    this: &mut ScriptScene,
+    target: &str,
+    value: Dynamic,
+) -> ScriptResult<Vec<f32>> {
+    let values = floats(value)?;
+    let parameter = this
+        .scene
+        .parameter(target)
+        .ok_or_else(|| format!("no parameter \"{}\"", target))?;
+    if parameter.len() != values.len() {
+        return Err(format!(
+            "\"{}\" takes {} values, got {}",
+            target,
+            parameter.len(),
+            values.len()
+        )
+        .into());
+    }
+
+    // The first change moves the parameter from the shader source into the uniforms
+    this.rebuild |= this.scene.make_live(target)?;
+    Ok(values)
+}
+
+fn objects(this: &mut ScriptScene) -> Array {
+    this.scene
+        .objects()
+        .iter()
+        .filter(|object| !object.name.is_empty())
+        .map(|object| object.name.clone().into())
+        .collect()
+}
+
+fn add(this: &mut ScriptScene, object: Map) -> ScriptResult<()> {
+    let object: SceneObject = rhai::serde::from_dynamic(&object.into())?;
+    if this.scene.node(&object.name).is_some() {
+        return Err(format!("an object named \"{}\" already exists", object.name).into());
+    }
+    this.scene.nodes.push(Node::Object(object));
+    this.rebuild = true;
+    Ok(())
+}
+
+fn remove(this: &mut ScriptScene, name: &str) -> ScriptResult<bool> {
+    let mut scene = this.scene.clone();
+    if scene.remove_node(name).is_none() {
+        return Ok(false);
+    }
+    if scene.objects().is_empty() {
+        return Err(format!("cannot remove \"{}\", the scene would be empty", name).into());
+    }
+    this.scene = scene;
+    this.rebuild = true;
+    Ok(true)
+}
+
+fn float_array(values: &[f32]) -> Array {
+    values
+        .iter()
+        .map(|&value| Dynamic::from_float(value))
+        .collect()
+}
+
+fn float_map(values: &[(&str, f32)]) -> Map {
+    values
+        .iter()
+        .map(|&(name, value)| (name.into(), Dynamic::from_float(value)))
+        .collect()
+}
+
+fn number(value: &Dynamic) -> ScriptResult<f32> {
+    Ok(value
+        .as_float()
+        .or_else(|_| value.as_int().map(|value: INT| value as FLOAT))
+        .map_err(|_| format!("expected a number, got {}", value.type_name()))?)
+}
+
+// A number or an array of numbers as floats
+fn floats(value: Dynamic) -> ScriptResult<Vec<f32>> {
+    if value.is_array() {
+        let array = value.cast::<Array>();
+        array.iter().map(number).collect()
+    } else {
+        Ok(vec![number(&value)?])
+    }
+}
@@ -0,0 +1,231 @@
+// Demo sequences: a playlist of scenes, each shown for a set time with an optional camera
+// path, joined by cuts or cross-fades. Playback follows the app's clock (fixed steps with
+// --render), so a sequence always plays out the same way. Sequence files are TOML:
+//
+//   name = "demo"
+//   repeat = true                          # Start over after the last entry
+//
+//   [[entries]]
+//   scene = "blobs.toml"                   # Relative to the sequence file
+//   duration = 8.0
+//   camera = [                             # Optional; without it the orbit camera is used
+//       { time = 0.0, position = [0.0, 2.0, -8.0] },
+//       { time = 8.0, position = [6.0, 3.0, -4.0], target = [0.0, 0.5, 0.0] },
+//   ]
+//   camera_interpolation = "catmull_rom"   # As for animations (see animation.rs)
+//   # tour = true                          # Instead of `camera`: fly through the scene's
+//                                          # [city] (see city.rs)
+//
+//   [[entries]]
+//   scene = "pulse.toml"
+//   duration = 10.0
+//   transition = { crossfade = 1.5 }       # Fade in over the end of the previous entry
+//                                          # ("cut" by default)
+//
+// Each entry's animations, expressions and script run on its own time, starting at 0 when
+// the entry starts (negative while it fades in). During a cross-fade both scenes are drawn
+// and blended; only the outgoing one runs its script.
+use crate::animation::{Interpolation, Keyframe, Track};
+use crate::camera::Camera;
+use crate::city;
+use crate::scene::Scene;
+use cgmath::Vector3;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transition {
+    #[default]
+    Cut,
+    Crossfade(f32), // Seconds
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CameraKey {
+    pub time: f32, // Seconds into the entry
+    pub position: [f32; 3],
+    #[serde(default)]
+    pub target: [f32; 3],
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub scene: PathBuf,
+    pub duration: f32, // Seconds
+    #[serde(default)]
+    pub transition: Transition, // Into this entry
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub camera: Vec<CameraKey>,
+    #[serde(default)]
+    pub camera_interpolation: Interpolation,
+    #[serde(default)]
+    pub tour: bool, // Lay the camera path through the scene's city when it is loaded
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Sequence {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub repeat: bool,
+    pub entries: Vec<Entry>,
+}
+
+// What a sequence shows at one moment
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Moment {
+    pub entry: usize,
+    pub time: f32, // Into the entry
+    pub incoming: Option<Incoming>,
+}
+
+// The next entry while it fades in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Incoming {
+    pub entry: usize,
+    pub time: f32, // Into the entry (negative until it starts)
+    pub opacity: f32,
+}
+
+impl Sequence {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+        let invalid = |err: String| format!("Invalid sequence {}: {}", path.display(), err);
+        let mut sequence: Sequence =
+            toml::from_str(&text).map_err(|err| invalid(err.to_string()))?;
+        if sequence.name.is_empty() {
+            sequence.name = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "sequence".to_string());
+        }
+        if sequence.entries.is_empty() {
+            return Err(invalid("no entries".to_string()));
+        }
+        let mut previous = f32::INFINITY;
+        for entry in &mut sequence.entries {
+            if let Some(dir) = path.parent() {
+                entry.scene = dir.join(&entry.scene);
+            }
+            if entry.duration <= 0.0 {
+                return Err(invalid(format!(
+                    "{} needs a positive duration",
+                    entry.scene.display()
+                )));
+            }
+            if let Transition::Crossfade(fade) = entry.transition {
+                if !(0.0..=previous).contains(&fade) {
+                    return Err(invalid(format!(
+                        "the cross-fade into {} is longer than the entry before it",
+                        entry.scene.display()
+                    )));
+                }
+            }
+            if entry
+                .camera
+                .windows(2)
+                .any(|pair| pair[1].time < pair[0].time)
+            {
+                return Err(invalid(format!(
+                    "camera keys of {} are not in time order",
+                    entry.scene.display()
+                )));
+            }
+            if entry.tour && !entry.camera.is_empty() {
+                return Err(invalid(format!(
+                    "{} tours its city and has camera keys too",
+                    entry.scene.display()
+                )));
+            }
+            previous = entry.duration;
+        }
+        Ok(sequence)
+    }
+
+    // Load the scene of every entry, and lay the camera paths of those touring their city
+    pub fn scenes(&mut self) -> Result<Vec<Scene>, String> {
+        let mut scenes = Vec::new();
+        for entry in &mut self.entries {
+            let scene = Scene::load(&entry.scene)?;
+            if entry.tour {
+                let Some(city) = &scene.city else {
+                    return Err(format!(
+                        "Scene {} has no [city] to tour",
+                        entry.scene.display()
+                    ));
+                };
+                entry.camera = city::tour(city, entry.duration);
+            }
+            scenes.push(scene);
+        }
+        Ok(scenes)
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.entries.iter().map(|entry| entry.duration).sum()
+    }
+
+    pub fn at(&self, time: f32) -> Moment {
+        let total = self.duration();
+        let time = if self.repeat {
+            time.rem_euclid(total)
+        } else {
+            time.max(0.0)
+        };
+
+        let mut start = 0.0;
+        for (index, entry) in self.entries.iter().enumerate() {
+            let end = start + entry.duration;
+            let last = index + 1 == self.entries.len();
+            if time < end || last {
+                let incoming = match self.entries.get(index + 1).map(|next| next.transition) {
+                    Some(Transition::Crossfade(fade)) if fade > 0.0 && time > end - fade => {
+                        Some(Incoming {
+                            entry: index + 1,
+                            time: time - end,
+                            opacity: (time - (end - fade)) / fade,
+                        })
+                    }
+                    _ => None,
+                };
+                return Moment {
+                    entry: index,
+                    time: (time - start).min(entry.duration),
+                    incoming,
+                };
+            }
+            start = end;
+        }
+        unreachable!("sequences have at least one entry")
+    }
+}
+
+impl Entry {
+    // Camera on the entry's path at `time` into it (None without a path)
+    pub fn camera_at(&self, time: f32) -> Option<Camera> {
+        if self.camera.is_empty() {
+            return None;
+        }
+        let track = Track {
+            target: String::new(),
+            interpolation: self.camera_interpolation,
+            repeat: false,
+            keys: self
+                .camera
+                .iter()
+                .map(|key| Keyframe {
+                    time: key.time,
+                    value: key.position.iter().chain(&key.target).copied().collect(),
+                })
+                .collect(),
+        };
+        let value = track.sample(time);
+        Some(Camera {
+            position: Vector3::new(value[0], value[1], value[2]),
+            target: Vector3::new(value[3], value[4], value[5]),
+            ..Camera::default()
+        })
+    }
+}
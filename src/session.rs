@@ -0,0 +1,282 @@
+// Session recording and replay. --record FILE writes the state behind every frame (time,
+// camera, mouse, audio levels, musical clock and the values of the animated, bound and scripted
+// parameters), plus a snapshot of the scene whenever its shader changes. --replay FILE
+// plays that back instead of running animations, expressions and scripts, in a window or
+// with --render to re-render a live take offline, e.g. at --quality ultra.
+//
+// The file is text: a header line, then records
+//   scene <n>   followed by n lines of TOML: the scene and its live parameters, in order
+//   frame <index> <time> <camera position> <camera target> <mouse> <audio> <clock> <values...>
+// Floats are written in their shortest exact form, so replays reproduce the uniforms.
+use cgmath::{Vector2, Vector3};
+use metal_raymarcher::audio::AudioLevels;
+use metal_raymarcher::clock::ClockPhase;
+use metal_raymarcher::codegen::{self, Dialect, ParamLayout};
+use metal_raymarcher::{Camera, Scene};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+const HEADER: &str = "metal-raymarcher session 2";
+// Values of a frame record before the live parameters
+const FRAME_VALUES: usize = 18;
+
+// Everything that drives one frame
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub scene: usize, // Index of the scene snapshot it was drawn with
+    pub time: f32,
+    pub camera: Camera,
+    pub mouse: Vector2<f32>,
+    pub audio: AudioLevels,
+    pub clock: ClockPhase,
+    pub values: Vec<f32>, // Live parameters of the snapshot, concatenated
+}
+
+// The scene as the shader was generated from it, and the parameters that changed per frame
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    live: Vec<String>,
+    scene: Scene,
+}
+
+pub struct Recorder {
+    writer: BufWriter<File>,
+    frame: u64,
+    code: String, // Scene code of the last snapshot
+    live: Vec<String>,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> Result<Self, String> {
+        let file = File::create(path)
+            .map_err(|err| format!("Failed to create {}: {}", path.display(), err))?;
+        let mut writer = BufWriter::new(file);
+        writeln!(writer, "{}", HEADER).map_err(|err| err.to_string())?;
+        println!("Recording session to {}", path.display());
+        Ok(Self {
+            writer,
+            frame: 0,
+            code: String::new(),
+            live: Vec::new(),
+        })
+    }
+
+    pub fn record(
+        &mut self,
+        scene: &Scene,
+        time: f32,
+        camera: &Camera,
+        mouse: Vector2<f32>,
+        audio: &AudioLevels,
+        clock: &ClockPhase,
+    ) -> Result<(), String> {
+        let layout = ParamLayout::of(scene);
+        let code = codegen::scene_source_with(scene, Dialect::Wgsl, &layout);
+        if code != self.code {
+            self.snapshot(scene, &layout)?;
+            self.code = code;
+        }
+
+        let mut line = format!("frame {} {:?}", self.frame, time);
+        let values = [
+            camera.position.x,
+            camera.position.y,
+            camera.position.z,
+            camera.target.x,
+            camera.target.y,
+            camera.target.z,
+            mouse.x,
+            mouse.y,
+            audio.bass,
+            audio.mid,
+            audio.high,
+            audio.level,
+            audio.beat,
+            clock.beats,
+            clock.beat,
+            clock.bar,
+            clock.bpm,
+        ]
+        .into_iter()
+        .chain(
+            self.live
+                .iter()
+                .flat_map(|target| scene.parameter(target).unwrap_or(&[]).iter().copied()),
+        );
+        for value in values {
+            line.push_str(&format!(" {:?}", value));
+        }
+        writeln!(self.writer, "{}", line).map_err(|err| err.to_string())?;
+        self.frame += 1;
+        Ok(())
+    }
+
+    fn snapshot(&mut self, scene: &Scene, layout: &ParamLayout) -> Result<(), String> {
+        self.live = layout.targets().map(str::to_string).collect();
+        // Only the live parameters change during replay, so drop what drove them
+        let scene = Scene {
+            animations: Vec::new(),
+            orbits: Vec::new(),
+            expressions: BTreeMap::new(),
+            script: None,
+            cues: Vec::new(),
+            ..scene.clone()
+        };
+        let snapshot = Snapshot {
+            live: self.live.clone(),
+            scene,
+        };
+        let text = toml::to_string(&snapshot).map_err(|err| err.to_string())?;
+        writeln!(
+            self.writer,
+            "scene {}\n{}",
+            text.lines().count(),
+            text.trim_end()
+        )
+        .map_err(|err| err.to_string())
+    }
+}
+
+// A recorded session, loaded for replay
+pub struct Session {
+    pub scenes: Vec<Scene>, // Snapshots, with their live parameters marked as scripted
+    pub frames: Vec<Frame>,
+}
+
+impl Session {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let file = File::open(path)
+            .map_err(|err| format!("Failed to open {}: {}", path.display(), err))?;
+        let invalid = |line: usize, what: &str| {
+            format!("Invalid session {}:{}: {}", path.display(), line, what)
+        };
+        let mut lines = BufReader::new(file).lines().enumerate();
+        let mut next_line = || -> Result<Option<(usize, String)>, String> {
+            match lines.next() {
+                Some((number, Ok(line))) => Ok(Some((number + 1, line))),
+                Some((_, Err(err))) => Err(format!("Failed to read {}: {}", path.display(), err)),
+                None => Ok(None),
+            }
+        };
+        if next_line()?.map(|(_, line)| line).as_deref() != Some(HEADER) {
+            return Err(format!("{} is not a recorded session", path.display()));
+        }
+
+        let mut session = Session {
+            scenes: Vec::new(),
+            frames: Vec::new(),
+        };
+        let mut live_len = 0; // Values per frame for the current snapshot
+        while let Some((number, line)) = next_line()? {
+            let mut fields = line.split(' ');
+            match fields.next() {
+                Some("scene") => {
+                    let count: usize = fields
+                        .next()
+                        .and_then(|count| count.parse().ok())
+                        .ok_or_else(|| invalid(number, "bad scene record"))?;
+                    let mut text = String::new();
+                    for _ in 0..count {
+                        let (_, line) =
+                            next_line()?.ok_or_else(|| invalid(number, "truncated scene"))?;
+                        text.push_str(&line);
+                        text.push('\n');
+                    }
+                    let Snapshot { live, mut scene } =
+                        toml::from_str(&text).map_err(|err| invalid(number, &err.to_string()))?;
+                    scene.validate().map_err(|err| invalid(number, &err))?;
+                    live_len = live
+                        .iter()
+                        .map(|target| scene.parameter(target).map_or(0, <[f32]>::len))
+                        .sum();
+                    scene.scripted = live;
+                    session.scenes.push(scene);
+                }
+                Some("frame") => {
+                    let values: Vec<f32> = fields
+                        .skip(1) // Frame index
+                        .map(str::parse)
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| invalid(number, "bad number"))?;
+                    if session.scenes.is_empty() || values.len() != FRAME_VALUES + live_len {
+                        return Err(invalid(number, "frame does not match its scene"));
+                    }
+                    let vector = |i: usize| Vector3::new(values[i], values[i + 1], values[i + 2]);
+                    session.frames.push(Frame {
+                        scene: session.scenes.len() - 1,
+                        time: values[0],
+                        camera: Camera {
+                            position: vector(1),
+                            target: vector(4),
+                            ..Camera::default()
+                        },
+                        mouse: Vector2::new(values[7], values[8]),
+                        audio: AudioLevels {
+                            bass: values[9],
+                            mid: values[10],
+                            high: values[11],
+                            level: values[12],
+                            beat: values[13],
+                        },
+                        clock: ClockPhase {
+                            beats: values[14],
+                            beat: values[15],
+                            bar: values[16],
+                            bpm: values[17],
+                        },
+                        values: values[FRAME_VALUES..].to_vec(),
+                    });
+                }
+                _ => return Err(invalid(number, "unknown record")),
+            }
+        }
+        if session.frames.is_empty() {
+            return Err(format!("Session {} has no frames", path.display()));
+        }
+        Ok(session)
+    }
+}
+
+// Playback position in a session
+pub struct Replay {
+    pub session: Session,
+    scene: usize, // Snapshot the app's scene currently comes from
+}
+
+impl Replay {
+    // Start at the first snapshot, which the app should be created with
+    pub fn new(session: Session) -> Self {
+        Self { session, scene: 0 }
+    }
+
+    pub fn first_scene(&self) -> &Scene {
+        &self.session.scenes[0]
+    }
+
+    // Bring `scene` to the last recorded frame at or before `time` and return that frame,
+    // with whether the scene's structure changed (the shader must be regenerated)
+    pub fn seek(&mut self, time: f32, scene: &mut Scene) -> (&Frame, bool) {
+        let frames = &self.session.frames;
+        let frame = &frames[frames
+            .partition_point(|frame| frame.time <= time)
+            .saturating_sub(1)];
+        let rebuild = frame.scene != self.scene;
+        if rebuild {
+            *scene = self.session.scenes[frame.scene].clone();
+            self.scene = frame.scene;
+        }
+
+        let mut values = frame.values.iter().copied();
+        for target in &self.session.scenes[frame.scene].scripted {
+            if let Some(parameter) = scene.parameter_mut(target) {
+                for (value, recorded) in parameter.iter_mut().zip(&mut values) {
+                    *value = recorded;
+                }
+            }
+        }
+        (frame, rebuild)
+    }
+}
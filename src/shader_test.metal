@@ -0,0 +1,19 @@
+// Shader test kernel (see shader_test.rs), appended to the built-in shader: at each probe
+// point, the scene's distance and object, its normal, and value noise, Perlin noise and the
+// hash of the probe's index
+
+kernel void shader_test_main(constant Uniforms& uniforms [[buffer(0)]],
+                             device const float4* probes [[buffer(1)]],
+                             device float4* results [[buffer(2)]],
+                             constant uint& count [[buffer(3)]],
+                             texture2d<float> noiseTex [[texture(4)]],
+                             uint i [[thread_position_in_grid]]) {
+    if (i >= count) {
+        return;
+    }
+    float3 p = probes[i].xyz;
+    results[i * 3] = float4(sceneMap(p, uniforms), 0.0, 0.0);
+    results[i * 3 + 1] = float4(calcNormal(p, uniforms), 0.0);
+    results[i * 3 + 2] = float4(valueNoise(p, noiseTex), perlinNoise(p, noiseTex),
+                                float(pcg(i) >> 8), 0.0);
+}
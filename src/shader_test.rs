@@ -0,0 +1,190 @@
+// Shader test harness (--test-shaders): a small compute kernel (shader_test.metal or
+// shader_test.wgsl, appended to the built-in shader) evaluates the scene's distance
+// function, its normals and the noise at a grid of points on the GPU, and the results are
+// compared with what the CPU makes of the same points: picking.rs's distance and normal,
+// NoiseTable's value and Perlin noise, and the path tracer's hash. A difference means the
+// Rust mirror and the shader have drifted apart.
+use crate::noise::{self, NoiseTable};
+use crate::picking;
+use crate::scene::Scene;
+use cgmath::{InnerSpace, Vector3};
+#[cfg(any(target_os = "macos", target_os = "ios", feature = "wgpu"))]
+use {
+    crate::camera::Camera,
+    crate::quality::QualityConfig,
+    crate::uniforms::{FrameInputs, SceneInputs, Uniforms},
+    cgmath::Vector2,
+};
+
+pub const MSL_KERNEL: &str = include_str!("shader_test.metal");
+pub const WGSL_KERNEL: &str = include_str!("shader_test.wgsl");
+
+// Results the kernel writes per probe point: distance and object id, normal, and value
+// noise, Perlin noise and the hash of the probe's index
+pub const RESULTS_PER_PROBE: usize = 3;
+
+// Points along each side of the grid, and half the side of the cube it fills
+const GRID: usize = 12;
+const EXTENT: f32 = 6.0;
+
+// Largest differences let through: distances relative to their size (at least 1), normals
+// as one minus the cosine between them, noise absolutely
+const DISTANCE_TOLERANCE: f32 = 1e-3;
+const NORMAL_TOLERANCE: f32 = 1e-3;
+const NOISE_TOLERANCE: f32 = 1e-3;
+
+// Differences reported per check before the rest are only counted
+const MAX_REPORTED: usize = 5;
+
+// The grid of points the kernel is run at (w unused). It is shifted off the axes so points
+// don't sit exactly between mirrored objects, where either could be the nearest.
+pub fn probes() -> Vec<[f32; 4]> {
+    let step = 2.0 * EXTENT / GRID as f32;
+    let coordinate = |i: usize, shift: f32| -EXTENT + (i as f32 + shift) * step;
+    let mut probes = Vec::with_capacity(GRID * GRID * GRID);
+    for z in 0..GRID {
+        for y in 0..GRID {
+            for x in 0..GRID {
+                probes.push([
+                    coordinate(x, 0.37),
+                    coordinate(y, 0.41),
+                    coordinate(z, 0.53),
+                    0.0,
+                ]);
+            }
+        }
+    }
+    probes
+}
+
+// Uniforms for the kernel: the scene's parameters at their current values, the rest at rest
+#[cfg(any(target_os = "macos", target_os = "ios", feature = "wgpu"))]
+pub(crate) fn uniforms(scene: &Scene) -> Uniforms {
+    let frame = FrameInputs {
+        resolution: [1, 1],
+        time: 0.0,
+        mouse: Vector2::new(0.5, 0.5),
+        video_mix: 0.0,
+        screen_mix: 0.0,
+        audio: Default::default(),
+        clock: Default::default(),
+        selected: None,
+        guides: false,
+        samples: 0,
+        toon: None,
+        edges: Default::default(),
+        shafts: None,
+        adaptive: None,
+        seed: 0,
+    };
+    Uniforms::new(
+        &frame,
+        &Camera::default(),
+        &QualityConfig::default(),
+        &SceneInputs::of(scene),
+    )
+}
+
+// Differences between the kernel's `results` at `probes` and the CPU's, one line per
+// difference (empty when they agree)
+pub fn compare(scene: &Scene, probes: &[[f32; 4]], results: &[[f32; 4]]) -> Vec<String> {
+    if results.len() != probes.len() * RESULTS_PER_PROBE {
+        return vec![format!(
+            "{} results for {} probes",
+            results.len(),
+            probes.len()
+        )];
+    }
+    let table = NoiseTable::of(scene, 0);
+    let floor_id = scene.objects().len();
+    let mut checks: [(&str, Vec<String>); 6] = [
+        ("distance", Vec::new()),
+        ("object", Vec::new()),
+        ("normal", Vec::new()),
+        ("value noise", Vec::new()),
+        ("Perlin noise", Vec::new()),
+        ("hash", Vec::new()),
+    ];
+
+    for (index, (probe, result)) in probes
+        .iter()
+        .zip(results.chunks_exact(RESULTS_PER_PROBE))
+        .enumerate()
+    {
+        let p = Vector3::new(probe[0], probe[1], probe[2]);
+        let at = format!("({:.2}, {:.2}, {:.2})", p.x, p.y, p.z);
+        let [distance, id, ..] = result[0];
+        let (cpu_distance, cpu_id) = picking::distance(scene, p);
+
+        if (distance - cpu_distance).abs() > DISTANCE_TOLERANCE * cpu_distance.abs().max(1.0) {
+            checks[0].1.push(format!(
+                "{}: {} on the GPU, {} on the CPU",
+                at, distance, cpu_distance
+            ));
+        } else if let Some(cpu_id) = cpu_id.or(scene.ground().map(|_| floor_id)) {
+            // Only where the distances agree: the nearest object is meaningless otherwise
+            if (id - cpu_id as f32).abs() > 0.5 {
+                checks[1]
+                    .1
+                    .push(format!("{}: {} on the GPU, {} on the CPU", at, id, cpu_id));
+            }
+        }
+
+        // Only where the field is smooth: near a crease or a point equally far from two
+        // surfaces, a small step changes the direction and either side's answer is fair
+        let normal = Vector3::new(result[1][0], result[1][1], result[1][2]);
+        let cpu_normal = picking::normal(scene, p);
+        if smooth(scene, p, cpu_normal) && 1.0 - normal.dot(cpu_normal) > NORMAL_TOLERANCE {
+            checks[2].1.push(format!(
+                "{}: {:?} on the GPU, {:?} on the CPU",
+                at, normal, cpu_normal
+            ));
+        }
+
+        let [value, perlin, hash, _] = result[2];
+        let expected = [
+            table.value(p),
+            table.perlin(p),
+            (noise::pcg(index as u32) >> 8) as f32,
+        ];
+        for (check, (got, expected)) in checks[3..]
+            .iter_mut()
+            .zip([value, perlin, hash].iter().zip(expected))
+        {
+            if (got - expected).abs() > NOISE_TOLERANCE {
+                check.1.push(format!(
+                    "{}: {} on the GPU, {} on the CPU",
+                    at, got, expected
+                ));
+            }
+        }
+    }
+
+    let mut report = Vec::new();
+    for (name, differences) in checks {
+        for difference in differences.iter().take(MAX_REPORTED) {
+            report.push(format!("{} at {}", name, difference));
+        }
+        if differences.len() > MAX_REPORTED {
+            report.push(format!(
+                "{}: {} more differences",
+                name,
+                differences.len() - MAX_REPORTED
+            ));
+        }
+    }
+    report
+}
+
+// Whether the distance field's direction at `p` holds still over a few times the normal's
+// step, i.e. `normal` isn't taken across a crease
+fn smooth(scene: &Scene, p: Vector3<f32>, normal: Vector3<f32>) -> bool {
+    let e = 0.01;
+    let d = |x: f32, y: f32, z: f32| picking::distance(scene, p + Vector3::new(x, y, z)).0;
+    let wide = Vector3::new(
+        d(e, 0.0, 0.0) - d(-e, 0.0, 0.0),
+        d(0.0, e, 0.0) - d(0.0, -e, 0.0),
+        d(0.0, 0.0, e) - d(0.0, 0.0, -e),
+    );
+    wide.magnitude() > 0.0 && 1.0 - wide.normalize().dot(normal) < NORMAL_TOLERANCE * 0.1
+}
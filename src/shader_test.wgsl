@@ -0,0 +1,18 @@
+// Shader test kernel (see shader_test.rs), appended to the built-in shader: at each probe
+// point, the scene's distance and object, its normal, and value noise, Perlin noise and the
+// hash of the probe's index
+
+@group(1) @binding(0) var<storage, read> probes: array<vec4<f32>>;
+@group(1) @binding(1) var<storage, read_write> results: array<vec4<f32>>;
+
+@compute @workgroup_size(64)
+fn shader_test_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if (i >= arrayLength(&probes)) {
+        return;
+    }
+    let p = probes[i].xyz;
+    results[i * 3u] = vec4<f32>(sceneMap(p), 0.0, 0.0);
+    results[i * 3u + 1u] = vec4<f32>(calcNormal(p), 0.0);
+    results[i * 3u + 2u] = vec4<f32>(valueNoise(p), perlinNoise(p), f32(pcg(i) >> 8u), 0.0);
+}
@@ -15,9 +15,83 @@ struct Uniforms {
     float time;
     float2 mouse;
     float3 camera_pos;
-    float _padding;
+    float video_mix; // 0 = no video, 1 = video replaces the color of textured objects
+    float screen_mix; // 0 = no screen capture, 1 = textured objects fully refract the captured screen
+    uint max_steps; // March iterations per ray
+    float epsilon; // Hit threshold
+    float max_distance; // Give up (sky) beyond this distance
+    uint shadow_steps; // Soft shadow iterations (0 = off)
+    uint ao_samples; // Ambient occlusion samples (0 = off)
+    uint aa; // Supersampling grid per axis
+    float3 camera_target; // Point the camera looks at
+    packed_float3 light_color; // Packed so fog_density shares its 16 bytes, as in WGSL
+    float fog_density; // 0 = no fog
+    float4 params[16]; // Animated scene parameters, read by the generated scene code
+    float4 audio; // Audio input: bass, mid, high, overall level (0..1)
+    float beat; // 1 on a detected beat, decaying to 0
+    float selected; // Object id picked with the mouse (-1 for none)
+    float guides; // 1 draws the ground grid and axes
+    float samples; // Path tracing: paths per pixel averaged so far
+    float4 clock; // Musical clock: beats since the start, beat phase, bar phase, bpm
+    uint projection; // 0 pinhole, 1 fisheye, 2 equisolid fisheye, 3 Panini, 4 cylindrical
+    float lens_amount; // 0 looks like the pinhole view, 1 is the full projection
+    uint stereo; // 0 off, 1 red/cyan anaglyph, 2 side by side
+    float ipd; // Distance between the eyes
+    float convergence; // Distance that shows with no parallax (at the screen)
+    uint toon_bands; // Toon shading's lit steps, 0 for standard shading
+    float toon_rim; // Toon rim light brightness
+    float toon_outline; // Toon ink width in pixels
+    packed_float3 edge_color; // Edge post pass: line color (packed, as in WGSL)
+    uint edge_mode; // 0 off, 1 over the image, 2 blueprint
+    packed_float3 edge_paper; // Blueprint background
+    float edge_width; // Line width in pixels
+    float edge_depth; // Sobel response to distance (relative to it) that makes a line
+    float edge_normal; // Sobel response to the normal that makes a line
+    float4 fog_color; // w 1 fogs towards this color, 0 towards the sky's
+    float height_fog; // Height fog density at fog_height (0 = none)
+    float fog_height;
+    float fog_falloff; // How fast height fog thins with height
+    float fog_sun; // Sunlight scattered by the fog towards the eye
+    uint shaft_samples; // Light shaft march steps (0 = no shafts)
+    float shaft_intensity;
+    float caustic_intensity; // Fake caustics under glass (0 = none)
+    float caustic_sharpness;
+    packed_float3 floor_color; // The floor's (see Floor in scene.rs), or its first squares
+    float floor_checker; // Square width, 0 for one color
+    packed_float3 floor_color2;
+    float floor_reflection; // Share of the scene mirrored looking straight down
+    float floor_contact; // Height over which things darken the floor below them, 0 for none
+    uint seed; // Offsets the path tracer's random numbers and the light shafts' jitter
+    uint tile_size; // Adaptive quality's tile side in pixels (0 = off; aa is for its detailed tiles)
+    float4 region; // Part of the view drawn: x, y (from the bottom left), width, height
+    float4 sites[64]; // Voronoi cell sites of objects with cells (xyz), in object order
+    packed_float3 volume_center; // Volume dataset's box (see volume.rs), packed as in WGSL
+    uint volume_mode; // 0 composite, 1 iso-surface
+    packed_float3 volume_size; // Half the box's sides
+    float volume_iso; // Value the iso-surface is drawn at
+    float2 volume_window; // Data values mapped to 0 and 1
+    float volume_density; // Opacity per unit of distance at full opacity
+    uint volume_steps; // Samples along the box's diagonal (0 = no volume)
+    float3 sun_dir; // Towards the sun, normalized (see sun.rs)
 };
 
+constexpr sampler videoSampler(filter::linear, address::repeat);
+constexpr sampler screenSampler(filter::linear, address::clamp_to_edge);
+constexpr sampler normalSampler(filter::linear, address::repeat);
+constexpr sampler volumeSampler(filter::linear, address::clamp_to_edge);
+
+// Captured screen as a backdrop facing the camera, looked up by ray direction
+float3 screenBackdrop(float3 dir, constant Uniforms& uniforms, texture2d<float> screenTex) {
+    float3 forward = normalize(uniforms.camera_target - uniforms.camera_pos);
+    float3 right = normalize(cross(float3(0.0, 1.0, 0.0), forward));
+    float3 up = cross(forward, right);
+
+    float depth = max(dot(dir, forward), 0.05);
+    float aspect = float(screenTex.get_width()) / float(max(screenTex.get_height(), 1u));
+    float2 uv = float2(dot(dir, right) / aspect, -dot(dir, up)) / depth * 0.5 + 0.5;
+    return screenTex.sample(screenSampler, uv).rgb;
+}
+
 vertex VertexOut vertex_main(uint vertexID [[vertex_id]],
                             constant float2* vertices [[buffer(0)]]) {
     VertexOut out;
@@ -42,21 +116,59 @@ float sdPlane(float3 p, float3 n, float h) {
     return dot(p, n) + h;
 }
 
+float sdCapsule(float3 p, float3 a, float3 b, float r) {
+    float3 pa = p - a, ba = b - a;
+    float h = clamp(dot(pa, ba) / max(dot(ba, ba), 1e-8), 0.0, 1.0);
+    return length(pa - ba * h) - r;
+}
+
 // Smooth minimum for blending objects
 float smin(float a, float b, float k) {
     float h = clamp(0.5 + 0.5*(b-a)/k, 0.0, 1.0);
     return mix(b, a, h) - k*h*(1.0-h);
 }
 
+// CSG operations on (distance, object id) pairs
+float2 opUnion(float2 a, float2 b) {
+    return a.x < b.x ? a : b;
+}
+
+float2 opSmoothUnion(float2 a, float2 b, float k) {
+    return float2(smin(a.x, b.x, k), a.x < b.x ? a.y : b.y);
+}
+
+float2 opSubtract(float2 a, float2 b) {
+    return float2(max(a.x, -b.x), a.y);
+}
+
+float2 opSmoothSubtract(float2 a, float2 b, float k) {
+    float h = clamp(0.5 - 0.5*(a.x+b.x)/k, 0.0, 1.0);
+    return float2(mix(a.x, -b.x, h) + k*h*(1.0-h), a.y);
+}
+
+float2 opIntersect(float2 a, float2 b) {
+    return a.x > b.x ? a : b;
+}
+
+// Diffuse light a point light gives a surface at p (normal n), falling off with distance
+float3 pointLight(float3 p, float3 n, float3 position, float3 color, float intensity) {
+    float3 l = position - p;
+    float d2 = max(dot(l, l), 1e-6);
+    return color * intensity * max(dot(n, l * rsqrt(d2)), 0.0) / (1.0 + d2);
+}
+
+// Noise functions (valueNoise, perlinNoise, simplexNoise, fbm, curlNoise; see noise.metal),
+// taking the noise table as their last argument
+// @noise@
+
+// Scene functions (sceneMap(p, uniforms, cull), sceneObject(p, id, uniforms),
+// sceneColor(id, uniforms), sceneLight(p, n, uniforms), sceneTextured(id)) generated from the
+// scene description
+// @scene@
+
 // Scene SDF
 float sceneSDF(float3 p, constant Uniforms& uniforms) {
-    // Sphere at origin (0,0,0) with new radius 1.5
-    float sphere_radius = 1.5; // Define the radius
-    float sphere = sdSphere(p - float3(0,0,0), sphere_radius); 
-
-    // Ground plane
-    float plane = sdPlane(p, float3(0.0, 1.0, 0.0), 2.0); 
-    return min(sphere, plane);
+    return sceneMap(p, uniforms).x;
 }
 
 // Calculate normal at a point
@@ -71,13 +183,97 @@ float3 calcNormal(float3 p, constant Uniforms& uniforms) {
     ));
 }
 
+// Tangent-space normal map detail on top of normal n at p, with bump = (intensity, tiling)
+// from sceneBump: the map is sampled on the planes facing the x, y and z axes and each sample
+// is blended onto n (whiteout blend), weighted by how squarely n faces that axis
+float3 bumpNormal(float3 p, float3 n, float2 bump, texture2d<float> normalTex) {
+    if (bump.x == 0.0) {
+        return n;
+    }
+    float3 q = p * bump.y;
+    float3 weights = pow(abs(n), float3(4.0));
+    weights /= weights.x + weights.y + weights.z;
+    float3 tx = normalTex.sample(normalSampler, q.zy, level(0.0)).xyz * 2.0 - 1.0;
+    float3 ty = normalTex.sample(normalSampler, q.xz, level(0.0)).xyz * 2.0 - 1.0;
+    float3 tz = normalTex.sample(normalSampler, q.xy, level(0.0)).xyz * 2.0 - 1.0;
+    tx = float3(tx.xy * bump.x + n.zy, abs(tx.z) * n.x);
+    ty = float3(ty.xy * bump.x + n.xz, abs(ty.z) * n.y);
+    tz = float3(tz.xy * bump.x + n.xy, abs(tz.z) * n.z);
+    return normalize(tx.zyx * weights.x + ty.xzy * weights.y + tz * weights.z);
+}
+
+// Color varied by the shared noise at p, with noise = (amount, scale, kind) from sceneNoise;
+// kind indexes NoiseKind (see scene.rs)
+float3 noiseColor(float3 p, float3 color, float3 noise, texture2d<float> noiseTex) {
+    if (noise.x == 0.0) {
+        return color;
+    }
+    float3 q = p * noise.y;
+    int kind = int(noise.z);
+    float3 n;
+    if (kind == 0) {
+        n = float3(valueNoise(q, noiseTex));
+    } else if (kind == 1) {
+        n = float3(perlinNoise(q, noiseTex));
+    } else if (kind == 2) {
+        n = float3(simplexNoise(q, noiseTex));
+    } else if (kind == 3) {
+        n = float3(fbm(q, 5, noiseTex));
+    } else {
+        n = clamp(curlNoise(q, noiseTex) * 0.25, float3(-1.0), float3(1.0));
+    }
+    return color * max(1.0 + noise.x * n, float3(0.0));
+}
+
+// Thin-film interference (see Iridescence in scene.rs): the share of red, green and blue
+// light a film of film.x nanometres (swung by up to film.z with the noise) reflects when seen
+// at `cosine` to its normal. Light off the film's bottom travels further, through the film,
+// and comes back half a wave out from the reflection off its top, so each wavelength is
+// reflected most where the delay is an odd number of half waves; a film much thinner than the
+// light's wavelengths looks dark.
+constant float FILM_IOR = 1.33; // Soapy water
+
+float3 thinFilm(float3 p, float cosine, float4 film, texture2d<float> noiseTex) {
+    float thickness = film.x;
+    if (film.z != 0.0) {
+        thickness += film.z * fbm(p * film.w, 4, noiseTex);
+    }
+    float inside = sqrt(max(1.0 - (1.0 - cosine * cosine) / (FILM_IOR * FILM_IOR), 0.0));
+    float delay = 2.0 * FILM_IOR * max(thickness, 0.0) * inside;
+    return 0.5 - 0.5 * cos(2.0 * M_PI_F * delay / float3(650.0, 532.0, 450.0));
+}
+
+// Color of an opaque object with its film's colors mixed in by the film's amount
+float3 iridescent(float3 color, float3 p, float cosine, float4 film, texture2d<float> noiseTex) {
+    if (film.y == 0.0) {
+        return color;
+    }
+    return mix(color, thinFilm(p, cosine, film, noiseTex), film.y);
+}
+
+// Weights of the light reflected off glass with a film, by color: as much as without it on
+// average
+float3 filmReflection(float3 p, float cosine, float4 film, texture2d<float> noiseTex) {
+    if (film.y == 0.0) {
+        return float3(1.0);
+    }
+    return mix(float3(1.0), 2.0 * thinFilm(p, cosine, film, noiseTex), film.y);
+}
+
+// Distance to the nearest surface that casts a shadow. Glass lets light through (untinted:
+// shadow rays don't refract), so it reads as far away and the ray steps on through it.
+float shadowSDF(float3 p, constant Uniforms& uniforms) {
+    float2 hit = sceneMap(p, uniforms);
+    return sceneGlass(hit.y, uniforms).x > 0.0 ? 1e10 : hit.x;
+}
+
 // Soft shadow calculation
 float softShadow(float3 ro, float3 rd, float mint, float maxt, constant Uniforms& uniforms) {
     float res = 1.0;
     float t = mint;
     
-    for(int i = 0; i < 16; i++) {
-        float h = sceneSDF(ro + rd * t, uniforms);
+    for(uint i = 0; i < uniforms.shadow_steps; i++) {
+        float h = shadowSDF(ro + rd * t, uniforms);
         res = min(res, 8.0 * h / t);
         t += clamp(h, 0.02, 0.10);
         if(h < 0.001 || t > maxt) break;
@@ -86,12 +282,35 @@ float softShadow(float3 ro, float3 rd, float mint, float maxt, constant Uniforms
     return clamp(res, 0.0, 1.0);
 }
 
+// Share (0-1) of a cone of `radius` covered by a surface `d` from its axis, taking the surface
+// as flat across the cone: half where the axis grazes it
+float coverage(float d, float radius) {
+    return clamp(0.5 - 0.5 * d / radius, 0.0, 1.0);
+}
+
+// Soft shadow for cone tracing: the share of the sun's disc (SUN_RADIUS across per unit of
+// distance) left uncovered by the surfaces the shadow ray passes
+constant float SUN_RADIUS = 0.1;
+
+float coneShadow(float3 ro, float3 rd, float mint, float maxt, constant Uniforms& uniforms) {
+    float visible = 1.0;
+    float t = mint;
+    for (uint i = 0; i < uniforms.shadow_steps; i++) {
+        float h = shadowSDF(ro + rd * t, uniforms);
+        visible = min(visible, 1.0 - coverage(h, t * SUN_RADIUS));
+        t += clamp(h, 0.02, 0.10);
+        if (visible < 0.001 || t > maxt) break;
+    }
+    return visible;
+}
+
 // Ambient occlusion
 float calcAO(float3 pos, float3 nor, constant Uniforms& uniforms) {
     float occ = 0.0;
     float sca = 1.0;
-    for(int i = 0; i < 5; i++) {
-        float hr = 0.01 + 0.12 * float(i) / 4.0;
+    float last = float(max(uniforms.ao_samples, 2u) - 1);
+    for(uint i = 0; i < uniforms.ao_samples; i++) {
+        float hr = 0.01 + 0.12 * float(i) / last;
         float3 aopos = nor * hr + pos;
         float dd = sceneSDF(aopos, uniforms);
         occ += -(dd - hr) * sca;
@@ -100,81 +319,1383 @@ float calcAO(float3 pos, float3 nor, constant Uniforms& uniforms) {
     return clamp(1.0 - 3.0 * occ, 0.0, 1.0);
 }
 
+// Sky gradient, blended with the captured screen
+float3 skyColor(float3 rd, constant Uniforms& uniforms, texture2d<float> screenTex) {
+    float y_coord = rd.y * 0.5 + 0.5;
+    float3 sky = mix(float3(0.2, 0.3, 0.5), float3(0.7, 0.8, 0.9), y_coord);
+    if (uniforms.screen_mix > 0.0) {
+        sky = mix(sky, screenBackdrop(rd, uniforms, screenTex), uniforms.screen_mix);
+    }
+    return sky;
+}
+
+// Height fog density at height y, capped where it would overflow far below the fog
+float heightFogDensity(float y, constant Uniforms& uniforms) {
+    return uniforms.height_fog * exp(min(-uniforms.fog_falloff * (y - uniforms.fog_height), 80.0));
+}
+
+// `color` seen through the fog between ro and t along rd: exponential distance fog plus height
+// fog, its density integrated along the ray in closed form, towards the fog color (the sky's
+// by default) brightened towards the sun by the light it scatters
+float3 fogged(float3 color, float3 ro, float3 rd, float t, constant Uniforms& uniforms,
+              texture2d<float> screenTex) {
+    float depth = uniforms.fog_density * t;
+    if (uniforms.height_fog > 0.0) {
+        float start = heightFogDensity(ro.y, uniforms);
+        float rate = uniforms.fog_falloff * rd.y;
+        if (abs(rate) < 1e-4) {
+            depth += start * t;
+        } else {
+            depth += (start - heightFogDensity(ro.y + rd.y * t, uniforms)) / rate;
+        }
+    }
+    if (depth <= 0.0) {
+        return color;
+    }
+    float3 tint = uniforms.fog_color.w > 0.5 ? uniforms.fog_color.rgb
+                                             : skyColor(rd, uniforms, screenTex);
+    float sun = max(dot(rd, uniforms.sun_dir), 0.0);
+    tint += float3(uniforms.light_color) * uniforms.fog_sun * pow(sun, 8.0);
+    return mix(color, tint, 1.0 - exp(-depth));
+}
+
+// Lines of the ground grid (1 unit apart on y = 0) and the X, Y and Z axes, drawn over `color`
+// where they are in front of the surface the ray hit at `depth` and fading with distance. Line
+// widths follow the pixel's footprint, so they stay about a pixel wide and antialiased.
+float3 withGuides(float3 color, float3 ro, float3 rd, float depth, constant Uniforms& uniforms) {
+    if (uniforms.guides < 0.5) {
+        return color;
+    }
+    float pixel = 1.5 * uniforms.region.w / uniforms.resolution.y; // Over a pixel and a half
+    float limit = depth * 1.001 + 0.01;        // Still drawn on a floor at y = 0
+
+    // Grid and the X (red) and Z (blue) axes where the ray crosses the ground
+    float t = -ro.y / rd.y;
+    if (t > 0.0 && t < limit) {
+        float2 q = (ro + rd * t).xz;
+        float width = t * pixel / max(abs(rd.y), 0.1);
+        float fade = (1.0 - smoothstep(10.0, 40.0, t)) * (1.0 - smoothstep(0.1, 0.4, width));
+        float2 g = abs(fract(q - 0.5) - 0.5);
+        float grid = 1.0 - smoothstep(0.0, width, min(g.x, g.y));
+        color = mix(color, float3(0.9), grid * fade * 0.35);
+        float axisFade = 1.0 - smoothstep(20.0, 80.0, t);
+        color = mix(color, float3(0.9, 0.2, 0.2), (1.0 - smoothstep(0.0, width * 2.0, abs(q.y))) * axisFade);
+        color = mix(color, float3(0.2, 0.4, 1.0), (1.0 - smoothstep(0.0, width * 2.0, abs(q.x))) * axisFade);
+    }
+
+    // Y axis (green): where the ray passes closest to the vertical line through the origin
+    float2 across = rd.xz;
+    if (dot(across, across) > 1e-8) {
+        float s = -dot(ro.xz, across) / dot(across, across);
+        if (s > 0.0 && s < limit) {
+            float d = length(ro.xz + across * s);
+            float width = s * pixel * 2.0;
+            color = mix(color, float3(0.3, 0.85, 0.2), 1.0 - smoothstep(0.0, width, d));
+        }
+    }
+    return color;
+}
+
 // Ray marching
-float3 rayMarch(float3 ro, float3 rd, constant Uniforms& uniforms) {
+// Portals: a ray reaching one carries on from the matching point of its twin, keeping the
+// distance travelled, up to PORTAL_JUMPS times (after that portals are passed like air).
+// Steps are cut short at the next portal, so a ray never steps into what is behind it.
+constant uint PORTAL_JUMPS = 4;
+
+// Where a ray ends up: the point it hit and the direction it got there in (turned by any
+// portals on the way), the distance travelled and the object's id. t is -1 for no hit, with
+// rd the direction it left the scene in.
+struct Hit {
+    float3 p;
+    float3 rd;
+    float t;
+    float id;
+};
+
+// First surface along the ray. A `shadow` ray passes through glass, as light does, and
+// through portals without being taken anywhere: lights shine in plain space. `cull` leaves
+// top-level nodes out of sceneMap (see culled_main) until a portal takes the ray elsewhere.
+Hit traceRay(float3 ro, float3 rd, bool shadow, constant Uniforms& uniforms,
+             uint cull = 0xffffffff) {
+    float base = 0.0; // Travelled up to ro
+    uint jumps = 0;
+    float2 portal = shadow ? float2(1e10, -1.0) : scenePortal(ro, rd);
     float t = 0.0;
-    
-    for(int i = 0; i < 100; i++) {
-        float3 p = ro + rd * t;
-        float d = sceneSDF(p, uniforms); // d is min(distance_to_sphere, distance_to_plane)
-        
-        if(d < 0.001) { // Hit condition
-            // We've hit *something*. Now, figure out what.
-            // Re-evaluate individual SDFs at the hit point 'p'
-            float sphere_dist_at_p = sdSphere(p - float3(0,0,0), 1.0);
-            float plane_dist_at_p = sdPlane(p, float3(0.0, 1.0, 0.0), 2.0);
-
-            float3 objectColor;
-            float3 normal_at_p;
-
-            // Determine which object is closer at point p
-            // (and thus is the one we actually hit)
-            if (sphere_dist_at_p < plane_dist_at_p) {
-                objectColor = float3(0.0, 0.8, 0.2); // Bright Green for sphere
-                // Calculate normal specifically for the sphere for better accuracy
-                // For a simple sphere at origin:
-                normal_at_p = normalize(p - float3(0,0,0));
-            } else {
-                objectColor = float3(1.0, 0.5, 0.0); // Orange for plane
-                // Normal for the plane is constant
-                normal_at_p = float3(0.0, 1.0, 0.0);
+    for (uint i = 0; i < uniforms.max_steps; i++) {
+        if (t >= portal.x) {
+            ro = portalPoint(ro + rd * portal.x, portal.y);
+            rd = portalDirection(rd, portal.y);
+            base += portal.x;
+            t = 0.0;
+            jumps++;
+            portal = jumps >= PORTAL_JUMPS ? float2(1e10, -1.0) : scenePortal(ro, rd);
+            cull = 0xffffffff;
+        }
+        float2 hit = sceneMap(ro + rd * t, uniforms, cull);
+        if (shadow && sceneGlass(hit.y, uniforms).x > 0.0) {
+            t += max(abs(hit.x), 0.02);
+            continue;
+        }
+        if (hit.x < uniforms.epsilon) {
+            return Hit{ro + rd * t, rd, base + t, hit.y};
+        }
+        if (base + t > uniforms.max_distance) {
+            break;
+        }
+        t = min(t + hit.x * 0.8, portal.x);
+    }
+    return Hit{ro, rd, -1.0, 0.0};
+}
+
+// Glass: light entering a glass object refracts, travels through it losing the share its
+// color doesn't pass (Beer-Lambert: the color to the power of density times the distance),
+// and refracts out where it reaches the surface again, or reflects back in where it meets it
+// too shallow to leave. The inside is where the scene's distance is negative, so an object
+// subtracted from the glass is a pocket of air: the ray leaves into it and enters again on
+// the far side. GLASS_EVENTS caps the surfaces passed on the way.
+constant uint GLASS_EVENTS = 8;
+
+// Share of light reflected where it crosses from index of refraction `a` to `b`, at `cosine`
+// to the normal on the lower index side (Schlick's approximation)
+float fresnel(float cosine, float a, float b) {
+    float r = (a - b) / (a + b);
+    return r * r + (1.0 - r * r) * pow(1.0 - clamp(cosine, 0.0, 1.0), 5.0);
+}
+
+// Where and in which direction a ray leaves the glass it meets at p (normal n), and the
+// share of light that makes it through
+struct Passage {
+    float3 p;
+    float3 rd;
+    float3 transmittance;
+};
+
+Passage throughGlass(float3 p, float3 rd, float3 n, float2 glass, float3 color,
+                     constant Uniforms& uniforms) {
+    float3 absorption = -log(max(color, float3(1e-3))) * glass.y;
+    float3 q = p - n * 0.01;
+    float3 dir = refract(rd, n, 1.0 / glass.x);
+    float3 transmittance = float3(1.0);
+    for (uint event = 0; event < GLASS_EVENTS; event++) {
+        float t = 0.0;
+        for (uint i = 0; i < uniforms.max_steps; i++) {
+            float d = -sceneSDF(q + dir * t, uniforms);
+            if (d < uniforms.epsilon || t > uniforms.max_distance) {
+                break;
             }
-            
-            // Basic lighting
-            float3 lightDir = normalize(float3(0.7, 0.7, -0.5)); // Adjusted light direction slightly
-            float diffuse = max(0.0, dot(normal_at_p, lightDir));
-            float3 ambient = float3(0.15, 0.15, 0.2); // Slightly brighter ambient
+            t += d * 0.8;
+        }
+        transmittance *= exp(-absorption * t);
+        q += dir * t;
+        float3 m = calcNormal(q, uniforms); // Outwards
+        float3 leaving = refract(dir, -m, glass.x);
+        if (dot(leaving, leaving) == 0.0) {
+            // Total internal reflection
+            dir = reflect(dir, m);
+            q -= m * 0.01;
+            continue;
+        }
+        transmittance *= 1.0 - fresnel(dot(leaving, m), glass.x, 1.0);
+        return Passage{q + m * 0.01, leaving, transmittance};
+    }
+    return Passage{q, dir, float3(0.0)};
+}
+
+// Fake caustics (see Caustics in scene.rs): how much more (or less) sunlight reaches p for
+// the glass the sun shines through on its way. Marching towards the sun, the ray steps on
+// through glass (as shadow rays do), measuring how much of it the light crosses: thicker glass
+// focuses more, and the effect fades out towards its rim. The net of lines is laid out across
+// the sun's beam from where the light left the glass, so it moves with the glass.
+constant float CAUSTIC_REACH = 10.0; // As far as shadows look
+constant float CAUSTIC_SCALE = 6.0; // Waves per unit
 
-            return ambient + objectColor * diffuse;
+// Four crossing wave trains, each bent by the ones before: their sum is zero along curves
+// that close into cells, which make the net of lines (0-1)
+float causticPattern(float2 q, constant Uniforms& uniforms) {
+    float2 at = q * CAUSTIC_SCALE;
+    float sum = 0.0;
+    for (uint k = 0; k < 4; k++) {
+        float angle = float(k) * 2.4 + 0.4;
+        float2 across = float2(cos(angle), sin(angle));
+        float wave = sin(dot(at, across) + uniforms.time * (0.6 + 0.25 * float(k)));
+        sum += wave;
+        at += 0.5 * wave * float2(-across.y, across.x);
+    }
+    return 1.0 - min(abs(sum), 1.0);
+}
+
+float3 caustics(float3 p, float3 sun, constant Uniforms& uniforms) {
+    float t = 0.02;
+    float thickness = 0.0;
+    float3 outlet = float3(0.0); // Where the light left the glass
+    float3 tint = float3(1.0);
+    for (uint i = 0; i < uniforms.max_steps; i++) {
+        float3 q = p + sun * t;
+        float2 hit = sceneMap(q, uniforms);
+        if (sceneGlass(hit.y, uniforms).x == 0.0) {
+            if (hit.x < uniforms.epsilon) {
+                return float3(0.0); // In shadow
+            }
+            t += hit.x;
+        } else {
+            float stride = max(abs(hit.x), 0.02);
+            if (hit.x < 0.0) {
+                if (thickness == 0.0) {
+                    outlet = q;
+                    tint = sceneColor(hit.y, uniforms);
+                }
+                thickness += stride;
+            }
+            t += stride;
         }
-        
-        if(t > 50.0) { // Max distance
+        if (t > CAUSTIC_REACH) {
             break;
         }
-        
-        t += d * 0.8; // Step conservatively
     }
-    
-    // Sky gradient if no hit
-    float y_coord = rd.y * 0.5 + 0.5;
-    return mix(float3(0.2, 0.3, 0.5), float3(0.7, 0.8, 0.9), y_coord);
+    float focus = 1.0 - exp(-2.0 * thickness);
+    float3 across = normalize(cross(sun, float3(0.0, 1.0, 0.0)));
+    float pattern =
+        causticPattern(float2(dot(outlet, across), dot(outlet, cross(across, sun))), uniforms);
+    // Light gathered into the lines is taken from between them
+    float3 gain = 3.0 * pow(pattern, uniforms.caustic_sharpness) * tint - 0.5;
+    return max(uniforms.caustic_intensity * focus * gain, float3(-1.0));
 }
 
-fragment float4 fragment_main(VertexOut in [[stage_in]],
-                            constant Uniforms& uniforms [[buffer(0)]]) {
-    // Debug: Show UV coordinates as colors
-    // return float4(in.uv.x, in.uv.y, 0.0, 1.0);
-    
-    // Calculate ray direction
-    float2 uv = (in.uv - 0.5) * 2.0;
-    uv.x *= uniforms.resolution.x / uniforms.resolution.y;
-    
-    // Camera setup
+// Toon shading: light stepped into toon_bands levels by its brightest channel, so each band
+// keeps the light's color
+float3 toonLight(float3 light, constant Uniforms& uniforms) {
+    float level = max(light.r, max(light.g, light.b));
+    float bands = float(uniforms.toon_bands);
+    return light * (ceil(level * bands) / bands) / max(level, 1e-4);
+}
+
+// Toon rim light: a hard-edged band where the surface turns away from the view, on the side
+// the sun doesn't reach (a back light)
+float3 toonRim(float3 n, float3 rd, constant Uniforms& uniforms) {
+    float edge = 1.0 - max(dot(n, -rd), 0.0);
+    return uniforms.toon_rim * smoothstep(0.8, 0.85, edge) * float3(uniforms.light_color);
+}
+
+// The sun's highlight on a specular object (see Specular in scene.rs) seen from v, to be
+// weighted by the light reaching p: an anisotropic GGX lobe whose roughness specular.y is
+// stretched across the tangent by anisotropy specular.z (along it when negative), with the
+// Kelemen visibility term and no Fresnel falloff, so intensity specular.x is the reflectance
+float highlight(float3 p, float3 n, float3 v, float3 l, float id, float4 specular,
+                constant Uniforms& uniforms) {
+    float3 tangent = sceneTangent(p, id, uniforms);
+    tangent -= n * dot(tangent, n);
+    if (dot(tangent, tangent) < 1e-8) {
+        // Brushed straight into the surface: any direction across it will do
+        tangent = cross(n, abs(n.x) > 0.9 ? float3(0.0, 1.0, 0.0) : float3(1.0, 0.0, 0.0));
+    }
+    tangent = normalize(tangent);
+    float3 bitangent = cross(n, tangent);
+    float3 h = normalize(l + v);
+    float alpha = max(specular.y * specular.y, 1e-3);
+    float along = max(alpha * (1.0 - specular.z), 1e-3); // Roughness along the tangent
+    float across = max(alpha * (1.0 + specular.z), 1e-3);
+    float area = along * across;
+    float3 d = float3(across * dot(tangent, h), along * dot(bitangent, h), area * dot(n, h));
+    float w = area / dot(d, d);
+    float distribution = area * w * w / M_PI_F;
+    float visibility = 0.25 / max(dot(l, h) * dot(l, h), 1e-4);
+    return specular.x * distribution * visibility;
+}
+
+// The floor's color at p: squares of floor_checker alternating between its two colors
+float3 floorColor(float3 p, constant Uniforms& uniforms) {
+    if (uniforms.floor_checker <= 0.0) {
+        return float3(uniforms.floor_color);
+    }
+    float2 cell = floor(p.xz / uniforms.floor_checker);
+    float odd = fmod(abs(cell.x + cell.y), 2.0);
+    return mix(float3(uniforms.floor_color), float3(uniforms.floor_color2), odd);
+}
+
+constant uint CONTACT_STEPS = 4;
+
+// Share of the open space above the floor at p left by whatever stands there, sampled up to
+// floor_contact: 1 in the open, towards 0 under and beside objects. The floor itself is never
+// closer than the height it is sampled at, so it does not darken itself.
+float contactShadow(float3 p, constant Uniforms& uniforms) {
+    float space = 0.0;
+    for (uint i = 1; i <= CONTACT_STEPS; i++) {
+        float h = uniforms.floor_contact * float(i) / float(CONTACT_STEPS);
+        space += clamp(sceneMap(p + float3(0.0, h, 0.0), uniforms).x / h, 0.0, 1.0);
+    }
+    return space / float(CONTACT_STEPS);
+}
+
+// Share of the scene the floor mirrors, seen at `cosine` to its normal: floor_reflection
+// looking straight down, rising towards all of it at grazing angles (Schlick's approximation)
+float floorMirror(float cosine, constant Uniforms& uniforms) {
+    float r = uniforms.floor_reflection;
+    if (r <= 0.0) {
+        return 0.0;
+    }
+    return r + (1.0 - r) * pow(1.0 - clamp(cosine, 0.0, 1.0), 5.0);
+}
+
+// Lit color of object `id` at p, seen along rd from t away
+float3 shade(float3 p, float id, float3 rd, float t, constant Uniforms& uniforms,
+             texture2d<float> videoTex, texture2d<float> screenTex,
+             texture2d<float> normalTex, texture2d<float> noiseTex) {
+    bool ground = sceneFloor(id);
+    float3 objectColor = ground ? floorColor(p, uniforms)
+        : noiseColor(p, sceneColorAt(p, id, sceneColor(id, uniforms), uniforms),
+                     sceneNoise(id, uniforms), noiseTex);
+    float3 normal_at_p =
+        bumpNormal(p, calcNormal(p, uniforms), sceneBump(id, uniforms), normalTex);
+    objectColor = iridescent(objectColor, p, max(dot(normal_at_p, -rd), 0.0),
+                             sceneFilm(id, uniforms), noiseTex);
+
+    if (sceneTextured(id)) {
+        // Project the video texture onto the object (equirectangular mapping of the normal)
+        if (uniforms.video_mix > 0.0) {
+            float2 sphereUV = float2(atan2(normal_at_p.z, normal_at_p.x) / (2.0 * M_PI_F) + 0.5,
+                                     acos(clamp(normal_at_p.y, -1.0, 1.0)) / M_PI_F);
+            float3 videoColor = videoTex.sample(videoSampler, sphereUV).rgb;
+            objectColor = mix(objectColor, videoColor, uniforms.video_mix);
+        }
+
+        // Glass-like surface: show the captured screen refracted through it
+        if (uniforms.screen_mix > 0.0) {
+            float3 refracted = refract(rd, normal_at_p, 1.0 / 1.45);
+            float3 seen = screenBackdrop(refracted, uniforms, screenTex);
+            objectColor = mix(objectColor, seen, uniforms.screen_mix);
+        }
+    }
+
+    // Basic lighting
+    float3 lightDir = uniforms.sun_dir;
+    float diffuse = max(0.0, dot(normal_at_p, lightDir));
+    float3 ambient = float3(0.15, 0.15, 0.2); // Slightly brighter ambient
+    if (uniforms.shadow_steps > 0 && diffuse > 0.0) {
+        float3 origin = p + normal_at_p * 0.01;
+        diffuse *= uniforms.aa == 0 ? coneShadow(origin, lightDir, 0.02, 10.0, uniforms)
+                                    : softShadow(origin, lightDir, 0.02, 10.0, uniforms);
+    }
+    if (uniforms.ao_samples > 0) {
+        ambient *= calcAO(p, normal_at_p, uniforms);
+    }
+    if (ground && uniforms.floor_contact > 0.0) {
+        float contact = contactShadow(p, uniforms);
+        ambient *= contact;
+        diffuse *= contact;
+    }
+
+    float3 light = diffuse * float3(uniforms.light_color) + sceneLight(p, normal_at_p, uniforms);
+    if (uniforms.caustic_intensity > 0.0 && diffuse > 0.0) {
+        float3 gathered = caustics(p + normal_at_p * 0.01, lightDir, uniforms);
+        light += diffuse * float3(uniforms.light_color) * gathered;
+    }
+    if (uniforms.toon_bands > 0) {
+        light = toonLight(light, uniforms);
+    }
+    float3 color = ambient + objectColor * light;
+    float4 specular = sceneSpecular(id, uniforms);
+    if (specular.x > 0.0 && diffuse > 0.0) {
+        // diffuse is the cosine of the sun's angle, shadowed
+        float shine = highlight(p, normal_at_p, -rd, lightDir, id, specular, uniforms);
+        color += shine * diffuse * float3(uniforms.light_color);
+    }
+    // Glowing objects shine whatever lights them
+    color += sceneEmission(id, uniforms);
+    if (uniforms.toon_bands > 0) {
+        color += toonRim(normal_at_p, rd, uniforms) * (1.0 - diffuse);
+    }
+
+    // From where the ray set out (ignoring any turns through glass or portals)
+    return fogged(color, p - rd * t, rd, t, uniforms, screenTex);
+}
+
+// Color of object `id` at p, seen along rd from t away: shaded, or for glass the sky it
+// reflects and what shows through it, and for a reflective floor what it mirrors
+float3 surface(float3 p, float id, float3 rd, float t, constant Uniforms& uniforms,
+               texture2d<float> videoTex, texture2d<float> screenTex,
+               texture2d<float> normalTex, texture2d<float> noiseTex) {
+    float3 color = float3(0.0);
+    float3 throughput = float3(1.0);
+    for (uint event = 0; event < GLASS_EVENTS; event++) {
+        float2 glass = sceneGlass(id, uniforms);
+        float3 origin;
+        if (glass.x == 0.0) {
+            float mirror = sceneFloor(id) ? floorMirror(-rd.y, uniforms) : 0.0;
+            color += throughput * (1.0 - mirror) * shade(p, id, rd, t, uniforms, videoTex,
+                                                         screenTex, normalTex, noiseTex);
+            if (mirror == 0.0) {
+                return color;
+            }
+            throughput *= mirror;
+            origin = p + float3(0.0, 0.01, 0.0);
+            rd = reflect(rd, float3(0.0, 1.0, 0.0));
+        } else {
+            float3 n = calcNormal(p, uniforms);
+            float reflectance = fresnel(-dot(rd, n), 1.0, glass.x);
+            float3 film = filmReflection(p, -dot(rd, n), sceneFilm(id, uniforms), noiseTex);
+            float3 sky = skyColor(reflect(rd, n), uniforms, screenTex);
+            color += throughput * reflectance * film * sky;
+            Passage passage = throughGlass(p, rd, n, glass, sceneColor(id, uniforms), uniforms);
+            throughput *= (1.0 - reflectance) * passage.transmittance;
+            origin = passage.p;
+            rd = passage.rd;
+        }
+        Hit hit = traceRay(origin, rd, false, uniforms);
+        rd = hit.rd;
+        if (hit.t < 0.0) {
+            return color + throughput * skyColor(rd, uniforms, screenTex);
+        }
+        p = hit.p;
+        id = hit.id;
+        t += hit.t;
+    }
+    return color;
+}
+
+// @meshes@
+#ifndef MESH_PARAMS
+// Without meshes (see meshes.metal) nothing is nearer than the scene
+#define MESH_PARAMS
+#define MESH_ARGS
+#define MESH_BUFFERS
+
+float4 meshSurface(float3 ro, float3 rd, float far, constant Uniforms& uniforms,
+                   texture2d<float> screenTex) {
+    return float4(0.0, 0.0, 0.0, -1.0);
+}
+
+float4 meshCone(float3 ro, float3 rd, float pixel, float far, constant Uniforms& uniforms,
+                texture2d<float> screenTex) {
+    return float4(0.0);
+}
+#endif
+
+// Color seen along the ray from ro along rd that traceRay took to `hit`
+float3 rayMarch(float3 ro, float3 rd, Hit hit, constant Uniforms& uniforms,
+               texture2d<float> videoTex, texture2d<float> screenTex,
+               texture2d<float> normalTex, texture2d<float> noiseTex) {
+    if (hit.t < 0.0) {
+        // Sky gradient if no hit, behind as much fog as the farthest surfaces
+        float3 sky = fogged(skyColor(hit.rd, uniforms, screenTex), hit.p, hit.rd,
+                            uniforms.max_distance, uniforms, screenTex);
+        return withGuides(sky, ro, rd, 1e10, uniforms);
+    }
+    float3 color = surface(hit.p, hit.id, hit.rd, hit.t, uniforms, videoTex, screenTex,
+                           normalTex, noiseTex);
+    return withGuides(color, ro, rd, hit.t, uniforms);
+}
+
+// Cone traced anti-aliasing: one ray per pixel, marched as a cone as wide as the pixel (`pixel`
+// per unit of distance). Where a surface passes through the cone without stopping the ray, at
+// a silhouette, its closest pass is shaded and blended in by the share of the cone it covers,
+// and the ray goes on to what is behind. Portals take the cone along like traceRay does.
+float3 coneMarch(float3 origin, float3 direction, float pixel, constant Uniforms& uniforms,
+                 texture2d<float> videoTex, texture2d<float> screenTex,
+                 texture2d<float> normalTex, texture2d<float> noiseTex MESH_PARAMS,
+                 uint cull = 0xffffffff) {
+    float3 ro = origin;
+    float3 rd = direction;
+    float3 color = float3(0.0);
+    float alpha = 0.0; // Share of the pixel covered so far
+    float3 edge = float3(0.0); // Closest pass of the surface in the cone: (t, id, coverage)
+    float depth = 1e10;
+    float base = 0.0; // Travelled up to ro, through portals
+    uint jumps = 0;
+    float2 portal = scenePortal(ro, rd);
+    float t = 0.0;
+    for (uint i = 0; i < uniforms.max_steps; i++) {
+        if (t >= portal.x) {
+            // An edge still open stays on this side
+            if (edge.z > 0.0) {
+                float3 seen = surface(ro + rd * edge.x, edge.y, rd, base + edge.x, uniforms,
+                                      videoTex, screenTex, normalTex, noiseTex);
+                color += (1.0 - alpha) * edge.z * seen;
+                alpha += (1.0 - alpha) * edge.z;
+                edge.z = 0.0;
+            }
+            ro = portalPoint(ro + rd * portal.x, portal.y);
+            rd = portalDirection(rd, portal.y);
+            base += portal.x;
+            t = 0.0;
+            jumps++;
+            portal = jumps >= PORTAL_JUMPS ? float2(1e10, -1.0) : scenePortal(ro, rd);
+            cull = 0xffffffff;
+        }
+        float3 p = ro + rd * t;
+        float2 hit = sceneMap(p, uniforms, cull);
+        if (hit.x < uniforms.epsilon) {
+            // Covering the rest; an edge still open is this same surface
+            color += (1.0 - alpha) * surface(p, hit.y, rd, base + t, uniforms, videoTex,
+                                             screenTex, normalTex, noiseTex);
+            alpha = 1.0;
+            edge.z = 0.0;
+            depth = base + t;
+            break;
+        }
+        float radius = max((base + t) * pixel * 0.5, uniforms.epsilon);
+        float covered = coverage(hit.x, radius);
+        if (covered > edge.z) {
+            edge = float3(t, hit.y, covered);
+        } else if (edge.z > 0.0 && covered == 0.0) {
+            // Past it: blend it in
+            float3 seen = surface(ro + rd * edge.x, edge.y, rd, base + edge.x, uniforms,
+                                  videoTex, screenTex, normalTex, noiseTex);
+            color += (1.0 - alpha) * edge.z * seen;
+            alpha += (1.0 - alpha) * edge.z;
+            edge.z = 0.0;
+        }
+        if (base + t > uniforms.max_distance) {
+            break;
+        }
+        t = min(t + hit.x * 0.8, portal.x);
+    }
+    if (edge.z > 0.0) {
+        float3 seen = surface(ro + rd * edge.x, edge.y, rd, base + edge.x, uniforms, videoTex,
+                              screenTex, normalTex, noiseTex);
+        color += (1.0 - alpha) * edge.z * seen;
+        alpha += (1.0 - alpha) * edge.z;
+    }
+    float3 sky = fogged(skyColor(rd, uniforms, screenTex), ro, rd, uniforms.max_distance,
+                        uniforms, screenTex);
+    color += (1.0 - alpha) * sky;
+    // Meshes in front of the surface, over the share of the pixel they cover
+    float4 mesh = meshCone(origin, direction, pixel, depth < 1e10 ? depth : -1.0, uniforms,
+                           screenTex MESH_ARGS);
+    color = color * (1.0 - mesh.a) + mesh.rgb;
+    return withGuides(color, origin, direction, depth, uniforms);
+}
+
+// Coverage (0-1) of the selection outline for a ray: a second march of the selected object on
+// its own, tracking how close the ray comes to it for the distance travelled. Rays that hit it
+// get none, so the outline sits just outside the silhouette, over anything in front of it.
+float selectionOutline(float3 ro, float3 rd, float pixel, constant Uniforms& uniforms) {
+    const float width = 2.0; // Pixels
+    float t = 0.0;
+    float closest = 1e10; // Smallest distance over t, the angle to the object
+    for (uint i = 0; i < uniforms.max_steps; i++) {
+        float d = sceneObject(ro + rd * t, uniforms.selected, uniforms);
+        if (d < uniforms.epsilon) {
+            return 0.0;
+        }
+        if (t > 0.0) {
+            closest = min(closest, d / t);
+        }
+        if (t > uniforms.max_distance) {
+            break;
+        }
+        t += d;
+    }
+    return 1.0 - smoothstep(width * 0.5, width, closest / pixel);
+}
+
+// Direction through `uv` for the camera's projection, in the view frame (x right, y up,
+// z forward). The projections agree at the center and ease in from the pinhole view with
+// lens_amount (see camera.rs).
+float3 lensDirection(float2 uv, constant Uniforms& uniforms) {
+    float a = uniforms.lens_amount;
+    switch (uniforms.projection) {
+        case 1:
+        case 2: {
+            // Fisheye: the angle off the axis grows with the distance from the center
+            float r = length(uv);
+            float full = uniforms.projection == 2 ? 2.0 * asin(min(r * 0.5, 1.0)) : r;
+            float theta = min(mix(atan(r), full, a), M_PI_F);
+            float2 side = r > 0.0 ? uv / r : float2(0.0);
+            return float3(side * sin(theta), cos(theta));
+        }
+        case 3: {
+            // Panini: onto a cylinder seen from `a` behind its axis, then out from the axis
+            float view_dist = 1.0 + a;
+            float hyp = uv.x * uv.x + view_dist * view_dist;
+            float isect = uv.x * a;
+            float c = (-isect * uv.x + view_dist * sqrt(max(hyp - isect * isect, 0.0))) / hyp;
+            return normalize(float3(uv * ((c + a) / view_dist), c));
+        }
+        case 4: {
+            // Cylindrical: angles across, straight up and down
+            float longitude = mix(atan(uv.x), uv.x, a);
+            float height = mix(sqrt(1.0 + uv.x * uv.x), 1.0, a);
+            return normalize(float3(sin(longitude), uv.y / height, cos(longitude)));
+        }
+        default:
+            return normalize(float3(uv, 1.0));
+    }
+}
+
+struct Ray {
+    float3 ro;
+    float3 rd;
+};
+
+// The camera ray through `uv` from an eye: -1 left, 1 right, 0 the camera itself. The eyes sit
+// ipd apart and aim at where the camera's own ray crosses the convergence distance.
+Ray eyeRay(float2 uv, float eye, constant Uniforms& uniforms) {
     float3 ro = uniforms.camera_pos;
-    float3 lookAt = float3(0.0, 0.0, 0.0);
-    
-    // Camera matrix
-    float3 forward = normalize(lookAt - ro);
+    float3 forward = normalize(uniforms.camera_target - ro);
     float3 right = normalize(cross(float3(0.0, 1.0, 0.0), forward));
     float3 up = cross(forward, right);
-    
-    float3 rd = normalize(forward + uv.x * right + uv.y * up);
-    
-    // Ray march
-    float3 color = rayMarch(ro, rd, uniforms);
-    
-    // Gamma correction (disabled for debugging)
-    // color = pow(color, float3(1.0/2.2));
-    
+    float3 d = lensDirection(uv, uniforms);
+    float3 rd = normalize(right * d.x + up * d.y + forward * d.z);
+    float3 origin = ro + right * (eye * uniforms.ipd * 0.5);
+    float depth = dot(rd, forward);
+    if (eye == 0.0 || depth < 0.01) {
+        return Ray{origin, rd};
+    }
+    return Ray{origin, normalize(ro + rd * (uniforms.convergence / depth) - origin)};
+}
+
+// View uv (-1..1 up, -aspect..aspect across) of a point on the screen (in.uv) and the eye
+// that sees it. Side by side, each half holds one eye's whole view squeezed to half width, as
+// VR video players expect.
+float3 screenView(float2 screen, constant Uniforms& uniforms) {
+    float2 at = screen;
+    float eye = 0.0;
+    if (uniforms.stereo == 2) {
+        at.x = fract(screen.x * 2.0);
+        eye = screen.x < 0.5 ? -1.0 : 1.0;
+    }
+    // Into the whole view, of which this image may be a tile (with its aspect ratio)
+    at = uniforms.region.xy + at * uniforms.region.zw;
+    float2 uv = (at - 0.5) * 2.0;
+    uv.x *= uniforms.resolution.x * uniforms.region.w / (uniforms.resolution.y * uniforms.region.z);
+    return float3(uv, eye);
+}
+
+// Toon ink: the share (0-1) of rays toon_outline / 2 pixels to each side of the one through
+// `uv` that break from it, hitting off the plane of its surface or a surface facing another
+// way (or hitting where it doesn't). Lines land on both sides of a break, toon_outline wide.
+float toonOutline(float2 uv, float eye, float pixel, constant Uniforms& uniforms) {
+    Ray ray = eyeRay(uv, eye, uniforms);
+    Hit hit = traceRay(ray.ro, ray.rd, false, uniforms);
+    float3 n = hit.t >= 0.0 ? calcNormal(hit.p, uniforms) : float3(0.0);
+    float ink = 0.0;
+    float reach = 0.5 * uniforms.toon_outline * pixel;
+    float2 offsets[4] = {float2(reach, 0.0), float2(-reach, 0.0), float2(0.0, reach),
+                         float2(0.0, -reach)};
+    for (int i = 0; i < 4; i++) {
+        Ray side = eyeRay(uv + offsets[i], eye, uniforms);
+        Hit other = traceRay(side.ro, side.rd, false, uniforms);
+        if ((hit.t < 0.0) != (other.t < 0.0)) {
+            ink += 0.25;
+        } else if (hit.t >= 0.0) {
+            bool off_plane = abs(dot(other.p - hit.p, n)) > 0.02 * hit.t;
+            if (off_plane || dot(calcNormal(other.p, uniforms), n) < 0.8) {
+                ink += 0.25;
+            }
+        }
+    }
+    return ink;
+}
+
+// The pixel at `uv` seen from `eye`: an aa x aa grid of rays spread over it averaged, or a
+// cone traced through it (aa 0), and with toon shading the ink over it. Each ray sees the
+// meshes nearer than the scene. `cull` is for the first march of each ray.
+float3 renderView(float2 uv, float eye, float pixel, uint aa, constant Uniforms& uniforms,
+                  texture2d<float> videoTex, texture2d<float> screenTex,
+                  texture2d<float> normalTex, texture2d<float> noiseTex MESH_PARAMS,
+                  uint cull = 0xffffffff) {
+    float3 color = float3(0.0);
+    if (aa == 0) {
+        Ray ray = eyeRay(uv, eye, uniforms);
+        color = coneMarch(ray.ro, ray.rd, pixel, uniforms, videoTex, screenTex, normalTex,
+                          noiseTex MESH_ARGS, cull);
+    }
+    for (uint sy = 0; sy < aa; sy++) {
+        for (uint sx = 0; sx < aa; sx++) {
+            float2 offset = (float2(sx, sy) + 0.5) / float(aa) - 0.5;
+            Ray ray = eyeRay(uv + offset * pixel, eye, uniforms);
+            Hit hit = traceRay(ray.ro, ray.rd, false, uniforms, cull);
+            float4 mesh = meshSurface(ray.ro, ray.rd, hit.t, uniforms, screenTex MESH_ARGS);
+            color += mesh.w >= 0.0 ? withGuides(mesh.rgb, ray.ro, ray.rd, mesh.w, uniforms)
+                                   : rayMarch(ray.ro, ray.rd, hit, uniforms, videoTex,
+                                              screenTex, normalTex, noiseTex);
+        }
+    }
+    color /= float(max(aa * aa, 1u));
+
+    if (uniforms.toon_bands > 0 && uniforms.toon_outline > 0.0) {
+        color *= 1.0 - toonOutline(uv, eye, pixel, uniforms);
+    }
+    if (uniforms.selected >= 0.0) {
+        Ray ray = eyeRay(uv, eye, uniforms);
+        color = mix(color, float3(1.0, 0.8, 0.2), selectionOutline(ray.ro, ray.rd, pixel, uniforms));
+    }
+    return color;
+}
+
+constant float VOLUME_TRANSFER = 256.0; // Entries in the transfer texture (TRANSFER_SIZE)
+constant uint ISO_REFINE = 6;           // Halvings of the step the iso-surface is found in
+
+// Volume dataset (see volume.rs): its value at p, 0 to 1 across the window
+float volumeValue(float3 p, constant Uniforms& uniforms, texture3d<float> volumeTex) {
+    float3 uvw = (p - float3(uniforms.volume_center)) / (2.0 * float3(uniforms.volume_size)) + 0.5;
+    float value = volumeTex.sample(volumeSampler, uvw).r;
+    float2 window = uniforms.volume_window;
+    return clamp((value - window.x) / (window.y - window.x), 0.0, 1.0);
+}
+
+// Color and opacity the transfer function gives a value
+float4 transfer(float value, texture2d<float> transferTex) {
+    float u = (value * (VOLUME_TRANSFER - 1.0) + 0.5) / VOLUME_TRANSFER;
+    return transferTex.sample(volumeSampler, float2(u, 0.5));
+}
+
+// The volume over `color`, the pixel at `uv` seen from `eye`: along the ray through the
+// volume's box up to the scene's first surface, either the transfer function's colors
+// gathered front to back, or the first point where the data reaches volume_iso, lit by the
+// sun with the normal of the data's gradient and shadowed by the scene
+float3 withVolume(float3 color, float2 uv, float eye, constant Uniforms& uniforms,
+                  texture3d<float> volumeTex, texture2d<float> transferTex) {
+    if (uniforms.volume_steps == 0) {
+        return color;
+    }
+    Ray ray = eyeRay(uv, eye, uniforms);
+    float3 center = float3(uniforms.volume_center);
+    float3 size = float3(uniforms.volume_size);
+    float3 t0 = (center - size - ray.ro) / ray.rd;
+    float3 t1 = (center + size - ray.ro) / ray.rd;
+    float3 near = min(t0, t1);
+    float3 far = max(t0, t1);
+    float enter = max(max(max(near.x, near.y), near.z), 0.0);
+    float leave = min(min(far.x, far.y), far.z);
+    if (enter >= leave) {
+        return color;
+    }
+    Hit hit = traceRay(ray.ro, ray.rd, false, uniforms);
+    if (hit.t >= 0.0) {
+        leave = min(leave, hit.t);
+    }
+    float stride = 2.0 * length(size) / float(uniforms.volume_steps);
+
+    if (uniforms.volume_mode == 1) {
+        float t = enter;
+        float before = volumeValue(ray.ro + ray.rd * t, uniforms, volumeTex) - uniforms.volume_iso;
+        for (uint i = 0; i <= uniforms.volume_steps && t < leave && before < 0.0; i++) {
+            float next = min(t + stride, leave);
+            float after =
+                volumeValue(ray.ro + ray.rd * next, uniforms, volumeTex) - uniforms.volume_iso;
+            if (after >= 0.0) {
+                // Narrow the crossing down between t and next
+                for (uint j = 0; j < ISO_REFINE; j++) {
+                    float middle = 0.5 * (t + next);
+                    float value = volumeValue(ray.ro + ray.rd * middle, uniforms, volumeTex);
+                    if (value >= uniforms.volume_iso) {
+                        next = middle;
+                    } else {
+                        t = middle;
+                    }
+                }
+            }
+            t = next;
+            before = after;
+        }
+        if (before < 0.0) {
+            return color;
+        }
+        float3 p = ray.ro + ray.rd * t;
+        float2 e = float2(0.5 * stride, 0.0);
+        float3 gradient = float3(
+            volumeValue(p + e.xyy, uniforms, volumeTex) - volumeValue(p - e.xyy, uniforms, volumeTex),
+            volumeValue(p + e.yxy, uniforms, volumeTex) - volumeValue(p - e.yxy, uniforms, volumeTex),
+            volumeValue(p + e.yyx, uniforms, volumeTex) - volumeValue(p - e.yyx, uniforms, volumeTex));
+        float3 n = length(gradient) > 0.0 ? -normalize(gradient) : -ray.rd;
+        float3 lightDir = uniforms.sun_dir;
+        float diffuse = max(0.0, dot(n, lightDir));
+        if (uniforms.shadow_steps > 0 && diffuse > 0.0) {
+            diffuse *= softShadow(p + n * 0.01, lightDir, 0.02, 10.0, uniforms);
+        }
+        float3 albedo = transfer(uniforms.volume_iso, transferTex).rgb;
+        return albedo * (float3(0.15, 0.15, 0.2) + diffuse * float3(uniforms.light_color));
+    }
+
+    float3 gathered = float3(0.0);
+    float transmittance = 1.0;
+    float t = enter;
+    for (uint i = 0; i <= uniforms.volume_steps && t < leave && transmittance > 0.01; i++) {
+        float span = min(stride, leave - t);
+        float4 entry = transfer(volumeValue(ray.ro + ray.rd * (t + 0.5 * span), uniforms,
+                                            volumeTex), transferTex);
+        float alpha = 1.0 - exp(-entry.a * uniforms.volume_density * span);
+        gathered += transmittance * alpha * entry.rgb;
+        transmittance *= 1.0 - alpha;
+        t += span;
+    }
+    return gathered + transmittance * color;
+}
+
+// The pixel at `screen` (in.uv) with an aa x aa grid of rays (a cone for 0), marched with the
+// top-level nodes whose bits are set in `cull`
+float4 renderPixel(float2 screen, uint aa, constant Uniforms& uniforms,
+                   texture2d<float> videoTex, texture2d<float> screenTex,
+                   texture2d<float> normalTex, texture2d<float> noiseTex,
+                   texture3d<float> volumeTex, texture2d<float> transferTex MESH_PARAMS,
+                   uint cull = 0xffffffff) {
+    float3 view = screenView(screen, uniforms);
+    float pixel = 2.0 * uniforms.region.w / uniforms.resolution.y;
+    if (uniforms.stereo == 1) {
+        // Anaglyph: red from the left eye, green and blue from the right
+        float3 left = renderView(view.xy, -1.0, pixel, aa, uniforms, videoTex, screenTex,
+                                 normalTex, noiseTex MESH_ARGS, cull);
+        float3 right = renderView(view.xy, 1.0, pixel, aa, uniforms, videoTex, screenTex,
+                                  normalTex, noiseTex MESH_ARGS, cull);
+        left = withVolume(left, view.xy, -1.0, uniforms, volumeTex, transferTex);
+        right = withVolume(right, view.xy, 1.0, uniforms, volumeTex, transferTex);
+        return float4(left.r, right.gb, 1.0);
+    }
+    float3 color = renderView(view.xy, view.z, pixel, aa, uniforms, videoTex, screenTex,
+                              normalTex, noiseTex MESH_ARGS, cull);
+    color = withVolume(color, view.xy, view.z, uniforms, volumeTex, transferTex);
     return float4(color, 1.0);
-}
\ No newline at end of file
+}
+
+fragment float4 fragment_main(VertexOut in [[stage_in]],
+                            constant Uniforms& uniforms [[buffer(0)]],
+                            texture2d<float> videoTex [[texture(0)]],
+                            texture2d<float> screenTex [[texture(1)]],
+                            texture2d<float> normalTex [[texture(3)]],
+                            texture2d<float> noiseTex [[texture(4)]],
+                            texture3d<float> volumeTex [[texture(5)]],
+                            texture2d<float> transferTex [[texture(6)]] MESH_BUFFERS) {
+    return renderPixel(in.uv, uniforms.aa, uniforms, videoTex, screenTex, normalTex, noiseTex,
+                       volumeTex, transferTex MESH_ARGS);
+}
+
+// Progressive path tracing: one path per pixel each frame, averaged with the paths of earlier
+// frames (uniforms.samples of them, in `previous`). Diffuse bounces gather light from the sky
+// and from other surfaces, and every hit samples the sun (with a hard shadow from a slightly
+// jittered direction, soft once averaged) and one of the point lights directly, with a shadow
+// ray. The average keeps the distance to the first hit in alpha, for the denoiser.
+constant uint PATH_BOUNCES = 4;
+constant int DENOISE_RADIUS = 3;
+constant float3 LUMINANCE = float3(0.2126, 0.7152, 0.0722);
+
+// PCG hash, used as a random number generator with a state per pixel and frame
+uint pcg(uint v) {
+    uint state = v * 747796405u + 2891336453u;
+    uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+float random(thread uint& seed) {
+    seed = pcg(seed);
+    return float(seed) / 4294967295.0;
+}
+
+// Uniformly distributed unit vector
+float3 sphereDirection(thread uint& seed) {
+    float a = 2.0 * M_PI_F * random(seed);
+    float z = 2.0 * random(seed) - 1.0;
+    float r = sqrt(max(1.0 - z * z, 0.0));
+    return float3(r * cos(a), r * sin(a), z);
+}
+
+// Direction around n, cosine weighted
+float3 cosineDirection(float3 n, thread uint& seed) {
+    return normalize(n + sphereDirection(seed));
+}
+
+// Light at p (normal n) from one of the scene's lights, picked with odds in proportion to its
+// unshadowed light there, from a random point of its sphere and unless a surface is in the way
+// of `origin` (just off p). Divided by those odds, it averages out to the sum over all lights
+// with less noise than picking any light alike when a few bright or near ones dominate.
+float3 sampleLights(float3 p, float3 n, float3 origin, thread uint& seed,
+                    constant Uniforms& uniforms) {
+    float total = 0.0;
+    for (int i = 0; i < SCENE_LIGHTS; i++) {
+        float3 position = sceneLightSphere(float(i), uniforms).xyz;
+        float3 power = sceneLightPower(float(i), uniforms);
+        total += dot(pointLight(p, n, position, power, 1.0), LUMINANCE);
+    }
+    if (total <= 0.0) {
+        return float3(0.0);
+    }
+    float pick = random(seed) * total;
+    for (int i = 0; i < SCENE_LIGHTS; i++) {
+        float4 sphere = sceneLightSphere(float(i), uniforms);
+        float3 power = sceneLightPower(float(i), uniforms);
+        float odds = dot(pointLight(p, n, sphere.xyz, power, 1.0), LUMINANCE);
+        pick -= odds;
+        if (pick > 0.0 || odds <= 0.0) {
+            continue;
+        }
+        float3 position = sphere.xyz + sphere.w * sphereDirection(seed);
+        float3 l = position - origin;
+        float span = length(l);
+        float hit = traceRay(origin, l / span, true, uniforms).t;
+        if (hit >= 0.0 && hit < span) {
+            return float3(0.0);
+        }
+        return pointLight(p, n, position, power, 1.0) * total / odds;
+    }
+    return float3(0.0);
+}
+
+// Color along the path, and the distance to its first hit (max_distance for none)
+float4 pathTrace(float3 ro, float3 rd, thread uint& seed, constant Uniforms& uniforms,
+                 texture2d<float> screenTex, texture2d<float> normalTex,
+                 texture2d<float> noiseTex) {
+    float3 eye = ro;
+    float3 view = rd;
+    float3 color = float3(0.0);
+    float3 throughput = float3(1.0);
+    float depth = uniforms.max_distance;
+    for (uint bounce = 0; bounce < PATH_BOUNCES; bounce++) {
+        Hit hit = traceRay(ro, rd, false, uniforms);
+        rd = hit.rd;
+        if (bounce == 0 && hit.t >= 0.0) {
+            depth = hit.t;
+        }
+        if (hit.t < 0.0) {
+            color += throughput * skyColor(rd, uniforms, screenTex);
+            break;
+        }
+        float3 p = hit.p;
+        float3 n = calcNormal(p, uniforms);
+        float3 base = noiseColor(p, sceneColorAt(p, hit.id, sceneColor(hit.id, uniforms), uniforms),
+                                 sceneNoise(hit.id, uniforms), noiseTex);
+        float4 film = sceneFilm(hit.id, uniforms);
+        float3 origin = p + n * 0.01;
+        color += throughput * sceneEmission(hit.id, uniforms);
+
+        // The floor: mirroring, picked by the share it mirrors, or lit like the rest
+        if (sceneFloor(hit.id)) {
+            if (random(seed) < floorMirror(-dot(rd, n), uniforms)) {
+                ro = origin;
+                rd = reflect(rd, n);
+                continue;
+            }
+            base = floorColor(p, uniforms);
+        }
+
+        // Glass: reflected or through, picked by the share of light going each way
+        float2 glass = sceneGlass(hit.id, uniforms);
+        if (glass.x > 0.0) {
+            if (random(seed) < fresnel(-dot(rd, n), 1.0, glass.x)) {
+                ro = origin;
+                throughput *= filmReflection(p, -dot(rd, n), film, noiseTex);
+                rd = reflect(rd, n);
+            } else {
+                Passage passage = throughGlass(p, rd, n, glass, base, uniforms);
+                throughput *= passage.transmittance;
+                ro = passage.p;
+                rd = passage.rd;
+            }
+            continue;
+        }
+
+        // Lit and bounced about the normal with the normal map's detail
+        float3 albedo = iridescent(base, p, abs(dot(rd, n)), film, noiseTex);
+        float3 shading = bumpNormal(p, n, sceneBump(hit.id, uniforms), normalTex);
+        float3 jitter = float3(random(seed), random(seed), random(seed)) - 0.5;
+        float3 sun = normalize(uniforms.sun_dir + jitter * 0.05);
+        float3 direct = sampleLights(p, shading, origin, seed, uniforms);
+        float facing = dot(shading, sun);
+        float3 gloss = float3(0.0); // The sun's highlight, not tinted by the albedo
+        if (facing > 0.0 && traceRay(origin, sun, true, uniforms).t < 0.0) {
+            direct += facing * float3(uniforms.light_color);
+            float4 specular = sceneSpecular(hit.id, uniforms);
+            if (specular.x > 0.0) {
+                float shine = highlight(p, shading, -rd, sun, hit.id, specular, uniforms);
+                gloss = shine * facing * float3(uniforms.light_color);
+            }
+        }
+        color += throughput * (albedo * direct + gloss);
+
+        throughput *= albedo;
+        ro = origin;
+        rd = cosineDirection(shading, seed);
+    }
+    // Fog on the way to the first hit (the bounces go through clear air)
+    return float4(fogged(color, eye, view, depth, uniforms, screenTex), depth);
+}
+
+// A new path for the pixel, averaged into the ones traced so far
+fragment float4 pathtrace_main(VertexOut in [[stage_in]],
+                               constant Uniforms& uniforms [[buffer(0)]],
+                               texture2d<float> screenTex [[texture(1)]],
+                               texture2d<float> previous [[texture(2)]],
+                               texture2d<float> normalTex [[texture(3)]],
+                               texture2d<float> noiseTex [[texture(4)]]) {
+    uint2 pixel = uint2(in.position.xy);
+    // The run's seed moves every pixel a long way along its sequence (none for seed 0)
+    uint sequence = uint(uniforms.samples) + uniforms.seed * 0x9E3779B9u;
+    uint seed = pcg(pixel.x + pcg(pixel.y + pcg(sequence)));
+
+    // Through a random point in the pixel, so edges are antialiased as paths add up
+    float2 jitter = float2(random(seed), random(seed)) - 0.5;
+    float3 view = screenView(in.uv + jitter / uniforms.resolution, uniforms);
+    float4 path;
+    if (uniforms.stereo == 1) {
+        // Anaglyph: a path for each eye, red from the left one (depth from the right)
+        Ray left = eyeRay(view.xy, -1.0, uniforms);
+        Ray right = eyeRay(view.xy, 1.0, uniforms);
+        float4 left_path =
+            pathTrace(left.ro, left.rd, seed, uniforms, screenTex, normalTex, noiseTex);
+        path = pathTrace(right.ro, right.rd, seed, uniforms, screenTex, normalTex, noiseTex);
+        path.r = left_path.r;
+    } else {
+        Ray ray = eyeRay(view.xy, view.z, uniforms);
+        path = pathTrace(ray.ro, ray.rd, seed, uniforms, screenTex, normalTex, noiseTex);
+    }
+    if (uniforms.samples > 0.0) {
+        path = mix(previous.read(pixel), path, 1.0 / (uniforms.samples + 1.0));
+    }
+    return path;
+}
+
+// The averaged paths, drawn to the target
+fragment float4 resolve_main(VertexOut in [[stage_in]],
+                             texture2d<float> accumulated [[texture(2)]]) {
+    return float4(accumulated.read(uint2(in.position.xy)).rgb, 1.0);
+}
+
+float4 texel(texture2d<float> image, int2 pixel) {
+    int2 size = int2(image.get_width(), image.get_height());
+    return image.read(uint2(clamp(pixel, int2(0), size - 1)));
+}
+
+// Of the differences to the left and right neighbours, the smaller one (so it doesn't step
+// over an edge)
+float smallerStep(float before, float here, float after) {
+    float left = here - before;
+    float right = after - here;
+    return abs(left) < abs(right) ? left : right;
+}
+
+// The averaged paths with an edge-aware blur (SVGF-style edge stopping), for images of a few
+// samples: neighbours count less the further their first hit is from the surface through the
+// pixel (continued along its depth gradient) and the more their luminance differs. That
+// tolerance shrinks with the noise as samples add up, so the blur fades away.
+fragment float4 denoise_main(VertexOut in [[stage_in]],
+                             constant Uniforms& uniforms [[buffer(0)]],
+                             texture2d<float> accumulated [[texture(2)]]) {
+    int2 pixel = int2(in.position.xy);
+    float4 center = texel(accumulated, pixel);
+    float2 gradient = float2(
+        smallerStep(texel(accumulated, pixel - int2(1, 0)).a, center.a,
+                    texel(accumulated, pixel + int2(1, 0)).a),
+        smallerStep(texel(accumulated, pixel - int2(0, 1)).a, center.a,
+                    texel(accumulated, pixel + int2(0, 1)).a));
+    float luminance = dot(center.rgb, LUMINANCE);
+    float luminanceSigma = 1.0 / sqrt(uniforms.samples + 1.0);
+
+    float3 sum = float3(0.0);
+    float weights = 0.0;
+    for (int y = -DENOISE_RADIUS; y <= DENOISE_RADIUS; y++) {
+        for (int x = -DENOISE_RADIUS; x <= DENOISE_RADIUS; x++) {
+            float4 neighbour = texel(accumulated, pixel + int2(x, y));
+            float spatial = exp(-float(x * x + y * y) / float(2 * DENOISE_RADIUS));
+            float depthSigma = abs(dot(gradient, float2(x, y))) + 0.01 * center.a + 1e-4;
+            float depth = exp(-abs(neighbour.a - center.a) / depthSigma);
+            float shade = exp(-abs(dot(neighbour.rgb, LUMINANCE) - luminance) / luminanceSigma);
+            float weight = spatial * depth * shade;
+            sum += neighbour.rgb * weight;
+            weights += weight;
+        }
+    }
+    return float4(sum / weights, 1.0);
+}
+
+// Edge detection post pass (see edges.rs): gbuffer_main renders the normal and distance of each
+// pixel's first hit (the center eye's for an anaglyph), and edges_main runs a Sobel filter over
+// them, drawing lines over the image (or on the blueprint's paper) where either changes sharply.
+Ray gbufferRay(float2 screen, constant Uniforms& uniforms) {
+    float3 view = screenView(screen, uniforms);
+    return eyeRay(view.xy, uniforms.stereo == 1 ? 0.0 : view.z, uniforms);
+}
+
+fragment float4 gbuffer_main(VertexOut in [[stage_in]],
+                             constant Uniforms& uniforms [[buffer(0)]]) {
+    Ray ray = gbufferRay(in.uv, uniforms);
+    Hit hit = traceRay(ray.ro, ray.rd, false, uniforms);
+    if (hit.t < 0.0) {
+        return float4(0.0, 0.0, 0.0, uniforms.max_distance);
+    }
+    return float4(calcNormal(hit.p, uniforms), hit.t);
+}
+
+fragment float4 edges_main(VertexOut in [[stage_in]],
+                           constant Uniforms& uniforms [[buffer(0)]],
+                           texture2d<float> gbuffer [[texture(2)]]) {
+    int2 pixel = int2(in.position.xy);
+    // Lines come out about twice as wide as the taps are apart
+    int reach = max(int(round(uniforms.edge_width * 0.5)), 1);
+    float4 gx = float4(0.0);
+    float4 gy = float4(0.0);
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            float4 tap = texel(gbuffer, pixel + int2(x, y) * reach);
+            gx += tap * float(x * (2 - abs(y)));
+            gy += tap * float(y * (2 - abs(x)));
+        }
+    }
+    // Distance changes by itself across a surface seen edge-on (a floor towards the horizon),
+    // so its change counts less the more the surface faces away; sky faces the eye
+    float4 center = texel(gbuffer, pixel);
+    float facing = center.w >= uniforms.max_distance
+        ? 1.0 : abs(dot(center.xyz, gbufferRay(in.uv, uniforms).rd));
+    float depth = length(float2(gx.w, gy.w)) / max(center.w, 1e-3) * facing;
+    float normal = sqrt(dot(gx.xyz, gx.xyz) + dot(gy.xyz, gy.xyz));
+    float edge = max(smoothstep(uniforms.edge_depth, uniforms.edge_depth * 1.5, depth),
+                     smoothstep(uniforms.edge_normal, uniforms.edge_normal * 1.5, normal));
+    if (uniforms.edge_mode == 2) {
+        return float4(mix(float3(uniforms.edge_paper), float3(uniforms.edge_color), edge), 1.0);
+    }
+    return float4(float3(uniforms.edge_color), edge);
+}
+
+// Volumetric light shafts (see shafts.rs): shafts_main marches each pixel's ray (at the shaft
+// texture's resolution) up to what it hits, adding the sunlight the fog scatters towards the
+// eye wherever a shadow ray (the path tracer's test) reaches the sun, and shafts_composite_main
+// adds that over the image, upsampled bilinearly. The march ignores glass and portals.
+constant float SHAFT_AIR = 0.01; // Scattering where the scene has less fog
+constant float SHAFT_DISTANCE = 40.0; // Farthest the march goes
+constant float SHAFT_FORWARD = 0.6; // How much more light scatters forwards than back
+
+fragment float4 shafts_main(VertexOut in [[stage_in]],
+                            constant Uniforms& uniforms [[buffer(0)]]) {
+    Ray ray = gbufferRay(in.uv, uniforms);
+    Hit hit = traceRay(ray.ro, ray.rd, false, uniforms);
+    float end = min(hit.t < 0.0 ? uniforms.max_distance : hit.t, SHAFT_DISTANCE);
+    float3 sun = uniforms.sun_dir;
+    float stride = end / float(uniforms.shaft_samples);
+    // Interleaved gradient noise: each pixel's steps start a different way in, so the
+    // upsampling blurs the banding away. The seed turns the whole pattern by a golden step.
+    float jitter = fract(52.9829189 * fract(dot(in.position.xy, float2(0.06711056, 0.00583715))));
+    jitter = fract(jitter + float((uniforms.seed * 0x9E3779B9u) >> 8) / 16777216.0);
+    float light = 0.0;
+    float depth = 0.0; // Optical depth from the eye
+    for (uint i = 0; i < uniforms.shaft_samples; i++) {
+        float3 p = ray.ro + ray.rd * ((float(i) + jitter) * stride);
+        float density = max(uniforms.fog_density + heightFogDensity(p.y, uniforms), SHAFT_AIR);
+        float lit = traceRay(p, sun, true, uniforms).t < 0.0 ? 1.0 : 0.0;
+        light += lit * density * exp(-depth) * stride;
+        depth += density * stride;
+    }
+    // Henyey-Greenstein phase: the share of the scattered light going towards the eye
+    float g = SHAFT_FORWARD;
+    float lobe = 1.0 + g * g - 2.0 * g * dot(ray.rd, sun);
+    float phase = (1.0 - g * g) / (4.0 * M_PI_F * pow(lobe, 1.5));
+    return float4(float3(uniforms.light_color) * light * phase * uniforms.shaft_intensity, 1.0);
+}
+
+fragment float4 shafts_composite_main(VertexOut in [[stage_in]],
+                                      constant Uniforms& uniforms [[buffer(0)]],
+                                      texture2d<float> shafts [[texture(2)]]) {
+    float2 size = float2(shafts.get_width(), shafts.get_height());
+    float2 at = in.position.xy / uniforms.resolution * size - 0.5;
+    int2 corner = int2(floor(at));
+    float2 f = fract(at);
+    float3 top = mix(texel(shafts, corner).rgb, texel(shafts, corner + int2(1, 0)).rgb, f.x);
+    float3 bottom = mix(texel(shafts, corner + int2(0, 1)).rgb,
+                        texel(shafts, corner + int2(1, 1)).rgb, f.x);
+    return float4(mix(top, bottom, f.y), 1.0);
+}
+
+// Adaptive quality (see adaptive.rs): tiles_main shades a 3 x 3 grid of rays over each
+// tile_size square of pixels (one texel of the tile texture), reaching a pixel past its sides
+// so the edges the next tile's grid spreads over count too, and marks it detailed where they
+// disagree: a miss beside a hit, another object, surfaces facing apart or off one plane, or
+// colors apart (a shadow's edge, a checker's, a reflection's). adaptive_main draws the image as
+// fragment_main does, with the aa x aa grid (or cone) in detailed tiles and one ray elsewhere.
+constant float TILE_CONTRAST = 0.08; // Difference in a color channel that makes a tile detailed
+constant float TILE_FACING = 0.9; // Normals less alike (their dot product) make it detailed
+
+fragment float4 tiles_main(VertexOut in [[stage_in]],
+                           constant Uniforms& uniforms [[buffer(0)]],
+                           texture2d<float> videoTex [[texture(0)]],
+                           texture2d<float> screenTex [[texture(1)]],
+                           texture2d<float> normalTex [[texture(3)]],
+                           texture2d<float> noiseTex [[texture(4)]]) {
+    float size = float(uniforms.tile_size);
+    // In pixels of the image, from its top left
+    float2 low = floor(in.position.xy) * size - 1.0;
+    float spacing = (size + 2.0) * 0.5;
+    Hit first;
+    float3 normal = float3(0.0);
+    float3 lowest = float3(1e10);
+    float3 highest = float3(-1e10);
+    for (int i = 0; i < 9; i++) {
+        float2 at = low + float2(i % 3, i / 3) * spacing;
+        Ray ray = gbufferRay(float2(at.x, uniforms.resolution.y - at.y) / uniforms.resolution,
+                             uniforms);
+        Hit hit = traceRay(ray.ro, ray.rd, false, uniforms);
+        float3 n = hit.t >= 0.0 ? calcNormal(hit.p, uniforms) : float3(0.0);
+        if (i == 0) {
+            first = hit;
+            normal = n;
+        } else if ((hit.t < 0.0) != (first.t < 0.0)) {
+            return float4(1.0);
+        } else if (hit.t >= 0.0) {
+            bool off_plane = abs(dot(hit.p - first.p, normal)) > 0.02 * first.t;
+            if (hit.id != first.id || off_plane || dot(n, normal) < TILE_FACING) {
+                return float4(1.0);
+            }
+        }
+        float3 color = rayMarch(ray.ro, ray.rd, hit, uniforms, videoTex, screenTex, normalTex,
+                                noiseTex);
+        lowest = min(lowest, color);
+        highest = max(highest, color);
+    }
+    bool contrast = any(highest - lowest > TILE_CONTRAST);
+    return contrast ? float4(1.0) : float4(0.0, 0.0, 0.0, 1.0);
+}
+
+fragment float4 adaptive_main(VertexOut in [[stage_in]],
+                              constant Uniforms& uniforms [[buffer(0)]],
+                              texture2d<float> videoTex [[texture(0)]],
+                              texture2d<float> screenTex [[texture(1)]],
+                              texture2d<float> tiles [[texture(2)]],
+                              texture2d<float> normalTex [[texture(3)]],
+                              texture2d<float> noiseTex [[texture(4)]],
+                              texture3d<float> volumeTex [[texture(5)]],
+                              texture2d<float> transferTex [[texture(6)]] MESH_BUFFERS) {
+    int2 tile = int2(in.position.xy) / int(max(uniforms.tile_size, 1u));
+    uint aa = texel(tiles, tile).r > 0.5 ? uniforms.aa : 1;
+    return renderPixel(in.uv, aa, uniforms, videoTex, screenTex, normalTex, noiseTex, volumeTex,
+                       transferTex MESH_ARGS);
+}
+
+// Culling (see codegen::proxies and raymarcher.rs): every frame the bounding spheres of the
+// scene's top-level nodes are drawn over the CULL_TILE pixel squares of the image they can be
+// seen in: proxies_object finds each one's tiles, proxies_mesh covers them with a quad and
+// proxies_fragment sets the node's bit in the mask of each tile it covers. culled_main draws
+// the image as fragment_main does, marching each pixel's rays without the nodes its tile's
+// mask leaves out. Mesh shaders came with Metal 3; older systems compile the shader without.
+#if __METAL_VERSION__ >= 300
+constant uint CULL_TILE = 16;   // Pixels along a tile's side (CULL_TILE in raymarcher.rs)
+constant uint MAX_PROXIES = 32; // Bits in a mask (MAX_PROXIES in codegen.rs)
+
+// Tiles across and down the image
+uint2 cullTiles(constant Uniforms& uniforms) {
+    return (uint2(uniforms.resolution) + CULL_TILE - 1) / CULL_TILE;
+}
+
+// The tiles a sphere can be seen in, from the top left: the first column and row, and the
+// ones after the last. Only a pinhole camera's rays leave one point in straight lines through
+// the screen, so with a lens or stereo it is every tile, as it is for a sphere around the
+// camera and for none (radius -1), which sceneMap doesn't leave out.
+uint4 proxyTiles(float4 sphere, constant Uniforms& uniforms) {
+    uint4 every = uint4(uint2(0), cullTiles(uniforms));
+    bool pinhole = uniforms.projection == 0 || uniforms.lens_amount <= 0.0;
+    if (sphere.w < 0.0 || uniforms.stereo != 0 || !pinhole) {
+        return every;
+    }
+    float3 forward = normalize(uniforms.camera_target - uniforms.camera_pos);
+    float3 right = normalize(cross(float3(0.0, 1.0, 0.0), forward));
+    float3 up = cross(forward, right);
+    float3 offset = sphere.xyz - uniforms.camera_pos;
+    float3 center = float3(dot(offset, right), dot(offset, up), dot(offset, forward));
+    float radius = sphere.w;
+    if (center.z + radius <= 0.0) {
+        return uint4(0);
+    }
+    if (center.z - radius <= 1e-3) {
+        return every;
+    }
+    // The view uv (as screenView gives it) the corners of the cube around it project to
+    float2 low = float2(1e10);
+    float2 high = float2(-1e10);
+    for (uint i = 0; i < 8; i++) {
+        float3 corner = center + radius * float3(i & 1 ? 1.0 : -1.0, i & 2 ? 1.0 : -1.0,
+                                                 i & 4 ? 1.0 : -1.0);
+        low = min(low, corner.xy / corner.z);
+        high = max(high, corner.xy / corner.z);
+    }
+    // Back through screenView to pixels from the top left, a pixel wider each way for the rays
+    // spread over a pixel
+    float aspect =
+        uniforms.resolution.x * uniforms.region.w / (uniforms.resolution.y * uniforms.region.z);
+    float2 scale = float2(0.5 / aspect, 0.5);
+    float2 a = (low * scale + 0.5 - uniforms.region.xy) / uniforms.region.zw;
+    float2 b = (high * scale + 0.5 - uniforms.region.xy) / uniforms.region.zw;
+    float2 from = float2(a.x, 1.0 - b.y) * uniforms.resolution - 1.0;
+    float2 to = float2(b.x, 1.0 - a.y) * uniforms.resolution + 1.0;
+    float2 tiles = float2(every.zw);
+    float2 first = clamp(floor(from / float(CULL_TILE)), float2(0.0), tiles);
+    float2 last = clamp(ceil(to / float(CULL_TILE)), float2(0.0), tiles);
+    return uint4(uint2(first), uint2(last));
+}
+
+struct ProxyPayload {
+    uint4 tiles[MAX_PROXIES]; // Of each proxy (see proxyTiles)
+};
+
+struct ProxyVertex {
+    float4 position [[position]];
+};
+
+struct ProxyPrimitive {
+    uint proxy;
+};
+
+struct ProxyFragment {
+    ProxyVertex at;
+    ProxyPrimitive primitive;
+};
+
+using ProxyMesh = metal::mesh<ProxyVertex, ProxyPrimitive, 4, 2, topology::triangle>;
+
+// A thread per proxy; a mesh threadgroup for each
+[[object]] void proxies_object(object_data ProxyPayload& payload [[payload]],
+                               mesh_grid_properties grid,
+                               uint i [[thread_index_in_threadgroup]],
+                               constant Uniforms& uniforms [[buffer(0)]],
+                               constant float4* proxies [[buffer(1)]],
+                               constant uint& count [[buffer(2)]]) {
+    if (i < count) {
+        payload.tiles[i] = proxyTiles(proxies[i], uniforms);
+    }
+    if (i == 0) {
+        grid.set_threadgroups_per_grid(uint3(count, 1, 1));
+    }
+}
+
+// The quad over a proxy's tiles, in a target with a pixel per tile (none if it has none)
+[[mesh]] void proxies_mesh(ProxyMesh output,
+                           const object_data ProxyPayload& payload [[payload]],
+                           uint proxy [[threadgroup_position_in_grid]],
+                           constant Uniforms& uniforms [[buffer(0)]]) {
+    uint4 tiles = payload.tiles[proxy];
+    if (tiles.x >= tiles.z || tiles.y >= tiles.w) {
+        output.set_primitive_count(0);
+        return;
+    }
+    float2 size = float2(cullTiles(uniforms));
+    for (uint i = 0; i < 4; i++) {
+        float2 corner = float2(uint2(i & 1 ? tiles.z : tiles.x, i & 2 ? tiles.w : tiles.y)) / size;
+        output.set_vertex(i, ProxyVertex{float4(corner.x * 2.0 - 1.0, 1.0 - corner.y * 2.0,
+                                                0.0, 1.0)});
+    }
+    uchar indices[6] = {0, 1, 2, 2, 1, 3};
+    for (uint i = 0; i < 6; i++) {
+        output.set_index(i, indices[i]);
+    }
+    output.set_primitive(0, ProxyPrimitive{proxy});
+    output.set_primitive(1, ProxyPrimitive{proxy});
+    output.set_primitive_count(2);
+}
+
+fragment void proxies_fragment(ProxyFragment in [[stage_in]],
+                               constant Uniforms& uniforms [[buffer(0)]],
+                               device atomic_uint* masks [[buffer(1)]]) {
+    uint2 tile = uint2(in.at.position.xy);
+    atomic_fetch_or_explicit(&masks[tile.y * cullTiles(uniforms).x + tile.x],
+                             1u << in.primitive.proxy, memory_order_relaxed);
+}
+
+fragment float4 culled_main(VertexOut in [[stage_in]],
+                            constant Uniforms& uniforms [[buffer(0)]],
+                            device const uint* masks [[buffer(1)]],
+                            texture2d<float> videoTex [[texture(0)]],
+                            texture2d<float> screenTex [[texture(1)]],
+                            texture2d<float> normalTex [[texture(3)]],
+                            texture2d<float> noiseTex [[texture(4)]],
+                            texture3d<float> volumeTex [[texture(5)]],
+                            texture2d<float> transferTex [[texture(6)]] MESH_BUFFERS) {
+    uint2 tile = uint2(in.position.xy) / CULL_TILE;
+    uint cull = masks[tile.y * cullTiles(uniforms).x + tile.x];
+    return renderPixel(in.uv, uniforms.aa, uniforms, videoTex, screenTex, normalTex, noiseTex,
+                       volumeTex, transferTex MESH_ARGS, cull);
+}
+#endif
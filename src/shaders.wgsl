@@ -0,0 +1,1440 @@
+// WGSL counterpart of shaders.metal for the wgpu backend (same uniforms and lighting;
+// video and screen textures are not supported here). wgpu translates it with naga into
+// SPIR-V, HLSL or MSL for the platform's GPU API.
+
+struct Uniforms {
+    resolution: vec2<f32>,
+    time: f32,
+    mouse: vec2<f32>,
+    camera_pos: vec3<f32>,
+    _pad0: f32, // float3 occupies 16 bytes in the MSL layout
+    video_mix: f32,
+    screen_mix: f32,
+    max_steps: u32,
+    epsilon: f32,
+    max_distance: f32,
+    shadow_steps: u32,
+    ao_samples: u32,
+    aa: u32,
+    camera_target: vec3<f32>,
+    _pad1: f32,
+    light_color: vec3<f32>,
+    fog_density: f32, // 0 = no fog
+    params: array<vec4<f32>, 16>, // Animated scene parameters, read by the generated scene code
+    audio: vec4<f32>, // Audio input: bass, mid, high, overall level (0..1)
+    beat: f32, // 1 on a detected beat, decaying to 0
+    selected: f32, // Object id picked with the mouse (-1 for none)
+    guides: f32, // 1 draws the ground grid and axes
+    samples: f32, // Path tracing: paths per pixel averaged so far
+    clock: vec4<f32>, // Musical clock: beats since the start, beat phase, bar phase, bpm
+    projection: u32, // 0 pinhole, 1 fisheye, 2 equisolid fisheye, 3 Panini, 4 cylindrical
+    lens_amount: f32, // 0 looks like the pinhole view, 1 is the full projection
+    stereo: u32, // 0 off, 1 red/cyan anaglyph, 2 side by side
+    ipd: f32, // Distance between the eyes
+    convergence: f32, // Distance that shows with no parallax (at the screen)
+    toon_bands: u32, // Toon shading's lit steps, 0 for standard shading
+    toon_rim: f32, // Toon rim light brightness
+    toon_outline: f32, // Toon ink width in pixels
+    edge_color: vec3<f32>, // Edge post pass: line color
+    edge_mode: u32, // 0 off, 1 over the image, 2 blueprint
+    edge_paper: vec3<f32>, // Blueprint background
+    edge_width: f32, // Line width in pixels
+    edge_depth: f32, // Sobel response to distance (relative to it) that makes a line
+    edge_normal: f32, // Sobel response to the normal that makes a line
+    fog_color: vec4<f32>, // w 1 fogs towards this color, 0 towards the sky's
+    height_fog: f32, // Height fog density at fog_height (0 = none)
+    fog_height: f32,
+    fog_falloff: f32, // How fast height fog thins with height
+    fog_sun: f32, // Sunlight scattered by the fog towards the eye
+    shaft_samples: u32, // Light shaft march steps (0 = no shafts)
+    shaft_intensity: f32,
+    caustic_intensity: f32, // Fake caustics under glass (0 = none)
+    caustic_sharpness: f32,
+    floor_color: vec3<f32>, // The floor's (see Floor in scene.rs), or its first squares
+    floor_checker: f32, // Square width, 0 for one color
+    floor_color2: vec3<f32>,
+    floor_reflection: f32, // Share of the scene mirrored looking straight down
+    floor_contact: f32, // Height over which things darken the floor below them, 0 for none
+    seed: u32, // Offsets the path tracer's random numbers and the light shafts' jitter
+    tile_size: u32, // Adaptive quality's tile side in pixels (0 = off; aa is for its detailed tiles)
+    region: vec4<f32>, // Part of the view drawn: x, y (from the bottom left), width, height
+    sites: array<vec4<f32>, 64>, // Voronoi cell sites of objects with cells (xyz), in object order
+    volume_center: vec3<f32>, // Volume dataset's box (see volume.rs)
+    volume_mode: u32, // 0 composite, 1 iso-surface
+    volume_size: vec3<f32>, // Half the box's sides
+    volume_iso: f32, // Value the iso-surface is drawn at
+    volume_window: vec2<f32>, // Data values mapped to 0 and 1
+    volume_density: f32, // Opacity per unit of distance at full opacity
+    volume_steps: u32, // Samples along the box's diagonal (0 = no volume)
+    sun_dir: vec3<f32>, // Towards the sun, normalized (see sun.rs)
+};
+
+@group(0) @binding(0) var<uniform> uniforms: Uniforms;
+@group(0) @binding(1) var normalMap: texture_2d<f32>; // The scene's, or a flat texel
+@group(0) @binding(2) var normalSampler: sampler;
+@group(0) @binding(3) var noiseTable: texture_2d<f32>; // Permutation and gradients (see noise.rs)
+@group(0) @binding(4) var volumeTex: texture_3d<f32>; // The scene's volume, or an empty voxel
+@group(0) @binding(5) var transferTex: texture_2d<f32>; // Its transfer function
+@group(0) @binding(6) var volumeSampler: sampler; // Linear, clamped to the edges
+
+struct VertexOut {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vertex_main(@builtin(vertex_index) vertex_id: u32) -> VertexOut {
+    // Fullscreen quad as two triangles
+    var positions = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(-1.0, 1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0)
+    );
+    let position = positions[vertex_id];
+    var out: VertexOut;
+    out.position = vec4<f32>(position, 0.0, 1.0);
+    out.uv = position * 0.5 + 0.5;
+    return out;
+}
+
+fn sdSphere(p: vec3<f32>, radius: f32) -> f32 {
+    return length(p) - radius;
+}
+
+fn sdBox(p: vec3<f32>, size: vec3<f32>) -> f32 {
+    let q = abs(p) - size;
+    return length(max(q, vec3<f32>(0.0))) + min(max(q.x, max(q.y, q.z)), 0.0);
+}
+
+fn sdPlane(p: vec3<f32>, n: vec3<f32>, h: f32) -> f32 {
+    return dot(p, n) + h;
+}
+
+fn sdCapsule(p: vec3<f32>, a: vec3<f32>, b: vec3<f32>, r: f32) -> f32 {
+    let pa = p - a;
+    let ba = b - a;
+    let h = clamp(dot(pa, ba) / max(dot(ba, ba), 1e-8), 0.0, 1.0);
+    return length(pa - ba * h) - r;
+}
+
+fn smin(a: f32, b: f32, k: f32) -> f32 {
+    let h = clamp(0.5 + 0.5 * (b - a) / k, 0.0, 1.0);
+    return mix(b, a, h) - k * h * (1.0 - h);
+}
+
+fn opUnion(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    return select(b, a, a.x < b.x);
+}
+
+fn opSmoothUnion(a: vec2<f32>, b: vec2<f32>, k: f32) -> vec2<f32> {
+    return vec2<f32>(smin(a.x, b.x, k), select(b.y, a.y, a.x < b.x));
+}
+
+fn opSubtract(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(max(a.x, -b.x), a.y);
+}
+
+fn opSmoothSubtract(a: vec2<f32>, b: vec2<f32>, k: f32) -> vec2<f32> {
+    let h = clamp(0.5 - 0.5 * (a.x + b.x) / k, 0.0, 1.0);
+    return vec2<f32>(mix(a.x, -b.x, h) + k * h * (1.0 - h), a.y);
+}
+
+fn opIntersect(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    return select(b, a, a.x > b.x);
+}
+
+// Diffuse light a point light gives a surface at p (normal n), falling off with distance
+fn pointLight(p: vec3<f32>, n: vec3<f32>, position: vec3<f32>, color: vec3<f32>, intensity: f32) -> vec3<f32> {
+    let l = position - p;
+    let d2 = max(dot(l, l), 1e-6);
+    return color * intensity * max(dot(n, l * inverseSqrt(d2)), 0.0) / (1.0 + d2);
+}
+
+// @noise@
+
+// @scene@
+
+fn calcNormal(p: vec3<f32>) -> vec3<f32> {
+    let e = vec2<f32>(0.001, 0.0);
+    return normalize(vec3<f32>(
+        sceneMap(p + e.xyy).x - sceneMap(p - e.xyy).x,
+        sceneMap(p + e.yxy).x - sceneMap(p - e.yxy).x,
+        sceneMap(p + e.yyx).x - sceneMap(p - e.yyx).x
+    ));
+}
+
+// Tangent-space normal map detail on top of normal n at p, with bump = (intensity, tiling)
+// from sceneBump: the map is sampled on the planes facing the x, y and z axes and each sample
+// is blended onto n (whiteout blend), weighted by how squarely n faces that axis
+fn bumpNormal(p: vec3<f32>, n: vec3<f32>, bump: vec2<f32>) -> vec3<f32> {
+    if (bump.x == 0.0) {
+        return n;
+    }
+    let q = p * bump.y;
+    var weights = pow(abs(n), vec3<f32>(4.0));
+    weights /= weights.x + weights.y + weights.z;
+    var tx = textureSampleLevel(normalMap, normalSampler, q.zy, 0.0).xyz * 2.0 - 1.0;
+    var ty = textureSampleLevel(normalMap, normalSampler, q.xz, 0.0).xyz * 2.0 - 1.0;
+    var tz = textureSampleLevel(normalMap, normalSampler, q.xy, 0.0).xyz * 2.0 - 1.0;
+    tx = vec3<f32>(tx.xy * bump.x + n.zy, abs(tx.z) * n.x);
+    ty = vec3<f32>(ty.xy * bump.x + n.xz, abs(ty.z) * n.y);
+    tz = vec3<f32>(tz.xy * bump.x + n.xy, abs(tz.z) * n.z);
+    return normalize(tx.zyx * weights.x + ty.xzy * weights.y + tz * weights.z);
+}
+
+// Color varied by the shared noise at p, with noise = (amount, scale, kind) from sceneNoise;
+// kind indexes NoiseKind (see scene.rs)
+fn noiseColor(p: vec3<f32>, color: vec3<f32>, noise: vec3<f32>) -> vec3<f32> {
+    if (noise.x == 0.0) {
+        return color;
+    }
+    let q = p * noise.y;
+    let kind = u32(noise.z);
+    var n = vec3<f32>(0.0);
+    if (kind == 0u) {
+        n = vec3<f32>(valueNoise(q));
+    } else if (kind == 1u) {
+        n = vec3<f32>(perlinNoise(q));
+    } else if (kind == 2u) {
+        n = vec3<f32>(simplexNoise(q));
+    } else if (kind == 3u) {
+        n = vec3<f32>(fbm(q, 5));
+    } else {
+        n = clamp(curlNoise(q) * 0.25, vec3<f32>(-1.0), vec3<f32>(1.0));
+    }
+    return color * max(1.0 + noise.x * n, vec3<f32>(0.0));
+}
+
+// Thin-film interference (see Iridescence in scene.rs): the share of red, green and blue
+// light a film of film.x nanometres (swung by up to film.z with the noise) reflects when seen
+// at `cosine` to its normal. Light off the film's bottom travels further, through the film,
+// and comes back half a wave out from the reflection off its top, so each wavelength is
+// reflected most where the delay is an odd number of half waves; a film much thinner than the
+// light's wavelengths looks dark.
+const FILM_IOR: f32 = 1.33; // Soapy water
+
+fn thinFilm(p: vec3<f32>, cosine: f32, film: vec4<f32>) -> vec3<f32> {
+    var thickness = film.x;
+    if (film.z != 0.0) {
+        thickness += film.z * fbm(p * film.w, 4);
+    }
+    let inside = sqrt(max(1.0 - (1.0 - cosine * cosine) / (FILM_IOR * FILM_IOR), 0.0));
+    let delay = 2.0 * FILM_IOR * max(thickness, 0.0) * inside;
+    return 0.5 - 0.5 * cos(2.0 * 3.14159265 * delay / vec3<f32>(650.0, 532.0, 450.0));
+}
+
+// Color of an opaque object with its film's colors mixed in by the film's amount
+fn iridescent(color: vec3<f32>, p: vec3<f32>, cosine: f32, film: vec4<f32>) -> vec3<f32> {
+    if (film.y == 0.0) {
+        return color;
+    }
+    return mix(color, thinFilm(p, cosine, film), film.y);
+}
+
+// Weights of the light reflected off glass with a film, by color: as much as without it on
+// average
+fn filmReflection(p: vec3<f32>, cosine: f32, film: vec4<f32>) -> vec3<f32> {
+    if (film.y == 0.0) {
+        return vec3<f32>(1.0);
+    }
+    return mix(vec3<f32>(1.0), 2.0 * thinFilm(p, cosine, film), film.y);
+}
+
+// Distance to the nearest surface that casts a shadow. Glass lets light through (untinted:
+// shadow rays don't refract), so it reads as far away and the ray steps on through it.
+fn shadowMap(p: vec3<f32>) -> f32 {
+    let hit = sceneMap(p);
+    return select(hit.x, 1e10, sceneGlass(hit.y).x > 0.0);
+}
+
+fn softShadow(ro: vec3<f32>, rd: vec3<f32>, mint: f32, maxt: f32) -> f32 {
+    var res = 1.0;
+    var t = mint;
+    for (var i = 0u; i < uniforms.shadow_steps; i++) {
+        let h = shadowMap(ro + rd * t);
+        res = min(res, 8.0 * h / t);
+        t += clamp(h, 0.02, 0.10);
+        if (h < 0.001 || t > maxt) {
+            break;
+        }
+    }
+    return clamp(res, 0.0, 1.0);
+}
+
+// Share (0-1) of a cone of `radius` covered by a surface `d` from its axis, taking the surface
+// as flat across the cone: half where the axis grazes it
+fn coverage(d: f32, radius: f32) -> f32 {
+    return clamp(0.5 - 0.5 * d / radius, 0.0, 1.0);
+}
+
+// Soft shadow for cone tracing: the share of the sun's disc (SUN_RADIUS across per unit of
+// distance) left uncovered by the surfaces the shadow ray passes
+const SUN_RADIUS: f32 = 0.1;
+
+fn coneShadow(ro: vec3<f32>, rd: vec3<f32>, mint: f32, maxt: f32) -> f32 {
+    var visible = 1.0;
+    var t = mint;
+    for (var i = 0u; i < uniforms.shadow_steps; i++) {
+        let h = shadowMap(ro + rd * t);
+        visible = min(visible, 1.0 - coverage(h, t * SUN_RADIUS));
+        t += clamp(h, 0.02, 0.10);
+        if (visible < 0.001 || t > maxt) {
+            break;
+        }
+    }
+    return visible;
+}
+
+fn calcAO(pos: vec3<f32>, nor: vec3<f32>) -> f32 {
+    var occ = 0.0;
+    var sca = 1.0;
+    let last = f32(max(uniforms.ao_samples, 2u) - 1u);
+    for (var i = 0u; i < uniforms.ao_samples; i++) {
+        let hr = 0.01 + 0.12 * f32(i) / last;
+        let dd = sceneMap(nor * hr + pos).x;
+        occ += -(dd - hr) * sca;
+        sca *= 0.95;
+    }
+    return clamp(1.0 - 3.0 * occ, 0.0, 1.0);
+}
+
+fn skyColor(rd: vec3<f32>) -> vec3<f32> {
+    return mix(vec3<f32>(0.2, 0.3, 0.5), vec3<f32>(0.7, 0.8, 0.9), rd.y * 0.5 + 0.5);
+}
+
+// Height fog density at height y, capped where it would overflow far below the fog
+fn heightFogDensity(y: f32) -> f32 {
+    return uniforms.height_fog * exp(min(-uniforms.fog_falloff * (y - uniforms.fog_height), 80.0));
+}
+
+// `color` seen through the fog between ro and t along rd: exponential distance fog plus height
+// fog, its density integrated along the ray in closed form, towards the fog color (the sky's
+// by default) brightened towards the sun by the light it scatters
+fn fogged(color: vec3<f32>, ro: vec3<f32>, rd: vec3<f32>, t: f32) -> vec3<f32> {
+    var depth = uniforms.fog_density * t;
+    if (uniforms.height_fog > 0.0) {
+        let start = heightFogDensity(ro.y);
+        let rate = uniforms.fog_falloff * rd.y;
+        if (abs(rate) < 1e-4) {
+            depth += start * t;
+        } else {
+            depth += (start - heightFogDensity(ro.y + rd.y * t)) / rate;
+        }
+    }
+    if (depth <= 0.0) {
+        return color;
+    }
+    var tint = select(skyColor(rd), uniforms.fog_color.rgb, uniforms.fog_color.w > 0.5);
+    let sun = max(dot(rd, uniforms.sun_dir), 0.0);
+    tint += uniforms.light_color * uniforms.fog_sun * pow(sun, 8.0);
+    return mix(color, tint, 1.0 - exp(-depth));
+}
+
+// Lines of the ground grid (1 unit apart on y = 0) and the X, Y and Z axes, drawn over `color`
+// where they are in front of the surface the ray hit at `depth` and fading with distance. Line
+// widths follow the pixel's footprint, so they stay about a pixel wide and antialiased.
+fn withGuides(base: vec3<f32>, ro: vec3<f32>, rd: vec3<f32>, depth: f32) -> vec3<f32> {
+    if (uniforms.guides < 0.5) {
+        return base;
+    }
+    var color = base;
+    let pixel = 1.5 * uniforms.region.w / uniforms.resolution.y; // Over a pixel and a half
+    let limit = depth * 1.001 + 0.01;        // Still drawn on a floor at y = 0
+
+    // Grid and the X (red) and Z (blue) axes where the ray crosses the ground
+    let t = -ro.y / rd.y;
+    if (t > 0.0 && t < limit) {
+        let q = (ro + rd * t).xz;
+        let width = t * pixel / max(abs(rd.y), 0.1);
+        let fade = (1.0 - smoothstep(10.0, 40.0, t)) * (1.0 - smoothstep(0.1, 0.4, width));
+        let g = abs(fract(q - 0.5) - 0.5);
+        let grid = 1.0 - smoothstep(0.0, width, min(g.x, g.y));
+        color = mix(color, vec3<f32>(0.9), grid * fade * 0.35);
+        let axisFade = 1.0 - smoothstep(20.0, 80.0, t);
+        color = mix(color, vec3<f32>(0.9, 0.2, 0.2), (1.0 - smoothstep(0.0, width * 2.0, abs(q.y))) * axisFade);
+        color = mix(color, vec3<f32>(0.2, 0.4, 1.0), (1.0 - smoothstep(0.0, width * 2.0, abs(q.x))) * axisFade);
+    }
+
+    // Y axis (green): where the ray passes closest to the vertical line through the origin
+    let across = rd.xz;
+    if (dot(across, across) > 1e-8) {
+        let s = -dot(ro.xz, across) / dot(across, across);
+        if (s > 0.0 && s < limit) {
+            let d = length(ro.xz + across * s);
+            let width = s * pixel * 2.0;
+            color = mix(color, vec3<f32>(0.3, 0.85, 0.2), 1.0 - smoothstep(0.0, width, d));
+        }
+    }
+    return color;
+}
+
+// Portals: a ray reaching one carries on from the matching point of its twin, keeping the
+// distance travelled, up to PORTAL_JUMPS times (after that portals are passed like air).
+// Steps are cut short at the next portal, so a ray never steps into what is behind it.
+const PORTAL_JUMPS: u32 = 4u;
+
+// Where a ray ends up: the point it hit and the direction it got there in (turned by any
+// portals on the way), the distance travelled and the object's id. t is -1 for no hit, with
+// rd the direction it left the scene in.
+struct Hit {
+    p: vec3<f32>,
+    rd: vec3<f32>,
+    t: f32,
+    id: f32,
+}
+
+// First surface along the ray. A `shadow` ray passes through glass, as light does, and
+// through portals without being taken anywhere: lights shine in plain space.
+fn traceRay(origin: vec3<f32>, direction: vec3<f32>, shadow: bool) -> Hit {
+    var ro = origin;
+    var rd = direction;
+    var base = 0.0; // Travelled up to ro
+    var jumps = 0u;
+    var portal = select(scenePortal(ro, rd), vec2<f32>(1e10, -1.0), shadow);
+    var t = 0.0;
+    for (var i = 0u; i < uniforms.max_steps; i++) {
+        if (t >= portal.x) {
+            ro = portalPoint(ro + rd * portal.x, portal.y);
+            rd = portalDirection(rd, portal.y);
+            base += portal.x;
+            t = 0.0;
+            jumps++;
+            portal = select(scenePortal(ro, rd), vec2<f32>(1e10, -1.0), jumps >= PORTAL_JUMPS);
+        }
+        let hit = sceneMap(ro + rd * t);
+        if (shadow && sceneGlass(hit.y).x > 0.0) {
+            t += max(abs(hit.x), 0.02);
+            continue;
+        }
+        if (hit.x < uniforms.epsilon) {
+            return Hit(ro + rd * t, rd, base + t, hit.y);
+        }
+        if (base + t > uniforms.max_distance) {
+            break;
+        }
+        t = min(t + hit.x * 0.8, portal.x);
+    }
+    return Hit(ro, rd, -1.0, 0.0);
+}
+
+// Glass: light entering a glass object refracts, travels through it losing the share its
+// color doesn't pass (Beer-Lambert: the color to the power of density times the distance),
+// and refracts out where it reaches the surface again, or reflects back in where it meets it
+// too shallow to leave. The inside is where the scene's distance is negative, so an object
+// subtracted from the glass is a pocket of air: the ray leaves into it and enters again on
+// the far side. GLASS_EVENTS caps the surfaces passed on the way.
+const GLASS_EVENTS: u32 = 8u;
+
+// Share of light reflected where it crosses from index of refraction `a` to `b`, at `cosine`
+// to the normal on the lower index side (Schlick's approximation)
+fn fresnel(cosine: f32, a: f32, b: f32) -> f32 {
+    let r = (a - b) / (a + b);
+    return r * r + (1.0 - r * r) * pow(1.0 - clamp(cosine, 0.0, 1.0), 5.0);
+}
+
+// Where and in which direction a ray leaves the glass it meets at p (normal n), and the
+// share of light that makes it through
+struct Passage {
+    p: vec3<f32>,
+    rd: vec3<f32>,
+    transmittance: vec3<f32>,
+}
+
+fn throughGlass(p: vec3<f32>, rd: vec3<f32>, n: vec3<f32>, glass: vec2<f32>, color: vec3<f32>) -> Passage {
+    let absorption = -log(max(color, vec3<f32>(1e-3))) * glass.y;
+    var q = p - n * 0.01;
+    var dir = refract(rd, n, 1.0 / glass.x);
+    var transmittance = vec3<f32>(1.0);
+    for (var event = 0u; event < GLASS_EVENTS; event++) {
+        var t = 0.0;
+        for (var i = 0u; i < uniforms.max_steps; i++) {
+            let d = -sceneMap(q + dir * t).x;
+            if (d < uniforms.epsilon || t > uniforms.max_distance) {
+                break;
+            }
+            t += d * 0.8;
+        }
+        transmittance *= exp(-absorption * t);
+        q += dir * t;
+        let m = calcNormal(q); // Outwards
+        let leaving = refract(dir, -m, glass.x);
+        if (dot(leaving, leaving) == 0.0) {
+            // Total internal reflection
+            dir = reflect(dir, m);
+            q -= m * 0.01;
+            continue;
+        }
+        transmittance *= 1.0 - fresnel(dot(leaving, m), glass.x, 1.0);
+        return Passage(q + m * 0.01, leaving, transmittance);
+    }
+    return Passage(q, dir, vec3<f32>(0.0));
+}
+
+// Fake caustics (see Caustics in scene.rs): how much more (or less) sunlight reaches p for
+// the glass the sun shines through on its way. Marching towards the sun, the ray steps on
+// through glass (as shadow rays do), measuring how much of it the light crosses: thicker glass
+// focuses more, and the effect fades out towards its rim. The net of lines is laid out across
+// the sun's beam from where the light left the glass, so it moves with the glass.
+const CAUSTIC_REACH: f32 = 10.0; // As far as shadows look
+const CAUSTIC_SCALE: f32 = 6.0; // Waves per unit
+
+// Four crossing wave trains, each bent by the ones before: their sum is zero along curves
+// that close into cells, which make the net of lines (0-1)
+fn causticPattern(q: vec2<f32>) -> f32 {
+    var at = q * CAUSTIC_SCALE;
+    var sum = 0.0;
+    for (var k = 0u; k < 4u; k++) {
+        let angle = f32(k) * 2.4 + 0.4;
+        let across = vec2<f32>(cos(angle), sin(angle));
+        let wave = sin(dot(at, across) + uniforms.time * (0.6 + 0.25 * f32(k)));
+        sum += wave;
+        at += 0.5 * wave * vec2<f32>(-across.y, across.x);
+    }
+    return 1.0 - min(abs(sum), 1.0);
+}
+
+fn caustics(p: vec3<f32>, sun: vec3<f32>) -> vec3<f32> {
+    var t = 0.02;
+    var thickness = 0.0;
+    var outlet = vec3<f32>(0.0); // Where the light left the glass
+    var tint = vec3<f32>(1.0);
+    for (var i = 0u; i < uniforms.max_steps; i++) {
+        let q = p + sun * t;
+        let hit = sceneMap(q);
+        if (sceneGlass(hit.y).x == 0.0) {
+            if (hit.x < uniforms.epsilon) {
+                return vec3<f32>(0.0); // In shadow
+            }
+            t += hit.x;
+        } else {
+            let stride = max(abs(hit.x), 0.02);
+            if (hit.x < 0.0) {
+                if (thickness == 0.0) {
+                    outlet = q;
+                    tint = sceneColor(hit.y);
+                }
+                thickness += stride;
+            }
+            t += stride;
+        }
+        if (t > CAUSTIC_REACH) {
+            break;
+        }
+    }
+    let focus = 1.0 - exp(-2.0 * thickness);
+    let across = normalize(cross(sun, vec3<f32>(0.0, 1.0, 0.0)));
+    let pattern = causticPattern(vec2<f32>(dot(outlet, across), dot(outlet, cross(across, sun))));
+    // Light gathered into the lines is taken from between them
+    let gain = 3.0 * pow(pattern, uniforms.caustic_sharpness) * tint - 0.5;
+    return max(uniforms.caustic_intensity * focus * gain, vec3<f32>(-1.0));
+}
+
+// Toon shading: light stepped into toon_bands levels by its brightest channel, so each band
+// keeps the light's color
+fn toonLight(light: vec3<f32>) -> vec3<f32> {
+    let level = max(light.r, max(light.g, light.b));
+    let bands = f32(uniforms.toon_bands);
+    return light * (ceil(level * bands) / bands) / max(level, 1e-4);
+}
+
+// Toon rim light: a hard-edged band where the surface turns away from the view, on the side
+// the sun doesn't reach (a back light)
+fn toonRim(n: vec3<f32>, rd: vec3<f32>) -> vec3<f32> {
+    let edge = 1.0 - max(dot(n, -rd), 0.0);
+    return uniforms.toon_rim * smoothstep(0.8, 0.85, edge) * uniforms.light_color;
+}
+
+// The sun's highlight on a specular object (see Specular in scene.rs) seen from v, to be
+// weighted by the light reaching p: an anisotropic GGX lobe whose roughness specular.y is
+// stretched across the tangent by anisotropy specular.z (along it when negative), with the
+// Kelemen visibility term and no Fresnel falloff, so intensity specular.x is the reflectance
+fn highlight(p: vec3<f32>, n: vec3<f32>, v: vec3<f32>, l: vec3<f32>, id: f32, specular: vec4<f32>) -> f32 {
+    var tangent = sceneTangent(p, id);
+    tangent -= n * dot(tangent, n);
+    if (dot(tangent, tangent) < 1e-8) {
+        // Brushed straight into the surface: any direction across it will do
+        tangent = cross(n, select(vec3<f32>(1.0, 0.0, 0.0), vec3<f32>(0.0, 1.0, 0.0), abs(n.x) > 0.9));
+    }
+    tangent = normalize(tangent);
+    let bitangent = cross(n, tangent);
+    let h = normalize(l + v);
+    let alpha = max(specular.y * specular.y, 1e-3);
+    let along = max(alpha * (1.0 - specular.z), 1e-3); // Roughness along the tangent
+    let across = max(alpha * (1.0 + specular.z), 1e-3);
+    let area = along * across;
+    let d = vec3<f32>(across * dot(tangent, h), along * dot(bitangent, h), area * dot(n, h));
+    let w = area / dot(d, d);
+    let distribution = area * w * w / 3.14159265;
+    let visibility = 0.25 / max(dot(l, h) * dot(l, h), 1e-4);
+    return specular.x * distribution * visibility;
+}
+
+// The floor's color at p: squares of floor_checker alternating between its two colors
+fn floorColor(p: vec3<f32>) -> vec3<f32> {
+    if (uniforms.floor_checker <= 0.0) {
+        return uniforms.floor_color;
+    }
+    let cell = floor(p.xz / uniforms.floor_checker);
+    return mix(uniforms.floor_color, uniforms.floor_color2, abs(cell.x + cell.y) % 2.0);
+}
+
+const CONTACT_STEPS: u32 = 4u;
+
+// Share of the open space above the floor at p left by whatever stands there, sampled up to
+// floor_contact: 1 in the open, towards 0 under and beside objects. The floor itself is never
+// closer than the height it is sampled at, so it does not darken itself.
+fn contactShadow(p: vec3<f32>) -> f32 {
+    var space = 0.0;
+    for (var i = 1u; i <= CONTACT_STEPS; i++) {
+        let h = uniforms.floor_contact * f32(i) / f32(CONTACT_STEPS);
+        space += clamp(sceneMap(p + vec3<f32>(0.0, h, 0.0)).x / h, 0.0, 1.0);
+    }
+    return space / f32(CONTACT_STEPS);
+}
+
+// Share of the scene the floor mirrors, seen at `cosine` to its normal: floor_reflection
+// looking straight down, rising towards all of it at grazing angles (Schlick's approximation)
+fn floorMirror(cosine: f32) -> f32 {
+    let r = uniforms.floor_reflection;
+    if (r <= 0.0) {
+        return 0.0;
+    }
+    return r + (1.0 - r) * pow(1.0 - clamp(cosine, 0.0, 1.0), 5.0);
+}
+
+// Lit color of object `id` at p, seen along rd from t away
+fn shade(p: vec3<f32>, id: f32, rd: vec3<f32>, t: f32) -> vec3<f32> {
+    let normal = bumpNormal(p, calcNormal(p), sceneBump(id));
+    let lightDir = uniforms.sun_dir;
+    var diffuse = max(0.0, dot(normal, lightDir));
+    var ambient = vec3<f32>(0.15, 0.15, 0.2);
+    if (uniforms.shadow_steps > 0u && diffuse > 0.0) {
+        if (uniforms.aa == 0u) {
+            diffuse *= coneShadow(p + normal * 0.01, lightDir, 0.02, 10.0);
+        } else {
+            diffuse *= softShadow(p + normal * 0.01, lightDir, 0.02, 10.0);
+        }
+    }
+    if (uniforms.ao_samples > 0u) {
+        ambient *= calcAO(p, normal);
+    }
+    let ground = sceneFloor(id);
+    if (ground && uniforms.floor_contact > 0.0) {
+        let contact = contactShadow(p);
+        ambient *= contact;
+        diffuse *= contact;
+    }
+    var light = diffuse * uniforms.light_color + sceneLight(p, normal);
+    if (uniforms.caustic_intensity > 0.0 && diffuse > 0.0) {
+        light += diffuse * uniforms.light_color * caustics(p + normal * 0.01, lightDir);
+    }
+    if (uniforms.toon_bands > 0u) {
+        light = toonLight(light);
+    }
+    var base = noiseColor(p, sceneColorAt(p, id, sceneColor(id)), sceneNoise(id));
+    if (ground) {
+        base = floorColor(p);
+    }
+    let albedo = iridescent(base, p, max(dot(normal, -rd), 0.0), sceneFilm(id));
+    var color = ambient + albedo * light;
+    let specular = sceneSpecular(id);
+    if (specular.x > 0.0 && diffuse > 0.0) {
+        // diffuse is the cosine of the sun's angle, shadowed
+        color += highlight(p, normal, -rd, lightDir, id, specular) * diffuse * uniforms.light_color;
+    }
+    // Glowing objects shine whatever lights them
+    color += sceneEmission(id);
+    if (uniforms.toon_bands > 0u) {
+        color += toonRim(normal, rd) * (1.0 - diffuse);
+    }
+    // From where the ray set out (ignoring any turns through glass or portals)
+    return fogged(color, p - rd * t, rd, t);
+}
+
+// Color of object `id` at p, seen along rd from t away: shaded, or for glass the sky it
+// reflects and what shows through it, and for a reflective floor what it mirrors
+fn surface(p: vec3<f32>, id: f32, rd: vec3<f32>, t: f32) -> vec3<f32> {
+    var q = p;
+    var dir = rd;
+    var object = id;
+    var span = t;
+    var color = vec3<f32>(0.0);
+    var throughput = vec3<f32>(1.0);
+    for (var event = 0u; event < GLASS_EVENTS; event++) {
+        let glass = sceneGlass(object);
+        var origin: vec3<f32>;
+        if (glass.x == 0.0) {
+            let mirror = select(0.0, floorMirror(-dir.y), sceneFloor(object));
+            color += throughput * (1.0 - mirror) * shade(q, object, dir, span);
+            if (mirror == 0.0) {
+                return color;
+            }
+            throughput *= mirror;
+            origin = q + vec3<f32>(0.0, 0.01, 0.0);
+            dir = reflect(dir, vec3<f32>(0.0, 1.0, 0.0));
+        } else {
+            let n = calcNormal(q);
+            let reflectance = fresnel(-dot(dir, n), 1.0, glass.x);
+            let film = filmReflection(q, -dot(dir, n), sceneFilm(object));
+            color += throughput * reflectance * film * skyColor(reflect(dir, n));
+            let passage = throughGlass(q, dir, n, glass, sceneColor(object));
+            throughput *= (1.0 - reflectance) * passage.transmittance;
+            origin = passage.p;
+            dir = passage.rd;
+        }
+        let hit = traceRay(origin, dir, false);
+        dir = hit.rd;
+        if (hit.t < 0.0) {
+            return color + throughput * skyColor(dir);
+        }
+        q = hit.p;
+        object = hit.id;
+        span += hit.t;
+    }
+    return color;
+}
+
+fn rayMarch(ro: vec3<f32>, rd: vec3<f32>) -> vec3<f32> {
+    let hit = traceRay(ro, rd, false);
+    if (hit.t < 0.0) {
+        // Sky gradient if no hit, behind as much fog as the farthest surfaces
+        let sky = fogged(skyColor(hit.rd), hit.p, hit.rd, uniforms.max_distance);
+        return withGuides(sky, ro, rd, 1e10);
+    }
+    return withGuides(surface(hit.p, hit.id, hit.rd, hit.t), ro, rd, hit.t);
+}
+
+// Cone traced anti-aliasing: one ray per pixel, marched as a cone as wide as the pixel (`pixel`
+// per unit of distance). Where a surface passes through the cone without stopping the ray, at
+// a silhouette, its closest pass is shaded and blended in by the share of the cone it covers,
+// and the ray goes on to what is behind. Portals take the cone along like traceRay does.
+fn coneMarch(origin: vec3<f32>, direction: vec3<f32>, pixel: f32) -> vec3<f32> {
+    var ro = origin;
+    var rd = direction;
+    var color = vec3<f32>(0.0);
+    var alpha = 0.0; // Share of the pixel covered so far
+    var edge = vec3<f32>(0.0); // Closest pass of the surface in the cone: (t, id, coverage)
+    var depth = 1e10;
+    var base = 0.0; // Travelled up to ro, through portals
+    var jumps = 0u;
+    var portal = scenePortal(ro, rd);
+    var t = 0.0;
+    for (var i = 0u; i < uniforms.max_steps; i++) {
+        if (t >= portal.x) {
+            // An edge still open stays on this side
+            if (edge.z > 0.0) {
+                color += (1.0 - alpha) * edge.z * surface(ro + rd * edge.x, edge.y, rd, base + edge.x);
+                alpha += (1.0 - alpha) * edge.z;
+                edge.z = 0.0;
+            }
+            ro = portalPoint(ro + rd * portal.x, portal.y);
+            rd = portalDirection(rd, portal.y);
+            base += portal.x;
+            t = 0.0;
+            jumps++;
+            portal = select(scenePortal(ro, rd), vec2<f32>(1e10, -1.0), jumps >= PORTAL_JUMPS);
+        }
+        let p = ro + rd * t;
+        let hit = sceneMap(p);
+        if (hit.x < uniforms.epsilon) {
+            // Covering the rest; an edge still open is this same surface
+            color += (1.0 - alpha) * surface(p, hit.y, rd, base + t);
+            alpha = 1.0;
+            edge.z = 0.0;
+            depth = base + t;
+            break;
+        }
+        let radius = max((base + t) * pixel * 0.5, uniforms.epsilon);
+        let covered = coverage(hit.x, radius);
+        if (covered > edge.z) {
+            edge = vec3<f32>(t, hit.y, covered);
+        } else if (edge.z > 0.0 && covered == 0.0) {
+            // Past it: blend it in
+            color += (1.0 - alpha) * edge.z * surface(ro + rd * edge.x, edge.y, rd, base + edge.x);
+            alpha += (1.0 - alpha) * edge.z;
+            edge.z = 0.0;
+        }
+        if (base + t > uniforms.max_distance) {
+            break;
+        }
+        t = min(t + hit.x * 0.8, portal.x);
+    }
+    if (edge.z > 0.0) {
+        color += (1.0 - alpha) * edge.z * surface(ro + rd * edge.x, edge.y, rd, base + edge.x);
+        alpha += (1.0 - alpha) * edge.z;
+    }
+    color += (1.0 - alpha) * fogged(skyColor(rd), ro, rd, uniforms.max_distance);
+    return withGuides(color, origin, direction, depth);
+}
+
+// Direction through `uv` for the camera's projection, in the view frame (x right, y up,
+// z forward). The projections agree at the center and ease in from the pinhole view with
+// lens_amount (see camera.rs).
+fn lensDirection(uv: vec2<f32>) -> vec3<f32> {
+    let a = uniforms.lens_amount;
+    switch uniforms.projection {
+        case 1u, 2u: {
+            // Fisheye: the angle off the axis grows with the distance from the center
+            let r = length(uv);
+            var full = r;
+            if (uniforms.projection == 2u) {
+                full = 2.0 * asin(min(r * 0.5, 1.0));
+            }
+            let theta = min(mix(atan(r), full, a), 3.14159265);
+            let side = select(vec2<f32>(0.0), uv / r, r > 0.0);
+            return vec3<f32>(side * sin(theta), cos(theta));
+        }
+        case 3u: {
+            // Panini: onto a cylinder seen from `a` behind its axis, then out from the axis
+            let view_dist = 1.0 + a;
+            let hyp = uv.x * uv.x + view_dist * view_dist;
+            let isect = uv.x * a;
+            let c = (-isect * uv.x + view_dist * sqrt(max(hyp - isect * isect, 0.0))) / hyp;
+            return normalize(vec3<f32>(uv * ((c + a) / view_dist), c));
+        }
+        case 4u: {
+            // Cylindrical: angles across, straight up and down
+            let longitude = mix(atan(uv.x), uv.x, a);
+            let height = mix(sqrt(1.0 + uv.x * uv.x), 1.0, a);
+            return normalize(vec3<f32>(sin(longitude), uv.y / height, cos(longitude)));
+        }
+        default: {
+            return normalize(vec3<f32>(uv, 1.0));
+        }
+    }
+}
+
+struct Ray {
+    ro: vec3<f32>,
+    rd: vec3<f32>,
+};
+
+// The camera ray through `uv` from an eye: -1 left, 1 right, 0 the camera itself. The eyes sit
+// ipd apart and aim at where the camera's own ray crosses the convergence distance.
+fn eyeRay(uv: vec2<f32>, eye: f32) -> Ray {
+    let ro = uniforms.camera_pos;
+    let forward = normalize(uniforms.camera_target - ro);
+    let right = normalize(cross(vec3<f32>(0.0, 1.0, 0.0), forward));
+    let up = cross(forward, right);
+    let d = lensDirection(uv);
+    let rd = normalize(right * d.x + up * d.y + forward * d.z);
+    let origin = ro + right * (eye * uniforms.ipd * 0.5);
+    let depth = dot(rd, forward);
+    if (eye == 0.0 || depth < 0.01) {
+        return Ray(origin, rd);
+    }
+    return Ray(origin, normalize(ro + rd * (uniforms.convergence / depth) - origin));
+}
+
+// View uv (-1..1 up, -aspect..aspect across) of a point on the screen (in.uv) and the eye
+// that sees it. Side by side, each half holds one eye's whole view squeezed to half width, as
+// VR video players expect.
+fn screenView(screen: vec2<f32>) -> vec3<f32> {
+    var at = screen;
+    var eye = 0.0;
+    if (uniforms.stereo == 2u) {
+        at.x = fract(screen.x * 2.0);
+        eye = select(1.0, -1.0, screen.x < 0.5);
+    }
+    // Into the whole view, of which this image may be a tile (with its aspect ratio)
+    at = uniforms.region.xy + at * uniforms.region.zw;
+    var uv = (at - 0.5) * 2.0;
+    uv.x *= uniforms.resolution.x * uniforms.region.w / (uniforms.resolution.y * uniforms.region.z);
+    return vec3<f32>(uv, eye);
+}
+
+// Coverage (0-1) of the selection outline for a ray: a second march of the selected object on
+// its own, tracking how close the ray comes to it for the distance travelled. Rays that hit it
+// get none, so the outline sits just outside the silhouette, over anything in front of it.
+fn selectionOutline(ro: vec3<f32>, rd: vec3<f32>, pixel: f32) -> f32 {
+    let width = 2.0; // Pixels
+    var t = 0.0;
+    var closest = 1e10; // Smallest distance over t, the angle to the object
+    for (var i = 0u; i < uniforms.max_steps; i++) {
+        let d = sceneObject(ro + rd * t, uniforms.selected);
+        if (d < uniforms.epsilon) {
+            return 0.0;
+        }
+        if (t > 0.0) {
+            closest = min(closest, d / t);
+        }
+        if (t > uniforms.max_distance) {
+            break;
+        }
+        t += d;
+    }
+    return 1.0 - smoothstep(width * 0.5, width, closest / pixel);
+}
+
+// Toon ink: the share (0-1) of rays toon_outline / 2 pixels to each side of the one through
+// `uv` that break from it, hitting off the plane of its surface or a surface facing another
+// way (or hitting where it doesn't). Lines land on both sides of a break, toon_outline wide.
+fn toonOutline(uv: vec2<f32>, eye: f32, pixel: f32) -> f32 {
+    let ray = eyeRay(uv, eye);
+    let hit = traceRay(ray.ro, ray.rd, false);
+    var n = vec3<f32>(0.0);
+    if (hit.t >= 0.0) {
+        n = calcNormal(hit.p);
+    }
+    var ink = 0.0;
+    let reach = 0.5 * uniforms.toon_outline * pixel;
+    var offsets = array<vec2<f32>, 4>(
+        vec2<f32>(reach, 0.0), vec2<f32>(-reach, 0.0), vec2<f32>(0.0, reach), vec2<f32>(0.0, -reach)
+    );
+    for (var i = 0; i < 4; i++) {
+        let side = eyeRay(uv + offsets[i], eye);
+        let other = traceRay(side.ro, side.rd, false);
+        if ((hit.t < 0.0) != (other.t < 0.0)) {
+            ink += 0.25;
+        } else if (hit.t >= 0.0) {
+            let off_plane = abs(dot(other.p - hit.p, n)) > 0.02 * hit.t;
+            if (off_plane || dot(calcNormal(other.p), n) < 0.8) {
+                ink += 0.25;
+            }
+        }
+    }
+    return ink;
+}
+
+// The pixel at `uv` seen from `eye`: an aa x aa grid of rays spread over it averaged, or a
+// cone traced through it (aa 0), and with toon shading the ink over it
+fn renderView(uv: vec2<f32>, eye: f32, pixel: f32, aa: u32) -> vec3<f32> {
+    var color = vec3<f32>(0.0);
+    if (aa == 0u) {
+        let ray = eyeRay(uv, eye);
+        color = coneMarch(ray.ro, ray.rd, pixel);
+    }
+    for (var sy = 0u; sy < aa; sy++) {
+        for (var sx = 0u; sx < aa; sx++) {
+            let offset = (vec2<f32>(f32(sx), f32(sy)) + 0.5) / f32(aa) - 0.5;
+            let ray = eyeRay(uv + offset * pixel, eye);
+            color += rayMarch(ray.ro, ray.rd);
+        }
+    }
+    color /= f32(max(aa * aa, 1u));
+
+    if (uniforms.toon_bands > 0u && uniforms.toon_outline > 0.0) {
+        color *= 1.0 - toonOutline(uv, eye, pixel);
+    }
+    if (uniforms.selected >= 0.0) {
+        let ray = eyeRay(uv, eye);
+        color = mix(color, vec3<f32>(1.0, 0.8, 0.2), selectionOutline(ray.ro, ray.rd, pixel));
+    }
+    return color;
+}
+
+const VOLUME_TRANSFER: f32 = 256.0; // Entries in the transfer texture (TRANSFER_SIZE)
+const ISO_REFINE: u32 = 6u;          // Halvings of the step the iso-surface is found in
+
+// Volume dataset (see volume.rs): its value at p, 0 to 1 across the window
+fn volumeValue(p: vec3<f32>) -> f32 {
+    let uvw = (p - uniforms.volume_center) / (2.0 * uniforms.volume_size) + 0.5;
+    let value = textureSampleLevel(volumeTex, volumeSampler, uvw, 0.0).r;
+    let window = uniforms.volume_window;
+    return clamp((value - window.x) / (window.y - window.x), 0.0, 1.0);
+}
+
+// Color and opacity the transfer function gives a value
+fn transfer(value: f32) -> vec4<f32> {
+    let u = (value * (VOLUME_TRANSFER - 1.0) + 0.5) / VOLUME_TRANSFER;
+    return textureSampleLevel(transferTex, volumeSampler, vec2<f32>(u, 0.5), 0.0);
+}
+
+// The volume over `color`, the pixel at `uv` seen from `eye`: along the ray through the
+// volume's box up to the scene's first surface, either the transfer function's colors
+// gathered front to back, or the first point where the data reaches volume_iso, lit by the
+// sun with the normal of the data's gradient and shadowed by the scene
+fn withVolume(color: vec3<f32>, uv: vec2<f32>, eye: f32) -> vec3<f32> {
+    if (uniforms.volume_steps == 0u) {
+        return color;
+    }
+    let ray = eyeRay(uv, eye);
+    let center = uniforms.volume_center;
+    let size = uniforms.volume_size;
+    let t0 = (center - size - ray.ro) / ray.rd;
+    let t1 = (center + size - ray.ro) / ray.rd;
+    let near = min(t0, t1);
+    let far = max(t0, t1);
+    let enter = max(max(max(near.x, near.y), near.z), 0.0);
+    var leave = min(min(far.x, far.y), far.z);
+    if (enter >= leave) {
+        return color;
+    }
+    let hit = traceRay(ray.ro, ray.rd, false);
+    if (hit.t >= 0.0) {
+        leave = min(leave, hit.t);
+    }
+    let stride = 2.0 * length(size) / f32(uniforms.volume_steps);
+
+    if (uniforms.volume_mode == 1u) {
+        var t = enter;
+        var before = volumeValue(ray.ro + ray.rd * t) - uniforms.volume_iso;
+        for (var i = 0u; i <= uniforms.volume_steps && t < leave && before < 0.0; i++) {
+            var next = min(t + stride, leave);
+            let after = volumeValue(ray.ro + ray.rd * next) - uniforms.volume_iso;
+            if (after >= 0.0) {
+                // Narrow the crossing down between t and next
+                for (var j = 0u; j < ISO_REFINE; j++) {
+                    let middle = 0.5 * (t + next);
+                    if (volumeValue(ray.ro + ray.rd * middle) >= uniforms.volume_iso) {
+                        next = middle;
+                    } else {
+                        t = middle;
+                    }
+                }
+            }
+            t = next;
+            before = after;
+        }
+        if (before < 0.0) {
+            return color;
+        }
+        let p = ray.ro + ray.rd * t;
+        let e = vec2<f32>(0.5 * stride, 0.0);
+        let gradient = vec3<f32>(
+            volumeValue(p + e.xyy) - volumeValue(p - e.xyy),
+            volumeValue(p + e.yxy) - volumeValue(p - e.yxy),
+            volumeValue(p + e.yyx) - volumeValue(p - e.yyx));
+        var n = -ray.rd;
+        if (length(gradient) > 0.0) {
+            n = -normalize(gradient);
+        }
+        let lightDir = uniforms.sun_dir;
+        var diffuse = max(0.0, dot(n, lightDir));
+        if (uniforms.shadow_steps > 0u && diffuse > 0.0) {
+            diffuse *= softShadow(p + n * 0.01, lightDir, 0.02, 10.0);
+        }
+        let albedo = transfer(uniforms.volume_iso).rgb;
+        return albedo * (vec3<f32>(0.15, 0.15, 0.2) + diffuse * uniforms.light_color);
+    }
+
+    var gathered = vec3<f32>(0.0);
+    var transmittance = 1.0;
+    var t = enter;
+    for (var i = 0u; i <= uniforms.volume_steps && t < leave && transmittance > 0.01; i++) {
+        let span = min(stride, leave - t);
+        let entry = transfer(volumeValue(ray.ro + ray.rd * (t + 0.5 * span)));
+        let alpha = 1.0 - exp(-entry.a * uniforms.volume_density * span);
+        gathered += transmittance * alpha * entry.rgb;
+        transmittance *= 1.0 - alpha;
+        t += span;
+    }
+    return gathered + transmittance * color;
+}
+
+// The pixel at `screen` (in.uv) with an aa x aa grid of rays (a cone for 0)
+fn renderPixel(screen: vec2<f32>, aa: u32) -> vec4<f32> {
+    let view = screenView(screen);
+    let pixel = 2.0 * uniforms.region.w / uniforms.resolution.y;
+    if (uniforms.stereo == 1u) {
+        // Anaglyph: red from the left eye, green and blue from the right
+        let left = withVolume(renderView(view.xy, -1.0, pixel, aa), view.xy, -1.0);
+        let right = withVolume(renderView(view.xy, 1.0, pixel, aa), view.xy, 1.0);
+        return vec4<f32>(left.r, right.gb, 1.0);
+    }
+    let color = renderView(view.xy, view.z, pixel, aa);
+    return vec4<f32>(withVolume(color, view.xy, view.z), 1.0);
+}
+
+@fragment
+fn fragment_main(in: VertexOut) -> @location(0) vec4<f32> {
+    return renderPixel(in.uv, uniforms.aa);
+}
+
+// Progressive path tracing: one path per pixel each frame, averaged with the paths of earlier
+// frames (uniforms.samples of them, in `previous`). Diffuse bounces gather light from the sky
+// and from other surfaces, and every hit samples the sun (with a hard shadow from a slightly
+// jittered direction, soft once averaged) and one of the point lights directly, with a shadow
+// ray. The average keeps the distance to the first hit in alpha, for the denoiser.
+const PATH_BOUNCES: u32 = 4u;
+const DENOISE_RADIUS: i32 = 3;
+const LUMINANCE: vec3<f32> = vec3<f32>(0.2126, 0.7152, 0.0722);
+
+@group(1) @binding(0) var previous: texture_2d<f32>;
+
+var<private> seed: u32;
+
+// PCG hash, used as a random number generator with a state per pixel and frame
+fn pcg(v: u32) -> u32 {
+    let state = v * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn random() -> f32 {
+    seed = pcg(seed);
+    return f32(seed) / 4294967295.0;
+}
+
+// Uniformly distributed unit vector
+fn sphereDirection() -> vec3<f32> {
+    let a = 6.2831853 * random();
+    let z = 2.0 * random() - 1.0;
+    let r = sqrt(max(1.0 - z * z, 0.0));
+    return vec3<f32>(r * cos(a), r * sin(a), z);
+}
+
+// Direction around n, cosine weighted
+fn cosineDirection(n: vec3<f32>) -> vec3<f32> {
+    return normalize(n + sphereDirection());
+}
+
+// Light at p (normal n) from one of the scene's lights, picked with odds in proportion to its
+// unshadowed light there, from a random point of its sphere and unless a surface is in the way
+// of `origin` (just off p). Divided by those odds, it averages out to the sum over all lights
+// with less noise than picking any light alike when a few bright or near ones dominate.
+fn sampleLights(p: vec3<f32>, n: vec3<f32>, origin: vec3<f32>) -> vec3<f32> {
+    var total = 0.0;
+    for (var i = 0; i < SCENE_LIGHTS; i++) {
+        let position = sceneLightSphere(f32(i)).xyz;
+        let power = sceneLightPower(f32(i));
+        total += dot(pointLight(p, n, position, power, 1.0), LUMINANCE);
+    }
+    if (total <= 0.0) {
+        return vec3<f32>(0.0);
+    }
+    var pick = random() * total;
+    for (var i = 0; i < SCENE_LIGHTS; i++) {
+        let sphere = sceneLightSphere(f32(i));
+        let power = sceneLightPower(f32(i));
+        let odds = dot(pointLight(p, n, sphere.xyz, power, 1.0), LUMINANCE);
+        pick -= odds;
+        if (pick > 0.0 || odds <= 0.0) {
+            continue;
+        }
+        let position = sphere.xyz + sphere.w * sphereDirection();
+        let l = position - origin;
+        let span = length(l);
+        let hit = traceRay(origin, l / span, true).t;
+        if (hit >= 0.0 && hit < span) {
+            return vec3<f32>(0.0);
+        }
+        return pointLight(p, n, position, power, 1.0) * total / odds;
+    }
+    return vec3<f32>(0.0);
+}
+
+// Color along the path, and the distance to its first hit (max_distance for none)
+fn pathTrace(origin: vec3<f32>, direction: vec3<f32>) -> vec4<f32> {
+    var ro = origin;
+    var rd = direction;
+    var color = vec3<f32>(0.0);
+    var throughput = vec3<f32>(1.0);
+    var depth = uniforms.max_distance;
+    for (var bounce = 0u; bounce < PATH_BOUNCES; bounce++) {
+        let hit = traceRay(ro, rd, false);
+        rd = hit.rd;
+        if (bounce == 0u && hit.t >= 0.0) {
+            depth = hit.t;
+        }
+        if (hit.t < 0.0) {
+            color += throughput * skyColor(rd);
+            break;
+        }
+        let p = hit.p;
+        let n = calcNormal(p);
+        var base = noiseColor(p, sceneColorAt(p, hit.id, sceneColor(hit.id)), sceneNoise(hit.id));
+        let film = sceneFilm(hit.id);
+        let offset = p + n * 0.01;
+        color += throughput * sceneEmission(hit.id);
+
+        // The floor: mirroring, picked by the share it mirrors, or lit like the rest
+        if (sceneFloor(hit.id)) {
+            if (random() < floorMirror(-dot(rd, n))) {
+                ro = offset;
+                rd = reflect(rd, n);
+                continue;
+            }
+            base = floorColor(p);
+        }
+
+        // Glass: reflected or through, picked by the share of light going each way
+        let glass = sceneGlass(hit.id);
+        if (glass.x > 0.0) {
+            if (random() < fresnel(-dot(rd, n), 1.0, glass.x)) {
+                ro = offset;
+                throughput *= filmReflection(p, -dot(rd, n), film);
+                rd = reflect(rd, n);
+            } else {
+                let passage = throughGlass(p, rd, n, glass, base);
+                throughput *= passage.transmittance;
+                ro = passage.p;
+                rd = passage.rd;
+            }
+            continue;
+        }
+
+        // Lit and bounced about the normal with the normal map's detail
+        let albedo = iridescent(base, p, abs(dot(rd, n)), film);
+        let shading = bumpNormal(p, n, sceneBump(hit.id));
+        let jitter = vec3<f32>(random(), random(), random()) - 0.5;
+        let sun = normalize(uniforms.sun_dir + jitter * 0.05);
+        var direct = sampleLights(p, shading, offset);
+        let facing = dot(shading, sun);
+        var gloss = vec3<f32>(0.0); // The sun's highlight, not tinted by the albedo
+        if (facing > 0.0 && traceRay(offset, sun, true).t < 0.0) {
+            direct += facing * uniforms.light_color;
+            let specular = sceneSpecular(hit.id);
+            if (specular.x > 0.0) {
+                gloss = highlight(p, shading, -rd, sun, hit.id, specular) * facing * uniforms.light_color;
+            }
+        }
+        color += throughput * (albedo * direct + gloss);
+
+        throughput *= albedo;
+        ro = offset;
+        rd = cosineDirection(shading);
+    }
+    // Fog on the way to the first hit (the bounces go through clear air)
+    return vec4<f32>(fogged(color, origin, direction, depth), depth);
+}
+
+// A new path for the pixel, averaged into the ones traced so far
+@fragment
+fn pathtrace_main(in: VertexOut) -> @location(0) vec4<f32> {
+    let pixel = vec2<u32>(in.position.xy);
+    // The run's seed moves every pixel a long way along its sequence (none for seed 0)
+    let sequence = u32(uniforms.samples) + uniforms.seed * 0x9E3779B9u;
+    seed = pcg(pixel.x + pcg(pixel.y + pcg(sequence)));
+
+    // Through a random point in the pixel, so edges are antialiased as paths add up
+    let jitter = vec2<f32>(random(), random()) - 0.5;
+    let view = screenView(in.uv + jitter / uniforms.resolution);
+    var path: vec4<f32>;
+    if (uniforms.stereo == 1u) {
+        // Anaglyph: a path for each eye, red from the left one (depth from the right)
+        let left = eyeRay(view.xy, -1.0);
+        let right = eyeRay(view.xy, 1.0);
+        let left_path = pathTrace(left.ro, left.rd);
+        path = pathTrace(right.ro, right.rd);
+        path.r = left_path.r;
+    } else {
+        let ray = eyeRay(view.xy, view.z);
+        path = pathTrace(ray.ro, ray.rd);
+    }
+    if (uniforms.samples > 0.0) {
+        let average = textureLoad(previous, vec2<i32>(pixel), 0);
+        path = mix(average, path, 1.0 / (uniforms.samples + 1.0));
+    }
+    return path;
+}
+
+// The averaged paths, drawn to the target
+@fragment
+fn resolve_main(in: VertexOut) -> @location(0) vec4<f32> {
+    return vec4<f32>(textureLoad(previous, vec2<i32>(in.position.xy), 0).rgb, 1.0);
+}
+
+fn texel(pixel: vec2<i32>) -> vec4<f32> {
+    let size = vec2<i32>(textureDimensions(previous));
+    return textureLoad(previous, clamp(pixel, vec2<i32>(0), size - 1), 0);
+}
+
+// Of the differences to the left and right neighbours, the smaller one (so it doesn't step
+// over an edge)
+fn smallerStep(before: f32, here: f32, after: f32) -> f32 {
+    let left = here - before;
+    let right = after - here;
+    return select(right, left, abs(left) < abs(right));
+}
+
+// The averaged paths with an edge-aware blur (SVGF-style edge stopping), for images of a few
+// samples: neighbours count less the further their first hit is from the surface through the
+// pixel (continued along its depth gradient) and the more their luminance differs. That
+// tolerance shrinks with the noise as samples add up, so the blur fades away.
+@fragment
+fn denoise_main(in: VertexOut) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.position.xy);
+    let center = texel(pixel);
+    let gradient = vec2<f32>(
+        smallerStep(texel(pixel - vec2<i32>(1, 0)).a, center.a, texel(pixel + vec2<i32>(1, 0)).a),
+        smallerStep(texel(pixel - vec2<i32>(0, 1)).a, center.a, texel(pixel + vec2<i32>(0, 1)).a),
+    );
+    let luminance = dot(center.rgb, LUMINANCE);
+    let luminanceSigma = 1.0 / sqrt(uniforms.samples + 1.0);
+
+    var sum = vec3<f32>(0.0);
+    var weights = 0.0;
+    for (var y = -DENOISE_RADIUS; y <= DENOISE_RADIUS; y++) {
+        for (var x = -DENOISE_RADIUS; x <= DENOISE_RADIUS; x++) {
+            let neighbour = texel(pixel + vec2<i32>(x, y));
+            let spatial = exp(-f32(x * x + y * y) / f32(2 * DENOISE_RADIUS));
+            let depthSigma = abs(dot(gradient, vec2<f32>(f32(x), f32(y)))) + 0.01 * center.a + 1e-4;
+            let depth = exp(-abs(neighbour.a - center.a) / depthSigma);
+            let shade = exp(-abs(dot(neighbour.rgb, LUMINANCE) - luminance) / luminanceSigma);
+            let weight = spatial * depth * shade;
+            sum += neighbour.rgb * weight;
+            weights += weight;
+        }
+    }
+    return vec4<f32>(sum / weights, 1.0);
+}
+
+// Edge detection post pass (see edges.rs): gbuffer_main renders the normal and distance of each
+// pixel's first hit (the center eye's for an anaglyph), and edges_main runs a Sobel filter over
+// them, drawing lines over the image (or on the blueprint's paper) where either changes sharply.
+@group(1) @binding(0) var gbuffer: texture_2d<f32>;
+
+fn gbufferRay(screen: vec2<f32>) -> Ray {
+    let view = screenView(screen);
+    return eyeRay(view.xy, select(view.z, 0.0, uniforms.stereo == 1u));
+}
+
+@fragment
+fn gbuffer_main(in: VertexOut) -> @location(0) vec4<f32> {
+    let ray = gbufferRay(in.uv);
+    let hit = traceRay(ray.ro, ray.rd, false);
+    if (hit.t < 0.0) {
+        return vec4<f32>(0.0, 0.0, 0.0, uniforms.max_distance);
+    }
+    return vec4<f32>(calcNormal(hit.p), hit.t);
+}
+
+fn gbufferTexel(pixel: vec2<i32>) -> vec4<f32> {
+    let size = vec2<i32>(textureDimensions(gbuffer));
+    return textureLoad(gbuffer, clamp(pixel, vec2<i32>(0), size - 1), 0);
+}
+
+@fragment
+fn edges_main(in: VertexOut) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.position.xy);
+    // Lines come out about twice as wide as the taps are apart
+    let reach = max(i32(round(uniforms.edge_width * 0.5)), 1);
+    var gx = vec4<f32>(0.0);
+    var gy = vec4<f32>(0.0);
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let tap = gbufferTexel(pixel + vec2<i32>(x, y) * reach);
+            gx += tap * f32(x * (2 - abs(y)));
+            gy += tap * f32(y * (2 - abs(x)));
+        }
+    }
+    // Distance changes by itself across a surface seen edge-on (a floor towards the horizon),
+    // so its change counts less the more the surface faces away; sky faces the eye
+    let center = gbufferTexel(pixel);
+    let sky = center.w >= uniforms.max_distance;
+    let facing = select(abs(dot(center.xyz, gbufferRay(in.uv).rd)), 1.0, sky);
+    let depth = length(vec2<f32>(gx.w, gy.w)) / max(center.w, 1e-3) * facing;
+    let normal = sqrt(dot(gx.xyz, gx.xyz) + dot(gy.xyz, gy.xyz));
+    let edge = max(
+        smoothstep(uniforms.edge_depth, uniforms.edge_depth * 1.5, depth),
+        smoothstep(uniforms.edge_normal, uniforms.edge_normal * 1.5, normal)
+    );
+    if (uniforms.edge_mode == 2u) {
+        return vec4<f32>(mix(uniforms.edge_paper, uniforms.edge_color, edge), 1.0);
+    }
+    return vec4<f32>(uniforms.edge_color, edge);
+}
+
+// Volumetric light shafts (see shafts.rs): shafts_main marches each pixel's ray (at the shaft
+// texture's resolution) up to what it hits, adding the sunlight the fog scatters towards the
+// eye wherever a shadow ray (the path tracer's test) reaches the sun, and shafts_composite_main
+// adds that over the image, upsampled bilinearly. The march ignores glass and portals.
+@group(1) @binding(0) var shafts: texture_2d<f32>;
+
+const SHAFT_AIR: f32 = 0.01; // Scattering where the scene has less fog
+const SHAFT_DISTANCE: f32 = 40.0; // Farthest the march goes
+const SHAFT_FORWARD: f32 = 0.6; // How much more light scatters forwards than back
+
+@fragment
+fn shafts_main(in: VertexOut) -> @location(0) vec4<f32> {
+    let ray = gbufferRay(in.uv);
+    let hit = traceRay(ray.ro, ray.rd, false);
+    let end = min(select(hit.t, uniforms.max_distance, hit.t < 0.0), SHAFT_DISTANCE);
+    let sun = uniforms.sun_dir;
+    let stride = end / f32(uniforms.shaft_samples);
+    // Interleaved gradient noise: each pixel's steps start a different way in, so the
+    // upsampling blurs the banding away. The seed turns the whole pattern by a golden step.
+    let pattern = fract(52.9829189 * fract(dot(in.position.xy, vec2<f32>(0.06711056, 0.00583715))));
+    let jitter = fract(pattern + f32((uniforms.seed * 0x9E3779B9u) >> 8u) / 16777216.0);
+    var light = 0.0;
+    var depth = 0.0; // Optical depth from the eye
+    for (var i = 0u; i < uniforms.shaft_samples; i++) {
+        let p = ray.ro + ray.rd * ((f32(i) + jitter) * stride);
+        let density = max(uniforms.fog_density + heightFogDensity(p.y), SHAFT_AIR);
+        let lit = select(0.0, 1.0, traceRay(p, sun, true).t < 0.0);
+        light += lit * density * exp(-depth) * stride;
+        depth += density * stride;
+    }
+    // Henyey-Greenstein phase: the share of the scattered light going towards the eye
+    let g = SHAFT_FORWARD;
+    let lobe = 1.0 + g * g - 2.0 * g * dot(ray.rd, sun);
+    let phase = (1.0 - g * g) / (4.0 * 3.14159265 * pow(lobe, 1.5));
+    return vec4<f32>(uniforms.light_color * light * phase * uniforms.shaft_intensity, 1.0);
+}
+
+fn shaftsTexel(pixel: vec2<i32>) -> vec3<f32> {
+    let size = vec2<i32>(textureDimensions(shafts));
+    return textureLoad(shafts, clamp(pixel, vec2<i32>(0), size - 1), 0).rgb;
+}
+
+@fragment
+fn shafts_composite_main(in: VertexOut) -> @location(0) vec4<f32> {
+    let at = in.position.xy / uniforms.resolution * vec2<f32>(textureDimensions(shafts)) - 0.5;
+    let corner = vec2<i32>(floor(at));
+    let f = fract(at);
+    let top = mix(shaftsTexel(corner), shaftsTexel(corner + vec2<i32>(1, 0)), f.x);
+    let bottom = mix(shaftsTexel(corner + vec2<i32>(0, 1)), shaftsTexel(corner + vec2<i32>(1, 1)), f.x);
+    return vec4<f32>(mix(top, bottom, f.y), 1.0);
+}
+
+// Adaptive quality (see adaptive.rs): tiles_main shades a 3 x 3 grid of rays over each
+// tile_size square of pixels (one texel of the tile texture), reaching a pixel past its sides
+// so the edges the next tile's grid spreads over count too, and marks it detailed where they
+// disagree: a miss beside a hit, another object, surfaces facing apart or off one plane, or
+// colors apart (a shadow's edge, a checker's, a reflection's). adaptive_main draws the image as
+// fragment_main does, with the aa x aa grid (or cone) in detailed tiles and one ray elsewhere.
+@group(1) @binding(0) var tiles: texture_2d<f32>;
+
+const TILE_CONTRAST: f32 = 0.08; // Difference in a color channel that makes a tile detailed
+const TILE_FACING: f32 = 0.9; // Normals less alike (their dot product) make it detailed
+
+@fragment
+fn tiles_main(in: VertexOut) -> @location(0) vec4<f32> {
+    let size = f32(uniforms.tile_size);
+    // In pixels of the image, from its top left
+    let low = floor(in.position.xy) * size - 1.0;
+    let spacing = (size + 2.0) * 0.5;
+    var first: Hit;
+    var normal = vec3<f32>(0.0);
+    var lowest = vec3<f32>(1e10);
+    var highest = vec3<f32>(-1e10);
+    for (var i = 0; i < 9; i++) {
+        let at = low + vec2<f32>(f32(i % 3), f32(i / 3)) * spacing;
+        let ray = gbufferRay(vec2<f32>(at.x, uniforms.resolution.y - at.y) / uniforms.resolution);
+        let hit = traceRay(ray.ro, ray.rd, false);
+        var n = vec3<f32>(0.0);
+        if (hit.t >= 0.0) {
+            n = calcNormal(hit.p);
+        }
+        if (i == 0) {
+            first = hit;
+            normal = n;
+        } else if ((hit.t < 0.0) != (first.t < 0.0)) {
+            return vec4<f32>(1.0);
+        } else if (hit.t >= 0.0) {
+            let off_plane = abs(dot(hit.p - first.p, normal)) > 0.02 * first.t;
+            if (hit.id != first.id || off_plane || dot(n, normal) < TILE_FACING) {
+                return vec4<f32>(1.0);
+            }
+        }
+        let color = rayMarch(ray.ro, ray.rd);
+        lowest = min(lowest, color);
+        highest = max(highest, color);
+    }
+    if (any(highest - lowest > vec3<f32>(TILE_CONTRAST))) {
+        return vec4<f32>(1.0);
+    }
+    return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+}
+
+@fragment
+fn adaptive_main(in: VertexOut) -> @location(0) vec4<f32> {
+    let tile = vec2<i32>(in.position.xy) / i32(max(uniforms.tile_size, 1u));
+    let size = vec2<i32>(textureDimensions(tiles));
+    let detailed = textureLoad(tiles, clamp(tile, vec2<i32>(0), size - 1), 0).r > 0.5;
+    return renderPixel(in.uv, select(1u, uniforms.aa, detailed));
+}
@@ -0,0 +1,35 @@
+// Volumetric light shafts (god rays): a second march along each pixel's ray, through the
+// scene's fog, that adds the sunlight scattered towards the eye wherever the sun reaches past
+// the scene (a shadow ray), so objects cast visible beams and shadows into the air.
+// Thin air stands in where a scene has no fog. The march runs at `half_resolution` by default
+// and is upsampled over the image. [shafts] in the config or --shafts turns it on.
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Shafts {
+    pub samples: u32,   // Steps of the march per pixel
+    pub intensity: f32, // Brightness of the scattered light
+    pub half_resolution: bool,
+}
+
+impl Default for Shafts {
+    fn default() -> Self {
+        Self {
+            samples: 32,
+            intensity: 1.0,
+            half_resolution: true,
+        }
+    }
+}
+
+impl Shafts {
+    // Size of the texture marched into for a target of `size`
+    pub fn size(&self, [width, height]: [u32; 2]) -> [u32; 2] {
+        if self.half_resolution {
+            [width.div_ceil(2), height.div_ceil(2)]
+        } else {
+            [width, height]
+        }
+    }
+}
@@ -0,0 +1,266 @@
+// Shared views: the scene, camera, mouse, time and the parameters changed from the scene file,
+// packed into a link short enough for a chat message:
+//
+//   metal-raymarcher://view/AQUAYmxvYnMRAHNjZW5lcy9ibG9icy50b21sAACgPwAAAEEAAAA_...
+//
+// Cmd+C (Ctrl+C outside macOS) copies the current view, Cmd+V opens the one on the clipboard
+// and --view opens one at startup; the part after the prefix works on its own too. Opening a
+// view loads its scene file afresh if it is there (relative to the working directory, so the
+// same checkout of scenes reproduces it exactly) and then applies the rest. Structural edits
+// (added, moved or deleted objects) are not part of a view: share the scene file for those.
+//
+// The link's data is URL-safe base64 of, little-endian:
+//   u8 VERSION, scene name, scene path ("" if none), f32 camera angle, camera distance,
+//   mouse x, mouse y, time, u16 count, then per parameter: target, u8 length, length f32s
+// with each string as a u16 length and its UTF-8 bytes.
+use crate::base64;
+use metal_raymarcher::animation::MAX_TIME;
+use std::process::{Command, Stdio};
+
+pub const PREFIX: &str = "metal-raymarcher://view/";
+const VERSION: u8 = 1;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SharedView {
+    pub scene: String,    // Name
+    pub path: String,     // Of the scene file, as it was loaded ("" if it wasn't from one)
+    pub camera: [f32; 2], // Angle and distance
+    pub mouse: [f32; 2],
+    pub time: f32,
+    pub parameters: Vec<(String, Vec<f32>)>, // Targets whose values differ from the file's
+}
+
+impl SharedView {
+    pub fn encode(&self) -> String {
+        let mut data = vec![VERSION];
+        // Cut to what a u16 counts, at the start of a character
+        let string = |data: &mut Vec<u8>, text: &str| {
+            let mut len = text.len().min(u16::MAX as usize);
+            while !text.is_char_boundary(len) {
+                len -= 1;
+            }
+            data.extend_from_slice(&(len as u16).to_le_bytes());
+            data.extend_from_slice(&text.as_bytes()[..len]);
+        };
+        string(&mut data, &self.scene);
+        string(&mut data, &self.path);
+        for value in [self.camera, self.mouse]
+            .concat()
+            .into_iter()
+            .chain([self.time])
+        {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        let parameters = &self.parameters[..self.parameters.len().min(u16::MAX as usize)];
+        data.extend_from_slice(&(parameters.len() as u16).to_le_bytes());
+        for (target, values) in parameters {
+            string(&mut data, target);
+            let values = &values[..values.len().min(u8::MAX as usize)];
+            data.push(values.len() as u8);
+            for value in values {
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        format!("{}{}", PREFIX, base64::encode_url(&data))
+    }
+
+    // A view from a link or its data alone (surrounding whitespace is ignored)
+    pub fn decode(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        let data = text.strip_prefix(PREFIX).unwrap_or(text);
+        let data = base64::decode_url(data).ok_or("not a shared view")?;
+        let mut reader = Reader { data: &data, at: 0 };
+        match reader.u8()? {
+            VERSION => {}
+            version => return Err(format!("view of an unknown version ({})", version)),
+        }
+        let scene = reader.string()?;
+        let path = reader.string()?;
+        let mut numbers = [0.0; 5];
+        for number in &mut numbers {
+            *number = reader.f32()?;
+        }
+        let [angle, distance, x, y, time] = numbers;
+        let count = reader.u16()?;
+        let mut parameters = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let target = reader.string()?;
+            let len = reader.u8()?;
+            let values = (0..len)
+                .map(|_| reader.f32())
+                .collect::<Result<Vec<_>, _>>()?;
+            parameters.push((target, values));
+        }
+        if reader.at != data.len() {
+            return Err("shared view has trailing data".to_string());
+        }
+        if !numbers.iter().all(|number| number.is_finite())
+            || !(parameters.iter().flat_map(|(_, values)| values)).all(|value| value.is_finite())
+        {
+            return Err("shared view has a number that isn't finite".to_string());
+        }
+        if !(0.0..=MAX_TIME).contains(&time) {
+            return Err(format!("shared view is at {}s, out of range", time));
+        }
+        Ok(Self {
+            scene,
+            path,
+            camera: [angle, distance],
+            mouse: [x, y],
+            time,
+            parameters,
+        })
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    at: usize,
+}
+
+impl Reader<'_> {
+    fn bytes(&mut self, len: usize) -> Result<&[u8], String> {
+        let bytes = (self.data.get(self.at..self.at + len)).ok_or("shared view is cut short")?;
+        self.at += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn f32(&mut self) -> Result<f32, String> {
+        let bytes = self.bytes(4)?;
+        Ok(f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.u16()? as usize;
+        String::from_utf8(self.bytes(len)?.to_vec())
+            .map_err(|_| "shared view has a name that isn't UTF-8".to_string())
+    }
+}
+
+// Put `text` on the system clipboard (pbcopy on macOS, wl-copy or xclip elsewhere)
+pub fn copy(text: &str) -> Result<(), String> {
+    let mut last = String::new();
+    for (program, args) in clipboard_commands(true) {
+        let child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(err) => {
+                last = format!("{}: {}", program, err);
+                continue;
+            }
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            use std::io::Write;
+            let _ = stdin.write_all(text.as_bytes());
+        }
+        match child.wait() {
+            Ok(status) if status.success() => return Ok(()),
+            Ok(status) => last = format!("{}: {}", program, status),
+            Err(err) => last = format!("{}: {}", program, err),
+        }
+    }
+    Err(format!("no clipboard ({})", last))
+}
+
+// The text on the system clipboard
+pub fn paste() -> Result<String, String> {
+    let mut last = String::new();
+    for (program, args) in clipboard_commands(false) {
+        match Command::new(program)
+            .args(args)
+            .stderr(Stdio::null())
+            .output()
+        {
+            Ok(output) if output.status.success() => {
+                return Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+            }
+            Ok(output) => last = format!("{}: {}", program, output.status),
+            Err(err) => last = format!("{}: {}", program, err),
+        }
+    }
+    Err(format!("no clipboard ({})", last))
+}
+
+// Programs that copy to (`copy`) or paste from the clipboard, to try in turn
+fn clipboard_commands(copy: bool) -> Vec<(&'static str, &'static [&'static str])> {
+    if cfg!(target_os = "macos") {
+        vec![if copy {
+            ("pbcopy", &[])
+        } else {
+            ("pbpaste", &[])
+        }]
+    } else if copy {
+        vec![("wl-copy", &[]), ("xclip", &["-selection", "clipboard"])]
+    } else {
+        vec![
+            ("wl-paste", &["--no-newline"]),
+            ("xclip", &["-selection", "clipboard", "-out"]),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn views_round_trip() {
+        let view = SharedView {
+            scene: "blobs".to_string(),
+            path: "scenes/blobs.toml".to_string(),
+            camera: [1.25, 8.0],
+            mouse: [0.5, 0.25],
+            time: 12.5,
+            parameters: vec![
+                ("ball.position".to_string(), vec![0.0, 1.5, -2.0]),
+                ("fog.density".to_string(), vec![0.02]),
+            ],
+        };
+        assert_eq!(SharedView::decode(&view.encode()), Ok(view));
+    }
+
+    #[test]
+    fn long_names_are_cut_between_characters() {
+        // A two-byte character across the u16 limit
+        let view = SharedView {
+            scene: "a".repeat(u16::MAX as usize - 1) + "é",
+            path: "€".repeat(u16::MAX as usize),
+            camera: [0.0, 5.0],
+            mouse: [0.5, 0.5],
+            time: 0.0,
+            parameters: Vec::new(),
+        };
+        let decoded = SharedView::decode(&view.encode()).unwrap();
+        assert_eq!(decoded.scene, "a".repeat(u16::MAX as usize - 1));
+        assert_eq!(decoded.path, "€".repeat(u16::MAX as usize / 3));
+    }
+
+    #[test]
+    fn times_out_of_range_are_rejected() {
+        for time in [-1.0, MAX_TIME * 2.0, f32::MAX] {
+            let view = SharedView {
+                scene: "blobs".to_string(),
+                path: String::new(),
+                camera: [0.0, 5.0],
+                mouse: [0.5, 0.5],
+                time,
+                parameters: Vec::new(),
+            };
+            assert!(SharedView::decode(&view.encode()).is_err(), "{}", time);
+        }
+    }
+}
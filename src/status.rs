@@ -0,0 +1,279 @@
+// Live status shown outside the viewport: the window title gives the scene, frame rate and
+// whether a session is being recorded or replayed, and on macOS the Dock tile shows how far an
+// offline render has got, as a badge and a progress bar over the app's icon. Subsystems report
+// into it from whichever thread they run on; the event loop polls `title` to keep the window's
+// up to date, while offline renders (which have no event loop) update the Dock as they report.
+// When a long job (an offline render, or a path-traced image reaching --samples) ends, macOS
+// shows a notification saying how it went, so it can be left running in the background.
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub const APP_NAME: &str = "Metal Ray Marcher";
+
+// How often the event loop looks for a new title
+pub const TITLE_INTERVAL: Duration = Duration::from_millis(500);
+
+// Jobs that end sooner than this do so without a notification
+const NOTIFY_AFTER: Duration = Duration::from_secs(10);
+
+// The app's icon, a render of assets/icon.toml
+#[cfg_attr(not(any(target_os = "macos", feature = "offline")), allow(dead_code))]
+pub const ICON: &[u8] = include_bytes!("../assets/icon.png");
+
+struct Status {
+    scene: String,
+    fps: u32, // Rounded, so the title only changes when what it shows does
+    recording: bool,
+    replaying: bool,
+    shown: Option<String>, // Title the window was last given
+}
+
+static STATUS: Mutex<Status> = Mutex::new(Status {
+    scene: String::new(),
+    fps: 0,
+    recording: false,
+    replaying: false,
+    shown: None,
+});
+
+fn status() -> std::sync::MutexGuard<'static, Status> {
+    // Status is only ever assigned to, so it is whole even if a reporter panicked
+    STATUS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+pub fn report_scene(name: &str) {
+    let mut status = status();
+    if status.scene != name {
+        status.scene = name.to_string();
+    }
+}
+
+pub fn report_fps(fps: f32) {
+    status().fps = fps.round() as u32;
+}
+
+pub fn report_recording(recording: bool) {
+    status().recording = recording;
+}
+
+pub fn report_replaying(replaying: bool) {
+    status().replaying = replaying;
+}
+
+// The window's title, when it has changed since it was last asked for
+pub fn title() -> Option<String> {
+    let mut status = status();
+    let mut parts = vec![APP_NAME.to_string()];
+    if !status.scene.is_empty() {
+        parts.push(status.scene.clone());
+    }
+    if status.fps > 0 {
+        parts.push(format!("{} fps", status.fps));
+    }
+    if status.recording {
+        parts.push("\u{25cf} recording".to_string());
+    }
+    if status.replaying {
+        parts.push("replaying".to_string());
+    }
+    let title = parts.join(" \u{2014} ");
+    if status.shown.as_ref() == Some(&title) {
+        return None;
+    }
+    status.shown = Some(title.clone());
+    Some(title)
+}
+
+// `done` of `total` frames of an offline render are written. Called on the main thread.
+#[cfg(feature = "offline")]
+pub fn report_progress(done: usize, total: usize) {
+    #[cfg(target_os = "macos")]
+    dock::show(Some(done as f64 / total.max(1) as f64));
+    #[cfg(not(target_os = "macos"))]
+    let _ = (done, total);
+}
+
+// The offline render is over, so the Dock tile goes back to the plain icon
+#[cfg(feature = "offline")]
+pub fn report_finished() {
+    #[cfg(target_os = "macos")]
+    dock::show(None);
+}
+
+// `job` (such as "Render of city"), started at `started`, is over: it wrote `outcome`'s path,
+// or failed with its error. Posts a notification on macOS, unless the job was quick.
+#[cfg_attr(not(feature = "offline"), allow(dead_code))]
+pub fn report_done(job: &str, started: Instant, outcome: Result<&Path, &str>) {
+    let took = started.elapsed();
+    if took < NOTIFY_AFTER {
+        return;
+    }
+    let (title, body) = match outcome {
+        Ok(path) => (
+            format!("{} finished", job),
+            format!("In {}: {}", duration(took), path.display()),
+        ),
+        Err(err) => (
+            format!("{} failed", job),
+            format!("After {}: {}", duration(took), err),
+        ),
+    };
+    #[cfg(target_os = "macos")]
+    notification::post(&title, &body);
+    #[cfg(not(target_os = "macos"))]
+    let _ = (title, body);
+}
+
+// "1h 5m", "3m 12s" or "42s"
+fn duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match (seconds / 3600, seconds / 60 % 60, seconds % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {}s", m, s),
+        (h, m, _) => format!("{}h {}m", h, m),
+    }
+}
+
+// The icon for the window, where the platform shows one there (not macOS, see set_app_icon)
+#[cfg(all(not(target_os = "macos"), feature = "offline"))]
+pub fn window_icon() -> Option<winit::window::Icon> {
+    let mut decoder = png::Decoder::new(ICON);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().ok()?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).ok()?;
+    buffer.truncate(info.buffer_size());
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => buffer,
+        png::ColorType::Rgb => buffer
+            .chunks_exact(3)
+            .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+            .collect(),
+        _ => return None,
+    };
+    winit::window::Icon::from_rgba(rgba, info.width, info.height).ok()
+}
+
+#[cfg(any(target_os = "macos", not(feature = "offline")))]
+pub fn window_icon() -> Option<winit::window::Icon> {
+    None
+}
+
+// Give the app its icon in the Dock and app switcher; run from the terminal it would
+// otherwise have a generic one. Called on the main thread.
+#[cfg(target_os = "macos")]
+pub fn set_app_icon() {
+    dock::set_icon();
+}
+
+#[cfg(not(target_os = "macos"))]
+pub fn set_app_icon() {}
+
+#[cfg(target_os = "macos")]
+mod dock {
+    use super::ICON;
+    use cocoa::base::{id, nil, NO};
+    use cocoa::foundation::{NSPoint, NSRect, NSSize, NSString};
+    use objc::{class, msg_send, sel, sel_impl};
+    use std::ffi::c_void;
+
+    const NS_APPLICATION_ACTIVATION_POLICY_REGULAR: i64 = 0;
+    const NS_PROGRESS_INDICATOR_STYLE_BAR: u64 = 0;
+
+    pub fn set_icon() {
+        unsafe {
+            let app: id = msg_send![class!(NSApplication), sharedApplication];
+            let data: id = msg_send![class!(NSData),
+                dataWithBytes: ICON.as_ptr() as *const c_void
+                length: ICON.len()];
+            let image: id = msg_send![class!(NSImage), alloc];
+            let image: id = msg_send![image, initWithData: data];
+            if image != nil {
+                let _: () = msg_send![app, setApplicationIconImage: image];
+                let _: () = msg_send![image, release];
+            }
+        }
+    }
+
+    // Show `progress` (0 to 1) on the Dock tile, or take it off with None. The tile's content
+    // becomes the icon with a bar over its lower edge while there is progress to show.
+    #[cfg_attr(not(feature = "offline"), allow(dead_code))]
+    pub fn show(progress: Option<f64>) {
+        unsafe {
+            let app: id = msg_send![class!(NSApplication), sharedApplication];
+            let tile: id = msg_send![app, dockTile];
+            let mut view: id = msg_send![tile, contentView];
+            let Some(progress) = progress else {
+                let _: () = msg_send![tile, setContentView: nil];
+                let _: () = msg_send![tile, setBadgeLabel: nil];
+                let _: () = msg_send![tile, display];
+                return;
+            };
+            if view == nil {
+                // Offline renders have no window, so the app isn't in the Dock yet
+                let _: () =
+                    msg_send![app, setActivationPolicy: NS_APPLICATION_ACTIVATION_POLICY_REGULAR];
+                set_icon();
+                let size: NSSize = msg_send![tile, size];
+                let frame = NSRect::new(NSPoint::new(0.0, 0.0), size);
+                view = msg_send![class!(NSImageView), alloc];
+                view = msg_send![view, initWithFrame: frame];
+                let icon: id = msg_send![app, applicationIconImage];
+                let _: () = msg_send![view, setImage: icon];
+                let bar_frame = NSRect::new(
+                    NSPoint::new(size.width * 0.1, size.height * 0.06),
+                    NSSize::new(size.width * 0.8, size.height * 0.12),
+                );
+                let bar: id = msg_send![class!(NSProgressIndicator), alloc];
+                let bar: id = msg_send![bar, initWithFrame: bar_frame];
+                let _: () = msg_send![bar, setStyle: NS_PROGRESS_INDICATOR_STYLE_BAR];
+                let _: () = msg_send![bar, setIndeterminate: NO];
+                let _: () = msg_send![bar, setMinValue: 0.0f64];
+                let _: () = msg_send![bar, setMaxValue: 1.0f64];
+                let _: () = msg_send![view, addSubview: bar];
+                let _: () = msg_send![bar, release];
+                let _: () = msg_send![tile, setContentView: view];
+                let _: () = msg_send![view, release];
+            }
+            let subviews: id = msg_send![view, subviews];
+            let bar: id = msg_send![subviews, firstObject];
+            let _: () = msg_send![bar, setDoubleValue: progress];
+            let percent = format!("{}%", (progress * 100.0).floor() as u32);
+            let label = NSString::alloc(nil).init_str(&percent);
+            let _: () = msg_send![tile, setBadgeLabel: label];
+            let _: () = msg_send![label, release];
+            let _: () = msg_send![tile, display];
+        }
+    }
+}
+
+// Notifications go through osascript: the notification center APIs need the app to be a
+// bundle, and it is usually run from a terminal. It is left to post on its own, since an
+// offline render exits as soon as it has reported.
+#[cfg(target_os = "macos")]
+mod notification {
+    use super::APP_NAME;
+    use std::process::{Command, Stdio};
+
+    pub fn post(title: &str, body: &str) {
+        let quoted =
+            |text: &str| format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+        let script = format!(
+            "display notification {} with title {} subtitle {}",
+            quoted(body),
+            quoted(APP_NAME),
+            quoted(title)
+        );
+        let posted = Command::new("osascript")
+            .args(["-e", &script])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        if let Err(err) = posted {
+            eprintln!("Failed to post a notification: {}", err);
+        }
+    }
+}
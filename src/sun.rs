@@ -0,0 +1,188 @@
+// The real sun: with `sun = { latitude = 51.48, longitude = -0.01 }` in a scene's
+// [environment], the sunlight comes from where the sun stands over that place on Earth right
+// now, by the system clock. `time = "2025-06-21 14:30"` (UTC, or with an offset such as
+// "+02:00" after it) fixes the moment instead, and `hours` is added to whichever it is, so
+// animating or binding "sun.hours" scrubs through the day. `north` turns the compass: the
+// degrees from -Z to the scene's north, clockwise seen from above (by default north is -Z and
+// east +X).
+//
+// The position is the usual low-precision one (good to about a hundredth of a degree for
+// centuries around 2000), without refraction near the horizon. Once the sun is down its light
+// fades out. The clock is read to the minute, so a path-traced image starts over once a
+// minute rather than every frame.
+use serde::{Deserialize, Serialize};
+use std::ops::RangeInclusive;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Where the sunlight comes from in scenes without a `sun`, unnormalized
+pub const DEFAULT_DIRECTION: [f32; 3] = [0.7, 0.7, -0.5];
+
+// Years a `time` can be in, which keeps the seconds well within an i64
+const YEARS: RangeInclusive<i64> = 1..=9999;
+// Largest offset from UTC in hours (the Line Islands' +14:00)
+const MAX_OFFSET: i64 = 14;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Sun {
+    pub latitude: f32,  // Degrees, north positive
+    pub longitude: f32, // Degrees, east positive
+    #[serde(skip_serializing_if = "is_zero")]
+    pub north: f32, // Degrees from -Z, clockwise seen from above
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time: Option<String>, // Instead of the clock
+    #[serde(skip_serializing_if = "is_zero")]
+    pub hours: f32, // Added to the time
+}
+
+fn is_zero(value: &f32) -> bool {
+    *value == 0.0
+}
+
+impl Sun {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(-90.0..=90.0).contains(&self.latitude) {
+            return Err(format!("latitude {} is not within ±90", self.latitude));
+        }
+        for (name, value) in [
+            ("longitude", self.longitude),
+            ("north", self.north),
+            ("hours", self.hours),
+        ] {
+            if !value.is_finite() {
+                return Err(format!("{} {} is not a number", name, value));
+            }
+        }
+        if let Some(time) = &self.time {
+            parse_time(time)?;
+        }
+        Ok(())
+    }
+
+    // Seconds since the Unix epoch that the sun is placed at, `now` being the clock's time
+    pub fn moment(&self, now: SystemTime) -> f64 {
+        let base = match self.time.as_deref().map(parse_time) {
+            Some(Ok(time)) => time,
+            // To the minute (see the top of this file)
+            _ => {
+                let seconds = now
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |since| since.as_secs());
+                (seconds - seconds % 60) as f64
+            }
+        };
+        base + self.hours as f64 * 3600.0
+    }
+
+    // Unit vector towards the sun in scene space at `now`
+    pub fn direction(&self, now: SystemTime) -> [f32; 3] {
+        let (elevation, azimuth) = position(self.moment(now), self.latitude, self.longitude);
+        let azimuth = azimuth + (self.north as f64).to_radians();
+        [
+            (elevation.cos() * azimuth.sin()) as f32,
+            elevation.sin() as f32,
+            (-elevation.cos() * azimuth.cos()) as f32,
+        ]
+    }
+}
+
+// Share of the sunlight left with the sun in `direction`: all of it until it is nearly down,
+// none once it is a few degrees below the horizon
+pub fn daylight(direction: [f32; 3]) -> f32 {
+    let t = ((direction[1] + 0.05) / 0.1).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// The sun's elevation above the horizon and azimuth (from north, towards east) in radians, at
+// `time` (seconds since the Unix epoch) seen from `latitude` and `longitude` in degrees
+pub fn position(time: f64, latitude: f32, longitude: f32) -> (f64, f64) {
+    let days = time / 86400.0 - 10957.5; // Since noon on 1 January 2000 (J2000)
+
+    // The sun's mean anomaly and longitude, then its longitude on the ecliptic
+    let anomaly = (357.529 + 0.98560028 * days).to_radians();
+    let mean = 280.459 + 0.98564736 * days;
+    let ecliptic = (mean + 1.915 * anomaly.sin() + 0.020 * (2.0 * anomaly).sin()).to_radians();
+    let obliquity = (23.439 - 0.00000036 * days).to_radians();
+    // Right ascension and declination, then the hour angle from the local sidereal time
+    let ascension = (obliquity.cos() * ecliptic.sin()).atan2(ecliptic.cos());
+    let declination = (obliquity.sin() * ecliptic.sin()).asin();
+    let sidereal = (18.697374558 + 24.06570982441908 * days) * 15.0 + longitude as f64;
+    let hour = sidereal.to_radians() - ascension;
+    let latitude = (latitude as f64).to_radians();
+    let elevation = (latitude.sin() * declination.sin()
+        + latitude.cos() * declination.cos() * hour.cos())
+    .asin();
+    let azimuth =
+        (-hour.sin()).atan2(declination.tan() * latitude.cos() - latitude.sin() * hour.cos());
+    (elevation, azimuth)
+}
+
+// "YYYY-MM-DD HH:MM[:SS]" (or with a T between) in YEARS, UTC unless followed by Z or an
+// offset like +02:00 of up to MAX_OFFSET hours, as seconds since the Unix epoch
+pub fn parse_time(text: &str) -> Result<f64, String> {
+    let invalid = || format!("time \"{}\" is not like \"2025-06-21 14:30\"", text);
+    let text = text.trim();
+    let (text, offset) = match text.strip_suffix('Z') {
+        Some(text) => (text, 0),
+        None => match text.get(text.len().saturating_sub(6)..) {
+            // Sliced by byte, so only when it's ASCII
+            Some(zone)
+                if zone.len() == 6
+                    && zone.is_ascii()
+                    && zone.starts_with(['+', '-'])
+                    && &zone[3..4] == ":" =>
+            {
+                let sign = if zone.starts_with('-') { -1 } else { 1 };
+                let digits = |part: &str| -> Result<i64, String> {
+                    match part.bytes().all(|byte| byte.is_ascii_digit()) {
+                        true => part.parse().map_err(|_| invalid()),
+                        false => Err(invalid()),
+                    }
+                };
+                let (hours, minutes) = (digits(&zone[1..3])?, digits(&zone[4..])?);
+                if minutes >= 60 || hours * 60 + minutes > MAX_OFFSET * 60 {
+                    return Err(invalid());
+                }
+                (
+                    &text[..text.len() - 6],
+                    sign * (hours * 3600 + minutes * 60),
+                )
+            }
+            _ => (text, 0),
+        },
+    };
+    let (date, clock) = text.split_once([' ', 'T']).ok_or_else(invalid)?;
+    let numbers = |part: &str, separator| -> Result<Vec<i64>, String> {
+        (part
+            .split(separator)
+            .map(|number| number.parse().map_err(|_| invalid())))
+        .collect()
+    };
+    let (date, clock) = (numbers(date, '-')?, numbers(clock, ':')?);
+    let (&[year, month, day], &[hour, minute, ..]) = (&date[..], &clock[..]) else {
+        return Err(invalid());
+    };
+    let second = clock.get(2).copied().unwrap_or(0);
+    if !YEARS.contains(&year)
+        || !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || !(0..24).contains(&hour)
+        || !(0..60).contains(&minute)
+        || !(0..60).contains(&second)
+        || clock.len() > 3
+    {
+        return Err(invalid());
+    }
+    let seconds = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    Ok((seconds - offset) as f64)
+}
+
+// Days from 1970-01-01 to the date, in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
@@ -0,0 +1,419 @@
+// Outlines from SVG files, for the extrusion and revolution shapes (see scene.rs). The
+// <path>, <polygon>, <polyline>, <rect>, <circle> and <ellipse> elements are read as closed
+// loops and filled even-odd, so a letter's counter is a hole. Curves and arcs are flattened
+// into line segments; transforms, strokes and styles are ignored, so flatten transforms in the
+// drawing program before exporting.
+//
+// The loops are fitted to the shape: y is flipped to point up and the drawing is scaled so
+// its longer side is 2 units. An extrusion's outline is centered on the origin; a
+// revolution's is centered vertically with the drawing's left edge on the axis it turns
+// around, so draw half of its profile.
+use std::f32::consts::PI;
+use std::path::Path;
+
+// Most points all of an object's loops may have together: the shader visits every edge
+// for each distance it takes
+pub const MAX_POINTS: usize = 1024;
+
+// Segments each Bézier curve is flattened into (arcs get one per 22.5 degrees)
+const CURVE_SEGMENTS: usize = 8;
+
+// Read the outline in the SVG file at `path`, fitted for a revolution or an extrusion
+pub fn load(path: &Path, revolve: bool) -> Result<Vec<Vec<[f32; 2]>>, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+    let loops = parse(&text).map_err(|err| format!("Invalid SVG {}: {}", path.display(), err))?;
+    let points: usize = loops.iter().map(Vec::len).sum();
+    if points == 0 {
+        return Err(format!("SVG {} has no closed shapes", path.display()));
+    }
+    if points > MAX_POINTS {
+        return Err(format!(
+            "SVG {} has {} points, at most {} are supported",
+            path.display(),
+            points,
+            MAX_POINTS
+        ));
+    }
+    Ok(fit(loops, revolve))
+}
+
+// The shapes of an SVG document as loops of points, in its own units (y down)
+pub fn parse(text: &str) -> Result<Vec<Vec<[f32; 2]>>, String> {
+    let mut loops = Vec::new();
+    for tag in text.split('<').skip(1) {
+        let tag = tag.split('>').next().unwrap_or_default();
+        let name = tag
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default();
+        let number = |attribute: &str| {
+            value(tag, attribute)
+                .map(|text| Numbers::new(text).number().unwrap_or(0.0))
+                .unwrap_or(0.0)
+        };
+        match name {
+            "path" => {
+                if let Some(data) = value(tag, "d") {
+                    loops.extend(path(data)?);
+                }
+            }
+            "polygon" | "polyline" => {
+                let mut numbers = Numbers::new(value(tag, "points").unwrap_or_default());
+                let mut points = Vec::new();
+                while let (Some(x), Some(y)) = (numbers.number(), numbers.number()) {
+                    points.push([x, y]);
+                }
+                loops.push(points);
+            }
+            "rect" => {
+                let (x, y) = (number("x"), number("y"));
+                let (w, h) = (number("width"), number("height"));
+                loops.push(vec![[x, y], [x + w, y], [x + w, y + h], [x, y + h]]);
+            }
+            "circle" => {
+                let r = number("r");
+                loops.push(ellipse([number("cx"), number("cy")], [r, r]));
+            }
+            "ellipse" => {
+                let radii = [number("rx"), number("ry")];
+                loops.push(ellipse([number("cx"), number("cy")], radii));
+            }
+            _ => {}
+        }
+    }
+    Ok(loops.into_iter().filter_map(clean).collect())
+}
+
+// The value of `attribute` in the text of a tag, e.g. `d` in `path d="M 0 0 ..."`
+fn value<'a>(tag: &'a str, attribute: &str) -> Option<&'a str> {
+    let pattern = format!("{}=", attribute);
+    let mut from = 0;
+    while let Some(at) = tag[from..].find(&pattern).map(|at| at + from) {
+        from = at + pattern.len();
+        // `d=` must not match the end of `id=`
+        if !tag[..at].ends_with(char::is_whitespace) {
+            continue;
+        }
+        let rest = &tag[from..];
+        let quote = rest.chars().next()?;
+        if quote != '"' && quote != '\'' {
+            return None;
+        }
+        return rest[1..].split(quote).next();
+    }
+    None
+}
+
+// Numbers in path data and point lists, which may run together ("1.5.5-2" is 1.5, .5, -2)
+struct Numbers<'a> {
+    text: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Numbers<'a> {
+    fn new(text: &'a str) -> Self {
+        Self {
+            text: text.as_bytes(),
+            at: 0,
+        }
+    }
+
+    fn skip_separators(&mut self) {
+        while self
+            .text
+            .get(self.at)
+            .is_some_and(|c| c.is_ascii_whitespace() || *c == b',')
+        {
+            self.at += 1;
+        }
+    }
+
+    // The next path command letter, if that is what comes next
+    fn command(&mut self) -> Option<u8> {
+        self.skip_separators();
+        let c = *self.text.get(self.at)?;
+        (c.is_ascii_alphabetic() && c != b'e' && c != b'E').then(|| {
+            self.at += 1;
+            c
+        })
+    }
+
+    fn at_end(&mut self) -> bool {
+        self.skip_separators();
+        self.at >= self.text.len()
+    }
+
+    fn number(&mut self) -> Option<f32> {
+        self.skip_separators();
+        let start = self.at;
+        let digits = |numbers: &mut Self| {
+            while numbers.text.get(numbers.at).is_some_and(u8::is_ascii_digit) {
+                numbers.at += 1;
+            }
+        };
+        if matches!(self.text.get(self.at), Some(b'+' | b'-')) {
+            self.at += 1;
+        }
+        digits(self);
+        if self.text.get(self.at) == Some(&b'.') {
+            self.at += 1;
+            digits(self);
+        }
+        if matches!(self.text.get(self.at), Some(b'e' | b'E')) {
+            let mantissa = self.at;
+            self.at += 1;
+            if matches!(self.text.get(self.at), Some(b'+' | b'-')) {
+                self.at += 1;
+            }
+            let exponent = self.at;
+            digits(self);
+            if self.at == exponent {
+                self.at = mantissa;
+            }
+        }
+        let number = std::str::from_utf8(&self.text[start..self.at])
+            .ok()
+            .and_then(|text| text.parse().ok());
+        if number.is_none() {
+            self.at = start;
+        }
+        number
+    }
+
+    // An arc's flag, a single 0 or 1 that needn't be separated from what follows
+    fn flag(&mut self) -> Option<bool> {
+        self.skip_separators();
+        let flag = match self.text.get(self.at)? {
+            b'0' => false,
+            b'1' => true,
+            _ => return None,
+        };
+        self.at += 1;
+        Some(flag)
+    }
+}
+
+// The loops of path data: every subpath is closed, whether or not it ends with Z
+fn path(data: &str) -> Result<Vec<Vec<[f32; 2]>>, String> {
+    let mut numbers = Numbers::new(data);
+    let mut loops = Vec::new();
+    let mut current: Vec<[f32; 2]> = Vec::new();
+    let (mut pos, mut start) = ([0.0; 2], [0.0; 2]);
+    // The last control point of a cubic (true) or quadratic curve, reflected by S and T
+    let mut control: Option<([f32; 2], bool)> = None;
+    let mut command = None;
+    let missing = || "path data ends in the middle of a command".to_string();
+
+    while !numbers.at_end() {
+        let c = match numbers.command() {
+            Some(c) => c,
+            None => command.ok_or_else(|| "numbers without a command in path data".to_string())?,
+        };
+        command = Some(c);
+        let relative = c.is_ascii_lowercase();
+        let origin = if relative { pos } else { [0.0; 2] };
+        let point = |numbers: &mut Numbers| -> Result<[f32; 2], String> {
+            let x = numbers.number().ok_or_else(missing)?;
+            let y = numbers.number().ok_or_else(missing)?;
+            Ok([origin[0] + x, origin[1] + y])
+        };
+        if current.is_empty() && !matches!(c, b'M' | b'm' | b'Z' | b'z') {
+            current.push(start);
+        }
+        let mut next_control = None;
+        match c.to_ascii_uppercase() {
+            b'M' => {
+                let to = point(&mut numbers)?;
+                loops.push(std::mem::take(&mut current));
+                current.push(to);
+                (pos, start) = (to, to);
+                // Further pairs are lines
+                command = Some(if relative { b'l' } else { b'L' });
+            }
+            b'L' => {
+                pos = point(&mut numbers)?;
+                current.push(pos);
+            }
+            b'H' | b'V' => {
+                let axis = usize::from(c.eq_ignore_ascii_case(&b'V'));
+                let value = numbers.number().ok_or_else(missing)?;
+                pos[axis] = if relative { pos[axis] + value } else { value };
+                current.push(pos);
+            }
+            b'C' | b'S' => {
+                let first = match c.to_ascii_uppercase() {
+                    b'C' => point(&mut numbers)?,
+                    _ => reflect(control.filter(|&(_, cubic)| cubic), pos),
+                };
+                let second = point(&mut numbers)?;
+                let to = point(&mut numbers)?;
+                current.extend(curve(|t| {
+                    let s = 1.0 - t;
+                    let weights = [s * s * s, 3.0 * s * s * t, 3.0 * s * t * t, t * t * t];
+                    mix(&[pos, first, second, to], &weights)
+                }));
+                next_control = Some((second, true));
+                pos = to;
+            }
+            b'Q' | b'T' => {
+                let middle = match c.to_ascii_uppercase() {
+                    b'Q' => point(&mut numbers)?,
+                    _ => reflect(control.filter(|&(_, cubic)| !cubic), pos),
+                };
+                let to = point(&mut numbers)?;
+                current.extend(curve(|t| {
+                    let s = 1.0 - t;
+                    mix(&[pos, middle, to], &[s * s, 2.0 * s * t, t * t])
+                }));
+                next_control = Some((middle, false));
+                pos = to;
+            }
+            b'A' => {
+                let rx = numbers.number().ok_or_else(missing)?;
+                let ry = numbers.number().ok_or_else(missing)?;
+                let rotation = numbers.number().ok_or_else(missing)?;
+                let large = numbers.flag().ok_or_else(missing)?;
+                let sweep = numbers.flag().ok_or_else(missing)?;
+                let to = point(&mut numbers)?;
+                current.extend(arc(pos, [rx, ry], rotation, large, sweep, to));
+                pos = to;
+            }
+            b'Z' => {
+                loops.push(std::mem::take(&mut current));
+                pos = start;
+                command = None;
+            }
+            _ => return Err(format!("unknown path command {}", c as char)),
+        }
+        control = next_control;
+    }
+    loops.push(current);
+    Ok(loops)
+}
+
+// The control point S or T starts with: the last one reflected through `pos`, if the previous
+// curve was of the same kind, else `pos`
+fn reflect(control: Option<([f32; 2], bool)>, pos: [f32; 2]) -> [f32; 2] {
+    match control {
+        Some((c, _)) => [2.0 * pos[0] - c[0], 2.0 * pos[1] - c[1]],
+        None => pos,
+    }
+}
+
+fn mix(points: &[[f32; 2]], weights: &[f32]) -> [f32; 2] {
+    let mut out = [0.0; 2];
+    for (point, weight) in points.iter().zip(weights) {
+        out[0] += point[0] * weight;
+        out[1] += point[1] * weight;
+    }
+    out
+}
+
+// Points along a curve from t = 0 (left out, it is the current point) to 1
+fn curve(at: impl Fn(f32) -> [f32; 2]) -> impl Iterator<Item = [f32; 2]> {
+    (1..=CURVE_SEGMENTS).map(move |i| at(i as f32 / CURVE_SEGMENTS as f32))
+}
+
+// Points along an elliptical arc from `from` (left out) to `to`, in the SVG's endpoint form:
+// its center is worked out as the SVG specification's implementation notes do
+fn arc(
+    from: [f32; 2],
+    radii: [f32; 2],
+    degrees: f32,
+    large: bool,
+    sweep: bool,
+    to: [f32; 2],
+) -> Vec<[f32; 2]> {
+    let (mut rx, mut ry) = (radii[0].abs(), radii[1].abs());
+    if from == to {
+        return Vec::new();
+    }
+    if rx == 0.0 || ry == 0.0 {
+        return vec![to];
+    }
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (dx, dy) = ((from[0] - to[0]) / 2.0, (from[1] - to[1]) / 2.0);
+    let (x, y) = (cos * dx + sin * dy, -sin * dx + cos * dy);
+    // Radii too small to reach are scaled up until they just do
+    let reach = (x * x) / (rx * rx) + (y * y) / (ry * ry);
+    if reach > 1.0 {
+        rx *= reach.sqrt();
+        ry *= reach.sqrt();
+    }
+    let num = rx * rx * ry * ry - rx * rx * y * y - ry * ry * x * x;
+    let den = rx * rx * y * y + ry * ry * x * x;
+    let sign = if large == sweep { -1.0 } else { 1.0 };
+    let coef = sign * (num / den).max(0.0).sqrt();
+    let (cx, cy) = (coef * rx * y / ry, -coef * ry * x / rx);
+    let center = [
+        cos * cx - sin * cy + (from[0] + to[0]) / 2.0,
+        sin * cx + cos * cy + (from[1] + to[1]) / 2.0,
+    ];
+    let angle =
+        |u: [f32; 2], v: [f32; 2]| (u[0] * v[1] - u[1] * v[0]).atan2(u[0] * v[0] + u[1] * v[1]);
+    let u = [(x - cx) / rx, (y - cy) / ry];
+    let v = [(-x - cx) / rx, (-y - cy) / ry];
+    let start = angle([1.0, 0.0], u);
+    let mut delta = angle(u, v);
+    if !sweep && delta > 0.0 {
+        delta -= 2.0 * PI;
+    } else if sweep && delta < 0.0 {
+        delta += 2.0 * PI;
+    }
+    let segments = (delta.abs() / (PI / 8.0)).ceil().max(1.0) as usize;
+    let mut points: Vec<[f32; 2]> = (1..segments)
+        .map(|i| {
+            let (s, c) = (start + delta * i as f32 / segments as f32).sin_cos();
+            [
+                center[0] + cos * rx * c - sin * ry * s,
+                center[1] + sin * rx * c + cos * ry * s,
+            ]
+        })
+        .collect();
+    points.push(to);
+    points
+}
+
+fn ellipse(center: [f32; 2], radii: [f32; 2]) -> Vec<[f32; 2]> {
+    (0..32)
+        .map(|i| {
+            let (s, c) = (i as f32 * PI / 16.0).sin_cos();
+            [center[0] + radii[0] * c, center[1] + radii[1] * s]
+        })
+        .collect()
+}
+
+// A loop without repeated points (nor its first point again at the end), which has to
+// enclose something
+fn clean(mut points: Vec<[f32; 2]>) -> Option<Vec<[f32; 2]>> {
+    points.dedup();
+    while points.len() > 1 && points.first() == points.last() {
+        points.pop();
+    }
+    (points.len() >= 3 && points.iter().flatten().all(|c| c.is_finite())).then_some(points)
+}
+
+// Flip the loops to y up and scale their longer side to 2 units, placed for a revolution or
+// an extrusion (see the top of the file)
+fn fit(mut loops: Vec<Vec<[f32; 2]>>, revolve: bool) -> Vec<Vec<[f32; 2]>> {
+    let (mut min, mut max) = ([f32::MAX; 2], [f32::MIN; 2]);
+    for point in loops.iter().flatten() {
+        for axis in 0..2 {
+            min[axis] = min[axis].min(point[axis]);
+            max[axis] = max[axis].max(point[axis]);
+        }
+    }
+    let side = (max[0] - min[0]).max(max[1] - min[1]).max(f32::EPSILON);
+    let scale = 2.0 / side;
+    let x = if revolve {
+        min[0]
+    } else {
+        (min[0] + max[0]) / 2.0
+    };
+    let y = (min[1] + max[1]) / 2.0;
+    for point in loops.iter_mut().flatten() {
+        *point = [(point[0] - x) * scale, (y - point[1]) * scale];
+    }
+    loops
+}
@@ -0,0 +1,287 @@
+// Frame sync for video walls: one instance (--sync-master ADDRESS) sends the frame it is about
+// to draw to ADDRESS over UDP, a broadcast or multicast address for a whole wall (e.g.
+// 192.168.1.255:7400), and the others (--sync-follow PORT, or an address to listen on such
+// as [::]:7400 for IPv6) draw that frame instead of keeping time of their own. Each draws its part of the shared view with --tile or --region (see
+// camera::Region), so a grid of screens shows one image.
+//
+// A tick is one datagram, little-endian:
+//   "RMSY", u8 VERSION, u32 session, u64 frame, f32 time, camera angle, camera distance,
+//   mouse x, mouse y
+// The session is picked when the master starts, so followers take its frames from 0 again
+// after a restart but drop older frames arriving late, and any from the last MAX_ENDED
+// sessions before. A
+// follower holds its last frame until the next tick: scene time, camera and mouse are the
+// master's, so with the same scene and config every screen draws the same instant. Nothing
+// else is synced; send scene changes to every instance (see remote.rs) or give them the same
+// drop folder (see drop_folder.rs).
+use crate::config::SyncConfig;
+use metal_raymarcher::animation::MAX_TIME;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MAGIC: &[u8; 4] = b"RMSY";
+const VERSION: u8 = 1;
+const TICK_SIZE: usize = 4 + 1 + 4 + 8 + 5 * 4;
+const MAX_ENDED: usize = 8;
+
+// ADDRESS for --sync-follow and the config: a port alone (on every IPv4 interface), e.g.
+// 7400, or an address and port, e.g. [::]:7400
+pub fn follow_address(text: &str) -> Result<SocketAddr, String> {
+    if let Ok(port) = text.trim().parse::<u16>() {
+        return Ok(SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)));
+    }
+    text.trim().parse().map_err(|_| {
+        "expected a port, e.g. 7400, or an address and port, e.g. [::]:7400".to_string()
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tick {
+    pub frame: u64,
+    pub time: f32,        // Scene time
+    pub camera: [f32; 2], // Angle and distance
+    pub mouse: [f32; 2],
+}
+
+pub enum FrameSync {
+    Master(Master),
+    Follower(Follower),
+}
+
+impl FrameSync {
+    // The part `config` gives this instance, if any (one that can't start is reported, and
+    // the instance keeps its own time)
+    pub fn start(config: &SyncConfig) -> Option<Self> {
+        let started = match (config.master, config.follow) {
+            (Some(address), _) => Master::new(address).map(FrameSync::Master),
+            (None, Some(address)) => Follower::new(address).map(FrameSync::Follower),
+            (None, None) => return None,
+        };
+        started.map_err(|err| eprintln!("Frame sync: {}", err)).ok()
+    }
+}
+
+pub struct Master {
+    socket: UdpSocket,
+    address: SocketAddr,
+    session: u32,
+    frame: u64,
+}
+
+impl Master {
+    pub fn new(address: SocketAddr) -> Result<Self, String> {
+        let any = match address {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            SocketAddr::V6(_) => SocketAddr::from(([0u16; 8], 0)),
+        };
+        let socket = UdpSocket::bind(any).map_err(|err| err.to_string())?;
+        socket.set_broadcast(true).map_err(|err| err.to_string())?;
+        // Non-blocking, so a full send buffer costs a tick rather than a frame
+        socket
+            .set_nonblocking(true)
+            .map_err(|err| err.to_string())?;
+        let session = (SystemTime::now().duration_since(UNIX_EPOCH))
+            .map_or(0, |since| since.subsec_nanos() ^ since.as_secs() as u32);
+        println!("Sending frame sync to {}", address);
+        Ok(Self {
+            socket,
+            address,
+            session,
+            frame: 0,
+        })
+    }
+
+    // Tell the followers about the next frame (`frame` is filled in)
+    pub fn send(&mut self, tick: Tick) {
+        let tick = Tick {
+            frame: self.frame,
+            ..tick
+        };
+        self.frame += 1;
+        let _ = self
+            .socket
+            .send_to(&encode(self.session, &tick), self.address);
+    }
+}
+
+pub struct Follower {
+    socket: UdpSocket,
+    latest: Option<(u32, Tick)>, // With its session
+    ended: Vec<u32>,             // The last sessions followed before, whose ticks are dropped
+}
+
+impl Follower {
+    pub fn new(address: SocketAddr) -> Result<Self, String> {
+        let socket = UdpSocket::bind(address)
+            .map_err(|err| format!("Failed to listen on UDP {}: {}", address, err))?;
+        socket
+            .set_nonblocking(true)
+            .map_err(|err| err.to_string())?;
+        println!("Following frame sync on UDP {}", address);
+        Ok(Self {
+            socket,
+            latest: None,
+            ended: Vec::new(),
+        })
+    }
+
+    // The newest frame the master has sent (None until the first arrives)
+    pub fn poll(&mut self) -> Option<Tick> {
+        let mut buffer = [0; 64];
+        while let Ok(len) = self.socket.recv(&mut buffer) {
+            if let Some((session, tick)) = decode(&buffer[..len]) {
+                self.take(session, tick);
+            }
+        }
+        self.latest.map(|(_, tick)| tick)
+    }
+
+    // Keep `tick` if it is newer than the latest or the first of a new session
+    fn take(&mut self, session: u32, tick: Tick) {
+        let newer = match self.latest {
+            Some((latest, last)) if session == latest => tick.frame > last.frame,
+            // A late tick from a master that has since restarted
+            _ if self.ended.contains(&session) => false,
+            Some((latest, _)) => {
+                println!("The frame master restarted");
+                if self.ended.len() == MAX_ENDED {
+                    self.ended.remove(0);
+                }
+                self.ended.push(latest);
+                true
+            }
+            None => {
+                println!("Synced to the frame master");
+                true
+            }
+        };
+        if newer {
+            self.latest = Some((session, tick));
+        }
+    }
+}
+
+fn encode(session: u32, tick: &Tick) -> Vec<u8> {
+    let mut data = Vec::with_capacity(TICK_SIZE);
+    data.extend_from_slice(MAGIC);
+    data.push(VERSION);
+    data.extend_from_slice(&session.to_le_bytes());
+    data.extend_from_slice(&tick.frame.to_le_bytes());
+    for value in [tick.time, tick.camera[0], tick.camera[1]]
+        .into_iter()
+        .chain(tick.mouse)
+    {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    data
+}
+
+// A tick and its session, or None for anything else arriving on the port
+fn decode(data: &[u8]) -> Option<(u32, Tick)> {
+    if data.len() != TICK_SIZE || &data[..4] != MAGIC || data[4] != VERSION {
+        return None;
+    }
+    let u32_at = |at: usize| u32::from_le_bytes(data[at..at + 4].try_into().unwrap());
+    let f32_at = |at: usize| f32::from_bits(u32_at(at));
+    let session = u32_at(5);
+    let frame = u64::from_le_bytes(data[9..17].try_into().unwrap());
+    let values = [17, 21, 25, 29, 33].map(f32_at);
+    if !values.iter().all(|value| value.is_finite()) {
+        return None;
+    }
+    let [time, angle, distance, x, y] = values;
+    if !(0.0..=MAX_TIME).contains(&time) {
+        return None;
+    }
+    Some((
+        session,
+        Tick {
+            frame,
+            time,
+            camera: [angle, distance],
+            mouse: [x, y],
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn tick(frame: u64) -> Tick {
+        Tick {
+            frame,
+            time: frame as f32,
+            camera: [0.0, 5.0],
+            mouse: [0.0; 2],
+        }
+    }
+
+    fn follower() -> Follower {
+        Follower::new(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).unwrap()
+    }
+
+    #[test]
+    fn times_out_of_range_are_dropped() {
+        assert!(decode(&encode(1, &tick(1))).is_some());
+        for time in [-1.0, MAX_TIME * 2.0, f32::MAX, f32::NAN] {
+            assert!(decode(&encode(1, &Tick { time, ..tick(1) })).is_none());
+        }
+    }
+
+    #[test]
+    fn late_ticks_are_dropped() {
+        let mut follower = follower();
+        let mut take = |ticks: &[(u32, u64)]| {
+            for &(session, frame) in ticks {
+                follower.take(session, tick(frame));
+            }
+            follower.latest.map(|(_, tick)| tick.frame)
+        };
+        assert_eq!(take(&[(1, 5), (1, 7), (1, 6)]), Some(7));
+        // The master restarts
+        assert_eq!(take(&[(2, 0), (1, 8)]), Some(0));
+        assert_eq!(take(&[(2, 1), (1, 9)]), Some(1));
+        assert_eq!(take(&[(2, 0)]), Some(1));
+    }
+
+    #[test]
+    fn only_the_last_sessions_are_kept() {
+        let mut follower = follower();
+        for session in 0..MAX_ENDED as u32 * 3 {
+            follower.take(session, tick(0));
+        }
+        assert_eq!(follower.ended.len(), MAX_ENDED);
+        assert!(!follower.ended.contains(&0));
+    }
+
+    #[test]
+    fn ticks_arrive_over_udp() {
+        let mut follower = follower();
+        let address = follower.socket.local_addr().unwrap();
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        for frame in [3, 4] {
+            socket.send_to(&encode(1, &tick(frame)), address).unwrap();
+        }
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while follower.poll().map(|tick| tick.frame) != Some(4) {
+            assert!(Instant::now() < deadline, "no tick arrived");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn ports_and_addresses_are_taken() {
+        assert_eq!(
+            follow_address("7400"),
+            Ok(SocketAddr::from(([0, 0, 0, 0], 7400)))
+        );
+        assert_eq!(
+            follow_address("[::]:7400"),
+            Ok(SocketAddr::from(([0u16; 8], 7400)))
+        );
+        assert!(follow_address("70000").is_err());
+    }
+}
@@ -0,0 +1,24 @@
+// Toon (cel) shading: light quantized into `bands` flat steps, a rim of light along the edges
+// of surfaces turning away from the camera, and ink outlines `outline` pixels wide where the
+// depth or the normal jumps between neighboring pixels. A scene picks it with
+// `toon = { bands = 3 }` in its [environment]; [toon] in the config or --toon turns it on for
+// every scene. It is a raymarching look: the path tracer keeps shading photorealistically.
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Toon {
+    pub bands: u32,   // Lit steps; 1 is flat lit and unlit
+    pub rim: f32,     // Brightness of the rim light; 0 turns it off
+    pub outline: f32, // Ink line width in pixels; 0 turns outlines off
+}
+
+impl Default for Toon {
+    fn default() -> Self {
+        Self {
+            bands: 3,
+            rim: 0.4,
+            outline: 1.5,
+        }
+    }
+}
@@ -0,0 +1,51 @@
+// Touch input mapped onto the mouse controls: one finger steers like the cursor,
+// a two-finger pinch zooms like the scroll wheel.
+use winit::dpi::PhysicalPosition;
+use winit::event::{Touch, TouchPhase};
+
+// Pinch distance change (in physical pixels) worth one scroll wheel line
+const PINCH_PIXELS_PER_LINE: f64 = 60.0;
+
+pub enum Gesture {
+    Point(PhysicalPosition<f64>), // Acts as a cursor move
+    Zoom(f32),                    // Acts as a scroll of this many lines
+}
+
+#[derive(Default)]
+pub struct Touches {
+    active: Vec<(u64, PhysicalPosition<f64>)>, // Fingers down, in touch order
+    pinch_distance: Option<f64>,               // Between the first two fingers
+}
+
+impl Touches {
+    pub fn handle(&mut self, touch: &Touch) -> Option<Gesture> {
+        match touch.phase {
+            TouchPhase::Started => self.active.push((touch.id, touch.location)),
+            TouchPhase::Moved => {
+                let finger = self.active.iter_mut().find(|(id, _)| *id == touch.id)?;
+                finger.1 = touch.location;
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                self.active.retain(|(id, _)| *id != touch.id);
+            }
+        }
+
+        match self.active.as_slice() {
+            [] => {
+                self.pinch_distance = None;
+                None
+            }
+            [(_, position)] => {
+                self.pinch_distance = None;
+                matches!(touch.phase, TouchPhase::Started | TouchPhase::Moved)
+                    .then_some(Gesture::Point(*position))
+            }
+            [(_, a), (_, b), ..] => {
+                let distance = (a.x - b.x).hypot(a.y - b.y);
+                let previous = self.pinch_distance.replace(distance)?;
+                let lines = (distance - previous) / PINCH_PIXELS_PER_LINE;
+                Some(Gesture::Zoom(lines as f32))
+            }
+        }
+    }
+}
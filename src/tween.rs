@@ -0,0 +1,114 @@
+// Smooth transitions between values over time: camera resets, quality preset changes and
+// `scene.tween` in scripts ease from the current value to the new one instead of snapping.
+//
+//   let tween = Tween::new(from, to, now, 0.5, Easing::EaseInOut);
+//   let value = tween.at(time); // `to` once tween.finished(time)
+use serde::{Deserialize, Serialize};
+
+// Shape of a transition: maps 0..1 progress to 0..1 of the way (spring overshoots)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    #[default]
+    EaseInOut,
+    Spring, // Overshoots and settles like a damped spring
+}
+
+impl Easing {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "linear" => Some(Easing::Linear),
+            "ease_in" => Some(Easing::EaseIn),
+            "ease_out" => Some(Easing::EaseOut),
+            "ease_in_out" => Some(Easing::EaseInOut),
+            "spring" => Some(Easing::Spring),
+            _ => None,
+        }
+    }
+
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+            Easing::EaseInOut => {
+                if t < 0.5 {
+                    4.0 * t * t * t
+                } else {
+                    1.0 - (2.0 - 2.0 * t).powi(3) / 2.0
+                }
+            }
+            // Damped oscillation around 1, scaled to end exactly there
+            Easing::Spring => {
+                let decay = (-6.0 * t).exp();
+                1.0 - decay * (1.0 - t) * (10.0 * t).cos()
+            }
+        }
+    }
+}
+
+// Values that can be blended: `t` is 0 at `self` and 1 at `to`, and may overshoot
+pub trait Lerp: Clone {
+    fn lerp(&self, to: &Self, t: f32) -> Self;
+}
+
+impl Lerp for f32 {
+    fn lerp(&self, to: &Self, t: f32) -> Self {
+        self + (to - self) * t
+    }
+}
+
+impl Lerp for u32 {
+    fn lerp(&self, to: &Self, t: f32) -> Self {
+        (*self as f32).lerp(&(*to as f32), t).round().max(0.0) as u32
+    }
+}
+
+impl<const N: usize> Lerp for [f32; N] {
+    fn lerp(&self, to: &Self, t: f32) -> Self {
+        std::array::from_fn(|i| self[i].lerp(&to[i], t))
+    }
+}
+
+impl Lerp for Vec<f32> {
+    fn lerp(&self, to: &Self, t: f32) -> Self {
+        self.iter().zip(to).map(|(a, b)| a.lerp(b, t)).collect()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Tween<T> {
+    from: T,
+    to: T,
+    start: f32,
+    duration: f32, // Seconds; 0 jumps straight to `to`
+    easing: Easing,
+}
+
+impl<T: Lerp> Tween<T> {
+    pub fn new(from: T, to: T, start: f32, duration: f32, easing: Easing) -> Self {
+        Self {
+            from,
+            to,
+            start,
+            duration: duration.max(0.0),
+            easing,
+        }
+    }
+
+    pub fn at(&self, time: f32) -> T {
+        if self.finished(time) {
+            return self.to.clone();
+        }
+        let t = ((time - self.start) / self.duration).max(0.0);
+        self.from.lerp(&self.to, self.easing.apply(t))
+    }
+
+    pub fn finished(&self, time: f32) -> bool {
+        time >= self.start + self.duration
+    }
+}
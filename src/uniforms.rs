@@ -0,0 +1,593 @@
+// Shader uniforms shared by the Metal and wgpu renderers. The layout follows MSL/WGSL
+// alignment rules: float3/vec3 starts on 16 bytes and the struct rounds up to 16. The gaps
+// that leaves are explicit `_padding` fields, so the struct is plain old data that is
+// uploaded as its bytes, and `msl_layout!` below checks every field's offset at compile time.
+// The tests at the end check shaders.metal and shaders.wgsl declare the same layout.
+use crate::cells::MAX_SITES;
+use crate::codegen::MAX_PARAMS;
+use bytemuck::{Pod, Zeroable};
+// Filling them in is only needed by the renderers
+#[cfg(any(target_os = "macos", target_os = "ios", feature = "wgpu"))]
+use {
+    crate::adaptive::Adaptive,
+    crate::audio::AudioLevels,
+    crate::camera::Camera,
+    crate::cells,
+    crate::clock::ClockPhase,
+    crate::codegen::{self, ParamLayout},
+    crate::edges::Edges,
+    crate::quality::{AaMode, QualityConfig},
+    crate::scene::{Caustics, Cells, Floor, HeightFog, Scene},
+    crate::shafts::Shafts,
+    crate::sun,
+    crate::toon::Toon,
+    crate::volume::Volume,
+    cgmath::{InnerSpace, Vector2, Vector3},
+    std::time::SystemTime,
+};
+
+// Pod needs #[repr(C)], plain old data fields and no padding, which msl_layout! also checks
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)] // Added Debug for easier inspection
+pub(crate) struct Uniforms {
+    resolution: [f32; 2],
+    time: f32,
+    _padding0: [f32; 1], // To align mouse to 16
+    mouse: [f32; 2],
+    _padding1: [f32; 2], // To align camera_pos to 32
+    camera_pos: [f32; 3],
+    _padding: f32, // float3 occupies 16 bytes in MSL
+    video_mix: f32,
+    screen_mix: f32,
+    max_steps: u32,
+    epsilon: f32,
+    max_distance: f32,
+    shadow_steps: u32,
+    ao_samples: u32,
+    aa: u32, // 0 for cone tracing
+    camera_target: [f32; 3],
+    _padding2: f32,
+    light_color: [f32; 3],
+    fog_density: f32,
+    params: [[f32; 4]; MAX_PARAMS / 4], // Animated parameters
+    audio: [f32; 4],                    // Bass, mid, high, level
+    beat: f32,
+    selected: f32,   // Object id, -1 for none
+    guides: f32,     // 1 draws the grid and axes
+    samples: f32,    // Path tracing: paths averaged so far
+    clock: [f32; 4], // Beats, beat, bar, bpm
+    projection: u32, // Projection, in declaration order
+    lens_amount: f32,
+    stereo: u32, // StereoMode, in declaration order
+    ipd: f32,
+    convergence: f32,
+    toon_bands: u32, // 0 for standard shading
+    toon_rim: f32,
+    toon_outline: f32, // Ink width in pixels
+    edge_color: [f32; 3],
+    edge_mode: u32, // EdgeMode, in declaration order
+    edge_paper: [f32; 3],
+    edge_width: f32,
+    edge_depth: f32,
+    edge_normal: f32,
+    _padding3: [f32; 2],
+    fog_color: [f32; 4], // w 1 for this color, 0 the sky's
+    height_fog: f32,     // Density at fog_height, 0 for none
+    fog_height: f32,
+    fog_falloff: f32,
+    fog_sun: f32,
+    shaft_samples: u32, // 0 for no light shafts
+    shaft_intensity: f32,
+    caustic_intensity: f32, // 0 for no caustics
+    caustic_sharpness: f32,
+    floor_color: [f32; 3], // The floor's first squares
+    floor_checker: f32,    // Square width, 0 for plain
+    floor_color2: [f32; 3],
+    floor_reflection: f32,
+    floor_contact: f32, // Contact shadow reach
+    seed: u32,          // Offsets the path tracer's random numbers and the shafts' jitter
+    tile_size: u32,     // Adaptive quality's tile side in pixels, 0 when off
+    _padding4: f32,
+    region: [f32; 4], // Part of the view drawn (x, y from the bottom left, width, height)
+    sites: [[f32; 4]; MAX_SITES], // Of the objects with cells (see cells.rs), w unused
+    volume_center: [f32; 3],
+    volume_mode: u32, // VolumeMode, in declaration order
+    volume_size: [f32; 3],
+    volume_iso: f32,
+    volume_window: [f32; 2],
+    volume_density: f32,
+    volume_steps: u32, // 0 for no volume
+    sun_dir: [f32; 3], // Towards the sun, normalized
+    _padding5: f32,
+}
+
+// Size of a field of the uniforms, picked out by `field`
+const fn field_size<T>(_field: fn(&Uniforms) -> &T) -> usize {
+    std::mem::size_of::<T>()
+}
+
+// Fails the build unless each field sits at its offset in the MSL struct (Uniforms in
+// shaders.metal, mirrored in shaders.wgsl), the fields fill the struct without gaps and it
+// is `$size` bytes long. Every field has to be listed, in order. The offsets are kept as
+// MSL_LAYOUT for checking compiled shaders against (see layout_mismatches).
+macro_rules! msl_layout {
+    ($($field:ident: $offset:literal),+ $(,)?; $size:literal) => {
+        #[cfg_attr(not(any(target_os = "macos", target_os = "ios")), allow(dead_code))]
+        const MSL_LAYOUT: &[(&str, usize)] = &[$((stringify!($field), $offset)),+];
+        $(const _: () = assert!(std::mem::offset_of!(Uniforms, $field) == $offset);)+
+        const _: () = assert!(0 $(+ field_size(|uniforms| &uniforms.$field))+ == $size);
+        const _: () = assert!(std::mem::size_of::<Uniforms>() == $size);
+        const _: () = assert!($size % 16 == 0);
+    };
+}
+
+msl_layout! {
+    resolution: 0,
+    time: 8,
+    _padding0: 12,
+    mouse: 16,
+    _padding1: 24,
+    camera_pos: 32,
+    _padding: 44,
+    video_mix: 48,
+    screen_mix: 52,
+    max_steps: 56,
+    epsilon: 60,
+    max_distance: 64,
+    shadow_steps: 68,
+    ao_samples: 72,
+    aa: 76,
+    camera_target: 80,
+    _padding2: 92,
+    light_color: 96,
+    fog_density: 108,
+    params: 112,
+    audio: 368,
+    beat: 384,
+    selected: 388,
+    guides: 392,
+    samples: 396,
+    clock: 400,
+    projection: 416,
+    lens_amount: 420,
+    stereo: 424,
+    ipd: 428,
+    convergence: 432,
+    toon_bands: 436,
+    toon_rim: 440,
+    toon_outline: 444,
+    edge_color: 448,
+    edge_mode: 460,
+    edge_paper: 464,
+    edge_width: 476,
+    edge_depth: 480,
+    edge_normal: 484,
+    _padding3: 488,
+    fog_color: 496,
+    height_fog: 512,
+    fog_height: 516,
+    fog_falloff: 520,
+    fog_sun: 524,
+    shaft_samples: 528,
+    shaft_intensity: 532,
+    caustic_intensity: 536,
+    caustic_sharpness: 540,
+    floor_color: 544,
+    floor_checker: 556,
+    floor_color2: 560,
+    floor_reflection: 572,
+    floor_contact: 576,
+    seed: 580,
+    tile_size: 584,
+    _padding4: 588,
+    region: 592,
+    sites: 608,
+    volume_center: 1632,
+    volume_mode: 1644,
+    volume_size: 1648,
+    volume_iso: 1660,
+    volume_window: 1664,
+    volume_density: 1672,
+    volume_steps: 1676,
+    sun_dir: 1680,
+    _padding5: 1692,
+    ; 1696
+}
+
+// How the uniforms a compiled shader declares (`size` bytes, with its members' names and
+// offsets, as Metal reflects them) differ from `Uniforms`, one line per difference. A shader
+// may leave out members it doesn't read, but the ones it has must sit where Rust puts them.
+#[cfg_attr(not(any(target_os = "macos", target_os = "ios")), allow(dead_code))]
+pub(crate) fn layout_mismatches(size: usize, members: &[(String, usize)]) -> Vec<String> {
+    let mut mismatches = Vec::new();
+    let expected = std::mem::size_of::<Uniforms>();
+    if size > expected {
+        mismatches.push(format!(
+            "size: {} bytes in the shader, {} in Rust",
+            size, expected
+        ));
+    }
+    for (name, offset) in members {
+        match MSL_LAYOUT.iter().find(|(field, _)| field == name) {
+            Some(&(_, expected)) if expected != *offset => mismatches.push(format!(
+                "{}: offset {} in the shader, {} in Rust",
+                name, offset, expected
+            )),
+            Some(_) => {}
+            // Padding holds no values; naga pads structs translated from WGSL with its own
+            None if name.starts_with("_pad") => {}
+            None => mismatches.push(format!(
+                "{}: offset {} in the shader, missing in Rust",
+                name, offset
+            )),
+        }
+    }
+    mismatches
+}
+
+// Per-frame values that are not part of the camera or quality settings
+#[cfg(any(target_os = "macos", target_os = "ios", feature = "wgpu"))]
+pub(crate) struct FrameInputs {
+    pub resolution: [u32; 2],
+    pub time: f32,
+    pub mouse: Vector2<f32>,
+    pub video_mix: f32, // 0 when there is no video texture
+    pub screen_mix: f32,
+    pub audio: AudioLevels,
+    pub clock: ClockPhase,
+    pub selected: Option<usize>, // Object to highlight
+    pub guides: bool,            // Draw the ground grid and axes
+    pub samples: u32,            // Path tracing: paths per pixel averaged so far
+    pub toon: Option<Toon>,      // For every scene, over the scene's own
+    pub edges: Edges,
+    pub shafts: Option<Shafts>,
+    pub adaptive: Option<Adaptive>,
+    pub seed: u32, // See Raymarcher::set_seed
+}
+
+// Scene values the shader reads every frame: lighting, style and the animated parameters
+#[cfg(any(target_os = "macos", target_os = "ios", feature = "wgpu"))]
+#[derive(Clone, Debug)]
+pub(crate) struct SceneInputs {
+    pub light_color: [f32; 3],
+    pub fog_density: f32,
+    pub height_fog: Option<HeightFog>,
+    pub fog_color: Option<[f32; 3]>,
+    pub fog_sun: f32,
+    pub sun: [f32; 3], // Towards the sun, normalized
+    pub caustics: Option<Caustics>,
+    pub floor: Option<Floor>, // Only if enabled
+    pub toon: Option<Toon>,
+    pub params: [[f32; 4]; MAX_PARAMS / 4], // Laid out by ParamLayout
+    pub cells: Vec<Cells>,                  // Of the objects that have them, in order
+    pub volume: Option<Volume>,
+    // Of the top-level nodes (see codegen::proxies), for the Metal renderer's culling
+    #[cfg_attr(not(any(target_os = "macos", target_os = "ios")), allow(dead_code))]
+    pub proxies: Vec<[f32; 4]>,
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios", feature = "wgpu"))]
+impl SceneInputs {
+    pub fn of(scene: &Scene) -> Self {
+        // The real sun's light fades out as it sets
+        let (sun, daylight) = match &scene.environment.sun {
+            Some(sun) => {
+                let direction = sun.direction(SystemTime::now());
+                (direction, sun::daylight(direction))
+            }
+            None => (sun::DEFAULT_DIRECTION, 1.0),
+        };
+        let layout = ParamLayout::of(scene);
+        Self {
+            light_color: scene
+                .environment
+                .light_color
+                .map(|channel| channel * daylight),
+            fog_density: scene.environment.fog_density,
+            height_fog: scene.environment.height_fog,
+            fog_color: scene.environment.fog_color,
+            fog_sun: scene.environment.fog_sun,
+            sun: Vector3::from(sun).normalize().into(),
+            caustics: scene.environment.caustics,
+            floor: scene.ground().copied(),
+            toon: scene.environment.toon,
+            params: layout.values(scene),
+            cells: (scene.objects().into_iter())
+                .filter_map(|object| object.cells.as_deref().cloned())
+                .collect(),
+            volume: scene.volume.clone(),
+            proxies: codegen::proxies(scene, &layout),
+        }
+    }
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios", feature = "wgpu"))]
+impl Uniforms {
+    pub fn new(
+        frame: &FrameInputs,
+        camera: &Camera,
+        quality: &QualityConfig,
+        scene: &SceneInputs,
+    ) -> Self {
+        let toon = frame.toon.or(scene.toon);
+        Self {
+            resolution: [frame.resolution[0] as f32, frame.resolution[1] as f32],
+            time: frame.time,
+            _padding0: [0.0; 1],
+            mouse: frame.mouse.into(),
+            _padding1: [0.0; 2],
+            camera_pos: camera.position.into(),
+            _padding: 0.0,
+            video_mix: frame.video_mix,
+            screen_mix: frame.screen_mix,
+            max_steps: quality.max_steps,
+            epsilon: quality.epsilon,
+            max_distance: quality.max_distance,
+            shadow_steps: quality.shadow_steps,
+            ao_samples: quality.ao_samples,
+            aa: match (frame.adaptive, quality.aa_mode) {
+                (Some(adaptive), _) => adaptive.samples, // In its detailed tiles
+                (None, AaMode::Supersample) => quality.aa.max(1),
+                (None, AaMode::Cone) => 0,
+            },
+            camera_target: camera.target.into(),
+            _padding2: 0.0,
+            light_color: scene.light_color,
+            fog_density: scene.fog_density,
+            params: scene.params,
+            audio: [
+                frame.audio.bass,
+                frame.audio.mid,
+                frame.audio.high,
+                frame.audio.level,
+            ],
+            beat: frame.audio.beat,
+            selected: frame.selected.map_or(-1.0, |id| id as f32),
+            guides: if frame.guides { 1.0 } else { 0.0 },
+            samples: frame.samples as f32,
+            clock: [
+                frame.clock.beats,
+                frame.clock.beat,
+                frame.clock.bar,
+                frame.clock.bpm,
+            ],
+            projection: camera.lens.projection as u32,
+            lens_amount: camera.lens.amount,
+            stereo: camera.stereo.mode as u32,
+            ipd: camera.stereo.ipd,
+            convergence: camera.stereo.convergence,
+            toon_bands: toon.map_or(0, |toon| toon.bands.max(1)),
+            toon_rim: toon.map_or(0.0, |toon| toon.rim),
+            toon_outline: toon.map_or(0.0, |toon| toon.outline),
+            edge_color: frame.edges.line_color(),
+            edge_mode: frame.edges.mode as u32,
+            edge_paper: frame.edges.paper,
+            edge_width: frame.edges.width,
+            edge_depth: frame.edges.depth,
+            edge_normal: frame.edges.normal,
+            _padding3: [0.0; 2],
+            fog_color: match scene.fog_color {
+                Some([r, g, b]) => [r, g, b, 1.0],
+                None => [0.0; 4],
+            },
+            height_fog: scene.height_fog.map_or(0.0, |fog| fog.density),
+            fog_height: scene.height_fog.map_or(0.0, |fog| fog.height),
+            fog_falloff: scene.height_fog.map_or(0.0, |fog| fog.falloff),
+            fog_sun: scene.fog_sun,
+            shaft_samples: frame.shafts.map_or(0, |shafts| shafts.samples),
+            shaft_intensity: frame.shafts.map_or(0.0, |shafts| shafts.intensity),
+            caustic_intensity: scene.caustics.map_or(0.0, |caustics| caustics.intensity),
+            caustic_sharpness: scene.caustics.map_or(0.0, |caustics| caustics.sharpness),
+            floor_color: scene.floor.map_or([0.0; 3], |floor| floor.colors[0]),
+            floor_checker: scene.floor.map_or(0.0, |floor| floor.checker),
+            floor_color2: scene.floor.map_or([0.0; 3], |floor| floor.colors[1]),
+            floor_reflection: scene.floor.map_or(0.0, |floor| floor.reflection),
+            floor_contact: scene.floor.map_or(0.0, |floor| floor.contact),
+            seed: frame.seed,
+            tile_size: frame.adaptive.map_or(0, |adaptive| adaptive.tile.max(1)),
+            _padding4: 0.0,
+            region: camera.region.flipped(),
+            sites: cells::pack(&scene.cells, frame.time),
+            volume_center: scene
+                .volume
+                .as_ref()
+                .map_or([0.0; 3], |volume| volume.position),
+            volume_mode: scene.volume.as_ref().map_or(0, |volume| volume.mode as u32),
+            volume_size: scene.volume.as_ref().map_or([0.0; 3], |volume| volume.size),
+            volume_iso: scene.volume.as_ref().map_or(0.0, |volume| volume.iso),
+            volume_window: scene
+                .volume
+                .as_ref()
+                .map_or([0.0, 1.0], |volume| volume.window),
+            volume_density: scene.volume.as_ref().map_or(0.0, |volume| volume.density),
+            volume_steps: scene.volume.as_ref().map_or(0, |volume| volume.steps),
+            sun_dir: scene.sun,
+            _padding5: 0.0,
+        }
+    }
+
+    #[cfg_attr(not(any(target_os = "macos", target_os = "ios")), allow(dead_code))]
+    pub fn set_samples(&mut self, samples: u32) {
+        self.samples = samples as f32;
+    }
+
+    // The uniforms without what moves on by itself every frame (time, audio, clock) or doesn't
+    // show in a path-traced image (the mouse, the selection and the grid), to tell whether one
+    // averaged over several frames still shows the same thing. Anything else changing (the
+    // camera, a parameter, the quality, the size) starts it over.
+    pub fn still_key(&self) -> Vec<u8> {
+        let still = Self {
+            time: 0.0,
+            mouse: [0.0; 2],
+            audio: [0.0; 4],
+            beat: 0.0,
+            clock: [0.0; 4],
+            selected: 0.0,
+            guides: 0.0,
+            samples: 0.0,
+            ..*self
+        };
+        still.as_bytes().to_vec()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        bytemuck::bytes_of(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Members of `struct Uniforms` in a shader's source with the offsets its layout rules give
+    // them, and the struct's size. `member` splits a line into name, type and array length,
+    // and `layout` gives a type's size and alignment.
+    fn shader_layout(
+        source: &str,
+        member: fn(&str) -> (&str, &str, usize),
+        layout: fn(&str) -> (usize, usize),
+    ) -> (Vec<(String, usize)>, usize) {
+        let (_, body) = source.split_once("struct Uniforms {").unwrap();
+        let (body, _) = body.split_once("\n}").unwrap();
+        let (mut members, mut offset, mut alignment) = (Vec::new(), 0, 1);
+        let lines = body
+            .lines()
+            .map(|line| line.split("//").next().unwrap().trim());
+        for line in lines.filter(|line| !line.is_empty()) {
+            let (name, kind, count) = member(line);
+            let (size, align) = layout(kind);
+            offset = usize::next_multiple_of(offset, align);
+            members.push((name.to_string(), offset));
+            offset += size * count; // Arrays are only of 16-byte vectors, so have no gaps
+            alignment = alignment.max(align);
+        }
+        (members, offset.next_multiple_of(alignment))
+    }
+
+    // "float4 params[16];"
+    fn msl_member(line: &str) -> (&str, &str, usize) {
+        let (kind, name) = line.trim_end_matches(';').split_once(' ').unwrap();
+        match name.split_once('[') {
+            Some((name, count)) => (name, kind, count.trim_end_matches(']').parse().unwrap()),
+            None => (name, kind, 1),
+        }
+    }
+
+    fn msl_type(kind: &str) -> (usize, usize) {
+        match kind {
+            "float" | "uint" | "int" => (4, 4),
+            "float2" => (8, 8),
+            "float3" | "float4" => (16, 16),
+            "packed_float3" => (12, 4),
+            _ => panic!("no layout for MSL type {}", kind),
+        }
+    }
+
+    // "params: array<vec4<f32>, 16>,"
+    fn wgsl_member(line: &str) -> (&str, &str, usize) {
+        let (name, kind) = line.trim_end_matches(',').split_once(':').unwrap();
+        match kind.trim().strip_prefix("array<") {
+            Some(array) => {
+                let (kind, count) = array.strip_suffix('>').unwrap().rsplit_once(',').unwrap();
+                (name, kind, count.trim().parse().unwrap())
+            }
+            None => (name, kind.trim(), 1),
+        }
+    }
+
+    fn wgsl_type(kind: &str) -> (usize, usize) {
+        match kind {
+            "f32" | "u32" | "i32" => (4, 4),
+            "vec2<f32>" => (8, 8),
+            "vec3<f32>" => (12, 16),
+            "vec4<f32>" => (16, 16),
+            _ => panic!("no layout for WGSL type {}", kind),
+        }
+    }
+
+    fn offset(field: &str) -> usize {
+        MSL_LAYOUT
+            .iter()
+            .find(|(name, _)| *name == field)
+            .unwrap()
+            .1
+    }
+
+    // The shader's fields sit where Rust puts them, and it has every one, in the same order
+    fn assert_declares_rust_layout(
+        file: &str,
+        source: &str,
+        member: fn(&str) -> (&str, &str, usize),
+        layout: fn(&str) -> (usize, usize),
+    ) {
+        let (members, size) = shader_layout(source, member, layout);
+        assert_eq!(size, std::mem::size_of::<Uniforms>(), "{}", file);
+        let mismatches = layout_mismatches(size, &members);
+        assert!(mismatches.is_empty(), "{}: {:?}", file, mismatches);
+        let fields = |names: Vec<&str>| -> Vec<String> {
+            let names = names.into_iter().filter(|name| !name.starts_with("_pad"));
+            names.map(str::to_string).collect()
+        };
+        assert_eq!(
+            fields(members.iter().map(|(name, _)| name.as_str()).collect()),
+            fields(MSL_LAYOUT.iter().map(|&(name, _)| name).collect()),
+            "{}",
+            file
+        );
+    }
+
+    #[test]
+    fn shaders_declare_the_rust_layout() {
+        let metal = include_str!("shaders.metal");
+        assert_declares_rust_layout("shaders.metal", metal, msl_member, msl_type);
+        let wgsl = include_str!("shaders.wgsl");
+        assert_declares_rust_layout("shaders.wgsl", wgsl, wgsl_member, wgsl_type);
+    }
+
+    #[test]
+    fn layout_mismatches_are_reported() {
+        let members = [
+            ("time".to_string(), offset("time")),
+            ("mouse".to_string(), offset("mouse") + 4),
+            ("missing".to_string(), 0),
+            ("_pad9".to_string(), 4),
+        ];
+        let mismatches = layout_mismatches(std::mem::size_of::<Uniforms>() + 16, &members);
+        assert_eq!(
+            mismatches,
+            [
+                "size: 1712 bytes in the shader, 1696 in Rust",
+                "mouse: offset 20 in the shader, 16 in Rust",
+                "missing: offset 0 in the shader, missing in Rust",
+            ]
+        );
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "ios", feature = "wgpu"))]
+    #[test]
+    fn bytes_are_the_fields_at_their_offsets() {
+        let mut uniforms = Uniforms::zeroed();
+        uniforms.time = 2.5;
+        uniforms.seed = 7;
+        uniforms.sun_dir = [0.0, 1.0, 0.5];
+        let bytes = uniforms.as_bytes();
+        assert_eq!(bytes.len(), std::mem::size_of::<Uniforms>());
+        let at = |field: &str, len: usize| &bytes[offset(field)..offset(field) + len];
+        assert_eq!(at("time", 4), 2.5f32.to_ne_bytes());
+        assert_eq!(at("seed", 4), 7u32.to_ne_bytes());
+        assert_eq!(at("sun_dir", 12), bytemuck::cast_slice(&[0.0f32, 1.0, 0.5]));
+        assert!(bytes[offset("_padding5")..].iter().all(|&byte| byte == 0));
+
+        // The still key ignores the time, but not the camera
+        let later = Uniforms {
+            time: 9.0,
+            ..uniforms
+        };
+        assert_eq!(later.still_key(), uniforms.still_key());
+        let moved = Uniforms {
+            camera_pos: [1.0, 0.0, 0.0],
+            ..uniforms
+        };
+        assert_ne!(moved.still_key(), uniforms.still_key());
+    }
+}
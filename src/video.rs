@@ -0,0 +1,297 @@
+use crate::frames::*;
+use crate::inputs::{TextureSource, VideoSourceKind};
+use cocoa::base::{id, nil};
+use metal::*;
+use objc::rc::autoreleasepool;
+use objc::runtime::{Class, Object, Sel, BOOL, NO, YES};
+use objc::{class, msg_send, sel, sel_impl};
+use std::sync::{Arc, Mutex, OnceLock};
+
+const AV_PLAYER_ACTION_AT_ITEM_END_NONE: i64 = 2;
+
+#[link(name = "AVFoundation", kind = "framework")]
+extern "C" {
+    static AVMediaTypeVideo: id;
+}
+
+#[link(name = "CoreMedia", kind = "framework")]
+extern "C" {
+    static kCMTimeZero: CMTime;
+    fn CMTimeCompare(time1: CMTime, time2: CMTime) -> i32;
+}
+
+#[link(name = "QuartzCore", kind = "framework")]
+extern "C" {
+    fn CACurrentMediaTime() -> f64;
+}
+
+enum Backend {
+    Camera {
+        session: id,
+        output: id,
+        delegate: id,
+        queue: id,
+    },
+    File {
+        player: id,
+        item: id,
+        output: id,
+    },
+}
+
+pub struct VideoInput {
+    backend: Backend,
+    latest: Arc<FrameSlot>, // Most recent frame not yet uploaded
+    texture: FrameTexture,
+}
+
+// The session/player objects are only messaged by the owner after setup, from one thread
+// at a time; frames arrive through the Mutex-guarded slot
+unsafe impl Send for VideoInput {}
+
+impl VideoInput {
+    pub fn new(device: &DeviceRef, kind: &VideoSourceKind) -> Result<Self, String> {
+        let latest: Arc<FrameSlot> = Arc::new(Mutex::new(None));
+        let backend = match kind {
+            VideoSourceKind::Camera { device: name } => unsafe {
+                open_camera(name.as_deref(), &latest)?
+            },
+            VideoSourceKind::File(path) => unsafe { open_file(path)? },
+        };
+
+        Ok(Self {
+            backend,
+            latest,
+            texture: FrameTexture::new(device),
+        })
+    }
+}
+
+impl TextureSource for VideoInput {
+    fn upload(&mut self, device: &DeviceRef) {
+        autoreleasepool(|| {
+            if let Backend::File { item, output, .. } = self.backend {
+                unsafe { poll_file_frame(item, output, &self.latest) };
+            }
+            self.texture.upload_from(device, &self.latest);
+        });
+    }
+
+    fn texture(&self) -> &TextureRef {
+        self.texture.texture()
+    }
+}
+
+impl Drop for VideoInput {
+    fn drop(&mut self) {
+        unsafe {
+            match self.backend {
+                Backend::Camera {
+                    session,
+                    output,
+                    delegate,
+                    queue,
+                } => {
+                    let _: () = msg_send![session, stopRunning];
+                    let _: () = msg_send![output, setSampleBufferDelegate: nil queue: nil];
+                    // A frame may still be on its way to the delegate and its slot
+                    drain(queue);
+                    let _: () = msg_send![delegate, release];
+                    let _: () = msg_send![output, release];
+                    let _: () = msg_send![session, release];
+                    dispatch_release(queue);
+                }
+                Backend::File {
+                    player,
+                    item,
+                    output,
+                } => {
+                    let _: () = msg_send![player, pause];
+                    let _: () = msg_send![output, release];
+                    let _: () = msg_send![item, release];
+                    let _: () = msg_send![player, release];
+                }
+            }
+        }
+    }
+}
+
+// Names of the available capture devices, for picking one in the config
+pub fn camera_names() -> Vec<String> {
+    unsafe {
+        let devices: id =
+            msg_send![class!(AVCaptureDevice), devicesWithMediaType: AVMediaTypeVideo];
+        let count: usize = msg_send![devices, count];
+        (0..count)
+            .map(|i| {
+                let device: id = msg_send![devices, objectAtIndex: i];
+                let name: id = msg_send![device, localizedName];
+                string_from_ns(name)
+            })
+            .collect()
+    }
+}
+
+// Pixel buffer attributes asking AVFoundation for BGRA frames (matches our texture format)
+unsafe fn bgra_settings() -> id {
+    let format: id =
+        msg_send![class!(NSNumber), numberWithUnsignedInt: K_CV_PIXEL_FORMAT_TYPE_32_BGRA];
+    msg_send![class!(NSDictionary), dictionaryWithObject: format forKey: kCVPixelBufferPixelFormatTypeKey]
+}
+
+unsafe fn open_camera(name: Option<&str>, latest: &Arc<FrameSlot>) -> Result<Backend, String> {
+    let capture_device: id = match name {
+        Some(wanted) => {
+            let devices: id =
+                msg_send![class!(AVCaptureDevice), devicesWithMediaType: AVMediaTypeVideo];
+            let count: usize = msg_send![devices, count];
+            let mut found = nil;
+            for i in 0..count {
+                let device: id = msg_send![devices, objectAtIndex: i];
+                let device_name: id = msg_send![device, localizedName];
+                if string_from_ns(device_name).contains(wanted) {
+                    found = device;
+                    break;
+                }
+            }
+            found
+        }
+        None => msg_send![class!(AVCaptureDevice), defaultDeviceWithMediaType: AVMediaTypeVideo],
+    };
+    if capture_device.is_null() {
+        return Err(format!(
+            "No camera matching {:?} (available: {:?})",
+            name.unwrap_or("default"),
+            camera_names()
+        ));
+    }
+
+    let mut error: id = nil;
+    let input: id = msg_send![class!(AVCaptureDeviceInput), deviceInputWithDevice: capture_device error: &mut error];
+    if input.is_null() {
+        let description: id = msg_send![error, localizedDescription];
+        return Err(format!(
+            "Failed to open camera: {}",
+            string_from_ns(description)
+        ));
+    }
+
+    let session: id = msg_send![class!(AVCaptureSession), new];
+    let can_add_input: BOOL = msg_send![session, canAddInput: input];
+    if can_add_input == NO {
+        let _: () = msg_send![session, release];
+        return Err("Camera input rejected by capture session".to_string());
+    }
+    let _: () = msg_send![session, addInput: input];
+
+    let output: id = msg_send![class!(AVCaptureVideoDataOutput), new];
+    let _: () = msg_send![output, setVideoSettings: bgra_settings()];
+    let _: () = msg_send![output, setAlwaysDiscardsLateVideoFrames: YES];
+
+    // Frames arrive on a private serial queue and are parked in the shared slot
+    let delegate = new_frame_sink(delegate_class(), latest);
+    let queue = dispatch_queue_create(c"raymarch.video".as_ptr(), std::ptr::null_mut());
+    let _: () = msg_send![output, setSampleBufferDelegate: delegate queue: queue];
+
+    let can_add_output: BOOL = msg_send![session, canAddOutput: output];
+    if can_add_output == NO {
+        let _: () = msg_send![delegate, release];
+        let _: () = msg_send![output, release];
+        let _: () = msg_send![session, release];
+        dispatch_release(queue);
+        return Err("Video output rejected by capture session".to_string());
+    }
+    let _: () = msg_send![session, addOutput: output];
+    let _: () = msg_send![session, startRunning];
+
+    let device_name: id = msg_send![capture_device, localizedName];
+    println!("Video input: camera \"{}\"", string_from_ns(device_name));
+
+    Ok(Backend::Camera {
+        session,
+        output,
+        delegate,
+        queue,
+    })
+}
+
+unsafe fn open_file(path: &std::path::Path) -> Result<Backend, String> {
+    if !path.exists() {
+        return Err(format!("Video file not found: {}", path.display()));
+    }
+    let url: id = msg_send![class!(NSURL), fileURLWithPath: ns_string(&path.to_string_lossy())];
+    let item: id = msg_send![class!(AVPlayerItem), playerItemWithURL: url];
+    let _: id = msg_send![item, retain];
+
+    let output: id = msg_send![class!(AVPlayerItemVideoOutput), alloc];
+    let output: id = msg_send![output, initWithPixelBufferAttributes: bgra_settings()];
+    let _: () = msg_send![item, addOutput: output];
+
+    let player: id = msg_send![class!(AVPlayer), playerWithPlayerItem: item];
+    let _: id = msg_send![player, retain];
+    let _: () = msg_send![player, setMuted: YES];
+    // We loop manually in poll_file_frame
+    let _: () = msg_send![player, setActionAtItemEnd: AV_PLAYER_ACTION_AT_ITEM_END_NONE];
+    let _: () = msg_send![player, play];
+
+    println!("Video input: file {}", path.display());
+    Ok(Backend::File {
+        player,
+        item,
+        output,
+    })
+}
+
+// Pull the frame for "now" from the player output and loop at the end of the file
+unsafe fn poll_file_frame(item: id, output: id, latest: &FrameSlot) {
+    let current: CMTime = msg_send![item, currentTime];
+    let duration: CMTime = msg_send![item, duration];
+    if CMTimeCompare(current, kCMTimeZero) > 0 && CMTimeCompare(current, duration) >= 0 {
+        let _: () = msg_send![item, seekToTime: kCMTimeZero];
+    }
+
+    let item_time: CMTime = msg_send![output, itemTimeForHostTime: CACurrentMediaTime()];
+    let has_new: BOOL = msg_send![output, hasNewPixelBufferForItemTime: item_time];
+    if has_new == NO {
+        return;
+    }
+    let pixel_buffer: CVPixelBufferRef = msg_send![output,
+        copyPixelBufferForItemTime: item_time
+        itemTimeForDisplay: std::ptr::null_mut::<CMTime>()];
+    // copyPixelBuffer returns a +1 reference, so no extra retain here
+    if let Some(frame) = PixelBuffer::from_owned(pixel_buffer) {
+        if let Ok(mut slot) = latest.lock() {
+            *slot = Some(frame);
+        }
+    }
+}
+
+// AVCaptureVideoDataOutputSampleBufferDelegate implementation, registered once
+fn delegate_class() -> &'static Class {
+    static CLASS: OnceLock<&'static Class> = OnceLock::new();
+    CLASS.get_or_init(|| {
+        let mut decl = frame_sink_class_decl("RaymarchVideoDelegate");
+
+        extern "C" fn did_output(
+            this: &Object,
+            _sel: Sel,
+            _output: id,
+            sample_buffer: CMSampleBufferRef,
+            _connection: id,
+        ) {
+            unsafe {
+                if let Some(frame) = PixelBuffer::from_sample_buffer(sample_buffer) {
+                    deliver_frame(this, frame);
+                }
+            }
+        }
+
+        unsafe {
+            decl.add_method(
+                sel!(captureOutput:didOutputSampleBuffer:fromConnection:),
+                did_output as extern "C" fn(&Object, Sel, id, CMSampleBufferRef, id),
+            );
+        }
+        decl.register()
+    })
+}
@@ -0,0 +1,508 @@
+// Volume datasets, such as CT or MRI scans, drawn into the scene by its [volume] table:
+//
+//   [volume]
+//   file = "head.nrrd"          # NRRD, OpenVDB, or raw voxels with `dims` and `format`
+//   position = [0.0, 1.0, 0.0]  # Center of the box the data fills
+//   size = [1.0, 1.0, 0.6]      # Half its width, height and depth, like a box shape
+//   mode = "composite"          # Or "iso" for the surface where the data crosses `iso`
+//   iso = 0.4
+//   density = 8.0               # Opacity per unit of distance at full opacity
+//   window = [0.1, 0.9]         # Data values mapped to 0 and 1, for `iso` and the ramps
+//   steps = 128                 # Samples along a ray through the whole box
+//   color = [[0.0, 0.2, 0.1, 0.1], [0.5, 0.9, 0.5, 0.4], [1.0, 1.0, 1.0, 0.95]]
+//   opacity = [[0.0, 0.0], [0.3, 0.0], [0.6, 0.4], [1.0, 0.9]]
+//
+// The data is read into a 3D texture of values from 0 to 1 (the file's lowest to its highest),
+// at most MAX_SIDE voxels along each axis: larger files are averaged down. The transfer
+// function turns a value, after `window`, into a color and opacity: the two ramps list
+// [value, r, g, b] and [value, opacity] stops, blended linearly between them and baked into a
+// TRANSFER_SIZE x 1 texture.
+//
+// The shader marches rays through the box after the scene's surfaces (see withVolume in the
+// shaders), stopping at them. "composite" adds up the ramps' color along the ray, and "iso"
+// stops where the data first crosses `iso` and shades that point with the sun, the normal
+// taken from the data's gradient. `iso`, `density` and `window` are read every frame, so they
+// can be dragged, animated or sent over the remote ("volume.iso" and so on); the ramps are
+// read when the scene is loaded or reloaded. Path tracing leaves the volume out.
+//
+// NRRD files may keep their data after the header or in the file named by `data file`, and
+// must be 3D, raw encoded (not gzip) and of an integer or float type. A raw file is just the
+// voxels, x fastest, of the type `format` names ("u8", "i8", "u16", "i16", "u32", "i32" or
+// "f32"), after `offset` bytes, little endian unless `big_endian`.
+//
+// OpenVDB files (.vdb), such as smoke and cloud simulations, need the "openvdb" feature (the
+// vdb-rs crate). The float grid named by `grid` ("density" by default, or else the first) is
+// read from the active voxels of its leaves, over the box around them; values held by the
+// tiles of larger nodes are left out. Sparse grids are filled in densely, averaged down to
+// MAX_SIDE as they are read, so a grid far larger than the texture never sits in memory whole.
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+// Most voxels along any axis of the texture
+pub const MAX_SIDE: u32 = 256;
+
+// Most voxels a raw or NRRD file may hold, before they are averaged down
+pub const MAX_VOXELS: usize = 1 << 30;
+
+// Entries in the transfer function's texture
+pub const TRANSFER_SIZE: u32 = 256;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Volume {
+    pub file: PathBuf, // Relative to the scene file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dims: Option<[u32; 3]>, // Of a raw file
+    pub format: Format,
+    pub big_endian: bool,
+    pub offset: usize, // Bytes before a raw file's voxels
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grid: Option<String>, // Of an OpenVDB file
+    pub position: [f32; 3],
+    pub size: [f32; 3],
+    pub mode: VolumeMode,
+    pub iso: f32,
+    pub density: f32,
+    pub window: [f32; 2],
+    pub steps: u32,
+    pub color: Vec<[f32; 4]>,
+    pub opacity: Vec<[f32; 2]>,
+}
+
+impl Default for Volume {
+    fn default() -> Self {
+        Self {
+            file: PathBuf::new(),
+            dims: None,
+            format: Format::U8,
+            big_endian: false,
+            offset: 0,
+            grid: None,
+            position: [0.0; 3],
+            size: [1.0; 3],
+            mode: VolumeMode::Composite,
+            iso: 0.5,
+            density: 8.0,
+            window: [0.0, 1.0],
+            steps: 128,
+            color: vec![[0.0, 0.0, 0.0, 0.0], [1.0, 1.0, 1.0, 1.0]],
+            opacity: vec![[0.0, 0.0], [1.0, 1.0]],
+        }
+    }
+}
+
+// What the shader draws of the volume
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VolumeMode {
+    Composite, // Color and opacity accumulated along the ray
+    Iso,       // The first surface where the data reaches `iso`
+}
+
+// Type of a voxel
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+}
+
+impl Format {
+    fn size(self) -> usize {
+        match self {
+            Format::U8 | Format::I8 => 1,
+            Format::U16 | Format::I16 => 2,
+            Format::U32 | Format::I32 | Format::F32 => 4,
+        }
+    }
+
+    // The voxel in `bytes` (as many as the size)
+    fn read(self, bytes: &[u8], big_endian: bool) -> f32 {
+        let mut raw = [0u8; 4];
+        raw[..bytes.len()].copy_from_slice(bytes);
+        if big_endian {
+            raw[..bytes.len()].reverse();
+        }
+        let [a, b, c, d] = raw;
+        match self {
+            Format::U8 => a as f32,
+            Format::I8 => a as i8 as f32,
+            Format::U16 => u16::from_le_bytes([a, b]) as f32,
+            Format::I16 => i16::from_le_bytes([a, b]) as f32,
+            Format::U32 => u32::from_le_bytes(raw) as f32,
+            Format::I32 => i32::from_le_bytes(raw) as f32,
+            Format::F32 => f32::from_le_bytes([a, b, c, d]),
+        }
+    }
+
+    // The type an NRRD header names
+    fn nrrd(name: &str) -> Result<Self, String> {
+        Ok(match name {
+            "uchar" | "unsigned char" | "uint8" | "uint8_t" => Format::U8,
+            "signed char" | "int8" | "int8_t" => Format::I8,
+            "ushort" | "unsigned short" | "unsigned short int" | "uint16" | "uint16_t" => {
+                Format::U16
+            }
+            "short" | "short int" | "signed short" | "signed short int" | "int16" | "int16_t" => {
+                Format::I16
+            }
+            "uint" | "unsigned int" | "uint32" | "uint32_t" => Format::U32,
+            "int" | "signed int" | "int32" | "int32_t" => Format::I32,
+            "float" => Format::F32,
+            _ => return Err(format!("unsupported type \"{}\"", name)),
+        })
+    }
+}
+
+impl Volume {
+    // Check the volume can be drawn: a file, a box, ramps with stops and a sample to a step
+    pub fn validate(&self) -> Result<(), String> {
+        if self.file.as_os_str().is_empty() {
+            return Err("needs a file".to_string());
+        }
+        if self.size.iter().any(|&side| side <= 0.0) {
+            return Err("size must be above 0 along every axis".to_string());
+        }
+        if self.color.is_empty() || self.opacity.is_empty() {
+            return Err("color and opacity need at least one stop".to_string());
+        }
+        if self.steps == 0 {
+            return Err("steps must be at least 1".to_string());
+        }
+        if self.window[1] <= self.window[0] {
+            return Err("window must run from a lower value to a higher one".to_string());
+        }
+        Ok(())
+    }
+}
+
+// The voxels of a volume as the renderers upload them
+pub struct VolumeData {
+    pub dims: [u32; 3],
+    pub voxels: Vec<u8>,   // R8, x fastest, then y, then z
+    pub transfer: Vec<u8>, // RGBA8, TRANSFER_SIZE wide and 1 high
+}
+
+impl VolumeData {
+    // A single empty voxel, bound when the scene has no volume
+    pub fn empty() -> Self {
+        Self {
+            dims: [1; 3],
+            voxels: vec![0],
+            transfer: vec![0; 4],
+        }
+    }
+
+    // The scene's volume, or the empty one when it has none or it can't be read
+    pub fn of(volume: Option<&Volume>) -> Self {
+        let Some(volume) = volume else {
+            return Self::empty();
+        };
+        match load(volume) {
+            Ok((dims, voxels)) => Self {
+                dims,
+                voxels,
+                transfer: transfer(volume),
+            },
+            Err(err) => {
+                eprintln!("{}", err);
+                Self::empty()
+            }
+        }
+    }
+}
+
+// The voxels of the volume's file, scaled to 0-255 and averaged down to MAX_SIDE, with the
+// number along each axis
+pub fn load(volume: &Volume) -> Result<([u32; 3], Vec<u8>), String> {
+    let path = &volume.file;
+    let error = |err: String| format!("Invalid volume {}: {}", path.display(), err);
+    let is_vdb = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("vdb"));
+    let bytes = if is_vdb {
+        Vec::new()
+    } else {
+        std::fs::read(path).map_err(|err| format!("Failed to read {}: {}", path.display(), err))?
+    };
+    let (dims, values) = if is_vdb {
+        parse_vdb(path, volume.grid.as_deref()).map_err(error)?
+    } else if bytes.starts_with(b"NRRD") {
+        parse_nrrd(&bytes, path).map_err(error)?
+    } else {
+        let dims = volume
+            .dims
+            .ok_or_else(|| error("raw files need dims".to_string()))?;
+        let data = bytes.get(volume.offset..).unwrap_or_default();
+        let values = voxels(data, dims, volume.format, volume.big_endian).map_err(error)?;
+        (dims, values)
+    };
+    let (dims, values) = shrink(dims, values);
+    let (low, high) = (values.iter())
+        .filter(|value| value.is_finite())
+        .fold((f32::MAX, f32::MIN), |(low, high), &value| {
+            (low.min(value), high.max(value))
+        });
+    let range = (high - low).max(f32::EPSILON);
+    let voxels = (values.iter())
+        .map(|&value| ((value - low) / range * 255.0).round().clamp(0.0, 255.0) as u8)
+        .collect();
+    Ok((dims, voxels))
+}
+
+// The first `dims` voxels of `data`
+fn voxels(
+    data: &[u8],
+    dims: [u32; 3],
+    format: Format,
+    big_endian: bool,
+) -> Result<Vec<f32>, String> {
+    if dims.contains(&0) {
+        return Err("dims must be at least 1 along every axis".to_string());
+    }
+    let count = (dims.iter())
+        .try_fold(1usize, |count, &n| count.checked_mul(n as usize))
+        .filter(|&count| count <= MAX_VOXELS)
+        .ok_or_else(|| {
+            let [x, y, z] = dims;
+            format!("{} x {} x {} is over {} voxels", x, y, z, MAX_VOXELS)
+        })?;
+    let size = format.size();
+    let bytes = count * size; // At most 4 bytes for each of MAX_VOXELS
+    if data.len() < bytes {
+        return Err(format!(
+            "{} bytes of voxels, {} x {} x {} of {} bytes need {}",
+            data.len(),
+            dims[0],
+            dims[1],
+            dims[2],
+            size,
+            bytes
+        ));
+    }
+    Ok((data.chunks_exact(size).take(count))
+        .map(|bytes| format.read(bytes, big_endian))
+        .collect())
+}
+
+// The size and voxels of an NRRD file, read from `path`
+pub fn parse_nrrd(bytes: &[u8], path: &Path) -> Result<([u32; 3], Vec<f32>), String> {
+    // The header ends at the first empty line
+    let mut at = 0;
+    let mut fields = Vec::new();
+    loop {
+        let end = (bytes[at..].iter())
+            .position(|&byte| byte == b'\n')
+            .ok_or("no end to the header")?;
+        let line = String::from_utf8_lossy(&bytes[at..at + end]);
+        let line = line.trim_end_matches('\r');
+        at += end + 1;
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with('#') || line.starts_with("NRRD") {
+            continue;
+        }
+        if let Some((key, value)) = line.split_once(':') {
+            let value = value.trim_start_matches('=').trim();
+            fields.push((key.trim().to_lowercase(), value.to_string()));
+        }
+    }
+    let field = |name: &str| {
+        (fields.iter())
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+
+    let format = Format::nrrd(field("type").ok_or("no type")?)?;
+    if field("dimension") != Some("3") {
+        return Err("only 3D volumes are supported".to_string());
+    }
+    let sizes: Vec<u32> = (field("sizes").ok_or("no sizes")?.split_whitespace())
+        .map(|word| word.parse().map_err(|_| format!("bad size \"{}\"", word)))
+        .collect::<Result<_, _>>()?;
+    let dims: [u32; 3] = sizes.try_into().map_err(|_| "sizes need three numbers")?;
+    match field("encoding") {
+        Some("raw") => {}
+        Some(encoding) => return Err(format!("unsupported encoding \"{}\"", encoding)),
+        None => return Err("no encoding".to_string()),
+    }
+    let big_endian = field("endian") == Some("big");
+    let skip: usize = match field("byte skip") {
+        Some(skip) => skip.parse().map_err(|_| "bad byte skip")?,
+        None => 0,
+    };
+
+    let detached = field("data file").or(field("datafile"));
+    let data = match detached {
+        Some(name) => {
+            let file = path.parent().unwrap_or(Path::new("")).join(name);
+            std::fs::read(&file)
+                .map_err(|err| format!("Failed to read {}: {}", file.display(), err))?
+        }
+        None => bytes[at..].to_vec(),
+    };
+    let data = data.get(skip..).unwrap_or_default();
+    Ok((dims, voxels(data, dims, format, big_endian)?))
+}
+
+// The size and voxels of grid `grid` of the OpenVDB file at `path`, averaged down to MAX_SIDE
+#[cfg(feature = "openvdb")]
+pub fn parse_vdb(path: &Path, grid: Option<&str>) -> Result<([u32; 3], Vec<f32>), String> {
+    let file = std::fs::File::open(path)
+        .map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+    let mut reader =
+        vdb_rs::VdbReader::new(std::io::BufReader::new(file)).map_err(|err| err.to_string())?;
+    let name = match grid {
+        Some(name) => name.to_string(),
+        None => {
+            let names = reader.available_grids();
+            (names.iter().find(|name| *name == "density"))
+                .or(names.first())
+                .cloned()
+                .ok_or("no grids")?
+        }
+    };
+    let grid =
+        (reader.read_grid::<f32>(&name)).map_err(|err| format!("grid \"{}\": {}", name, err))?;
+
+    let (mut low, mut high) = ([i32::MAX; 3], [i32::MIN; 3]);
+    for (at, _) in grid.iter() {
+        let at = [at.x, at.y, at.z].map(|c| c.round() as i32);
+        for axis in 0..3 {
+            low[axis] = low[axis].min(at[axis]);
+            high[axis] = high[axis].max(at[axis]);
+        }
+    }
+    if low[0] > high[0] {
+        return Err(format!("grid \"{}\" has no active voxels", name));
+    }
+    // In i64, as the grid may span more than i32 or u32 holds
+    let extent = [0, 1, 2].map(|axis| high[axis] as i64 - low[axis] as i64 + 1);
+    let factor = extent.map(|n| (n as u64).div_ceil(MAX_SIDE as u64));
+    let dims = [0, 1, 2].map(|axis| (extent[axis] as u64).div_ceil(factor[axis]) as u32);
+    let mut values = vec![0.0; dims.iter().map(|&n| n as usize).product()];
+    for (at, value) in grid.iter() {
+        let at = [at.x, at.y, at.z].map(|c| c.round() as i32);
+        let [x, y, z] = [0, 1, 2]
+            .map(|axis| ((at[axis] as i64 - low[axis] as i64) as u64 / factor[axis]) as usize);
+        let [width, height] = [dims[0], dims[1]].map(|n| n as usize);
+        if value.is_finite() {
+            values[x + width * (y + height * z)] += value;
+        }
+    }
+    // Inactive voxels count as empty in the average
+    let block = factor.iter().map(|&n| n as f32).product::<f32>();
+    for value in &mut values {
+        *value /= block;
+    }
+    Ok((dims, values))
+}
+
+#[cfg(not(feature = "openvdb"))]
+pub fn parse_vdb(path: &Path, _grid: Option<&str>) -> Result<([u32; 3], Vec<f32>), String> {
+    Err(format!(
+        "can't read {}: built without the \"openvdb\" feature",
+        path.display()
+    ))
+}
+
+// At most MAX_SIDE voxels along each axis, averaging blocks of them
+fn shrink(dims: [u32; 3], values: Vec<f32>) -> ([u32; 3], Vec<f32>) {
+    let factor = dims.map(|n| n.div_ceil(MAX_SIDE));
+    if factor == [1; 3] {
+        return (dims, values);
+    }
+    let shrunk = [0, 1, 2].map(|axis| dims[axis].div_ceil(factor[axis]));
+    let index = |[x, y, z]: [u32; 3], dims: [u32; 3]| {
+        let [x, y, z, width, height] = [x, y, z, dims[0], dims[1]].map(|n| n as usize);
+        x + width * (y + height * z)
+    };
+    let mut out = vec![0.0; shrunk.iter().map(|&n| n as usize).product()];
+    let mut counts = vec![0.0f32; out.len()];
+    for z in 0..dims[2] {
+        for y in 0..dims[1] {
+            for x in 0..dims[0] {
+                let value = values[index([x, y, z], dims)];
+                if !value.is_finite() {
+                    continue;
+                }
+                let cell = index([x / factor[0], y / factor[1], z / factor[2]], shrunk);
+                out[cell] += value;
+                counts[cell] += 1.0;
+            }
+        }
+    }
+    for (value, count) in out.iter_mut().zip(counts) {
+        *value /= count.max(1.0);
+    }
+    (shrunk, out)
+}
+
+// The transfer function of `volume`: its ramps at TRANSFER_SIZE evenly spaced values, as RGBA8
+pub fn transfer(volume: &Volume) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(TRANSFER_SIZE as usize * 4);
+    for i in 0..TRANSFER_SIZE {
+        let value = i as f32 / (TRANSFER_SIZE - 1) as f32;
+        let [r, g, b] = [1, 2, 3].map(|channel| ramp(&volume.color, value, channel));
+        let a = ramp(&volume.opacity, value, 1);
+        pixels.extend([r, g, b, a].map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8));
+    }
+    pixels
+}
+
+// Channel `channel` of the stops (value first) at `value`, held flat past the ends
+fn ramp<const N: usize>(stops: &[[f32; N]], value: f32, channel: usize) -> f32 {
+    let mut stops = stops.to_vec();
+    stops.sort_by(|a, b| a[0].total_cmp(&b[0]));
+    let Some(first) = stops.first() else {
+        return 0.0;
+    };
+    if value <= first[0] {
+        return first[channel];
+    }
+    for pair in stops.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        if value <= b[0] {
+            let t = (value - a[0]) / (b[0] - a[0]).max(f32::EPSILON);
+            return a[channel] + (b[channel] - a[channel]) * t;
+        }
+    }
+    stops[stops.len() - 1][channel]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oversized_volumes_are_rejected() {
+        let header = |sizes: &str| {
+            let text = format!(
+                "NRRD0004\ntype: uint8\ndimension: 3\nsizes: {}\nencoding: raw\n\n",
+                sizes
+            );
+            text.into_bytes()
+        };
+        let mut small = header("2 2 2");
+        small.extend([0; 8]);
+        assert_eq!(parse_nrrd(&small, Path::new("")).unwrap().1.len(), 8);
+        // 2^22 * 2^21 * 2^21 wraps around to 0 in a usize
+        for sizes in [
+            "4194304 2097152 2097152",
+            "4294967295 4294967295 4294967295",
+            "2048 2048 2048",
+        ] {
+            assert!(
+                parse_nrrd(&header(sizes), Path::new("")).is_err(),
+                "{}",
+                sizes
+            );
+        }
+    }
+}
@@ -0,0 +1,384 @@
+// The server side of WebSocket (RFC 6455), just enough for remote.rs and livecode.rs: the
+// opening handshake, text messages (fragmented or not) both ways, pings and closing. Binary
+// messages are refused, and so are messages over MAX_MESSAGE bytes.
+use crate::base64;
+use crate::http;
+use std::io::{BufReader, Read, Write};
+use std::net::TcpStream;
+
+const MAX_MESSAGE: usize = 1 << 20;
+
+// Appended to the client's key before hashing it into the handshake's answer
+const KEY_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+pub struct WebSocket {
+    reader: BufReader<TcpStream>,
+    stream: TcpStream,
+}
+
+impl WebSocket {
+    // Answer the HTTP upgrade request a client opens with
+    pub fn accept(stream: TcpStream) -> Result<Self, String> {
+        let mut reader = BufReader::new(stream.try_clone().map_err(|err| err.to_string())?);
+        let head = http::read_head(&mut reader)?;
+        let mut stream = stream;
+        let Some(key) = head.header("sec-websocket-key") else {
+            let _ = stream.write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n");
+            return Err("not a WebSocket request".to_string());
+        };
+        let answer = base64::encode(&sha1(format!("{}{}", key, KEY_GUID).as_bytes()));
+        write!(
+            stream,
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Accept: {}\r\n\r\n",
+            answer
+        )
+        .map_err(|err| err.to_string())?;
+        Ok(Self { reader, stream })
+    }
+
+    // The next text message, or None once the client has closed the connection
+    pub fn read(&mut self) -> Result<Option<String>, String> {
+        let mut message = Vec::new();
+        loop {
+            let mut head = [0; 2];
+            self.read_exact(&mut head)?;
+            let (fin, opcode, masked) = (head[0] & 0x80 != 0, head[0] & 0x0F, head[1] & 0x80 != 0);
+            let len = match head[1] & 0x7F {
+                126 => {
+                    let mut len = [0; 2];
+                    self.read_exact(&mut len)?;
+                    u16::from_be_bytes(len) as u64
+                }
+                127 => {
+                    let mut len = [0; 8];
+                    self.read_exact(&mut len)?;
+                    u64::from_be_bytes(len)
+                }
+                len => len as u64,
+            };
+            if !masked {
+                return Err("unmasked frame from the client".to_string());
+            }
+            if len > (MAX_MESSAGE - message.len()) as u64 {
+                self.close(1009);
+                return Err(format!("message over {} bytes", MAX_MESSAGE));
+            }
+            let mut mask = [0; 4];
+            self.read_exact(&mut mask)?;
+            let mut payload = vec![0; len as usize];
+            self.read_exact(&mut payload)?;
+            for (index, byte) in payload.iter_mut().enumerate() {
+                *byte ^= mask[index % 4];
+            }
+
+            match opcode {
+                // Text, or the continuation of a fragmented one
+                0x1 | 0x0 => {
+                    message.extend_from_slice(&payload);
+                    if fin {
+                        return String::from_utf8(message)
+                            .map(Some)
+                            .map_err(|_| "message is not UTF-8".to_string());
+                    }
+                }
+                0x2 => {
+                    self.close(1003);
+                    return Err("binary messages are not supported".to_string());
+                }
+                0x8 => {
+                    self.close(1000);
+                    return Ok(None);
+                }
+                0x9 => self.frame(0xA, &payload)?, // Ping: pong with its payload
+                0xA => {}                          // Unasked pong
+                _ => return Err(format!("unknown frame type {:#x}", opcode)),
+            }
+        }
+    }
+
+    pub fn send(&mut self, text: &str) -> Result<(), String> {
+        self.frame(0x1, text.as_bytes())
+    }
+
+    // A single unmasked frame, as servers send them
+    fn frame(&mut self, opcode: u8, payload: &[u8]) -> Result<(), String> {
+        let mut frame = vec![0x80 | opcode];
+        match payload.len() {
+            len @ 0..=125 => frame.push(len as u8),
+            len @ 126..=0xFFFF => {
+                frame.push(126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(payload);
+        self.stream.write_all(&frame).map_err(|err| err.to_string())
+    }
+
+    // Tell the client the connection is closing, with a status code (best effort: it may be
+    // gone already)
+    fn close(&mut self, status: u16) {
+        let _ = self.frame(0x8, &status.to_be_bytes());
+    }
+
+    fn read_exact(&mut self, buffer: &mut [u8]) -> Result<(), String> {
+        self.reader
+            .read_exact(buffer)
+            .map_err(|err| err.to_string())
+    }
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    // Padded with a 1 bit, zeros and the length in bits, to a whole number of 64-byte blocks
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = (a.rotate_left(5))
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (state, value) in state.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (bytes, word) in digest.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn sha1_matches_the_fips_vectors() {
+        assert_eq!(hex(&sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(
+            hex(&sha1(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        assert_eq!(
+            hex(&sha1(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+        );
+        assert_eq!(
+            hex(&sha1(&[b'a'; 1_000_000])),
+            "34aa973cd4c4daa4f61eeb2bdbad27316534016f"
+        );
+    }
+
+    // A server socket that has answered the handshake of RFC 6455's example key, and the
+    // client's end with the answer read off it
+    fn connect() -> (WebSocket, TcpStream, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client
+            .write_all(
+                b"GET /remote HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+                  Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                  Sec-WebSocket-Version: 13\r\n\r\n",
+            )
+            .unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let socket = WebSocket::accept(stream).unwrap();
+        let mut answer = Vec::new();
+        while !answer.ends_with(b"\r\n\r\n") {
+            let mut byte = [0];
+            client.read_exact(&mut byte).unwrap();
+            answer.push(byte[0]);
+        }
+        (socket, client, String::from_utf8(answer).unwrap())
+    }
+
+    // A frame as a client sends it, masked
+    fn frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xFA, 0x21, 0x3D];
+        let mut frame = vec![if fin { 0x80 } else { 0 } | opcode];
+        match payload.len() {
+            len @ 0..=125 => frame.push(0x80 | len as u8),
+            len @ 126..=0xFFFF => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(0x80 | 127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(&mask);
+        frame.extend(
+            payload
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| byte ^ mask[i % 4]),
+        );
+        frame
+    }
+
+    fn read_bytes(client: &mut TcpStream, len: usize) -> Vec<u8> {
+        let mut bytes = vec![0; len];
+        client.read_exact(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn handshake_answers_the_rfc_example() {
+        let (_, _, answer) = connect();
+        assert!(answer.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(answer.contains("\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+    }
+
+    #[test]
+    fn handshake_without_a_key_is_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        assert!(WebSocket::accept(stream).is_err());
+        let mut answer = String::new();
+        client.read_to_string(&mut answer).unwrap();
+        assert!(answer.starts_with("HTTP/1.1 400 "));
+    }
+
+    #[test]
+    fn masked_messages_are_read() {
+        let (mut socket, mut client, _) = connect();
+        let long = "x".repeat(300); // With a 16-bit length
+        client.write_all(&frame(true, 0x1, b"hello")).unwrap();
+        client
+            .write_all(&frame(true, 0x1, long.as_bytes()))
+            .unwrap();
+        assert_eq!(socket.read().unwrap().as_deref(), Some("hello"));
+        assert_eq!(socket.read().unwrap(), Some(long));
+    }
+
+    #[test]
+    fn fragments_are_joined_around_pings() {
+        let (mut socket, mut client, _) = connect();
+        client.write_all(&frame(false, 0x1, b"{\"get\":")).unwrap();
+        client
+            .write_all(&frame(true, 0x9, b"are you there"))
+            .unwrap();
+        client
+            .write_all(&frame(true, 0x0, b"\"ball.radius\"}"))
+            .unwrap();
+        assert_eq!(
+            socket.read().unwrap().as_deref(),
+            Some("{\"get\":\"ball.radius\"}")
+        );
+        // The ping is answered with a pong of its payload
+        assert_eq!(read_bytes(&mut client, 2), [0x8A, 13]);
+        assert_eq!(read_bytes(&mut client, 13), b"are you there");
+    }
+
+    #[test]
+    fn sent_messages_are_single_unmasked_frames() {
+        let (mut socket, mut client, _) = connect();
+        socket.send("hi").unwrap();
+        assert_eq!(read_bytes(&mut client, 4), [0x81, 2, b'h', b'i']);
+        let long = "y".repeat(70_000); // With a 64-bit length
+        socket.send(&long).unwrap();
+        let head = read_bytes(&mut client, 10);
+        assert_eq!(head[..2], [0x81, 127]);
+        assert_eq!(head[2..], (70_000u64).to_be_bytes());
+        assert_eq!(read_bytes(&mut client, 70_000), long.as_bytes());
+    }
+
+    #[test]
+    fn close_ends_the_messages() {
+        let (mut socket, mut client, _) = connect();
+        client
+            .write_all(&frame(true, 0x8, &1000u16.to_be_bytes()))
+            .unwrap();
+        assert_eq!(socket.read().unwrap(), None);
+        assert_eq!(read_bytes(&mut client, 4), [0x88, 2, 0x03, 0xE8]);
+    }
+
+    #[test]
+    fn unmasked_frames_are_refused() {
+        let (mut socket, mut client, _) = connect();
+        client.write_all(&[0x81, 2, b'h', b'i']).unwrap();
+        assert!(socket.read().is_err());
+    }
+
+    #[test]
+    fn oversized_messages_are_refused_before_their_payload() {
+        let (mut socket, mut client, _) = connect();
+        // Only the head: the claimed length alone has to turn it away
+        let mut head = vec![0x81, 0x80 | 127];
+        head.extend_from_slice(&(MAX_MESSAGE as u64 + 1).to_be_bytes());
+        client.write_all(&head).unwrap();
+        assert!(socket.read().is_err());
+        assert_eq!(read_bytes(&mut client, 4), [0x88, 2, 0x03, 0xF1]); // 1009, too big
+
+        // Fragments count towards the limit together
+        // (written from another thread, as they are more than the socket buffers)
+        let (mut socket, mut client, _) = connect();
+        let writer = std::thread::spawn(move || {
+            let half = vec![b'z'; MAX_MESSAGE / 2 + 1];
+            let _ = client.write_all(&frame(false, 0x1, &half));
+            let _ = client.write_all(&frame(true, 0x0, &half));
+        });
+        assert!(socket.read().is_err());
+        drop(socket);
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn binary_and_invalid_messages_are_refused() {
+        let (mut socket, mut client, _) = connect();
+        client.write_all(&frame(true, 0x2, &[1, 2, 3])).unwrap();
+        assert!(socket.read().is_err());
+        assert_eq!(read_bytes(&mut client, 4), [0x88, 2, 0x03, 0xEB]); // 1003, unsupported
+
+        let (mut socket, mut client, _) = connect();
+        client.write_all(&frame(true, 0x1, &[0xC3, 0x28])).unwrap();
+        assert!(socket.read().is_err());
+
+        let (mut socket, mut client, _) = connect();
+        client.write_all(&frame(true, 0x5, b"")).unwrap();
+        assert!(socket.read().is_err());
+    }
+}
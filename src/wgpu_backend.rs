@@ -0,0 +1,563 @@
+// wgpu backend: WgpuRaymarcher drawing into a window surface (Vulkan, DirectX 12, Metal
+// or OpenGL, whichever the platform offers)
+use crate::backend::Backend;
+#[cfg(feature = "offline")]
+use crate::backend::PassImage;
+use crate::config::Config;
+use cgmath::Vector2;
+use metal_raymarcher::adaptive::Adaptive;
+use metal_raymarcher::audio::AudioLevels;
+use metal_raymarcher::clock::ClockPhase;
+use metal_raymarcher::edges::Edges;
+use metal_raymarcher::gizmo::OverlayVertex;
+use metal_raymarcher::quality::{QualityConfig, QualityPreset};
+use metal_raymarcher::shafts::Shafts;
+use metal_raymarcher::toon::Toon;
+use metal_raymarcher::{wgpu_raymarcher, Camera, Scene, WgpuRaymarcher};
+#[cfg(feature = "offline")]
+use std::collections::VecDeque;
+#[cfg(feature = "offline")]
+use std::sync::{Arc, Mutex};
+use winit::dpi::PhysicalSize;
+use winit::window::Window;
+
+pub struct WgpuBackend {
+    surface: Option<(wgpu::Surface, wgpu::SurfaceConfiguration)>, // None when rendering offline
+    adapter: wgpu::Adapter,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    raymarcher: WgpuRaymarcher,
+    low_latency: bool, // Each frame finished before the next (see PresentConfig)
+    #[cfg(feature = "offline")]
+    readbacks: Readbacks,
+}
+
+// Frames read back without waiting for the GPU (see Backend::request_readback): each is copied
+// into a buffer that is mapped once the GPU is done with it
+#[cfg(feature = "offline")]
+#[derive(Default)]
+struct Readbacks {
+    ticket: u64, // The last one handed out
+    pending: VecDeque<Readback>,
+    spare: Vec<wgpu::Buffer>, // Buffers of finished readbacks, for the next ones
+}
+
+#[cfg(feature = "offline")]
+struct Readback {
+    ticket: u64,
+    size: PhysicalSize<u32>,
+    padded_row_bytes: u32,
+    buffer: wgpu::Buffer,
+    mapped: Arc<Mutex<Option<Result<(), wgpu::BufferAsyncError>>>>, // Set by map_async
+}
+
+// Readbacks in flight at most; one more waits for the oldest
+#[cfg(feature = "offline")]
+const READBACKS: usize = 3;
+
+impl WgpuBackend {
+    pub fn new(window: Option<&Window>, config: &Config, scene: &Scene) -> Result<Self, String> {
+        if config.shader.is_some() {
+            eprintln!("Custom shaders are Metal only; the wgpu backend uses its built-in shader");
+        }
+        if config.video.source.is_some() || config.screen.target.is_some() {
+            eprintln!("Video input and screen capture are not supported by the wgpu backend");
+        }
+
+        let instance = wgpu::Instance::default();
+        let surface = match window {
+            // The window outlives the backend: both live until the event loop exits
+            Some(window) => Some(
+                unsafe { instance.create_surface(window) }
+                    .map_err(|err| format!("Failed to create surface: {}", err))?,
+            ),
+            None => None,
+        };
+        let adapter = select_adapter(&instance, config.gpu.as_deref(), surface.as_ref())?;
+        let info = adapter.get_info();
+        println!("Using GPU: {} ({:?})", info.name, info.backend);
+
+        let (device, queue) = pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                // For the pass times, where the GPU has them
+                features: adapter.features() & wgpu::Features::TIMESTAMP_QUERY,
+                limits: wgpu::Limits::downlevel_defaults().using_resolution(adapter.limits()),
+            },
+            None,
+        ))
+        .map_err(|err| format!("Failed to open GPU: {}", err))?;
+
+        // Match the Metal path: plain (non-sRGB) 8-bit output
+        let surface = surface.map(|surface| {
+            let capabilities = surface.get_capabilities(&adapter);
+            let format = capabilities
+                .formats
+                .iter()
+                .copied()
+                .find(|format| !format.is_srgb())
+                .unwrap_or(capabilities.formats[0]);
+            let configuration = wgpu::SurfaceConfiguration {
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                format,
+                width: 1,
+                height: 1,
+                present_mode: wgpu::PresentMode::Fifo,
+                alpha_mode: capabilities.alpha_modes[0],
+                view_formats: vec![],
+            };
+            (surface, configuration)
+        });
+        let format = match &surface {
+            Some((_, configuration)) => configuration.format,
+            None => wgpu::TextureFormat::Rgba8Unorm,
+        };
+        let raymarcher = WgpuRaymarcher::new(&device, &queue, scene, format)?;
+
+        Ok(Self {
+            surface,
+            adapter,
+            device,
+            queue,
+            raymarcher,
+            low_latency: config.present.low_latency,
+            #[cfg(feature = "offline")]
+            readbacks: Readbacks::default(),
+        })
+    }
+}
+
+impl Backend for WgpuBackend {
+    fn default_preset(&self) -> QualityPreset {
+        let device_type = self.adapter.get_info().device_type;
+        QualityPreset::for_gpu(matches!(
+            device_type,
+            wgpu::DeviceType::IntegratedGpu | wgpu::DeviceType::Cpu
+        ))
+    }
+
+    fn set_quality(&mut self, quality: QualityConfig) {
+        self.raymarcher.set_quality(quality);
+    }
+
+    fn set_mouse(&mut self, mouse: Vector2<f32>) {
+        self.raymarcher.set_mouse(mouse);
+    }
+
+    fn set_audio(&mut self, audio: AudioLevels) {
+        self.raymarcher.set_audio(audio);
+    }
+
+    fn set_clock(&mut self, clock: ClockPhase) {
+        self.raymarcher.set_clock(clock);
+    }
+
+    fn set_selected(&mut self, selected: Option<usize>) {
+        self.raymarcher.set_selected(selected);
+    }
+
+    fn set_guides(&mut self, guides: bool) {
+        self.raymarcher.set_guides(guides);
+    }
+
+    fn set_toon(&mut self, toon: Option<Toon>) {
+        self.raymarcher.set_toon(toon);
+    }
+
+    fn set_edges(&mut self, edges: Edges) -> Result<(), String> {
+        self.raymarcher.set_edges(&self.device, edges)
+    }
+
+    fn set_shafts(&mut self, shafts: Option<Shafts>) -> Result<(), String> {
+        self.raymarcher.set_shafts(&self.device, shafts)
+    }
+
+    fn set_adaptive(&mut self, adaptive: Option<Adaptive>) -> Result<(), String> {
+        self.raymarcher.set_adaptive(&self.device, adaptive)
+    }
+
+    fn set_culling(&mut self, on: bool) -> Result<(), String> {
+        if on {
+            return Err("Culling is Metal only".to_string());
+        }
+        Ok(())
+    }
+
+    fn set_overlay(&mut self, vertices: &[OverlayVertex]) {
+        self.raymarcher.set_overlay(vertices);
+    }
+
+    fn set_scene(&mut self, scene: &Scene) {
+        self.raymarcher.set_scene(scene);
+    }
+
+    fn rebuild(&mut self, scene: &Scene) -> Result<(), String> {
+        self.raymarcher.rebuild(&self.device, &self.queue, scene)
+    }
+
+    fn set_shader(&mut self, _template: String, _scene: &Scene) -> Result<(), String> {
+        Err("Custom shaders are Metal only".to_string())
+    }
+
+    fn shader_template(&self) -> Option<String> {
+        None
+    }
+
+    fn begin_crossfade(&mut self, scene: &Scene) -> Result<(), String> {
+        self.raymarcher
+            .begin_crossfade(&self.device, &self.queue, scene)
+    }
+
+    fn set_crossfade(&mut self, scene: &Scene, camera: &Camera, opacity: f32) {
+        self.raymarcher.set_crossfade(scene, camera, opacity);
+    }
+
+    fn end_crossfade(&mut self, keep: bool) {
+        self.raymarcher.end_crossfade(keep);
+    }
+
+    fn set_path_tracing(&mut self, on: bool) -> Result<(), String> {
+        self.raymarcher.set_path_tracing(&self.device, on)
+    }
+
+    fn samples(&self) -> Option<u32> {
+        self.raymarcher.samples()
+    }
+
+    fn set_denoise(&mut self, denoise: bool) {
+        self.raymarcher.set_denoise(denoise);
+    }
+
+    fn set_seed(&mut self, seed: u32, scene: &Scene) {
+        self.raymarcher
+            .set_seed(&self.device, &self.queue, seed, scene);
+    }
+
+    fn describe_device(&self) -> String {
+        let info = self.adapter.get_info();
+        format!(
+            "wgpu {:?}: {} ({:?}, vendor {:#x}, device {:#x}), driver: {} {}",
+            info.backend,
+            info.name,
+            info.device_type,
+            info.vendor,
+            info.device,
+            info.driver,
+            info.driver_info
+        )
+    }
+
+    fn describe_uniforms(&self) -> Option<String> {
+        self.raymarcher.describe_uniforms()
+    }
+
+    fn pass_times(&self) -> Vec<(&'static str, f64)> {
+        self.raymarcher.pass_times().to_vec()
+    }
+
+    fn test_shader(&mut self, scene: &Scene, probes: &[[f32; 4]]) -> Result<Vec<[f32; 4]>, String> {
+        wgpu_raymarcher::run_shader_test(&self.device, &self.queue, scene, probes)
+    }
+
+    fn resize(&mut self, drawable_size: PhysicalSize<u32>) {
+        let Some((surface, configuration)) = self.surface.as_mut() else {
+            return;
+        };
+        configuration.width = drawable_size.width.max(1);
+        configuration.height = drawable_size.height.max(1);
+        surface.configure(&self.device, configuration);
+    }
+
+    fn render(&mut self, camera: &Camera, time: f32) -> bool {
+        let Some((surface, configuration)) = self.surface.as_ref() else {
+            return false;
+        };
+        let frame = match surface.get_current_texture() {
+            Ok(frame) => frame,
+            // Lost or outdated swapchain (e.g. mid-resize): reconfigure and skip this frame
+            Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                surface.configure(&self.device, configuration);
+                return false;
+            }
+            // No frame came in time (e.g. the window is hidden)
+            Err(wgpu::SurfaceError::Timeout) => return false,
+            Err(err) => {
+                eprintln!("Failed to acquire frame: {}", err);
+                return false;
+            }
+        };
+        self.raymarcher
+            .render_into(&self.device, &self.queue, &frame.texture, camera, time);
+        frame.present();
+        // Delivers the pass times once the GPU is done with them; in low-latency mode waits
+        // for that, so frames don't queue up
+        self.device.poll(if self.low_latency {
+            wgpu::Maintain::Wait
+        } else {
+            wgpu::Maintain::Poll
+        });
+        true
+    }
+
+    #[cfg(feature = "offline")]
+    fn render_to_rgba(&mut self, size: PhysicalSize<u32>, camera: &Camera, time: f32) -> Vec<u8> {
+        let target = self.render_offscreen(size, camera, time);
+        let mut pixels = self.read_back(&target, 4);
+        self.to_rgba(&mut pixels);
+        pixels
+    }
+
+    #[cfg(feature = "offline")]
+    fn request_readback(&mut self, size: PhysicalSize<u32>, camera: &Camera, time: f32) -> u64 {
+        if let Some(oldest) = (self.readbacks.pending.len() >= READBACKS)
+            .then(|| self.readbacks.pending.front())
+            .flatten()
+        {
+            // Asked for faster than the GPU draws them
+            while oldest.mapped.lock().unwrap().is_none() {
+                self.device.poll(wgpu::Maintain::Wait);
+            }
+        }
+        let target = self.render_offscreen(size, camera, time);
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_row_bytes = (size.width * 4).div_ceil(align) * align;
+        let length = (padded_row_bytes * size.height) as u64;
+        let spare = (self.readbacks.spare.iter()).position(|buffer| buffer.size() == length);
+        let buffer = match spare {
+            Some(index) => self.readbacks.spare.swap_remove(index),
+            None => self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("readback"),
+                size: length,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            }),
+        };
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("readback"),
+            });
+        encoder.copy_texture_to_buffer(
+            target.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_bytes),
+                    rows_per_image: Some(size.height),
+                },
+            },
+            target.size(),
+        );
+        self.queue.submit(Some(encoder.finish()));
+
+        let mapped = Arc::new(Mutex::new(None));
+        let result = mapped.clone();
+        (buffer.slice(..)).map_async(wgpu::MapMode::Read, move |mapped| {
+            *result.lock().unwrap() = Some(mapped);
+        });
+        self.readbacks.ticket += 1;
+        let ticket = self.readbacks.ticket;
+        self.readbacks.pending.push_back(Readback {
+            ticket,
+            size,
+            padded_row_bytes,
+            buffer,
+            mapped,
+        });
+        ticket
+    }
+
+    #[cfg(feature = "offline")]
+    fn readbacks(&mut self) -> Vec<(u64, Result<Vec<u8>, String>)> {
+        self.device.poll(wgpu::Maintain::Poll);
+        let mut done = Vec::new();
+        while let Some(readback) = self.readbacks.pending.front() {
+            let Some(mapped) = readback.mapped.lock().unwrap().take() else {
+                break;
+            };
+            let readback = self.readbacks.pending.pop_front().unwrap();
+            if let Err(err) = mapped {
+                done.push((
+                    readback.ticket,
+                    Err(format!("Failed to read back a frame: {}", err)),
+                ));
+                continue;
+            }
+            let row_bytes = (readback.size.width * 4) as usize;
+            let mut pixels = Vec::with_capacity(row_bytes * readback.size.height as usize);
+            {
+                let mapped = readback.buffer.slice(..).get_mapped_range();
+                for row in mapped.chunks_exact(readback.padded_row_bytes as usize) {
+                    pixels.extend_from_slice(&row[..row_bytes]);
+                }
+            }
+            readback.buffer.unmap();
+            self.to_rgba(&mut pixels);
+            if self.readbacks.spare.len() < READBACKS {
+                self.readbacks.spare.push(readback.buffer);
+            }
+            done.push((readback.ticket, Ok(pixels)));
+        }
+        done
+    }
+
+    #[cfg(feature = "offline")]
+    fn read_passes(&mut self) -> Vec<PassImage> {
+        (self.raymarcher.passes().into_iter())
+            .map(|(name, texture)| PassImage {
+                name,
+                size: PhysicalSize::new(texture.width(), texture.height()),
+                pixels: (self.read_back(texture, 16).chunks_exact(4))
+                    .map(|bytes| f32::from_ne_bytes(bytes.try_into().unwrap()))
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+impl WgpuBackend {
+    // Draw a frame into a new texture that can be copied from
+    #[cfg(feature = "offline")]
+    fn render_offscreen(
+        &mut self,
+        size: PhysicalSize<u32>,
+        camera: &Camera,
+        time: f32,
+    ) -> wgpu::Texture {
+        let target = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("offline target"),
+            size: wgpu::Extent3d {
+                width: size.width,
+                height: size.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.raymarcher.format(),
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        let view = target.create_view(&wgpu::TextureViewDescriptor::default());
+        self.raymarcher
+            .prepare(&self.device, [size.width, size.height]);
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("offline"),
+            });
+        self.raymarcher.encode(
+            &self.queue,
+            &mut encoder,
+            &view,
+            [size.width, size.height],
+            camera,
+            time,
+        );
+        self.queue.submit(Some(encoder.finish()));
+        self.raymarcher.submitted();
+        target
+    }
+
+    // Pixels of the output format read back, as RGBA8
+    #[cfg(feature = "offline")]
+    fn to_rgba(&self, pixels: &mut [u8]) {
+        if matches!(self.raymarcher.format(), wgpu::TextureFormat::Bgra8Unorm) {
+            // BGRA -> RGBA
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+    }
+
+    // Copy a texture of `pixel_bytes` per pixel back to the CPU, waiting for the GPU
+    #[cfg(feature = "offline")]
+    fn read_back(&self, texture: &wgpu::Texture, pixel_bytes: u32) -> Vec<u8> {
+        // Buffer rows must be padded to COPY_BYTES_PER_ROW_ALIGNMENT
+        let row_bytes = texture.width() * pixel_bytes;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_row_bytes = row_bytes.div_ceil(align) * align;
+        let readback = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("readback"),
+            size: (padded_row_bytes * texture.height()) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("readback"),
+            });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &readback,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_bytes),
+                    rows_per_image: Some(texture.height()),
+                },
+            },
+            texture.size(),
+        );
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = readback.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |_| {});
+        self.device.poll(wgpu::Maintain::Wait);
+
+        let mut pixels = Vec::with_capacity((row_bytes * texture.height()) as usize);
+        {
+            let mapped = slice.get_mapped_range();
+            for row in mapped.chunks_exact(padded_row_bytes as usize) {
+                pixels.extend_from_slice(&row[..row_bytes as usize]);
+            }
+        }
+        readback.unmap();
+        pixels
+    }
+}
+
+// Pick an adapter by index or (case-insensitive) part of its name, defaulting to wgpu's choice
+fn select_adapter(
+    instance: &wgpu::Instance,
+    gpu: Option<&str>,
+    surface: Option<&wgpu::Surface>,
+) -> Result<wgpu::Adapter, String> {
+    let Some(wanted) = gpu else {
+        return pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            force_fallback_adapter: false,
+            compatible_surface: surface,
+        }))
+        .ok_or_else(|| "No compatible GPU found".to_string());
+    };
+
+    let adapters: Vec<wgpu::Adapter> = instance
+        .enumerate_adapters(wgpu::Backends::all())
+        .filter(|adapter| surface.is_none_or(|surface| adapter.is_surface_supported(surface)))
+        .collect();
+    let index = match wanted.parse::<usize>() {
+        Ok(index) => (index < adapters.len()).then_some(index),
+        Err(_) => adapters.iter().position(|adapter| {
+            adapter
+                .get_info()
+                .name
+                .to_lowercase()
+                .contains(&wanted.to_lowercase())
+        }),
+    };
+    match index {
+        Some(index) => Ok(adapters.into_iter().nth(index).unwrap()),
+        None => {
+            let mut message = format!("No GPU matching \"{}\". Available:", wanted);
+            for (index, adapter) in adapters.iter().enumerate() {
+                let info = adapter.get_info();
+                message += &format!("\n  {}: {} ({:?})", index, info.name, info.backend);
+            }
+            Err(message)
+        }
+    }
+}
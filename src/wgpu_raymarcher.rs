@@ -0,0 +1,1811 @@
+// The wgpu renderer: the same raymarching pass as `Raymarcher`, drawn through wgpu so scenes
+// also run on Vulkan, DirectX 12 and OpenGL. Video and screen textures are Metal-only.
+//
+// With path tracing on, each frame traces one more path per pixel into a float texture holding
+// the average so far (two of them, read from one and written to the other in turn) and draws
+// that average, through an edge-aware blur while denoising. It starts over whenever anything
+// but time, audio and the clock changes, and stops adding paths once MAX_SAMPLES are in.
+//
+// With edges on, each frame first renders the normal and distance of every pixel's first hit
+// into another float texture (the G-buffer), and draws lines found in it over the image. With
+// light shafts on, it marches the light scattered in the fog into one more (at half resolution
+// by default) and adds that over the image. With adaptive quality on, it first marks the tiles
+// of the image that need supersampling in a small texture of their own, which the image reads.
+//
+// Where the device has timestamp queries, the time the GPU spends on each of those passes is
+// read back a frame or so later (see pass_times).
+use crate::adaptive::Adaptive;
+use crate::audio::AudioLevels;
+use crate::camera::Camera;
+use crate::clock::ClockPhase;
+use crate::codegen::{self, Dialect, ParamLayout, SCENE_MARKER};
+use crate::edges::{EdgeMode, Edges};
+use crate::gizmo::{OverlayVertex, MAX_OVERLAY_VERTICES};
+use crate::noise::{self, NoiseTable, NOISE_MARKER};
+use crate::normal_map::NormalMap;
+use crate::quality::QualityConfig;
+use crate::scene::Scene;
+use crate::shader_test;
+use crate::shafts::Shafts;
+use crate::toon::Toon;
+use crate::uniforms::{FrameInputs, SceneInputs, Uniforms};
+use crate::volume::VolumeData;
+use cgmath::Vector2;
+use std::sync::{Arc, Mutex};
+
+// Built-in WGSL shader template ("// @scene@" is replaced by the scene code, "// @noise@" by
+// the noise functions)
+pub const BUILTIN_SHADER: &str = include_str!("shaders.wgsl");
+const OVERLAY_SHADER: &str = include_str!("overlay.wgsl");
+
+// Paths per pixel after which a path-traced image is left as it is
+pub const MAX_SAMPLES: u32 = 4096;
+// Of the path tracer's average, the G-buffer, the light shafts and the tiles
+const ACCUMULATION_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+// Tiles, paths, G-buffer, light shafts and the image
+const MAX_TIMED_PASSES: usize = 5;
+
+pub struct WgpuRaymarcher {
+    module: wgpu::ShaderModule, // Of the current scene
+    pipeline: wgpu::RenderPipeline,
+    pipeline_layout: wgpu::PipelineLayout,
+    path_layout: wgpu::PipelineLayout, // The uniforms and the average so far (or G-buffer)
+    accumulation_layout: wgpu::BindGroupLayout,
+    path_tracer: Option<PathTracer>,
+    edge_pass: Option<EdgePass>,
+    shaft_pass: Option<ShaftPass>,
+    tile_pass: Option<TilePass>,
+    bind_group_layout: wgpu::BindGroupLayout, // The uniforms and the scene's textures
+    normal_sampler: wgpu::Sampler,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    crossfade_uniforms: (wgpu::Buffer, wgpu::BindGroup), // For the scene fading in
+    crossfade: Option<Crossfade>,
+    overlay_pipeline: wgpu::RenderPipeline,
+    overlay_buffer: wgpu::Buffer,
+    overlay: Vec<OverlayVertex>, // Drawn over the image (gizmos)
+    format: wgpu::TextureFormat,
+    quality: QualityConfig,
+    mouse: Vector2<f32>, // Normalized, passed through to the shader
+    audio: AudioLevels,
+    clock: ClockPhase,
+    selected: Option<usize>,
+    guides: bool,
+    toon: Option<Toon>, // Over the scene's own
+    denoise: bool,      // Path-traced images are drawn through denoise_main
+    seed: u32,
+    edges: Edges,
+    shafts: Option<Shafts>,
+    adaptive: Option<Adaptive>,
+    scene: SceneInputs,
+    last_uniforms: Option<Uniforms>, // Of the last frame drawn, for reports
+    timer: PassTimer,
+}
+
+// A second scene drawn over the first with some opacity, with its own camera
+struct Crossfade {
+    module: wgpu::ShaderModule,
+    pipeline: wgpu::RenderPipeline,
+    // When edges, shafts and adaptive quality are on, for when it replaces the current scene
+    edges: Option<EdgePipelines>,
+    shafts: Option<ShaftPipelines>,
+    tiles: Option<TilePipelines>,
+    scene: SceneInputs,
+    camera: Camera,
+    opacity: f32,
+}
+
+struct PathTracer {
+    trace: wgpu::RenderPipeline, // pathtrace_main, into an accumulation texture
+    resolve: wgpu::RenderPipeline, // resolve_main, the average into the target
+    denoise: wgpu::RenderPipeline, // denoise_main, the average blurred into the target
+    accumulation: Option<Accumulation>, // Made by prepare_path_tracing
+    samples: u32,
+    key: Vec<u8>, // Uniforms the average was traced with (see Uniforms::still_key)
+}
+
+// Two textures of `size`, and a bind group reading each
+struct Accumulation {
+    size: [u32; 2],
+    textures: [wgpu::Texture; 2],
+    views: [wgpu::TextureView; 2],
+    bind_groups: [wgpu::BindGroup; 2],
+}
+
+struct EdgePass {
+    pipelines: EdgePipelines,
+    gbuffer: Option<FloatTarget>, // Made by prepare
+}
+
+struct EdgePipelines {
+    gbuffer: wgpu::RenderPipeline, // gbuffer_main, into the G-buffer
+    edges: wgpu::RenderPipeline,   // edges_main, blended over the target
+}
+
+struct ShaftPass {
+    pipelines: ShaftPipelines,
+    light: Option<FloatTarget>, // Made by prepare
+}
+
+struct ShaftPipelines {
+    trace: wgpu::RenderPipeline,     // shafts_main, into the light texture
+    composite: wgpu::RenderPipeline, // shafts_composite_main, added over the target
+}
+
+struct TilePass {
+    pipelines: TilePipelines,
+    tiles: Option<FloatTarget>, // Made by prepare
+}
+
+struct TilePipelines {
+    classify: wgpu::RenderPipeline, // tiles_main, into the tile texture
+    image: wgpu::RenderPipeline,    // adaptive_main, in place of fragment_main
+}
+
+// Timestamps written around a frame's passes and read back once the GPU is done with them, one
+// frame at a time (the ones encoded meanwhile aren't timed)
+struct PassTimer {
+    // Where the device has Features::TIMESTAMP_QUERY: the queries (two per pass), and the
+    // buffers they are resolved into and read back from
+    gpu: Option<(wgpu::QuerySet, wgpu::Buffer, wgpu::Buffer)>,
+    period: f64,               // Nanoseconds per timestamp tick
+    passes: Vec<&'static str>, // Of the frame being timed
+    state: TimerState,
+    times: Vec<(&'static str, f64)>, // Of the last frame timed, in seconds
+}
+
+enum TimerState {
+    Idle,
+    Encoding,                          // Timestamps go into the frame being encoded
+    Encoded, // Waiting for the frame to be submitted before the readback can be mapped
+    Mapping(Arc<Mutex<Option<bool>>>), // Whether mapping it worked, once it is done
+}
+
+impl PassTimer {
+    fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let supported = device.features().contains(wgpu::Features::TIMESTAMP_QUERY);
+        let size = (MAX_TIMED_PASSES * 2 * std::mem::size_of::<u64>()) as u64;
+        let buffer = |label, usage| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+        Self {
+            gpu: supported.then(|| {
+                (
+                    device.create_query_set(&wgpu::QuerySetDescriptor {
+                        label: Some("pass times"),
+                        ty: wgpu::QueryType::Timestamp,
+                        count: (MAX_TIMED_PASSES * 2) as u32,
+                    }),
+                    buffer(
+                        "pass times",
+                        wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+                    ),
+                    buffer(
+                        "pass times readback",
+                        wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                    ),
+                )
+            }),
+            period: queue.get_timestamp_period() as f64,
+            passes: Vec::new(),
+            state: TimerState::Idle,
+            times: Vec::new(),
+        }
+    }
+
+    // Start a frame: take the times of the one being timed if they are in, and time this one
+    // if none is
+    fn begin(&mut self) {
+        let Some((_, _, readback)) = &self.gpu else {
+            return;
+        };
+        if let TimerState::Mapping(mapped) = &self.state {
+            let mapped = *mapped.lock().unwrap();
+            match mapped {
+                None => return,
+                Some(true) => {
+                    let slice = readback.slice(..(self.passes.len() * 16) as u64);
+                    let stamps: Vec<u64> = (slice.get_mapped_range().chunks_exact(8))
+                        .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+                        .collect();
+                    self.times = (self.passes.iter().zip(stamps.chunks_exact(2)))
+                        .map(|(&name, stamps)| {
+                            let ticks = stamps[1].saturating_sub(stamps[0]);
+                            (name, ticks as f64 * self.period * 1e-9)
+                        })
+                        .collect();
+                    readback.unmap();
+                }
+                Some(false) => {}
+            }
+            self.state = TimerState::Idle;
+        }
+        if matches!(self.state, TimerState::Idle) {
+            self.passes.clear();
+            self.state = TimerState::Encoding;
+        }
+    }
+
+    // Before and after encoding pass `name`
+    fn start(&mut self, encoder: &mut wgpu::CommandEncoder, name: &'static str) {
+        let (Some((queries, _, _)), TimerState::Encoding) = (&self.gpu, &self.state) else {
+            return;
+        };
+        if self.passes.len() < MAX_TIMED_PASSES {
+            encoder.write_timestamp(queries, self.passes.len() as u32 * 2);
+            self.passes.push(name);
+        }
+    }
+
+    fn end(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let (Some((queries, _, _)), TimerState::Encoding) = (&self.gpu, &self.state) else {
+            return;
+        };
+        if !self.passes.is_empty() {
+            encoder.write_timestamp(queries, self.passes.len() as u32 * 2 - 1);
+        }
+    }
+
+    // After the frame's last pass: copy the timestamps out where they can be read
+    fn finish(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let (Some((queries, resolved, readback)), TimerState::Encoding) = (&self.gpu, &self.state)
+        else {
+            return;
+        };
+        if self.passes.is_empty() {
+            self.state = TimerState::Idle;
+            return;
+        }
+        let count = self.passes.len() as u32 * 2;
+        encoder.resolve_query_set(queries, 0..count, resolved, 0);
+        encoder.copy_buffer_to_buffer(resolved, 0, readback, 0, count as u64 * 8);
+        self.state = TimerState::Encoded;
+    }
+
+    // Once the frame is submitted: map the timestamps for `begin` to read
+    fn submitted(&mut self) {
+        let (Some((_, _, readback)), TimerState::Encoded) = (&self.gpu, &self.state) else {
+            return;
+        };
+        let mapped = Arc::new(Mutex::new(None));
+        let done = mapped.clone();
+        (readback.slice(..(self.passes.len() * 16) as u64)).map_async(
+            wgpu::MapMode::Read,
+            move |result| {
+                *done.lock().unwrap() = Some(result.is_ok());
+            },
+        );
+        self.state = TimerState::Mapping(mapped);
+    }
+}
+
+// A float texture of `size` and a bind group reading it
+struct FloatTarget {
+    size: [u32; 2],
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    bind_group: wgpu::BindGroup,
+}
+
+// WGSL shader with the noise functions and the generated scene code spliced in
+pub fn shader_source(scene: &Scene) -> String {
+    let layout = ParamLayout::of(scene);
+    BUILTIN_SHADER
+        .replace(NOISE_MARKER, noise::WGSL_SOURCE)
+        .replace(
+            SCENE_MARKER,
+            &codegen::scene_source_with(scene, Dialect::Wgsl, &layout),
+        )
+}
+
+// Run the shader test kernel (see shader_test.rs) for `scene` at `probes` and wait for its
+// results
+pub fn run_shader_test(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    scene: &Scene,
+    probes: &[[f32; 4]],
+) -> Result<Vec<[f32; 4]>, String> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("shader test"),
+        source: wgpu::ShaderSource::Wgsl((shader_source(scene) + shader_test::WGSL_KERNEL).into()),
+    });
+    // Spelled out rather than derived from the shader, which leaves out the uniforms in
+    // scenes that don't read them
+    let entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty,
+        count: None,
+    };
+    let buffer = |ty| wgpu::BindingType::Buffer {
+        ty,
+        has_dynamic_offset: false,
+        min_binding_size: None,
+    };
+    let scene_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("shader test uniforms"),
+        entries: &[
+            entry(0, buffer(wgpu::BufferBindingType::Uniform)),
+            entry(
+                3,
+                wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+            ),
+        ],
+    });
+    let probe_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("shader test buffers"),
+        entries: &[
+            entry(
+                0,
+                buffer(wgpu::BufferBindingType::Storage { read_only: true }),
+            ),
+            entry(
+                1,
+                buffer(wgpu::BufferBindingType::Storage { read_only: false }),
+            ),
+        ],
+    });
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("shader test"),
+        bind_group_layouts: &[&scene_layout, &probe_layout],
+        push_constant_ranges: &[],
+    });
+    let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("shader test"),
+        layout: Some(&layout),
+        module: &module,
+        entry_point: "shader_test_main",
+    });
+
+    let uniforms = create_uniform_buffer(device);
+    queue.write_buffer(&uniforms, 0, shader_test::uniforms(scene).as_bytes());
+    let table = NoiseTable::of(scene, 0);
+    let noise = rgba_view(
+        device,
+        queue,
+        "noise",
+        [noise::TABLE_SIZE, 1],
+        &table.pixels,
+    );
+    let probe_bytes: &[u8] = bytemuck::cast_slice(probes);
+    let probe_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("shader test probes"),
+        size: probe_bytes.len() as u64,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    queue.write_buffer(&probe_buffer, 0, probe_bytes);
+    let results_size = (probe_bytes.len() * shader_test::RESULTS_PER_PROBE) as u64;
+    let results = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("shader test results"),
+        size: results_size,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    });
+    let readback = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("shader test readback"),
+        size: results_size,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let scene_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("shader test uniforms"),
+        layout: &scene_layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniforms.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(&noise),
+            },
+        ],
+    });
+    let probe_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("shader test buffers"),
+        layout: &probe_layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: probe_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: results.as_entire_binding(),
+            },
+        ],
+    });
+    if let Some(err) = pollster::block_on(device.pop_error_scope()) {
+        return Err(format!(
+            "Failed to create the shader test pipeline: {}",
+            err
+        ));
+    }
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("shader test"),
+    });
+    {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("shader test"),
+        });
+        pass.set_pipeline(&pipeline);
+        pass.set_bind_group(0, &scene_group, &[]);
+        pass.set_bind_group(1, &probe_group, &[]);
+        pass.dispatch_workgroups((probes.len() as u32).div_ceil(64), 1, 1);
+    }
+    encoder.copy_buffer_to_buffer(&results, 0, &readback, 0, results_size);
+    queue.submit(Some(encoder.finish()));
+
+    let slice = readback.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |_| {});
+    device.poll(wgpu::Maintain::Wait);
+    let values = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+    readback.unmap();
+    Ok(values)
+}
+
+impl WgpuRaymarcher {
+    // Renderer for `scene` drawing into textures of `format`
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        scene: &Scene,
+        format: wgpu::TextureFormat,
+    ) -> Result<Self, String> {
+        // Report shader/pipeline validation errors instead of panicking
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("uniforms"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let normal_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("normal map"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let uniform_buffer = create_uniform_buffer(device);
+        let bind_group = create_bind_group(
+            device,
+            queue,
+            &bind_group_layout,
+            &uniform_buffer,
+            scene,
+            0,
+            &normal_sampler,
+        );
+        let crossfade_buffer = create_uniform_buffer(device);
+        let crossfade_group = create_bind_group(
+            device,
+            queue,
+            &bind_group_layout,
+            &crossfade_buffer,
+            scene,
+            0,
+            &normal_sampler,
+        );
+        let accumulation_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("accumulation"),
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                }],
+            });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("raymarch"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let path_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("path trace"),
+            bind_group_layouts: &[&bind_group_layout, &accumulation_layout],
+            push_constant_ranges: &[],
+        });
+        let module = shader_module(device, scene);
+        let pipeline = create_pipeline(device, &layout, &module, format);
+        let overlay_pipeline = create_overlay_pipeline(device, format);
+        let overlay_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("overlay"),
+            size: (MAX_OVERLAY_VERTICES * std::mem::size_of::<OverlayVertex>()) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        if let Some(err) = pollster::block_on(device.pop_error_scope()) {
+            return Err(format!("Failed to create wgpu pipeline: {}", err));
+        }
+
+        Ok(Self {
+            module,
+            pipeline,
+            pipeline_layout: layout,
+            path_layout,
+            accumulation_layout,
+            path_tracer: None,
+            edge_pass: None,
+            shaft_pass: None,
+            tile_pass: None,
+            bind_group_layout,
+            normal_sampler,
+            uniform_buffer,
+            bind_group,
+            crossfade_uniforms: (crossfade_buffer, crossfade_group),
+            crossfade: None,
+            overlay_pipeline,
+            overlay_buffer,
+            overlay: Vec::new(),
+            format,
+            quality: QualityConfig::default(),
+            mouse: Vector2::new(0.5, 0.5),
+            audio: AudioLevels::default(),
+            clock: ClockPhase::default(),
+            selected: None,
+            guides: false,
+            toon: None,
+            denoise: true,
+            seed: 0,
+            last_uniforms: None,
+            edges: Edges::default(),
+            shafts: None,
+            adaptive: None,
+            scene: SceneInputs::of(scene),
+            timer: PassTimer::new(device, queue),
+        })
+    }
+
+    pub fn format(&self) -> wgpu::TextureFormat {
+        self.format
+    }
+
+    // Regenerate the shader after the scene's structure changed (objects added or removed,
+    // other parameters changing at runtime) and reload its textures; keeps the settings
+    pub fn rebuild(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        scene: &Scene,
+    ) -> Result<(), String> {
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let module = shader_module(device, scene);
+        let pipeline = create_pipeline(device, &self.pipeline_layout, &module, self.format);
+        let paths = self
+            .path_tracer
+            .is_some()
+            .then(|| self.path_pipelines(device, &module));
+        let edges = (self.edge_pass.is_some()).then(|| self.edge_pipelines(device, &module));
+        let shafts = (self.shaft_pass.is_some()).then(|| self.shaft_pipelines(device, &module));
+        let tiles = (self.tile_pass.is_some()).then(|| self.tile_pipelines(device, &module));
+        if let Some(err) = pollster::block_on(device.pop_error_scope()) {
+            return Err(format!("Failed to create wgpu pipeline: {}", err));
+        }
+        self.module = module;
+        self.pipeline = pipeline;
+        self.bind_group = create_bind_group(
+            device,
+            queue,
+            &self.bind_group_layout,
+            &self.uniform_buffer,
+            scene,
+            self.seed,
+            &self.normal_sampler,
+        );
+        if let (Some(tracer), Some(paths)) = (self.path_tracer.as_mut(), paths) {
+            (tracer.trace, tracer.resolve, tracer.denoise) = paths;
+            tracer.samples = 0;
+        }
+        if let (Some(pass), Some(edges)) = (self.edge_pass.as_mut(), edges) {
+            pass.pipelines = edges;
+        }
+        if let (Some(pass), Some(shafts)) = (self.shaft_pass.as_mut(), shafts) {
+            pass.pipelines = shafts;
+        }
+        if let (Some(pass), Some(tiles)) = (self.tile_pass.as_mut(), tiles) {
+            pass.pipelines = tiles;
+        }
+        Ok(())
+    }
+
+    // Switch between raymarching and progressive path tracing. Path tracing needs `prepare`
+    // with the target's size before each `encode` (`render_into` does it); without it frames
+    // are raymarched.
+    pub fn set_path_tracing(&mut self, device: &wgpu::Device, on: bool) -> Result<(), String> {
+        if !on {
+            self.path_tracer = None;
+            return Ok(());
+        }
+        if self.path_tracer.is_some() {
+            return Ok(());
+        }
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let (trace, resolve, denoise) = self.path_pipelines(device, &self.module);
+        if let Some(err) = pollster::block_on(device.pop_error_scope()) {
+            return Err(format!(
+                "Failed to create the path tracing pipelines: {}",
+                err
+            ));
+        }
+        self.path_tracer = Some(PathTracer {
+            trace,
+            resolve,
+            denoise,
+            accumulation: None,
+            samples: 0,
+            key: Vec::new(),
+        });
+        Ok(())
+    }
+
+    // Paths per pixel in the path-traced image so far (None when not path tracing)
+    pub fn samples(&self) -> Option<u32> {
+        self.path_tracer.as_ref().map(|tracer| tracer.samples)
+    }
+
+    // Intermediate textures of the last frame (RGBA floats), in the order they were drawn: the
+    // tiles marked for adaptive quality (1 where detailed), the G-buffer (normal and distance of
+    // each pixel's first hit), the light shafts and the path tracer's average
+    pub fn passes(&self) -> Vec<(&'static str, &wgpu::Texture)> {
+        let mut passes = Vec::new();
+        if let Some(tiles) = self.tile_pass.as_ref().and_then(|pass| pass.tiles.as_ref()) {
+            passes.push(("tiles", &tiles.texture));
+        }
+        if let Some(gbuffer) = self
+            .edge_pass
+            .as_ref()
+            .and_then(|pass| pass.gbuffer.as_ref())
+        {
+            passes.push(("gbuffer", &gbuffer.texture));
+        }
+        if let Some(light) = self
+            .shaft_pass
+            .as_ref()
+            .and_then(|pass| pass.light.as_ref())
+        {
+            passes.push(("shafts", &light.texture));
+        }
+        if let Some(tracer) = &self.path_tracer {
+            if let Some(accumulation) = &tracer.accumulation {
+                passes.push(("paths", &accumulation.textures[tracer.samples as usize % 2]));
+            }
+        }
+        passes
+    }
+
+    // Make the path tracer's, edge pass's, light shafts' and tiles' textures for targets of
+    // `size` (the path tracer starts over when it changed)
+    pub fn prepare(&mut self, device: &wgpu::Device, size: [u32; 2]) {
+        self.prepare_path_tracing(device, size);
+        self.prepare_edges(device, size);
+        self.prepare_shafts(device, size);
+        self.prepare_tiles(device, size);
+    }
+
+    fn prepare_path_tracing(&mut self, device: &wgpu::Device, size: [u32; 2]) {
+        let Some(tracer) = self.path_tracer.as_mut() else {
+            return;
+        };
+        if tracer
+            .accumulation
+            .as_ref()
+            .map(|accumulation| accumulation.size)
+            == Some(size)
+        {
+            return;
+        }
+        let textures: [wgpu::Texture; 2] =
+            std::array::from_fn(|_| float_texture(device, "accumulation", size));
+        let views: [wgpu::TextureView; 2] = std::array::from_fn(|i| {
+            textures[i].create_view(&wgpu::TextureViewDescriptor::default())
+        });
+        let bind_groups = std::array::from_fn(|i| {
+            texture_bind_group(device, &self.accumulation_layout, "accumulation", &views[i])
+        });
+        tracer.accumulation = Some(Accumulation {
+            size,
+            textures,
+            views,
+            bind_groups,
+        });
+        tracer.samples = 0;
+    }
+
+    fn path_pipelines(
+        &self,
+        device: &wgpu::Device,
+        module: &wgpu::ShaderModule,
+    ) -> (
+        wgpu::RenderPipeline,
+        wgpu::RenderPipeline,
+        wgpu::RenderPipeline,
+    ) {
+        let pipeline = |entry_point, format| {
+            post_pipeline(device, &self.path_layout, module, entry_point, format, None)
+        };
+        (
+            pipeline("pathtrace_main", ACCUMULATION_FORMAT),
+            pipeline("resolve_main", self.format),
+            pipeline("denoise_main", self.format),
+        )
+    }
+
+    // Edge detection over the image (see edges.rs); needs `prepare` like path tracing
+    pub fn set_edges(&mut self, device: &wgpu::Device, edges: Edges) -> Result<(), String> {
+        self.edges = edges;
+        if edges.mode == EdgeMode::Off {
+            self.edge_pass = None;
+            return Ok(());
+        }
+        if self.edge_pass.is_some() {
+            return Ok(());
+        }
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let pipelines = self.edge_pipelines(device, &self.module);
+        if let Some(err) = pollster::block_on(device.pop_error_scope()) {
+            return Err(format!("Failed to create the edge pipelines: {}", err));
+        }
+        self.edge_pass = Some(EdgePass {
+            pipelines,
+            gbuffer: None,
+        });
+        Ok(())
+    }
+
+    fn prepare_edges(&mut self, device: &wgpu::Device, size: [u32; 2]) {
+        let Some(pass) = self.edge_pass.as_mut() else {
+            return;
+        };
+        if pass.gbuffer.as_ref().map(|gbuffer| gbuffer.size) == Some(size) {
+            return;
+        }
+        pass.gbuffer = Some(float_target(
+            device,
+            &self.accumulation_layout,
+            "G-buffer",
+            size,
+        ));
+    }
+
+    fn edge_pipelines(&self, device: &wgpu::Device, module: &wgpu::ShaderModule) -> EdgePipelines {
+        EdgePipelines {
+            gbuffer: post_pipeline(
+                device,
+                &self.pipeline_layout,
+                module,
+                "gbuffer_main",
+                ACCUMULATION_FORMAT,
+                None,
+            ),
+            edges: post_pipeline(
+                device,
+                &self.path_layout,
+                module,
+                "edges_main",
+                self.format,
+                Some(wgpu::BlendState::ALPHA_BLENDING),
+            ),
+        }
+    }
+
+    // Volumetric light shafts (see shafts.rs); needs `prepare` like path tracing
+    pub fn set_shafts(
+        &mut self,
+        device: &wgpu::Device,
+        shafts: Option<Shafts>,
+    ) -> Result<(), String> {
+        self.shafts = shafts;
+        if shafts.is_none() {
+            self.shaft_pass = None;
+            return Ok(());
+        }
+        if let Some(pass) = self.shaft_pass.as_mut() {
+            pass.light = None; // Its resolution may have changed
+            return Ok(());
+        }
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let pipelines = self.shaft_pipelines(device, &self.module);
+        if let Some(err) = pollster::block_on(device.pop_error_scope()) {
+            return Err(format!(
+                "Failed to create the light shaft pipelines: {}",
+                err
+            ));
+        }
+        self.shaft_pass = Some(ShaftPass {
+            pipelines,
+            light: None,
+        });
+        Ok(())
+    }
+
+    fn prepare_shafts(&mut self, device: &wgpu::Device, size: [u32; 2]) {
+        let (Some(pass), Some(shafts)) = (self.shaft_pass.as_mut(), self.shafts) else {
+            return;
+        };
+        let size = shafts.size(size);
+        if pass.light.as_ref().map(|light| light.size) == Some(size) {
+            return;
+        }
+        pass.light = Some(float_target(
+            device,
+            &self.accumulation_layout,
+            "light shafts",
+            size,
+        ));
+    }
+
+    fn shaft_pipelines(
+        &self,
+        device: &wgpu::Device,
+        module: &wgpu::ShaderModule,
+    ) -> ShaftPipelines {
+        ShaftPipelines {
+            trace: post_pipeline(
+                device,
+                &self.pipeline_layout,
+                module,
+                "shafts_main",
+                ACCUMULATION_FORMAT,
+                None,
+            ),
+            composite: post_pipeline(
+                device,
+                &self.path_layout,
+                module,
+                "shafts_composite_main",
+                self.format,
+                Some(ADDITIVE_BLEND),
+            ),
+        }
+    }
+
+    // Adaptive quality (see adaptive.rs); needs `prepare` like path tracing, and is left out
+    // of path-traced images
+    pub fn set_adaptive(
+        &mut self,
+        device: &wgpu::Device,
+        adaptive: Option<Adaptive>,
+    ) -> Result<(), String> {
+        self.adaptive = adaptive;
+        if adaptive.is_none() {
+            self.tile_pass = None;
+            return Ok(());
+        }
+        if let Some(pass) = self.tile_pass.as_mut() {
+            pass.tiles = None; // Its tile size may have changed
+            return Ok(());
+        }
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let pipelines = self.tile_pipelines(device, &self.module);
+        if let Some(err) = pollster::block_on(device.pop_error_scope()) {
+            return Err(format!(
+                "Failed to create the adaptive quality pipelines: {}",
+                err
+            ));
+        }
+        self.tile_pass = Some(TilePass {
+            pipelines,
+            tiles: None,
+        });
+        Ok(())
+    }
+
+    fn prepare_tiles(&mut self, device: &wgpu::Device, size: [u32; 2]) {
+        let (Some(pass), Some(adaptive)) = (self.tile_pass.as_mut(), self.adaptive) else {
+            return;
+        };
+        let size = adaptive.size(size);
+        if pass.tiles.as_ref().map(|tiles| tiles.size) == Some(size) {
+            return;
+        }
+        pass.tiles = Some(float_target(
+            device,
+            &self.accumulation_layout,
+            "tiles",
+            size,
+        ));
+    }
+
+    fn tile_pipelines(&self, device: &wgpu::Device, module: &wgpu::ShaderModule) -> TilePipelines {
+        TilePipelines {
+            classify: post_pipeline(
+                device,
+                &self.pipeline_layout,
+                module,
+                "tiles_main",
+                ACCUMULATION_FORMAT,
+                None,
+            ),
+            image: post_pipeline(
+                device,
+                &self.path_layout,
+                module,
+                "adaptive_main",
+                self.format,
+                Some(wgpu::BlendState {
+                    color: CONSTANT_BLEND,
+                    alpha: CONSTANT_BLEND,
+                }),
+            ),
+        }
+    }
+
+    // Start cross-fading to another scene; it is drawn over the current one with the opacity
+    // given to `set_crossfade`
+    pub fn begin_crossfade(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        scene: &Scene,
+    ) -> Result<(), String> {
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let module = shader_module(device, scene);
+        let pipeline = create_pipeline(device, &self.pipeline_layout, &module, self.format);
+        let edges = (self.edge_pass.is_some()).then(|| self.edge_pipelines(device, &module));
+        let shafts = (self.shaft_pass.is_some()).then(|| self.shaft_pipelines(device, &module));
+        let tiles = (self.tile_pass.is_some()).then(|| self.tile_pipelines(device, &module));
+        if let Some(err) = pollster::block_on(device.pop_error_scope()) {
+            return Err(format!("Failed to create wgpu pipeline: {}", err));
+        }
+        self.crossfade_uniforms.1 = create_bind_group(
+            device,
+            queue,
+            &self.bind_group_layout,
+            &self.crossfade_uniforms.0,
+            scene,
+            self.seed,
+            &self.normal_sampler,
+        );
+        self.crossfade = Some(Crossfade {
+            module,
+            pipeline,
+            edges,
+            shafts,
+            tiles,
+            scene: SceneInputs::of(scene),
+            camera: Camera::default(),
+            opacity: 0.0,
+        });
+        Ok(())
+    }
+
+    // Parameter values, camera and opacity (0..1) of the scene being faded in
+    pub fn set_crossfade(&mut self, scene: &Scene, camera: &Camera, opacity: f32) {
+        if let Some(crossfade) = self.crossfade.as_mut() {
+            crossfade.scene = SceneInputs::of(scene);
+            crossfade.camera = *camera;
+            crossfade.opacity = opacity.clamp(0.0, 1.0);
+        }
+    }
+
+    // End the cross-fade; with `keep` the scene faded in replaces the current one (a path
+    // tracer keeps the current one's pipelines until the next rebuild). Edges, light shafts and
+    // adaptive quality are for the current scene only: the one fading in is drawn under the
+    // edges and shafts, with the preset's supersampling.
+    pub fn end_crossfade(&mut self, keep: bool) {
+        match self.crossfade.take() {
+            Some(crossfade) if keep => {
+                self.module = crossfade.module;
+                self.pipeline = crossfade.pipeline;
+                self.scene = crossfade.scene;
+                if let (Some(pass), Some(edges)) = (self.edge_pass.as_mut(), crossfade.edges) {
+                    pass.pipelines = edges;
+                }
+                if let (Some(pass), Some(shafts)) = (self.shaft_pass.as_mut(), crossfade.shafts) {
+                    pass.pipelines = shafts;
+                }
+                if let (Some(pass), Some(tiles)) = (self.tile_pass.as_mut(), crossfade.tiles) {
+                    pass.pipelines = tiles;
+                }
+                // Its uniforms come with its normal map
+                std::mem::swap(&mut self.uniform_buffer, &mut self.crossfade_uniforms.0);
+                std::mem::swap(&mut self.bind_group, &mut self.crossfade_uniforms.1);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn set_quality(&mut self, quality: QualityConfig) {
+        self.quality = quality;
+    }
+
+    pub fn set_mouse(&mut self, mouse: Vector2<f32>) {
+        self.mouse = mouse;
+    }
+
+    // Audio levels for this frame (see audio.rs)
+    pub fn set_audio(&mut self, audio: AudioLevels) {
+        self.audio = audio;
+    }
+
+    // Musical clock for this frame (see clock.rs)
+    pub fn set_clock(&mut self, clock: ClockPhase) {
+        self.clock = clock;
+    }
+
+    // Object to highlight, by its index in `Scene::objects()` (see picking.rs)
+    pub fn set_selected(&mut self, selected: Option<usize>) {
+        self.selected = selected;
+    }
+
+    // Draw the ground grid and axes over the scene
+    pub fn set_guides(&mut self, guides: bool) {
+        self.guides = guides;
+    }
+
+    // Toon shading for every scene; None leaves it to each scene (see toon.rs)
+    pub fn set_toon(&mut self, toon: Option<Toon>) {
+        self.toon = toon;
+    }
+
+    // Blur path-traced images of few samples without blurring over edges (on by default)
+    pub fn set_denoise(&mut self, denoise: bool) {
+        self.denoise = denoise;
+    }
+
+    // Offset the path tracer's random numbers and the light shafts' jitter (see
+    // Raymarcher::set_seed), and the noise of `scene` and those after it
+    pub fn set_seed(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        seed: u32,
+        scene: &Scene,
+    ) {
+        self.seed = seed;
+        self.bind_group = create_bind_group(
+            device,
+            queue,
+            &self.bind_group_layout,
+            &self.uniform_buffer,
+            scene,
+            seed,
+            &self.normal_sampler,
+        );
+    }
+
+    // How long the GPU spent on each pass of the latest frame timed (a frame or two behind the
+    // one being drawn), in seconds, named as in `passes` with "image" for the pass drawing
+    // into the target; empty without timestamp queries
+    pub fn pass_times(&self) -> &[(&'static str, f64)] {
+        &self.timer.times
+    }
+
+    // Call after submitting an encoder `encode` wrote into (`render_into` does), for the pass
+    // times to be read back; they come in as the device is polled
+    pub fn submitted(&mut self) {
+        self.timer.submitted();
+    }
+
+    // The uniforms of the last frame drawn, spelled out for a crash or error report
+    pub fn describe_uniforms(&self) -> Option<String> {
+        self.last_uniforms
+            .map(|uniforms| format!("{:#?}", uniforms))
+    }
+
+    // Triangles drawn over the image in normalized device coordinates (see gizmo.rs)
+    pub fn set_overlay(&mut self, vertices: &[OverlayVertex]) {
+        self.overlay.clear();
+        self.overlay
+            .extend_from_slice(&vertices[..vertices.len().min(MAX_OVERLAY_VERTICES)]);
+    }
+
+    // Lighting and animated parameter values of `scene` (the one the renderer was built for,
+    // possibly with different values); call whenever they change
+    pub fn set_scene(&mut self, scene: &Scene) {
+        self.scene = SceneInputs::of(scene);
+    }
+
+    // Render into `target` and submit (does not wait for the GPU)
+    pub fn render_into(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        target: &wgpu::Texture,
+        camera: &Camera,
+        time: f32,
+    ) {
+        let view = target.create_view(&wgpu::TextureViewDescriptor::default());
+        self.prepare(device, [target.width(), target.height()]);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("raymarch"),
+        });
+        self.encode(
+            queue,
+            &mut encoder,
+            &view,
+            [target.width(), target.height()],
+            camera,
+            time,
+        );
+        queue.submit(Some(encoder.finish()));
+        self.submitted();
+    }
+
+    // Encode the raymarching pass (and the passes around it) into `target` (of the given size)
+    // in the caller's encoder.
+    // The uniforms are written through the queue, so use one pass per submission.
+    pub fn encode(
+        &mut self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        size: [u32; 2],
+        camera: &Camera,
+        time: f32,
+    ) {
+        let mut frame = FrameInputs {
+            resolution: size,
+            time,
+            mouse: self.mouse,
+            video_mix: 0.0,
+            screen_mix: 0.0,
+            audio: self.audio,
+            clock: self.clock,
+            selected: self.selected,
+            guides: self.guides,
+            samples: 0,
+            seed: self.seed,
+            toon: self.toon,
+            edges: self.edges,
+            shafts: self.shafts,
+            adaptive: self.adaptive,
+        };
+        // Path tracing (when its textures fit the target) starts over when the view changes
+        let mut tracer = self.path_tracer.as_mut().filter(|tracer| {
+            let accumulation = tracer.accumulation.as_ref();
+            accumulation.is_some_and(|accumulation| accumulation.size == size)
+        });
+        if let Some(tracer) = tracer.as_mut() {
+            let key = Uniforms::new(&frame, camera, &self.quality, &self.scene).still_key();
+            if key != tracer.key {
+                tracer.key = key;
+                tracer.samples = 0;
+            }
+            frame.samples = tracer.samples;
+        }
+        let uniforms = Uniforms::new(&frame, camera, &self.quality, &self.scene);
+        queue.write_buffer(&self.uniform_buffer, 0, uniforms.as_bytes());
+        self.last_uniforms = Some(uniforms);
+        self.timer.begin();
+        // Path traced images don't cross-fade: the next scene just replaces them
+        let crossfade =
+            (self.crossfade.as_ref()).filter(|fade| fade.opacity > 0.0 && tracer.is_none());
+        if let Some(crossfade) = crossfade {
+            let frame = FrameInputs {
+                selected: None,
+                adaptive: None,
+                ..frame
+            };
+            let uniforms =
+                Uniforms::new(&frame, &crossfade.camera, &self.quality, &crossfade.scene);
+            queue.write_buffer(&self.crossfade_uniforms.0, 0, uniforms.as_bytes());
+        }
+        if !self.overlay.is_empty() {
+            // Plain #[repr(C)] floats
+            let bytes = unsafe {
+                std::slice::from_raw_parts(
+                    self.overlay.as_ptr() as *const u8,
+                    std::mem::size_of_val(self.overlay.as_slice()),
+                )
+            };
+            queue.write_buffer(&self.overlay_buffer, 0, bytes);
+        }
+
+        let tiles = (self.tile_pass.as_ref())
+            .zip(self.adaptive)
+            .filter(|_| tracer.is_none())
+            .and_then(|(pass, adaptive)| {
+                let tiles =
+                    (pass.tiles.as_ref()).filter(|tiles| tiles.size == adaptive.size(size))?;
+                Some((&pass.pipelines, tiles))
+            });
+        if let Some((pipelines, tiles)) = tiles {
+            self.timer.start(encoder, "tiles");
+            encode_into(
+                encoder,
+                "tiles",
+                tiles,
+                &pipelines.classify,
+                &self.bind_group,
+            );
+            self.timer.end(encoder);
+        }
+
+        if let Some(tracer) = tracer
+            .as_mut()
+            .filter(|tracer| tracer.samples < MAX_SAMPLES)
+        {
+            if let Some(accumulation) = &tracer.accumulation {
+                self.timer.start(encoder, "paths");
+                let read = tracer.samples as usize % 2;
+                let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("path trace"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &accumulation.views[1 - read],
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                });
+                pass.set_pipeline(&tracer.trace);
+                pass.set_bind_group(0, &self.bind_group, &[]);
+                pass.set_bind_group(1, &accumulation.bind_groups[read], &[]);
+                pass.draw(0..6, 0..1);
+                drop(pass);
+                self.timer.end(encoder);
+            }
+            tracer.samples += 1;
+        }
+
+        let edges = (self.edge_pass.as_ref()).and_then(|pass| {
+            let gbuffer = pass
+                .gbuffer
+                .as_ref()
+                .filter(|gbuffer| gbuffer.size == size)?;
+            Some((&pass.pipelines, gbuffer))
+        });
+        if let Some((pipelines, gbuffer)) = edges {
+            self.timer.start(encoder, "gbuffer");
+            encode_into(
+                encoder,
+                "G-buffer",
+                gbuffer,
+                &pipelines.gbuffer,
+                &self.bind_group,
+            );
+            self.timer.end(encoder);
+        }
+        let shafts = (self.shaft_pass.as_ref())
+            .zip(self.shafts)
+            .and_then(|(pass, shafts)| {
+                let light =
+                    (pass.light.as_ref()).filter(|light| light.size == shafts.size(size))?;
+                Some((&pass.pipelines, light))
+            });
+        if let Some((pipelines, light)) = shafts {
+            self.timer.start(encoder, "shafts");
+            encode_into(
+                encoder,
+                "light shafts",
+                light,
+                &pipelines.trace,
+                &self.bind_group,
+            );
+            self.timer.end(encoder);
+        }
+
+        self.timer.start(encoder, "image");
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("raymarch"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        if let Some(tracer) = tracer {
+            if let Some(accumulation) = &tracer.accumulation {
+                if self.denoise {
+                    pass.set_pipeline(&tracer.denoise);
+                } else {
+                    pass.set_pipeline(&tracer.resolve);
+                }
+                pass.set_bind_group(0, &self.bind_group, &[]);
+                let latest = tracer.samples as usize % 2;
+                pass.set_bind_group(1, &accumulation.bind_groups[latest], &[]);
+                pass.draw(0..6, 0..1);
+            }
+        } else if let Some((pipelines, tiles)) = tiles {
+            pass.set_pipeline(&pipelines.image);
+            pass.set_bind_group(0, &self.bind_group, &[]);
+            pass.set_bind_group(1, &tiles.bind_group, &[]);
+            pass.set_blend_constant(wgpu::Color::WHITE);
+            pass.draw(0..6, 0..1);
+        } else {
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &self.bind_group, &[]);
+            pass.set_blend_constant(wgpu::Color::WHITE);
+            pass.draw(0..6, 0..1);
+        }
+
+        if let Some((pipelines, light)) = shafts {
+            pass.set_pipeline(&pipelines.composite);
+            pass.set_bind_group(0, &self.bind_group, &[]);
+            pass.set_bind_group(1, &light.bind_group, &[]);
+            pass.draw(0..6, 0..1);
+        }
+
+        if let Some((pipelines, gbuffer)) = edges {
+            pass.set_pipeline(&pipelines.edges);
+            pass.set_bind_group(0, &self.bind_group, &[]);
+            pass.set_bind_group(1, &gbuffer.bind_group, &[]);
+            pass.draw(0..6, 0..1);
+        }
+
+        if let Some(crossfade) = crossfade {
+            let opacity = crossfade.opacity as f64;
+            pass.set_pipeline(&crossfade.pipeline);
+            pass.set_bind_group(0, &self.crossfade_uniforms.1, &[]);
+            pass.set_blend_constant(wgpu::Color {
+                r: opacity,
+                g: opacity,
+                b: opacity,
+                a: opacity,
+            });
+            pass.draw(0..6, 0..1);
+        }
+
+        if !self.overlay.is_empty() {
+            pass.set_pipeline(&self.overlay_pipeline);
+            pass.set_vertex_buffer(0, self.overlay_buffer.slice(..));
+            pass.draw(0..self.overlay.len() as u32, 0..1);
+        }
+        drop(pass);
+        self.timer.end(encoder);
+        self.timer.finish(encoder);
+    }
+}
+
+// A float texture of `size`, bound for reading through `layout`
+fn float_target(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    label: &str,
+    size: [u32; 2],
+) -> FloatTarget {
+    let texture = float_texture(device, label, size);
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let bind_group = texture_bind_group(device, layout, label, &view);
+    FloatTarget {
+        size,
+        texture,
+        view,
+        bind_group,
+    }
+}
+
+// Draw `pipeline` over all of `target`, cleared first
+fn encode_into(
+    encoder: &mut wgpu::CommandEncoder,
+    label: &str,
+    target: &FloatTarget,
+    pipeline: &wgpu::RenderPipeline,
+    bind_group: &wgpu::BindGroup,
+) {
+    let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: &target.view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                store: true,
+            },
+        })],
+        depth_stencil_attachment: None,
+    });
+    pass.set_pipeline(pipeline);
+    pass.set_bind_group(0, bind_group, &[]);
+    pass.draw(0..6, 0..1);
+}
+
+// A float texture of `size` to render into and read back (also to the CPU, see `passes`)
+fn float_texture(device: &wgpu::Device, label: &str, size: [u32; 2]) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: size[0].max(1),
+            height: size[1].max(1),
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: ACCUMULATION_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+            | wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    })
+}
+
+fn texture_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    label: &str,
+    view: &wgpu::TextureView,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some(label),
+        layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::TextureView(view),
+        }],
+    })
+}
+
+fn shader_module(device: &wgpu::Device, scene: &Scene) -> wgpu::ShaderModule {
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("raymarch"),
+        source: wgpu::ShaderSource::Wgsl(shader_source(scene).into()),
+    })
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    module: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("raymarch"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module,
+            entry_point: "vertex_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module,
+            entry_point: "fragment_main",
+            // Weighted by the blend constant: 1 draws over the target, less fades in over it
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState {
+                    color: CONSTANT_BLEND,
+                    alpha: CONSTANT_BLEND,
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+// Screen-space triangles with per-vertex colors, alpha blended over the image
+fn create_overlay_pipeline(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("overlay"),
+        source: wgpu::ShaderSource::Wgsl(OVERLAY_SHADER.into()),
+    });
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("overlay"),
+        bind_group_layouts: &[],
+        push_constant_ranges: &[],
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("overlay"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: &module,
+            entry_point: "overlay_vertex",
+            buffers: &[wgpu::VertexBufferLayout {
+                array_stride: std::mem::size_of::<OverlayVertex>() as u64,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x4],
+            }],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &module,
+            entry_point: "overlay_fragment",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+const CONSTANT_BLEND: wgpu::BlendComponent = wgpu::BlendComponent {
+    src_factor: wgpu::BlendFactor::Constant,
+    dst_factor: wgpu::BlendFactor::OneMinusConstant,
+    operation: wgpu::BlendOperation::Add,
+};
+
+// Color added to the target's, which keeps its alpha
+const ADDITIVE_BLEND: wgpu::BlendState = wgpu::BlendState {
+    color: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::One,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+    alpha: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::Zero,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+};
+
+// A fullscreen pass of `entry_point` (besides the main one) into textures of `format`
+fn post_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    module: &wgpu::ShaderModule,
+    entry_point: &str,
+    format: wgpu::TextureFormat,
+    blend: Option<wgpu::BlendState>,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(entry_point),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module,
+            entry_point: "vertex_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module,
+            entry_point,
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+fn create_uniform_buffer(device: &wgpu::Device) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("uniforms"),
+        size: std::mem::size_of::<Uniforms>() as u64,
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+// The uniforms with the normal map, noise table (for a run with `seed`) and volume of `scene`,
+// as the shader's group 0
+fn create_bind_group(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    buffer: &wgpu::Buffer,
+    scene: &Scene,
+    seed: u32,
+    sampler: &wgpu::Sampler,
+) -> wgpu::BindGroup {
+    let normal_map = NormalMap::of(scene);
+    let normal_map = rgba_view(
+        device,
+        queue,
+        "normal map",
+        [normal_map.width, normal_map.height],
+        &normal_map.pixels,
+    );
+    let noise = NoiseTable::of(scene, seed);
+    let noise = rgba_view(
+        device,
+        queue,
+        "noise",
+        [noise::TABLE_SIZE, 1],
+        &noise.pixels,
+    );
+    let volume = VolumeData::of(scene.volume.as_ref());
+    let transfer = rgba_view(
+        device,
+        queue,
+        "transfer function",
+        [volume.transfer.len() as u32 / 4, 1],
+        &volume.transfer,
+    );
+    let volume = volume_view(device, queue, &volume);
+    let volume_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some("volume"),
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        ..Default::default()
+    });
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("uniforms"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&normal_map),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(&noise),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::TextureView(&volume),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: wgpu::BindingResource::TextureView(&transfer),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: wgpu::BindingResource::Sampler(&volume_sampler),
+            },
+        ],
+    })
+}
+
+// The 3D texture of a volume's voxels (see volume.rs)
+fn volume_view(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    volume: &VolumeData,
+) -> wgpu::TextureView {
+    let [width, height, depth] = volume.dims;
+    let size = wgpu::Extent3d {
+        width,
+        height,
+        depth_or_array_layers: depth,
+    };
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("volume"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D3,
+        format: wgpu::TextureFormat::R8Unorm,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+    queue.write_texture(
+        texture.as_image_copy(),
+        &volume.voxels,
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(width),
+            rows_per_image: Some(height),
+        },
+        size,
+    );
+    texture.create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+fn rgba_view(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    label: &str,
+    [width, height]: [u32; 2],
+    pixels: &[u8],
+) -> wgpu::TextureView {
+    let size = wgpu::Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+    };
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba8Unorm,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+    queue.write_texture(
+        texture.as_image_copy(),
+        pixels,
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(width * 4),
+            rows_per_image: Some(height),
+        },
+        size,
+    );
+    texture.create_view(&wgpu::TextureViewDescriptor::default())
+}
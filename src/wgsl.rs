@@ -0,0 +1,103 @@
+// Shaders written in WGSL, for the Metal renderer: a custom shader (--shader, a drop folder or
+// a livecode push) may be WGSL instead of MSL, written against the wgpu renderer's bindings (see
+// shaders.wgsl, which is one). Its markers take the WGSL noise functions and scene code, and
+// the result is translated to MSL with naga before Metal compiles it.
+//
+// The renderer binds the same resources either way, so each WGSL binding is given the slot the
+// MSL shader declares for it: the uniforms buffer 0, the normal map texture 3, the noise table
+// 4, the volume 5 and its transfer function 6, and a pass's input (group 1) texture 2. The two
+// samplers become constexpr samplers matching the wgpu renderer's.
+use naga::back::msl::{self, sampler, BindSamplerTarget, BindTarget, EntryPointResources};
+use naga::valid::{Capabilities, ValidationFlags, Validator};
+use naga::ResourceBinding;
+
+// WGSL has attributes where MSL has none
+pub fn is_wgsl(source: &str) -> bool {
+    source.contains("@fragment") || source.contains("@vertex")
+}
+
+// Complete WGSL source translated to MSL, or naga's errors with the lines they point at
+pub fn to_msl(source: &str) -> Result<String, String> {
+    let module = naga::front::wgsl::parse_str(source)
+        .map_err(|err| format!("Invalid WGSL shader:\n{}", err.emit_to_string(source)))?;
+    let info = Validator::new(ValidationFlags::all(), Capabilities::empty())
+        .validate(&module)
+        .map_err(|err| format!("Invalid WGSL shader:\n{}", err.emit_to_string(source)))?;
+
+    let resources = EntryPointResources {
+        resources: bindings(),
+        push_constant_buffer: None,
+        sizes_buffer: None,
+    };
+    let options = msl::Options {
+        lang_version: (2, 0),
+        per_entry_point_map: (module.entry_points.iter())
+            .map(|entry| (entry.name.clone(), resources.clone()))
+            .collect(),
+        inline_samplers: vec![
+            linear_sampler(sampler::Address::Repeat),
+            linear_sampler(sampler::Address::ClampToEdge),
+        ],
+        fake_missing_bindings: false,
+        ..Default::default()
+    };
+    let (msl, translation) =
+        msl::write_string(&module, &info, &options, &msl::PipelineOptions::default())
+            .map_err(|err| format!("Failed to translate WGSL shader: {}", err))?;
+    // naga leaves out entry points it can't translate, but the renderer needs every one
+    let mut failed = Vec::new();
+    for (entry, name) in module
+        .entry_points
+        .iter()
+        .zip(&translation.entry_point_names)
+    {
+        if let Err(err) = name {
+            failed.push(format!("{}: {}", entry.name, err));
+        }
+    }
+    if !failed.is_empty() {
+        return Err(format!(
+            "Failed to translate WGSL shader:\n  {}",
+            failed.join("\n  ")
+        ));
+    }
+    Ok(msl)
+}
+
+// (group, binding) of shaders.wgsl's resources and where the MSL shader has them
+fn bindings() -> msl::BindingMap {
+    let buffer = |slot| BindTarget {
+        buffer: Some(slot),
+        ..Default::default()
+    };
+    let texture = |slot| BindTarget {
+        texture: Some(slot),
+        ..Default::default()
+    };
+    let sampler = |index| BindTarget {
+        sampler: Some(BindSamplerTarget::Inline(index)),
+        ..Default::default()
+    };
+    let targets = [
+        (0, 0, buffer(0)),  // uniforms
+        (0, 1, texture(3)), // normalMap
+        (0, 2, sampler(0)), // normalSampler
+        (0, 3, texture(4)), // noiseTable
+        (0, 4, texture(5)), // volumeTex
+        (0, 5, texture(6)), // transferTex
+        (0, 6, sampler(1)), // volumeSampler
+        (1, 0, texture(2)), // The pass's input: previous, gbuffer, shafts or tiles
+    ];
+    (targets.into_iter())
+        .map(|(group, binding, target)| (ResourceBinding { group, binding }, target))
+        .collect()
+}
+
+fn linear_sampler(address: sampler::Address) -> sampler::InlineSampler {
+    sampler::InlineSampler {
+        address: [address; 3],
+        mag_filter: sampler::Filter::Linear,
+        min_filter: sampler::Filter::Linear,
+        ..Default::default()
+    }
+}
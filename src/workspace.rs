@@ -0,0 +1,90 @@
+// Workspaces: the whole state of a run (scene with its current values, animations, bindings,
+// script and cues, the camera, scene time, tempo, quality and window layout) saved so work in
+// progress can be picked up again. S saves one to <capture_dir>/<scene>.workspace.toml, and
+// every windowed run saves workspace.toml next to the config file on exit. --restore FILE
+// (or --restore alone, for the one saved on exit) starts from a workspace instead of a scene.
+//
+// The script's `state` map is not saved: a restored script starts over from an empty map.
+use metal_raymarcher::animation::MAX_TIME;
+use metal_raymarcher::quality::QualityPreset;
+use metal_raymarcher::Scene;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+// Where and how big the window was
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Layout {
+    pub width: u32, // Logical pixels
+    pub height: u32,
+    pub position: Option<[i32; 2]>, // Physical pixels
+    pub fullscreen: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Workspace {
+    pub time: f32, // Scene time, which continues from here
+    pub camera_angle: f32,
+    pub camera_distance: f32,
+    pub mouse: [f32; 2],
+    pub preset: QualityPreset,
+    pub bpm: f32,
+    pub downbeat: f32, // Time of the clock's first downbeat
+    pub layout: Option<Layout>,
+    pub live: Vec<String>, // Parameters made live at runtime (script, cues)
+    pub scene: Scene,
+}
+
+impl Workspace {
+    // Next to the config file (None when there is no config directory)
+    pub fn exit_path(config_path: Option<&Path>) -> Option<PathBuf> {
+        Some(config_path?.with_file_name("workspace.toml"))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+        let mut workspace: Workspace = toml::from_str(&text)
+            .map_err(|err| format!("Invalid workspace {}: {}", path.display(), err))?;
+        if ![workspace.camera_angle, workspace.camera_distance]
+            .iter()
+            .all(|value| value.is_finite())
+        {
+            return Err(format!(
+                "Invalid workspace {}: the camera is not finite",
+                path.display()
+            ));
+        }
+        if !(0.0..=MAX_TIME).contains(&workspace.time) {
+            return Err(format!(
+                "Invalid workspace {}: time {} is out of range",
+                path.display(),
+                workspace.time
+            ));
+        }
+        if workspace.scene.objects().is_empty() {
+            return Err(format!("Workspace {} has no objects", path.display()));
+        }
+        workspace
+            .scene
+            .validate()
+            .map_err(|err| format!("Invalid workspace {}: {}", path.display(), err))?;
+        workspace.scene.scripted = std::mem::take(&mut workspace.live);
+        Ok(workspace)
+    }
+
+    pub fn save(mut self, path: &Path) -> Result<(), String> {
+        // Scene paths are relative to the working directory, which may differ next time
+        for path in self.scene.files_mut() {
+            if let Ok(absolute) = std::path::absolute(&*path) {
+                *path = absolute;
+            }
+        }
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)
+                .map_err(|err| format!("Failed to create {}: {}", dir.display(), err))?;
+        }
+        let text = toml::to_string(&self).map_err(|err| err.to_string())?;
+        std::fs::write(path, text)
+            .map_err(|err| format!("Failed to write {}: {}", path.display(), err))
+    }
+}
@@ -0,0 +1,339 @@
+// Property tests for the scene parser and validator: the example scenes round-trip through
+// TOML, and copies of them with numbers, entries and items changed at random either fail to
+// parse or validate, or animate and generate shaders without panicking. fuzz/ feeds arbitrary
+// text through the same checks.
+use cgmath::Vector2;
+use metal_raymarcher::codegen::{self, Dialect};
+use metal_raymarcher::expression::{self, Variables};
+use metal_raymarcher::scene::MAX_DEPTH;
+use metal_raymarcher::{animation, sun, Scene};
+use proptest::prelude::*;
+use std::path::Path;
+use std::sync::OnceLock;
+use toml::Value;
+
+// The scenes in scenes/ (not the demo sequences), loaded so cities, outlines and clouds are
+// filled in
+fn examples() -> &'static [(String, Scene)] {
+    static EXAMPLES: OnceLock<Vec<(String, Scene)>> = OnceLock::new();
+    EXAMPLES.get_or_init(|| {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes");
+        let mut paths: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "toml")
+            })
+            .filter(|path| {
+                let text = std::fs::read_to_string(path).unwrap();
+                !text.parse::<toml::Table>().unwrap().contains_key("entries")
+            })
+            .collect();
+        paths.sort();
+        paths
+            .into_iter()
+            .map(|path| {
+                let scene = Scene::load(&path).unwrap_or_else(|err| panic!("{}", err));
+                (path.display().to_string(), scene)
+            })
+            .collect()
+    })
+}
+
+#[derive(Clone, Debug)]
+enum Mutation {
+    Number(f64),  // Replace a value with a number
+    Text(String), // Or with a string
+    Nest(usize),  // Wrap it in arrays
+    Remove,       // Drop a table entry or array item
+    Duplicate,    // Repeat an array item
+}
+
+fn mutation() -> impl Strategy<Value = Mutation> {
+    let number = prop_oneof![
+        Just(f64::NAN),
+        Just(f64::INFINITY),
+        Just(f64::NEG_INFINITY),
+        Just(0.0),
+        Just(-0.0),
+        Just(-1.0),
+        Just(1e300),
+        any::<f32>().prop_map(f64::from),
+    ];
+    prop_oneof![
+        number.prop_map(Mutation::Number),
+        "[a-z._]{0,12}".prop_map(Mutation::Text),
+        (1..4usize).prop_map(Mutation::Nest),
+        Just(Mutation::Remove),
+        Just(Mutation::Duplicate),
+    ]
+}
+
+// Apply `mutation` to the `at`th value below `value`, counting in preorder. False when there
+// are fewer.
+fn mutate(value: &mut Value, at: &mut usize, mutation: &Mutation) -> bool {
+    match value {
+        Value::Table(table) => {
+            let keys: Vec<String> = table.keys().cloned().collect();
+            for key in keys {
+                if *at == 0 {
+                    match mutation {
+                        Mutation::Remove => drop(table.remove(&key)),
+                        Mutation::Duplicate => {}
+                        _ => replace(&mut table[&key], mutation),
+                    }
+                    return true;
+                }
+                *at -= 1;
+                if mutate(&mut table[&key], at, mutation) {
+                    return true;
+                }
+            }
+            false
+        }
+        Value::Array(items) => {
+            for index in 0..items.len() {
+                if *at == 0 {
+                    match mutation {
+                        Mutation::Remove => drop(items.remove(index)),
+                        Mutation::Duplicate => items.insert(index, items[index].clone()),
+                        _ => replace(&mut items[index], mutation),
+                    }
+                    return true;
+                }
+                *at -= 1;
+                if mutate(&mut items[index], at, mutation) {
+                    return true;
+                }
+            }
+            false
+        }
+        _ => false,
+    }
+}
+
+fn replace(value: &mut Value, mutation: &Mutation) {
+    *value = match mutation {
+        Mutation::Number(x) if value.is_integer() => Value::Integer(*x as i64),
+        Mutation::Number(x) => Value::Float(*x),
+        Mutation::Text(text) => Value::String(text.clone()),
+        Mutation::Nest(levels) => {
+            (0..*levels).fold(value.clone(), |value, _| Value::Array(vec![value]))
+        }
+        Mutation::Remove | Mutation::Duplicate => unreachable!(),
+    };
+}
+
+// What a scene that passed validation must survive: saving and loading it again, animating
+// it and evaluating its expressions at `times`, and generating its shader in every dialect
+fn check_valid(scene: &Scene, times: &[f32]) {
+    let text = toml::to_string(scene).unwrap();
+    let again: Scene = toml::from_str(&text).unwrap();
+    // The city's nodes and lights are already in the scene, and it isn't saved
+    let expected = Scene {
+        city: None,
+        ..scene.clone()
+    };
+    assert_eq!(
+        again, expected,
+        "scene changed saving and loading it:\n{}",
+        text
+    );
+    again.validate().unwrap();
+
+    for &time in times {
+        for track in &scene.animations {
+            let len = scene.parameter(&track.target).unwrap().len();
+            assert_eq!(track.sample(time).len(), len, "\"{}\"", track.target);
+        }
+        animation::apply(&mut scene.clone(), time);
+        let variables = Variables {
+            time,
+            mouse: Vector2::new(0.5, 0.5),
+            audio: Default::default(),
+            clock: Default::default(),
+        };
+        expression::apply(&mut scene.clone(), &variables);
+    }
+    for dialect in [Dialect::Msl, Dialect::Glsl, Dialect::Wgsl] {
+        codegen::scene_source(scene, dialect);
+    }
+}
+
+#[test]
+fn examples_are_valid() {
+    for (path, scene) in examples() {
+        println!("{}", path);
+        check_valid(scene, &[0.0, 1.5, 1000.0]);
+    }
+}
+
+proptest! {
+    #[test]
+    fn mutated_examples_are_rejected_or_valid(
+        example in any::<prop::sample::Index>(),
+        mutations in prop::collection::vec((any::<prop::sample::Index>(), mutation()), 1..4),
+        times in prop::collection::vec(-100.0f32..100.0, 1..4),
+    ) {
+        let (_, scene) = example.get(examples());
+        let mut value = Value::try_from(scene).unwrap();
+        for (at, mutation) in &mutations {
+            let mut count = 0;
+            count_values(&value, &mut count);
+            mutate(&mut value, &mut at.index(count.max(1)), mutation);
+        }
+        let text = toml::to_string(&value).unwrap();
+        if let Ok(scene) = toml::from_str::<Scene>(&text) {
+            if scene.validate().is_ok() {
+                check_valid(&scene, &times);
+            }
+        }
+    }
+
+    // Groups nested `depth` deep around a sphere, as [[nodes.children.children...]] tables.
+    // The TOML parser has a recursion limit of its own, which may reject the deepest first.
+    #[test]
+    fn nesting_is_limited(depth in 0..2 * MAX_DEPTH) {
+        let mut text = String::new();
+        for level in 0..=depth {
+            let header = format!("nodes{}", ".children".repeat(level));
+            let node = match level < depth {
+                true => "type = \"group\"",
+                false => "type = \"object\"\nshape = { type = \"sphere\", radius = 1.0 }",
+            };
+            text.push_str(&format!("[[{}]]\n{}\n", header, node));
+        }
+        match toml::from_str::<Scene>(&text) {
+            Ok(scene) => prop_assert_eq!(scene.validate().is_ok(), depth <= MAX_DEPTH),
+            Err(_) => prop_assert!(depth > MAX_DEPTH),
+        }
+    }
+}
+
+// A scene binding a sphere's radius to `expression`
+fn expression_scene(expression: &str) -> String {
+    format!(
+        "[[nodes]]\ntype = \"object\"\nname = \"ball\"\n\
+         shape = {{ type = \"sphere\", radius = 1.0 }}\n\
+         [expressions]\n\"ball.radius\" = {:?}\n",
+        expression
+    )
+}
+
+#[test]
+fn deep_expressions_are_rejected() {
+    // Far deeper than the stack would allow if they were parsed without a limit
+    for expression in [
+        format!("{}1{}", "(".repeat(50_000), ")".repeat(50_000)),
+        format!("{}1", "-".repeat(50_000)),
+        format!("1{}", " + 1".repeat(50_000)),
+    ] {
+        assert!(toml::from_str::<Scene>(&expression_scene(&expression)).is_err());
+    }
+}
+
+proptest! {
+    // `1` inside `wrappers` (parentheses, minus signs and calls), one within the other
+    #[test]
+    fn expression_nesting_is_limited(
+        wrappers in prop::collection::vec(0..3usize, 0..2 * expression::MAX_DEPTH),
+    ) {
+        let expression = wrappers.iter().fold("1".to_string(), |inner, wrapper| match wrapper {
+            0 => format!("({})", inner),
+            1 => format!("-{}", inner),
+            _ => format!("abs({})", inner),
+        });
+        let parsed = toml::from_str::<Scene>(&expression_scene(&expression));
+        prop_assert_eq!(parsed.is_ok(), wrappers.len() <= expression::MAX_DEPTH);
+        if let Ok(scene) = parsed {
+            scene.validate().unwrap();
+            check_valid(&scene, &[0.0]);
+        }
+    }
+}
+
+proptest! {
+    // A sun's `time` in any year, with an offset made of anything, including characters of
+    // several bytes
+    #[test]
+    fn sun_times_are_parsed_or_rejected(year in "[0-9]{1,20}", zone in "[-+:0-9a€]{0,7}") {
+        let _ = sun::parse_time(&format!("{}-06-21 14:30{}", year, zone));
+    }
+}
+
+#[test]
+fn sun_times_and_places_out_of_range_are_rejected() {
+    for time in [
+        "99999999999999999-01-01 00:00",
+        "0-01-01 00:00",
+        "10000-01-01 00:00",
+        "2025-06-21 14:30-99:99",
+        "2025-06-21 14:30+14:01",
+        "2025-06-21 14:30+02:60",
+        "2025-06-21 14:30++1:00",
+    ] {
+        assert!(sun::parse_time(time).is_err(), "{}", time);
+    }
+    for time in ["9999-12-31 23:59-14:00", "2025-06-21 14:30+05:45"] {
+        assert!(sun::parse_time(time).is_ok(), "{}", time);
+    }
+    for value in [f32::NAN, f32::INFINITY] {
+        let suns = [
+            sun::Sun {
+                longitude: value,
+                ..Default::default()
+            },
+            sun::Sun {
+                north: value,
+                ..Default::default()
+            },
+            sun::Sun {
+                hours: value,
+                ..Default::default()
+            },
+        ];
+        for sun in suns {
+            assert!(sun.validate().is_err(), "{:?}", sun);
+        }
+    }
+}
+
+// Values below `value`, as `mutate` counts them
+fn count_values(value: &Value, count: &mut usize) {
+    let children: Box<dyn Iterator<Item = &Value>> = match value {
+        Value::Table(table) => Box::new(table.values()),
+        Value::Array(items) => Box::new(items.iter()),
+        _ => return,
+    };
+    for child in children {
+        *count += 1;
+        count_values(child, count);
+    }
+}
+
+#[test]
+fn scene_names_stay_in_their_folder() {
+    let scene = |name: &str| Scene {
+        name: name.to_string(),
+        ..toml::from_str(&expression_scene("1")).unwrap()
+    };
+    scene("city-2").validate().unwrap();
+    for name in ["../../x", "a/b", "a\\b", "..", "a\0b", "a\nb"] {
+        assert!(scene(name).validate().is_err(), "{:?}", name);
+    }
+}
+
+#[test]
+fn names_hold_no_control_characters() {
+    let scene = |name: &str| {
+        let text = format!(
+            "[[nodes]]\ntype = \"object\"\nname = \"{}\"\n\
+             shape = {{ type = \"sphere\", radius = 1.0 }}\n",
+            name
+        );
+        toml::from_str::<Scene>(&text).unwrap()
+    };
+    scene("ball").validate().unwrap();
+    assert!(scene("ball\\n}").validate().is_err());
+}